@@ -1,5 +1,13 @@
-use flowy_storage::{ObjectIdentity, ObjectStorageService};
+use bytes::Bytes;
+use flowy_storage::{
+  storage_error, CancellationToken, DeleteReport, DownloadProgressCallback, HealthStatus,
+  ListOptions, ObjectByteStream, ObjectIdentity, ObjectMeta, ObjectStorageService, ObjectStream,
+  OverwritePolicy, PartETag, ProgressCallback, PutOutcome, StorageErrorKind, StorageObject,
+  UploadId, VersionMeta,
+};
+use mime::Mime;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::Error;
 use client_api::collab_sync::{SinkConfig, SinkStrategy, SyncObject, SyncPlugin};
@@ -26,41 +34,508 @@ use flowy_server_pub::supabase_config::SupabaseConfiguration;
 use flowy_storage::ObjectValue;
 use flowy_user_pub::cloud::{UserCloudService, UserCloudServiceProvider};
 use flowy_user_pub::entities::{Authenticator, UserTokenState};
+use lib_infra::async_trait::async_trait;
 use lib_infra::future::{to_fut, Fut, FutureResult};
 
 use crate::integrate::server::{Server, ServerProvider};
 
+/// The server of the current authenticator, for example the local one, doesn't store files.
+fn no_file_storage() -> FlowyError {
+  storage_error(
+    StorageErrorKind::BackendUnsupported,
+    "the server doesn't support file storage",
+  )
+}
+
+#[async_trait]
 impl ObjectStorageService for ServerProvider {
-  fn get_object_url(&self, object_id: ObjectIdentity) -> FutureResult<String, FlowyError> {
-    let server = self.get_server();
-    FutureResult::new(async move {
-      let storage = server?.file_storage().ok_or(FlowyError::internal())?;
-      storage.get_object_url(object_id).await
-    })
+  async fn get_object_url(&self, object_id: ObjectIdentity) -> Result<String, FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage.get_object_url(object_id).await
   }
 
-  fn put_object(&self, url: String, val: ObjectValue) -> FutureResult<(), FlowyError> {
-    let server = self.get_server();
-    FutureResult::new(async move {
-      let storage = server?.file_storage().ok_or(FlowyError::internal())?;
-      storage.put_object(url, val).await
-    })
+  async fn put_object(&self, url: String, val: ObjectValue) -> Result<(), FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage.put_object(url, val).await
   }
 
-  fn delete_object(&self, url: String) -> FutureResult<(), FlowyError> {
-    let server = self.get_server();
-    FutureResult::new(async move {
-      let storage = server?.file_storage().ok_or(FlowyError::internal())?;
-      storage.delete_object(url).await
-    })
+  async fn put_object_with_progress(
+    &self,
+    url: String,
+    val: ObjectValue,
+    progress: ProgressCallback,
+  ) -> Result<(), FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage.put_object_with_progress(url, val, progress).await
   }
 
-  fn get_object(&self, url: String) -> FutureResult<flowy_storage::ObjectValue, FlowyError> {
-    let server = self.get_server();
-    FutureResult::new(async move {
-      let storage = server?.file_storage().ok_or(FlowyError::internal())?;
-      storage.get_object(url).await
-    })
+  async fn put_object_cancellable(
+    &self,
+    url: String,
+    val: ObjectValue,
+    cancel: CancellationToken,
+  ) -> Result<(), FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage.put_object_cancellable(url, val, cancel).await
+  }
+
+  fn supports_object_ttl(&self) -> bool {
+    self
+      .get_server()
+      .ok()
+      .and_then(|server| server.file_storage())
+      .map(|storage| storage.supports_object_ttl())
+      .unwrap_or(false)
+  }
+
+  async fn put_object_with_ttl(
+    &self,
+    url: String,
+    val: ObjectValue,
+    ttl: Duration,
+  ) -> Result<(), FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage.put_object_with_ttl(url, val, ttl).await
+  }
+
+  async fn put_object_if_absent(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+  ) -> Result<(), FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage.put_object_if_absent(url, object_value).await
+  }
+
+  async fn put_object_if_match(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    etag: String,
+  ) -> Result<(), FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage.put_object_if_match(url, object_value, etag).await
+  }
+
+  async fn put_objects(
+    &self,
+    objects: Vec<StorageObject>,
+  ) -> Result<Vec<Result<String, FlowyError>>, FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage.put_objects(objects).await
+  }
+
+  async fn put_objects_transactional(
+    &self,
+    objects: Vec<StorageObject>,
+  ) -> Result<Vec<String>, FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage.put_objects_transactional(objects).await
+  }
+
+  async fn put_object_with_policy(
+    &self,
+    object_id: ObjectIdentity,
+    object_value: ObjectValue,
+    policy: OverwritePolicy,
+  ) -> Result<PutOutcome, FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage
+      .put_object_with_policy(object_id, object_value, policy)
+      .await
+  }
+
+  async fn put_object_from_channel(
+    &self,
+    url: String,
+    mime: Mime,
+    rx: tokio::sync::mpsc::Receiver<Bytes>,
+  ) -> Result<(), FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage.put_object_from_channel(url, mime, rx).await
+  }
+
+  async fn delete_object(&self, url: String) -> Result<(), FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage.delete_object(url).await
+  }
+
+  async fn get_object(&self, url: String) -> Result<flowy_storage::ObjectValue, FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage.get_object(url).await
+  }
+
+  async fn get_object_named(
+    &self,
+    url: String,
+  ) -> Result<(flowy_storage::ObjectValue, Option<String>), FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage.get_object_named(url).await
+  }
+
+  async fn delete_objects(
+    &self,
+    urls: Vec<String>,
+  ) -> Result<Vec<Result<(), FlowyError>>, FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage.delete_objects(urls).await
+  }
+
+  async fn delete_workspace_objects(&self, workspace_id: &str) -> Result<DeleteReport, FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage.delete_workspace_objects(workspace_id).await
+  }
+
+  async fn get_object_if_modified(
+    &self,
+    url: String,
+    etag: Option<String>,
+  ) -> Result<Option<(ObjectValue, String)>, FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage.get_object_if_modified(url, etag).await
+  }
+
+  async fn get_object_stream(&self, url: String) -> Result<ObjectStream, FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage.get_object_stream(url).await
+  }
+
+  async fn get_object_with_progress(
+    &self,
+    url: String,
+    progress: DownloadProgressCallback,
+  ) -> Result<flowy_storage::ObjectValue, FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage.get_object_with_progress(url, progress).await
+  }
+
+  async fn get_object_stream_with_meta(
+    &self,
+    url: String,
+  ) -> Result<(ObjectMeta, ObjectByteStream), FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage.get_object_stream_with_meta(url).await
+  }
+
+  async fn get_object_cancellable(
+    &self,
+    url: String,
+    cancel: CancellationToken,
+  ) -> Result<ObjectValue, FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage.get_object_cancellable(url, cancel).await
+  }
+
+  async fn get_object_verified(
+    &self,
+    url: String,
+    expected_file_id: &str,
+  ) -> Result<ObjectValue, FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage.get_object_verified(url, expected_file_id).await
+  }
+
+  async fn list_objects(
+    &self,
+    workspace_id: &str,
+    prefix: Option<&str>,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage.list_objects(workspace_id, prefix).await
+  }
+
+  async fn list_objects_with_options(
+    &self,
+    workspace_id: &str,
+    options: &ListOptions,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage
+      .list_objects_with_options(workspace_id, options)
+      .await
+  }
+
+  fn supports_range_requests(&self) -> bool {
+    self
+      .get_server()
+      .ok()
+      .and_then(|server| server.file_storage())
+      .map(|storage| storage.supports_range_requests())
+      .unwrap_or(false)
+  }
+
+  async fn get_object_range(
+    &self,
+    url: String,
+    start: u64,
+    end: Option<u64>,
+  ) -> Result<ObjectValue, FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage.get_object_range(url, start, end).await
+  }
+
+  async fn head_object(&self, url: String) -> Result<ObjectMeta, FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage.head_object(url).await
+  }
+
+  async fn object_exists(&self, url: String) -> Result<bool, FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage.object_exists(url).await
+  }
+
+  async fn presign_get_url(&self, url: String, expires_in: Duration) -> Result<String, FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage.presign_get_url(url, expires_in).await
+  }
+
+  async fn presign_put_url(&self, url: String, expires_in: Duration) -> Result<String, FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage.presign_put_url(url, expires_in).await
+  }
+
+  fn supports_copy_object(&self) -> bool {
+    self
+      .get_server()
+      .ok()
+      .and_then(|server| server.file_storage())
+      .map(|storage| storage.supports_copy_object())
+      .unwrap_or(false)
+  }
+
+  async fn copy_object(
+    &self,
+    src_url: String,
+    dst_identity: ObjectIdentity,
+  ) -> Result<String, FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage.copy_object(src_url, dst_identity).await
+  }
+
+  async fn move_object(
+    &self,
+    src_url: String,
+    dst_identity: ObjectIdentity,
+    overwrite: bool,
+  ) -> Result<String, FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage.move_object(src_url, dst_identity, overwrite).await
+  }
+
+  async fn trash_object(&self, url: String) -> Result<(), FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage.trash_object(url).await
+  }
+
+  async fn restore_object(&self, url: String) -> Result<(), FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage.restore_object(url).await
+  }
+
+  async fn purge_trash(&self, older_than: Duration) -> Result<Vec<String>, FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage.purge_trash(older_than).await
+  }
+
+  fn supports_versioning(&self) -> bool {
+    self
+      .get_server()
+      .ok()
+      .and_then(|server| server.file_storage())
+      .map(|storage| storage.supports_versioning())
+      .unwrap_or(false)
+  }
+
+  async fn list_versions(&self, url: String) -> Result<Vec<VersionMeta>, FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage.list_versions(url).await
+  }
+
+  async fn get_object_version(
+    &self,
+    url: String,
+    version_id: String,
+  ) -> Result<ObjectValue, FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage.get_object_version(url, version_id).await
+  }
+
+  async fn restore_version(&self, url: String, version_id: String) -> Result<(), FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage.restore_version(url, version_id).await
+  }
+
+  fn supports_multipart(&self) -> bool {
+    self
+      .get_server()
+      .ok()
+      .and_then(|server| server.file_storage())
+      .map(|storage| storage.supports_multipart())
+      .unwrap_or(false)
+  }
+
+  async fn initiate_multipart(&self, url: String, mime: Mime) -> Result<UploadId, FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage.initiate_multipart(url, mime).await
+  }
+
+  async fn upload_part(
+    &self,
+    url: String,
+    upload_id: UploadId,
+    part_number: u32,
+    bytes: Bytes,
+  ) -> Result<PartETag, FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage
+      .upload_part(url, upload_id, part_number, bytes)
+      .await
+  }
+
+  async fn complete_multipart(
+    &self,
+    url: String,
+    upload_id: UploadId,
+    parts: Vec<PartETag>,
+  ) -> Result<(), FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage.complete_multipart(url, upload_id, parts).await
+  }
+
+  async fn abort_multipart(&self, url: String, upload_id: UploadId) -> Result<(), FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage.abort_multipart(url, upload_id).await
+  }
+
+  async fn health_check(&self) -> Result<HealthStatus, FlowyError> {
+    let storage = self
+      .get_server()?
+      .file_storage()
+      .ok_or_else(no_file_storage)?;
+    storage.health_check().await
   }
 }
 
@@ -1,6 +1,7 @@
 #![allow(unused_doc_comments)]
 
-use flowy_storage::ObjectStorageService;
+use flowy_storage::{init_storage_config, ObjectStorageService, StorageConfig};
+use std::path::Path;
 use std::sync::Arc;
 
 use std::time::Duration;
@@ -89,6 +90,15 @@ impl AppFlowyCore {
 
     // Init the key value database
     let store_preference = Arc::new(StorePreferences::new(&config.storage_path).unwrap());
+    // The system temp directory of the mobile apps is small, stage the temporary files in the app
+    // directory instead.
+    let storage_config = StorageConfig {
+      temp_dir: Some(Path::new(&config.storage_path).join("temp")),
+      ..Default::default()
+    };
+    if let Err(err) = init_storage_config(storage_config) {
+      error!("{}", err);
+    }
     info!("🔥{:?}", &config);
     let task_scheduler = TaskDispatcher::new(Duration::from_secs(2));
     let task_dispatcher = Arc::new(RwLock::new(task_scheduler));
@@ -11,7 +11,7 @@ use collab_document::document::Document;
 use collab_document::document_data::default_document_data;
 use collab_entity::CollabType;
 use collab_plugins::CollabKVDB;
-use flowy_storage::object_from_disk;
+use flowy_storage::{object_from_disk, ObjectIdentity, ObjectValue};
 use lru::LruCache;
 use parking_lot::Mutex;
 use tokio::io::AsyncWriteExt;
@@ -254,7 +254,42 @@ impl DocumentManager {
     workspace_id: String,
     local_file_path: &str,
   ) -> FlowyResult<String> {
-    let (object_identity, object_value) = object_from_disk(&workspace_id, local_file_path).await?;
+    #[cfg(target_arch = "wasm32")]
+    {
+      let _ = (workspace_id, local_file_path);
+      Err(FlowyError::new(
+        ErrorCode::NotSupportYet,
+        "upload file from local path is not supported on wasm, use upload_file_content",
+      ))
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+      let (object_identity, object_value) =
+        object_from_disk(&workspace_id, local_file_path, true, None).await?;
+      self.upload_object(object_identity, object_value).await
+    }
+  }
+
+  /// The browser doesn't expose the local file system, the web layer hands over the content of
+  /// the file instead of its path.
+  #[cfg(target_arch = "wasm32")]
+  pub async fn upload_file_content(
+    &self,
+    workspace_id: String,
+    file_name: &str,
+    content: Vec<u8>,
+  ) -> FlowyResult<String> {
+    let (object_identity, object_value) =
+      object_from_disk(&workspace_id, file_name, content, true, None).await?;
+    self.upload_object(object_identity, object_value).await
+  }
+
+  async fn upload_object(
+    &self,
+    object_identity: ObjectIdentity,
+    object_value: ObjectValue,
+  ) -> FlowyResult<String> {
     let storage_service = self.storage_service_upgrade()?;
     let url = storage_service.get_object_url(object_identity).await?;
 
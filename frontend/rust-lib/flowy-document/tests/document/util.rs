@@ -164,27 +164,29 @@ impl DocumentCloudService for LocalTestDocumentCloudServiceImpl {
 }
 
 pub struct DocumentTestFileStorageService;
+
+#[async_trait]
 impl ObjectStorageService for DocumentTestFileStorageService {
-  fn get_object_url(
+  async fn get_object_url(
     &self,
     _object_id: flowy_storage::ObjectIdentity,
-  ) -> FutureResult<String, FlowyError> {
+  ) -> Result<String, FlowyError> {
     todo!()
   }
 
-  fn put_object(
+  async fn put_object(
     &self,
     _url: String,
     _object_value: flowy_storage::ObjectValue,
-  ) -> FutureResult<(), FlowyError> {
+  ) -> Result<(), FlowyError> {
     todo!()
   }
 
-  fn delete_object(&self, _url: String) -> FutureResult<(), FlowyError> {
+  async fn delete_object(&self, _url: String) -> Result<(), FlowyError> {
     todo!()
   }
 
-  fn get_object(&self, _url: String) -> FutureResult<flowy_storage::ObjectValue, FlowyError> {
+  async fn get_object(&self, _url: String) -> Result<flowy_storage::ObjectValue, FlowyError> {
     todo!()
   }
 }
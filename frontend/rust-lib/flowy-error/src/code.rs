@@ -268,6 +268,39 @@ pub enum ErrorCode {
 
   #[error("AppFlowy data folder import error")]
   AppFlowyDataFolderImportError = 89,
+
+  #[error("File too large")]
+  FileTooLarge = 90,
+
+  #[error("Content hash mismatch")]
+  ContentHashMismatch = 91,
+
+  #[error("Operation cancelled")]
+  Cancelled = 92,
+
+  #[error("Operation timed out")]
+  Timeout = 93,
+
+  #[error("Invalid image")]
+  InvalidImage = 94,
+
+  #[error("Service temporarily unavailable")]
+  ServiceUnavailable = 95,
+
+  #[error("Precondition failed")]
+  PreconditionFailed = 96,
+
+  #[error("Permission denied")]
+  PermissionDenied = 97,
+
+  #[error("Not enough disk space")]
+  DiskFull = 98,
+
+  #[error("The path is outside of the storage")]
+  PathTraversal = 99,
+
+  #[error("The file type is not allowed")]
+  FileTypeNotAllowed = 100,
 }
 
 impl ErrorCode {
@@ -1,6 +1,19 @@
+use std::time::SystemTime;
+
+use bytes::Bytes;
+use chrono::DateTime;
 use flowy_error::FlowyError;
-use flowy_storage::{ObjectIdentity, ObjectStorageService, ObjectValue};
-use lib_infra::future::FutureResult;
+use flowy_storage::{
+  file_id_from_url, if_match_header_value, object_from_response, object_stream_from_response,
+  progress_stream, storage_config, storage_error, with_accept_encoding, ObjectByteStream,
+  ObjectIdentity, ObjectMeta, ObjectStorageService, ObjectStream, ObjectValue, ProgressCallback,
+  ProgressReporter, StorageErrorKind,
+};
+use lib_infra::async_trait::async_trait;
+use reqwest::header::{
+  HeaderMap, HeaderName, CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_MATCH, IF_NONE_MATCH, LAST_MODIFIED,
+};
+use reqwest::{Body, Method, Response, StatusCode};
 
 use crate::af_cloud::AFServer;
 
@@ -12,48 +25,194 @@ impl<T> AFCloudFileStorageServiceImpl<T> {
   }
 }
 
+impl<T> AFCloudFileStorageServiceImpl<T>
+where
+  T: AFServer,
+{
+  /// Downloads the blob, the server may compress the response, see [with_accept_encoding].
+  async fn get_blob_response(&self, url: &str) -> Result<Response, FlowyError> {
+    let client = self.0.try_get_client()?;
+    let request = client.http_client_with_auth(Method::GET, url).await?;
+    check_status(with_accept_encoding(request).send().await?)
+  }
+
+  /// Uploads the blob with a precondition header, the server responds 412 when it doesn't hold.
+  async fn put_blob_with_precondition(
+    &self,
+    url: &str,
+    file: ObjectValue,
+    header: HeaderName,
+    value: String,
+  ) -> Result<(), FlowyError> {
+    let client = self.0.try_get_client()?;
+    let file = file.decompress()?;
+    client
+      .http_client_with_auth(Method::PUT, url)
+      .await?
+      .header(CONTENT_TYPE, file.mime.to_string())
+      .header(header, value)
+      .body(file.raw)
+      .send()
+      .await
+      .map_err(FlowyError::from)
+      .and_then(check_status)?;
+    Ok(())
+  }
+}
+
+#[async_trait]
 impl<T> ObjectStorageService for AFCloudFileStorageServiceImpl<T>
 where
   T: AFServer,
 {
-  fn get_object_url(&self, object_id: ObjectIdentity) -> FutureResult<String, FlowyError> {
-    let try_get_client = self.0.try_get_client();
-    FutureResult::new(async move {
-      let file_name = format!("{}.{}", object_id.file_id, object_id.ext);
-      let client = try_get_client?;
-      let url = client.get_blob_url(&object_id.workspace_id, &file_name);
-      Ok(url)
-    })
-  }
-
-  fn put_object(&self, url: String, file: ObjectValue) -> FutureResult<(), FlowyError> {
-    let try_get_client = self.0.try_get_client();
-    let file = file.clone();
-    FutureResult::new(async move {
-      let client = try_get_client?;
-      client.put_blob(&url, file.raw, &file.mime).await?;
-      Ok(())
-    })
-  }
-
-  fn delete_object(&self, url: String) -> FutureResult<(), FlowyError> {
-    let try_get_client = self.0.try_get_client();
-    FutureResult::new(async move {
-      let client = try_get_client?;
-      client.delete_blob(&url).await?;
-      Ok(())
-    })
-  }
-
-  fn get_object(&self, url: String) -> FutureResult<ObjectValue, FlowyError> {
-    let try_get_client = self.0.try_get_client();
-    FutureResult::new(async move {
-      let client = try_get_client?;
-      let (mime, raw) = client.get_blob(&url).await?;
-      Ok(ObjectValue {
-        raw: raw.into(),
-        mime,
-      })
-    })
+  async fn get_object_url(&self, object_id: ObjectIdentity) -> Result<String, FlowyError> {
+    let file_name = format!("{}.{}", object_id.file_id, object_id.ext);
+    let client = self.0.try_get_client()?;
+    let url = client.get_blob_url(&object_id.workspace_id, &file_name);
+    Ok(url)
+  }
+
+  async fn put_object(&self, url: String, file: ObjectValue) -> Result<(), FlowyError> {
+    let client = self.0.try_get_client()?;
+    // The client can't set the content encoding of the blob, so the content is stored
+    // uncompressed.
+    let file = file.decompress()?;
+    client.put_blob(&url, file.raw, &file.mime).await?;
+    Ok(())
+  }
+
+  async fn put_object_with_progress(
+    &self,
+    url: String,
+    file: ObjectValue,
+    progress: ProgressCallback,
+  ) -> Result<(), FlowyError> {
+    let client = self.0.try_get_client()?;
+    let file = file.decompress()?;
+    let total = file.raw.len() as u64;
+    let reporter = ProgressReporter::new(total, progress);
+    // The body is pulled chunk by chunk as it's sent, so the progress follows the upload.
+    let stream = progress_stream(
+      chunk_stream(file.raw, storage_config().read_buffer_size),
+      reporter.clone(),
+    );
+    client
+      .http_client_with_auth(Method::PUT, &url)
+      .await?
+      .header(CONTENT_TYPE, file.mime.to_string())
+      .header(CONTENT_LENGTH, total)
+      .body(Body::wrap_stream(stream))
+      .send()
+      .await
+      .map_err(FlowyError::from)
+      .and_then(check_status)?;
+    reporter.finish();
+    Ok(())
+  }
+
+  async fn put_object_if_absent(&self, url: String, file: ObjectValue) -> Result<(), FlowyError> {
+    self
+      .put_blob_with_precondition(&url, file, IF_NONE_MATCH, "*".to_string())
+      .await
+  }
+
+  async fn put_object_if_match(
+    &self,
+    url: String,
+    file: ObjectValue,
+    etag: String,
+  ) -> Result<(), FlowyError> {
+    self
+      .put_blob_with_precondition(&url, file, IF_MATCH, if_match_header_value(&etag))
+      .await
+  }
+
+  async fn delete_object(&self, url: String) -> Result<(), FlowyError> {
+    let client = self.0.try_get_client()?;
+    client.delete_blob(&url).await?;
+    Ok(())
+  }
+
+  async fn get_object(&self, url: String) -> Result<ObjectValue, FlowyError> {
+    let response = self.get_blob_response(&url).await?;
+    object_from_response(response).await
+  }
+
+  async fn get_object_stream(&self, url: String) -> Result<ObjectStream, FlowyError> {
+    let response = self.get_blob_response(&url).await?;
+    object_stream_from_response(response)
+  }
+
+  async fn head_object(&self, url: String) -> Result<ObjectMeta, FlowyError> {
+    let client = self.0.try_get_client()?;
+    let response = client
+      .http_client_with_auth(Method::HEAD, &url)
+      .await?
+      .send()
+      .await?;
+    let response = check_status(response)?;
+    Ok(object_meta_from_headers(url, response.headers()))
+  }
+
+  async fn object_exists(&self, url: String) -> Result<bool, FlowyError> {
+    match self.head_object(url).await {
+      Ok(_) => Ok(true),
+      Err(err) if err.is_record_not_found() => Ok(false),
+      Err(err) => Err(err),
+    }
+  }
+}
+
+/// Describes the blob from the headers of a `HEAD` response. The body of the response is empty,
+/// so the size comes from the `Content-Length` header.
+fn object_meta_from_headers(url: String, headers: &HeaderMap) -> ObjectMeta {
+  let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+  ObjectMeta {
+    file_id: file_id_from_url(&url).unwrap_or_default().to_string(),
+    size: header(CONTENT_LENGTH)
+      .and_then(|value| value.parse().ok())
+      .unwrap_or_default(),
+    mime: header(CONTENT_TYPE)
+      .and_then(|value| value.parse().ok())
+      .unwrap_or(mime_guess::mime::APPLICATION_OCTET_STREAM),
+    etag: header(ETAG).map(str::to_string),
+    last_modified: header(LAST_MODIFIED)
+      .and_then(|value| DateTime::parse_from_rfc2822(value).ok())
+      .map(SystemTime::from),
+    trashed_at: None,
+    expires_at: None,
+    url,
+  }
+}
+
+/// Splits the content into chunks of at most `chunk_size` bytes. The chunks share the memory of
+/// `raw`, nothing is copied.
+fn chunk_stream(raw: Bytes, chunk_size: usize) -> ObjectByteStream {
+  let chunk_size = chunk_size.max(1);
+  let len = raw.len();
+  let chunks = (0..len)
+    .step_by(chunk_size)
+    .map(move |start| Ok(raw.slice(start..(start + chunk_size).min(len))));
+  Box::pin(futures::stream::iter(chunks))
+}
+
+/// Classifies the failed responses, so the callers can tell a missing blob from a network error,
+/// see [StorageErrorKind].
+fn check_status(response: Response) -> Result<Response, FlowyError> {
+  let status = response.status();
+  if status.is_success() {
+    return Ok(response);
   }
+  let kind = match status {
+    StatusCode::NOT_FOUND => StorageErrorKind::NotFound,
+    StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => StorageErrorKind::Unauthorized,
+    StatusCode::PAYLOAD_TOO_LARGE => StorageErrorKind::QuotaExceeded,
+    StatusCode::PRECONDITION_FAILED => StorageErrorKind::PreconditionFailed,
+    StatusCode::SERVICE_UNAVAILABLE => StorageErrorKind::Unavailable,
+    _ => StorageErrorKind::Network,
+  };
+  Err(storage_error(
+    kind,
+    format!("{} responded {}", response.url(), status),
+  ))
 }
@@ -1,16 +1,17 @@
 use std::borrow::Cow;
 
 use anyhow::Error;
-use flowy_storage::StorageObject;
+use flowy_storage::{storage_config, StorageObject};
 use hyper::header::CONTENT_TYPE;
 use reqwest::header::IntoHeaderName;
 use reqwest::multipart::{Form, Part};
 use reqwest::{
   header::{HeaderMap, HeaderValue},
-  Client, Method, RequestBuilder,
+  Body, Client, Method, RequestBuilder,
 };
 use tokio::fs::File;
 use tokio::io::AsyncReadExt;
+use tokio_util::io::ReaderStream;
 use url::Url;
 
 use crate::supabase::file_storage::{DeleteObjects, FileOptions, NewBucket, RequestBody};
@@ -87,7 +88,7 @@ impl StorageRequestBuilder {
 
   pub fn upload_object(mut self, bucket_name: &str, object: StorageObject) -> Self {
     self.method = Method::POST;
-    let options = FileOptions::from_mime(object.value.mime_type());
+    let options = FileOptions::from_mime(object.value.mime_type(true));
     self
       .url
       .path_segments_mut()
@@ -143,6 +144,23 @@ impl StorageRequestBuilder {
 
         builder = builder.multipart(form);
       },
+      RequestBody::MultiPartStream { reader, options } => {
+        self.headers.insert(
+          "x-upsert",
+          HeaderValue::from_str(&options.upsert.to_string()).unwrap(),
+        );
+        // The content is sent as it's read, one chunk at a time.
+        let stream = ReaderStream::with_capacity(reader.take()?, storage_config().read_buffer_size);
+        let part = Part::stream(Body::wrap_stream(stream))
+          .file_name("")
+          .mime_str(&options.content_type)?;
+
+        let form = Form::new()
+          .part("", part)
+          .text("cacheControl", options.cache_control);
+
+        builder = builder.multipart(form);
+      },
       RequestBody::BodyString { text } => {
         builder = builder.body(text);
       },
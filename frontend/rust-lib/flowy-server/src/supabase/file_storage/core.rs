@@ -11,7 +11,7 @@ use flowy_encrypt::{decrypt_data, encrypt_data};
 use flowy_error::FlowyError;
 use flowy_server_pub::supabase_config::SupabaseConfiguration;
 use flowy_storage::{FileStoragePlan, ObjectStorageService};
-use lib_infra::future::FutureResult;
+use lib_infra::async_trait::async_trait;
 
 use crate::supabase::file_storage::builder::StorageRequestBuilder;
 use crate::AppFlowyEncryption;
@@ -26,27 +26,28 @@ pub struct SupabaseFileStorage {
   storage_plan: Arc<dyn FileStoragePlan>,
 }
 
+#[async_trait]
 impl ObjectStorageService for SupabaseFileStorage {
-  fn get_object_url(
+  async fn get_object_url(
     &self,
     _object_id: flowy_storage::ObjectIdentity,
-  ) -> FutureResult<String, FlowyError> {
+  ) -> Result<String, FlowyError> {
     todo!()
   }
 
-  fn put_object(
+  async fn put_object(
     &self,
     _url: String,
     _object_value: flowy_storage::ObjectValue,
-  ) -> FutureResult<(), FlowyError> {
+  ) -> Result<(), FlowyError> {
     todo!()
   }
 
-  fn delete_object(&self, _url: String) -> FutureResult<(), FlowyError> {
+  async fn delete_object(&self, _url: String) -> Result<(), FlowyError> {
     todo!()
   }
 
-  fn get_object(&self, _url: String) -> FutureResult<flowy_storage::ObjectValue, FlowyError> {
+  async fn get_object(&self, _url: String) -> Result<flowy_storage::ObjectValue, FlowyError> {
     todo!()
   }
 
@@ -1,7 +1,7 @@
 use bytes::Bytes;
 use serde::{Deserialize, Serialize};
 
-use flowy_storage::ObjectValueSupabase;
+use flowy_storage::{ObjectReader, ObjectValueSupabase};
 
 use crate::supabase;
 
@@ -85,6 +85,10 @@ pub enum RequestBody {
     bytes: Bytes,
     options: FileOptions,
   },
+  MultiPartStream {
+    reader: ObjectReader,
+    options: FileOptions,
+  },
   BodyString {
     text: String,
   },
@@ -99,10 +103,15 @@ impl From<(FileOptions, ObjectValueSupabase)> for RequestBody {
   ) -> Self {
     let (options, value) = params;
     match value {
-      ObjectValueSupabase::File { file_path } => RequestBody::MultiPartFile { file_path, options },
+      ObjectValueSupabase::File { file_path, .. } => {
+        RequestBody::MultiPartFile { file_path, options }
+      },
       ObjectValueSupabase::Bytes { bytes, mime: _ } => {
         RequestBody::MultiPartBytes { bytes, options }
       },
+      ObjectValueSupabase::Reader { reader, .. } => {
+        RequestBody::MultiPartStream { reader, options }
+      },
     }
   }
 }
@@ -4,7 +4,7 @@ use parking_lot::RwLock;
 
 use flowy_error::FlowyError;
 use flowy_storage::{FileStoragePlan, StorageObject};
-use lib_infra::future::FutureResult;
+use lib_infra::async_trait::async_trait;
 
 use crate::supabase::api::RESTfulPostgresServer;
 
@@ -25,18 +25,19 @@ impl FileStoragePlanImpl {
   }
 }
 
+#[async_trait]
 impl FileStoragePlan for FileStoragePlanImpl {
-  fn storage_size(&self) -> FutureResult<u64, FlowyError> {
+  async fn storage_size(&self) -> Result<u64, FlowyError> {
     // 1 GB
-    FutureResult::new(async { Ok(1024 * 1024 * 1024) })
+    Ok(1024 * 1024 * 1024)
   }
 
-  fn maximum_file_size(&self) -> FutureResult<u64, FlowyError> {
+  async fn maximum_file_size(&self) -> Result<u64, FlowyError> {
     // 5 MB
-    FutureResult::new(async { Ok(5 * 1024 * 1024) })
+    Ok(5 * 1024 * 1024)
   }
 
-  fn check_upload_object(&self, _object: &StorageObject) -> FutureResult<(), FlowyError> {
-    FutureResult::new(async { Ok(()) })
+  async fn check_upload_object(&self, _object: &StorageObject) -> Result<(), FlowyError> {
+    Ok(())
   }
 }
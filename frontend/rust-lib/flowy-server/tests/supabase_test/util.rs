@@ -20,7 +20,7 @@ use flowy_server::{AppFlowyEncryption, EncryptionImpl};
 use flowy_server_pub::supabase_config::SupabaseConfiguration;
 use flowy_storage::{FileStoragePlan, StorageObject};
 use flowy_user_pub::cloud::UserCloudService;
-use lib_infra::future::FutureResult;
+use lib_infra::async_trait::async_trait;
 
 use crate::setup_log;
 
@@ -156,18 +156,19 @@ pub fn third_party_sign_up_param(uuid: String) -> HashMap<String, String> {
 
 pub struct TestFileStoragePlan;
 
+#[async_trait]
 impl FileStoragePlan for TestFileStoragePlan {
-  fn storage_size(&self) -> FutureResult<u64, FlowyError> {
+  async fn storage_size(&self) -> Result<u64, FlowyError> {
     // 1 GB
-    FutureResult::new(async { Ok(1024 * 1024 * 1024) })
+    Ok(1024 * 1024 * 1024)
   }
 
-  fn maximum_file_size(&self) -> FutureResult<u64, FlowyError> {
+  async fn maximum_file_size(&self) -> Result<u64, FlowyError> {
     // 5 MB
-    FutureResult::new(async { Ok(5 * 1024 * 1024) })
+    Ok(5 * 1024 * 1024)
   }
 
-  fn check_upload_object(&self, _object: &StorageObject) -> FutureResult<(), FlowyError> {
-    FutureResult::new(async { Ok(()) })
+  async fn check_upload_object(&self, _object: &StorageObject) -> Result<(), FlowyError> {
+    Ok(())
   }
 }
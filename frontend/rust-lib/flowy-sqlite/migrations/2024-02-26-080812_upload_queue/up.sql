@@ -0,0 +1,13 @@
+-- Your SQL goes here
+CREATE TABLE upload_queue_table (
+  id TEXT NOT NULL PRIMARY KEY,
+  workspace_id TEXT NOT NULL,
+  file_name TEXT NOT NULL,
+  file_path TEXT,
+  bytes BLOB,
+  mime TEXT NOT NULL DEFAULT '',
+  url TEXT NOT NULL DEFAULT '',
+  status INTEGER NOT NULL DEFAULT 0,
+  error TEXT NOT NULL DEFAULT '',
+  created_at BIGINT NOT NULL DEFAULT 0
+);
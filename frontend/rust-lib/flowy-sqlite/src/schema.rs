@@ -12,6 +12,21 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    upload_queue_table (id) {
+        id -> Text,
+        workspace_id -> Text,
+        file_name -> Text,
+        file_path -> Nullable<Text>,
+        bytes -> Nullable<Binary>,
+        mime -> Text,
+        url -> Text,
+        status -> Integer,
+        error -> Text,
+        created_at -> BigInt,
+    }
+}
+
 diesel::table! {
     user_data_migration_records (id) {
         id -> Integer,
@@ -48,6 +63,7 @@ diesel::table! {
 
 diesel::allow_tables_to_appear_in_same_query!(
   collab_snapshot,
+  upload_queue_table,
   user_data_migration_records,
   user_table,
   user_workspace_table,
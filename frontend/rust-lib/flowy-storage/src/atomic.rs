@@ -0,0 +1,217 @@
+use std::future::Future;
+use std::path::{Path, PathBuf};
+
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tracing::warn;
+
+use flowy_error::{ErrorCode, FlowyError};
+
+use crate::io_error;
+
+/// Writes `content` to `dest` atomically: readers see either the previous file or the new one,
+/// never a partial file, even if the process dies in the middle of the write. See
+/// [atomic_write_with].
+pub async fn atomic_write(dest: &Path, content: &[u8]) -> Result<(), FlowyError> {
+  atomic_write_with(dest, |temp_path| async move {
+    tokio::fs::write(&temp_path, content)
+      .await
+      .map_err(|err| io_error(err, &temp_path))
+  })
+  .await
+}
+
+/// Writes the chunks of the stream to `dest` atomically and returns the number of bytes written,
+/// see [atomic_write_with].
+pub async fn atomic_write_stream<S>(dest: &Path, mut stream: S) -> Result<u64, FlowyError>
+where
+  S: Stream<Item = Result<Bytes, FlowyError>> + Unpin,
+{
+  atomic_write_with(dest, |temp_path| async move {
+    let io_error = |err| io_error(err, &temp_path);
+    let mut file = File::create(&temp_path).await.map_err(io_error)?;
+    let mut written = 0u64;
+    while let Some(chunk) = stream.next().await {
+      let chunk = chunk?;
+      file.write_all(&chunk).await.map_err(io_error)?;
+      written += chunk.len() as u64;
+    }
+    file.flush().await.map_err(io_error)?;
+    Ok(written)
+  })
+  .await
+}
+
+/// Lets `write` create the content of `dest` in a temporary file next to it, then flushes the
+/// file to the disk and renames it into place. The temporary file is removed if `write` or the
+/// rename fails, `dest` is left untouched. The parent directories of `dest` are created if
+/// needed.
+///
+/// The temporary file is named `.{file name}.{uuid}.tmp`, a write interrupted by a crash leaves a
+/// hidden file the owner of the directory can clean up.
+pub async fn atomic_write_with<T, F, Fut>(dest: &Path, write: F) -> Result<T, FlowyError>
+where
+  F: FnOnce(PathBuf) -> Fut,
+  Fut: Future<Output = Result<T, FlowyError>>,
+{
+  let file_name = file_name(dest)?;
+  if let Some(parent) = dest
+    .parent()
+    .filter(|parent| !parent.as_os_str().is_empty())
+  {
+    tokio::fs::create_dir_all(parent)
+      .await
+      .map_err(|err| io_error(err, parent))?;
+  }
+  let temp_path = dest.with_file_name(temp_file_name(file_name));
+  let result = match write(temp_path.clone()).await {
+    Ok(value) => persist(&temp_path, dest).await.map(|_| value),
+    Err(err) => Err(err),
+  };
+  remove_temp_file(&temp_path).await;
+  result
+}
+
+fn file_name(dest: &Path) -> Result<&str, FlowyError> {
+  dest
+    .file_name()
+    .and_then(|name| name.to_str())
+    .ok_or_else(|| {
+      FlowyError::new(
+        ErrorCode::InvalidParams,
+        format!("{} is not a file path", dest.display()),
+      )
+    })
+}
+
+fn temp_file_name(file_name: &str) -> String {
+  format!(".{}.{}.tmp", file_name, uuid::Uuid::new_v4())
+}
+
+async fn remove_temp_file(temp_path: &Path) {
+  if let Err(err) = tokio::fs::remove_file(temp_path).await {
+    if err.kind() != std::io::ErrorKind::NotFound {
+      warn!("remove temporary file {:?} failed: {}", temp_path, err);
+    }
+  }
+}
+
+async fn persist(temp_path: &Path, dest: &Path) -> Result<(), FlowyError> {
+  sync_file(temp_path)
+    .await
+    .map_err(|err| io_error(err, temp_path))?;
+  match rename(temp_path, dest).await {
+    Ok(()) => {},
+    // `dest` is on another file system than its directory, for example a file bind mounted into
+    // a container. It can only be overwritten in place, which is not atomic.
+    Err(err) if is_cross_device(&err) => copy_over(temp_path, dest)
+      .await
+      .map_err(|err| io_error(err, dest))?,
+    Err(err) => return Err(io_error(err, dest)),
+  }
+  // Flush the directory entry too, otherwise the rename itself may not survive a power loss.
+  #[cfg(unix)]
+  if let Some(parent) = dest.parent() {
+    if let Ok(dir) = File::open(parent).await {
+      let _ = dir.sync_all().await;
+    }
+  }
+  Ok(())
+}
+
+async fn sync_file(path: &Path) -> std::io::Result<()> {
+  OpenOptions::new()
+    .write(true)
+    .open(path)
+    .await?
+    .sync_all()
+    .await
+}
+
+async fn copy_over(temp_path: &Path, dest: &Path) -> std::io::Result<()> {
+  tokio::fs::copy(temp_path, dest).await?;
+  sync_file(dest).await
+}
+
+/// Returns true if the rename failed because the paths are on different file systems: `EXDEV` on
+/// unix, `ERROR_NOT_SAME_DEVICE` on Windows.
+fn is_cross_device(err: &std::io::Error) -> bool {
+  #[cfg(unix)]
+  const CROSS_DEVICE: i32 = 18;
+  #[cfg(windows)]
+  const CROSS_DEVICE: i32 = 17;
+  #[cfg(any(unix, windows))]
+  return err.raw_os_error() == Some(CROSS_DEVICE);
+  #[cfg(not(any(unix, windows)))]
+  {
+    let _ = err;
+    false
+  }
+}
+
+/// Renames the file over `dest`. Windows replaces an existing file too, but fails while another
+/// handle to it is open, for example a reader or an antivirus scan, so it's retried a few times.
+async fn rename(from: &Path, to: &Path) -> std::io::Result<()> {
+  #[cfg(windows)]
+  {
+    const RETRIES: u32 = 5;
+    let mut attempt = 0;
+    loop {
+      match tokio::fs::rename(from, to).await {
+        Err(err) if err.kind() == std::io::ErrorKind::PermissionDenied && attempt < RETRIES => {
+          attempt += 1;
+          tokio::time::sleep(std::time::Duration::from_millis(10 * attempt as u64)).await;
+        },
+        result => return result,
+      }
+    }
+  }
+  #[cfg(not(windows))]
+  tokio::fs::rename(from, to).await
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn interrupted_write_keeps_original_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let dest = dir.path().join("nested").join("object.txt");
+    atomic_write(&dest, b"original").await.unwrap();
+    assert_eq!(std::fs::read(&dest).unwrap(), b"original");
+
+    // The write fails after writing a part of the new content.
+    let result = atomic_write_with(&dest, |temp_path| async move {
+      tokio::fs::write(&temp_path, b"trunc").await?;
+      Err::<(), _>(FlowyError::new(ErrorCode::Internal, "interrupted"))
+    })
+    .await;
+    assert!(result.is_err());
+    assert_eq!(std::fs::read(&dest).unwrap(), b"original");
+    let files = std::fs::read_dir(dest.parent().unwrap()).unwrap().count();
+    assert_eq!(files, 1);
+
+    let chunks = futures::stream::iter(vec![
+      Ok(Bytes::from_static(b"new ")),
+      Ok(Bytes::from_static(b"content")),
+    ]);
+    assert_eq!(atomic_write_stream(&dest, chunks).await.unwrap(), 11);
+    assert_eq!(std::fs::read(&dest).unwrap(), b"new content");
+  }
+
+  #[tokio::test]
+  async fn failed_rename_is_returned_test() {
+    let dir = tempfile::tempdir().unwrap();
+    // A file can't replace a directory, and that is not a cross-device error.
+    let dest = dir.path().join("object.txt");
+    std::fs::create_dir_all(dest.join("child")).unwrap();
+    let err = atomic_write(&dest, b"content").await.unwrap_err();
+    assert!(!err.is_record_not_found());
+    assert!(dest.join("child").is_dir());
+    let files = std::fs::read_dir(dir.path()).unwrap().count();
+    assert_eq!(files, 1);
+  }
+}
@@ -0,0 +1,208 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use futures::{stream, StreamExt};
+use tracing::error;
+
+use flowy_error::FlowyError;
+
+use crate::{put_if_absent, read_storage_object, ObjectStorageService, StorageObject};
+
+/// The number of objects deleted at the same time by the default implementation of
+/// [crate::ObjectStorageService::delete_objects].
+pub const DEFAULT_DELETE_CONCURRENCY: usize = 8;
+
+/// The number of objects uploaded at the same time by the default implementations of
+/// [crate::ObjectStorageService::put_objects] and
+/// [crate::ObjectStorageService::put_objects_transactional].
+pub const DEFAULT_UPLOAD_CONCURRENCY: usize = 4;
+
+/// Runs the deletions with at most `concurrency` of them in flight. The returned results line up
+/// with the `deletions`.
+pub(crate) async fn buffered_deletions<F>(
+  deletions: Vec<F>,
+  concurrency: usize,
+) -> Vec<Result<(), FlowyError>>
+where
+  F: Future<Output = Result<(), FlowyError>>,
+{
+  stream::iter(deletions)
+    .buffered(concurrency.max(1))
+    .collect()
+    .await
+}
+
+/// Uploads every object with at most `concurrency` uploads in flight, or none of them: once an
+/// upload fails the pending ones are not started, and the objects written by the batch are
+/// deleted before the error is returned. The objects that were already stored, the same content
+/// uploaded before, are left in place.
+pub(crate) async fn put_objects_transactional<S>(
+  service: &S,
+  objects: Vec<StorageObject>,
+  concurrency: usize,
+) -> Result<Vec<String>, FlowyError>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  let failed = AtomicBool::new(false);
+  let uploads = objects
+    .iter()
+    .map(|object| {
+      let failed = &failed;
+      async move {
+        if failed.load(Ordering::SeqCst) {
+          return Err(None);
+        }
+        let result = put_new_object(service, object).await;
+        if result.is_err() {
+          failed.store(true, Ordering::SeqCst);
+        }
+        result.map_err(Some)
+      }
+    })
+    .collect::<Vec<_>>();
+  let results = stream::iter(uploads)
+    .buffered(concurrency.max(1))
+    .collect::<Vec<_>>()
+    .await;
+
+  let mut urls = vec![];
+  let mut written = vec![];
+  let mut first_error = None;
+  for result in results {
+    match result {
+      Ok((url, created)) => {
+        if created {
+          written.push(url.clone());
+        }
+        urls.push(url);
+      },
+      Err(Some(err)) => {
+        first_error.get_or_insert(err);
+      },
+      // Not attempted after an earlier failure.
+      Err(None) => {},
+    }
+  }
+  let err = match first_error {
+    None => return Ok(urls),
+    Some(err) => err,
+  };
+
+  let left = match service.delete_objects(written.clone()).await {
+    Ok(results) => written
+      .into_iter()
+      .zip(results)
+      .filter_map(|(url, result)| result.err().map(|_| url))
+      .collect::<Vec<_>>(),
+    Err(_) => written,
+  };
+  if left.is_empty() {
+    return Err(err);
+  }
+  error!("failed to roll back the uploads of {:?}", left);
+  Err(FlowyError::new(
+    err.code.clone(),
+    format!(
+      "{}, and {} of the uploaded objects could not be deleted",
+      err.msg,
+      left.len()
+    ),
+  ))
+}
+
+/// Returns the url of the object, and whether the object was written by this upload.
+async fn put_new_object<S>(
+  service: &S,
+  object: &StorageObject,
+) -> Result<(String, bool), FlowyError>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  let (identity, value) = read_storage_object(object).await?;
+  let url = service.get_object_url(identity).await?;
+  let created = put_if_absent(service, &url, &value).await?;
+  Ok((url, created))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use flowy_error::ErrorCode;
+
+  use super::*;
+  use crate::{InMemoryObjectStorage, ObjectStorageService, StorageOperation};
+
+  #[tokio::test]
+  async fn delete_objects_results_line_up_with_urls() {
+    let storage = InMemoryObjectStorage::new();
+    storage.set_latency(StorageOperation::Delete, Duration::from_millis(5));
+    // Deleting a missing object succeeds, only the invalid urls fail.
+    let urls = (0..20)
+      .map(|i| {
+        if i % 3 == 0 {
+          format!("invalid-{}", i)
+        } else {
+          format!("memory://w1/{}.txt", i)
+        }
+      })
+      .collect::<Vec<_>>();
+
+    let results = storage.delete_objects(urls.clone()).await.unwrap();
+    assert_eq!(results.len(), urls.len());
+    for (url, result) in urls.iter().zip(results) {
+      match result {
+        Ok(()) => assert!(url.starts_with("memory://")),
+        Err(err) => {
+          assert!(url.starts_with("invalid"));
+          assert_eq!(err.code, ErrorCode::InvalidURL);
+        },
+      }
+    }
+    let max_in_flight = storage.max_concurrent_calls(StorageOperation::Delete);
+    assert!(max_in_flight <= DEFAULT_DELETE_CONCURRENCY);
+  }
+
+  fn text_object(file_name: &str, content: &str) -> StorageObject {
+    StorageObject::from_bytes("w1", file_name, content.to_string(), "text/plain".into())
+  }
+
+  #[tokio::test]
+  async fn put_objects_transactional_test() {
+    let storage = InMemoryObjectStorage::new();
+    let urls = storage
+      .put_objects_transactional(vec![text_object("a.txt", "shared")])
+      .await
+      .unwrap();
+    assert_eq!(urls.len(), 1);
+
+    let dir = tempfile::tempdir().unwrap();
+    let missing = dir.path().join("missing.txt");
+    let objects = vec![
+      text_object("a.txt", "shared"),
+      text_object("b.txt", "new"),
+      StorageObject::from_file("w1", "missing.txt", missing.display()),
+    ];
+    let err = storage
+      .put_objects_transactional(objects)
+      .await
+      .unwrap_err();
+    assert_eq!(err.code, ErrorCode::RecordNotFound);
+    // The object written by the batch is deleted, the one stored before is kept.
+    assert_eq!(storage.len(), 1);
+    assert!(storage.object(&urls[0]).is_some());
+
+    let objects = vec![
+      text_object("b.txt", "new"),
+      StorageObject::from_file("w1", "missing.txt", missing.display()),
+    ];
+    let results = storage.put_objects(objects).await.unwrap();
+    assert!(results[0].is_ok());
+    assert_eq!(
+      results[1].as_ref().unwrap_err().code,
+      ErrorCode::RecordNotFound
+    );
+    assert_eq!(storage.len(), 2);
+  }
+}
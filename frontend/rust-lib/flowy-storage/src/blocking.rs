@@ -0,0 +1,150 @@
+use std::future::Future;
+use std::sync::Arc;
+
+use tokio::runtime::{Handle, Runtime};
+
+use flowy_error::{ErrorCode, FlowyError};
+
+use crate::{ObjectIdentity, ObjectMeta, ObjectStorageService, ObjectValue};
+
+enum BlockingRuntime {
+  Owned(Runtime),
+  Borrowed(Handle),
+}
+
+/// A synchronous façade over an [ObjectStorageService], for the FFI and CLI call sites and the
+/// tests that don't run in a tokio runtime. Each method blocks the calling thread until the
+/// operation completes.
+///
+/// The methods must not be called from an async context, blocking a thread of the runtime could
+/// deadlock it and tokio panics when a runtime is started from another one. They return an
+/// [ErrorCode::Internal] error instead. A storage created with [Self::new] must not be dropped
+/// in an async context either, tokio panics when a runtime is dropped there.
+pub struct BlockingObjectStorage<S: ?Sized> {
+  inner: Arc<S>,
+  runtime: BlockingRuntime,
+}
+
+impl<S> BlockingObjectStorage<S>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  /// Runs the operations on a runtime of its own, started on the calling thread.
+  pub fn new(inner: Arc<S>) -> Result<Self, FlowyError> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+      .enable_all()
+      .build()
+      .map_err(|err| {
+        FlowyError::new(
+          ErrorCode::Internal,
+          format!("failed to start the storage runtime: {}", err),
+        )
+      })?;
+    Ok(Self {
+      inner,
+      runtime: BlockingRuntime::Owned(runtime),
+    })
+  }
+
+  /// Runs the operations on an existing runtime, for example the one of the app.
+  pub fn with_handle(inner: Arc<S>, handle: Handle) -> Self {
+    Self {
+      inner,
+      runtime: BlockingRuntime::Borrowed(handle),
+    }
+  }
+
+  pub fn inner(&self) -> &Arc<S> {
+    &self.inner
+  }
+
+  pub fn get_object_url(&self, object_id: ObjectIdentity) -> Result<String, FlowyError> {
+    self.block_on(self.inner.get_object_url(object_id))
+  }
+
+  pub fn put_object(&self, url: String, object_value: ObjectValue) -> Result<(), FlowyError> {
+    self.block_on(self.inner.put_object(url, object_value))
+  }
+
+  pub fn get_object(&self, url: String) -> Result<ObjectValue, FlowyError> {
+    self.block_on(self.inner.get_object(url))
+  }
+
+  pub fn head_object(&self, url: String) -> Result<ObjectMeta, FlowyError> {
+    self.block_on(self.inner.head_object(url))
+  }
+
+  pub fn object_exists(&self, url: String) -> Result<bool, FlowyError> {
+    self.block_on(self.inner.object_exists(url))
+  }
+
+  pub fn delete_object(&self, url: String) -> Result<(), FlowyError> {
+    self.block_on(self.inner.delete_object(url))
+  }
+
+  fn block_on<T, F>(&self, fut: F) -> Result<T, FlowyError>
+  where
+    F: Future<Output = Result<T, FlowyError>>,
+  {
+    if Handle::try_current().is_ok() {
+      return Err(FlowyError::new(
+        ErrorCode::Internal,
+        "BlockingObjectStorage can't be used from an async context, use the async storage instead",
+      ));
+    }
+    match &self.runtime {
+      BlockingRuntime::Owned(runtime) => runtime.block_on(fut),
+      BlockingRuntime::Borrowed(handle) => handle.block_on(fut),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{memory_object_value, InMemoryObjectStorage};
+
+  #[test]
+  fn blocking_put_get_delete_test() {
+    let storage = BlockingObjectStorage::new(Arc::new(InMemoryObjectStorage::new())).unwrap();
+    let url = "memory://w1/1.txt".to_string();
+    storage
+      .put_object(url.clone(), memory_object_value("1.txt", "hello"))
+      .unwrap();
+    assert_eq!(
+      storage.get_object(url.clone()).unwrap().raw.as_ref(),
+      b"hello"
+    );
+    assert!(storage.object_exists(url.clone()).unwrap());
+    storage.delete_object(url.clone()).unwrap();
+    assert!(!storage.object_exists(url).unwrap());
+  }
+
+  #[test]
+  fn blocking_with_handle_test() {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+      .worker_threads(1)
+      .build()
+      .unwrap();
+    let storage = BlockingObjectStorage::with_handle(
+      Arc::new(InMemoryObjectStorage::new()),
+      runtime.handle().clone(),
+    );
+    let url = "memory://w1/1.txt".to_string();
+    storage
+      .put_object(url.clone(), memory_object_value("1.txt", "hello"))
+      .unwrap();
+    assert!(storage.object_exists(url).unwrap());
+  }
+
+  #[tokio::test]
+  async fn blocking_in_async_context_test() {
+    let storage =
+      BlockingObjectStorage::with_handle(Arc::new(InMemoryObjectStorage::new()), Handle::current());
+    let err = storage
+      .object_exists("memory://w1/1.txt".to_string())
+      .unwrap_err();
+    assert_eq!(err.code, ErrorCode::Internal);
+    assert!(err.msg.contains("async context"));
+  }
+}
@@ -0,0 +1,615 @@
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use mime::Mime;
+use parking_lot::Mutex;
+
+use flowy_error::FlowyError;
+
+use crate::{
+  slice_object_range, DeleteReport, DownloadProgressCallback, HealthStatus, ListOptions,
+  ObjectIdentity, ObjectMeta, ObjectStorageService, ObjectStream, ObjectValue, PartETag,
+  ProgressCallback, StorageErrorExt, StorageErrorKind, UploadId, VersionMeta,
+};
+
+/// Controls how much memory [CachingObjectStorage] can use.
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+  /// The total size of the cached objects. The least recently used objects are evicted when the
+  /// cache grows over it.
+  pub max_total_bytes: usize,
+  /// Objects larger than this are never cached, so that a few large objects can't evict all the
+  /// small ones.
+  pub max_object_bytes: usize,
+}
+
+impl Default for CacheConfig {
+  fn default() -> Self {
+    Self {
+      max_total_bytes: 32 * 1024 * 1024,
+      max_object_bytes: 1024 * 1024,
+    }
+  }
+}
+
+/// A least recently used cache of [ObjectValue]s keyed by url, bounded by the size of the
+/// objects instead of their number.
+struct ObjectCache {
+  config: CacheConfig,
+  entries: HashMap<String, CacheEntry>,
+  /// The urls ordered from the least to the most recently used.
+  recency: BTreeMap<u64, String>,
+  total_bytes: usize,
+  tick: u64,
+  /// Incremented on every invalidation. A download that started before an invalidation must not
+  /// populate the cache, since its content might be stale.
+  generation: u64,
+}
+
+struct CacheEntry {
+  value: ObjectValue,
+  tick: u64,
+}
+
+impl ObjectCache {
+  fn new(config: CacheConfig) -> Self {
+    Self {
+      config,
+      entries: HashMap::new(),
+      recency: BTreeMap::new(),
+      total_bytes: 0,
+      tick: 0,
+      generation: 0,
+    }
+  }
+
+  fn get(&mut self, url: &str) -> Option<ObjectValue> {
+    self.tick += 1;
+    let tick = self.tick;
+    let entry = self.entries.get_mut(url)?;
+    self.recency.remove(&entry.tick);
+    self.recency.insert(tick, url.to_string());
+    entry.tick = tick;
+    Some(entry.value.clone())
+  }
+
+  fn insert(&mut self, url: String, value: ObjectValue, generation: u64) {
+    let size = value.raw.len();
+    if generation != self.generation
+      || size > self.config.max_object_bytes
+      || size > self.config.max_total_bytes
+    {
+      return;
+    }
+
+    self.remove(&url);
+    while self.total_bytes + size > self.config.max_total_bytes {
+      match self.recency.pop_first() {
+        Some((_, lru_url)) => {
+          if let Some(entry) = self.entries.remove(&lru_url) {
+            self.total_bytes -= entry.value.raw.len();
+          }
+        },
+        None => break,
+      }
+    }
+
+    self.tick += 1;
+    self.recency.insert(self.tick, url.clone());
+    self.total_bytes += size;
+    self.entries.insert(
+      url,
+      CacheEntry {
+        value,
+        tick: self.tick,
+      },
+    );
+  }
+
+  fn remove(&mut self, url: &str) {
+    if let Some(entry) = self.entries.remove(url) {
+      self.recency.remove(&entry.tick);
+      self.total_bytes -= entry.value.raw.len();
+    }
+  }
+
+  fn invalidate(&mut self, url: &str) {
+    self.generation += 1;
+    self.remove(url);
+  }
+}
+
+/// An [ObjectStorageService] that keeps the recently downloaded objects in memory, so opening
+/// the same document again doesn't download its attachments again.
+///
+/// Writing or deleting an object through this storage invalidates its cached content, both
+/// before and after the operation, so a download racing with the write can't cache stale content.
+pub struct CachingObjectStorage<S: ?Sized> {
+  inner: Arc<S>,
+  cache: Arc<Mutex<ObjectCache>>,
+}
+
+impl<S> CachingObjectStorage<S>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  pub fn new(inner: Arc<S>, config: CacheConfig) -> Self {
+    Self {
+      inner,
+      cache: Arc::new(Mutex::new(ObjectCache::new(config))),
+    }
+  }
+
+  /// Returns the total size of the cached objects.
+  pub fn cached_bytes(&self) -> usize {
+    self.cache.lock().total_bytes
+  }
+
+  pub fn clear(&self) {
+    let mut cache = self.cache.lock();
+    cache.generation += 1;
+    cache.entries.clear();
+    cache.recency.clear();
+    cache.total_bytes = 0;
+  }
+
+  /// Runs `fut` with the cached content of `urls` invalidated before and after it.
+  async fn invalidating<T>(
+    &self,
+    urls: Vec<String>,
+    fut: impl Future<Output = Result<T, FlowyError>>,
+  ) -> Result<T, FlowyError>
+  where
+    T: Send + Sync + 'static,
+  {
+    invalidate_urls(&self.cache, &urls);
+    let result = fut.await;
+    invalidate_urls(&self.cache, &urls);
+    result
+  }
+}
+
+fn invalidate_urls(cache: &Mutex<ObjectCache>, urls: &[String]) {
+  let mut cache = cache.lock();
+  for url in urls {
+    cache.invalidate(url);
+  }
+}
+
+/// The object was deleted behind the back of the cache, for example from another device, so the
+/// cached content must not be served anymore.
+fn evict_if_not_found(cache: &Mutex<ObjectCache>, url: &str, err: FlowyError) -> FlowyError {
+  if err.is_storage_kind(StorageErrorKind::NotFound) {
+    cache.lock().invalidate(url);
+  }
+  err
+}
+
+#[async_trait]
+impl<S> ObjectStorageService for CachingObjectStorage<S>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  async fn get_object_url(&self, object_id: ObjectIdentity) -> Result<String, FlowyError> {
+    self.inner.get_object_url(object_id).await
+  }
+
+  async fn put_object(&self, url: String, object_value: ObjectValue) -> Result<(), FlowyError> {
+    let fut = self.inner.put_object(url.clone(), object_value);
+    self.invalidating(vec![url], fut).await
+  }
+
+  async fn put_object_with_progress(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    progress: ProgressCallback,
+  ) -> Result<(), FlowyError> {
+    let fut = self
+      .inner
+      .put_object_with_progress(url.clone(), object_value, progress);
+    self.invalidating(vec![url], fut).await
+  }
+
+  fn supports_object_ttl(&self) -> bool {
+    self.inner.supports_object_ttl()
+  }
+
+  async fn put_object_with_ttl(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    ttl: Duration,
+  ) -> Result<(), FlowyError> {
+    let fut = self
+      .inner
+      .put_object_with_ttl(url.clone(), object_value, ttl);
+    self.invalidating(vec![url], fut).await
+  }
+
+  async fn put_object_if_absent(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+  ) -> Result<(), FlowyError> {
+    let fut = self.inner.put_object_if_absent(url.clone(), object_value);
+    self.invalidating(vec![url], fut).await
+  }
+
+  async fn put_object_if_match(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    etag: String,
+  ) -> Result<(), FlowyError> {
+    let fut = self
+      .inner
+      .put_object_if_match(url.clone(), object_value, etag);
+    self.invalidating(vec![url], fut).await
+  }
+
+  async fn delete_object(&self, url: String) -> Result<(), FlowyError> {
+    let fut = self.inner.delete_object(url.clone());
+    self.invalidating(vec![url], fut).await
+  }
+
+  async fn delete_objects(
+    &self,
+    urls: Vec<String>,
+  ) -> Result<Vec<Result<(), FlowyError>>, FlowyError> {
+    let fut = self.inner.delete_objects(urls.clone());
+    self.invalidating(urls, fut).await
+  }
+
+  async fn delete_workspace_objects(&self, workspace_id: &str) -> Result<DeleteReport, FlowyError> {
+    // The urls of the deleted objects are not known, the whole cache is invalidated.
+    self.clear();
+    let result = self.inner.delete_workspace_objects(workspace_id).await;
+    self.clear();
+    result
+  }
+
+  async fn list_objects(
+    &self,
+    workspace_id: &str,
+    prefix: Option<&str>,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    self.inner.list_objects(workspace_id, prefix).await
+  }
+
+  async fn list_objects_with_options(
+    &self,
+    workspace_id: &str,
+    options: &ListOptions,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    self
+      .inner
+      .list_objects_with_options(workspace_id, options)
+      .await
+  }
+
+  async fn get_object(&self, url: String) -> Result<ObjectValue, FlowyError> {
+    let (cached, generation) = {
+      let mut cache = self.cache.lock();
+      (cache.get(&url), cache.generation)
+    };
+    if let Some(value) = cached {
+      return Ok(value);
+    }
+    let value = self.inner.get_object(url.clone()).await?;
+    self.cache.lock().insert(url, value.clone(), generation);
+    Ok(value)
+  }
+
+  async fn get_object_if_modified(
+    &self,
+    url: String,
+    etag: Option<String>,
+  ) -> Result<Option<(ObjectValue, String)>, FlowyError> {
+    let generation = self.cache.lock().generation;
+    let modified = self
+      .inner
+      .get_object_if_modified(url.clone(), etag)
+      .await
+      .map_err(|err| evict_if_not_found(&self.cache, &url, err))?;
+    // The cached content is left as is if the object didn't change.
+    if let Some((value, _)) = &modified {
+      self.cache.lock().insert(url, value.clone(), generation);
+    }
+    Ok(modified)
+  }
+
+  async fn get_object_stream(&self, url: String) -> Result<ObjectStream, FlowyError> {
+    let cached = self.cache.lock().get(&url);
+    match cached {
+      Some(value) => Ok(value.decompress()?.into()),
+      None => self.inner.get_object_stream(url).await,
+    }
+  }
+
+  async fn get_object_with_progress(
+    &self,
+    url: String,
+    progress: DownloadProgressCallback,
+  ) -> Result<ObjectValue, FlowyError> {
+    let (cached, generation) = {
+      let mut cache = self.cache.lock();
+      (cache.get(&url), cache.generation)
+    };
+    if let Some(value) = cached {
+      let value = value.decompress()?;
+      let len = value.raw.len() as u64;
+      progress(len, Some(len));
+      return Ok(value);
+    }
+    let value = self
+      .inner
+      .get_object_with_progress(url.clone(), progress)
+      .await?;
+    self.cache.lock().insert(url, value.clone(), generation);
+    Ok(value)
+  }
+
+  fn supports_range_requests(&self) -> bool {
+    self.inner.supports_range_requests()
+  }
+
+  async fn get_object_range(
+    &self,
+    url: String,
+    start: u64,
+    end: Option<u64>,
+  ) -> Result<ObjectValue, FlowyError> {
+    let cached = self.cache.lock().get(&url);
+    match cached {
+      Some(value) => slice_object_range(value, start, end),
+      None => self.inner.get_object_range(url, start, end).await,
+    }
+  }
+
+  async fn head_object(&self, url: String) -> Result<ObjectMeta, FlowyError> {
+    self
+      .inner
+      .head_object(url.clone())
+      .await
+      .map_err(|err| evict_if_not_found(&self.cache, &url, err))
+  }
+
+  async fn object_exists(&self, url: String) -> Result<bool, FlowyError> {
+    let exists = self.inner.object_exists(url.clone()).await?;
+    if !exists {
+      self.cache.lock().invalidate(&url);
+    }
+    Ok(exists)
+  }
+
+  async fn presign_get_url(&self, url: String, expires_in: Duration) -> Result<String, FlowyError> {
+    self.inner.presign_get_url(url, expires_in).await
+  }
+
+  async fn presign_put_url(&self, url: String, expires_in: Duration) -> Result<String, FlowyError> {
+    self.inner.presign_put_url(url, expires_in).await
+  }
+
+  fn supports_copy_object(&self) -> bool {
+    self.inner.supports_copy_object()
+  }
+
+  async fn copy_object(
+    &self,
+    src_url: String,
+    dst_identity: ObjectIdentity,
+  ) -> Result<String, FlowyError> {
+    let dst_url = self.inner.copy_object(src_url, dst_identity).await?;
+    self.cache.lock().invalidate(&dst_url);
+    Ok(dst_url)
+  }
+
+  async fn trash_object(&self, url: String) -> Result<(), FlowyError> {
+    let fut = self.inner.trash_object(url.clone());
+    self.invalidating(vec![url], fut).await
+  }
+
+  async fn restore_object(&self, url: String) -> Result<(), FlowyError> {
+    let fut = self.inner.restore_object(url.clone());
+    self.invalidating(vec![url], fut).await
+  }
+
+  async fn purge_trash(&self, older_than: Duration) -> Result<Vec<String>, FlowyError> {
+    self.inner.purge_trash(older_than).await
+  }
+
+  fn supports_versioning(&self) -> bool {
+    self.inner.supports_versioning()
+  }
+
+  async fn list_versions(&self, url: String) -> Result<Vec<VersionMeta>, FlowyError> {
+    self.inner.list_versions(url).await
+  }
+
+  async fn get_object_version(
+    &self,
+    url: String,
+    version_id: String,
+  ) -> Result<ObjectValue, FlowyError> {
+    self.inner.get_object_version(url, version_id).await
+  }
+
+  async fn restore_version(&self, url: String, version_id: String) -> Result<(), FlowyError> {
+    let fut = self.inner.restore_version(url.clone(), version_id);
+    self.invalidating(vec![url], fut).await
+  }
+
+  fn supports_multipart(&self) -> bool {
+    self.inner.supports_multipart()
+  }
+
+  async fn initiate_multipart(&self, url: String, mime: Mime) -> Result<UploadId, FlowyError> {
+    self.inner.initiate_multipart(url, mime).await
+  }
+
+  async fn upload_part(
+    &self,
+    url: String,
+    upload_id: UploadId,
+    part_number: u32,
+    bytes: Bytes,
+  ) -> Result<PartETag, FlowyError> {
+    self
+      .inner
+      .upload_part(url, upload_id, part_number, bytes)
+      .await
+  }
+
+  async fn complete_multipart(
+    &self,
+    url: String,
+    upload_id: UploadId,
+    parts: Vec<PartETag>,
+  ) -> Result<(), FlowyError> {
+    let fut = self.inner.complete_multipart(url.clone(), upload_id, parts);
+    self.invalidating(vec![url], fut).await
+  }
+
+  async fn abort_multipart(&self, url: String, upload_id: UploadId) -> Result<(), FlowyError> {
+    self.inner.abort_multipart(url, upload_id).await
+  }
+
+  async fn health_check(&self) -> Result<HealthStatus, FlowyError> {
+    self.inner.health_check().await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{InMemoryObjectStorage, StorageOperation};
+
+  fn value(len: usize, byte: u8) -> ObjectValue {
+    ObjectValue {
+      raw: vec![byte; len].into(),
+      mime: mime::APPLICATION_OCTET_STREAM,
+      content_encoding: None,
+    }
+  }
+
+  fn caching_storage(
+    max_total_bytes: usize,
+    max_object_bytes: usize,
+  ) -> (
+    Arc<InMemoryObjectStorage>,
+    CachingObjectStorage<InMemoryObjectStorage>,
+  ) {
+    let inner = Arc::new(InMemoryObjectStorage::new());
+    let storage = CachingObjectStorage::new(
+      inner.clone(),
+      CacheConfig {
+        max_total_bytes,
+        max_object_bytes,
+      },
+    );
+    (inner, storage)
+  }
+
+  #[tokio::test]
+  async fn lru_eviction_by_size_test() {
+    let (inner, storage) = caching_storage(100, 60);
+    for (url, len) in [
+      ("memory://w1/a", 40),
+      ("memory://w1/b", 40),
+      ("memory://w1/large", 80),
+    ] {
+      storage
+        .put_object(url.to_string(), value(len, 1))
+        .await
+        .unwrap();
+    }
+
+    storage
+      .get_object("memory://w1/a".to_string())
+      .await
+      .unwrap();
+    storage
+      .get_object("memory://w1/b".to_string())
+      .await
+      .unwrap();
+    // Over the size threshold, so it bypasses the cache instead of evicting the others.
+    storage
+      .get_object("memory://w1/large".to_string())
+      .await
+      .unwrap();
+    storage
+      .get_object("memory://w1/large".to_string())
+      .await
+      .unwrap();
+    assert_eq!(storage.cached_bytes(), 80);
+    assert_eq!(inner.call_count(StorageOperation::Get), 4);
+
+    // "a" becomes the most recently used, so caching "c" evicts "b".
+    storage
+      .get_object("memory://w1/a".to_string())
+      .await
+      .unwrap();
+    storage
+      .put_object("memory://w1/c".to_string(), value(40, 1))
+      .await
+      .unwrap();
+    storage
+      .get_object("memory://w1/c".to_string())
+      .await
+      .unwrap();
+    assert_eq!(inner.call_count(StorageOperation::Get), 5);
+
+    storage
+      .get_object("memory://w1/a".to_string())
+      .await
+      .unwrap();
+    assert_eq!(inner.call_count(StorageOperation::Get), 5);
+    storage
+      .get_object("memory://w1/b".to_string())
+      .await
+      .unwrap();
+    assert_eq!(inner.call_count(StorageOperation::Get), 6);
+    assert!(storage.cached_bytes() <= 100);
+  }
+
+  #[tokio::test]
+  async fn invalidate_on_write_test() {
+    let (_inner, storage) = caching_storage(100, 100);
+    let url = "memory://w1/url".to_string();
+    storage.put_object(url.clone(), value(10, 1)).await.unwrap();
+    assert_eq!(storage.get_object(url.clone()).await.unwrap().raw[0], 1);
+
+    storage.put_object(url.clone(), value(10, 2)).await.unwrap();
+    assert_eq!(storage.get_object(url.clone()).await.unwrap().raw[0], 2);
+    let range = storage
+      .get_object_range(url.clone(), 0, Some(4))
+      .await
+      .unwrap();
+    assert_eq!(range.raw.len(), 5);
+
+    storage.delete_object(url.clone()).await.unwrap();
+    assert!(storage.get_object(url).await.is_err());
+    assert_eq!(storage.cached_bytes(), 0);
+  }
+
+  #[tokio::test]
+  async fn evict_deleted_object_test() {
+    let (inner, storage) = caching_storage(100, 100);
+    let url = "memory://w1/url".to_string();
+    storage.put_object(url.clone(), value(10, 1)).await.unwrap();
+    storage.get_object(url.clone()).await.unwrap();
+    assert_eq!(storage.cached_bytes(), 10);
+
+    // Deleted from another device, the cache learns it from the not found error.
+    inner.delete_object(url.clone()).await.unwrap();
+    let err = storage.head_object(url.clone()).await.unwrap_err();
+    assert!(err.is_storage_kind(StorageErrorKind::NotFound));
+    assert_eq!(storage.cached_bytes(), 0);
+    assert!(storage.get_object(url).await.is_err());
+  }
+}
@@ -0,0 +1,59 @@
+use std::future::Future;
+
+use futures::future::{select, Either};
+
+use flowy_error::{ErrorCode, FlowyError};
+
+pub use tokio_util::sync::CancellationToken;
+
+/// Runs `fut` until it completes or `cancel` is cancelled, whichever comes first. When cancelled,
+/// `fut` is dropped, which aborts the HTTP request it's waiting for, and an
+/// [ErrorCode::Cancelled] error is returned.
+pub async fn cancellable<T, F>(fut: F, cancel: &CancellationToken) -> Result<T, FlowyError>
+where
+  F: Future<Output = Result<T, FlowyError>>,
+{
+  if cancel.is_cancelled() {
+    return Err(cancelled_error());
+  }
+
+  let fut = Box::pin(fut);
+  let cancelled = Box::pin(cancel.cancelled());
+  match select(cancelled, fut).await {
+    Either::Left(_) => Err(cancelled_error()),
+    Either::Right((result, _)) => result,
+  }
+}
+
+pub fn cancelled_error() -> FlowyError {
+  FlowyError::new(ErrorCode::Cancelled, "the operation was cancelled")
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use super::*;
+
+  #[tokio::test]
+  async fn cancellable_test() {
+    let cancel = CancellationToken::new();
+    let result = cancellable(async { Ok::<_, FlowyError>(1) }, &cancel).await;
+    assert_eq!(result.unwrap(), 1);
+
+    let cloned_cancel = cancel.clone();
+    tokio::spawn(async move {
+      tokio::time::sleep(Duration::from_millis(10)).await;
+      cloned_cancel.cancel();
+    });
+    let result = cancellable(
+      async {
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        Ok::<_, FlowyError>(1)
+      },
+      &cancel,
+    )
+    .await;
+    assert_eq!(result.unwrap_err().code, ErrorCode::Cancelled);
+  }
+}
@@ -0,0 +1,540 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bytes::BytesMut;
+use mime::Mime;
+use serde::{Deserialize, Serialize};
+use tracing::trace;
+
+use flowy_error::FlowyError;
+
+use crate::conditional::Precondition;
+use crate::{
+  content_hash, verify_content_hash, Compression, DeleteReport, HealthStatus, ListOptions,
+  ObjectIdentity, ObjectMeta, ObjectStorageService, ObjectValue,
+};
+
+/// The mime type of the manifests stored by [ChunkedObjectStorage] at the url of the objects.
+const MANIFEST_MIME: &str = "application/vnd.appflowy.chunk-manifest+json";
+
+const CHUNK_EXT: &str = "chunk";
+
+/// The sizes of the chunks cut by [content_defined_chunks]. Most chunks are close to `avg_size`,
+/// none is smaller than `min_size` except the last one, and none is larger than `max_size`.
+#[derive(Debug, Clone)]
+pub struct ChunkingConfig {
+  pub min_size: usize,
+  /// Rounded down to a power of two.
+  pub avg_size: usize,
+  pub max_size: usize,
+}
+
+impl Default for ChunkingConfig {
+  fn default() -> Self {
+    Self {
+      min_size: 16 * 1024,
+      avg_size: 64 * 1024,
+      max_size: 256 * 1024,
+    }
+  }
+}
+
+/// The random values the rolling hash mixes the bytes with, generated at compile time with
+/// splitmix64 so that every client cuts the same content at the same offsets.
+const GEAR: [u64; 256] = {
+  let mut table = [0u64; 256];
+  let mut state = 0x9e37_79b9_7f4a_7c15u64;
+  let mut i = 0;
+  while i < 256 {
+    state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    table[i] = z ^ (z >> 31);
+    i += 1;
+  }
+  table
+};
+
+/// A mask of the `bits` highest bits. The high bits of the gear hash depend on the last 64
+/// bytes, the low bits only on the last few ones.
+fn high_bits_mask(bits: u32) -> u64 {
+  !(u64::MAX >> bits)
+}
+
+/// Splits `data` into chunks whose boundaries depend on the content around them (FastCDC), so
+/// inserting or changing a few bytes only changes the chunks around the edit. Returns the ranges
+/// of the chunks, in order and covering the whole data.
+pub fn content_defined_chunks(data: &[u8], config: &ChunkingConfig) -> Vec<Range<usize>> {
+  let min_size = config.min_size.max(1);
+  let max_size = config.max_size.max(min_size);
+  let avg_bits = config.avg_size.max(2).ilog2();
+  // Normalized chunking: cutting is harder before the average size and easier after it, which
+  // narrows the distribution of the chunk sizes.
+  let strict_mask = high_bits_mask(avg_bits + 1);
+  let loose_mask = high_bits_mask(avg_bits - 1);
+
+  let mut chunks = vec![];
+  let mut start = 0;
+  while start < data.len() {
+    let remaining = &data[start..];
+    let end = remaining.len().min(max_size);
+    let normal = (1usize << avg_bits).clamp(min_size, max_size).min(end);
+    let mut cut = end;
+    let mut hash = 0u64;
+    let mut i = min_size.min(end);
+    while i < end {
+      hash = (hash << 1).wrapping_add(GEAR[remaining[i] as usize]);
+      let mask = if i < normal { strict_mask } else { loose_mask };
+      if hash & mask == 0 {
+        cut = i + 1;
+        break;
+      }
+      i += 1;
+    }
+    chunks.push(start..start + cut);
+    start += cut;
+  }
+  chunks
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ChunkRef {
+  id: String,
+  size: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ChunkManifest {
+  mime: String,
+  /// The `Content-Encoding` of the content, see [Compression::content_encoding].
+  content_encoding: Option<String>,
+  size: u64,
+  chunks: Vec<ChunkRef>,
+}
+
+fn manifest_mime() -> Mime {
+  MANIFEST_MIME.parse().unwrap_or(mime::APPLICATION_JSON)
+}
+
+/// The chunks are stored next to the object, in the same directory of the backend, so that the
+/// objects of a workspace share their chunks.
+fn chunk_url(url: &str, chunk_id: &str) -> String {
+  let path = url.split(['?', '#']).next().unwrap_or(url);
+  match path.rsplit_once('/') {
+    Some((dir, _)) => format!("{}/{}.{}", dir, chunk_id, CHUNK_EXT),
+    None => format!("{}.{}", chunk_id, CHUNK_EXT),
+  }
+}
+
+fn is_chunk(meta: &ObjectMeta) -> bool {
+  meta.url.ends_with(&format!(".{}", CHUNK_EXT))
+}
+
+fn without_chunks(objects: Vec<ObjectMeta>) -> Vec<ObjectMeta> {
+  objects
+    .into_iter()
+    .filter(|object| !is_chunk(object))
+    .collect()
+}
+
+/// An [ObjectStorageService] that uploads the objects larger than [ChunkingConfig::max_size] as
+/// [content_defined_chunks]. Each chunk is stored under its content hash and only uploaded if the
+/// backend doesn't have it yet, so re-uploading an edited file only uploads the chunks around the
+/// edits. The url of the object stores a manifest listing its chunks, which
+/// [ObjectStorageService::get_object] reassembles. The objects stored before the wrapper was
+/// added are returned as they are.
+///
+/// The chunks are shared by the objects, deleting an object only deletes its manifest. The
+/// [Self::chunk_ids] of the remaining objects must be referenced when collecting the orphans of
+/// the inner service with [crate::gc_orphans]. The uploads that can't go through
+/// [Self::put_object] store the object as a single blob: multipart uploads, server side copies
+/// and presigned upload urls.
+///
+/// The ETags are the ones of the objects stored at the urls, the manifests of the chunked
+/// objects, so the conditional writes only check the manifests.
+pub struct ChunkedObjectStorage<S: ?Sized> {
+  inner: Arc<S>,
+  config: ChunkingConfig,
+}
+
+impl<S> ChunkedObjectStorage<S>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  pub fn new(inner: Arc<S>, config: ChunkingConfig) -> Self {
+    Self { inner, config }
+  }
+
+  /// Returns the content hashes of the chunks of the object, empty if it's not chunked.
+  pub async fn chunk_ids(&self, url: String) -> Result<Vec<String>, FlowyError> {
+    let value = self.inner.get_object(url).await?;
+    Ok(match parse_manifest(&value)? {
+      Some(manifest) => manifest.chunks.into_iter().map(|chunk| chunk.id).collect(),
+      None => vec![],
+    })
+  }
+
+  /// Uploads the missing chunks, then the manifest with the `precondition`. The chunks uploaded
+  /// for a manifest whose precondition fails are left to [crate::gc_orphans].
+  async fn put_chunked(
+    &self,
+    url: String,
+    value: ObjectValue,
+    precondition: Precondition,
+  ) -> Result<(), FlowyError> {
+    let mut chunks = vec![];
+    let mut uploaded = 0;
+    for range in content_defined_chunks(&value.raw, &self.config) {
+      let content = value.raw.slice(range);
+      let id = content_hash(&content);
+      let chunk_url = chunk_url(&url, &id);
+      if !self.inner.object_exists(chunk_url.clone()).await? {
+        let chunk = ObjectValue {
+          raw: content.clone(),
+          mime: mime::APPLICATION_OCTET_STREAM,
+          content_encoding: None,
+        };
+        self.inner.put_object(chunk_url, chunk).await?;
+        uploaded += 1;
+      }
+      chunks.push(ChunkRef {
+        id,
+        size: content.len() as u64,
+      });
+    }
+    trace!(
+      "uploaded {} of the {} chunks of {}",
+      uploaded,
+      chunks.len(),
+      url
+    );
+
+    let manifest = ChunkManifest {
+      mime: value.mime.to_string(),
+      content_encoding: value
+        .content_encoding
+        .map(|compression| compression.content_encoding().to_string()),
+      size: value.raw.len() as u64,
+      chunks,
+    };
+    let manifest = ObjectValue {
+      raw: serde_json::to_vec(&manifest)
+        .map_err(|err| FlowyError::serde().with_context(err))?
+        .into(),
+      mime: manifest_mime(),
+      content_encoding: None,
+    };
+    precondition.put(&*self.inner, url, manifest).await
+  }
+
+  async fn reassemble(
+    &self,
+    url: &str,
+    manifest: ChunkManifest,
+  ) -> Result<ObjectValue, FlowyError> {
+    let mut raw = BytesMut::with_capacity(manifest.size as usize);
+    for chunk in manifest.chunks {
+      let value = self.inner.get_object(chunk_url(url, &chunk.id)).await?;
+      verify_content_hash(&value.raw, &chunk.id)?;
+      raw.extend_from_slice(&value.raw);
+    }
+    Ok(ObjectValue {
+      raw: raw.freeze(),
+      mime: manifest
+        .mime
+        .parse()
+        .unwrap_or(mime::APPLICATION_OCTET_STREAM),
+      content_encoding: manifest
+        .content_encoding
+        .as_deref()
+        .and_then(Compression::from_content_encoding),
+    })
+  }
+}
+
+fn parse_manifest(value: &ObjectValue) -> Result<Option<ChunkManifest>, FlowyError> {
+  if value.mime.essence_str() != MANIFEST_MIME {
+    return Ok(None);
+  }
+  serde_json::from_slice(&value.raw)
+    .map(Some)
+    .map_err(|err| FlowyError::serde().with_context(err))
+}
+
+#[async_trait]
+impl<S> ObjectStorageService for ChunkedObjectStorage<S>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  async fn get_object_url(&self, object_id: ObjectIdentity) -> Result<String, FlowyError> {
+    self.inner.get_object_url(object_id).await
+  }
+
+  async fn put_object(&self, url: String, object_value: ObjectValue) -> Result<(), FlowyError> {
+    if object_value.raw.len() <= self.config.max_size {
+      return self.inner.put_object(url, object_value).await;
+    }
+    self
+      .put_chunked(url, object_value, Precondition::None)
+      .await
+  }
+
+  async fn put_object_if_absent(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+  ) -> Result<(), FlowyError> {
+    if object_value.raw.len() <= self.config.max_size {
+      return self.inner.put_object_if_absent(url, object_value).await;
+    }
+    self
+      .put_chunked(url, object_value, Precondition::Absent)
+      .await
+  }
+
+  async fn put_object_if_match(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    etag: String,
+  ) -> Result<(), FlowyError> {
+    if object_value.raw.len() <= self.config.max_size {
+      return self
+        .inner
+        .put_object_if_match(url, object_value, etag)
+        .await;
+    }
+    self
+      .put_chunked(url, object_value, Precondition::Match(etag))
+      .await
+  }
+
+  async fn delete_object(&self, url: String) -> Result<(), FlowyError> {
+    self.inner.delete_object(url).await
+  }
+
+  async fn delete_workspace_objects(&self, workspace_id: &str) -> Result<DeleteReport, FlowyError> {
+    self.inner.delete_workspace_objects(workspace_id).await
+  }
+
+  async fn list_objects(
+    &self,
+    workspace_id: &str,
+    prefix: Option<&str>,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    Ok(without_chunks(
+      self.inner.list_objects(workspace_id, prefix).await?,
+    ))
+  }
+
+  async fn list_objects_with_options(
+    &self,
+    workspace_id: &str,
+    options: &ListOptions,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    Ok(without_chunks(
+      self
+        .inner
+        .list_objects_with_options(workspace_id, options)
+        .await?,
+    ))
+  }
+
+  async fn get_object(&self, url: String) -> Result<ObjectValue, FlowyError> {
+    let value = self.inner.get_object(url.clone()).await?;
+    match parse_manifest(&value)? {
+      Some(manifest) => self.reassemble(&url, manifest).await,
+      None => Ok(value),
+    }
+  }
+
+  async fn get_object_if_modified(
+    &self,
+    url: String,
+    etag: Option<String>,
+  ) -> Result<Option<(ObjectValue, String)>, FlowyError> {
+    let (value, etag) = match self.inner.get_object_if_modified(url.clone(), etag).await? {
+      Some(modified) => modified,
+      None => return Ok(None),
+    };
+    match parse_manifest(&value)? {
+      Some(manifest) => Ok(Some((self.reassemble(&url, manifest).await?, etag))),
+      None => Ok(Some((value, etag))),
+    }
+  }
+
+  async fn head_object(&self, url: String) -> Result<ObjectMeta, FlowyError> {
+    let mut meta = self.inner.head_object(url.clone()).await?;
+    if meta.mime.essence_str() == MANIFEST_MIME {
+      if let Some(manifest) = parse_manifest(&self.inner.get_object(url).await?)? {
+        meta.size = manifest.size;
+        meta.mime = manifest
+          .mime
+          .parse()
+          .unwrap_or(mime::APPLICATION_OCTET_STREAM);
+      }
+    }
+    Ok(meta)
+  }
+
+  async fn object_exists(&self, url: String) -> Result<bool, FlowyError> {
+    self.inner.object_exists(url).await
+  }
+
+  async fn health_check(&self) -> Result<HealthStatus, FlowyError> {
+    self.inner.health_check().await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use bytes::Bytes;
+
+  use super::*;
+  use crate::{InMemoryObjectStorage, StorageErrorExt, StorageErrorKind, StorageOperation};
+
+  fn test_config() -> ChunkingConfig {
+    ChunkingConfig {
+      min_size: 256,
+      avg_size: 1024,
+      max_size: 4096,
+    }
+  }
+
+  /// Pseudo-random content from a fixed seed, so the chunk boundaries are the same on every
+  /// run.
+  fn random_content(len: usize) -> Vec<u8> {
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    (0..len)
+      .map(|_| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state >> 56) as u8
+      })
+      .collect()
+  }
+
+  #[test]
+  fn content_defined_chunks_test() {
+    let config = test_config();
+    let data = random_content(64 * 1024);
+    let chunks = content_defined_chunks(&data, &config);
+    assert_eq!(chunks.first().unwrap().start, 0);
+    assert_eq!(chunks.last().unwrap().end, data.len());
+    for (i, chunk) in chunks.iter().enumerate() {
+      assert!(chunk.len() <= config.max_size);
+      if i + 1 < chunks.len() {
+        assert!(chunk.len() >= config.min_size);
+        assert_eq!(chunk.end, chunks[i + 1].start);
+      }
+    }
+    assert!(chunks.len() > 64 * 1024 / config.max_size);
+  }
+
+  #[tokio::test]
+  async fn one_byte_edit_uploads_the_affected_chunks_test() {
+    let inner = Arc::new(InMemoryObjectStorage::new());
+    let storage = ChunkedObjectStorage::new(inner.clone(), test_config());
+    let url = "memory://w1/1.bin".to_string();
+    let mut content = random_content(64 * 1024);
+    let value = |content: &[u8]| ObjectValue {
+      raw: Bytes::copy_from_slice(content),
+      mime: mime::APPLICATION_OCTET_STREAM,
+      content_encoding: None,
+    };
+
+    storage
+      .put_object(url.clone(), value(&content))
+      .await
+      .unwrap();
+    let chunk_count = storage.chunk_ids(url.clone()).await.unwrap().len();
+    // Every chunk and the manifest.
+    assert_eq!(inner.call_count(StorageOperation::Put), chunk_count + 1);
+
+    content[32 * 1024] ^= 0xff;
+    storage
+      .put_object(url.clone(), value(&content))
+      .await
+      .unwrap();
+    let uploaded = inner.call_count(StorageOperation::Put) - chunk_count - 1;
+    // The edited chunk, the chunk after it if the edit moved the boundary, and the manifest.
+    assert!(uploaded <= 3, "uploaded {} objects", uploaded);
+
+    let stored = storage.get_object(url.clone()).await.unwrap();
+    assert_eq!(stored.raw, Bytes::from(content));
+    assert_eq!(stored.mime, mime::APPLICATION_OCTET_STREAM);
+
+    let listed = storage.list_objects("w1", None).await.unwrap();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].url, url);
+  }
+
+  #[tokio::test]
+  async fn small_objects_are_not_chunked_test() {
+    let inner = Arc::new(InMemoryObjectStorage::new());
+    let storage = ChunkedObjectStorage::new(inner.clone(), test_config());
+    let url = "memory://w1/1.txt".to_string();
+    storage
+      .put_object(url.clone(), crate::memory_object_value("1.txt", "hello"))
+      .await
+      .unwrap();
+    assert_eq!(inner.call_count(StorageOperation::Put), 1);
+    assert!(storage.chunk_ids(url.clone()).await.unwrap().is_empty());
+    let stored = storage.get_object(url).await.unwrap();
+    assert_eq!(stored.raw, Bytes::from("hello"));
+  }
+
+  #[tokio::test]
+  async fn conditional_put_of_chunked_object_test() {
+    let storage = ChunkedObjectStorage::new(Arc::new(InMemoryObjectStorage::new()), test_config());
+    let url = "memory://w1/1.bin".to_string();
+    let value = |seed: u8| ObjectValue {
+      raw: random_content(16 * 1024)
+        .into_iter()
+        .map(|byte| byte ^ seed)
+        .collect::<Vec<_>>()
+        .into(),
+      mime: mime::APPLICATION_OCTET_STREAM,
+      content_encoding: None,
+    };
+
+    storage
+      .put_object_if_absent(url.clone(), value(0))
+      .await
+      .unwrap();
+    let err = storage
+      .put_object_if_absent(url.clone(), value(1))
+      .await
+      .unwrap_err();
+    assert!(err.is_storage_kind(StorageErrorKind::PreconditionFailed));
+
+    let meta = storage.head_object(url.clone()).await.unwrap();
+    assert_eq!(meta.size, 16 * 1024);
+    assert_eq!(meta.mime, mime::APPLICATION_OCTET_STREAM);
+    let etag = meta.etag.unwrap();
+    storage
+      .put_object_if_match(url.clone(), value(2), etag.clone())
+      .await
+      .unwrap();
+    let err = storage
+      .put_object_if_match(url.clone(), value(3), etag.clone())
+      .await
+      .unwrap_err();
+    assert!(err.is_storage_kind(StorageErrorKind::PreconditionFailed));
+
+    let (stored, new_etag) = storage
+      .get_object_if_modified(url.clone(), Some(etag))
+      .await
+      .unwrap()
+      .unwrap();
+    assert_eq!(stored.raw, value(2).raw);
+    assert!(storage
+      .get_object_if_modified(url, Some(new_etag))
+      .await
+      .unwrap()
+      .is_none());
+  }
+}
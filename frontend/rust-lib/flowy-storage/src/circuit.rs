@@ -0,0 +1,540 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use mime::Mime;
+use parking_lot::Mutex;
+use tracing::{info, warn};
+
+use flowy_error::FlowyError;
+
+use crate::{
+  storage_error, DeleteReport, HealthStatus, ListOptions, ObjectIdentity, ObjectMeta,
+  ObjectStorageService, ObjectStream, ObjectValue, PartETag, ProgressCallback, StorageErrorExt,
+  StorageErrorKind, UploadId, VersionMeta,
+};
+
+/// Controls when [CircuitBreakerObjectStorage] opens and closes the circuit.
+#[derive(Debug, Clone)]
+pub struct CircuitBreakerConfig {
+  /// The number of consecutive failures that open the circuit.
+  pub failure_threshold: usize,
+  /// The failures only add up if they happen within this window of the first one, a failure
+  /// now and then never opens the circuit.
+  pub failure_window: Duration,
+  /// How long the operations fail fast once the circuit is open. The first operation after it
+  /// is sent to the backend to probe it.
+  pub cooldown: Duration,
+}
+
+impl Default for CircuitBreakerConfig {
+  fn default() -> Self {
+    Self {
+      failure_threshold: 5,
+      failure_window: Duration::from_secs(30),
+      cooldown: Duration::from_secs(30),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+  /// The operations go to the backend.
+  Closed,
+  /// The operations fail with a [StorageErrorKind::Unavailable] error without reaching the
+  /// backend.
+  Open,
+  /// A probe is in flight, the other operations fail fast until it succeeds.
+  HalfOpen,
+}
+
+struct Circuit {
+  state: CircuitState,
+  failures: usize,
+  first_failure_at: Option<Instant>,
+  /// When the circuit opened, or when the probe started.
+  changed_at: Instant,
+}
+
+impl Circuit {
+  fn closed() -> Self {
+    Self {
+      state: CircuitState::Closed,
+      failures: 0,
+      first_failure_at: None,
+      changed_at: Instant::now(),
+    }
+  }
+
+  fn open(&mut self) {
+    self.state = CircuitState::Open;
+    self.changed_at = Instant::now();
+  }
+}
+
+/// Only the failures of the backend itself count, a missing object or a denied access means the
+/// backend is up.
+fn is_backend_failure(error: &FlowyError) -> bool {
+  matches!(
+    error.storage_kind(),
+    Some(StorageErrorKind::Network | StorageErrorKind::Timeout)
+  )
+}
+
+/// An [ObjectStorageService] that stops calling the inner service when it keeps failing. After
+/// [CircuitBreakerConfig::failure_threshold] network failures or timeouts in a row the circuit
+/// opens: the operations fail right away with a [StorageErrorKind::Unavailable] error for the
+/// [CircuitBreakerConfig::cooldown]. Then a single operation probes the backend, the circuit
+/// closes if it succeeds and opens again if it fails.
+///
+/// Put it under the [crate::RetryingObjectStorage] so that each attempt counts. The unavailable
+/// errors are not transient, so an operation isn't retried into an open circuit.
+pub struct CircuitBreakerObjectStorage<S: ?Sized> {
+  inner: Arc<S>,
+  config: CircuitBreakerConfig,
+  circuit: Mutex<Circuit>,
+}
+
+impl<S> CircuitBreakerObjectStorage<S>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  pub fn new(inner: Arc<S>, config: CircuitBreakerConfig) -> Self {
+    Self {
+      inner,
+      config,
+      circuit: Mutex::new(Circuit::closed()),
+    }
+  }
+
+  pub fn state(&self) -> CircuitState {
+    self.circuit.lock().state
+  }
+
+  /// Fails if the circuit is open. Once the cooldown is over the caller becomes the probe, a
+  /// probe that never completes, for example because it was dropped, is replaced after another
+  /// cooldown.
+  fn acquire(&self) -> Result<(), FlowyError> {
+    let mut circuit = self.circuit.lock();
+    if circuit.state == CircuitState::Closed {
+      return Ok(());
+    }
+    let elapsed = circuit.changed_at.elapsed();
+    if elapsed < self.config.cooldown {
+      return Err(storage_error(
+        StorageErrorKind::Unavailable,
+        format!(
+          "the storage backend is unavailable, the next attempt is allowed in {:?}",
+          self.config.cooldown - elapsed
+        ),
+      ));
+    }
+    circuit.state = CircuitState::HalfOpen;
+    circuit.changed_at = Instant::now();
+    Ok(())
+  }
+
+  fn record<T>(&self, result: &Result<T, FlowyError>) {
+    let mut circuit = self.circuit.lock();
+    let err = match result {
+      Err(err) if is_backend_failure(err) => err,
+      _ => {
+        if circuit.state != CircuitState::Closed {
+          info!("the storage backend is available again, closing the circuit");
+        }
+        *circuit = Circuit::closed();
+        return;
+      },
+    };
+    match circuit.state {
+      CircuitState::HalfOpen => {
+        warn!("the storage backend is still unavailable: {}", err);
+        circuit.open();
+      },
+      // An operation that started before the circuit opened.
+      CircuitState::Open => {},
+      CircuitState::Closed => {
+        let now = Instant::now();
+        match circuit.first_failure_at {
+          Some(first) if now.duration_since(first) <= self.config.failure_window => {
+            circuit.failures += 1;
+          },
+          _ => {
+            circuit.failures = 1;
+            circuit.first_failure_at = Some(now);
+          },
+        }
+        if circuit.failures >= self.config.failure_threshold {
+          warn!(
+            "the storage backend failed {} times in a row, opening the circuit: {}",
+            circuit.failures, err
+          );
+          circuit.open();
+        }
+      },
+    }
+  }
+
+  async fn call<T>(
+    &self,
+    fut: impl Future<Output = Result<T, FlowyError>>,
+  ) -> Result<T, FlowyError> {
+    self.acquire()?;
+    let result = fut.await;
+    self.record(&result);
+    result
+  }
+}
+
+#[async_trait]
+impl<S> ObjectStorageService for CircuitBreakerObjectStorage<S>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  async fn get_object_url(&self, object_id: ObjectIdentity) -> Result<String, FlowyError> {
+    self.call(self.inner.get_object_url(object_id)).await
+  }
+
+  async fn put_object(&self, url: String, object_value: ObjectValue) -> Result<(), FlowyError> {
+    self.call(self.inner.put_object(url, object_value)).await
+  }
+
+  async fn put_object_with_progress(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    progress: ProgressCallback,
+  ) -> Result<(), FlowyError> {
+    self
+      .call(
+        self
+          .inner
+          .put_object_with_progress(url, object_value, progress),
+      )
+      .await
+  }
+
+  fn supports_object_ttl(&self) -> bool {
+    self.inner.supports_object_ttl()
+  }
+
+  async fn put_object_with_ttl(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    ttl: Duration,
+  ) -> Result<(), FlowyError> {
+    self
+      .call(self.inner.put_object_with_ttl(url, object_value, ttl))
+      .await
+  }
+
+  async fn put_object_if_absent(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+  ) -> Result<(), FlowyError> {
+    self
+      .call(self.inner.put_object_if_absent(url, object_value))
+      .await
+  }
+
+  async fn put_object_if_match(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    etag: String,
+  ) -> Result<(), FlowyError> {
+    self
+      .call(self.inner.put_object_if_match(url, object_value, etag))
+      .await
+  }
+
+  async fn delete_object(&self, url: String) -> Result<(), FlowyError> {
+    self.call(self.inner.delete_object(url)).await
+  }
+
+  async fn delete_objects(
+    &self,
+    urls: Vec<String>,
+  ) -> Result<Vec<Result<(), FlowyError>>, FlowyError> {
+    self.call(self.inner.delete_objects(urls)).await
+  }
+
+  async fn delete_workspace_objects(&self, workspace_id: &str) -> Result<DeleteReport, FlowyError> {
+    self
+      .call(self.inner.delete_workspace_objects(workspace_id))
+      .await
+  }
+
+  async fn list_objects(
+    &self,
+    workspace_id: &str,
+    prefix: Option<&str>,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    self
+      .call(self.inner.list_objects(workspace_id, prefix))
+      .await
+  }
+
+  async fn list_objects_with_options(
+    &self,
+    workspace_id: &str,
+    options: &ListOptions,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    self
+      .call(self.inner.list_objects_with_options(workspace_id, options))
+      .await
+  }
+
+  async fn get_object(&self, url: String) -> Result<ObjectValue, FlowyError> {
+    self.call(self.inner.get_object(url)).await
+  }
+
+  async fn get_object_if_modified(
+    &self,
+    url: String,
+    etag: Option<String>,
+  ) -> Result<Option<(ObjectValue, String)>, FlowyError> {
+    self
+      .call(self.inner.get_object_if_modified(url, etag))
+      .await
+  }
+
+  /// Only opening the stream counts, a failure in the middle of the body doesn't.
+  async fn get_object_stream(&self, url: String) -> Result<ObjectStream, FlowyError> {
+    self.call(self.inner.get_object_stream(url)).await
+  }
+
+  async fn get_object_verified(
+    &self,
+    url: String,
+    expected_file_id: &str,
+  ) -> Result<ObjectValue, FlowyError> {
+    self
+      .call(self.inner.get_object_verified(url, expected_file_id))
+      .await
+  }
+
+  fn supports_range_requests(&self) -> bool {
+    self.inner.supports_range_requests()
+  }
+
+  async fn get_object_range(
+    &self,
+    url: String,
+    start: u64,
+    end: Option<u64>,
+  ) -> Result<ObjectValue, FlowyError> {
+    self
+      .call(self.inner.get_object_range(url, start, end))
+      .await
+  }
+
+  async fn head_object(&self, url: String) -> Result<ObjectMeta, FlowyError> {
+    self.call(self.inner.head_object(url)).await
+  }
+
+  async fn object_exists(&self, url: String) -> Result<bool, FlowyError> {
+    self.call(self.inner.object_exists(url)).await
+  }
+
+  async fn presign_get_url(&self, url: String, expires_in: Duration) -> Result<String, FlowyError> {
+    self.call(self.inner.presign_get_url(url, expires_in)).await
+  }
+
+  async fn presign_put_url(&self, url: String, expires_in: Duration) -> Result<String, FlowyError> {
+    self.call(self.inner.presign_put_url(url, expires_in)).await
+  }
+
+  fn supports_copy_object(&self) -> bool {
+    self.inner.supports_copy_object()
+  }
+
+  async fn copy_object(
+    &self,
+    src_url: String,
+    dst_identity: ObjectIdentity,
+  ) -> Result<String, FlowyError> {
+    self
+      .call(self.inner.copy_object(src_url, dst_identity))
+      .await
+  }
+
+  async fn trash_object(&self, url: String) -> Result<(), FlowyError> {
+    self.call(self.inner.trash_object(url)).await
+  }
+
+  async fn restore_object(&self, url: String) -> Result<(), FlowyError> {
+    self.call(self.inner.restore_object(url)).await
+  }
+
+  async fn purge_trash(&self, older_than: Duration) -> Result<Vec<String>, FlowyError> {
+    self.call(self.inner.purge_trash(older_than)).await
+  }
+
+  fn supports_versioning(&self) -> bool {
+    self.inner.supports_versioning()
+  }
+
+  async fn list_versions(&self, url: String) -> Result<Vec<VersionMeta>, FlowyError> {
+    self.call(self.inner.list_versions(url)).await
+  }
+
+  async fn get_object_version(
+    &self,
+    url: String,
+    version_id: String,
+  ) -> Result<ObjectValue, FlowyError> {
+    self
+      .call(self.inner.get_object_version(url, version_id))
+      .await
+  }
+
+  async fn restore_version(&self, url: String, version_id: String) -> Result<(), FlowyError> {
+    self.call(self.inner.restore_version(url, version_id)).await
+  }
+
+  fn supports_multipart(&self) -> bool {
+    self.inner.supports_multipart()
+  }
+
+  async fn initiate_multipart(&self, url: String, mime: Mime) -> Result<UploadId, FlowyError> {
+    self.call(self.inner.initiate_multipart(url, mime)).await
+  }
+
+  async fn upload_part(
+    &self,
+    url: String,
+    upload_id: UploadId,
+    part_number: u32,
+    bytes: Bytes,
+  ) -> Result<PartETag, FlowyError> {
+    self
+      .call(self.inner.upload_part(url, upload_id, part_number, bytes))
+      .await
+  }
+
+  async fn complete_multipart(
+    &self,
+    url: String,
+    upload_id: UploadId,
+    parts: Vec<PartETag>,
+  ) -> Result<(), FlowyError> {
+    self
+      .call(self.inner.complete_multipart(url, upload_id, parts))
+      .await
+  }
+
+  async fn abort_multipart(&self, url: String, upload_id: UploadId) -> Result<(), FlowyError> {
+    self.call(self.inner.abort_multipart(url, upload_id)).await
+  }
+
+  /// The probe is sent even if the circuit is open, and doesn't change its state.
+  async fn health_check(&self) -> Result<HealthStatus, FlowyError> {
+    self.inner.health_check().await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use flowy_error::ErrorCode;
+
+  use super::*;
+  use crate::{
+    memory_object_value, InMemoryObjectStorage, RetryPolicy, RetryingObjectStorage,
+    StorageOperation,
+  };
+
+  fn network_error() -> FlowyError {
+    FlowyError::new(ErrorCode::ConnectTimeout, "connect timeout")
+  }
+
+  #[tokio::test]
+  async fn circuit_breaker_test() {
+    let inner = Arc::new(InMemoryObjectStorage::new());
+    let config = CircuitBreakerConfig {
+      failure_threshold: 3,
+      failure_window: Duration::from_secs(60),
+      cooldown: Duration::from_millis(50),
+    };
+    let storage = CircuitBreakerObjectStorage::new(inner.clone(), config);
+    let url = storage
+      .get_object_url(ObjectIdentity {
+        workspace_id: "w1".to_string(),
+        file_id: "1".to_string(),
+        ext: "txt".to_string(),
+        hash_algorithm: None,
+        size: None,
+      })
+      .await
+      .unwrap();
+    storage
+      .put_object(url.clone(), memory_object_value("1.txt", "hello"))
+      .await
+      .unwrap();
+
+    // A success in between resets the count, and a missing object is not a failure.
+    for _ in 0..2 {
+      inner.fail_next(StorageOperation::Get, network_error());
+    }
+    assert!(storage.get_object(url.clone()).await.is_err());
+    assert!(storage.get_object(url.clone()).await.is_err());
+    storage.get_object(url.clone()).await.unwrap();
+    inner.fail_next(StorageOperation::Get, network_error());
+    assert!(storage.get_object(url.clone()).await.is_err());
+    assert!(storage
+      .get_object("memory://w1/2".to_string())
+      .await
+      .is_err());
+    assert_eq!(storage.state(), CircuitState::Closed);
+
+    for _ in 0..3 {
+      inner.fail_next(StorageOperation::Get, network_error());
+      assert!(storage.get_object(url.clone()).await.is_err());
+    }
+    assert_eq!(storage.state(), CircuitState::Open);
+    let calls = inner.call_count(StorageOperation::Get);
+    let err = storage.get_object(url.clone()).await.err().unwrap();
+    assert!(err.is_storage_kind(StorageErrorKind::Unavailable));
+    assert_eq!(inner.call_count(StorageOperation::Get), calls);
+
+    // The probe fails, then the next one closes the circuit.
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    inner.fail_next(StorageOperation::Get, network_error());
+    assert!(storage.get_object(url.clone()).await.is_err());
+    assert_eq!(storage.state(), CircuitState::Open);
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    storage.get_object(url.clone()).await.unwrap();
+    assert_eq!(storage.state(), CircuitState::Closed);
+  }
+
+  #[tokio::test]
+  async fn retry_stops_at_open_circuit_test() {
+    let inner = Arc::new(InMemoryObjectStorage::new());
+    let config = CircuitBreakerConfig {
+      failure_threshold: 2,
+      ..Default::default()
+    };
+    let breaker = Arc::new(CircuitBreakerObjectStorage::new(inner.clone(), config));
+    let policy = RetryPolicy {
+      max_attempts: 5,
+      base_delay: Duration::from_millis(1),
+      ..Default::default()
+    };
+    let storage = RetryingObjectStorage::new(breaker.clone(), policy);
+    for _ in 0..5 {
+      inner.fail_next(StorageOperation::Get, network_error());
+    }
+    let err = storage
+      .get_object("memory://w1/1.txt".to_string())
+      .await
+      .err()
+      .unwrap();
+    assert!(err.is_storage_kind(StorageErrorKind::Unavailable));
+    assert_eq!(inner.call_count(StorageOperation::Get), 2);
+    assert_eq!(breaker.state(), CircuitState::Open);
+  }
+}
@@ -0,0 +1,380 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::future::{BoxFuture, Shared};
+use futures::FutureExt;
+use mime::Mime;
+use parking_lot::Mutex;
+use tracing::info;
+
+use flowy_error::FlowyError;
+
+use crate::{
+  file_id_from_url, DeleteReport, HealthStatus, ListOptions, ObjectIdentity, ObjectMeta,
+  ObjectStorageService, ObjectStream, ObjectValue, PartETag, ProgressCallback, UploadId,
+  VersionMeta,
+};
+
+type SharedPut = Shared<BoxFuture<'static, Result<(), FlowyError>>>;
+
+/// An [ObjectStorageService] that runs concurrent [ObjectStorageService::put_object] calls for
+/// the same url only once: while an upload is in flight, the following callers await its result
+/// instead of uploading the same content again. The entry is removed as soon as the upload
+/// completes or fails, so the next call after it starts a new upload.
+///
+/// Only the objects whose `file_id` is a content hash are coalesced, see [file_id_from_url]. Two
+/// uploads under an explicit `file_id` may carry different content, they are both sent. The
+/// uploads with a progress callback are never coalesced either, each caller expects its own
+/// progress.
+///
+/// An upload keeps running as long as one caller awaits it. If all of them drop it, the upload is
+/// resumed by the next caller for the same url.
+pub struct CoalescingObjectStorage<S: ?Sized> {
+  inner: Arc<S>,
+  in_flight: Arc<Mutex<HashMap<String, SharedPut>>>,
+  coalesced_puts: AtomicU64,
+}
+
+impl<S> CoalescingObjectStorage<S>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  pub fn new(inner: Arc<S>) -> Self {
+    Self {
+      inner,
+      in_flight: Default::default(),
+      coalesced_puts: AtomicU64::new(0),
+    }
+  }
+
+  /// The number of [ObjectStorageService::put_object] calls that awaited an upload in flight
+  /// instead of starting their own.
+  pub fn coalesced_puts(&self) -> u64 {
+    self.coalesced_puts.load(Ordering::Relaxed)
+  }
+
+  /// The number of uploads in flight.
+  pub fn in_flight_puts(&self) -> usize {
+    self.in_flight.lock().len()
+  }
+
+  fn shared_put(&self, url: String, object_value: ObjectValue) -> SharedPut {
+    let mut in_flight = self.in_flight.lock();
+    if let Some(put) = in_flight.get(&url) {
+      info!("{} is already being uploaded, awaiting that upload", url);
+      self.coalesced_puts.fetch_add(1, Ordering::Relaxed);
+      return put.clone();
+    }
+
+    let inner = self.inner.clone();
+    let entries = Arc::downgrade(&self.in_flight);
+    let key = url.clone();
+    let put = async move {
+      let result = inner.put_object(key.clone(), object_value).await;
+      if let Some(entries) = entries.upgrade() {
+        entries.lock().remove(&key);
+      }
+      result
+    }
+    .boxed()
+    .shared();
+    in_flight.insert(url, put.clone());
+    put
+  }
+}
+
+#[async_trait]
+impl<S> ObjectStorageService for CoalescingObjectStorage<S>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  async fn get_object_url(&self, object_id: ObjectIdentity) -> Result<String, FlowyError> {
+    self.inner.get_object_url(object_id).await
+  }
+
+  async fn put_object(&self, url: String, object_value: ObjectValue) -> Result<(), FlowyError> {
+    if file_id_from_url(&url).is_none() {
+      return self.inner.put_object(url, object_value).await;
+    }
+    self.shared_put(url, object_value).await
+  }
+
+  async fn put_object_with_progress(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    progress: ProgressCallback,
+  ) -> Result<(), FlowyError> {
+    self
+      .inner
+      .put_object_with_progress(url, object_value, progress)
+      .await
+  }
+
+  fn supports_object_ttl(&self) -> bool {
+    self.inner.supports_object_ttl()
+  }
+
+  async fn put_object_with_ttl(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    ttl: Duration,
+  ) -> Result<(), FlowyError> {
+    self.inner.put_object_with_ttl(url, object_value, ttl).await
+  }
+
+  async fn put_object_if_absent(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+  ) -> Result<(), FlowyError> {
+    self.inner.put_object_if_absent(url, object_value).await
+  }
+
+  async fn put_object_if_match(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    etag: String,
+  ) -> Result<(), FlowyError> {
+    self
+      .inner
+      .put_object_if_match(url, object_value, etag)
+      .await
+  }
+
+  async fn delete_object(&self, url: String) -> Result<(), FlowyError> {
+    self.inner.delete_object(url).await
+  }
+
+  async fn delete_objects(
+    &self,
+    urls: Vec<String>,
+  ) -> Result<Vec<Result<(), FlowyError>>, FlowyError> {
+    self.inner.delete_objects(urls).await
+  }
+
+  async fn delete_workspace_objects(&self, workspace_id: &str) -> Result<DeleteReport, FlowyError> {
+    self.inner.delete_workspace_objects(workspace_id).await
+  }
+
+  async fn list_objects(
+    &self,
+    workspace_id: &str,
+    prefix: Option<&str>,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    self.inner.list_objects(workspace_id, prefix).await
+  }
+
+  async fn list_objects_with_options(
+    &self,
+    workspace_id: &str,
+    options: &ListOptions,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    self
+      .inner
+      .list_objects_with_options(workspace_id, options)
+      .await
+  }
+
+  async fn get_object(&self, url: String) -> Result<ObjectValue, FlowyError> {
+    self.inner.get_object(url).await
+  }
+
+  async fn get_object_stream(&self, url: String) -> Result<ObjectStream, FlowyError> {
+    self.inner.get_object_stream(url).await
+  }
+
+  async fn get_object_verified(
+    &self,
+    url: String,
+    expected_file_id: &str,
+  ) -> Result<ObjectValue, FlowyError> {
+    self.inner.get_object_verified(url, expected_file_id).await
+  }
+
+  async fn get_object_if_modified(
+    &self,
+    url: String,
+    etag: Option<String>,
+  ) -> Result<Option<(ObjectValue, String)>, FlowyError> {
+    self.inner.get_object_if_modified(url, etag).await
+  }
+
+  fn supports_range_requests(&self) -> bool {
+    self.inner.supports_range_requests()
+  }
+
+  async fn get_object_range(
+    &self,
+    url: String,
+    start: u64,
+    end: Option<u64>,
+  ) -> Result<ObjectValue, FlowyError> {
+    self.inner.get_object_range(url, start, end).await
+  }
+
+  async fn head_object(&self, url: String) -> Result<ObjectMeta, FlowyError> {
+    self.inner.head_object(url).await
+  }
+
+  async fn object_exists(&self, url: String) -> Result<bool, FlowyError> {
+    self.inner.object_exists(url).await
+  }
+
+  async fn presign_get_url(&self, url: String, expires_in: Duration) -> Result<String, FlowyError> {
+    self.inner.presign_get_url(url, expires_in).await
+  }
+
+  async fn presign_put_url(&self, url: String, expires_in: Duration) -> Result<String, FlowyError> {
+    self.inner.presign_put_url(url, expires_in).await
+  }
+
+  fn supports_copy_object(&self) -> bool {
+    self.inner.supports_copy_object()
+  }
+
+  async fn copy_object(
+    &self,
+    src_url: String,
+    dst_identity: ObjectIdentity,
+  ) -> Result<String, FlowyError> {
+    self.inner.copy_object(src_url, dst_identity).await
+  }
+
+  async fn trash_object(&self, url: String) -> Result<(), FlowyError> {
+    self.inner.trash_object(url).await
+  }
+
+  async fn restore_object(&self, url: String) -> Result<(), FlowyError> {
+    self.inner.restore_object(url).await
+  }
+
+  async fn purge_trash(&self, older_than: Duration) -> Result<Vec<String>, FlowyError> {
+    self.inner.purge_trash(older_than).await
+  }
+
+  fn supports_versioning(&self) -> bool {
+    self.inner.supports_versioning()
+  }
+
+  async fn list_versions(&self, url: String) -> Result<Vec<VersionMeta>, FlowyError> {
+    self.inner.list_versions(url).await
+  }
+
+  async fn get_object_version(
+    &self,
+    url: String,
+    version_id: String,
+  ) -> Result<ObjectValue, FlowyError> {
+    self.inner.get_object_version(url, version_id).await
+  }
+
+  async fn restore_version(&self, url: String, version_id: String) -> Result<(), FlowyError> {
+    self.inner.restore_version(url, version_id).await
+  }
+
+  fn supports_multipart(&self) -> bool {
+    self.inner.supports_multipart()
+  }
+
+  async fn initiate_multipart(&self, url: String, mime: Mime) -> Result<UploadId, FlowyError> {
+    self.inner.initiate_multipart(url, mime).await
+  }
+
+  async fn upload_part(
+    &self,
+    url: String,
+    upload_id: UploadId,
+    part_number: u32,
+    bytes: Bytes,
+  ) -> Result<PartETag, FlowyError> {
+    self
+      .inner
+      .upload_part(url, upload_id, part_number, bytes)
+      .await
+  }
+
+  async fn complete_multipart(
+    &self,
+    url: String,
+    upload_id: UploadId,
+    parts: Vec<PartETag>,
+  ) -> Result<(), FlowyError> {
+    self.inner.complete_multipart(url, upload_id, parts).await
+  }
+
+  async fn abort_multipart(&self, url: String, upload_id: UploadId) -> Result<(), FlowyError> {
+    self.inner.abort_multipart(url, upload_id).await
+  }
+
+  async fn health_check(&self) -> Result<HealthStatus, FlowyError> {
+    self.inner.health_check().await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use flowy_error::ErrorCode;
+
+  use super::*;
+  use crate::{content_hash, memory_object_value, InMemoryObjectStorage, StorageOperation};
+
+  async fn url(storage: &InMemoryObjectStorage, file_id: String) -> String {
+    let identity = ObjectIdentity {
+      workspace_id: "w1".to_string(),
+      file_id,
+      ext: "txt".to_string(),
+      hash_algorithm: None,
+      size: None,
+    };
+    storage.get_object_url(identity).await.unwrap()
+  }
+
+  #[tokio::test]
+  async fn coalesce_concurrent_puts_test() {
+    let inner = Arc::new(InMemoryObjectStorage::new());
+    // Keeps the uploads in flight for a moment, the in-memory ones complete on the first poll.
+    inner.set_latency(StorageOperation::Put, Duration::from_millis(10));
+    let storage = CoalescingObjectStorage::new(inner.clone());
+    let url = url(&inner, content_hash(b"hello")).await;
+    let put = || storage.put_object(url.clone(), memory_object_value("a.txt", "hello"));
+
+    let (first, second) = futures::join!(put(), put());
+    assert!(first.is_ok() && second.is_ok());
+    assert_eq!(inner.call_count(StorageOperation::Put), 1);
+    assert_eq!(storage.coalesced_puts(), 1);
+    assert_eq!(storage.in_flight_puts(), 0);
+
+    // A failure is shared too, and the next upload starts over.
+    inner.fail_next(
+      StorageOperation::Put,
+      FlowyError::new(ErrorCode::ConnectTimeout, "timeout"),
+    );
+    let (first, second) = futures::join!(put(), put());
+    assert_eq!(first.unwrap_err().code, ErrorCode::ConnectTimeout);
+    assert_eq!(second.unwrap_err().code, ErrorCode::ConnectTimeout);
+    assert_eq!(inner.call_count(StorageOperation::Put), 2);
+    put().await.unwrap();
+    assert_eq!(inner.call_count(StorageOperation::Put), 3);
+  }
+
+  #[tokio::test]
+  async fn explicit_file_id_is_not_coalesced_test() {
+    let inner = Arc::new(InMemoryObjectStorage::new());
+    let storage = CoalescingObjectStorage::new(inner.clone());
+    let url = url(&inner, "cover".to_string()).await;
+    let (first, second) = futures::join!(
+      storage.put_object(url.clone(), memory_object_value("a.txt", "v1")),
+      storage.put_object(url.clone(), memory_object_value("a.txt", "v2")),
+    );
+    assert!(first.is_ok() && second.is_ok());
+    assert_eq!(inner.call_count(StorageOperation::Put), 2);
+    assert_eq!(storage.coalesced_puts(), 0);
+  }
+}
@@ -0,0 +1,195 @@
+use std::path::Path;
+
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, SeekFrom};
+
+use flowy_error::FlowyError;
+
+#[cfg(feature = "blake3-hash")]
+use crate::Blake3ContentHash;
+use crate::{
+  copy_and_hash, storage_config, ContentHashAlgorithm, FxContentHash, IncrementalHash,
+  ObjectStorageService,
+};
+
+/// The number of bytes compared at the start and at the end of the object when the backend
+/// doesn't report a content hash.
+const SPOT_CHECK_BYTES: u64 = 4096;
+
+/// The result of [ObjectStorageService::is_up_to_date].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LocalComparison {
+  /// The stored object has the content of the local file.
+  UpToDate,
+  /// The stored object differs from the local file, or it doesn't exist.
+  Changed,
+  /// The backend doesn't report a comparable hash and the spot-check found no difference, only
+  /// downloading the object would tell.
+  Unknown,
+}
+
+/// Returns the content hash the ETag carries, `None` if the backend computes its ETags
+/// differently.
+fn etag_content_hash(etag: &str) -> Option<&str> {
+  let etag = etag.strip_prefix("W/").unwrap_or(etag).trim_matches('"');
+  let is_hash = FxContentHash::is_hash_id(etag);
+  #[cfg(feature = "blake3-hash")]
+  let is_hash = is_hash || Blake3ContentHash::is_hash_id(etag);
+  is_hash.then_some(etag)
+}
+
+async fn hash_file<A: ContentHashAlgorithm>(
+  path: &Path,
+  size: u64,
+) -> Result<Option<String>, FlowyError> {
+  let mut file = File::open(path).await?;
+  let hasher = copy_and_hash(
+    &mut file,
+    &mut tokio::io::sink(),
+    A::hasher(size),
+    storage_config().read_buffer_size,
+  )
+  .await?;
+  // The file changed while it was read.
+  if !hasher.is_complete() {
+    return Ok(None);
+  }
+  Ok(Some(hasher.finish()))
+}
+
+/// Hashes the file with the algorithm that produced `hash`, `None` if the file changed while it
+/// was read.
+async fn hash_file_like(path: &Path, size: u64, hash: &str) -> Result<Option<String>, FlowyError> {
+  #[cfg(feature = "blake3-hash")]
+  if Blake3ContentHash::is_hash_id(hash) {
+    return hash_file::<Blake3ContentHash>(path, size).await;
+  }
+  if FxContentHash::is_hash_id(hash) {
+    return hash_file::<FxContentHash>(path, size).await;
+  }
+  Ok(None)
+}
+
+async fn read_local_range(path: &Path, start: u64, len: u64) -> Result<Vec<u8>, FlowyError> {
+  let mut file = File::open(path).await?;
+  file.seek(SeekFrom::Start(start)).await?;
+  let mut content = Vec::with_capacity(len as usize);
+  file.take(len).read_to_end(&mut content).await?;
+  Ok(content)
+}
+
+/// Compares a few bytes at the start and at the end of the object with the local file. It can
+/// only tell that they differ.
+async fn spot_check<S>(
+  service: &S,
+  url: &str,
+  local_path: &Path,
+  size: u64,
+) -> Result<LocalComparison, FlowyError>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  let len = SPOT_CHECK_BYTES.min(size);
+  let mut starts = vec![0];
+  if size > len {
+    starts.push(size - len);
+  }
+  for start in starts {
+    let remote = service
+      .get_object_range(url.to_string(), start, Some(start + len - 1))
+      .await?
+      .decompress()?;
+    if remote.raw != read_local_range(local_path, start, len).await? {
+      return Ok(LocalComparison::Changed);
+    }
+  }
+  Ok(LocalComparison::Unknown)
+}
+
+/// The default implementation of [ObjectStorageService::is_up_to_date].
+pub(crate) async fn compare_with_local<S>(
+  service: &S,
+  url: String,
+  local_path: &Path,
+) -> Result<LocalComparison, FlowyError>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  let meta = match service.head_object(url.clone()).await {
+    Ok(meta) => meta,
+    Err(err) if err.is_record_not_found() => return Ok(LocalComparison::Changed),
+    Err(err) => return Err(err),
+  };
+  let local_size = tokio::fs::metadata(local_path).await?.len();
+  if meta.size != local_size {
+    return Ok(LocalComparison::Changed);
+  }
+
+  if let Some(remote_hash) = meta.etag.as_deref().and_then(etag_content_hash) {
+    return Ok(
+      match hash_file_like(local_path, local_size, remote_hash).await? {
+        Some(local_hash) if local_hash == remote_hash => LocalComparison::UpToDate,
+        Some(_) => LocalComparison::Changed,
+        None => LocalComparison::Unknown,
+      },
+    );
+  }
+  if local_size == 0 {
+    return Ok(LocalComparison::UpToDate);
+  }
+  if !service.supports_range_requests() {
+    return Ok(LocalComparison::Unknown);
+  }
+  spot_check(service, &url, local_path, local_size).await
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{memory_object_value, InMemoryObjectStorage, StorageOperation};
+
+  #[tokio::test]
+  async fn is_up_to_date_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("a.txt");
+    tokio::fs::write(&path, "hello").await.unwrap();
+    let storage = InMemoryObjectStorage::new();
+    let url = "memory://w1/a.txt".to_string();
+    assert_eq!(
+      storage.is_up_to_date(url.clone(), &path).await.unwrap(),
+      LocalComparison::Changed
+    );
+
+    storage
+      .put_object(url.clone(), memory_object_value("a.txt", "hello"))
+      .await
+      .unwrap();
+    assert_eq!(
+      storage.is_up_to_date(url.clone(), &path).await.unwrap(),
+      LocalComparison::UpToDate
+    );
+
+    // A size mismatch is found without hashing or downloading anything.
+    tokio::fs::write(&path, "hello world").await.unwrap();
+    assert_eq!(
+      storage.is_up_to_date(url.clone(), &path).await.unwrap(),
+      LocalComparison::Changed
+    );
+    tokio::fs::write(&path, "jello").await.unwrap();
+    assert_eq!(
+      storage.is_up_to_date(url, &path).await.unwrap(),
+      LocalComparison::Changed
+    );
+    assert_eq!(storage.call_count(StorageOperation::Get), 0);
+  }
+
+  #[test]
+  fn etag_content_hash_test() {
+    assert_eq!(etag_content_hash("\"123\""), Some("123"));
+    assert_eq!(etag_content_hash("W/\"123\""), Some("123"));
+    assert_eq!(
+      etag_content_hash("\"d41d8cd98f00b204e9800998ecf8427e-2\""),
+      None
+    );
+  }
+}
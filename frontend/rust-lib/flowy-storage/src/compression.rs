@@ -0,0 +1,253 @@
+use std::io::{Read, Write};
+
+use mime::Mime;
+
+use flowy_error::{ErrorCode, FlowyError};
+
+use crate::{storage_error, ObjectValue, StorageErrorKind};
+
+/// Objects smaller than this are not worth compressing.
+pub const MIN_COMPRESS_SIZE: usize = 1024;
+
+/// The algorithm used to compress the content of an [ObjectValue].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+  Gzip,
+  /// The zlib format, which is what HTTP calls `deflate`.
+  Deflate,
+  /// Not available on wasm.
+  Zstd,
+}
+
+impl Compression {
+  /// The value of the HTTP `Content-Encoding` header for this algorithm.
+  pub fn content_encoding(&self) -> &'static str {
+    match self {
+      Compression::Gzip => "gzip",
+      Compression::Deflate => "deflate",
+      Compression::Zstd => "zstd",
+    }
+  }
+
+  /// Parses the value of an HTTP `Content-Encoding` header. Returns `None` for `identity` and
+  /// the encodings that are not supported.
+  pub fn from_content_encoding(content_encoding: &str) -> Option<Self> {
+    match content_encoding.trim().to_ascii_lowercase().as_str() {
+      "gzip" | "x-gzip" => Some(Compression::Gzip),
+      "deflate" => Some(Compression::Deflate),
+      "zstd" => Some(Compression::Zstd),
+      _ => None,
+    }
+  }
+
+  fn encode(&self, content: &[u8]) -> std::io::Result<Vec<u8>> {
+    match self {
+      Compression::Gzip => {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(content)?;
+        encoder.finish()
+      },
+      Compression::Deflate => {
+        let mut encoder =
+          flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(content)?;
+        encoder.finish()
+      },
+      #[cfg(not(target_arch = "wasm32"))]
+      Compression::Zstd => zstd::encode_all(content, zstd::DEFAULT_COMPRESSION_LEVEL),
+      #[cfg(target_arch = "wasm32")]
+      Compression::Zstd => Err(zstd_not_support()),
+    }
+  }
+
+  fn decode(&self, content: &[u8]) -> std::io::Result<Vec<u8>> {
+    match self {
+      Compression::Gzip => {
+        let mut decoded = Vec::new();
+        flate2::read::GzDecoder::new(content).read_to_end(&mut decoded)?;
+        Ok(decoded)
+      },
+      Compression::Deflate => {
+        let mut decoded = Vec::new();
+        flate2::read::ZlibDecoder::new(content).read_to_end(&mut decoded)?;
+        Ok(decoded)
+      },
+      #[cfg(not(target_arch = "wasm32"))]
+      Compression::Zstd => zstd::decode_all(content),
+      #[cfg(target_arch = "wasm32")]
+      Compression::Zstd => Err(zstd_not_support()),
+    }
+  }
+}
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn zstd_not_support() -> std::io::Error {
+  std::io::Error::new(
+    std::io::ErrorKind::Unsupported,
+    "zstd is not supported on wasm",
+  )
+}
+
+/// Returns true if content of the given mime type usually gets smaller when compressed. Images,
+/// videos, audios and archives are already compressed.
+pub fn should_compress(mime: &Mime) -> bool {
+  if mime.type_() == mime::TEXT {
+    return true;
+  }
+  if let Some(suffix) = mime.suffix() {
+    if suffix == mime::JSON || suffix == mime::XML {
+      return true;
+    }
+  }
+  mime.type_() == mime::APPLICATION
+    && matches!(
+      mime.subtype().as_str(),
+      "json" | "xml" | "javascript" | "x-ndjson" | "yaml" | "x-yaml" | "toml" | "csv" | "sql"
+    )
+}
+
+impl ObjectValue {
+  /// Compresses the content with `algo` and records it in [ObjectValue::content_encoding]. The
+  /// value is returned unchanged if it's already compressed.
+  pub fn compress(self, algo: Compression) -> Result<ObjectValue, FlowyError> {
+    if self.content_encoding.is_some() {
+      return Ok(self);
+    }
+
+    let compressed = algo.encode(&self.raw).map_err(|err| {
+      FlowyError::new(
+        ErrorCode::Internal,
+        format!(
+          "failed to compress with {}: {}",
+          algo.content_encoding(),
+          err
+        ),
+      )
+    })?;
+    Ok(ObjectValue {
+      raw: compressed.into(),
+      mime: self.mime,
+      content_encoding: Some(algo),
+    })
+  }
+
+  /// Compresses the content if its mime type compresses well and the compressed content is
+  /// smaller than the original one.
+  pub fn compress_if_beneficial(self, algo: Compression) -> Result<ObjectValue, FlowyError> {
+    if self.content_encoding.is_some()
+      || self.raw.len() < MIN_COMPRESS_SIZE
+      || !should_compress(&self.mime)
+    {
+      return Ok(self);
+    }
+
+    let compressed = self.clone().compress(algo)?;
+    if compressed.raw.len() < self.raw.len() {
+      Ok(compressed)
+    } else {
+      Ok(self)
+    }
+  }
+
+  /// Inflates the content according to [ObjectValue::content_encoding]. The value is returned
+  /// unchanged if it isn't compressed.
+  pub fn decompress(self) -> Result<ObjectValue, FlowyError> {
+    let algo = match self.content_encoding {
+      None => return Ok(self),
+      Some(algo) => algo,
+    };
+
+    let decompressed = algo.decode(&self.raw).map_err(|err| {
+      storage_error(
+        StorageErrorKind::Corrupt,
+        format!(
+          "failed to decompress with {}: {}",
+          algo.content_encoding(),
+          err
+        ),
+      )
+    })?;
+    Ok(ObjectValue {
+      raw: decompressed.into(),
+      mime: self.mime,
+      content_encoding: None,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn value(raw: Vec<u8>, mime: Mime) -> ObjectValue {
+    ObjectValue {
+      raw: raw.into(),
+      mime,
+      content_encoding: None,
+    }
+  }
+
+  #[test]
+  fn compress_round_trip_test() {
+    let markdown = "# title\n\nsome text\n".repeat(200).into_bytes();
+    let already_compressed = value(markdown.clone(), mime::TEXT_PLAIN)
+      .compress(Compression::Gzip)
+      .unwrap()
+      .raw
+      .to_vec();
+
+    for algo in [Compression::Gzip, Compression::Deflate, Compression::Zstd] {
+      for raw in [vec![], markdown.clone(), already_compressed.clone()] {
+        let compressed = value(raw.clone(), mime::TEXT_PLAIN).compress(algo).unwrap();
+        assert_eq!(compressed.content_encoding, Some(algo));
+
+        let decompressed = compressed.decompress().unwrap();
+        assert_eq!(decompressed.raw.as_ref(), raw.as_slice());
+        assert_eq!(decompressed.mime, mime::TEXT_PLAIN);
+        assert_eq!(decompressed.content_encoding, None);
+      }
+    }
+  }
+
+  #[test]
+  fn compress_if_beneficial_test() {
+    let markdown = "# title\n\nsome text\n".repeat(200).into_bytes();
+    let text = value(markdown.clone(), "text/markdown".parse().unwrap())
+      .compress_if_beneficial(Compression::Gzip)
+      .unwrap();
+    assert_eq!(text.content_encoding, Some(Compression::Gzip));
+    assert!(text.raw.len() < markdown.len());
+
+    let image = value(markdown.clone(), mime::IMAGE_JPEG)
+      .compress_if_beneficial(Compression::Gzip)
+      .unwrap();
+    assert_eq!(image.content_encoding, None);
+
+    let empty = value(vec![], mime::APPLICATION_JSON)
+      .compress_if_beneficial(Compression::Gzip)
+      .unwrap();
+    assert_eq!(empty.content_encoding, None);
+  }
+
+  #[test]
+  fn should_compress_test() {
+    assert!(should_compress(&mime::TEXT_PLAIN));
+    assert!(should_compress(&mime::APPLICATION_JSON));
+    assert!(should_compress(&"application/ld+json".parse().unwrap()));
+    assert!(should_compress(&mime::IMAGE_SVG));
+    assert!(!should_compress(&mime::IMAGE_PNG));
+    assert!(!should_compress(&"video/mp4".parse().unwrap()));
+    assert!(!should_compress(&"application/zip".parse().unwrap()));
+  }
+
+  #[test]
+  fn content_encoding_test() {
+    for algo in [Compression::Gzip, Compression::Deflate, Compression::Zstd] {
+      assert_eq!(
+        Compression::from_content_encoding(algo.content_encoding()),
+        Some(algo)
+      );
+    }
+    assert_eq!(Compression::from_content_encoding("identity"), None);
+  }
+}
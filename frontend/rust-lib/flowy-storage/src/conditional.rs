@@ -0,0 +1,302 @@
+use flowy_error::FlowyError;
+
+use crate::{
+  content_hash, storage_error, ObjectIdentity, ObjectStorageService, ObjectValue, StorageErrorExt,
+  StorageErrorKind,
+};
+
+/// What [ObjectStorageService::put_object_with_policy] does when there is already an object at
+/// the url.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverwritePolicy {
+  /// Replaces the existing object, like [ObjectStorageService::put_object].
+  #[default]
+  Overwrite,
+  /// Keeps the existing object, nothing is uploaded.
+  Skip,
+  /// Uploads the object under another `file_id`: `{file_id}-1`, then `{file_id}-2`, and so on.
+  Rename,
+}
+
+/// What [ObjectStorageService::put_object_with_policy] did, with the url of the object.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PutOutcome {
+  /// The object was uploaded to its url, replacing the existing object if there was one.
+  Written(String),
+  /// There was already an object at the url, it was left untouched.
+  Skipped(String),
+  /// There was already an object at the url, the object was uploaded to another url.
+  Renamed(String),
+}
+
+impl PutOutcome {
+  pub fn url(&self) -> &str {
+    match self {
+      PutOutcome::Written(url) | PutOutcome::Skipped(url) | PutOutcome::Renamed(url) => url,
+    }
+  }
+}
+
+/// The number of other `file_id`s [OverwritePolicy::Rename] tries before giving up.
+const MAX_RENAME_ATTEMPTS: u32 = 100;
+
+pub(crate) async fn put_object_with_policy<S>(
+  service: &S,
+  object_id: ObjectIdentity,
+  object_value: ObjectValue,
+  policy: OverwritePolicy,
+) -> Result<PutOutcome, FlowyError>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  let url = service.get_object_url(object_id.clone()).await?;
+  if policy == OverwritePolicy::Overwrite {
+    service.put_object(url.clone(), object_value).await?;
+    return Ok(PutOutcome::Written(url));
+  }
+  if put_if_absent(service, &url, &object_value).await? {
+    return Ok(PutOutcome::Written(url));
+  }
+  if policy == OverwritePolicy::Skip {
+    return Ok(PutOutcome::Skipped(url));
+  }
+
+  for attempt in 1..=MAX_RENAME_ATTEMPTS {
+    // The renamed object is not identified by its content hash anymore.
+    let renamed = ObjectIdentity {
+      file_id: format!("{}-{}", object_id.file_id, attempt),
+      hash_algorithm: None,
+      ..object_id.clone()
+    };
+    let renamed_url = service.get_object_url(renamed).await?;
+    if put_if_absent(service, &renamed_url, &object_value).await? {
+      return Ok(PutOutcome::Renamed(renamed_url));
+    }
+  }
+  Err(precondition_failed(
+    &url,
+    &format!("it and the {} next names are taken", MAX_RENAME_ATTEMPTS),
+  ))
+}
+
+/// Returns false if there is already an object at the url.
+pub(crate) async fn put_if_absent<S>(
+  service: &S,
+  url: &str,
+  object_value: &ObjectValue,
+) -> Result<bool, FlowyError>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  match service
+    .put_object_if_absent(url.to_string(), object_value.clone())
+    .await
+  {
+    Ok(_) => Ok(true),
+    Err(err) if err.is_storage_kind(StorageErrorKind::PreconditionFailed) => Ok(false),
+    Err(err) => Err(err),
+  }
+}
+
+/// Returns the ETag of the content for the services that don't get one from their backend, see
+/// [crate::ObjectStorageService::get_object_if_modified].
+pub fn content_etag(content: &[u8]) -> String {
+  content_hash(content)
+}
+
+/// Formats the value of an HTTP `If-None-Match` header. The ETag is quoted unless it already is,
+/// weak ETags are kept as is.
+pub fn if_none_match_header_value(etag: &str) -> String {
+  if etag.starts_with('"') || etag.starts_with("W/\"") {
+    etag.to_string()
+  } else {
+    format!("\"{}\"", etag)
+  }
+}
+
+/// Formats the value of an HTTP `If-Match` header, like [if_none_match_header_value].
+pub fn if_match_header_value(etag: &str) -> String {
+  if_none_match_header_value(etag)
+}
+
+/// The error of a conditional write whose precondition doesn't hold, see
+/// [StorageErrorKind::PreconditionFailed].
+pub(crate) fn precondition_failed(url: &str, reason: &str) -> FlowyError {
+  storage_error(
+    StorageErrorKind::PreconditionFailed,
+    format!("{} was not written, {}", url, reason),
+  )
+}
+
+/// Checks the precondition of [crate::ObjectStorageService::put_object_if_match] against the
+/// ETag of the stored object, `None` if there is no object at the url.
+pub(crate) fn check_if_match(
+  url: &str,
+  current: Option<&str>,
+  etag: &str,
+) -> Result<(), FlowyError> {
+  match current {
+    Some(current) if etag_matches(current, etag) => Ok(()),
+    Some(current) => Err(precondition_failed(
+      url,
+      &format!("its ETag is {} instead of {}", current, etag),
+    )),
+    None => Err(precondition_failed(url, "it doesn't exist")),
+  }
+}
+
+/// The condition the object at the url must meet to be replaced, for the wrappers that write the
+/// object in several steps and only check it on the last one.
+pub(crate) enum Precondition {
+  None,
+  Absent,
+  Match(String),
+}
+
+impl Precondition {
+  /// Writes the object with the put of the `service` that checks the precondition.
+  pub(crate) async fn put<S>(
+    self,
+    service: &S,
+    url: String,
+    object_value: ObjectValue,
+  ) -> Result<(), FlowyError>
+  where
+    S: ObjectStorageService + ?Sized,
+  {
+    match self {
+      Precondition::None => service.put_object(url, object_value).await,
+      Precondition::Absent => service.put_object_if_absent(url, object_value).await,
+      Precondition::Match(etag) => service.put_object_if_match(url, object_value, etag).await,
+    }
+  }
+}
+
+/// Compares two ETags with the weak comparison of HTTP, which ignores the `W/` prefix. The
+/// quotes are optional, so an ETag stored without them still matches.
+pub fn etag_matches(a: &str, b: &str) -> bool {
+  fn opaque(etag: &str) -> &str {
+    let etag = etag.strip_prefix("W/").unwrap_or(etag);
+    etag
+      .strip_prefix('"')
+      .and_then(|etag| etag.strip_suffix('"'))
+      .unwrap_or(etag)
+  }
+  opaque(a) == opaque(b)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::memory::DefaultsOnly;
+  use crate::{
+    memory_object_value, InMemoryObjectStorage, ObjectIdentity, ObjectStorageService,
+    StorageErrorExt,
+  };
+
+  async fn conditional_put_test<S: ObjectStorageService>(storage: S, url: &str) {
+    let url = url.to_string();
+    let put_if_absent = |content: &'static str| {
+      storage.put_object_if_absent(url.clone(), memory_object_value("1.txt", content))
+    };
+    put_if_absent("first").await.unwrap();
+    let err = put_if_absent("second").await.unwrap_err();
+    assert!(err.is_storage_kind(StorageErrorKind::PreconditionFailed));
+
+    let etag = storage
+      .head_object(url.clone())
+      .await
+      .unwrap()
+      .etag
+      .unwrap();
+    let put_if_match = |content: &'static str, etag: &str| {
+      storage.put_object_if_match(
+        url.clone(),
+        memory_object_value("1.txt", content),
+        etag.to_string(),
+      )
+    };
+    put_if_match("mine", &etag).await.unwrap();
+    // The object changed since `etag` was read.
+    let err = put_if_match("theirs", &etag).await.unwrap_err();
+    assert!(err.is_storage_kind(StorageErrorKind::PreconditionFailed));
+    let value = storage.get_object(url.clone()).await.unwrap();
+    assert_eq!(value.raw.as_ref(), b"mine");
+
+    storage.delete_object(url.clone()).await.unwrap();
+    let err = put_if_match("mine", &etag).await.unwrap_err();
+    assert!(err.msg.contains("doesn't exist"));
+  }
+
+  #[tokio::test]
+  async fn emulated_conditional_put_test() {
+    // The conditional writes are emulated on top of the required methods.
+    conditional_put_test(DefaultsOnly::default(), "memory://w1/1.txt").await;
+  }
+
+  #[tokio::test]
+  async fn memory_conditional_put_test() {
+    conditional_put_test(InMemoryObjectStorage::new(), "memory://w1/1.txt").await;
+  }
+
+  #[test]
+  fn etag_test() {
+    assert_eq!(if_none_match_header_value("abc"), "\"abc\"");
+    assert_eq!(if_none_match_header_value("W/\"abc\""), "W/\"abc\"");
+    assert!(etag_matches("\"abc\"", "abc"));
+    assert!(etag_matches("W/\"abc\"", "\"abc\""));
+    assert!(!etag_matches("abc", "abd"));
+  }
+
+  #[tokio::test]
+  async fn put_object_with_policy_test() {
+    let storage = InMemoryObjectStorage::new();
+    let identity = ObjectIdentity {
+      workspace_id: "w1".to_string(),
+      file_id: "1".to_string(),
+      ext: "txt".to_string(),
+      hash_algorithm: None,
+      size: None,
+    };
+    let put = |content: &'static str, policy| {
+      storage.put_object_with_policy(
+        identity.clone(),
+        memory_object_value("1.txt", content),
+        policy,
+      )
+    };
+    let outcome = put("first", OverwritePolicy::Skip).await.unwrap();
+    assert_eq!(
+      outcome,
+      PutOutcome::Written("memory://w1/1.txt".to_string())
+    );
+
+    let outcome = put("second", OverwritePolicy::Skip).await.unwrap();
+    assert_eq!(
+      outcome,
+      PutOutcome::Skipped("memory://w1/1.txt".to_string())
+    );
+    let value = storage.object("memory://w1/1.txt").unwrap();
+    assert_eq!(value.raw.as_ref(), b"first");
+
+    let outcome = put("third", OverwritePolicy::Rename).await.unwrap();
+    assert_eq!(
+      outcome,
+      PutOutcome::Renamed("memory://w1/1-1.txt".to_string())
+    );
+    let outcome = put("fourth", OverwritePolicy::Rename).await.unwrap();
+    assert_eq!(outcome.url(), "memory://w1/1-2.txt");
+    assert_eq!(
+      storage.object(outcome.url()).unwrap().raw.as_ref(),
+      b"fourth"
+    );
+
+    let outcome = put("fifth", OverwritePolicy::Overwrite).await.unwrap();
+    assert_eq!(
+      outcome,
+      PutOutcome::Written("memory://w1/1.txt".to_string())
+    );
+    let value = storage.object("memory://w1/1.txt").unwrap();
+    assert_eq!(value.raw.as_ref(), b"fifth");
+  }
+}
@@ -0,0 +1,216 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use flowy_error::{ErrorCode, FlowyError};
+
+use crate::{
+  DEFAULT_IDLE_TIMEOUT, DEFAULT_MULTIPART_PART_SIZE, DEFAULT_PROGRESS_RATE_WINDOW,
+  DEFAULT_READ_BUFFER_SIZE,
+};
+
+/// The smallest part of a multipart upload the backends accept, except for the last part.
+pub const MIN_MULTIPART_PART_SIZE: usize = 5 * 1024 * 1024;
+
+/// The largest part of a multipart upload the backends accept.
+pub const MAX_MULTIPART_PART_SIZE: u64 = 5 * 1024 * 1024 * 1024;
+
+/// The default of [StorageConfig::multipart_threshold], the objects that fit in a single part
+/// are uploaded in one request.
+pub const DEFAULT_MULTIPART_THRESHOLD: u64 = DEFAULT_MULTIPART_PART_SIZE as u64;
+
+static STORAGE_CONFIG: RwLock<Option<StorageConfig>> = RwLock::new(None);
+
+/// The settings shared by every storage of the process, see [init_storage_config].
+#[derive(Debug, Clone)]
+pub struct StorageConfig {
+  /// Where the temporary files that have no destination yet are staged. The system temp
+  /// directory is used if it's `None`, it's often on a small partition on mobile so the apps
+  /// should set it to a directory of their sandbox. The writes done with
+  /// [crate::atomic_write_with] are staged next to their destination instead, so they are
+  /// renamed into place without crossing file systems.
+  pub temp_dir: Option<PathBuf>,
+  /// The longest time a streaming upload or download can go without moving a byte before it's
+  /// aborted, see [crate::idle_timeout_stream]. It's independent of the deadline of the whole
+  /// transfer, and catches the connections that are half closed without notice on flaky mobile
+  /// networks. `None` disables the check.
+  pub idle_timeout: Option<Duration>,
+  /// The size of the buffer used to read and hash the files chunk by chunk. Larger buffers make
+  /// fewer system calls at the cost of memory.
+  pub read_buffer_size: usize,
+  /// The objects larger than this, or whose size is unknown, are uploaded part by part, see
+  /// [crate::put_object_in_parts]. It must not be smaller than [Self::multipart_part_size].
+  pub multipart_threshold: u64,
+  /// The size of each part of a multipart upload, at most one part is held in memory at a time.
+  /// It must be between [MIN_MULTIPART_PART_SIZE] and [MAX_MULTIPART_PART_SIZE].
+  pub multipart_part_size: usize,
+  /// Adds the charset of the text files read by [crate::object_from_disk] to their mime type,
+  /// see [crate::with_text_charset]. Off by default, it reads the whole content once more.
+  pub detect_text_charset: bool,
+  /// How far back the speed reported with the upload progress looks, see [crate::TransferRate].
+  /// A longer window gives a steadier speed and time left, a shorter one follows the network
+  /// more closely.
+  pub progress_rate_window: Duration,
+}
+
+impl Default for StorageConfig {
+  fn default() -> Self {
+    Self {
+      temp_dir: None,
+      idle_timeout: Some(DEFAULT_IDLE_TIMEOUT),
+      read_buffer_size: DEFAULT_READ_BUFFER_SIZE,
+      multipart_threshold: DEFAULT_MULTIPART_THRESHOLD,
+      multipart_part_size: DEFAULT_MULTIPART_PART_SIZE,
+      detect_text_charset: false,
+      progress_rate_window: DEFAULT_PROGRESS_RATE_WINDOW,
+    }
+  }
+}
+
+impl StorageConfig {
+  /// The configured temp directory, or the system one.
+  pub fn temp_dir(&self) -> PathBuf {
+    self.temp_dir.clone().unwrap_or_else(std::env::temp_dir)
+  }
+
+  /// Checks the transfer sizes, then creates the temp directory and checks that files can be
+  /// written in it.
+  pub fn validate(&self) -> Result<(), FlowyError> {
+    if self.read_buffer_size == 0 {
+      return Err(invalid_config("the read buffer size must not be 0"));
+    }
+    let part_size = self.multipart_part_size as u64;
+    if self.multipart_part_size < MIN_MULTIPART_PART_SIZE || part_size > MAX_MULTIPART_PART_SIZE {
+      return Err(invalid_config(format!(
+        "the multipart part size is {} bytes, it must be between {} and {} bytes",
+        part_size, MIN_MULTIPART_PART_SIZE, MAX_MULTIPART_PART_SIZE
+      )));
+    }
+    if self.multipart_threshold < part_size {
+      return Err(invalid_config(format!(
+        "the multipart threshold of {} bytes is smaller than the part size of {} bytes",
+        self.multipart_threshold, part_size
+      )));
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+      let temp_dir = self.temp_dir();
+      check_writable(&temp_dir).map_err(|err| {
+        invalid_config(format!(
+          "the storage temp directory {} is not writable: {}",
+          temp_dir.display(),
+          err
+        ))
+      })?;
+    }
+    Ok(())
+  }
+
+  /// Returns true if the object of `content_length` bytes is uploaded part by part, see
+  /// [Self::multipart_threshold].
+  pub fn uses_multipart(&self, content_length: Option<u64>) -> bool {
+    content_length.map_or(true, |len| len > self.multipart_threshold)
+  }
+}
+
+fn invalid_config<T: ToString>(msg: T) -> FlowyError {
+  FlowyError::new(ErrorCode::InvalidParams, msg)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn check_writable(dir: &Path) -> std::io::Result<()> {
+  std::fs::create_dir_all(dir)?;
+  let probe = dir.join(format!(".probe.{}.tmp", uuid::Uuid::new_v4()));
+  std::fs::write(&probe, b"")?;
+  std::fs::remove_file(&probe)
+}
+
+/// Validates the config and makes it the one of the process. It should be called once at
+/// startup, before the first download; the previous config is kept if the new one is invalid.
+pub fn init_storage_config(config: StorageConfig) -> Result<(), FlowyError> {
+  config.validate()?;
+  *STORAGE_CONFIG.write().unwrap() = Some(config);
+  Ok(())
+}
+
+/// The config set by [init_storage_config], or the default one.
+pub fn storage_config() -> StorageConfig {
+  STORAGE_CONFIG.read().unwrap().clone().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn validate_temp_dir_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = StorageConfig {
+      temp_dir: Some(dir.path().join("staging")),
+      ..Default::default()
+    };
+    config.validate().unwrap();
+    assert!(dir.path().join("staging").is_dir());
+    assert_eq!(
+      std::fs::read_dir(dir.path().join("staging"))
+        .unwrap()
+        .count(),
+      0
+    );
+
+    // A file is in the way of the directory.
+    let file = dir.path().join("file");
+    std::fs::write(&file, b"").unwrap();
+    let config = StorageConfig {
+      temp_dir: Some(file.join("staging")),
+      ..Default::default()
+    };
+    let err = config.validate().unwrap_err();
+    assert_eq!(err.code, ErrorCode::InvalidParams);
+    assert!(err.msg.contains("not writable"));
+  }
+
+  #[test]
+  fn validate_transfer_sizes_test() {
+    let config = StorageConfig {
+      multipart_threshold: 32 * 1024 * 1024,
+      multipart_part_size: 16 * 1024 * 1024,
+      ..Default::default()
+    };
+    config.validate().unwrap();
+    assert!(!config.uses_multipart(Some(32 * 1024 * 1024)));
+    assert!(config.uses_multipart(Some(32 * 1024 * 1024 + 1)));
+    assert!(config.uses_multipart(None));
+
+    for (config, msg) in [
+      (
+        StorageConfig {
+          read_buffer_size: 0,
+          ..Default::default()
+        },
+        "read buffer size",
+      ),
+      (
+        StorageConfig {
+          multipart_part_size: 1024,
+          ..Default::default()
+        },
+        "must be between",
+      ),
+      (
+        StorageConfig {
+          multipart_threshold: 1024,
+          ..Default::default()
+        },
+        "smaller than the part size",
+      ),
+    ] {
+      let err = config.validate().unwrap_err();
+      assert_eq!(err.code, ErrorCode::InvalidParams);
+      assert!(err.msg.contains(msg), "{}", err.msg);
+    }
+  }
+}
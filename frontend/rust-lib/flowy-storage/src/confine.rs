@@ -0,0 +1,124 @@
+use std::path::{Component, Path};
+
+use flowy_error::FlowyError;
+
+use crate::{io_error, storage_error, StorageErrorKind};
+
+/// Checks that `path` stays inside `root`: below the root, it must only have normal components,
+/// no `..`, and its existing part must not resolve to somewhere else through a symlink. The
+/// local backends check the paths their urls and keys map to with it before reading or writing
+/// them, the error is a [StorageErrorKind::PathTraversal].
+///
+/// The check is not atomic with the operation that follows, a symlink created in between is not
+/// caught. It guards against crafted urls and keys, not against another process writing in the
+/// root.
+pub async fn check_path_in_root(root: &Path, path: &Path) -> Result<(), FlowyError> {
+  let relative = path.strip_prefix(root).map_err(|_| path_traversal(path))?;
+  if !relative
+    .components()
+    .all(|component| matches!(component, Component::Normal(_)))
+  {
+    return Err(path_traversal(path));
+  }
+
+  let canonical_root = match tokio::fs::canonicalize(root).await {
+    Ok(canonical_root) => canonical_root,
+    // Nothing was written yet, there is no symlink to follow.
+    Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+    Err(err) => return Err(io_error(err, root)),
+  };
+  // The file, or the directories it will be written in, may not exist yet. The deepest ancestor
+  // that exists is the one a symlink could redirect.
+  let mut existing = path;
+  let resolved = loop {
+    match tokio::fs::canonicalize(existing).await {
+      Ok(resolved) => break resolved,
+      Err(err) if err.kind() == std::io::ErrorKind::NotFound && existing != root => {
+        existing = existing.parent().unwrap_or(root);
+      },
+      Err(err) => return Err(io_error(err, existing)),
+    }
+  };
+  if resolved.starts_with(&canonical_root) {
+    Ok(())
+  } else {
+    Err(path_traversal(path))
+  }
+}
+
+pub(crate) fn path_traversal(path: &Path) -> FlowyError {
+  storage_error(
+    StorageErrorKind::PathTraversal,
+    format!("{} is outside of the storage directory", path.display()),
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::StorageErrorExt;
+
+  #[tokio::test]
+  async fn check_path_in_root_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("root");
+    // The root doesn't exist yet.
+    check_path_in_root(&root, &root.join("w1/1.txt"))
+      .await
+      .unwrap();
+
+    std::fs::create_dir_all(root.join("w1")).unwrap();
+    check_path_in_root(&root, &root.join("w1/1.txt"))
+      .await
+      .unwrap();
+    check_path_in_root(&root, &root.join("w2/1.txt"))
+      .await
+      .unwrap();
+
+    for path in [
+      root.join("../../etc/passwd"),
+      root.join("w1/../../secret.txt"),
+      dir.path().join("secret.txt"),
+    ] {
+      let err = check_path_in_root(&root, &path).await.unwrap_err();
+      assert!(
+        err.is_storage_kind(StorageErrorKind::PathTraversal),
+        "{:?}",
+        path
+      );
+    }
+  }
+
+  #[cfg(unix)]
+  #[tokio::test]
+  async fn reject_symlink_out_of_root_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("root");
+    let outside = dir.path().join("outside");
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::create_dir_all(&outside).unwrap();
+    std::fs::write(outside.join("secret.txt"), b"secret").unwrap();
+
+    std::os::unix::fs::symlink(&outside, root.join("w1")).unwrap();
+    std::os::unix::fs::symlink(outside.join("secret.txt"), root.join("link.txt")).unwrap();
+    for path in [
+      root.join("w1/secret.txt"),
+      root.join("w1/new.txt"),
+      root.join("link.txt"),
+    ] {
+      let err = check_path_in_root(&root, &path).await.unwrap_err();
+      assert!(
+        err.is_storage_kind(StorageErrorKind::PathTraversal),
+        "{:?}",
+        path
+      );
+    }
+
+    // A symlink that stays inside the root is followed.
+    std::fs::create_dir_all(root.join("w2")).unwrap();
+    std::os::unix::fs::symlink(root.join("w2"), root.join("w3")).unwrap();
+    check_path_in_root(&root, &root.join("w3/1.txt"))
+      .await
+      .unwrap();
+  }
+}
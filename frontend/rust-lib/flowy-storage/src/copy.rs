@@ -0,0 +1,190 @@
+use flowy_error::{ErrorCode, FlowyError};
+
+use crate::{ObjectIdentity, ObjectStorageService};
+
+/// Copies the object on the server if the service supports it, see
+/// [ObjectStorageService::copy_object]. Otherwise the object is downloaded and uploaded again
+/// under the url of `dst_identity`, with the same mime type.
+///
+/// Returns the url of the copy. Nothing is transferred if the copy has the same url as the
+/// source, which happens when the source already belongs to the destination workspace.
+pub async fn copy_object_or_reupload<S>(
+  service: &S,
+  src_url: String,
+  dst_identity: ObjectIdentity,
+) -> Result<String, FlowyError>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  if service.supports_copy_object() {
+    return service.copy_object(src_url, dst_identity).await;
+  }
+
+  let dst_url = service.get_object_url(dst_identity).await?;
+  if dst_url == src_url {
+    return Ok(dst_url);
+  }
+  let value = service.get_object(src_url).await?;
+  service.put_object(dst_url.clone(), value).await?;
+  Ok(dst_url)
+}
+
+/// The default implementation of [ObjectStorageService::move_object].
+pub(crate) async fn copy_and_delete<S>(
+  service: &S,
+  src_url: String,
+  dst_identity: ObjectIdentity,
+  overwrite: bool,
+) -> Result<String, FlowyError>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  let dst_url = service.get_object_url(dst_identity.clone()).await?;
+  if dst_url == src_url {
+    return Ok(dst_url);
+  }
+  if !overwrite && service.object_exists(dst_url.clone()).await? {
+    return Err(destination_exists(&dst_url));
+  }
+  let dst_url = copy_object_or_reupload(service, src_url.clone(), dst_identity).await?;
+  service.delete_object(src_url).await?;
+  Ok(dst_url)
+}
+
+/// The error of a move onto an existing object without `overwrite`.
+pub(crate) fn destination_exists(url: &str) -> FlowyError {
+  FlowyError::new(
+    ErrorCode::RecordAlreadyExists,
+    format!("{} already exists", url),
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::memory::DefaultsOnly;
+  use crate::{InMemoryObjectStorage, ObjectValue, StorageOperation};
+
+  use super::*;
+
+  const SRC_URL: &str = "memory://w1/1.pdf";
+
+  fn identity(workspace_id: &str) -> ObjectIdentity {
+    ObjectIdentity {
+      workspace_id: workspace_id.to_string(),
+      file_id: "1".to_string(),
+      ext: "pdf".to_string(),
+      hash_algorithm: None,
+      size: None,
+    }
+  }
+
+  async fn put_pdf(storage: &impl ObjectStorageService) {
+    let value = ObjectValue {
+      raw: b"pdf".to_vec().into(),
+      mime: mime::APPLICATION_PDF,
+      content_encoding: None,
+    };
+    storage
+      .put_object(SRC_URL.to_string(), value)
+      .await
+      .unwrap();
+  }
+
+  /// The number of uploads and downloads, a server side copy transfers nothing.
+  fn transfers(storage: &InMemoryObjectStorage) -> usize {
+    storage.call_count(StorageOperation::Put) + storage.call_count(StorageOperation::Get)
+  }
+
+  fn read_only() -> FlowyError {
+    FlowyError::new(ErrorCode::NotEnoughPermissions, "read only")
+  }
+
+  async fn copy_test<S: ObjectStorageService>(
+    storage: &S,
+    inner: &InMemoryObjectStorage,
+    server_side_copy: bool,
+  ) {
+    put_pdf(storage).await;
+    let transfers_before = transfers(inner);
+    let url = copy_object_or_reupload(storage, SRC_URL.to_string(), identity("w2"))
+      .await
+      .unwrap();
+    assert_eq!(url, "memory://w2/1.pdf");
+    let copy = storage.get_object(url).await.unwrap();
+    assert_eq!(copy.mime, mime::APPLICATION_PDF);
+    assert_eq!(copy.raw.as_ref(), b"pdf");
+    // The server side copy doesn't transfer the content, only the get above does.
+    let expected = if server_side_copy { 1 } else { 3 };
+    assert_eq!(transfers(inner) - transfers_before, expected);
+  }
+
+  #[tokio::test]
+  async fn copy_object_test() {
+    let storage = InMemoryObjectStorage::new();
+    copy_test(&storage, &storage, true).await;
+    let storage = DefaultsOnly::default();
+    copy_test(&storage, &storage.0, false).await;
+  }
+
+  #[tokio::test]
+  async fn copy_to_read_only_workspace_test() {
+    let storage = DefaultsOnly::default();
+    put_pdf(&storage).await;
+    let transfers_before = transfers(&storage.0);
+    storage.0.fail_next(StorageOperation::Put, read_only());
+    let err = copy_object_or_reupload(&storage, SRC_URL.to_string(), identity("readonly"))
+      .await
+      .unwrap_err();
+    assert_eq!(err.code, ErrorCode::NotEnoughPermissions);
+
+    // Copying to the same url doesn't transfer anything.
+    let url = copy_object_or_reupload(&storage, SRC_URL.to_string(), identity("w1"))
+      .await
+      .unwrap();
+    assert_eq!(url, SRC_URL);
+    assert_eq!(transfers(&storage.0) - transfers_before, 2);
+  }
+
+  async fn move_test<S: ObjectStorageService>(storage: &S, inner: &InMemoryObjectStorage) {
+    put_pdf(storage).await;
+    let url = copy_and_delete(storage, SRC_URL.to_string(), identity("w2"), false)
+      .await
+      .unwrap();
+    assert_eq!(url, "memory://w2/1.pdf");
+    assert!(inner.object(SRC_URL).is_none());
+    assert_eq!(inner.len(), 1);
+  }
+
+  #[tokio::test]
+  async fn move_object_test() {
+    let storage = InMemoryObjectStorage::new();
+    move_test(&storage, &storage).await;
+    let storage = DefaultsOnly::default();
+    move_test(&storage, &storage.0).await;
+
+    // The source is kept when the destination can't be written.
+    let storage = DefaultsOnly::default();
+    put_pdf(&storage).await;
+    storage.0.fail_next(StorageOperation::Put, read_only());
+    let err = storage
+      .move_object(SRC_URL.to_string(), identity("readonly"), false)
+      .await
+      .unwrap_err();
+    assert_eq!(err.code, ErrorCode::NotEnoughPermissions);
+    assert!(storage.0.object(SRC_URL).is_some());
+
+    copy_object_or_reupload(&storage, SRC_URL.to_string(), identity("w2"))
+      .await
+      .unwrap();
+    let err = storage
+      .move_object(SRC_URL.to_string(), identity("w2"), false)
+      .await
+      .unwrap_err();
+    assert_eq!(err.code, ErrorCode::RecordAlreadyExists);
+    storage
+      .move_object(SRC_URL.to_string(), identity("w2"), true)
+      .await
+      .unwrap();
+    assert_eq!(storage.0.len(), 1);
+  }
+}
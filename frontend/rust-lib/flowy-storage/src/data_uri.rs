@@ -0,0 +1,113 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use bytes::Bytes;
+use mime::Mime;
+use percent_encoding::percent_decode_str;
+
+use flowy_error::{ErrorCode, FlowyError};
+
+/// The media type of a `data:` URI that doesn't give one, see RFC 2397.
+const DEFAULT_DATA_URI_MIME: &str = "text/plain;charset=US-ASCII";
+
+fn invalid_data_uri<T: ToString>(msg: T) -> FlowyError {
+  FlowyError::new(
+    ErrorCode::InvalidParams,
+    format!("invalid data URI: {}", msg.to_string()),
+  )
+}
+
+/// Parses a `data:[<media type>][;base64],<data>` URI, as defined by RFC 2397, and returns its
+/// media type and its decoded content. The data is percent-decoded, then base64-decoded if the
+/// URI says so. Whitespace in the base64 data is ignored, editors often wrap long URIs.
+///
+/// Returns an [ErrorCode::InvalidParams] error if the URI is not a `data:` URI, has no comma
+/// before its data, has an invalid media type, uses an encoding other than base64, or if its
+/// base64 data can't be decoded.
+pub fn parse_data_uri(uri: &str) -> Result<(Mime, Bytes), FlowyError> {
+  let uri = uri.trim();
+  let rest = uri
+    .get(..5)
+    .filter(|scheme| scheme.eq_ignore_ascii_case("data:"))
+    .map(|_| &uri[5..])
+    .ok_or_else(|| invalid_data_uri("it doesn't start with data:"))?;
+  let (header, data) = rest
+    .split_once(',')
+    .ok_or_else(|| invalid_data_uri("there is no comma before the data"))?;
+
+  let mut params = header.split(';').map(str::trim).collect::<Vec<_>>();
+  let is_base64 = params
+    .last()
+    .map_or(false, |param| param.eq_ignore_ascii_case("base64"));
+  if is_base64 {
+    params.pop();
+  }
+  let (media_type, params) = params.split_first().unwrap_or((&"", &[]));
+  if let Some(encoding) = params.iter().find(|param| !param.contains('=')) {
+    return Err(invalid_data_uri(format!(
+      "the encoding {} is not supported",
+      encoding
+    )));
+  }
+  let mime = if media_type.is_empty() && params.is_empty() {
+    DEFAULT_DATA_URI_MIME.to_string()
+  } else if media_type.is_empty() {
+    format!("text/plain;{}", params.join(";"))
+  } else {
+    std::iter::once(*media_type)
+      .chain(params.iter().copied())
+      .collect::<Vec<_>>()
+      .join(";")
+  };
+  let mime = mime
+    .parse::<Mime>()
+    .map_err(|err| invalid_data_uri(format!("the media type {} is invalid: {}", mime, err)))?;
+
+  let data = percent_decode_str(data).collect::<Vec<u8>>();
+  let content = if is_base64 {
+    let data = data
+      .into_iter()
+      .filter(|byte| !byte.is_ascii_whitespace())
+      .collect::<Vec<u8>>();
+    STANDARD
+      .decode(data)
+      .map_err(|err| invalid_data_uri(format!("the base64 data is invalid: {}", err)))?
+  } else {
+    data
+  };
+  Ok((mime, content.into()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parse_data_uri_test() {
+    let (mime, content) = parse_data_uri("data:image/png;base64,iVBORw0K\nGgo=").unwrap();
+    assert_eq!(mime, mime::IMAGE_PNG);
+    assert_eq!(content.as_ref(), b"\x89PNG\r\n\x1a\n");
+
+    let (mime, content) = parse_data_uri("data:,Hello%2C%20World").unwrap();
+    assert_eq!(mime.essence_str(), "text/plain");
+    assert_eq!(mime.get_param(mime::CHARSET).unwrap(), "US-ASCII");
+    assert_eq!(content.as_ref(), b"Hello, World");
+
+    let (mime, _) = parse_data_uri("DATA:text/html;charset=utf-8,<p></p>").unwrap();
+    assert_eq!(mime, mime::TEXT_HTML_UTF_8);
+  }
+
+  #[test]
+  fn malformed_data_uri_test() {
+    for (uri, msg) in [
+      ("https://host/a.png", "doesn't start with data:"),
+      ("data:image/png;base64", "no comma"),
+      ("data:image/png;base64,%%%", "base64 data is invalid"),
+      ("data:image/png;gzip,abc", "encoding gzip is not supported"),
+      ("data:image,abc", "media type image is invalid"),
+    ] {
+      let err = parse_data_uri(uri).unwrap_err();
+      assert_eq!(err.code, ErrorCode::InvalidParams);
+      assert!(err.msg.contains(msg), "{}: {}", uri, err.msg);
+    }
+  }
+}
@@ -0,0 +1,228 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use tracing::{info, warn};
+
+use flowy_error::FlowyError;
+
+use crate::{ObjectIdentity, ObjectStorageService, ObjectValue};
+
+/// Counts the uploads skipped by [put_object_dedup], for telemetry. It can be shared by all the
+/// uploads of the app.
+#[derive(Debug, Default)]
+pub struct DedupStats {
+  skipped_uploads: AtomicU64,
+  bytes_saved: AtomicU64,
+}
+
+impl DedupStats {
+  pub fn skipped_uploads(&self) -> u64 {
+    self.skipped_uploads.load(Ordering::Relaxed)
+  }
+
+  /// The number of bytes that weren't uploaded because the content was already stored.
+  pub fn bytes_saved(&self) -> u64 {
+    self.bytes_saved.load(Ordering::Relaxed)
+  }
+
+  fn record_skipped(&self, bytes: u64) {
+    self.skipped_uploads.fetch_add(1, Ordering::Relaxed);
+    self.bytes_saved.fetch_add(bytes, Ordering::Relaxed);
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct DedupOptions {
+  /// Only skip the upload if the stored object has the same size as the content, with
+  /// [ObjectStorageService::head_object] instead of [ObjectStorageService::object_exists]. It
+  /// guards against collisions of the content hash, at the cost of a more expensive check on
+  /// services that don't implement `head_object`. Enabled by default, the content hash is not
+  /// cryptographic.
+  pub compare_size: bool,
+}
+
+impl Default for DedupOptions {
+  fn default() -> Self {
+    Self { compare_size: true }
+  }
+}
+
+pub enum DedupOutcome {
+  Uploaded(String),
+  /// The content was already stored, the upload was skipped.
+  AlreadyExists(String),
+}
+
+impl DedupOutcome {
+  pub fn url(&self) -> &str {
+    match self {
+      DedupOutcome::Uploaded(url) | DedupOutcome::AlreadyExists(url) => url,
+    }
+  }
+
+  pub fn into_url(self) -> String {
+    match self {
+      DedupOutcome::Uploaded(url) | DedupOutcome::AlreadyExists(url) => url,
+    }
+  }
+}
+
+/// Uploads the object unless an object with the same content is already stored. The `file_id`
+/// of an object is its content hash, so the object stored under the same identity has the same
+/// content and re-uploading it is a waste of bandwidth.
+///
+/// Objects whose `file_id` is not a content hash, see [ObjectIdentity::hash_algorithm], are
+/// always uploaded because uploading them replaces the stored content. If the existence of the
+/// object can't be checked, the object is uploaded as well.
+pub async fn put_object_dedup<S>(
+  service: &S,
+  identity: ObjectIdentity,
+  value: ObjectValue,
+  options: &DedupOptions,
+  stats: Option<&DedupStats>,
+) -> Result<DedupOutcome, FlowyError>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  let is_content_hash = identity.hash_algorithm.is_some();
+  let size = identity.size;
+  let url = service.get_object_url(identity).await?;
+  if is_content_hash {
+    let size = match size {
+      Some(size) => size,
+      None => value.clone().decompress()?.raw.len() as u64,
+    };
+    let exists = if options.compare_size {
+      match service.head_object(url.clone()).await {
+        Ok(meta) if meta.size == size => Ok(true),
+        Ok(meta) => {
+          warn!(
+            "{} is stored with {} bytes instead of {}, uploading it again",
+            url, meta.size, size
+          );
+          Ok(false)
+        },
+        Err(err) if err.is_record_not_found() => Ok(false),
+        Err(err) => Err(err),
+      }
+    } else {
+      service.object_exists(url.clone()).await
+    };
+
+    match exists {
+      Ok(true) => {
+        info!("{} is already stored, skip uploading {} bytes", url, size);
+        if let Some(stats) = stats {
+          stats.record_skipped(size);
+        }
+        return Ok(DedupOutcome::AlreadyExists(url));
+      },
+      Ok(false) => {},
+      Err(err) => warn!("failed to check if {} exists: {}, uploading it", url, err),
+    }
+  }
+
+  service.put_object(url.clone(), value).await?;
+  Ok(DedupOutcome::Uploaded(url))
+}
+
+#[cfg(test)]
+mod tests {
+  use flowy_error::ErrorCode;
+
+  use super::*;
+  use crate::{content_hash, memory_object_value, InMemoryObjectStorage, StorageOperation};
+
+  fn identity(content: &[u8]) -> ObjectIdentity {
+    ObjectIdentity {
+      workspace_id: "w1".to_string(),
+      file_id: content_hash(content),
+      ext: "txt".to_string(),
+      hash_algorithm: Some(crate::DEFAULT_HASH_ALGORITHM.to_string()),
+      size: None,
+    }
+  }
+
+  #[tokio::test]
+  async fn skip_upload_of_stored_content_test() {
+    let storage = InMemoryObjectStorage::new();
+    let stats = DedupStats::default();
+    let options = DedupOptions::default();
+    for expected_uploads in [1, 1] {
+      let outcome = put_object_dedup(
+        &storage,
+        identity(b"hello"),
+        memory_object_value("a.txt", "hello"),
+        &options,
+        Some(&stats),
+      )
+      .await
+      .unwrap();
+      assert!(outcome.url().ends_with(".txt"));
+      assert_eq!(storage.call_count(StorageOperation::Put), expected_uploads);
+    }
+    assert_eq!((stats.skipped_uploads(), stats.bytes_saved()), (1, 5));
+  }
+
+  #[tokio::test]
+  async fn upload_on_size_mismatch_or_failed_check_test() {
+    let storage = InMemoryObjectStorage::new();
+    let url = storage.get_object_url(identity(b"hello")).await.unwrap();
+    storage
+      .put_object(url, memory_object_value("a.txt", "collision"))
+      .await
+      .unwrap();
+
+    let outcome = put_object_dedup(
+      &storage,
+      identity(b"hello"),
+      memory_object_value("a.txt", "hello"),
+      &DedupOptions::default(),
+      None,
+    )
+    .await
+    .unwrap();
+    assert!(matches!(outcome, DedupOutcome::Uploaded(_)));
+
+    storage.fail_next(
+      StorageOperation::Head,
+      FlowyError::new(ErrorCode::ConnectTimeout, "timeout"),
+    );
+    let outcome = put_object_dedup(
+      &storage,
+      identity(b"hello"),
+      memory_object_value("a.txt", "hello"),
+      &DedupOptions::default(),
+      None,
+    )
+    .await
+    .unwrap();
+    assert!(matches!(outcome, DedupOutcome::Uploaded(_)));
+    assert_eq!(storage.call_count(StorageOperation::Put), 3);
+  }
+
+  #[tokio::test]
+  async fn colliding_file_id_is_not_the_same_object_test() {
+    let storage = InMemoryObjectStorage::new();
+    let stats = DedupStats::default();
+    // Two payloads forced to share the same fake id.
+    let fake_id = |size: u64| ObjectIdentity {
+      file_id: "fake".to_string(),
+      size: Some(size),
+      ..identity(b"")
+    };
+    for (content, expected_uploads) in [("hello", 1), ("hello world", 2)] {
+      let outcome = put_object_dedup(
+        &storage,
+        fake_id(content.len() as u64),
+        memory_object_value("a.txt", content),
+        &DedupOptions::default(),
+        Some(&stats),
+      )
+      .await
+      .unwrap();
+      assert!(matches!(outcome, DedupOutcome::Uploaded(_)));
+      assert_eq!(storage.call_count(StorageOperation::Put), expected_uploads);
+    }
+    assert_eq!(stats.skipped_uploads(), 0);
+  }
+}
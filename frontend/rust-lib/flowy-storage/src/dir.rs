@@ -0,0 +1,458 @@
+use std::collections::BTreeSet;
+use std::path::{Component, Path, PathBuf};
+
+use flowy_error::{ErrorCode, FlowyError};
+
+use crate::StorageObject;
+
+/// Controls which files [objects_from_dir] picks up.
+#[derive(Debug, Clone)]
+pub struct DirWalkOptions {
+  /// Walk the subdirectories too.
+  pub recursive: bool,
+  /// Include the files and directories whose name starts with a `.`.
+  pub include_hidden: bool,
+  /// Include the symlinks that point to a regular file. Symlinks to directories are never
+  /// walked, so a symlink cycle can't make the walk loop forever.
+  pub follow_symlinks: bool,
+}
+
+impl Default for DirWalkOptions {
+  fn default() -> Self {
+    Self {
+      recursive: true,
+      include_hidden: false,
+      follow_symlinks: false,
+    }
+  }
+}
+
+/// The result of [objects_from_dir].
+#[derive(Default)]
+pub struct DirObjects {
+  /// One object per regular file, sorted by `original_file_name`.
+  pub objects: Vec<StorageObject>,
+  /// The files and directories that couldn't be read. They don't stop the walk.
+  pub errors: Vec<(PathBuf, FlowyError)>,
+}
+
+/// Creates a [StorageObject] for every regular file in `dir_path`. The `original_file_name` of an
+/// object is the path of the file relative to `dir_path`, with `/` as separator, for example
+/// `images/cover.png`, its `file_name` is the name of the file.
+///
+/// Returns an [ErrorCode::InvalidParams] error if `dir_path` isn't a directory. Any other error
+/// is recorded in [DirObjects::errors] and the walk goes on with the next file.
+pub fn objects_from_dir(
+  workspace_id: &str,
+  dir_path: impl AsRef<Path>,
+  options: &DirWalkOptions,
+) -> Result<DirObjects, FlowyError> {
+  let dir_path = dir_path.as_ref();
+  let is_dir = std::fs::metadata(dir_path)
+    .map(|metadata| metadata.is_dir())
+    .unwrap_or(false);
+  if !is_dir {
+    return Err(FlowyError::new(
+      ErrorCode::InvalidParams,
+      format!("{} is not a directory", dir_path.display()),
+    ));
+  }
+
+  let mut result = DirObjects::default();
+  walk_dir(workspace_id, dir_path, "", options, &mut result);
+  result
+    .objects
+    .sort_by(|a, b| a.original_file_name.cmp(&b.original_file_name));
+  Ok(result)
+}
+
+fn walk_dir(
+  workspace_id: &str,
+  dir_path: &Path,
+  relative_dir: &str,
+  options: &DirWalkOptions,
+  result: &mut DirObjects,
+) {
+  let entries = match std::fs::read_dir(dir_path) {
+    Ok(entries) => entries,
+    Err(err) => {
+      result.errors.push((dir_path.to_path_buf(), err.into()));
+      return;
+    },
+  };
+
+  for entry in entries {
+    let entry = match entry {
+      Ok(entry) => entry,
+      Err(err) => {
+        result.errors.push((dir_path.to_path_buf(), err.into()));
+        continue;
+      },
+    };
+    let path = entry.path();
+    let name = entry.file_name().to_string_lossy().into_owned();
+    if !options.include_hidden && name.starts_with('.') {
+      continue;
+    }
+    let relative_path = if relative_dir.is_empty() {
+      name
+    } else {
+      format!("{}/{}", relative_dir, name)
+    };
+
+    let file_type = match entry.file_type() {
+      Ok(file_type) => file_type,
+      Err(err) => {
+        result.errors.push((path, err.into()));
+        continue;
+      },
+    };
+    if file_type.is_dir() {
+      if options.recursive {
+        walk_dir(workspace_id, &path, &relative_path, options, result);
+      }
+      continue;
+    }
+    if file_type.is_symlink() {
+      if !options.follow_symlinks || path.is_dir() {
+        continue;
+      }
+    } else if !file_type.is_file() {
+      continue;
+    }
+
+    match StorageObject::try_from_file(workspace_id, &relative_path, path.display()) {
+      Ok(object) => result.objects.push(object),
+      Err(err) => result.errors.push((path, err)),
+    }
+  }
+}
+
+/// Creates a [StorageObject] for every file matched by the glob `pattern`, for example
+/// `~/Pictures/**/*.png`. A leading `~` is the home directory of the user.
+///
+/// A component of the pattern can use `*` and `?`, which don't match a leading `.` unless the
+/// component starts with one, and character classes like `[a-z]` or `[!0-9]`. A `**` component
+/// matches any number of directories, it doesn't walk the hidden ones or the symlinks to
+/// directories. The directories that match are skipped, and so are the ones that can't be read.
+///
+/// The `original_file_name` of an object is the path of the file relative to the directory the
+/// pattern starts from, like with [objects_from_dir], and the objects are sorted by it. A pattern
+/// that matches nothing returns no objects, an invalid one an [ErrorCode::InvalidParams] error.
+pub fn objects_from_glob(
+  workspace_id: &str,
+  pattern: &str,
+) -> Result<Vec<StorageObject>, FlowyError> {
+  let expanded = expand_home(pattern)?;
+  let (base_dir, components) = parse_glob(pattern, &expanded)?;
+  let mut paths = BTreeSet::new();
+  glob_dir(&base_dir, &components, &mut paths);
+
+  let mut objects = paths
+    .into_iter()
+    .map(|path| {
+      let relative_path = relative_file_name(&base_dir, &path);
+      StorageObject::from_file(workspace_id, &relative_path, path.display())
+    })
+    .collect::<Vec<_>>();
+  objects.sort_by(|a, b| a.original_file_name.cmp(&b.original_file_name));
+  Ok(objects)
+}
+
+enum GlobComponent {
+  /// `**`, any number of directories.
+  AnyDirs,
+  Pattern(Vec<char>),
+}
+
+fn invalid_glob(pattern: &str, reason: &str) -> FlowyError {
+  FlowyError::new(
+    ErrorCode::InvalidParams,
+    format!("invalid glob pattern {:?}: {}", pattern, reason),
+  )
+}
+
+fn expand_home(pattern: &str) -> Result<PathBuf, FlowyError> {
+  let rest = match pattern.strip_prefix('~') {
+    Some(rest) if rest.is_empty() || rest.starts_with(std::path::is_separator) => rest,
+    _ => return Ok(PathBuf::from(pattern)),
+  };
+  let home = std::env::var_os("HOME")
+    .or_else(|| std::env::var_os("USERPROFILE"))
+    .ok_or_else(|| invalid_glob(pattern, "the home directory is unknown"))?;
+  Ok(PathBuf::from(home).join(rest.trim_start_matches(std::path::is_separator)))
+}
+
+/// Splits the pattern into the directory it starts from, the leading components without
+/// wildcards, and the components to match.
+fn parse_glob(pattern: &str, expanded: &Path) -> Result<(PathBuf, Vec<GlobComponent>), FlowyError> {
+  let mut base_dir = PathBuf::new();
+  let mut components = vec![];
+  for component in expanded.components() {
+    let name = match component {
+      Component::Normal(name) => name.to_string_lossy(),
+      _ if components.is_empty() => {
+        base_dir.push(component);
+        continue;
+      },
+      _ => {
+        return Err(invalid_glob(
+          pattern,
+          "`.` and `..` can't follow a wildcard",
+        ))
+      },
+    };
+    if components.is_empty() && !has_wildcard(&name) {
+      base_dir.push(name.as_ref());
+      continue;
+    }
+    if name == "**" {
+      components.push(GlobComponent::AnyDirs);
+      continue;
+    }
+    if name.contains("**") {
+      return Err(invalid_glob(pattern, "`**` must be a whole path component"));
+    }
+    let chars = name.chars().collect::<Vec<_>>();
+    check_classes(&chars).map_err(|reason| invalid_glob(pattern, reason))?;
+    components.push(GlobComponent::Pattern(chars));
+  }
+  Ok((base_dir, components))
+}
+
+fn has_wildcard(name: &str) -> bool {
+  name.contains(['*', '?', '['])
+}
+
+fn check_classes(pattern: &[char]) -> Result<(), &'static str> {
+  let mut i = 0;
+  while i < pattern.len() {
+    if pattern[i] == '[' {
+      i = class_end(pattern, i).ok_or("unclosed character class")?;
+    }
+    i += 1;
+  }
+  Ok(())
+}
+
+/// The index of the `]` closing the class that starts at `start`. A `]` right after the `[` or
+/// the negation is part of the class.
+fn class_end(pattern: &[char], start: usize) -> Option<usize> {
+  let mut i = start + 1;
+  if matches!(pattern.get(i), Some('!' | '^')) {
+    i += 1;
+  }
+  if pattern.get(i) == Some(&']') {
+    i += 1;
+  }
+  (i..pattern.len()).find(|&i| pattern[i] == ']')
+}
+
+fn glob_dir(dir: &Path, components: &[GlobComponent], paths: &mut BTreeSet<PathBuf>) {
+  let (component, rest) = match components.split_first() {
+    Some(split) => split,
+    None => {
+      if dir.is_file() {
+        paths.insert(dir.to_path_buf());
+      }
+      return;
+    },
+  };
+  let entries = match std::fs::read_dir(dir) {
+    Ok(entries) => entries.flatten().collect::<Vec<_>>(),
+    Err(_) => return,
+  };
+  match component {
+    GlobComponent::AnyDirs => {
+      glob_dir(dir, rest, paths);
+      for entry in entries {
+        let is_dir = entry.file_type().map(|file_type| file_type.is_dir());
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        if is_dir.unwrap_or(false) && !hidden {
+          glob_dir(&entry.path(), components, paths);
+        }
+      }
+    },
+    GlobComponent::Pattern(pattern) => {
+      for entry in entries {
+        let name = entry
+          .file_name()
+          .to_string_lossy()
+          .chars()
+          .collect::<Vec<_>>();
+        if name.first() == Some(&'.') && pattern.first() != Some(&'.') {
+          continue;
+        }
+        if matches_component(pattern, &name) {
+          glob_dir(&entry.path(), rest, paths);
+        }
+      }
+    },
+  }
+}
+
+fn matches_component(pattern: &[char], name: &[char]) -> bool {
+  match pattern.first() {
+    None => name.is_empty(),
+    Some('*') => (0..=name.len()).any(|skip| matches_component(&pattern[1..], &name[skip..])),
+    Some('?') => !name.is_empty() && matches_component(&pattern[1..], &name[1..]),
+    Some('[') => {
+      let end = match class_end(pattern, 0) {
+        Some(end) => end,
+        None => return false,
+      };
+      match name.first() {
+        Some(c) if matches_class(&pattern[1..end], *c) => {
+          matches_component(&pattern[end + 1..], &name[1..])
+        },
+        _ => false,
+      }
+    },
+    Some(c) => name.first() == Some(c) && matches_component(&pattern[1..], &name[1..]),
+  }
+}
+
+fn matches_class(class: &[char], c: char) -> bool {
+  let (negated, class) = match class.first() {
+    Some('!' | '^') => (true, &class[1..]),
+    _ => (false, class),
+  };
+  let mut matched = false;
+  let mut i = 0;
+  while i < class.len() {
+    if i + 2 < class.len() && class[i + 1] == '-' {
+      matched |= (class[i]..=class[i + 2]).contains(&c);
+      i += 3;
+    } else {
+      matched |= class[i] == c;
+      i += 1;
+    }
+  }
+  matched != negated
+}
+
+/// The path of the file relative to `base_dir` with `/` as separator, or its name if the
+/// pattern had no wildcard.
+fn relative_file_name(base_dir: &Path, path: &Path) -> String {
+  let relative = path
+    .strip_prefix(base_dir)
+    .ok()
+    .filter(|relative| !relative.as_os_str().is_empty())
+    .unwrap_or_else(|| path.file_name().map(Path::new).unwrap_or(path));
+  relative
+    .components()
+    .map(|component| component.as_os_str().to_string_lossy())
+    .collect::<Vec<_>>()
+    .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn file_names(objects: &DirObjects) -> Vec<&str> {
+    objects
+      .objects
+      .iter()
+      .map(|object| object.original_file_name.as_str())
+      .collect()
+  }
+
+  #[test]
+  fn objects_from_dir_test() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(dir.path().join("images/icons")).unwrap();
+    std::fs::create_dir_all(dir.path().join(".git")).unwrap();
+    for file in [
+      "a.md",
+      ".hidden",
+      "images/cover.png",
+      "images/icons/x.svg",
+      ".git/HEAD",
+    ] {
+      std::fs::write(dir.path().join(file), b"content").unwrap();
+    }
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(dir.path().join("a.md"), dir.path().join("link.md")).unwrap();
+
+    let objects = objects_from_dir("w1", dir.path(), &DirWalkOptions::default()).unwrap();
+    assert_eq!(
+      file_names(&objects),
+      vec!["a.md", "images/cover.png", "images/icons/x.svg"]
+    );
+    assert!(objects.errors.is_empty());
+    assert_eq!(objects.objects[0].workspace_id, "w1");
+
+    let options = DirWalkOptions {
+      recursive: false,
+      ..Default::default()
+    };
+    let objects = objects_from_dir("w1", dir.path(), &options).unwrap();
+    assert_eq!(file_names(&objects), vec!["a.md"]);
+
+    #[cfg(unix)]
+    {
+      let options = DirWalkOptions {
+        recursive: false,
+        follow_symlinks: true,
+        include_hidden: true,
+      };
+      let objects = objects_from_dir("w1", dir.path(), &options).unwrap();
+      assert_eq!(file_names(&objects), vec![".hidden", "a.md", "link.md"]);
+    }
+
+    let err = objects_from_dir("w1", dir.path().join("a.md"), &DirWalkOptions::default())
+      .err()
+      .unwrap();
+    assert_eq!(err.code, ErrorCode::InvalidParams);
+  }
+
+  #[test]
+  fn objects_from_glob_test() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(dir.path().join("images/2024/cover.png")).unwrap();
+    std::fs::create_dir_all(dir.path().join(".cache")).unwrap();
+    for file in [
+      "a.png",
+      "b.txt",
+      ".hidden.png",
+      "images/c.png",
+      "images/2024/d.png",
+      ".cache/e.png",
+    ] {
+      std::fs::write(dir.path().join(file), b"content").unwrap();
+    }
+    let root = dir.path().display().to_string();
+    let glob = |pattern: &str| {
+      let objects = objects_from_glob("w1", &format!("{}/{}", root, pattern)).unwrap();
+      objects
+        .into_iter()
+        .map(|object| object.original_file_name)
+        .collect::<Vec<_>>()
+    };
+
+    // The directory named cover.png is skipped.
+    assert_eq!(
+      glob("**/*.png"),
+      vec!["a.png", "images/2024/d.png", "images/c.png"]
+    );
+    assert_eq!(glob("*.png"), vec!["a.png"]);
+    assert_eq!(glob(".*"), vec![".hidden.png"]);
+    assert_eq!(glob("[ab].*"), vec!["a.png", "b.txt"]);
+    assert_eq!(glob("images/*/?.png"), vec!["2024/d.png"]);
+    assert_eq!(glob("b.txt"), vec!["b.txt"]);
+    assert!(glob("*.gif").is_empty());
+    assert!(glob("missing/**/*.png").is_empty());
+
+    let objects = objects_from_glob("w1", &format!("{}/*.txt", root)).unwrap();
+    assert_eq!(objects[0].workspace_id, "w1");
+
+    for pattern in ["[ab.png", "a**.png"] {
+      let err = objects_from_glob("w1", &format!("{}/{}", root, pattern))
+        .err()
+        .unwrap();
+      assert_eq!(err.code, ErrorCode::InvalidParams);
+      assert!(err.msg.contains("invalid glob pattern"));
+    }
+  }
+}
@@ -0,0 +1,679 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use mime::Mime;
+use parking_lot::Mutex;
+use tracing::{trace, warn};
+
+use flowy_error::{ErrorCode, FlowyError};
+
+use crate::{
+  atomic_write, check_path_in_root, file_id_from_url, io_error, slice_object_range,
+  verify_content_hash, DeleteReport, DownloadProgressCallback, HealthStatus, ListOptions,
+  ObjectIdentity, ObjectMeta, ObjectStorageService, ObjectStream, ObjectValue, PartETag,
+  ProgressCallback, UploadId, VersionMeta,
+};
+
+const TEMP_FILE_EXT: &str = "tmp";
+
+/// Controls where and how much [DiskCachedObjectStorage] caches.
+#[derive(Debug, Clone)]
+pub struct DiskCacheConfig {
+  pub cache_dir: PathBuf,
+  /// The total size of the cached files. The least recently accessed files are evicted when the
+  /// cache grows over it.
+  pub max_total_bytes: u64,
+}
+
+impl DiskCacheConfig {
+  pub fn new(cache_dir: impl Into<PathBuf>) -> Self {
+    Self {
+      cache_dir: cache_dir.into(),
+      max_total_bytes: 512 * 1024 * 1024,
+    }
+  }
+
+  pub fn with_max_total_bytes(mut self, max_total_bytes: u64) -> Self {
+    self.max_total_bytes = max_total_bytes;
+    self
+  }
+}
+
+struct CacheIndex {
+  config: DiskCacheConfig,
+  /// The size and the last access time of every cached file, keyed by `file_id`.
+  entries: HashMap<String, (u64, SystemTime)>,
+  total_bytes: u64,
+}
+
+impl CacheIndex {
+  fn path(&self, file_id: &str) -> PathBuf {
+    self.config.cache_dir.join(file_id)
+  }
+
+  fn touch(&mut self, file_id: &str) -> Option<PathBuf> {
+    let entry = self.entries.get_mut(file_id)?;
+    entry.1 = SystemTime::now();
+    Some(self.path(file_id))
+  }
+
+  fn remove(&mut self, file_id: &str) -> Option<PathBuf> {
+    let (size, _) = self.entries.remove(file_id)?;
+    self.total_bytes -= size;
+    Some(self.path(file_id))
+  }
+
+  /// Records a file that was just written, and returns the files that must be deleted to stay
+  /// under [DiskCacheConfig::max_total_bytes].
+  fn insert(&mut self, file_id: String, size: u64) -> Vec<PathBuf> {
+    self.remove(&file_id);
+    let mut evicted = vec![];
+    while self.total_bytes + size > self.config.max_total_bytes {
+      let lru = self
+        .entries
+        .iter()
+        .min_by_key(|(_, (_, accessed_at))| *accessed_at)
+        .map(|(file_id, _)| file_id.clone());
+      match lru.and_then(|file_id| self.remove(&file_id)) {
+        Some(path) => evicted.push(path),
+        None => break,
+      }
+    }
+    self.total_bytes += size;
+    self.entries.insert(file_id, (size, SystemTime::now()));
+    evicted
+  }
+}
+
+/// An [ObjectStorageService] that writes the downloaded objects to a cache directory, so they
+/// are still available offline or after the app restarts.
+///
+/// The cached files are keyed by `file_id`, which is the content hash of the object, so two urls
+/// pointing at the same content share one file. Urls that don't end with a `file_id` are not
+/// cached. Every cached file is verified against its `file_id` before being served.
+pub struct DiskCachedObjectStorage<S: ?Sized> {
+  inner: Arc<S>,
+  index: Arc<Mutex<CacheIndex>>,
+}
+
+impl<S> DiskCachedObjectStorage<S>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  /// Creates the cache directory if needed and indexes the files left by a previous run. The
+  /// modification time of a cached file is its last access time.
+  pub fn new(inner: Arc<S>, config: DiskCacheConfig) -> Result<Self, FlowyError> {
+    std::fs::create_dir_all(&config.cache_dir).map_err(|err| io_error(err, &config.cache_dir))?;
+    let mut entries = HashMap::new();
+    let mut total_bytes = 0;
+    for entry in std::fs::read_dir(&config.cache_dir)? {
+      let entry = entry?;
+      let path = entry.path();
+      let metadata = entry.metadata()?;
+      if !metadata.is_file() {
+        continue;
+      }
+      // Files left by a write that was interrupted.
+      if path.extension().map_or(false, |ext| ext == TEMP_FILE_EXT) {
+        let _ = std::fs::remove_file(&path);
+        continue;
+      }
+      let file_id = match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => name.to_string(),
+        None => continue,
+      };
+      let accessed_at = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+      total_bytes += metadata.len();
+      entries.insert(file_id, (metadata.len(), accessed_at));
+    }
+
+    Ok(Self {
+      inner,
+      index: Arc::new(Mutex::new(CacheIndex {
+        config,
+        entries,
+        total_bytes,
+      })),
+    })
+  }
+
+  /// Returns the total size of the cached files.
+  pub fn cached_bytes(&self) -> u64 {
+    self.index.lock().total_bytes
+  }
+
+  fn remove_cached(&self, urls: &[String]) {
+    let paths = {
+      let mut index = self.index.lock();
+      urls
+        .iter()
+        .filter_map(|url| file_id_from_url(url))
+        .filter_map(|file_id| index.remove(file_id))
+        .collect::<Vec<_>>()
+    };
+    for path in paths {
+      remove_file(&path);
+    }
+  }
+}
+
+fn remove_file(path: &Path) {
+  if let Err(err) = std::fs::remove_file(path) {
+    if err.kind() != std::io::ErrorKind::NotFound {
+      warn!("remove cached file {:?} failed: {}", path, err);
+    }
+  }
+}
+
+/// A cached file is the mime type of the object followed by a newline and the decompressed
+/// content.
+fn encode_cached_file(value: &ObjectValue) -> Vec<u8> {
+  let mime = value.mime.to_string();
+  let mut data = Vec::with_capacity(mime.len() + 1 + value.raw.len());
+  data.extend_from_slice(mime.as_bytes());
+  data.push(b'\n');
+  data.extend_from_slice(&value.raw);
+  data
+}
+
+fn decode_cached_file(data: Vec<u8>, file_id: &str) -> Result<ObjectValue, FlowyError> {
+  let invalid = || {
+    FlowyError::new(
+      ErrorCode::Internal,
+      format!("invalid cached file {}", file_id),
+    )
+  };
+  let newline = data.iter().position(|b| *b == b'\n').ok_or_else(invalid)?;
+  let mime = std::str::from_utf8(&data[..newline])
+    .ok()
+    .and_then(|mime| mime.parse::<Mime>().ok())
+    .ok_or_else(invalid)?;
+  let raw = Bytes::from(data).slice(newline + 1..);
+  verify_content_hash(&raw, file_id)?;
+  Ok(ObjectValue {
+    raw,
+    mime,
+    content_encoding: None,
+  })
+}
+
+/// A cached file replaced by a symlink to a file outside of the cache directory is discarded
+/// rather than read, see [check_path_in_root].
+async fn read_cached(index: &Mutex<CacheIndex>, file_id: &str) -> Option<ObjectValue> {
+  let (cache_dir, path) = {
+    let mut index = index.lock();
+    let path = index.touch(file_id)?;
+    (index.config.cache_dir.clone(), path)
+  };
+  let result = match check_path_in_root(&cache_dir, &path).await {
+    Ok(_) => match tokio::fs::read(&path).await {
+      Ok(data) => decode_cached_file(data, file_id),
+      Err(err) => Err(err.into()),
+    },
+    Err(err) => Err(err),
+  };
+  match result {
+    Ok(value) => {
+      let file = std::fs::File::options().write(true).open(&path).ok();
+      if let Some(file) = file {
+        let _ = file.set_modified(SystemTime::now());
+      }
+      Some(value)
+    },
+    Err(err) => {
+      warn!("discard cached file {}: {}", file_id, err);
+      if let Some(path) = index.lock().remove(file_id) {
+        remove_file(&path);
+      }
+      None
+    },
+  }
+}
+
+/// Writes the value with [atomic_write], so an interrupted write never leaves a partial file in
+/// the cache.
+async fn write_cached(
+  index: &Mutex<CacheIndex>,
+  file_id: &str,
+  value: &ObjectValue,
+) -> Result<(), FlowyError> {
+  let data = encode_cached_file(value);
+  let (cache_dir, path) = {
+    let index = index.lock();
+    (index.config.cache_dir.clone(), index.path(file_id))
+  };
+  check_path_in_root(&cache_dir, &path).await?;
+  atomic_write(&path, &data).await?;
+
+  let evicted = index.lock().insert(file_id.to_string(), data.len() as u64);
+  for path in evicted {
+    trace!("evict cached file {:?}", path);
+    remove_file(&path);
+  }
+  Ok(())
+}
+
+#[async_trait]
+impl<S> ObjectStorageService for DiskCachedObjectStorage<S>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  async fn get_object_url(&self, object_id: ObjectIdentity) -> Result<String, FlowyError> {
+    self.inner.get_object_url(object_id).await
+  }
+
+  async fn put_object(&self, url: String, object_value: ObjectValue) -> Result<(), FlowyError> {
+    self.inner.put_object(url, object_value).await
+  }
+
+  async fn put_object_with_progress(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    progress: ProgressCallback,
+  ) -> Result<(), FlowyError> {
+    self
+      .inner
+      .put_object_with_progress(url, object_value, progress)
+      .await
+  }
+
+  fn supports_object_ttl(&self) -> bool {
+    self.inner.supports_object_ttl()
+  }
+
+  async fn put_object_with_ttl(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    ttl: Duration,
+  ) -> Result<(), FlowyError> {
+    self.inner.put_object_with_ttl(url, object_value, ttl).await
+  }
+
+  async fn put_object_if_absent(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+  ) -> Result<(), FlowyError> {
+    self.inner.put_object_if_absent(url, object_value).await
+  }
+
+  async fn put_object_if_match(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    etag: String,
+  ) -> Result<(), FlowyError> {
+    self
+      .inner
+      .put_object_if_match(url, object_value, etag)
+      .await
+  }
+
+  async fn delete_object(&self, url: String) -> Result<(), FlowyError> {
+    self.remove_cached(std::slice::from_ref(&url));
+    self.inner.delete_object(url).await
+  }
+
+  async fn delete_objects(
+    &self,
+    urls: Vec<String>,
+  ) -> Result<Vec<Result<(), FlowyError>>, FlowyError> {
+    self.remove_cached(&urls);
+    self.inner.delete_objects(urls).await
+  }
+
+  async fn delete_workspace_objects(&self, workspace_id: &str) -> Result<DeleteReport, FlowyError> {
+    // The cache is keyed by the file_id of the objects, so they are listed to find their entries.
+    let urls = self
+      .inner
+      .list_objects(workspace_id, None)
+      .await?
+      .into_iter()
+      .map(|object| object.url)
+      .collect::<Vec<_>>();
+    self.remove_cached(&urls);
+    self.inner.delete_workspace_objects(workspace_id).await
+  }
+
+  async fn list_objects(
+    &self,
+    workspace_id: &str,
+    prefix: Option<&str>,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    self.inner.list_objects(workspace_id, prefix).await
+  }
+
+  async fn list_objects_with_options(
+    &self,
+    workspace_id: &str,
+    options: &ListOptions,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    self
+      .inner
+      .list_objects_with_options(workspace_id, options)
+      .await
+  }
+
+  async fn get_object(&self, url: String) -> Result<ObjectValue, FlowyError> {
+    let file_id = match file_id_from_url(&url) {
+      Some(file_id) => file_id.to_string(),
+      None => return self.inner.get_object(url).await,
+    };
+    if let Some(value) = read_cached(&self.index, &file_id).await {
+      return Ok(value);
+    }
+
+    let value = self.inner.get_object(url).await?.decompress()?;
+    // Only content that matches its file_id is cached, otherwise the entry would be discarded
+    // on the next read anyway.
+    if verify_content_hash(&value.raw, &file_id).is_ok() {
+      if let Err(err) = write_cached(&self.index, &file_id, &value).await {
+        warn!("cache object {} failed: {}", file_id, err);
+      }
+    }
+    Ok(value)
+  }
+
+  async fn get_object_if_modified(
+    &self,
+    url: String,
+    etag: Option<String>,
+  ) -> Result<Option<(ObjectValue, String)>, FlowyError> {
+    self.inner.get_object_if_modified(url, etag).await
+  }
+
+  async fn get_object_stream(&self, url: String) -> Result<ObjectStream, FlowyError> {
+    let file_id = match file_id_from_url(&url) {
+      Some(file_id) => file_id.to_string(),
+      None => return self.inner.get_object_stream(url).await,
+    };
+    match read_cached(&self.index, &file_id).await {
+      Some(value) => Ok(value.into()),
+      None => self.inner.get_object_stream(url).await,
+    }
+  }
+
+  async fn get_object_with_progress(
+    &self,
+    url: String,
+    progress: DownloadProgressCallback,
+  ) -> Result<ObjectValue, FlowyError> {
+    let file_id = match file_id_from_url(&url) {
+      Some(file_id) => file_id.to_string(),
+      None => return self.inner.get_object_with_progress(url, progress).await,
+    };
+    if let Some(value) = read_cached(&self.index, &file_id).await {
+      let len = value.raw.len() as u64;
+      progress(len, Some(len));
+      return Ok(value);
+    }
+
+    let value = self
+      .inner
+      .get_object_with_progress(url, progress)
+      .await?
+      .decompress()?;
+    if verify_content_hash(&value.raw, &file_id).is_ok() {
+      if let Err(err) = write_cached(&self.index, &file_id, &value).await {
+        warn!("cache object {} failed: {}", file_id, err);
+      }
+    }
+    Ok(value)
+  }
+
+  fn supports_range_requests(&self) -> bool {
+    self.inner.supports_range_requests()
+  }
+
+  async fn get_object_range(
+    &self,
+    url: String,
+    start: u64,
+    end: Option<u64>,
+  ) -> Result<ObjectValue, FlowyError> {
+    let file_id = match file_id_from_url(&url) {
+      Some(file_id) => file_id.to_string(),
+      None => return self.inner.get_object_range(url, start, end).await,
+    };
+    match read_cached(&self.index, &file_id).await {
+      Some(value) => slice_object_range(value, start, end),
+      None => self.inner.get_object_range(url, start, end).await,
+    }
+  }
+
+  async fn head_object(&self, url: String) -> Result<ObjectMeta, FlowyError> {
+    self.inner.head_object(url).await
+  }
+
+  async fn object_exists(&self, url: String) -> Result<bool, FlowyError> {
+    self.inner.object_exists(url).await
+  }
+
+  async fn presign_get_url(&self, url: String, expires_in: Duration) -> Result<String, FlowyError> {
+    self.inner.presign_get_url(url, expires_in).await
+  }
+
+  async fn presign_put_url(&self, url: String, expires_in: Duration) -> Result<String, FlowyError> {
+    self.inner.presign_put_url(url, expires_in).await
+  }
+
+  fn supports_copy_object(&self) -> bool {
+    self.inner.supports_copy_object()
+  }
+
+  async fn copy_object(
+    &self,
+    src_url: String,
+    dst_identity: ObjectIdentity,
+  ) -> Result<String, FlowyError> {
+    self.inner.copy_object(src_url, dst_identity).await
+  }
+
+  async fn trash_object(&self, url: String) -> Result<(), FlowyError> {
+    self.remove_cached(std::slice::from_ref(&url));
+    self.inner.trash_object(url).await
+  }
+
+  async fn restore_object(&self, url: String) -> Result<(), FlowyError> {
+    self.inner.restore_object(url).await
+  }
+
+  async fn purge_trash(&self, older_than: Duration) -> Result<Vec<String>, FlowyError> {
+    self.inner.purge_trash(older_than).await
+  }
+
+  fn supports_versioning(&self) -> bool {
+    self.inner.supports_versioning()
+  }
+
+  async fn list_versions(&self, url: String) -> Result<Vec<VersionMeta>, FlowyError> {
+    self.inner.list_versions(url).await
+  }
+
+  async fn get_object_version(
+    &self,
+    url: String,
+    version_id: String,
+  ) -> Result<ObjectValue, FlowyError> {
+    self.inner.get_object_version(url, version_id).await
+  }
+
+  async fn restore_version(&self, url: String, version_id: String) -> Result<(), FlowyError> {
+    self.remove_cached(std::slice::from_ref(&url));
+    self.inner.restore_version(url, version_id).await
+  }
+
+  fn supports_multipart(&self) -> bool {
+    self.inner.supports_multipart()
+  }
+
+  async fn initiate_multipart(&self, url: String, mime: Mime) -> Result<UploadId, FlowyError> {
+    self.inner.initiate_multipart(url, mime).await
+  }
+
+  async fn upload_part(
+    &self,
+    url: String,
+    upload_id: UploadId,
+    part_number: u32,
+    bytes: Bytes,
+  ) -> Result<PartETag, FlowyError> {
+    self
+      .inner
+      .upload_part(url, upload_id, part_number, bytes)
+      .await
+  }
+
+  async fn complete_multipart(
+    &self,
+    url: String,
+    upload_id: UploadId,
+    parts: Vec<PartETag>,
+  ) -> Result<(), FlowyError> {
+    self.inner.complete_multipart(url, upload_id, parts).await
+  }
+
+  async fn abort_multipart(&self, url: String, upload_id: UploadId) -> Result<(), FlowyError> {
+    self.inner.abort_multipart(url, upload_id).await
+  }
+
+  async fn health_check(&self) -> Result<HealthStatus, FlowyError> {
+    self.inner.health_check().await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::{content_hash, InMemoryObjectStorage, StorageOperation};
+
+  use super::*;
+
+  async fn put(storage: &impl ObjectStorageService, workspace_id: &str, content: &[u8]) -> String {
+    let url = storage
+      .get_object_url(ObjectIdentity {
+        workspace_id: workspace_id.to_string(),
+        file_id: content_hash(content),
+        ext: "png".to_string(),
+        hash_algorithm: None,
+        size: None,
+      })
+      .await
+      .unwrap();
+    storage
+      .put_object(
+        url.clone(),
+        ObjectValue {
+          raw: content.to_vec().into(),
+          mime: mime::IMAGE_PNG,
+          content_encoding: None,
+        },
+      )
+      .await
+      .unwrap();
+    url
+  }
+
+  #[test]
+  fn file_id_from_url_test() {
+    assert_eq!(
+      file_id_from_url("https://host/api/file_storage/w1/blob/1234.png?v=1"),
+      Some("1234")
+    );
+    assert_eq!(file_id_from_url("https://host/blob/1234"), Some("1234"));
+    assert_eq!(file_id_from_url("https://host/blob/../x.png"), None);
+    assert_eq!(file_id_from_url("https://host/blob/"), None);
+  }
+
+  #[tokio::test]
+  async fn serve_from_disk_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let inner = Arc::new(InMemoryObjectStorage::new());
+    let storage =
+      DiskCachedObjectStorage::new(inner.clone(), DiskCacheConfig::new(dir.path())).unwrap();
+
+    let url_1 = put(&storage, "w1", b"same content").await;
+    let url_2 = put(&storage, "w2", b"same content").await;
+    let value = storage.get_object(url_1.clone()).await.unwrap();
+    assert_eq!(value.raw.as_ref(), b"same content");
+    assert_eq!(value.mime, mime::IMAGE_PNG);
+
+    // Both urls share the cached file, and the cache survives a restart.
+    let storage =
+      DiskCachedObjectStorage::new(inner.clone(), DiskCacheConfig::new(dir.path())).unwrap();
+    storage.get_object(url_1.clone()).await.unwrap();
+    storage.get_object(url_2.clone()).await.unwrap();
+    assert_eq!(inner.call_count(StorageOperation::Get), 1);
+
+    storage.delete_object(url_1.clone()).await.unwrap();
+    assert!(storage.get_object(url_1).await.is_err());
+    assert_eq!(storage.cached_bytes(), 0);
+  }
+
+  #[tokio::test]
+  async fn evict_least_recently_accessed_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let inner = Arc::new(InMemoryObjectStorage::new());
+    let config = DiskCacheConfig::new(dir.path()).with_max_total_bytes(100);
+    let storage = DiskCachedObjectStorage::new(inner.clone(), config).unwrap();
+
+    let a = put(&storage, "w", &[1; 40]).await;
+    let b = put(&storage, "w", &[2; 40]).await;
+    let c = put(&storage, "w", &[3; 40]).await;
+    storage.get_object(a.clone()).await.unwrap();
+    storage.get_object(b.clone()).await.unwrap();
+    storage.get_object(a.clone()).await.unwrap();
+    storage.get_object(c.clone()).await.unwrap();
+    assert_eq!(inner.call_count(StorageOperation::Get), 3);
+    assert!(storage.cached_bytes() <= 100);
+
+    // b was the least recently accessed, so it was evicted to make room for c.
+    storage.get_object(a).await.unwrap();
+    assert_eq!(inner.call_count(StorageOperation::Get), 3);
+    storage.get_object(b).await.unwrap();
+    assert_eq!(inner.call_count(StorageOperation::Get), 4);
+  }
+
+  #[tokio::test]
+  async fn corrupted_file_is_discarded_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let inner = Arc::new(InMemoryObjectStorage::new());
+    let storage =
+      DiskCachedObjectStorage::new(inner.clone(), DiskCacheConfig::new(dir.path())).unwrap();
+    let url = put(&storage, "w", b"content").await;
+    storage.get_object(url.clone()).await.unwrap();
+
+    let file_id = file_id_from_url(&url).unwrap();
+    std::fs::write(dir.path().join(file_id), b"image/png\ncorrupted").unwrap();
+    let value = storage.get_object(url).await.unwrap();
+    assert_eq!(value.raw.as_ref(), b"content");
+    assert_eq!(inner.call_count(StorageOperation::Get), 2);
+  }
+
+  #[cfg(unix)]
+  #[tokio::test]
+  async fn symlink_out_of_cache_is_discarded_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache_dir = dir.path().join("cache");
+    let inner = Arc::new(InMemoryObjectStorage::new());
+    let storage =
+      DiskCachedObjectStorage::new(inner.clone(), DiskCacheConfig::new(&cache_dir)).unwrap();
+    let url = put(&storage, "w", b"content").await;
+    storage.get_object(url.clone()).await.unwrap();
+
+    // The file outside of the cache is a valid cached file, only the symlink check refuses it.
+    let file_id = file_id_from_url(&url).unwrap();
+    let outside = dir.path().join("outside");
+    std::fs::rename(cache_dir.join(file_id), &outside).unwrap();
+    std::os::unix::fs::symlink(&outside, cache_dir.join(file_id)).unwrap();
+    let value = storage.get_object(url).await.unwrap();
+    assert_eq!(value.raw.as_ref(), b"content");
+    assert_eq!(inner.call_count(StorageOperation::Get), 2);
+    assert!(outside.exists());
+  }
+}
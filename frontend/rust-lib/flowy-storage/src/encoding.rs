@@ -0,0 +1,397 @@
+use std::io::Write;
+
+use bytes::Bytes;
+use futures::StreamExt;
+use parking_lot::Mutex;
+use reqwest::header::{HeaderMap, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE};
+
+use flowy_error::FlowyError;
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::ObjectStream;
+use crate::{storage_error, Compression, ObjectByteStream, ObjectValue, StorageErrorKind};
+
+/// The value of the `Accept-Encoding` header sent with the downloads, the encodings
+/// [object_from_response] can decode.
+pub const SUPPORTED_ACCEPT_ENCODING: &str = "gzip, deflate";
+
+/// Asks the backend to compress the response if it can, see [object_from_response].
+pub fn with_accept_encoding(request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+  request.header(ACCEPT_ENCODING, SUPPORTED_ACCEPT_ENCODING)
+}
+
+/// Parses the `Content-Encoding` header of the response, in the order the encodings were
+/// applied. It's empty if the header is missing or is `identity`, which is what the backends
+/// that ignore `Accept-Encoding` send.
+pub fn content_encodings(headers: &HeaderMap) -> Result<Vec<Compression>, FlowyError> {
+  let mut encodings = vec![];
+  for value in headers.get_all(CONTENT_ENCODING) {
+    let value = value
+      .to_str()
+      .map_err(|_| unsupported_encoding("is not valid"))?;
+    for encoding in value.split(',').map(str::trim) {
+      if encoding.is_empty() || encoding.eq_ignore_ascii_case("identity") {
+        continue;
+      }
+      let algo = Compression::from_content_encoding(encoding)
+        .ok_or_else(|| unsupported_encoding(&format!("{} is not supported", encoding)))?;
+      encodings.push(algo);
+    }
+  }
+  Ok(encodings)
+}
+
+/// Reads the body of a successful download, inflating it as it's received according to its
+/// `Content-Encoding` header. At most one chunk of the response and its inflated content are
+/// held in memory on top of the returned value, and the value is not compressed anymore.
+///
+/// It's only decoded once: reqwest removes the header when it inflates the body itself, and the
+/// backends that ignore `Accept-Encoding` don't send it.
+pub async fn object_from_response(response: reqwest::Response) -> Result<ObjectValue, FlowyError> {
+  let mime = response_mime(response.headers());
+  let mut decoder = ContentDecoder::new(&content_encodings(response.headers())?)?;
+  let mut raw = Vec::new();
+  let mut stream = response.bytes_stream();
+  while let Some(chunk) = stream.next().await {
+    raw.extend_from_slice(&decoder.update(&chunk?)?);
+  }
+  raw.extend_from_slice(&decoder.finish()?);
+  Ok(ObjectValue {
+    raw: raw.into(),
+    mime,
+    content_encoding: None,
+  })
+}
+
+/// The streaming counterpart of [object_from_response]. The `content_length` of the stream is
+/// unknown when the response is encoded, the length of the inflated content is only known once
+/// the stream ends.
+///
+/// The body of a response is not `Send` on wasm, so it can't be an [ObjectByteStream].
+#[cfg(not(target_arch = "wasm32"))]
+pub fn object_stream_from_response(
+  response: reqwest::Response,
+) -> Result<ObjectStream, FlowyError> {
+  let mime = response_mime(response.headers());
+  let encodings = content_encodings(response.headers())?;
+  let content_length = if encodings.is_empty() {
+    response.content_length().unwrap_or_default()
+  } else {
+    0
+  };
+  let stream = Box::pin(response.bytes_stream().map(|chunk| Ok(chunk?)));
+  Ok(ObjectStream {
+    content_length,
+    mime,
+    stream: decode_stream(stream, &encodings)?,
+  })
+}
+
+fn response_mime(headers: &HeaderMap) -> mime::Mime {
+  headers
+    .get(CONTENT_TYPE)
+    .and_then(|value| value.to_str().ok())
+    .and_then(|value| value.parse().ok())
+    .unwrap_or(mime::APPLICATION_OCTET_STREAM)
+}
+
+/// Inflates the content chunk by chunk, see [content_encodings] for the order of `encodings`.
+/// The stream is returned as is if `encodings` is empty.
+pub fn decode_stream(
+  stream: ObjectByteStream,
+  encodings: &[Compression],
+) -> Result<ObjectByteStream, FlowyError> {
+  if encodings.is_empty() {
+    return Ok(stream);
+  }
+
+  // The zstd decoder is not `Sync`, the streams must be.
+  let decoder = Mutex::new(ContentDecoder::new(encodings)?);
+  Ok(Box::pin(futures::stream::unfold(
+    (stream, Some(decoder)),
+    |(mut stream, mut decoder)| async move {
+      loop {
+        decoder.as_ref()?;
+        let decoded = match stream.next().await {
+          Some(Ok(chunk)) => decoder.as_ref()?.lock().update(&chunk),
+          Some(Err(err)) => Err(err),
+          None => decoder.take()?.into_inner().finish(),
+        };
+        match decoded {
+          // The compressed chunk didn't complete a block, wait for the next one.
+          Ok(decoded) if decoded.is_empty() && decoder.is_some() => continue,
+          Ok(decoded) if decoded.is_empty() => return None,
+          Ok(decoded) => return Some((Ok(decoded), (stream, decoder))),
+          Err(err) => return Some((Err(err), (stream, None))),
+        }
+      }
+    },
+  )))
+}
+
+enum Decoder {
+  Gzip(flate2::write::GzDecoder<Vec<u8>>),
+  Deflate(flate2::write::ZlibDecoder<Vec<u8>>),
+  #[cfg(not(target_arch = "wasm32"))]
+  Zstd(zstd::stream::write::Decoder<'static, Vec<u8>>),
+}
+
+impl Decoder {
+  fn new(algo: Compression) -> std::io::Result<Self> {
+    match algo {
+      Compression::Gzip => Ok(Decoder::Gzip(flate2::write::GzDecoder::new(Vec::new()))),
+      Compression::Deflate => Ok(Decoder::Deflate(
+        flate2::write::ZlibDecoder::new(Vec::new()),
+      )),
+      #[cfg(not(target_arch = "wasm32"))]
+      Compression::Zstd => Ok(Decoder::Zstd(
+        zstd::stream::write::Decoder::new(Vec::new())?,
+      )),
+      #[cfg(target_arch = "wasm32")]
+      Compression::Zstd => Err(crate::zstd_not_support()),
+    }
+  }
+
+  /// Feeds the compressed content and takes what was inflated so far.
+  fn update(&mut self, content: &[u8]) -> std::io::Result<Vec<u8>> {
+    match self {
+      Decoder::Gzip(decoder) => decoder.write_all(content)?,
+      Decoder::Deflate(decoder) => decoder.write_all(content)?,
+      #[cfg(not(target_arch = "wasm32"))]
+      Decoder::Zstd(decoder) => decoder.write_all(content)?,
+    }
+    Ok(self.take())
+  }
+
+  fn finish(&mut self) -> std::io::Result<Vec<u8>> {
+    match self {
+      Decoder::Gzip(decoder) => decoder.try_finish()?,
+      Decoder::Deflate(decoder) => decoder.try_finish()?,
+      #[cfg(not(target_arch = "wasm32"))]
+      Decoder::Zstd(decoder) => decoder.flush()?,
+    }
+    Ok(self.take())
+  }
+
+  fn take(&mut self) -> Vec<u8> {
+    match self {
+      Decoder::Gzip(decoder) => std::mem::take(decoder.get_mut()),
+      Decoder::Deflate(decoder) => std::mem::take(decoder.get_mut()),
+      #[cfg(not(target_arch = "wasm32"))]
+      Decoder::Zstd(decoder) => std::mem::take(decoder.get_mut()),
+    }
+  }
+}
+
+/// Undoes the encodings in the reverse order they were applied.
+struct ContentDecoder {
+  decoders: Vec<Decoder>,
+}
+
+impl ContentDecoder {
+  fn new(encodings: &[Compression]) -> Result<Self, FlowyError> {
+    let decoders = encodings
+      .iter()
+      .rev()
+      .map(|algo| Decoder::new(*algo))
+      .collect::<std::io::Result<Vec<_>>>()
+      .map_err(corrupt)?;
+    Ok(Self { decoders })
+  }
+
+  fn update(&mut self, chunk: &[u8]) -> Result<Bytes, FlowyError> {
+    let mut content = chunk.to_vec();
+    for decoder in &mut self.decoders {
+      content = decoder.update(&content).map_err(corrupt)?;
+    }
+    Ok(content.into())
+  }
+
+  /// Flushes every decoder, the content left in one is fed to the next one before it's flushed.
+  fn finish(self) -> Result<Bytes, FlowyError> {
+    let mut content = Vec::new();
+    for mut decoder in self.decoders {
+      let mut decoded = decoder.update(&content).map_err(corrupt)?;
+      decoded.extend(decoder.finish().map_err(corrupt)?);
+      content = decoded;
+    }
+    Ok(content.into())
+  }
+}
+
+fn unsupported_encoding(reason: &str) -> FlowyError {
+  storage_error(
+    StorageErrorKind::Corrupt,
+    format!("the Content-Encoding of the response {}", reason),
+  )
+}
+
+fn corrupt(err: std::io::Error) -> FlowyError {
+  storage_error(
+    StorageErrorKind::Corrupt,
+    format!("failed to inflate the response: {}", err),
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use reqwest::header::HeaderValue;
+
+  use super::*;
+  use crate::StorageErrorExt;
+
+  fn compressed(content: &[u8], algo: Compression) -> Vec<u8> {
+    ObjectValue {
+      raw: Bytes::copy_from_slice(content),
+      mime: mime::TEXT_PLAIN,
+      content_encoding: None,
+    }
+    .compress(algo)
+    .unwrap()
+    .raw
+    .to_vec()
+  }
+
+  async fn decode_in_chunks(content: Vec<u8>, encodings: &[Compression]) -> Vec<u8> {
+    let chunks = content
+      .chunks(7)
+      .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+      .collect::<Vec<_>>();
+    let stream = decode_stream(Box::pin(futures::stream::iter(chunks)), encodings).unwrap();
+    stream.map(|chunk| chunk.unwrap().to_vec()).concat().await
+  }
+
+  /// Answers the first request sent to the returned url with `headers` and `body`. The handle
+  /// returns the head of the request.
+  fn serve_once(headers: &str, body: Vec<u8>) -> (String, std::thread::JoinHandle<String>) {
+    use std::io::Read;
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/blob", listener.local_addr().unwrap());
+    let headers = headers.to_string();
+    let handle = std::thread::spawn(move || {
+      let (mut socket, _) = listener.accept().unwrap();
+      let mut request = Vec::new();
+      let mut buffer = [0; 1024];
+      while !request.ends_with(b"\r\n\r\n") {
+        let n = socket.read(&mut buffer).unwrap();
+        request.extend_from_slice(&buffer[..n]);
+      }
+      let head = format!(
+        "HTTP/1.1 200 OK\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n",
+        headers,
+        body.len()
+      );
+      socket.write_all(head.as_bytes()).unwrap();
+      socket.write_all(&body).unwrap();
+      String::from_utf8(request).unwrap().to_ascii_lowercase()
+    });
+    (url, handle)
+  }
+
+  async fn download(url: &str) -> reqwest::Response {
+    let request = with_accept_encoding(reqwest::Client::new().get(url));
+    request.send().await.unwrap()
+  }
+
+  #[tokio::test]
+  async fn object_from_response_test() {
+    let content = "# title\n\nsome text\n".repeat(200).into_bytes();
+    let (url, handle) = serve_once(
+      "Content-Type: text/markdown\r\nContent-Encoding: gzip\r\n",
+      compressed(&content, Compression::Gzip),
+    );
+    let value = object_from_response(download(&url).await).await.unwrap();
+    assert!(handle
+      .join()
+      .unwrap()
+      .contains("accept-encoding: gzip, deflate\r\n"));
+    assert_eq!(value.raw.to_vec(), content);
+    assert_eq!(value.mime, "text/markdown".parse::<mime::Mime>().unwrap());
+    assert!(value.content_encoding.is_none());
+  }
+
+  #[cfg(not(target_arch = "wasm32"))]
+  #[tokio::test]
+  async fn object_stream_from_response_test() {
+    let content = "# title\n\nsome text\n".repeat(200).into_bytes();
+    let (url, _) = serve_once(
+      "Content-Encoding: deflate\r\n",
+      compressed(&content, Compression::Deflate),
+    );
+    let object = object_stream_from_response(download(&url).await).unwrap();
+    assert_eq!(object.content_length, 0);
+    assert_eq!(object.mime, mime::APPLICATION_OCTET_STREAM);
+    let decoded = object
+      .stream
+      .map(|chunk| chunk.unwrap().to_vec())
+      .concat()
+      .await;
+    assert_eq!(decoded, content);
+
+    // The backend ignored the `Accept-Encoding` header, the length of the content is known.
+    let (url, _) = serve_once("", content.clone());
+    let object = object_stream_from_response(download(&url).await).unwrap();
+    assert_eq!(object.content_length, content.len() as u64);
+    let decoded = object
+      .stream
+      .map(|chunk| chunk.unwrap().to_vec())
+      .concat()
+      .await;
+    assert_eq!(decoded, content);
+  }
+
+  #[test]
+  fn content_encodings_test() {
+    let mut headers = HeaderMap::new();
+    assert!(content_encodings(&headers).unwrap().is_empty());
+
+    headers.insert(CONTENT_ENCODING, HeaderValue::from_static("identity"));
+    assert!(content_encodings(&headers).unwrap().is_empty());
+
+    headers.insert(CONTENT_ENCODING, HeaderValue::from_static("deflate, GZIP"));
+    assert_eq!(
+      content_encodings(&headers).unwrap(),
+      vec![Compression::Deflate, Compression::Gzip]
+    );
+
+    headers.insert(CONTENT_ENCODING, HeaderValue::from_static("br"));
+    let err = content_encodings(&headers).unwrap_err();
+    assert!(err.is_storage_kind(StorageErrorKind::Corrupt));
+  }
+
+  #[tokio::test]
+  async fn decode_stream_test() {
+    let content = "# title\n\nsome text\n".repeat(200).into_bytes();
+    for algo in [Compression::Gzip, Compression::Deflate, Compression::Zstd] {
+      let decoded = decode_in_chunks(compressed(&content, algo), &[algo]).await;
+      assert_eq!(decoded, content);
+    }
+
+    // Applied one after the other.
+    let twice = compressed(
+      &compressed(&content, Compression::Deflate),
+      Compression::Gzip,
+    );
+    let decoded = decode_in_chunks(twice, &[Compression::Deflate, Compression::Gzip]).await;
+    assert_eq!(decoded, content);
+
+    // The backend ignored the `Accept-Encoding` header, the content is left as is.
+    let decoded = decode_in_chunks(content.clone(), &[]).await;
+    assert_eq!(decoded, content);
+  }
+
+  #[tokio::test]
+  async fn decode_invalid_stream_test() {
+    let content = b"not compressed at all".to_vec();
+    let chunks = vec![Ok(Bytes::from(content))];
+    let mut stream = decode_stream(
+      Box::pin(futures::stream::iter(chunks)),
+      &[Compression::Gzip],
+    )
+    .unwrap();
+    let err = stream.next().await.unwrap().unwrap_err();
+    assert!(err.is_storage_kind(StorageErrorKind::Corrupt));
+    assert!(stream.next().await.is_none());
+  }
+}
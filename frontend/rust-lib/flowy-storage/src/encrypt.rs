@@ -0,0 +1,528 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use aes_gcm::aead::generic_array::GenericArray;
+use aes_gcm::aead::Aead;
+use aes_gcm::{Aes256Gcm, KeyInit};
+use async_trait::async_trait;
+use bytes::Bytes;
+use parking_lot::RwLock;
+use rand::Rng;
+
+use flowy_error::{ErrorCode, FlowyError};
+
+use crate::{
+  DeleteReport, HealthStatus, ListOptions, ObjectIdentity, ObjectMeta, ObjectStorageService,
+  ObjectValue, ProgressCallback, VersionMeta,
+};
+
+/// The magic bytes and the version of the encrypted blob format, see [encrypt_object_data].
+const ENCRYPTED_MAGIC: &[u8; 5] = b"AFENC";
+const ENCRYPTED_VERSION: u8 = 1;
+const HEADER_LENGTH: usize = ENCRYPTED_MAGIC.len() + 1;
+const NONCE_LENGTH: usize = 12;
+const TAG_LENGTH: usize = 16;
+
+/// A 256 bits key used to encrypt the objects of a workspace.
+#[derive(Clone, PartialEq, Eq)]
+pub struct EncryptionKey([u8; 32]);
+
+impl EncryptionKey {
+  pub fn new(key: [u8; 32]) -> Self {
+    Self(key)
+  }
+
+  /// Generates a random key.
+  pub fn generate() -> Self {
+    Self(rand::thread_rng().gen())
+  }
+
+  pub fn as_bytes(&self) -> &[u8; 32] {
+    &self.0
+  }
+}
+
+impl std::fmt::Debug for EncryptionKey {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.write_str("EncryptionKey(..)")
+  }
+}
+
+/// Provides the key used to encrypt and decrypt the object at the given url.
+pub trait EncryptionKeyProvider: Send + Sync + 'static {
+  fn encryption_key(&self, url: &str) -> Result<EncryptionKey, FlowyError>;
+}
+
+/// Holds one key per workspace. The key of an object is looked up by the workspace id found in
+/// its url, see [workspace_id_of_url].
+#[derive(Default)]
+pub struct WorkspaceEncryptionKeys {
+  keys: RwLock<HashMap<String, EncryptionKey>>,
+}
+
+impl WorkspaceEncryptionKeys {
+  pub fn set_key(&self, workspace_id: &str, key: EncryptionKey) {
+    self.keys.write().insert(workspace_id.to_string(), key);
+  }
+
+  pub fn remove_key(&self, workspace_id: &str) {
+    self.keys.write().remove(workspace_id);
+  }
+}
+
+impl EncryptionKeyProvider for WorkspaceEncryptionKeys {
+  fn encryption_key(&self, url: &str) -> Result<EncryptionKey, FlowyError> {
+    workspace_id_of_url(url)
+      .and_then(|workspace_id| self.keys.read().get(workspace_id).cloned())
+      .ok_or_else(|| {
+        FlowyError::new(
+          ErrorCode::InvalidEncryptSecret,
+          format!("no encryption key for the workspace of {}", url),
+        )
+      })
+  }
+}
+
+/// Returns the workspace of the object at the url. [ObjectStorageService::get_object_url] puts
+/// the object in the directory of its workspace: `{workspace_id}/{file_id}.{ext}`, or
+/// `file_storage/{workspace_id}/blob/{file_id}.{ext}` on AppFlowy Cloud. Only that segment is
+/// looked at, so a file or a directory named after another workspace doesn't pick its key.
+fn workspace_id_of_url(url: &str) -> Option<&str> {
+  let path = url.split(['?', '#']).next()?;
+  match path.split('/').collect::<Vec<_>>().as_slice() {
+    [.., "file_storage", workspace_id, "blob", _] | [.., workspace_id, _]
+      if !workspace_id.is_empty() =>
+    {
+      Some(workspace_id)
+    },
+    _ => None,
+  }
+}
+
+/// Encrypts `plaintext` with AES-256-GCM. The returned blob is laid out as:
+/// ```text
+/// | magic "AFENC" (5 bytes) | version (1 byte) | nonce (12 bytes) | ciphertext | tag (16 bytes) |
+/// ```
+/// The nonce is random. The magic and the version are authenticated as associated data, so
+/// everything needed to decrypt the blob, except the key, is stored in the blob.
+pub fn encrypt_object_data(plaintext: &[u8], key: &EncryptionKey) -> Result<Vec<u8>, FlowyError> {
+  let cipher = Aes256Gcm::new(GenericArray::from_slice(key.as_bytes()));
+  let nonce: [u8; NONCE_LENGTH] = rand::thread_rng().gen();
+  let mut header = ENCRYPTED_MAGIC.to_vec();
+  header.push(ENCRYPTED_VERSION);
+  let ciphertext = cipher
+    .encrypt(
+      GenericArray::from_slice(&nonce),
+      aes_gcm::aead::Payload {
+        msg: plaintext,
+        aad: &header,
+      },
+    )
+    .map_err(|_| FlowyError::internal().with_context("failed to encrypt the object"))?;
+
+  let mut data = Vec::with_capacity(HEADER_LENGTH + NONCE_LENGTH + ciphertext.len());
+  data.extend_from_slice(&header);
+  data.extend_from_slice(&nonce);
+  data.extend_from_slice(&ciphertext);
+  Ok(data)
+}
+
+/// Returns true if `data` starts with the header of an encrypted blob.
+pub fn is_encrypted_object_data(data: &[u8]) -> bool {
+  data.starts_with(ENCRYPTED_MAGIC)
+}
+
+/// Decrypts the data produced by [encrypt_object_data]. Returns an
+/// [ErrorCode::InvalidEncryptSecret] error if the key is wrong or the data was tampered with.
+pub fn decrypt_object_data(data: &[u8], key: &EncryptionKey) -> Result<Vec<u8>, FlowyError> {
+  if data.len() < HEADER_LENGTH + NONCE_LENGTH + TAG_LENGTH || !is_encrypted_object_data(data) {
+    return Err(FlowyError::new(
+      ErrorCode::InvalidEncryptSecret,
+      "the object is not encrypted or is truncated",
+    ));
+  }
+  let (header, rest) = data.split_at(HEADER_LENGTH);
+  if header[ENCRYPTED_MAGIC.len()] != ENCRYPTED_VERSION {
+    return Err(FlowyError::new(
+      ErrorCode::InvalidEncryptSecret,
+      format!(
+        "unsupported encrypted object version: {}",
+        header[ENCRYPTED_MAGIC.len()]
+      ),
+    ));
+  }
+
+  let (nonce, ciphertext) = rest.split_at(NONCE_LENGTH);
+  let cipher = Aes256Gcm::new(GenericArray::from_slice(key.as_bytes()));
+  cipher
+    .decrypt(
+      GenericArray::from_slice(nonce),
+      aes_gcm::aead::Payload {
+        msg: ciphertext,
+        aad: header,
+      },
+    )
+    .map_err(|_| {
+      FlowyError::new(
+        ErrorCode::InvalidEncryptSecret,
+        "failed to decrypt the object, the encryption key is invalid",
+      )
+    })
+}
+
+/// An [ObjectStorageService] that encrypts the objects with AES-256-GCM before they are uploaded
+/// and decrypts them after they are downloaded, see [encrypt_object_data] for the format of the
+/// stored blob.
+///
+/// The identity passed to [ObjectStorageService::get_object_url] is forwarded as is, so the
+/// `file_id` stays the hash of the plaintext and identical files are still deduplicated.
+/// The objects of a workspace without a key are returned unchanged. Once the workspace has a key,
+/// an object without the encryption header is rejected, the server could have swapped the
+/// ciphertext for a plaintext of its choice.
+///
+/// Multipart uploads and presigned urls are not supported: the whole object is encrypted at
+/// once, and the content behind a presigned url would be the ciphertext.
+pub struct EncryptingObjectStorage<S: ?Sized> {
+  inner: Arc<S>,
+  keys: Arc<dyn EncryptionKeyProvider>,
+}
+
+impl<S> EncryptingObjectStorage<S>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  pub fn new(inner: Arc<S>, keys: Arc<dyn EncryptionKeyProvider>) -> Self {
+    Self { inner, keys }
+  }
+
+  fn encrypt(&self, url: &str, value: ObjectValue) -> Result<ObjectValue, FlowyError> {
+    let key = self.keys.encryption_key(url)?;
+    let value = value.decompress()?;
+    let raw = encrypt_object_data(&value.raw, &key)?;
+    Ok(ObjectValue {
+      raw: Bytes::from(raw),
+      mime: value.mime,
+      content_encoding: None,
+    })
+  }
+}
+
+fn decrypt(
+  keys: &dyn EncryptionKeyProvider,
+  url: &str,
+  value: ObjectValue,
+) -> Result<ObjectValue, FlowyError> {
+  let value = value.decompress()?;
+  if !is_encrypted_object_data(&value.raw) {
+    if keys.encryption_key(url).is_ok() {
+      return Err(FlowyError::new(
+        ErrorCode::InvalidEncryptSecret,
+        format!("{} is not encrypted but its workspace is", url),
+      ));
+    }
+    return Ok(value);
+  }
+
+  let key = keys.encryption_key(url)?;
+  let raw = decrypt_object_data(&value.raw, &key)?;
+  Ok(ObjectValue {
+    raw: Bytes::from(raw),
+    mime: value.mime,
+    content_encoding: None,
+  })
+}
+
+fn presign_encrypted_not_support() -> FlowyError {
+  FlowyError::not_support().with_context("encrypted objects can't be accessed with presigned urls")
+}
+
+#[async_trait]
+impl<S> ObjectStorageService for EncryptingObjectStorage<S>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  async fn get_object_url(&self, object_id: ObjectIdentity) -> Result<String, FlowyError> {
+    self.inner.get_object_url(object_id).await
+  }
+
+  async fn put_object(&self, url: String, object_value: ObjectValue) -> Result<(), FlowyError> {
+    let value = self.encrypt(&url, object_value)?;
+    self.inner.put_object(url, value).await
+  }
+
+  async fn put_object_with_progress(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    progress: ProgressCallback,
+  ) -> Result<(), FlowyError> {
+    let value = self.encrypt(&url, object_value)?;
+    self
+      .inner
+      .put_object_with_progress(url, value, progress)
+      .await
+  }
+
+  fn supports_object_ttl(&self) -> bool {
+    self.inner.supports_object_ttl()
+  }
+
+  async fn put_object_with_ttl(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    ttl: Duration,
+  ) -> Result<(), FlowyError> {
+    let value = self.encrypt(&url, object_value)?;
+    self.inner.put_object_with_ttl(url, value, ttl).await
+  }
+
+  async fn put_object_if_absent(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+  ) -> Result<(), FlowyError> {
+    let value = self.encrypt(&url, object_value)?;
+    self.inner.put_object_if_absent(url, value).await
+  }
+
+  async fn put_object_if_match(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    etag: String,
+  ) -> Result<(), FlowyError> {
+    let value = self.encrypt(&url, object_value)?;
+    self.inner.put_object_if_match(url, value, etag).await
+  }
+
+  async fn delete_object(&self, url: String) -> Result<(), FlowyError> {
+    self.inner.delete_object(url).await
+  }
+
+  async fn delete_objects(
+    &self,
+    urls: Vec<String>,
+  ) -> Result<Vec<Result<(), FlowyError>>, FlowyError> {
+    self.inner.delete_objects(urls).await
+  }
+
+  async fn delete_workspace_objects(&self, workspace_id: &str) -> Result<DeleteReport, FlowyError> {
+    self.inner.delete_workspace_objects(workspace_id).await
+  }
+
+  async fn list_objects(
+    &self,
+    workspace_id: &str,
+    prefix: Option<&str>,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    self.inner.list_objects(workspace_id, prefix).await
+  }
+
+  async fn list_objects_with_options(
+    &self,
+    workspace_id: &str,
+    options: &ListOptions,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    self
+      .inner
+      .list_objects_with_options(workspace_id, options)
+      .await
+  }
+
+  async fn get_object(&self, url: String) -> Result<ObjectValue, FlowyError> {
+    decrypt(&*self.keys, &url, self.inner.get_object(url.clone()).await?)
+  }
+
+  // A server side copy would keep the content encrypted with the key of the source workspace, so
+  // the storage doesn't support it and [copy_object_or_reupload] decrypts and encrypts it again.
+  fn supports_copy_object(&self) -> bool {
+    false
+  }
+
+  // The size of an encrypted object includes the encryption overhead, so [Self::head_object]
+  // keeps the default implementation which downloads and decrypts the object. It reports the
+  // ETag of the decrypted content, so [Self::get_object_if_modified] keeps the default
+  // implementation too, the ETags of the inner service wouldn't match it.
+  async fn object_exists(&self, url: String) -> Result<bool, FlowyError> {
+    self.inner.object_exists(url).await
+  }
+
+  async fn presign_get_url(
+    &self,
+    _url: String,
+    _expires_in: Duration,
+  ) -> Result<String, FlowyError> {
+    Err(presign_encrypted_not_support())
+  }
+
+  async fn presign_put_url(
+    &self,
+    _url: String,
+    _expires_in: Duration,
+  ) -> Result<String, FlowyError> {
+    Err(presign_encrypted_not_support())
+  }
+
+  async fn trash_object(&self, url: String) -> Result<(), FlowyError> {
+    self.inner.trash_object(url).await
+  }
+
+  async fn restore_object(&self, url: String) -> Result<(), FlowyError> {
+    self.inner.restore_object(url).await
+  }
+
+  async fn purge_trash(&self, older_than: Duration) -> Result<Vec<String>, FlowyError> {
+    self.inner.purge_trash(older_than).await
+  }
+
+  fn supports_versioning(&self) -> bool {
+    self.inner.supports_versioning()
+  }
+
+  async fn list_versions(&self, url: String) -> Result<Vec<VersionMeta>, FlowyError> {
+    self.inner.list_versions(url).await
+  }
+
+  async fn get_object_version(
+    &self,
+    url: String,
+    version_id: String,
+  ) -> Result<ObjectValue, FlowyError> {
+    decrypt(
+      &*self.keys,
+      &url,
+      self
+        .inner
+        .get_object_version(url.clone(), version_id)
+        .await?,
+    )
+  }
+
+  async fn restore_version(&self, url: String, version_id: String) -> Result<(), FlowyError> {
+    self.inner.restore_version(url, version_id).await
+  }
+
+  async fn health_check(&self) -> Result<HealthStatus, FlowyError> {
+    self.inner.health_check().await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::InMemoryObjectStorage;
+
+  fn encrypting_storage(
+    workspace_id: &str,
+  ) -> (
+    Arc<InMemoryObjectStorage>,
+    Arc<WorkspaceEncryptionKeys>,
+    EncryptingObjectStorage<InMemoryObjectStorage>,
+  ) {
+    let inner = Arc::new(InMemoryObjectStorage::new());
+    let keys = Arc::new(WorkspaceEncryptionKeys::default());
+    keys.set_key(workspace_id, EncryptionKey::generate());
+    let storage = EncryptingObjectStorage::new(inner.clone(), keys.clone());
+    (inner, keys, storage)
+  }
+
+  fn identity() -> ObjectIdentity {
+    ObjectIdentity {
+      workspace_id: "w1".to_string(),
+      file_id: crate::content_hash(b"hello world"),
+      ext: "txt".to_string(),
+      hash_algorithm: None,
+      size: None,
+    }
+  }
+
+  fn value() -> ObjectValue {
+    ObjectValue {
+      raw: Bytes::from_static(b"hello world"),
+      mime: mime::TEXT_PLAIN,
+      content_encoding: None,
+    }
+  }
+
+  #[tokio::test]
+  async fn encrypt_round_trip_test() {
+    let (inner, _, storage) = encrypting_storage("w1");
+    let url = storage.get_object_url(identity()).await.unwrap();
+    storage.put_object(url.clone(), value()).await.unwrap();
+
+    let stored = inner.object(&url).unwrap();
+    assert!(is_encrypted_object_data(&stored.raw));
+    assert_ne!(stored.raw, value().raw);
+
+    let object = storage.get_object(url).await.unwrap();
+    assert_eq!(object.raw, value().raw);
+    assert_eq!(object.mime, mime::TEXT_PLAIN);
+  }
+
+  #[tokio::test]
+  async fn wrong_key_fails_test() {
+    let (_, keys, storage) = encrypting_storage("w1");
+    let url = storage.get_object_url(identity()).await.unwrap();
+    storage.put_object(url.clone(), value()).await.unwrap();
+
+    keys.set_key("w1", EncryptionKey::generate());
+    let err = storage.get_object(url).await.err().unwrap();
+    assert_eq!(err.code, ErrorCode::InvalidEncryptSecret);
+  }
+
+  #[tokio::test]
+  async fn plaintext_object_test() {
+    let (inner, _, storage) = encrypting_storage("w1");
+    // The workspace has a key, the plaintext may have been swapped in by the server.
+    let url = storage.get_object_url(identity()).await.unwrap();
+    inner.put_object(url.clone(), value()).await.unwrap();
+    let err = storage.get_object(url).await.err().unwrap();
+    assert_eq!(err.code, ErrorCode::InvalidEncryptSecret);
+
+    // The workspace is not encrypted.
+    let url = storage
+      .get_object_url(ObjectIdentity {
+        workspace_id: "w2".to_string(),
+        ..identity()
+      })
+      .await
+      .unwrap();
+    inner.put_object(url.clone(), value()).await.unwrap();
+    let object = storage.get_object(url).await.unwrap();
+    assert_eq!(object.raw, value().raw);
+  }
+
+  #[test]
+  fn key_of_workspace_segment_test() {
+    let keys = WorkspaceEncryptionKeys::default();
+    let (w1, w2) = (EncryptionKey::generate(), EncryptionKey::generate());
+    keys.set_key("w1", w1.clone());
+    keys.set_key("w2", w2.clone());
+    for (url, key) in [
+      ("https://storage.appflowy.io/w2/1.txt", &w2),
+      ("https://host/w1/w2/1.txt?v=w1", &w2),
+      ("https://host/api/file_storage/w1/blob/w2", &w1),
+    ] {
+      assert_eq!(&keys.encryption_key(url).unwrap(), key, "{}", url);
+    }
+    // The workspace of the url has no key, the other segments don't count.
+    assert!(keys.encryption_key("https://host/w1/w3/w2").is_err());
+    assert!(keys.encryption_key("w1").is_err());
+  }
+
+  #[test]
+  fn tampered_data_fails_test() {
+    let key = EncryptionKey::generate();
+    let mut data = encrypt_object_data(b"hello world", &key).unwrap();
+    assert_eq!(decrypt_object_data(&data, &key).unwrap(), b"hello world");
+
+    let last = data.len() - 1;
+    data[last] ^= 1;
+    let err = decrypt_object_data(&data, &key).unwrap_err();
+    assert_eq!(err.code, ErrorCode::InvalidEncryptSecret);
+    let err = decrypt_object_data(&data[..10], &key).unwrap_err();
+    assert_eq!(err.code, ErrorCode::InvalidEncryptSecret);
+  }
+}
@@ -0,0 +1,221 @@
+use std::path::Path;
+
+use flowy_error::{ErrorCode, FlowyError};
+
+/// The class of a failed storage operation. Storage errors are [FlowyError]s whose code tells the
+/// kind, see [StorageErrorKind::of], so they go through the `?` operator and the FFI like any
+/// other error while the callers can still tell a missing object from a network failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StorageErrorKind {
+  /// The object doesn't exist, or it expired.
+  NotFound,
+  /// The user is not signed in or is not allowed to access the object.
+  Unauthorized,
+  /// The upload goes over the storage limit or the maximum file size of the user.
+  QuotaExceeded,
+  /// The backend couldn't be reached, or it failed to handle the request.
+  Network,
+  /// The operation didn't complete within its deadline.
+  Timeout,
+  /// The content doesn't match its hash or can't be decoded, usually a truncated response.
+  Corrupt,
+  Cancelled,
+  /// The backend doesn't implement the operation.
+  BackendUnsupported,
+  /// The backend failed too many times in a row, the operation was not even attempted. See
+  /// [crate::CircuitBreakerObjectStorage].
+  Unavailable,
+  /// The object changed since the caller read it, or it exists while it shouldn't, so a
+  /// conditional write was not done. See [crate::ObjectStorageService::put_object_if_match]. The
+  /// caller should fetch the object again before retrying.
+  PreconditionFailed,
+  /// The app is not allowed to read or write a local file, the user should grant it access to
+  /// the folder.
+  PermissionDenied,
+  /// A local file can't be written because the disk is full, the user should free up space.
+  DiskFull,
+  /// A url or a key resolves, through `..` or a symlink, to a local path outside of the
+  /// directory of the storage. It's refused rather than read or written.
+  PathTraversal,
+}
+
+impl StorageErrorKind {
+  /// Classifies an error, `None` if it's not a storage error, for example invalid parameters.
+  pub fn of(error: &FlowyError) -> Option<Self> {
+    let kind = match error.code {
+      ErrorCode::RecordNotFound => Self::NotFound,
+      ErrorCode::UserUnauthorized | ErrorCode::NotEnoughPermissions => Self::Unauthorized,
+      ErrorCode::ExcessStorageLimited | ErrorCode::FileTooLarge => Self::QuotaExceeded,
+      ErrorCode::HttpError
+      | ErrorCode::ConnectTimeout
+      | ErrorCode::ConnectClose
+      | ErrorCode::ConnectRefused
+      | ErrorCode::InternalServerError => Self::Network,
+      ErrorCode::Timeout => Self::Timeout,
+      ErrorCode::ContentHashMismatch => Self::Corrupt,
+      ErrorCode::Cancelled | ErrorCode::ConnectCancel => Self::Cancelled,
+      ErrorCode::NotSupportYet => Self::BackendUnsupported,
+      ErrorCode::ServiceUnavailable => Self::Unavailable,
+      ErrorCode::PreconditionFailed => Self::PreconditionFailed,
+      ErrorCode::PermissionDenied => Self::PermissionDenied,
+      ErrorCode::DiskFull => Self::DiskFull,
+      ErrorCode::PathTraversal => Self::PathTraversal,
+      _ => return None,
+    };
+    Some(kind)
+  }
+
+  /// The code of the errors created by [storage_error] for this kind.
+  pub fn error_code(self) -> ErrorCode {
+    match self {
+      Self::NotFound => ErrorCode::RecordNotFound,
+      Self::Unauthorized => ErrorCode::UserUnauthorized,
+      Self::QuotaExceeded => ErrorCode::ExcessStorageLimited,
+      Self::Network => ErrorCode::HttpError,
+      Self::Timeout => ErrorCode::Timeout,
+      Self::Corrupt => ErrorCode::ContentHashMismatch,
+      Self::Cancelled => ErrorCode::Cancelled,
+      Self::BackendUnsupported => ErrorCode::NotSupportYet,
+      Self::Unavailable => ErrorCode::ServiceUnavailable,
+      Self::PreconditionFailed => ErrorCode::PreconditionFailed,
+      Self::PermissionDenied => ErrorCode::PermissionDenied,
+      Self::DiskFull => ErrorCode::DiskFull,
+      Self::PathTraversal => ErrorCode::PathTraversal,
+    }
+  }
+
+  /// Returns true if the operation might succeed when it's tried again. An [Self::Unavailable]
+  /// operation is not, it would only hit the open circuit again.
+  pub fn is_transient(self) -> bool {
+    matches!(self, Self::Network | Self::Timeout | Self::Corrupt)
+  }
+}
+
+impl From<StorageErrorKind> for FlowyError {
+  fn from(kind: StorageErrorKind) -> Self {
+    FlowyError::from(kind.error_code())
+  }
+}
+
+/// Creates an error of the given kind.
+pub fn storage_error<T: ToString>(kind: StorageErrorKind, msg: T) -> FlowyError {
+  FlowyError::new(kind.error_code(), msg)
+}
+
+/// Converts the error of an operation on a local file, keeping the path in the message. Missing
+/// files, denied permissions and full disks get their own [StorageErrorKind], so the UI can tell
+/// the user what to do, the other errors are internal errors.
+pub fn io_error(err: std::io::Error, path: &Path) -> FlowyError {
+  let path = path.display();
+  if is_disk_full(&err) {
+    return storage_error(
+      StorageErrorKind::DiskFull,
+      format!("not enough space to write {}: {}", path, err),
+    );
+  }
+  match err.kind() {
+    std::io::ErrorKind::NotFound => storage_error(
+      StorageErrorKind::NotFound,
+      format!("{} doesn't exist", path),
+    ),
+    std::io::ErrorKind::PermissionDenied => storage_error(
+      StorageErrorKind::PermissionDenied,
+      format!("permission denied: {}", path),
+    ),
+    _ => FlowyError::new(ErrorCode::Internal, format!("{}: {}", path, err)),
+  }
+}
+
+/// `std::io::ErrorKind::StorageFull` is not stable yet, so the OS error codes are checked.
+fn is_disk_full(err: &std::io::Error) -> bool {
+  // ENOSPC, and EDQUOT when the disk quota of the user is exceeded.
+  #[cfg(any(target_os = "linux", target_os = "android"))]
+  const DISK_FULL_CODES: &[i32] = &[28, 122];
+  #[cfg(any(target_os = "macos", target_os = "ios"))]
+  const DISK_FULL_CODES: &[i32] = &[28, 69];
+  // ERROR_HANDLE_DISK_FULL and ERROR_DISK_FULL.
+  #[cfg(windows)]
+  const DISK_FULL_CODES: &[i32] = &[39, 112];
+  #[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    windows
+  )))]
+  const DISK_FULL_CODES: &[i32] = &[];
+  err
+    .raw_os_error()
+    .map_or(false, |code| DISK_FULL_CODES.contains(&code))
+}
+
+/// Classifies the [FlowyError]s returned by the storage operations, see [StorageErrorKind::of].
+pub trait StorageErrorExt {
+  fn storage_kind(&self) -> Option<StorageErrorKind>;
+
+  fn is_storage_kind(&self, kind: StorageErrorKind) -> bool {
+    self.storage_kind() == Some(kind)
+  }
+}
+
+impl StorageErrorExt for FlowyError {
+  fn storage_kind(&self) -> Option<StorageErrorKind> {
+    StorageErrorKind::of(self)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn storage_error_kind_test() {
+    let kinds = [
+      StorageErrorKind::NotFound,
+      StorageErrorKind::Unauthorized,
+      StorageErrorKind::QuotaExceeded,
+      StorageErrorKind::Network,
+      StorageErrorKind::Timeout,
+      StorageErrorKind::Corrupt,
+      StorageErrorKind::Cancelled,
+      StorageErrorKind::BackendUnsupported,
+      StorageErrorKind::Unavailable,
+      StorageErrorKind::PreconditionFailed,
+      StorageErrorKind::PermissionDenied,
+      StorageErrorKind::DiskFull,
+      StorageErrorKind::PathTraversal,
+    ];
+    for kind in kinds {
+      let err = storage_error(kind, "failed");
+      assert_eq!(err.storage_kind(), Some(kind));
+      assert_eq!(FlowyError::from(kind).storage_kind(), Some(kind));
+    }
+
+    assert!(FlowyError::record_not_found().is_storage_kind(StorageErrorKind::NotFound));
+    assert_eq!(
+      FlowyError::new(ErrorCode::ConnectRefused, "refused").storage_kind(),
+      Some(StorageErrorKind::Network)
+    );
+    assert_eq!(FlowyError::invalid_data().storage_kind(), None);
+  }
+
+  #[test]
+  fn io_error_test() {
+    let path = Path::new("/data/a.txt");
+    let err = io_error(std::io::ErrorKind::NotFound.into(), path);
+    assert!(err.is_storage_kind(StorageErrorKind::NotFound));
+    assert!(err.msg.contains("/data/a.txt"));
+    let err = io_error(std::io::ErrorKind::PermissionDenied.into(), path);
+    assert!(err.is_storage_kind(StorageErrorKind::PermissionDenied));
+    assert!(err.msg.contains("/data/a.txt"));
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    {
+      let err = io_error(std::io::Error::from_raw_os_error(28), path);
+      assert!(err.is_storage_kind(StorageErrorKind::DiskFull));
+      assert!(err.msg.contains("/data/a.txt"));
+    }
+    let err = io_error(std::io::ErrorKind::InvalidData.into(), path);
+    assert_eq!(err.code, ErrorCode::Internal);
+    assert!(err.msg.contains("/data/a.txt"));
+  }
+}
@@ -0,0 +1,189 @@
+use flowy_error::FlowyError;
+
+use crate::{FileStoragePlan, StorageObject};
+
+/// An object of the batch that the plan would reject, see [estimate_upload].
+#[derive(Debug, Clone)]
+pub struct RejectedUpload {
+  pub file_name: String,
+  /// The size of the object, `None` if it can't be known without reading it.
+  pub size: Option<u64>,
+  pub error: FlowyError,
+}
+
+/// What a batch upload would do, as returned by [estimate_upload].
+#[derive(Debug, Clone, Default)]
+pub struct UploadEstimate {
+  /// The number of objects that would be uploaded.
+  pub files: usize,
+  /// The bytes of the objects that would be uploaded.
+  pub total_bytes: u64,
+  /// The objects the plan would reject, in the order of the batch. They are not counted in
+  /// [Self::files] and [Self::total_bytes].
+  pub rejected: Vec<RejectedUpload>,
+  /// The bytes the user can still store before the batch is uploaded, `None` if the plan has no
+  /// known limit, see [FileStoragePlan::storage_limit].
+  pub headroom: Option<u64>,
+}
+
+impl UploadEstimate {
+  /// Returns true if the objects that would be uploaded fit in the remaining quota.
+  pub fn fits_quota(&self) -> bool {
+    self
+      .headroom
+      .map_or(true, |headroom| self.total_bytes <= headroom)
+  }
+}
+
+/// Tells how many objects and bytes uploading the batch would transfer and which objects the
+/// plan would reject, so the user can confirm the upload. Each object is checked like
+/// [crate::PlanEnforcingObjectStorage] checks an upload: against the allowed types, the limit of
+/// its mime type, then with [FileStoragePlan::check_upload_object]. Nothing is uploaded and the content of the
+/// objects is not read.
+///
+/// An object whose size can't be known without reading it, like a stream of unknown length, is
+/// reported as rejected. The call only fails if the limits of the plan can't be fetched.
+pub async fn estimate_upload<P>(
+  objects: &[StorageObject],
+  plan: &P,
+) -> Result<UploadEstimate, FlowyError>
+where
+  P: FileStoragePlan + ?Sized,
+{
+  let maximum_file_size = plan.maximum_file_size().await?;
+  let mime_size_limits = plan.mime_size_limits();
+  let type_policy = plan.upload_type_policy();
+  let mut estimate = UploadEstimate::default();
+  for object in objects {
+    let size = match object.file_size_async().await {
+      Ok(size) => size,
+      Err(error) => {
+        estimate.rejected.push(RejectedUpload {
+          file_name: object.file_name.clone(),
+          size: None,
+          error,
+        });
+        continue;
+      },
+    };
+    let checked = match type_policy.check(object) {
+      Ok(_) => mime_size_limits.check(object, maximum_file_size).await,
+      Err(err) => Err(err),
+    };
+    let checked = match checked {
+      Ok(_) => plan.check_upload_object(object).await,
+      Err(err) => Err(err),
+    };
+    match checked {
+      Ok(_) => {
+        estimate.files += 1;
+        estimate.total_bytes += size;
+      },
+      Err(error) => estimate.rejected.push(RejectedUpload {
+        file_name: object.file_name.clone(),
+        size: Some(size),
+        error,
+      }),
+    }
+  }
+
+  if let Some(limit) = plan.storage_limit().await? {
+    let storage_size = plan.storage_size().await?;
+    estimate.headroom = Some(limit.saturating_sub(storage_size));
+  }
+  Ok(estimate)
+}
+
+#[cfg(test)]
+mod tests {
+  use async_trait::async_trait;
+  use flowy_error::ErrorCode;
+
+  use super::*;
+  use crate::MimeSizeLimits;
+
+  struct TestPlan;
+
+  #[async_trait]
+  impl FileStoragePlan for TestPlan {
+    async fn storage_size(&self) -> Result<u64, FlowyError> {
+      Ok(90)
+    }
+
+    async fn storage_limit(&self) -> Result<Option<u64>, FlowyError> {
+      Ok(Some(100))
+    }
+
+    async fn maximum_file_size(&self) -> Result<u64, FlowyError> {
+      Ok(8)
+    }
+
+    fn mime_size_limits(&self) -> MimeSizeLimits {
+      MimeSizeLimits::new().with_rule("image/", 3)
+    }
+
+    async fn check_upload_object(&self, object: &StorageObject) -> Result<(), FlowyError> {
+      if object.workspace_id == "blocked" {
+        Err(FlowyError::new(ErrorCode::ExcessStorageLimited, "quota"))
+      } else {
+        Ok(())
+      }
+    }
+  }
+
+  fn object(workspace_id: &str, file_name: &str, mime: &str, size: usize) -> StorageObject {
+    StorageObject::from_bytes(workspace_id, file_name, vec![0; size], mime.to_string())
+  }
+
+  #[tokio::test]
+  async fn estimate_upload_test() {
+    let (reader, _writer) = tokio::io::duplex(8);
+    let objects = vec![
+      object("w1", "a.txt", "text/plain", 5),
+      object("w1", "b.txt", "text/plain", 4),
+      object("w1", "big.txt", "text/plain", 9),
+      object("w1", "c.png", "image/png", 4),
+      object("blocked", "d.txt", "text/plain", 1),
+      StorageObject::from_reader(
+        "w1",
+        "e.bin",
+        reader,
+        None,
+        "application/octet-stream".into(),
+      ),
+    ];
+    let estimate = estimate_upload(&objects, &TestPlan).await.unwrap();
+    assert_eq!((estimate.files, estimate.total_bytes), (2, 9));
+    assert_eq!(estimate.headroom, Some(10));
+    assert!(estimate.fits_quota());
+
+    let rejected = estimate
+      .rejected
+      .iter()
+      .map(|rejected| {
+        (
+          rejected.file_name.as_str(),
+          rejected.size,
+          rejected.error.code.clone(),
+        )
+      })
+      .collect::<Vec<_>>();
+    assert_eq!(
+      rejected,
+      vec![
+        ("big.txt", Some(9), ErrorCode::FileTooLarge),
+        ("c.png", Some(4), ErrorCode::FileTooLarge),
+        ("d.txt", Some(1), ErrorCode::ExcessStorageLimited),
+        ("e.bin", None, ErrorCode::InvalidParams),
+      ]
+    );
+
+    let objects = vec![
+      object("w1", "a.txt", "text/plain", 6),
+      object("w1", "b.txt", "text/plain", 6),
+    ];
+    let estimate = estimate_upload(&objects, &TestPlan).await.unwrap();
+    assert_eq!(estimate.total_bytes, 12);
+    assert!(!estimate.fits_quota());
+  }
+}
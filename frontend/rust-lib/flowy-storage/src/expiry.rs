@@ -0,0 +1,577 @@
+use std::collections::HashMap;
+use std::future::Future;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use mime::Mime;
+use parking_lot::Mutex;
+use tracing::{error, info, warn};
+
+use flowy_error::FlowyError;
+
+use crate::conditional::precondition_failed;
+use crate::{
+  workspace_id_from_url, CancellationToken, DeleteReport, HealthStatus, ListOptions,
+  ObjectIdentity, ObjectMeta, ObjectStorageService, ObjectStream, ObjectValue, PartETag,
+  ProgressCallback, UploadId,
+};
+
+/// How often the task spawned by [ExpiringObjectStorage::start_reaper] deletes the expired
+/// objects, unless another interval is given.
+pub const DEFAULT_REAPER_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// An [ObjectStorageService] that implements [ObjectStorageService::put_object_with_ttl] for the
+/// services that can't expire objects. The expiries are tracked in memory and the expired
+/// objects are deleted by [Self::reap], which runs periodically once [Self::start_reaper] is
+/// called. The expiries are lost when the app is closed, so the objects that expire after that
+/// are kept until they are deleted.
+///
+/// If the inner service expires objects itself, see [ObjectStorageService::supports_object_ttl],
+/// the uploads are forwarded to it and the reaper leaves the objects to the storage. In both
+/// cases an object is reported as not found as soon as it expires, even if it's not deleted yet.
+///
+/// Wrap the caches, like [crate::CachingObjectStorage], with it instead of the other way
+/// around, otherwise the cached content of an expired object is served until it's evicted.
+pub struct ExpiringObjectStorage<S: ?Sized> {
+  inner: Arc<S>,
+  expiries: Arc<Mutex<HashMap<String, Expiry>>>,
+}
+
+#[derive(Clone, Copy)]
+struct Expiry {
+  expires_at: SystemTime,
+  /// The inner service deletes the object itself.
+  native: bool,
+}
+
+impl Expiry {
+  fn is_expired(&self, now: SystemTime) -> bool {
+    self.expires_at <= now
+  }
+}
+
+impl<S> ExpiringObjectStorage<S>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  pub fn new(inner: Arc<S>) -> Self {
+    Self {
+      inner,
+      expiries: Default::default(),
+    }
+  }
+
+  /// Returns when the object uploaded with [ObjectStorageService::put_object_with_ttl] expires,
+  /// `None` if it has no expiry or was uploaded before the app started.
+  pub fn expires_at(&self, url: &str) -> Option<SystemTime> {
+    self
+      .expiries
+      .lock()
+      .get(url)
+      .map(|expiry| expiry.expires_at)
+  }
+
+  /// Deletes the expired objects that the inner service doesn't delete itself. The objects that
+  /// can't be deleted are retried on the next pass.
+  ///
+  /// # Returns
+  /// - `Ok(Vec<String>)`: The urls of the deleted objects.
+  /// - `Err(Error)`: The deletion failed, all the expired objects are retried on the next pass.
+  pub async fn reap(&self) -> Result<Vec<String>, FlowyError> {
+    let now = SystemTime::now();
+    let expired = {
+      let mut expiries = self.expiries.lock();
+      // The storage deletes the expired objects it tracks itself, they can be forgotten.
+      expiries.retain(|_, expiry| !expiry.native || !expiry.is_expired(now));
+      expiries
+        .iter()
+        .filter(|(_, expiry)| expiry.is_expired(now))
+        .map(|(url, _)| url.clone())
+        .collect::<Vec<_>>()
+    };
+    if expired.is_empty() {
+      return Ok(vec![]);
+    }
+    let results = self.inner.delete_objects(expired.clone()).await?;
+    let mut deleted = vec![];
+    let mut expiries = self.expiries.lock();
+    for (url, result) in expired.into_iter().zip(results) {
+      match result {
+        Ok(()) => {},
+        Err(err) if err.is_record_not_found() => {},
+        Err(err) => {
+          warn!("failed to delete the expired object {}: {}", url, err);
+          continue;
+        },
+      }
+      expiries.remove(&url);
+      deleted.push(url);
+    }
+    info!("deleted {} expired objects", deleted.len());
+    Ok(deleted)
+  }
+
+  /// Spawns the task that calls [Self::reap] every `interval`. The task stops when the storage
+  /// is dropped.
+  pub fn start_reaper(self: &Arc<Self>, interval: Duration) {
+    let storage = Arc::downgrade(self);
+    tokio::spawn(async move {
+      loop {
+        tokio::time::sleep(interval).await;
+        match Weak::upgrade(&storage) {
+          None => break,
+          Some(storage) => {
+            if let Err(err) = storage.reap().await {
+              error!("failed to delete the expired objects: {}", err);
+            }
+          },
+        }
+      }
+    });
+  }
+
+  fn is_expired(&self, url: &str) -> bool {
+    self
+      .expiries
+      .lock()
+      .get(url)
+      .map(|expiry| expiry.is_expired(SystemTime::now()))
+      .unwrap_or(false)
+  }
+
+  /// Returns a not found error if the object expired, so the inner service isn't asked for it.
+  fn ensure_not_expired(&self, url: &str) -> Result<(), FlowyError> {
+    if self.is_expired(url) {
+      return Err(expired_error(url));
+    }
+    Ok(())
+  }
+
+  /// Runs `fut`, then forgets the expiry of the url if it succeeded, because the object was
+  /// replaced or deleted.
+  async fn forgetting(
+    &self,
+    url: String,
+    fut: impl Future<Output = Result<(), FlowyError>>,
+  ) -> Result<(), FlowyError> {
+    fut.await?;
+    self.expiries.lock().remove(&url);
+    Ok(())
+  }
+
+  /// Drops the expired objects from a listing and fills in the expiry of the others.
+  async fn listing(
+    &self,
+    fut: impl Future<Output = Result<Vec<ObjectMeta>, FlowyError>>,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    let objects = fut.await?;
+    let now = SystemTime::now();
+    let expiries = self.expiries.lock();
+    Ok(
+      objects
+        .into_iter()
+        .filter_map(|mut object| match expiries.get(&object.url) {
+          Some(expiry) if expiry.is_expired(now) => None,
+          Some(expiry) => {
+            object.expires_at = Some(expiry.expires_at);
+            Some(object)
+          },
+          None => Some(object),
+        })
+        .collect(),
+    )
+  }
+}
+
+fn expired_error(url: &str) -> FlowyError {
+  FlowyError::record_not_found().with_context(format!("{} expired", url))
+}
+
+#[async_trait]
+impl<S> ObjectStorageService for ExpiringObjectStorage<S>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  async fn get_object_url(&self, object_id: ObjectIdentity) -> Result<String, FlowyError> {
+    self.inner.get_object_url(object_id).await
+  }
+
+  async fn put_object(&self, url: String, object_value: ObjectValue) -> Result<(), FlowyError> {
+    let fut = self.inner.put_object(url.clone(), object_value);
+    self.forgetting(url, fut).await
+  }
+
+  async fn put_object_with_progress(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    progress: ProgressCallback,
+  ) -> Result<(), FlowyError> {
+    let fut = self
+      .inner
+      .put_object_with_progress(url.clone(), object_value, progress);
+    self.forgetting(url, fut).await
+  }
+
+  async fn put_object_cancellable(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    cancel: CancellationToken,
+  ) -> Result<(), FlowyError> {
+    let fut = self
+      .inner
+      .put_object_cancellable(url.clone(), object_value, cancel);
+    self.forgetting(url, fut).await
+  }
+
+  fn supports_object_ttl(&self) -> bool {
+    true
+  }
+
+  async fn put_object_with_ttl(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    ttl: Duration,
+  ) -> Result<(), FlowyError> {
+    let native = self.inner.supports_object_ttl();
+    if native {
+      self
+        .inner
+        .put_object_with_ttl(url.clone(), object_value, ttl)
+        .await?;
+    } else {
+      self.inner.put_object(url.clone(), object_value).await?;
+    }
+    let expiry = Expiry {
+      expires_at: SystemTime::now() + ttl,
+      native,
+    };
+    self.expiries.lock().insert(url, expiry);
+    Ok(())
+  }
+
+  async fn put_object_if_absent(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+  ) -> Result<(), FlowyError> {
+    // An expired object is already gone for the callers, even if it's not reaped yet.
+    if self.is_expired(&url) {
+      let fut = self.inner.put_object(url.clone(), object_value);
+      return self.forgetting(url, fut).await;
+    }
+    let fut = self.inner.put_object_if_absent(url.clone(), object_value);
+    self.forgetting(url, fut).await
+  }
+
+  async fn put_object_if_match(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    etag: String,
+  ) -> Result<(), FlowyError> {
+    if self.is_expired(&url) {
+      return Err(precondition_failed(&url, "it expired"));
+    }
+    let fut = self
+      .inner
+      .put_object_if_match(url.clone(), object_value, etag);
+    self.forgetting(url, fut).await
+  }
+
+  async fn delete_object(&self, url: String) -> Result<(), FlowyError> {
+    let fut = self.inner.delete_object(url.clone());
+    self.forgetting(url, fut).await
+  }
+
+  async fn delete_objects(
+    &self,
+    urls: Vec<String>,
+  ) -> Result<Vec<Result<(), FlowyError>>, FlowyError> {
+    let results = self.inner.delete_objects(urls.clone()).await?;
+    let mut expiries = self.expiries.lock();
+    for (url, result) in urls.iter().zip(&results) {
+      if result.is_ok() {
+        expiries.remove(url);
+      }
+    }
+    Ok(results)
+  }
+
+  async fn delete_workspace_objects(&self, workspace_id: &str) -> Result<DeleteReport, FlowyError> {
+    let report = self.inner.delete_workspace_objects(workspace_id).await?;
+    self.expiries.lock().retain(|url, _| {
+      workspace_id_from_url(url) != Some(workspace_id)
+        || report.failed.iter().any(|(failed, _)| failed == url)
+    });
+    Ok(report)
+  }
+
+  async fn list_objects(
+    &self,
+    workspace_id: &str,
+    prefix: Option<&str>,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    self
+      .listing(self.inner.list_objects(workspace_id, prefix))
+      .await
+  }
+
+  async fn list_objects_with_options(
+    &self,
+    workspace_id: &str,
+    options: &ListOptions,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    self
+      .listing(self.inner.list_objects_with_options(workspace_id, options))
+      .await
+  }
+
+  async fn get_object(&self, url: String) -> Result<ObjectValue, FlowyError> {
+    self.ensure_not_expired(&url)?;
+    self.inner.get_object(url).await
+  }
+
+  async fn get_object_if_modified(
+    &self,
+    url: String,
+    etag: Option<String>,
+  ) -> Result<Option<(ObjectValue, String)>, FlowyError> {
+    self.ensure_not_expired(&url)?;
+    self.inner.get_object_if_modified(url, etag).await
+  }
+
+  async fn get_object_stream(&self, url: String) -> Result<ObjectStream, FlowyError> {
+    self.ensure_not_expired(&url)?;
+    self.inner.get_object_stream(url).await
+  }
+
+  #[cfg(not(target_arch = "wasm32"))]
+  async fn download_to_file(&self, url: String, dest: &Path) -> Result<u64, FlowyError> {
+    self.ensure_not_expired(&url)?;
+    self.inner.download_to_file(url, dest).await
+  }
+
+  async fn get_object_cancellable(
+    &self,
+    url: String,
+    cancel: CancellationToken,
+  ) -> Result<ObjectValue, FlowyError> {
+    self.ensure_not_expired(&url)?;
+    self.inner.get_object_cancellable(url, cancel).await
+  }
+
+  async fn get_object_verified(
+    &self,
+    url: String,
+    expected_file_id: &str,
+  ) -> Result<ObjectValue, FlowyError> {
+    self.ensure_not_expired(&url)?;
+    self.inner.get_object_verified(url, expected_file_id).await
+  }
+
+  fn supports_range_requests(&self) -> bool {
+    self.inner.supports_range_requests()
+  }
+
+  async fn get_object_range(
+    &self,
+    url: String,
+    start: u64,
+    end: Option<u64>,
+  ) -> Result<ObjectValue, FlowyError> {
+    self.ensure_not_expired(&url)?;
+    self.inner.get_object_range(url, start, end).await
+  }
+
+  async fn head_object(&self, url: String) -> Result<ObjectMeta, FlowyError> {
+    let expires_at = self.expires_at(&url);
+    self.ensure_not_expired(&url)?;
+    let mut meta = self.inner.head_object(url).await?;
+    if expires_at.is_some() {
+      meta.expires_at = expires_at;
+    }
+    Ok(meta)
+  }
+
+  async fn object_exists(&self, url: String) -> Result<bool, FlowyError> {
+    if self.is_expired(&url) {
+      return Ok(false);
+    }
+    self.inner.object_exists(url).await
+  }
+
+  async fn presign_get_url(&self, url: String, expires_in: Duration) -> Result<String, FlowyError> {
+    self.ensure_not_expired(&url)?;
+    self.inner.presign_get_url(url, expires_in).await
+  }
+
+  async fn presign_put_url(&self, url: String, expires_in: Duration) -> Result<String, FlowyError> {
+    self.inner.presign_put_url(url, expires_in).await
+  }
+
+  fn supports_copy_object(&self) -> bool {
+    self.inner.supports_copy_object()
+  }
+
+  /// The copy doesn't expire, like any object uploaded without a TTL.
+  async fn copy_object(
+    &self,
+    src_url: String,
+    dst_identity: ObjectIdentity,
+  ) -> Result<String, FlowyError> {
+    self.ensure_not_expired(&src_url)?;
+    let dst_url = self.inner.copy_object(src_url, dst_identity).await?;
+    self.expiries.lock().remove(&dst_url);
+    Ok(dst_url)
+  }
+
+  async fn trash_object(&self, url: String) -> Result<(), FlowyError> {
+    self.ensure_not_expired(&url)?;
+    self.inner.trash_object(url).await
+  }
+
+  async fn restore_object(&self, url: String) -> Result<(), FlowyError> {
+    self.inner.restore_object(url).await
+  }
+
+  async fn purge_trash(&self, older_than: Duration) -> Result<Vec<String>, FlowyError> {
+    self.inner.purge_trash(older_than).await
+  }
+
+  fn supports_multipart(&self) -> bool {
+    self.inner.supports_multipart()
+  }
+
+  async fn initiate_multipart(&self, url: String, mime: Mime) -> Result<UploadId, FlowyError> {
+    self.inner.initiate_multipart(url, mime).await
+  }
+
+  async fn upload_part(
+    &self,
+    url: String,
+    upload_id: UploadId,
+    part_number: u32,
+    bytes: Bytes,
+  ) -> Result<PartETag, FlowyError> {
+    self
+      .inner
+      .upload_part(url, upload_id, part_number, bytes)
+      .await
+  }
+
+  async fn complete_multipart(
+    &self,
+    url: String,
+    upload_id: UploadId,
+    parts: Vec<PartETag>,
+  ) -> Result<(), FlowyError> {
+    let fut = self.inner.complete_multipart(url.clone(), upload_id, parts);
+    self.forgetting(url, fut).await
+  }
+
+  async fn abort_multipart(&self, url: String, upload_id: UploadId) -> Result<(), FlowyError> {
+    self.inner.abort_multipart(url, upload_id).await
+  }
+
+  async fn health_check(&self) -> Result<HealthStatus, FlowyError> {
+    self.inner.health_check().await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    memory_object_value, InMemoryObjectStorage, StorageErrorExt, StorageErrorKind, StorageOperation,
+  };
+
+  async fn put_with_ttl(
+    storage: &ExpiringObjectStorage<InMemoryObjectStorage>,
+    file_id: &str,
+    ttl: Duration,
+  ) -> String {
+    let identity = ObjectIdentity {
+      workspace_id: "w1".to_string(),
+      file_id: file_id.to_string(),
+      ext: "zip".to_string(),
+      hash_algorithm: None,
+      size: None,
+    };
+    let url = storage.get_object_url(identity).await.unwrap();
+    storage
+      .put_object_with_ttl(url.clone(), memory_object_value("export.zip", "data"), ttl)
+      .await
+      .unwrap();
+    url
+  }
+
+  #[tokio::test]
+  async fn expired_object_is_not_found_before_reaped_test() {
+    let inner = Arc::new(InMemoryObjectStorage::new());
+    let storage = ExpiringObjectStorage::new(inner.clone());
+    let expired = put_with_ttl(&storage, "a", Duration::ZERO).await;
+    let kept = put_with_ttl(&storage, "b", Duration::from_secs(3600)).await;
+
+    // The expired object is still stored, but it's reported as not found.
+    assert!(inner.object(&expired).is_some());
+    let err = storage.get_object(expired.clone()).await.err().unwrap();
+    assert!(err.is_record_not_found());
+    assert!(!storage.object_exists(expired.clone()).await.unwrap());
+    assert_eq!(inner.call_count(StorageOperation::Get), 0);
+
+    let meta = storage.head_object(kept.clone()).await.unwrap();
+    let ttl = meta.remaining_ttl().unwrap();
+    assert!(ttl > Duration::from_secs(3500) && ttl <= Duration::from_secs(3600));
+    let objects = storage.list_objects("w1", None).await.unwrap();
+    assert_eq!(objects.len(), 1);
+    assert_eq!(objects[0].url, kept);
+
+    assert_eq!(storage.reap().await.unwrap(), vec![expired.clone()]);
+    assert!(inner.object(&expired).is_none());
+    assert!(inner.object(&kept).is_some());
+    assert!(storage.reap().await.unwrap().is_empty());
+  }
+
+  #[tokio::test]
+  async fn put_without_ttl_clears_expiry_test() {
+    let inner = Arc::new(InMemoryObjectStorage::new());
+    let storage = ExpiringObjectStorage::new(inner.clone());
+    let url = put_with_ttl(&storage, "a", Duration::ZERO).await;
+    storage
+      .put_object(url.clone(), memory_object_value("export.zip", "data"))
+      .await
+      .unwrap();
+    assert!(storage.expires_at(&url).is_none());
+    assert!(storage.get_object(url.clone()).await.is_ok());
+    assert!(storage.reap().await.unwrap().is_empty());
+    assert!(inner.object(&url).is_some());
+  }
+
+  #[tokio::test]
+  async fn expired_object_is_absent_for_conditional_puts_test() {
+    let inner = Arc::new(InMemoryObjectStorage::new());
+    let storage = ExpiringObjectStorage::new(inner.clone());
+    let url = put_with_ttl(&storage, "a", Duration::ZERO).await;
+    let etag = inner.head_object(url.clone()).await.unwrap().etag.unwrap();
+    let err = storage
+      .put_object_if_match(url.clone(), memory_object_value("export.zip", "new"), etag)
+      .await
+      .unwrap_err();
+    assert!(err.is_storage_kind(StorageErrorKind::PreconditionFailed));
+
+    storage
+      .put_object_if_absent(url.clone(), memory_object_value("export.zip", "new"))
+      .await
+      .unwrap();
+    assert!(storage.expires_at(&url).is_none());
+    let value = storage.get_object(url).await.unwrap();
+    assert_eq!(value.raw, Bytes::from("new"));
+  }
+}
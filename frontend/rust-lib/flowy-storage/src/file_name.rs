@@ -0,0 +1,242 @@
+/// The maximum length in bytes of a sanitized file name, the limit of most file systems.
+pub const MAX_FILE_NAME_LEN: usize = 255;
+
+/// Extensions longer than this are not preserved when a name is truncated.
+const MAX_EXT_LEN: usize = 16;
+
+/// Characters that are rejected by at least one of the supported backends or file systems.
+const ILLEGAL_CHARS: &[char] = &['<', '>', ':', '"', '|', '?', '*'];
+
+/// Names that Windows reserves for devices, with or without an extension.
+const RESERVED_NAMES: &[&str] = &[
+  "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+  "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Normalizes a file name so it can be used as an object name on every backend:
+///
+/// * Directory components are stripped, `a/b\c.txt` becomes `c.txt`.
+/// * Control characters and `<>:"|?*` are removed.
+/// * Runs of whitespace are collapsed into a single space, leading and trailing whitespace and
+///   trailing dots are trimmed, so `.` and `..` are empty.
+/// * Names reserved by Windows, like `CON` or `nul.txt`, are prefixed with `_`.
+/// * Names longer than [MAX_FILE_NAME_LEN] bytes are truncated, keeping the extension.
+///
+/// Returns `None` if nothing is left of the name. Sanitizing a sanitized name returns it
+/// unchanged.
+pub fn sanitize_file_name(file_name: &str) -> Option<String> {
+  let base_name = file_name
+    .rsplit(['/', '\\'])
+    .find(|component| !component.trim().is_empty())
+    .unwrap_or("");
+  let legal = base_name
+    .chars()
+    .filter(|c| !c.is_control() && !ILLEGAL_CHARS.contains(c))
+    .collect::<String>();
+  let collapsed = legal.split_whitespace().collect::<Vec<_>>().join(" ");
+  let trimmed = collapsed.trim_end_matches(['.', ' ']);
+  if trimmed.is_empty() {
+    return None;
+  }
+
+  let stem = trimmed.split('.').next().unwrap_or(trimmed);
+  let name = if RESERVED_NAMES
+    .iter()
+    .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+  {
+    format!("_{}", trimmed)
+  } else {
+    trimmed.to_string()
+  };
+  Some(truncate(name))
+}
+
+fn truncate(name: String) -> String {
+  if name.len() <= MAX_FILE_NAME_LEN {
+    return name;
+  }
+  let (stem, ext) = match name.rfind('.') {
+    Some(index) if index > 0 && name.len() - index <= MAX_EXT_LEN + 1 => name.split_at(index),
+    _ => (name.as_str(), ""),
+  };
+  let mut end = MAX_FILE_NAME_LEN - ext.len();
+  while !stem.is_char_boundary(end) {
+    end -= 1;
+  }
+  format!("{}{}", stem[..end].trim_end(), ext)
+}
+
+/// Returns the file name suggested by a `Content-Disposition` header, sanitized with
+/// [sanitize_file_name]. The RFC 5987 `filename*` parameter, like
+/// `filename*=UTF-8''na%C3%AFve.txt`, takes precedence over `filename`. `None` if the header has
+/// no usable file name.
+pub fn content_disposition_file_name(header: &str) -> Option<String> {
+  let mut file_name = None;
+  let mut encoded_file_name = None;
+  // The disposition type comes first, `inline` or `attachment`.
+  for (key, value) in disposition_params(header).into_iter().skip(1) {
+    if key.eq_ignore_ascii_case("filename*") {
+      encoded_file_name = encoded_file_name.or_else(|| decode_ext_value(&value));
+    } else if key.eq_ignore_ascii_case("filename") {
+      file_name = file_name.or(Some(value));
+    }
+  }
+  encoded_file_name
+    .or(file_name)
+    .and_then(|file_name| sanitize_file_name(&file_name))
+}
+
+/// Returns the last segment of the path of the url, percent-decoded and sanitized with
+/// [sanitize_file_name].
+pub fn file_name_from_url(url: &str) -> Option<String> {
+  let path = url.split(['?', '#']).next()?;
+  let segment = path.trim_end_matches('/').rsplit('/').next()?;
+  let segment = percent_encoding::percent_decode_str(segment).decode_utf8_lossy();
+  sanitize_file_name(&segment)
+}
+
+/// Returns the name a downloaded object should be saved under: the one suggested by the
+/// `Content-Disposition` header of the response if there is one, the last segment of the url
+/// otherwise.
+pub fn suggested_file_name(content_disposition: Option<&str>, url: &str) -> Option<String> {
+  content_disposition
+    .and_then(content_disposition_file_name)
+    .or_else(|| file_name_from_url(url))
+}
+
+/// Same as [suggested_file_name], with the headers of the response.
+pub fn suggested_file_name_from_headers(
+  headers: &reqwest::header::HeaderMap,
+  url: &str,
+) -> Option<String> {
+  let content_disposition = headers
+    .get(reqwest::header::CONTENT_DISPOSITION)
+    .and_then(|value| value.to_str().ok());
+  suggested_file_name(content_disposition, url)
+}
+
+/// Splits a header into its `;` separated parameters. A value can be a quoted string, in which
+/// `;` is not a separator and `\` escapes the next character. A parameter without `=`, like the
+/// disposition type, has an empty value.
+fn disposition_params(header: &str) -> Vec<(String, String)> {
+  let mut params = vec![];
+  let mut chars = header.chars().peekable();
+  while chars.peek().is_some() {
+    let mut key = String::new();
+    while let Some(c) = chars.next_if(|c| *c != '=' && *c != ';') {
+      key.push(c);
+    }
+    let mut value = String::new();
+    if chars.next_if_eq(&'=').is_some() {
+      while chars.next_if(|c| c.is_whitespace()).is_some() {}
+      if chars.next_if_eq(&'"').is_some() {
+        while let Some(c) = chars.next() {
+          match c {
+            '\\' => value.extend(chars.next()),
+            '"' => break,
+            c => value.push(c),
+          }
+        }
+      }
+      while let Some(c) = chars.next_if(|c| *c != ';') {
+        value.push(c);
+      }
+    }
+    // Skips the `;`.
+    chars.next();
+    params.push((key.trim().to_string(), value.trim().to_string()));
+  }
+  params
+}
+
+/// Decodes an RFC 5987 value, `{charset}'{language}'{percent-encoded value}`. Only the UTF-8 and
+/// ISO-8859-1 charsets are required by the RFC, the others are not supported.
+fn decode_ext_value(value: &str) -> Option<String> {
+  let mut parts = value.splitn(3, '\'');
+  let (charset, _language, encoded) = (parts.next()?, parts.next()?, parts.next()?);
+  let bytes = percent_encoding::percent_decode_str(encoded).collect::<Vec<u8>>();
+  if charset.eq_ignore_ascii_case("utf-8") {
+    String::from_utf8(bytes).ok()
+  } else if charset.eq_ignore_ascii_case("iso-8859-1") {
+    Some(bytes.into_iter().map(char::from).collect())
+  } else {
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn sanitize_file_name_test() {
+    assert_eq!(sanitize_file_name("report.pdf").unwrap(), "report.pdf");
+    assert_eq!(sanitize_file_name("../../etc/passwd").unwrap(), "passwd");
+    assert_eq!(sanitize_file_name("C:\\Users\\a\\b.txt").unwrap(), "b.txt");
+    assert_eq!(sanitize_file_name("dir/").unwrap(), "dir");
+    assert_eq!(
+      sanitize_file_name("  my \t  file\n?.txt ").unwrap(),
+      "my file.txt"
+    );
+    assert_eq!(sanitize_file_name("nul.txt").unwrap(), "_nul.txt");
+    assert_eq!(sanitize_file_name(".env").unwrap(), ".env");
+
+    // Unicode names are kept, truncation doesn't split a character.
+    assert_eq!(
+      sanitize_file_name("日本語 ファイル.png").unwrap(),
+      "日本語 ファイル.png"
+    );
+    let long = format!("{}.jpeg", "é".repeat(200));
+    let sanitized = sanitize_file_name(&long).unwrap();
+    assert!(sanitized.len() <= MAX_FILE_NAME_LEN);
+    assert!(sanitized.ends_with("é.jpeg"));
+    assert_eq!(sanitize_file_name(&sanitized).unwrap(), sanitized);
+
+    for name in ["", "..", ".", "/", "a/..", "???", "\u{0}\u{7}", " * "] {
+      assert!(sanitize_file_name(name).is_none(), "{:?}", name);
+    }
+  }
+
+  #[test]
+  fn content_disposition_file_name_test() {
+    let file_name = content_disposition_file_name;
+    assert_eq!(
+      file_name("attachment; filename=\"report.pdf\"").unwrap(),
+      "report.pdf"
+    );
+    assert_eq!(file_name("inline; filename=a.txt").unwrap(), "a.txt");
+    assert_eq!(
+      file_name(r#"attachment; filename="a \"b\"; c.txt""#).unwrap(),
+      "a b; c.txt"
+    );
+    assert_eq!(
+      file_name("attachment; filename=\"fallback.txt\"; filename*=UTF-8''na%C3%AFve%20file.txt")
+        .unwrap(),
+      "naïve file.txt"
+    );
+    assert_eq!(
+      file_name("attachment; filename*=iso-8859-1'en'caf%E9.txt").unwrap(),
+      "café.txt"
+    );
+    assert_eq!(
+      file_name("attachment; filename=\"../../etc/passwd\"").unwrap(),
+      "passwd"
+    );
+    assert!(file_name("attachment").is_none());
+    assert!(file_name("attachment; filename*=UTF-16''abc").is_none());
+  }
+
+  #[test]
+  fn suggested_file_name_test() {
+    let url = "https://host/blob/w1/my%20file.pdf?token=1";
+    assert_eq!(suggested_file_name(None, url).unwrap(), "my file.pdf");
+    assert_eq!(
+      suggested_file_name(Some("attachment; filename=report.pdf"), url).unwrap(),
+      "report.pdf"
+    );
+    assert_eq!(
+      suggested_file_name(Some("attachment"), url).unwrap(),
+      "my file.pdf"
+    );
+  }
+}
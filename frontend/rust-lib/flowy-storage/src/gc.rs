@@ -0,0 +1,187 @@
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime};
+
+use tracing::{info, warn};
+
+use flowy_error::FlowyError;
+
+use crate::{ObjectMeta, ObjectStorageService};
+
+/// The default [GcOptions::grace_period], long enough for the slowest uploads to complete and be
+/// referenced by their document.
+pub const DEFAULT_GC_GRACE_PERIOD: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Clone)]
+pub struct GcOptions {
+  /// Reports the orphaned objects without deleting them.
+  pub dry_run: bool,
+  /// The objects written less than `grace_period` ago are kept even if they are not referenced:
+  /// they may belong to an upload whose document is not saved yet. The objects whose modification
+  /// time is unknown are kept too, unless the grace period is zero.
+  pub grace_period: Duration,
+}
+
+impl Default for GcOptions {
+  fn default() -> Self {
+    Self {
+      dry_run: false,
+      grace_period: DEFAULT_GC_GRACE_PERIOD,
+    }
+  }
+}
+
+#[derive(Debug, Default)]
+pub struct GcReport {
+  /// The number of objects listed in the workspace.
+  pub scanned: usize,
+  /// The urls of the orphaned objects that were deleted, or that would be deleted in a dry run.
+  pub deleted: Vec<String>,
+  /// The number of bytes of the deleted objects.
+  pub reclaimed_bytes: u64,
+  /// The number of orphaned objects kept because of the grace period.
+  pub skipped_recent: usize,
+  /// The orphaned objects that couldn't be deleted, they are retried by the next collection.
+  pub failed: Vec<(String, FlowyError)>,
+}
+
+/// Deletes the objects of the workspace whose `file_id` is not in `referenced_file_ids`, the
+/// objects left behind by the deleted documents. The caller provides the ids still referenced by
+/// the documents of the workspace, an incomplete set deletes objects that are still in use.
+///
+/// The objects in the trash are not collected, they are purged by
+/// [ObjectStorageService::purge_trash]. Failing to list the objects fails the collection, a
+/// failed deletion is only reported in [GcReport::failed].
+pub async fn gc_orphans<S>(
+  service: &S,
+  workspace_id: &str,
+  referenced_file_ids: &HashSet<String>,
+  options: &GcOptions,
+) -> Result<GcReport, FlowyError>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  let objects = service.list_objects(workspace_id, None).await?;
+  let now = SystemTime::now();
+  let mut report = GcReport {
+    scanned: objects.len(),
+    ..Default::default()
+  };
+  let mut orphans = vec![];
+  for object in objects {
+    if referenced_file_ids.contains(&object.file_id) {
+      continue;
+    }
+    if is_within_grace_period(&object, now, options.grace_period) {
+      report.skipped_recent += 1;
+      continue;
+    }
+    orphans.push(object);
+  }
+
+  if options.dry_run {
+    report.reclaimed_bytes = orphans.iter().map(|object| object.size).sum();
+    report.deleted = orphans.into_iter().map(|object| object.url).collect();
+    return Ok(report);
+  }
+  if orphans.is_empty() {
+    return Ok(report);
+  }
+
+  let urls = orphans.iter().map(|object| object.url.clone()).collect();
+  let results = service.delete_objects(urls).await?;
+  for (object, result) in orphans.into_iter().zip(results) {
+    match result {
+      Ok(()) => {
+        report.reclaimed_bytes += object.size;
+        report.deleted.push(object.url);
+      },
+      Err(err) => {
+        warn!(
+          "failed to delete the orphaned object {}: {}",
+          object.url, err
+        );
+        report.failed.push((object.url, err));
+      },
+    }
+  }
+  info!(
+    "collected {} orphaned objects in {}, {} bytes reclaimed",
+    report.deleted.len(),
+    workspace_id,
+    report.reclaimed_bytes
+  );
+  Ok(report)
+}
+
+fn is_within_grace_period(object: &ObjectMeta, now: SystemTime, grace_period: Duration) -> bool {
+  if grace_period.is_zero() {
+    return false;
+  }
+  match object.last_modified {
+    Some(last_modified) => now
+      .duration_since(last_modified)
+      .map_or(true, |age| age < grace_period),
+    None => true,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{memory_object_value, InMemoryObjectStorage, ObjectIdentity};
+
+  async fn put(storage: &InMemoryObjectStorage, file_id: &str, content: &'static str) -> String {
+    let identity = ObjectIdentity {
+      workspace_id: "w1".to_string(),
+      file_id: file_id.to_string(),
+      ext: "txt".to_string(),
+      hash_algorithm: None,
+      size: None,
+    };
+    let url = storage.get_object_url(identity).await.unwrap();
+    storage
+      .put_object(url.clone(), memory_object_value("a.txt", content))
+      .await
+      .unwrap();
+    url
+  }
+
+  #[tokio::test]
+  async fn gc_orphans_test() {
+    let storage = InMemoryObjectStorage::new();
+    put(&storage, "kept", "kept").await;
+    let orphan = put(&storage, "orphan", "orphan").await;
+    let referenced = HashSet::from(["kept".to_string()]);
+
+    // The objects were just written, the grace period keeps them.
+    let report = gc_orphans(&storage, "w1", &referenced, &GcOptions::default())
+      .await
+      .unwrap();
+    assert_eq!(report.scanned, 2);
+    assert_eq!(report.skipped_recent, 1);
+    assert!(report.deleted.is_empty());
+
+    let options = GcOptions {
+      dry_run: true,
+      grace_period: Duration::ZERO,
+    };
+    let report = gc_orphans(&storage, "w1", &referenced, &options)
+      .await
+      .unwrap();
+    assert_eq!(report.deleted, vec![orphan.clone()]);
+    assert_eq!(report.reclaimed_bytes, 6);
+    assert_eq!(storage.len(), 2);
+
+    let options = GcOptions {
+      dry_run: false,
+      ..options
+    };
+    let report = gc_orphans(&storage, "w1", &referenced, &options)
+      .await
+      .unwrap();
+    assert_eq!(report.deleted, vec![orphan.clone()]);
+    assert_eq!(report.reclaimed_bytes, 6);
+    assert!(storage.object(&orphan).is_none());
+    assert_eq!(storage.len(), 1);
+  }
+}
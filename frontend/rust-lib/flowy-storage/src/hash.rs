@@ -0,0 +1,317 @@
+use std::hash::Hasher;
+
+use fxhash::FxHasher;
+
+use flowy_error::{ErrorCode, FlowyError};
+
+/// Computes the content hash used as `file_id` incrementally.
+///
+/// Feeding the content chunk by chunk produces exactly the same value as `fxhash::hash` over
+/// the whole `Vec<u8>`, which means the caller doesn't need to keep the whole content in memory.
+/// The total length must be known up front because the hash of a `Vec<u8>` starts with its
+/// length.
+pub struct ContentHasher {
+  hasher: FxHasher,
+  /// The `FxHasher` consumes the input word by word. Bytes that don't fill a complete word are
+  /// kept here until the next chunk arrives or the hasher is finished.
+  pending: Vec<u8>,
+  expected_len: u64,
+  len: u64,
+}
+
+const WORD_SIZE: usize = 8;
+
+impl ContentHasher {
+  pub fn new(expected_len: u64) -> Self {
+    let mut hasher = FxHasher::default();
+    hasher.write_usize(expected_len as usize);
+    Self {
+      hasher,
+      pending: Vec::with_capacity(WORD_SIZE),
+      expected_len,
+      len: 0,
+    }
+  }
+
+  pub fn update(&mut self, mut chunk: &[u8]) {
+    self.len += chunk.len() as u64;
+    if !self.pending.is_empty() {
+      let n = (WORD_SIZE - self.pending.len()).min(chunk.len());
+      self.pending.extend_from_slice(&chunk[..n]);
+      chunk = &chunk[n..];
+      if self.pending.len() < WORD_SIZE {
+        return;
+      }
+      self.hasher.write(&self.pending);
+      self.pending.clear();
+    }
+
+    let aligned = chunk.len() - chunk.len() % WORD_SIZE;
+    self.hasher.write(&chunk[..aligned]);
+    self.pending.extend_from_slice(&chunk[aligned..]);
+  }
+
+  /// Number of bytes fed into the hasher so far.
+  pub fn len(&self) -> u64 {
+    self.len
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len == 0
+  }
+
+  /// Returns true if the number of bytes fed into the hasher matches the length the hasher was
+  /// created with. The hash is meaningless otherwise.
+  pub fn is_complete(&self) -> bool {
+    self.len == self.expected_len
+  }
+
+  pub fn finish(mut self) -> String {
+    if !self.pending.is_empty() {
+      self.hasher.write(&self.pending);
+    }
+    (self.hasher.finish() as usize).to_string()
+  }
+}
+
+/// A hash that is computed chunk by chunk, see [ContentHashAlgorithm::hasher].
+pub trait IncrementalHash: Send + Sync {
+  fn update(&mut self, chunk: &[u8]);
+
+  /// Returns false if the hash is meaningless because the content wasn't fed completely, see
+  /// [ContentHasher::is_complete]. Algorithms that don't depend on the length up front always
+  /// return true.
+  fn is_complete(&self) -> bool;
+
+  fn finish(self) -> String;
+}
+
+impl IncrementalHash for ContentHasher {
+  fn update(&mut self, chunk: &[u8]) {
+    ContentHasher::update(self, chunk)
+  }
+
+  fn is_complete(&self) -> bool {
+    ContentHasher::is_complete(self)
+  }
+
+  fn finish(self) -> String {
+    ContentHasher::finish(self)
+  }
+}
+
+/// An algorithm computing the content hash used as `file_id`. Adding an algorithm only requires
+/// implementing this trait and handling its ids in [verify_content_hash].
+pub trait ContentHashAlgorithm {
+  /// The name recorded in [crate::ObjectIdentity::hash_algorithm].
+  const NAME: &'static str;
+
+  type Hasher: IncrementalHash;
+
+  /// Creates a hasher for content of `expected_len` bytes.
+  fn hasher(expected_len: u64) -> Self::Hasher;
+
+  /// Returns true if `file_id` has the format of the ids produced by the algorithm.
+  fn is_hash_id(file_id: &str) -> bool;
+
+  fn hash(content: &[u8]) -> String {
+    let mut hasher = Self::hasher(content.len() as u64);
+    hasher.update(content);
+    hasher.finish()
+  }
+}
+
+/// The `fxhash` of the content, written as a decimal number. It's fast, but not collision
+/// resistant and it depends on the pointer width of the platform.
+pub struct FxContentHash;
+
+impl ContentHashAlgorithm for FxContentHash {
+  const NAME: &'static str = "fxhash";
+  type Hasher = ContentHasher;
+
+  fn hasher(expected_len: u64) -> Self::Hasher {
+    ContentHasher::new(expected_len)
+  }
+
+  fn is_hash_id(file_id: &str) -> bool {
+    !file_id.is_empty() && file_id.bytes().all(|b| b.is_ascii_digit())
+  }
+}
+
+/// The BLAKE3 hash of the content, written as 64 lowercase hex digits. It's the same on every
+/// platform and collision resistant. Enabled by the `blake3-hash` feature, which makes it the
+/// [DefaultContentHash].
+#[cfg(feature = "blake3-hash")]
+pub struct Blake3ContentHash;
+
+#[cfg(feature = "blake3-hash")]
+pub struct Blake3Hasher(blake3::Hasher);
+
+/// The chunks at least this large are hashed on several threads, the threads cost more than they
+/// save on smaller chunks. BLAKE3 is a tree hash, so the result doesn't depend on how the chunks
+/// are split or on the number of threads.
+#[cfg(all(feature = "blake3-hash", not(target_arch = "wasm32")))]
+pub const PARALLEL_HASH_MIN_LEN: usize = 128 * 1024;
+
+#[cfg(feature = "blake3-hash")]
+impl IncrementalHash for Blake3Hasher {
+  fn update(&mut self, chunk: &[u8]) {
+    #[cfg(not(target_arch = "wasm32"))]
+    if chunk.len() >= PARALLEL_HASH_MIN_LEN {
+      self.0.update_rayon(chunk);
+      return;
+    }
+    self.0.update(chunk);
+  }
+
+  fn is_complete(&self) -> bool {
+    true
+  }
+
+  fn finish(self) -> String {
+    self.0.finalize().to_hex().to_string()
+  }
+}
+
+#[cfg(feature = "blake3-hash")]
+impl ContentHashAlgorithm for Blake3ContentHash {
+  const NAME: &'static str = "blake3";
+  type Hasher = Blake3Hasher;
+
+  fn hasher(_expected_len: u64) -> Self::Hasher {
+    Blake3Hasher(blake3::Hasher::new())
+  }
+
+  fn is_hash_id(file_id: &str) -> bool {
+    file_id.len() == 64
+      && file_id
+        .bytes()
+        .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+  }
+}
+
+/// The algorithm computing the `file_id` of new objects. Switching it changes the ids of the
+/// objects, so the content that was uploaded before isn't deduplicated with the new uploads.
+#[cfg(not(feature = "blake3-hash"))]
+pub type DefaultContentHash = FxContentHash;
+#[cfg(feature = "blake3-hash")]
+pub type DefaultContentHash = Blake3ContentHash;
+
+/// The name of the [DefaultContentHash].
+pub const DEFAULT_HASH_ALGORITHM: &str = <DefaultContentHash as ContentHashAlgorithm>::NAME;
+
+/// Computes the content hash of the given content in one go, with the [DefaultContentHash].
+pub fn content_hash(content: &[u8]) -> String {
+  DefaultContentHash::hash(content)
+}
+
+/// Returns true if `file_id` is a content hash computed by one of the enabled algorithms.
+pub fn is_content_hash_id(file_id: &str) -> bool {
+  #[cfg(feature = "blake3-hash")]
+  if Blake3ContentHash::is_hash_id(file_id) {
+    return true;
+  }
+  FxContentHash::is_hash_id(file_id)
+}
+
+/// Hashes the content with the algorithm that produced `file_id`, `None` if the id doesn't
+/// come from any of the enabled algorithms.
+fn hash_with_algorithm_of(file_id: &str, content: &[u8]) -> Option<String> {
+  #[cfg(feature = "blake3-hash")]
+  if Blake3ContentHash::is_hash_id(file_id) {
+    return Some(Blake3ContentHash::hash(content));
+  }
+  if FxContentHash::is_hash_id(file_id) {
+    return Some(FxContentHash::hash(content));
+  }
+  None
+}
+
+/// Returns an [ErrorCode::ContentHashMismatch] error if the hash of the content is not
+/// `expected_hash`. The `file_id` of an object is its content hash, see [crate::object_from_disk],
+/// so it can be used to verify a downloaded object. The content is hashed with the algorithm
+/// the `expected_hash` comes from, so the objects uploaded before switching the
+/// [DefaultContentHash] can still be verified.
+pub fn verify_content_hash(content: &[u8], expected_hash: &str) -> Result<(), FlowyError> {
+  let hash = match hash_with_algorithm_of(expected_hash, content) {
+    Some(hash) if hash == expected_hash => return Ok(()),
+    Some(hash) => hash,
+    None => {
+      return Err(FlowyError::new(
+        ErrorCode::ContentHashMismatch,
+        format!("{:?} is not a content hash", expected_hash),
+      ))
+    },
+  };
+  Err(FlowyError::new(
+    ErrorCode::ContentHashMismatch,
+    format!(
+      "the hash of the {} bytes content is {}, expected {}",
+      content.len(),
+      hash,
+      expected_hash
+    ),
+  ))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn chunked_hash_matches_one_shot_hash() {
+    let content = (0..10_000u32).map(|i| (i % 251) as u8).collect::<Vec<u8>>();
+    for chunk_size in [1, 3, 8, 13, 4096, content.len()] {
+      let mut hasher = ContentHasher::new(content.len() as u64);
+      for chunk in content.chunks(chunk_size) {
+        hasher.update(chunk);
+      }
+      assert!(hasher.is_complete());
+      assert_eq!(hasher.finish(), fxhash::hash(&content).to_string());
+    }
+  }
+
+  #[test]
+  fn verify_content_hash_test() {
+    let content = b"hello world";
+    assert!(verify_content_hash(content, &content_hash(content)).is_ok());
+
+    let err = verify_content_hash(&content[..5], &content_hash(content)).unwrap_err();
+    assert_eq!(err.code, ErrorCode::ContentHashMismatch);
+  }
+
+  #[test]
+  fn verify_content_hash_only_accepts_hash_ids_test() {
+    assert!(is_content_hash_id(&FxContentHash::hash(b"hello")));
+    assert!(!is_content_hash_id("cover-image"));
+    let err = verify_content_hash(b"", "").unwrap_err();
+    assert_eq!(err.code, ErrorCode::ContentHashMismatch);
+  }
+
+  #[test]
+  fn empty_content_hash() {
+    let hasher = ContentHasher::new(0);
+    assert_eq!(hasher.finish(), FxContentHash::hash(&[]));
+  }
+
+  #[cfg(feature = "blake3-hash")]
+  #[test]
+  fn parallel_blake3_hash_test() {
+    let content = (0..4 * PARALLEL_HASH_MIN_LEN as u32)
+      .map(|i| (i % 251) as u8)
+      .collect::<Vec<u8>>();
+    let expected = blake3::hash(&content).to_hex().to_string();
+    for chunk_size in [
+      1000,
+      PARALLEL_HASH_MIN_LEN,
+      PARALLEL_HASH_MIN_LEN + 7,
+      content.len(),
+    ] {
+      let mut hasher = Blake3ContentHash::hasher(content.len() as u64);
+      for chunk in content.chunks(chunk_size) {
+        hasher.update(chunk);
+      }
+      assert_eq!(hasher.finish(), expected);
+    }
+  }
+}
@@ -0,0 +1,103 @@
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use flowy_error::FlowyError;
+
+use crate::{StorageErrorExt, StorageErrorKind};
+
+/// The workspace of the sentinel object probed by the default
+/// [crate::ObjectStorageService::health_check]. The object is never written.
+pub const HEALTH_CHECK_WORKSPACE_ID: &str = "health-check";
+
+/// The result of [crate::ObjectStorageService::health_check].
+#[derive(Debug, Clone)]
+pub struct HealthStatus {
+  /// False if the backend couldn't be reached or didn't answer in time.
+  pub reachable: bool,
+  /// False if the backend rejected the credentials of the user, or if it couldn't be reached.
+  pub authorized: bool,
+  /// How long the probe took, including the failed ones.
+  pub latency: Duration,
+  /// The message of the error the probe failed with, `None` if it succeeded.
+  pub error: Option<String>,
+}
+
+impl HealthStatus {
+  pub fn healthy(latency: Duration) -> Self {
+    Self {
+      reachable: true,
+      authorized: true,
+      latency,
+      error: None,
+    }
+  }
+
+  /// Classifies the error a probe failed with. A missing object means the backend is up and
+  /// accepted the request.
+  pub fn from_error(error: &FlowyError, latency: Duration) -> Self {
+    let (reachable, authorized) = match error.storage_kind() {
+      Some(StorageErrorKind::NotFound) => return Self::healthy(latency),
+      Some(StorageErrorKind::Unauthorized) => (true, false),
+      Some(
+        StorageErrorKind::Network | StorageErrorKind::Timeout | StorageErrorKind::Unavailable,
+      ) => (false, false),
+      _ => (true, true),
+    };
+    Self {
+      reachable,
+      authorized,
+      latency,
+      error: Some(error.msg.clone()),
+    }
+  }
+
+  pub fn is_healthy(&self) -> bool {
+    self.reachable && self.authorized && self.error.is_none()
+  }
+}
+
+/// Runs `probe` and measures how long it takes. Implementations of
+/// [crate::ObjectStorageService::health_check] can use it with the cheapest request their backend
+/// has.
+pub async fn probe_health<T>(probe: impl Future<Output = Result<T, FlowyError>>) -> HealthStatus {
+  let started_at = Instant::now();
+  let result = probe.await;
+  let latency = started_at.elapsed();
+  match result {
+    Ok(_) => HealthStatus::healthy(latency),
+    Err(err) => HealthStatus::from_error(&err, latency),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+
+  use flowy_error::ErrorCode;
+
+  use super::*;
+  use crate::{InMemoryObjectStorage, ObjectStorageService, StorageOperation};
+
+  #[tokio::test]
+  async fn health_check_test() {
+    let storage = Arc::new(InMemoryObjectStorage::new());
+    let status = storage.health_check().await.unwrap();
+    assert!(status.is_healthy());
+
+    storage.fail_next(
+      StorageOperation::Head,
+      FlowyError::new(ErrorCode::ConnectRefused, "connection refused"),
+    );
+    let status = storage.health_check().await.unwrap();
+    assert!(!status.reachable);
+    assert_eq!(status.error.as_deref(), Some("connection refused"));
+
+    storage.fail_next(
+      StorageOperation::Head,
+      FlowyError::new(ErrorCode::UserUnauthorized, ""),
+    );
+    let status = storage.health_check().await.unwrap();
+    assert!(status.reachable);
+    assert!(!status.authorized);
+  }
+}
@@ -1,160 +1,1700 @@
+use std::collections::HashMap;
 use std::path::Path;
+use std::sync::OnceLock;
+use std::time::Duration;
 
+use async_trait::async_trait;
 use bytes::Bytes;
+use futures::StreamExt;
 
-use flowy_error::FlowyError;
-use lib_infra::future::FutureResult;
+use flowy_error::{ErrorCode, FlowyError};
 use mime::Mime;
+use tokio::io::AsyncRead;
+#[cfg(not(target_arch = "wasm32"))]
 use tokio::io::AsyncReadExt;
 use tracing::info;
 
-pub struct ObjectIdentity {
-  pub workspace_id: String,
-  pub file_id: String,
-  pub ext: String,
-}
+#[cfg(not(target_arch = "wasm32"))]
+pub use atomic::*;
+pub use batch::*;
+#[cfg(not(target_arch = "wasm32"))]
+pub use blocking::*;
+pub use cache::*;
+pub use cancel::*;
+pub use chunking::*;
+pub use circuit::*;
+pub use coalesce::*;
+#[cfg(not(target_arch = "wasm32"))]
+pub use compare::*;
+pub use compression::*;
+pub use conditional::*;
+pub use config::*;
+#[cfg(not(target_arch = "wasm32"))]
+pub use confine::*;
+pub use copy::*;
+pub use data_uri::*;
+pub use dedup::*;
+#[cfg(not(target_arch = "wasm32"))]
+pub use dir::*;
+#[cfg(not(target_arch = "wasm32"))]
+pub use disk_cache::*;
+pub use encoding::*;
+pub use encrypt::*;
+pub use error::*;
+pub use estimate::*;
+pub use expiry::*;
+pub use file_name::*;
+pub use gc::*;
+pub use hash::*;
+pub use health::*;
+pub use list::*;
+#[cfg(not(target_arch = "wasm32"))]
+pub use local_fs::*;
+#[cfg(any(test, feature = "test-util"))]
+pub use memory::*;
+pub use metrics::*;
+pub use multipart::*;
+pub use observer::*;
+#[cfg(not(target_arch = "wasm32"))]
+pub use parallel::*;
+pub use prefetch::*;
+pub use presign::*;
+pub use progress::*;
+pub use quota::*;
+pub use range::*;
+pub use reader::*;
+#[cfg(not(target_arch = "wasm32"))]
+pub use reconnect::*;
+pub use refcount::*;
+#[cfg(not(target_arch = "wasm32"))]
+pub use resumable::*;
+pub use retry::*;
+pub use routing::*;
+pub use scheduler::*;
+pub use size_limit::*;
+pub use sniff::*;
+pub use stream::*;
+pub use throttle::*;
+pub use thumbnail::*;
+pub use timeout::*;
+pub use type_policy::*;
+pub use upload::*;
+pub use upload_events::*;
+#[cfg(not(target_arch = "wasm32"))]
+pub use upload_queue::*;
+pub use usage::*;
+pub use versioning::*;
+pub use wipe::*;
+
+#[cfg(not(target_arch = "wasm32"))]
+mod atomic;
+mod batch;
+#[cfg(not(target_arch = "wasm32"))]
+mod blocking;
+mod cache;
+mod cancel;
+mod chunking;
+mod circuit;
+mod coalesce;
+#[cfg(not(target_arch = "wasm32"))]
+mod compare;
+mod compression;
+mod conditional;
+mod config;
+#[cfg(not(target_arch = "wasm32"))]
+mod confine;
+mod copy;
+mod data_uri;
+mod dedup;
+#[cfg(not(target_arch = "wasm32"))]
+mod dir;
+#[cfg(not(target_arch = "wasm32"))]
+mod disk_cache;
+mod encoding;
+mod encrypt;
+mod error;
+mod estimate;
+mod expiry;
+mod file_name;
+mod gc;
+mod hash;
+mod health;
+mod list;
+#[cfg(not(target_arch = "wasm32"))]
+mod local_fs;
+#[cfg(any(test, feature = "test-util"))]
+mod memory;
+mod metrics;
+mod multipart;
+mod observer;
+#[cfg(not(target_arch = "wasm32"))]
+mod parallel;
+mod prefetch;
+mod presign;
+mod progress;
+mod quota;
+mod range;
+mod reader;
+#[cfg(not(target_arch = "wasm32"))]
+mod reconnect;
+mod refcount;
+#[cfg(not(target_arch = "wasm32"))]
+mod resumable;
+mod retry;
+mod routing;
+mod scheduler;
+mod size_limit;
+mod sniff;
+mod stream;
+mod throttle;
+mod thumbnail;
+mod timeout;
+mod type_policy;
+mod upload;
+mod upload_events;
+#[cfg(not(target_arch = "wasm32"))]
+mod upload_queue;
+mod usage;
+mod versioning;
+mod wipe;
+
+#[derive(Clone)]
+pub struct ObjectIdentity {
+  pub workspace_id: String,
+  pub file_id: String,
+  pub ext: String,
+  /// The [ContentHashAlgorithm::NAME] of the algorithm the `file_id` was computed with, `None`
+  /// if the `file_id` is not a content hash, see [object_from_disk_with_file_id].
+  pub hash_algorithm: Option<String>,
+  /// The size of the content in bytes, `None` if it's not known yet. The content hashes are not
+  /// cryptographic, so two objects are only considered the same if both their `file_id` and their
+  /// size match.
+  pub size: Option<u64>,
+}
+
+#[derive(Clone)]
+pub struct ObjectValue {
+  pub raw: Bytes,
+  pub mime: Mime,
+  /// The algorithm `raw` is compressed with, `None` if it's not compressed. Implementations that
+  /// can set the `Content-Encoding` header when uploading should store the compressed content
+  /// with the header, and [ObjectValue::decompress] the downloaded content.
+  pub content_encoding: Option<Compression>,
+}
+
+/// The size of some content, whether it's held in memory or streamed, for the callers that only
+/// need the length, like the quota checks and the progress reporting.
+pub trait ContentLength {
+  /// The number of bytes of the content, `None` if it's not known before the content is read.
+  fn content_length(&self) -> Option<u64>;
+}
+
+/// The length of [ObjectValue::raw], which is the compressed length if the value is compressed.
+impl ContentLength for ObjectValue {
+  fn content_length(&self) -> Option<u64> {
+    Some(self.raw.len() as u64)
+  }
+}
+
+/// The streams of unknown length have a [ObjectStream::content_length] of 0, so the empty
+/// streams are of unknown length too.
+impl ContentLength for ObjectStream {
+  fn content_length(&self) -> Option<u64> {
+    (self.content_length > 0).then_some(self.content_length)
+  }
+}
+
+/// The size of a file is only known once it has been read by [StorageObject::content_length],
+/// which is async, it's `None` before.
+impl ContentLength for ObjectValueSupabase {
+  fn content_length(&self) -> Option<u64> {
+    match self {
+      ObjectValueSupabase::File { size, .. } => size.get().copied(),
+      ObjectValueSupabase::Bytes { bytes, .. } => Some(bytes.len() as u64),
+      ObjectValueSupabase::Reader { content_length, .. } => *content_length,
+    }
+  }
+}
+
+/// Guesses the mime type of a file from its name. Falls back to `application/octet-stream`.
+pub(crate) fn guess_mime(file_name: &str) -> Mime {
+  mime_guess::from_ext(&file_ext(file_name)).first_or_octet_stream()
+}
+
+/// Returns the extension of the last component of a path, empty if there is none. Both `/` and
+/// `\` separate the components whatever the platform, so a Windows path, including an
+/// extended-length `\\?\` one, gives the same extension everywhere. Like [Path::extension], a
+/// name starting with its only dot, like `.env`, has no extension.
+///
+/// A path that isn't valid UTF-8 is converted lossily beforehand, see [Path::to_string_lossy].
+/// An extension that lost characters in the conversion is dropped, the callers infer it from the
+/// mime type instead, see [fill_ext_from_mime].
+pub(crate) fn file_ext(file_name: &str) -> String {
+  let name = file_name.rsplit(['/', '\\']).next().unwrap_or_default();
+  match name.rfind('.') {
+    Some(index) if index > 0 => {
+      let ext = &name[index + 1..];
+      if ext.contains(char::REPLACEMENT_CHARACTER) {
+        String::new()
+      } else {
+        ext.to_string()
+      }
+    },
+    _ => String::new(),
+  }
+}
+
+/// Builds the identity of an object whose `file_id` is computed with the [DefaultContentHash].
+/// The extension is taken from the `file_name`.
+pub(crate) fn object_identity(
+  workspace_id: &str,
+  file_name: &str,
+  file_id: String,
+  size: Option<u64>,
+) -> ObjectIdentity {
+  let ext = file_ext(file_name);
+  ObjectIdentity {
+    workspace_id: workspace_id.to_owned(),
+    file_id,
+    ext,
+    hash_algorithm: Some(DEFAULT_HASH_ALGORITHM.to_string()),
+    size,
+  }
+}
+
+/// Fills the extension of an identity built from a file name without extension with the usual
+/// extension of the `mime` type, see [ext_from_mime], so the url still tells the type of the
+/// object.
+pub(crate) fn fill_ext_from_mime(identity: &mut ObjectIdentity, mime: &Mime) {
+  if identity.ext.is_empty() {
+    if let Some(ext) = ext_from_mime(mime) {
+      identity.ext = ext.to_string();
+    }
+  }
+}
+
+/// A random `file_id` for an object whose content hash is not known yet.
+pub(crate) fn random_file_id() -> String {
+  format!("{:032x}", rand::random::<u128>())
+}
+
+/// Returns the `file_id` of the object the url points to. The url of an object ends with
+/// `{file_id}.{ext}`, see [ObjectStorageService::get_object_url]. `None` if the last segment of
+/// the url is not a content hash.
+pub fn file_id_from_url(url: &str) -> Option<&str> {
+  let path = url.split(['?', '#']).next()?;
+  let file_name = path.rsplit('/').next()?;
+  let file_id = file_name.split('.').next()?;
+  if is_content_hash_id(file_id) {
+    Some(file_id)
+  } else {
+    None
+  }
+}
+
+/// Builds the identity and value of an object from its file name and content. Unless the `mime`
+/// is given, it's guessed from the extension, and detected from the content as well if
+/// `sniff_mime` is true, see [detect_mime]. The `file_id` is the content hash unless it's given,
+/// either because it's explicit or because it was computed while reading the content.
+///
+/// All the versions of [object_from_disk] and [object_from_bytes] go through this function, so
+/// the same content always produces the same `file_id`, extension and mime type.
+fn object_from_content(
+  workspace_id: &str,
+  file_name: &str,
+  content: Vec<u8>,
+  sniff_mime: bool,
+  file_id: Option<String>,
+  mime: Option<Mime>,
+) -> (ObjectIdentity, ObjectValue) {
+  let file_id = file_id.unwrap_or_else(|| content_hash(&content));
+  let mime = mime.unwrap_or_else(|| {
+    let mime = detect_mime(file_name, &content, sniff_mime);
+    if storage_config().detect_text_charset {
+      with_text_charset(mime, &content)
+    } else {
+      mime
+    }
+  });
+  let size = Some(content.len() as u64);
+  let mut identity = object_identity(workspace_id, file_name, file_id, size);
+  fill_ext_from_mime(&mut identity, &mime);
+  (
+    identity,
+    ObjectValue {
+      raw: content.into(),
+      mime,
+      content_encoding: None,
+    },
+  )
+}
+
+/// Checks that an explicit `file_id` can be used in the url of an object, which ends with
+/// `{file_id}.{ext}`. Only ASCII letters, digits, `-` and `_` are allowed.
+fn check_explicit_file_id(file_id: &str) -> Result<(), FlowyError> {
+  let valid = !file_id.is_empty()
+    && file_id
+      .bytes()
+      .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+  if valid {
+    Ok(())
+  } else {
+    Err(FlowyError::new(
+      ErrorCode::InvalidParams,
+      format!(
+        "invalid file id {:?}, only ASCII letters, digits, '-' and '_' are allowed",
+        file_id
+      ),
+    ))
+  }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn check_content_size(
+  file_name: &str,
+  content: &[u8],
+  max_bytes: Option<u64>,
+) -> Result<(), FlowyError> {
+  info!("read {} bytes from file: {}", content.len(), file_name);
+  match max_bytes {
+    Some(max_bytes) if content.len() as u64 > max_bytes => Err(file_too_large_error(
+      file_name,
+      max_bytes,
+      content.len() as u64,
+    )),
+    _ => Ok(()),
+  }
+}
+
+/// The browser doesn't expose the local file system, so the content of the file is expected to be
+/// read by the caller (for example from a `File`/`Blob` handed over by the web layer). The
+/// `file_name` is used to guess the extension and the mime type. Set `sniff_mime` to detect the
+/// mime type from the content too. Returns an [ErrorCode::FileTooLarge] error if the content is
+/// larger than `max_bytes`.
+#[cfg(target_arch = "wasm32")]
+pub async fn object_from_disk(
+  workspace_id: &str,
+  file_name: &str,
+  content: Vec<u8>,
+  sniff_mime: bool,
+  max_bytes: Option<u64>,
+) -> Result<(ObjectIdentity, ObjectValue), FlowyError> {
+  check_content_size(file_name, &content, max_bytes)?;
+  Ok(object_from_content(
+    workspace_id,
+    file_name,
+    content,
+    sniff_mime,
+    None,
+    None,
+  ))
+}
+
+/// Same as [object_from_disk], but the object is identified by the given `file_id` instead of
+/// its content hash, see the native version for the implications.
+#[cfg(target_arch = "wasm32")]
+pub async fn object_from_disk_with_file_id(
+  workspace_id: &str,
+  file_name: &str,
+  content: Vec<u8>,
+  file_id: &str,
+  sniff_mime: bool,
+  max_bytes: Option<u64>,
+) -> Result<(ObjectIdentity, ObjectValue), FlowyError> {
+  check_explicit_file_id(file_id)?;
+  check_content_size(file_name, &content, max_bytes)?;
+  let (mut identity, value) = object_from_content(
+    workspace_id,
+    file_name,
+    content,
+    sniff_mime,
+    Some(file_id.to_string()),
+    None,
+  );
+  identity.hash_algorithm = None;
+  Ok((identity, value))
+}
+
+/// Reads the file at `local_file_path`. The mime type is guessed from the extension, set
+/// `sniff_mime` to detect it from the content too. The charset of text files is added to it when
+/// [StorageConfig::detect_text_charset] is set.
+///
+/// Returns an [ErrorCode::FileTooLarge] error if the file is larger than `max_bytes`. The size
+/// is checked before reading the file, and the read stops as soon as it goes over the limit in
+/// case the file grew in the meantime. Returns an [ErrorCode::InvalidParams] error, before
+/// reading anything, if the path is a directory, a broken symlink or anything else than a regular
+/// file.
+///
+/// An empty file is a valid object. Its `file_id` is the content hash of the empty content, so
+/// every empty file is deduplicated into one object per extension, and its mime type comes from
+/// the extension alone, `application/octet-stream` without one.
+///
+/// The path doesn't need to be valid UTF-8, the extension is still taken from it when it is,
+/// see [file_ext].
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn object_from_disk(
+  workspace_id: &str,
+  local_file_path: impl AsRef<Path>,
+  sniff_mime: bool,
+  max_bytes: Option<u64>,
+) -> Result<(ObjectIdentity, ObjectValue), FlowyError> {
+  let local_file_path = local_file_path.as_ref();
+  let (content, hash) = read_file_content(local_file_path, max_bytes).await?;
+  Ok(object_from_content(
+    workspace_id,
+    &local_file_path.to_string_lossy(),
+    content,
+    sniff_mime,
+    Some(hash),
+    None,
+  ))
+}
+
+/// Same as [object_from_disk], but the object is identified by the given `file_id`, for example
+/// `cover-image`, instead of its content hash. The extension and the mime type are still taken
+/// from the file.
+///
+/// An explicit `file_id` opts out of the content deduplication: uploading the same content under
+/// two ids stores it twice, and uploading new content under the same id replaces the previous
+/// object. The content can't be verified with [ObjectStorageService::get_object_verified]
+/// either. Returns an [ErrorCode::InvalidParams] error if the `file_id` contains anything else
+/// than ASCII letters, digits, `-` and `_`.
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn object_from_disk_with_file_id(
+  workspace_id: &str,
+  local_file_path: impl AsRef<Path>,
+  file_id: &str,
+  sniff_mime: bool,
+  max_bytes: Option<u64>,
+) -> Result<(ObjectIdentity, ObjectValue), FlowyError> {
+  check_explicit_file_id(file_id)?;
+  let local_file_path = local_file_path.as_ref();
+  let (content, _) = read_file_content(local_file_path, max_bytes).await?;
+  let (mut identity, value) = object_from_content(
+    workspace_id,
+    &local_file_path.to_string_lossy(),
+    content,
+    sniff_mime,
+    Some(file_id.to_string()),
+    None,
+  );
+  identity.hash_algorithm = None;
+  Ok((identity, value))
+}
+
+/// Builds an object from content held in memory, for example a pasted image, the way
+/// [object_from_disk] builds it from a file: the `file_id` is the same content hash and the
+/// extension is taken from `file_name`, so the same content gets the same identity wherever it
+/// comes from. Unless it's given, the mime type is detected like [object_from_disk] does with
+/// the same `sniff_mime`, which must match for the identities to match when the file name has no
+/// extension.
+pub fn object_from_bytes(
+  workspace_id: &str,
+  file_name: &str,
+  bytes: impl Into<Vec<u8>>,
+  sniff_mime: bool,
+  mime: Option<Mime>,
+) -> (ObjectIdentity, ObjectValue) {
+  object_from_content(
+    workspace_id,
+    file_name,
+    bytes.into(),
+    sniff_mime,
+    None,
+    mime,
+  )
+}
+
+/// Reads the file and computes its content hash in the same pass. Falls back to hashing the
+/// content that was read if the file changed size since its metadata was read, so the hash is
+/// always the one [content_hash] computes for the returned content.
+#[cfg(not(target_arch = "wasm32"))]
+async fn read_file_content(
+  local_file_path: &Path,
+  max_bytes: Option<u64>,
+) -> Result<(Vec<u8>, String), FlowyError> {
+  check_regular_file(local_file_path, tokio::fs::metadata(local_file_path).await)?;
+  let display_path = local_file_path.display().to_string();
+  let io_error = |err| io_error(err, local_file_path);
+  let mut file = tokio::fs::File::open(local_file_path)
+    .await
+    .map_err(io_error)?;
+  let size = file.metadata().await.map_err(io_error)?.len();
+  let mut content = match max_bytes {
+    Some(max_bytes) if size > max_bytes => {
+      return Err(file_too_large_error(&display_path, max_bytes, size));
+    },
+    Some(max_bytes) => Vec::with_capacity(size.min(max_bytes) as usize),
+    None => Vec::with_capacity(size as usize),
+  };
+
+  // Read one byte over the limit to find out if the file grew in the meantime.
+  let limit = max_bytes.map_or(u64::MAX, |max_bytes| max_bytes + 1);
+  let hasher = copy_and_hash(
+    &mut (&mut file).take(limit),
+    &mut content,
+    DefaultContentHash::hasher(size),
+    storage_config().read_buffer_size,
+  )
+  .await?;
+  let n = content.len() as u64;
+  if let Some(max_bytes) = max_bytes.filter(|max_bytes| n > *max_bytes) {
+    return Err(file_too_large_error(&display_path, max_bytes, n));
+  }
+  info!("read {} bytes from file: {}", n, display_path);
+  let hash = if hasher.is_complete() {
+    hasher.finish()
+  } else {
+    content_hash(&content)
+  };
+  Ok((content, hash))
+}
+
+fn file_too_large_error(file_name: &str, max_bytes: u64, size: u64) -> FlowyError {
+  FlowyError::new(
+    ErrorCode::FileTooLarge,
+    format!(
+      "{} is {} bytes, which is {} bytes over the limit of {} bytes",
+      file_name,
+      size,
+      size - max_bytes,
+      max_bytes
+    ),
+  )
+}
+
+/// Provides a service for object storage.
+///
+/// The trait includes methods for CRUD operations on storage objects.
+#[async_trait]
+pub trait ObjectStorageService: Send + Sync + 'static {
+  /// Creates a new storage object.
+  ///
+  /// # Parameters
+  /// - `url`: url of the object to be created.
+  ///
+  /// # Returns
+  /// - `Ok()`
+  /// - `Err(Error)`: An error occurred during the operation.
+  async fn get_object_url(&self, object_id: ObjectIdentity) -> Result<String, FlowyError>;
+
+  /// Creates a new storage object.
+  ///
+  /// # Parameters
+  /// - `url`: url of the object to be created.
+  ///
+  /// # Returns
+  /// - `Ok()`
+  /// - `Err(Error)`: An error occurred during the operation.
+  async fn put_object(&self, url: String, object_value: ObjectValue) -> Result<(), FlowyError>;
+
+  /// Creates a new storage object and reports the progress of the upload.
+  ///
+  /// The default implementation can't observe the upload, so it reports 0% when the upload
+  /// starts and 100% once it succeeds. Implementations that upload the content in chunks should
+  /// report every flushed chunk, for example by wrapping the body with [progress_stream].
+  ///
+  /// # Parameters
+  /// - `url`: url of the object to be created.
+  /// - `progress`: called with the number of bytes sent and the total number of bytes.
+  ///
+  /// # Returns
+  /// - `Ok()`
+  /// - `Err(Error)`: An error occurred during the operation.
+  async fn put_object_with_progress(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    progress: ProgressCallback,
+  ) -> Result<(), FlowyError> {
+    let total = object_value.raw.len() as u64;
+    let reporter = ProgressReporter::new(total, progress);
+    self.put_object(url, object_value).await?;
+    reporter.finish();
+    Ok(())
+  }
+
+  /// The cancellable variant of [Self::put_object]. The upload is dropped as soon as `cancel` is
+  /// cancelled, which aborts the HTTP request, so the object is not created on the server.
+  ///
+  /// # Returns
+  /// - `Ok()`
+  /// - `Err(Error)`: An error occurred during the operation. The code is [ErrorCode::Cancelled]
+  ///   if the upload was cancelled.
+  async fn put_object_cancellable(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    cancel: CancellationToken,
+  ) -> Result<(), FlowyError> {
+    let fut = self.put_object(url, object_value);
+    cancellable(fut, &cancel).await
+  }
+
+  /// Returns true if the storage expires the objects uploaded by [Self::put_object_with_ttl]
+  /// itself, for example with the lifecycle rules of the bucket. [ExpiringObjectStorage] tracks
+  /// the expiries of the services that don't.
+  fn supports_object_ttl(&self) -> bool {
+    false
+  }
+
+  /// Creates a new storage object that is deleted once `ttl` elapsed. Until it's deleted,
+  /// [Self::head_object] reports the expiry in [ObjectMeta::expires_at], and fetching the object
+  /// after the expiry fails with a not found error even if the storage didn't delete it yet.
+  ///
+  /// # Parameters
+  /// - `url`: url of the object to be created.
+  /// - `ttl`: how long the object is kept.
+  ///
+  /// # Returns
+  /// - `Ok()`
+  /// - `Err(Error)`: An error occurred during the operation, or the storage can't expire objects.
+  async fn put_object_with_ttl(
+    &self,
+    _url: String,
+    _object_value: ObjectValue,
+    _ttl: Duration,
+  ) -> Result<(), FlowyError> {
+    Err(FlowyError::not_support().with_context("expiring objects is not supported by the storage"))
+  }
+
+  /// Creates a storage object unless there is already one at the url, so two clients creating
+  /// the same object don't overwrite each other. Implementations backed by HTTP should send an
+  /// `If-None-Match: *` header.
+  ///
+  /// The default implementation emulates the precondition with [Self::object_exists] then
+  /// [Self::put_object]. It's not atomic: an object created by another client between the two
+  /// requests is overwritten.
+  ///
+  /// # Returns
+  /// - `Ok()`: The object was created.
+  /// - `Err(Error)`: An error occurred during the operation. Its kind is
+  ///   [StorageErrorKind::PreconditionFailed] if there is already an object at the url.
+  async fn put_object_if_absent(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+  ) -> Result<(), FlowyError> {
+    if self.object_exists(url.clone()).await? {
+      return Err(precondition_failed(&url, "it already exists"));
+    }
+    self.put_object(url, object_value).await
+  }
+
+  /// Replaces a storage object only if it's still the one identified by `etag`, for optimistic
+  /// concurrency: the callers read the object along with its ETag, see [ObjectMeta::etag] or
+  /// [Self::get_object_if_modified], and their write fails if another client changed it since.
+  /// Implementations backed by HTTP should send an `If-Match` header, see
+  /// [if_match_header_value].
+  ///
+  /// The default implementation emulates the precondition with [Self::head_object] then
+  /// [Self::put_object]. It's not atomic: a change made by another client between the two
+  /// requests is overwritten.
+  ///
+  /// # Returns
+  /// - `Ok()`: The object was replaced.
+  /// - `Err(Error)`: An error occurred during the operation. Its kind is
+  ///   [StorageErrorKind::PreconditionFailed] if the object changed or doesn't exist.
+  async fn put_object_if_match(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    etag: String,
+  ) -> Result<(), FlowyError> {
+    let current = match self.head_object(url.clone()).await {
+      Ok(ObjectMeta {
+        etag: Some(etag), ..
+      }) => Some(etag),
+      // The ETag of a service that doesn't report one is the one of its content.
+      Ok(_) => {
+        let value = self.get_object(url.clone()).await?.decompress()?;
+        Some(content_etag(&value.raw))
+      },
+      Err(err) if err.is_record_not_found() => None,
+      Err(err) => return Err(err),
+    };
+    check_if_match(&url, current.as_deref(), &etag)?;
+    self.put_object(url, object_value).await
+  }
+
+  /// Creates a storage object at the url of `object_id`, and decides what happens to the object
+  /// already at that url with the `policy`. Built on [Self::put_object_if_absent], so
+  /// [OverwritePolicy::Skip] and [OverwritePolicy::Rename] are only as atomic as it is.
+  ///
+  /// # Parameters
+  /// - `object_id`: the identity of the object, see [Self::get_object_url].
+  /// - `policy`: what to do if there is already an object at the url.
+  ///
+  /// # Returns
+  /// - `Ok(PutOutcome)`: What was done, with the url of the object the caller should use.
+  /// - `Err(Error)`: An error occurred during the operation. Its kind is
+  ///   [StorageErrorKind::PreconditionFailed] if no free name was found for
+  ///   [OverwritePolicy::Rename].
+  async fn put_object_with_policy(
+    &self,
+    object_id: ObjectIdentity,
+    object_value: ObjectValue,
+    policy: OverwritePolicy,
+  ) -> Result<PutOutcome, FlowyError> {
+    put_object_with_policy(self, object_id, object_value, policy).await
+  }
+
+  /// Uploads multiple objects, a failed upload doesn't stop the other ones. The default
+  /// implementation uploads them with [upload_many] with at most [DEFAULT_UPLOAD_CONCURRENCY]
+  /// uploads in flight.
+  ///
+  /// # Returns
+  /// - `Ok(Vec<Result>)`: The url of each uploaded object or its error, in the same order as
+  ///   `objects`.
+  /// - `Err(Error)`: The whole batch failed.
+  async fn put_objects(
+    &self,
+    objects: Vec<StorageObject>,
+  ) -> Result<Vec<Result<String, FlowyError>>, FlowyError> {
+    Ok(
+      upload_many(
+        self,
+        objects,
+        DEFAULT_UPLOAD_CONCURRENCY,
+        CancellationToken::new(),
+      )
+      .await,
+    )
+  }
+
+  /// Uploads multiple objects that only make sense together, like a document and its images:
+  /// either all of them are stored or none of them. The default implementation uploads them
+  /// with at most [DEFAULT_UPLOAD_CONCURRENCY] uploads in flight, each one with
+  /// [Self::put_object_if_absent], and deletes the objects it wrote once an upload fails. It's
+  /// not atomic, the other clients can see the objects before they are rolled back.
+  ///
+  /// # Returns
+  /// - `Ok(Vec<String>)`: The url of each object, in the same order as `objects`.
+  /// - `Err(Error)`: The first upload that failed, the objects written by the batch are deleted.
+  async fn put_objects_transactional(
+    &self,
+    objects: Vec<StorageObject>,
+  ) -> Result<Vec<String>, FlowyError> {
+    put_objects_transactional(self, objects, DEFAULT_UPLOAD_CONCURRENCY).await
+  }
+
+  /// Uploads the chunks received on the channel as they arrive, for content that is generated
+  /// over time and whose size isn't known in advance. The object is completed once every sender
+  /// is dropped.
+  ///
+  /// The content is uploaded part by part with [put_object_in_parts], the channel is only read
+  /// while a part is filled, so with a bounded channel a slow backend slows the producer down
+  /// instead of the chunks piling up in memory. Services that don't support multipart uploads
+  /// hold the whole content in memory before uploading it.
+  ///
+  /// # Parameters
+  /// - `url`: url of the object to be created.
+  /// - `mime`: mime type of the object.
+  /// - `rx`: the receiving half of the channel the producer sends the chunks on.
+  ///
+  /// # Returns
+  /// - `Ok()`: The object was uploaded.
+  /// - `Err(Error)`: An error occurred during the operation, the multipart upload is aborted.
+  async fn put_object_from_channel(
+    &self,
+    url: String,
+    mime: Mime,
+    rx: tokio::sync::mpsc::Receiver<Bytes>,
+  ) -> Result<(), FlowyError> {
+    let object = ObjectStream {
+      content_length: 0,
+      mime,
+      stream: channel_stream(rx),
+    };
+    put_object_in_parts(
+      self,
+      url,
+      object,
+      storage_config().multipart_part_size,
+      RetryPolicy::default(),
+    )
+    .await
+  }
+
+  /// Deletes a storage object by its URL.
+  ///
+  /// # Parameters
+  /// - `url`: url of the object to be deleted.
+  ///
+  /// # Returns
+  /// - `Ok()`
+  /// - `Err(Error)`: An error occurred during the operation.
+  async fn delete_object(&self, url: String) -> Result<(), FlowyError>;
+
+  /// Deletes multiple storage objects. Implementations should use a bulk delete API when the
+  /// backend has one. The default implementation calls [Self::delete_object] for each url with
+  /// at most [DEFAULT_DELETE_CONCURRENCY] requests in flight.
+  ///
+  /// # Parameters
+  /// - `urls`: urls of the objects to be deleted.
+  ///
+  /// # Returns
+  /// - `Ok(Vec<Result>)`: The result of each deletion, in the same order as `urls`.
+  /// - `Err(Error)`: The whole batch failed, for example because the bulk delete request failed.
+  async fn delete_objects(
+    &self,
+    urls: Vec<String>,
+  ) -> Result<Vec<Result<(), FlowyError>>, FlowyError> {
+    let deletions = urls
+      .into_iter()
+      .map(|url| self.delete_object(url))
+      .collect::<Vec<_>>();
+    Ok(buffered_deletions(deletions, DEFAULT_DELETE_CONCURRENCY).await)
+  }
+
+  /// Deletes every object of a workspace, to reclaim its storage once the workspace is deleted.
+  /// Implementations should delete the prefix of the workspace at once when the backend can,
+  /// including the objects in the trash. The default implementation lists the objects with
+  /// [Self::list_objects] and deletes them with [Self::delete_objects], the objects in the trash
+  /// are left to [Self::purge_trash].
+  ///
+  /// It can be called again when it was interrupted or some deletions failed, only the objects
+  /// left are deleted.
+  ///
+  /// # Parameters
+  /// - `workspace_id`: the workspace whose objects are deleted.
+  ///
+  /// # Returns
+  /// - `Ok(DeleteReport)`: How many objects and bytes were deleted, and the deletions that
+  ///   failed. It's empty if the workspace has no objects.
+  /// - `Err(Error)`: The objects couldn't be listed, or the bulk delete failed.
+  async fn delete_workspace_objects(&self, workspace_id: &str) -> Result<DeleteReport, FlowyError> {
+    delete_listed_objects(self, workspace_id).await
+  }
+
+  /// Lists the objects stored for a workspace. Implementations should follow the continuation
+  /// tokens of a paginated listing and return all the objects, see [list_all_pages].
+  ///
+  /// # Parameters
+  /// - `workspace_id`: the workspace the objects belong to.
+  /// - `prefix`: only the objects whose `file_id` starts with the prefix are returned.
+  ///
+  /// # Returns
+  /// - `Ok(Vec<ObjectMeta>)`: The objects of the workspace.
+  /// - `Err(Error)`: An error occurred during the operation.
+  async fn list_objects(
+    &self,
+    _workspace_id: &str,
+    _prefix: Option<&str>,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    Err(FlowyError::not_support().with_context("listing objects is not supported by the storage"))
+  }
+
+  /// Lists the objects stored for a workspace like [Self::list_objects], with more options. The
+  /// default implementation calls [Self::list_objects], so it can't list the objects in the
+  /// trash.
+  ///
+  /// # Parameters
+  /// - `workspace_id`: the workspace the objects belong to.
+  /// - `options`: the prefix of the objects and whether the trashed objects are returned.
+  ///
+  /// # Returns
+  /// - `Ok(Vec<ObjectMeta>)`: The objects of the workspace.
+  /// - `Err(Error)`: An error occurred during the operation.
+  async fn list_objects_with_options(
+    &self,
+    workspace_id: &str,
+    options: &ListOptions,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    if options.include_trashed {
+      return Err(trash_not_support());
+    }
+    self
+      .list_objects(workspace_id, options.prefix.as_deref())
+      .await
+  }
+
+  /// Fetches a storage object by its URL.
+  ///
+  /// # Parameters
+  /// - `url`: url of the object
+  ///
+  /// # Returns
+  /// - `Ok(File)`: The returned file object.
+  /// - `Err(Error)`: An error occurred during the operation.
+  async fn get_object(&self, url: String) -> Result<ObjectValue, FlowyError>;
+
+  /// Fetches a storage object by its URL along with the name it should be saved under, see
+  /// [suggested_file_name]. The default implementation uses the last segment of the url, services
+  /// whose responses carry a `Content-Disposition` header should override it and use
+  /// [suggested_file_name_from_headers] so the original name of the file is kept.
+  ///
+  /// # Returns
+  /// - `Ok((ObjectValue, Option<String>))`: The object and its file name, if one can be derived.
+  /// - `Err(Error)`: An error occurred during the operation.
+  async fn get_object_named(
+    &self,
+    url: String,
+  ) -> Result<(ObjectValue, Option<String>), FlowyError> {
+    let value = self.get_object(url.clone()).await?;
+    Ok((value, file_name_from_url(&url)))
+  }
+
+  /// Fetches a storage object by its URL as a stream, so the whole content doesn't need to be
+  /// held in memory. The stream yields the decompressed content. The default implementation
+  /// fetches the whole object with [Self::get_object].
+  ///
+  /// # Parameters
+  /// - `url`: url of the object
+  ///
+  /// # Returns
+  /// - `Ok(ObjectStream)`: The content of the object.
+  /// - `Err(Error)`: An error occurred during the operation.
+  async fn get_object_stream(&self, url: String) -> Result<ObjectStream, FlowyError> {
+    Ok(self.get_object(url).await?.decompress()?.into())
+  }
+
+  /// Fetches a storage object by its URL and reports the progress of the download, see
+  /// [download_progress_stream].
+  ///
+  /// The default implementation downloads the object with [Self::get_object_stream], the size of
+  /// the object is the [ContentLength] of the stream. The caches report the completion at once
+  /// when the object is cached.
+  ///
+  /// # Parameters
+  /// - `url`: url of the object
+  /// - `progress`: called with the number of bytes downloaded and the size of the object.
+  ///
+  /// # Returns
+  /// - `Ok(ObjectValue)`: The decompressed content of the object.
+  /// - `Err(Error)`: An error occurred during the operation.
+  async fn get_object_with_progress(
+    &self,
+    url: String,
+    progress: DownloadProgressCallback,
+  ) -> Result<ObjectValue, FlowyError> {
+    let object = self.get_object_stream(url).await?;
+    let total = ContentLength::content_length(&object);
+    let mut stream = download_progress_stream(object.stream, total, progress);
+    let mut raw = Vec::with_capacity(total.unwrap_or_default() as usize);
+    while let Some(chunk) = stream.next().await {
+      raw.extend_from_slice(&chunk?);
+    }
+    Ok(ObjectValue {
+      raw: raw.into(),
+      mime: object.mime,
+      content_encoding: None,
+    })
+  }
+
+  /// Fetches a storage object by its URL as a stream like [Self::get_object_stream], along with
+  /// its metadata, so the consumers like a video player can start with the first chunks. A
+  /// failure in the middle of the transfer is the last item of the stream, see [end_on_error].
+  ///
+  /// The default implementation takes the metadata from the [ObjectStream] without another
+  /// request, so only the url, the `file_id`, the size and the mime type are known. Services
+  /// that get more from the headers of the response, like the ETag, should override it.
+  ///
+  /// # Parameters
+  /// - `url`: url of the object
+  ///
+  /// # Returns
+  /// - `Ok((ObjectMeta, ObjectByteStream))`: The metadata of the object and its content.
+  /// - `Err(Error)`: An error occurred before the transfer started.
+  async fn get_object_stream_with_meta(
+    &self,
+    url: String,
+  ) -> Result<(ObjectMeta, ObjectByteStream), FlowyError> {
+    let object = self.get_object_stream(url.clone()).await?;
+    let meta = ObjectMeta {
+      file_id: file_id_from_url(&url).unwrap_or_default().to_string(),
+      url,
+      size: object.content_length,
+      mime: object.mime,
+      trashed_at: None,
+      expires_at: None,
+      etag: None,
+      last_modified: None,
+    };
+    Ok((meta, end_on_error(object.stream)))
+  }
+
+  /// Downloads an object straight to a file with [Self::get_object_stream], see
+  /// [write_stream_to_file].
+  ///
+  /// # Parameters
+  /// - `url`: url of the object
+  /// - `dest`: the path of the file. An existing file is replaced once the download completes.
+  ///
+  /// # Returns
+  /// - `Ok(u64)`: The number of bytes written.
+  /// - `Err(Error)`: An error occurred during the operation, `dest` is left untouched.
+  #[cfg(not(target_arch = "wasm32"))]
+  async fn download_to_file(&self, url: String, dest: &Path) -> Result<u64, FlowyError> {
+    let dest = dest.to_path_buf();
+    write_stream_to_file(self.get_object_stream(url).await?, &dest).await
+  }
+
+  /// Checks whether the stored object has the content of a local file without downloading it,
+  /// so a sync can skip the uploads that would change nothing. The default implementation
+  /// compares the size of the object from [Self::head_object] first, then its ETag if it's a
+  /// content hash. Otherwise it compares a few bytes at both ends of the object with
+  /// [Self::get_object_range] if ranges are supported.
+  ///
+  /// # Parameters
+  /// - `url`: url of the object
+  /// - `local_path`: the path of the local file.
+  ///
+  /// # Returns
+  /// - `Ok(LocalComparison)`: [LocalComparison::Unknown] if the backend doesn't report enough
+  ///   to tell. A missing object is [LocalComparison::Changed].
+  /// - `Err(Error)`: An error occurred during the operation.
+  #[cfg(not(target_arch = "wasm32"))]
+  async fn is_up_to_date(
+    &self,
+    url: String,
+    local_path: &Path,
+  ) -> Result<LocalComparison, FlowyError> {
+    compare_with_local(self, url, local_path).await
+  }
+
+  /// The cancellable variant of [Self::get_object]. The download is dropped as soon as `cancel`
+  /// is cancelled, which aborts the HTTP request.
+  ///
+  /// # Returns
+  /// - `Ok(File)`: The returned file object.
+  /// - `Err(Error)`: An error occurred during the operation. The code is [ErrorCode::Cancelled]
+  ///   if the download was cancelled.
+  async fn get_object_cancellable(
+    &self,
+    url: String,
+    cancel: CancellationToken,
+  ) -> Result<ObjectValue, FlowyError> {
+    let fut = self.get_object(url);
+    cancellable(fut, &cancel).await
+  }
+
+  /// Fetches a storage object by its URL and checks that its content hash matches the
+  /// `file_id` of the object, which is computed by [object_from_disk] with [content_hash].
+  ///
+  /// # Parameters
+  /// - `url`: url of the object
+  /// - `expected_file_id`: the `file_id` of the [ObjectIdentity] the url was created from.
+  ///
+  /// # Returns
+  /// - `Ok(File)`: The returned file object.
+  /// - `Err(Error)`: An error occurred during the operation. The code is
+  ///   [ErrorCode::ContentHashMismatch] if the content is corrupted or truncated.
+  async fn get_object_verified(
+    &self,
+    url: String,
+    expected_file_id: &str,
+  ) -> Result<ObjectValue, FlowyError> {
+    let value = self.get_object(url).await?.decompress()?;
+    verify_content_hash(&value.raw, expected_file_id)?;
+    Ok(value)
+  }
+
+  /// Fetches a storage object unless its content is still the one identified by `etag`.
+  /// Implementations backed by HTTP should send an `If-None-Match` header, see
+  /// [if_none_match_header_value], and return `None` on a `304 Not Modified`. The default
+  /// implementation downloads the object and compares the ETag of its content, see
+  /// [content_etag], so it saves nothing but lets the callers handle all the services the same
+  /// way.
+  ///
+  /// # Parameters
+  /// - `url`: url of the object
+  /// - `etag`: the ETag returned with the cached content, `None` to always fetch the object.
+  ///
+  /// # Returns
+  /// - `Ok(None)`: The object didn't change, the cached content can be kept.
+  /// - `Ok(Some((ObjectValue, String)))`: The content of the object and its ETag.
+  /// - `Err(Error)`: An error occurred during the operation.
+  async fn get_object_if_modified(
+    &self,
+    url: String,
+    etag: Option<String>,
+  ) -> Result<Option<(ObjectValue, String)>, FlowyError> {
+    let value = self.get_object(url).await?.decompress()?;
+    let current = content_etag(&value.raw);
+    match etag {
+      Some(etag) if etag_matches(&etag, &current) => Ok(None),
+      _ => Ok(Some((value, current))),
+    }
+  }
+
+  /// Returns true if [Self::get_object_range] fetches only the requested bytes. The default
+  /// implementation downloads the whole object, so [download_parallel] falls back to a single
+  /// download for the services that don't override it.
+  fn supports_range_requests(&self) -> bool {
+    false
+  }
+
+  /// Fetches a part of a storage object by its URL. Implementations backed by HTTP should send a
+  /// `Range` header, see [range_header_value]. The default implementation fetches the whole
+  /// object and slices it.
+  ///
+  /// # Parameters
+  /// - `url`: url of the object
+  /// - `start`: the first byte to fetch.
+  /// - `end`: the last byte to fetch, inclusive. `None` fetches until the end of the object.
+  ///
+  /// # Returns
+  /// - `Ok(ObjectValue)`: The requested bytes, with the mime type of the whole object.
+  /// - `Err(Error)`: An error occurred during the operation. The code is
+  ///   [ErrorCode::OutOfBounds] if `start` is beyond the end of the object.
+  async fn get_object_range(
+    &self,
+    url: String,
+    start: u64,
+    end: Option<u64>,
+  ) -> Result<ObjectValue, FlowyError> {
+    slice_object_range(self.get_object(url).await?, start, end)
+  }
+
+  /// Fetches the metadata of a storage object without its content. Implementations backed by
+  /// HTTP should send a `HEAD` request. The default implementation downloads the whole object.
+  ///
+  /// # Parameters
+  /// - `url`: url of the object
+  ///
+  /// # Returns
+  /// - `Ok(ObjectMeta)`: The size and the mime type of the object.
+  /// - `Err(Error)`: An error occurred during the operation. [FlowyError::is_record_not_found]
+  ///   is true if the object doesn't exist.
+  async fn head_object(&self, url: String) -> Result<ObjectMeta, FlowyError> {
+    let value = self.get_object(url.clone()).await?.decompress()?;
+    Ok(ObjectMeta {
+      file_id: file_id_from_url(&url).unwrap_or_default().to_string(),
+      url,
+      size: value.raw.len() as u64,
+      etag: Some(content_etag(&value.raw)),
+      mime: value.mime,
+      trashed_at: None,
+      expires_at: None,
+      last_modified: None,
+    })
+  }
+
+  /// Returns whether the object exists. The default implementation fetches the first byte of the
+  /// object with [Self::get_object_range].
+  ///
+  /// # Parameters
+  /// - `url`: url of the object
+  ///
+  /// # Returns
+  /// - `Ok(true)`: The object exists.
+  /// - `Ok(false)`: The storage reported that the object doesn't exist.
+  /// - `Err(Error)`: The existence of the object couldn't be checked, for example because of a
+  ///   network error.
+  async fn object_exists(&self, url: String) -> Result<bool, FlowyError> {
+    match self.get_object_range(url, 0, Some(0)).await {
+      Ok(_) => Ok(true),
+      // The range is out of bounds only if the object exists but is empty.
+      Err(err) if err.code == ErrorCode::OutOfBounds => Ok(true),
+      Err(err) if err.is_record_not_found() => Ok(false),
+      Err(err) => Err(err),
+    }
+  }
+
+  /// Returns a time-limited URL that downloads the object without authenticating through
+  /// AppFlowy. Implementations should clamp `expires_in` with [clamp_presign_expiration].
+  ///
+  /// # Parameters
+  /// - `url`: url of the object
+  /// - `expires_in`: how long the returned URL stays valid.
+  ///
+  /// # Returns
+  /// - `Ok(String)`: The presigned URL.
+  /// - `Err(Error)`: An error occurred during the operation, or the storage can't presign URLs.
+  async fn presign_get_url(
+    &self,
+    _url: String,
+    _expires_in: Duration,
+  ) -> Result<String, FlowyError> {
+    Err(presign_not_support("download"))
+  }
+
+  /// Returns a time-limited URL that uploads the object with a `PUT` request without
+  /// authenticating through AppFlowy. Implementations should clamp `expires_in` with
+  /// [clamp_presign_expiration].
+  ///
+  /// # Parameters
+  /// - `url`: url of the object to be created.
+  /// - `expires_in`: how long the returned URL stays valid.
+  ///
+  /// # Returns
+  /// - `Ok(String)`: The presigned URL.
+  /// - `Err(Error)`: An error occurred during the operation, or the storage can't presign URLs.
+  async fn presign_put_url(
+    &self,
+    _url: String,
+    _expires_in: Duration,
+  ) -> Result<String, FlowyError> {
+    Err(presign_not_support("upload"))
+  }
+
+  /// Returns true if the service implements [Self::copy_object]. Callers usually don't need to
+  /// check it themselves, [copy_object_or_reupload] falls back to downloading and uploading the
+  /// object for services that can't copy on the server.
+  fn supports_copy_object(&self) -> bool {
+    false
+  }
+
+  /// Asks the storage to copy an object on the server, without transferring its content. The
+  /// copy keeps the mime type of the source object.
+  ///
+  /// # Parameters
+  /// - `src_url`: url of the object to copy
+  /// - `dst_identity`: the identity of the copy, usually the same `file_id` in another workspace.
+  ///
+  /// # Returns
+  /// - `Ok(String)`: The url of the copy.
+  /// - `Err(Error)`: An error occurred during the operation, for example because the caller
+  ///   can't write to the destination workspace, or the storage can't copy objects.
+  async fn copy_object(
+    &self,
+    _src_url: String,
+    _dst_identity: ObjectIdentity,
+  ) -> Result<String, FlowyError> {
+    Err(FlowyError::not_support().with_context("copying objects is not supported by the storage"))
+  }
+
+  /// Moves an object to the url of `dst_identity`, keeping its content and mime type. The
+  /// default implementation copies the object, see [copy_object_or_reupload], and deletes the
+  /// source only once the copy is stored, so a failed move never loses the object. Storages that
+  /// can rename objects should override it.
+  ///
+  /// # Parameters
+  /// - `src_url`: url of the object to move
+  /// - `dst_identity`: the identity of the object at its new url.
+  /// - `overwrite`: whether an object already stored at the new url is replaced.
+  ///
+  /// # Returns
+  /// - `Ok(String)`: The new url of the object.
+  /// - `Err(Error)`: An error occurred during the operation. The error code is
+  ///   [ErrorCode::RecordAlreadyExists] if an object is stored at the new url and `overwrite` is
+  ///   false.
+  async fn move_object(
+    &self,
+    src_url: String,
+    dst_identity: ObjectIdentity,
+    overwrite: bool,
+  ) -> Result<String, FlowyError> {
+    copy_and_delete(self, src_url, dst_identity, overwrite).await
+  }
+
+  /// Moves the object to the trash. A trashed object behaves like a deleted one: it can't be
+  /// fetched and it's not listed, unless [ListOptions::include_trashed] is set, but it keeps
+  /// using storage until it's purged by [Self::purge_trash].
+  ///
+  /// # Parameters
+  /// - `url`: url of the object
+  ///
+  /// # Returns
+  /// - `Ok()`
+  /// - `Err(Error)`: An error occurred during the operation. [FlowyError::is_record_not_found]
+  ///   is true if the object doesn't exist.
+  async fn trash_object(&self, _url: String) -> Result<(), FlowyError> {
+    Err(trash_not_support())
+  }
+
+  /// Puts a trashed object back at its original url. It replaces the object uploaded at the url
+  /// since it was trashed, if any.
+  ///
+  /// # Parameters
+  /// - `url`: the url the object had before it was trashed.
+  ///
+  /// # Returns
+  /// - `Ok()`
+  /// - `Err(Error)`: An error occurred during the operation. [FlowyError::is_record_not_found]
+  ///   is true if the object is not in the trash.
+  async fn restore_object(&self, _url: String) -> Result<(), FlowyError> {
+    Err(trash_not_support())
+  }
 
-#[derive(Clone)]
-pub struct ObjectValue {
-  pub raw: Bytes,
-  pub mime: Mime,
-}
+  /// Permanently deletes the objects that were trashed more than `older_than` ago, in all the
+  /// workspaces.
+  ///
+  /// # Returns
+  /// - `Ok(Vec<String>)`: The original urls of the purged objects.
+  /// - `Err(Error)`: An error occurred during the operation.
+  async fn purge_trash(&self, _older_than: Duration) -> Result<Vec<String>, FlowyError> {
+    Err(trash_not_support())
+  }
 
-#[cfg(target_arch = "wasm32")]
-pub async fn object_from_disk(
-  workspace_id: &str,
-  local_file_path: &str,
-) -> Result<(ObjectIdentity, ObjectValue), FlowyError> {
-  todo!("object_from_disk is not implemented for wasm32")
-}
+  /// Returns true if the service keeps the previous versions of the objects, see
+  /// [Self::list_versions]. [VersionedObjectStorage] emulates it for the services that don't.
+  fn supports_versioning(&self) -> bool {
+    false
+  }
 
-#[cfg(not(target_arch = "wasm32"))]
-pub async fn object_from_disk(
-  workspace_id: &str,
-  local_file_path: &str,
-) -> Result<(ObjectIdentity, ObjectValue), FlowyError> {
-  let ext = Path::new(local_file_path)
-    .extension()
-    .and_then(std::ffi::OsStr::to_str)
-    .unwrap_or("")
-    .to_owned();
-  let mut file = tokio::fs::File::open(local_file_path).await?;
-  let mut content = Vec::new();
-  let n = file.read_to_end(&mut content).await?;
-  info!("read {} bytes from file: {}", n, local_file_path);
-  let mime = mime_guess::from_path(local_file_path).first_or_octet_stream();
-  let hash = fxhash::hash(&content);
-
-  Ok((
-    ObjectIdentity {
-      workspace_id: workspace_id.to_owned(),
-      file_id: hash.to_string(),
-      ext,
-    },
-    ObjectValue {
-      raw: content.into(),
-      mime,
-    },
-  ))
-}
+  /// Lists the versions of an object, the oldest first. The last one is the current version,
+  /// the one returned by [Self::get_object].
+  ///
+  /// # Parameters
+  /// - `url`: url of the object
+  ///
+  /// # Returns
+  /// - `Ok(Vec<VersionMeta>)`: The versions of the object, empty if it was never versioned.
+  /// - `Err(Error)`: An error occurred during the operation, or the storage can't version
+  ///   objects.
+  async fn list_versions(&self, _url: String) -> Result<Vec<VersionMeta>, FlowyError> {
+    Err(versioning_not_support())
+  }
 
-/// Provides a service for object storage.
-///
-/// The trait includes methods for CRUD operations on storage objects.
-pub trait ObjectStorageService: Send + Sync + 'static {
-  /// Creates a new storage object.
+  /// Fetches a version of a storage object.
   ///
   /// # Parameters
-  /// - `url`: url of the object to be created.
+  /// - `url`: url of the object
+  /// - `version_id`: the [VersionMeta::version_id] returned by [Self::list_versions].
+  ///
+  /// # Returns
+  /// - `Ok(ObjectValue)`: The content of the version.
+  /// - `Err(Error)`: An error occurred during the operation. [FlowyError::is_record_not_found]
+  ///   is true if the version doesn't exist.
+  async fn get_object_version(
+    &self,
+    _url: String,
+    _version_id: String,
+  ) -> Result<ObjectValue, FlowyError> {
+    Err(versioning_not_support())
+  }
+
+  /// Makes an old version the current version of the object. The version is uploaded again as a
+  /// new version, so the versions uploaded after it are kept.
+  ///
+  /// # Parameters
+  /// - `url`: url of the object
+  /// - `version_id`: the [VersionMeta::version_id] returned by [Self::list_versions].
   ///
   /// # Returns
   /// - `Ok()`
+  /// - `Err(Error)`: An error occurred during the operation. [FlowyError::is_record_not_found]
+  ///   is true if the version doesn't exist.
+  async fn restore_version(&self, _url: String, _version_id: String) -> Result<(), FlowyError> {
+    Err(versioning_not_support())
+  }
+
+  /// Returns true if the service implements the multipart upload methods. Callers usually don't
+  /// need to check it themselves, [put_object_in_parts] falls back to [Self::put_object] for
+  /// services that don't support multipart uploads.
+  fn supports_multipart(&self) -> bool {
+    false
+  }
+
+  /// Starts a multipart upload of the object.
+  ///
+  /// # Parameters
+  /// - `url`: url of the object to be created.
+  /// - `mime`: mime type of the object.
+  ///
+  /// # Returns
+  /// - `Ok(UploadId)`: The id used to upload the parts of the object.
   /// - `Err(Error)`: An error occurred during the operation.
-  fn get_object_url(&self, object_id: ObjectIdentity) -> FutureResult<String, FlowyError>;
+  async fn initiate_multipart(&self, _url: String, _mime: Mime) -> Result<UploadId, FlowyError> {
+    Err(multipart_not_support())
+  }
 
-  /// Creates a new storage object.
+  /// Uploads one part of a multipart upload. Uploading the same part number again replaces the
+  /// previously uploaded part.
   ///
   /// # Parameters
   /// - `url`: url of the object to be created.
+  /// - `upload_id`: the id returned by [Self::initiate_multipart].
+  /// - `part_number`: the position of the part in the object, starts from 1.
+  /// - `bytes`: the content of the part.
   ///
   /// # Returns
-  /// - `Ok()`
+  /// - `Ok(PartETag)`: Identifies the uploaded part.
   /// - `Err(Error)`: An error occurred during the operation.
-  fn put_object(&self, url: String, object_value: ObjectValue) -> FutureResult<(), FlowyError>;
+  async fn upload_part(
+    &self,
+    _url: String,
+    _upload_id: UploadId,
+    _part_number: u32,
+    _bytes: Bytes,
+  ) -> Result<PartETag, FlowyError> {
+    Err(multipart_not_support())
+  }
 
-  /// Deletes a storage object by its URL.
+  /// Assembles the uploaded parts into the object.
   ///
   /// # Parameters
-  /// - `url`: url of the object to be deleted.
+  /// - `url`: url of the object to be created.
+  /// - `upload_id`: the id returned by [Self::initiate_multipart].
+  /// - `parts`: all the uploaded parts, ordered by part number.
   ///
   /// # Returns
   /// - `Ok()`
   /// - `Err(Error)`: An error occurred during the operation.
-  fn delete_object(&self, url: String) -> FutureResult<(), FlowyError>;
+  async fn complete_multipart(
+    &self,
+    _url: String,
+    _upload_id: UploadId,
+    _parts: Vec<PartETag>,
+  ) -> Result<(), FlowyError> {
+    Err(multipart_not_support())
+  }
 
-  /// Fetches a storage object by its URL.
+  /// Cancels a multipart upload and discards the uploaded parts.
   ///
   /// # Parameters
-  /// - `url`: url of the object
+  /// - `url`: url of the object to be created.
+  /// - `upload_id`: the id returned by [Self::initiate_multipart].
   ///
   /// # Returns
-  /// - `Ok(File)`: The returned file object.
+  /// - `Ok()`
   /// - `Err(Error)`: An error occurred during the operation.
-  fn get_object(&self, url: String) -> FutureResult<ObjectValue, FlowyError>;
+  async fn abort_multipart(&self, _url: String, _upload_id: UploadId) -> Result<(), FlowyError> {
+    Ok(())
+  }
+
+  /// Probes the backend with a cheap request, so that the UI can tell whether uploading will work
+  /// before the user tries. The default implementation checks whether a sentinel object of the
+  /// [HEALTH_CHECK_WORKSPACE_ID] workspace exists with [Self::object_exists]; implementations
+  /// with a cheaper request should override it, measuring it with [probe_health].
+  ///
+  /// # Returns
+  /// - `Ok(HealthStatus)`: Whether the backend is reachable and accepted the credentials, and
+  ///   how long the probe took. A failed probe is reported in the status.
+  /// - `Err(Error)`: The probe couldn't be sent.
+  async fn health_check(&self) -> Result<HealthStatus, FlowyError> {
+    let url = self
+      .get_object_url(ObjectIdentity {
+        workspace_id: HEALTH_CHECK_WORKSPACE_ID.to_string(),
+        file_id: "sentinel".to_string(),
+        ext: "txt".to_string(),
+        hash_algorithm: None,
+        size: None,
+      })
+      .await?;
+    Ok(probe_health(self.object_exists(url)).await)
+  }
+}
+
+fn trash_not_support() -> FlowyError {
+  FlowyError::not_support().with_context("the trash is not supported by the storage")
+}
+
+fn versioning_not_support() -> FlowyError {
+  FlowyError::not_support().with_context("versioning objects is not supported by the storage")
+}
+
+fn multipart_not_support() -> FlowyError {
+  FlowyError::not_support().with_context("multipart upload is not supported by the storage")
 }
 
+/// The storage limits of the user, enforced on the uploads by [PlanEnforcingObjectStorage]. The
+/// workspaces can have their own plan, see [FileStoragePlanProvider].
+#[async_trait]
 pub trait FileStoragePlan: Send + Sync + 'static {
-  fn storage_size(&self) -> FutureResult<u64, FlowyError>;
-  fn maximum_file_size(&self) -> FutureResult<u64, FlowyError>;
+  /// The number of bytes the user stores.
+  async fn storage_size(&self) -> Result<u64, FlowyError>;
+
+  /// The number of bytes the user can store, `None` if there is no limit or if it's unknown, the
+  /// default. [estimate_upload] uses it to tell the remaining quota.
+  async fn storage_limit(&self) -> Result<Option<u64>, FlowyError> {
+    Ok(None)
+  }
+
+  /// The number of bytes the user stores in each workspace. [StorageUsage] computes it from the
+  /// objects of the workspaces. Not supported by default.
+  async fn storage_size_by_workspace(&self) -> Result<HashMap<String, u64>, FlowyError> {
+    Err(FlowyError::not_support().with_context("the storage breakdown is not supported"))
+  }
+
+  /// The number of bytes stored in each of the workspaces, in a single round trip. A workspace
+  /// whose size can't be computed has its own error, the call only fails if none of the sizes
+  /// can be computed. [StorageSizeCache] caches the sizes on top of it.
+  ///
+  /// By default the sizes are taken from [Self::storage_size_by_workspace], a workspace missing
+  /// from the breakdown stores nothing. Backends that can aggregate the size of a set of
+  /// workspaces should override it.
+  async fn storage_sizes(
+    &self,
+    workspace_ids: Vec<String>,
+  ) -> Result<HashMap<String, Result<u64, FlowyError>>, FlowyError> {
+    let sizes = self.storage_size_by_workspace().await?;
+    Ok(
+      workspace_ids
+        .into_iter()
+        .map(|workspace_id| {
+          let size = sizes.get(&workspace_id).copied().unwrap_or_default();
+          (workspace_id, Ok(size))
+        })
+        .collect(),
+    )
+  }
+
+  /// The size of the largest object the user can upload, unless one of the
+  /// [Self::mime_size_limits] applies.
+  async fn maximum_file_size(&self) -> Result<u64, FlowyError>;
+
+  /// The size limits that depend on the mime type of the object. There are none by default, so
+  /// only [Self::maximum_file_size] applies.
+  fn mime_size_limits(&self) -> MimeSizeLimits {
+    MimeSizeLimits::default()
+  }
+
+  /// The types of files the user can upload, every type by default. It's checked before
+  /// anything is uploaded, see [UploadTypePolicy::check].
+  fn upload_type_policy(&self) -> UploadTypePolicy {
+    UploadTypePolicy::default()
+  }
 
-  fn check_upload_object(&self, object: &StorageObject) -> FutureResult<(), FlowyError>;
+  /// Returns an error, usually [ErrorCode::ExcessStorageLimited], if the object can't be
+  /// uploaded. By default the object must be of a type the [Self::upload_type_policy] accepts
+  /// and must fit in the [Self::storage_limit], the error then carries the
+  /// [QuotaExceededDetails].
+  async fn check_upload_object(&self, object: &StorageObject) -> Result<(), FlowyError> {
+    check_storage_quota(self, object).await
+  }
 }
 
 pub struct StorageObject {
   pub workspace_id: String,
+  /// The name given to the constructor after [sanitize_file_name].
   pub file_name: String,
+  /// The name as it was given to the constructor.
+  pub original_file_name: String,
   pub value: ObjectValueSupabase,
 }
 
 pub enum ObjectValueSupabase {
-  File { file_path: String },
-  Bytes { bytes: Bytes, mime: String },
+  File {
+    file_path: String,
+    /// The mime type given to [StorageObject::from_file_with_mime], or the one guessed from the
+    /// extension by the first call of [ObjectValueSupabase::mime_type].
+    mime: OnceLock<String>,
+    /// The size of the file, read by the first call of [StorageObject::content_length].
+    size: OnceLock<u64>,
+  },
+  Bytes {
+    bytes: Bytes,
+    mime: String,
+  },
+  /// Content read from a stream, see [StorageObject::from_reader].
+  Reader {
+    reader: ObjectReader,
+    /// The number of bytes the reader produces, `None` if it's not known up front.
+    content_length: Option<u64>,
+    mime: String,
+  },
 }
 
 impl ObjectValueSupabase {
-  pub fn mime_type(&self) -> String {
+  /// Returns the mime type of the content: the one given by the caller, or the one guessed from
+  /// the extension of the file.
+  ///
+  /// If `sniff` is true and that type is empty or `application/octet-stream`, the type detected
+  /// from the first bytes of the content is preferred, see [sniff_mime]. A file is read for it,
+  /// so callers that trust the type should pass false. The content of a reader can't be peeked
+  /// at, its type is never sniffed.
+  pub fn mime_type(&self, sniff: bool) -> String {
     match self {
-      ObjectValueSupabase::File { file_path } => mime_guess::from_path(file_path)
-        .first_or_octet_stream()
-        .to_string(),
-      ObjectValueSupabase::Bytes { mime, .. } => mime.clone(),
+      ObjectValueSupabase::File {
+        file_path, mime, ..
+      } => {
+        let guess = mime.get_or_init(|| {
+          mime_guess::from_path(file_path)
+            .first_or_octet_stream()
+            .to_string()
+        });
+        if sniff && guess.parse().ok() == Some(mime::APPLICATION_OCTET_STREAM) {
+          if let Some(detected) = read_file_head(file_path).and_then(|head| sniff_mime(&head)) {
+            return detected.to_string();
+          }
+        }
+        guess.clone()
+      },
+      ObjectValueSupabase::Bytes { bytes, mime } => {
+        let is_generic =
+          mime.trim().is_empty() || mime.parse().ok() == Some(mime::APPLICATION_OCTET_STREAM);
+        if sniff && is_generic {
+          if let Some(detected) = sniff_mime(bytes) {
+            return detected.to_string();
+          }
+        }
+        mime.clone()
+      },
+      ObjectValueSupabase::Reader { mime, .. } => mime.clone(),
     }
   }
 }
 
+/// Reads the first [MIME_SNIFF_LEN] bytes of the file, `None` if it can't be read.
+fn read_file_head(file_path: &str) -> Option<Vec<u8>> {
+  use std::io::Read;
+
+  let mut head = Vec::with_capacity(MIME_SNIFF_LEN);
+  std::fs::File::open(file_path)
+    .ok()?
+    .take(MIME_SNIFF_LEN as u64)
+    .read_to_end(&mut head)
+    .ok()?;
+  Some(head)
+}
+
 impl StorageObject {
   /// Creates a `StorageObject` from a file.
   ///
   /// # Parameters
   ///
-  /// * `name`: The name of the storage object.
+  /// * `name`: The name of the storage object, normalized with [sanitize_file_name]. A random
+  ///   id is used if nothing is left of it, the content hash is not known before the upload.
   /// * `file_path`: The file path to the storage object's data.
   ///
   pub fn from_file<T: ToString>(workspace_id: &str, file_name: &str, file_path: T) -> Self {
     Self {
       workspace_id: workspace_id.to_string(),
-      file_name: file_name.to_string(),
+      file_name: sanitize_file_name(file_name).unwrap_or_else(random_file_id),
+      original_file_name: file_name.to_string(),
       value: ObjectValueSupabase::File {
         file_path: file_path.to_string(),
+        mime: OnceLock::new(),
+        size: OnceLock::new(),
       },
     }
   }
 
-  /// Creates a `StorageObject` from bytes.
+  /// Creates a `StorageObject` from a file whose MIME type is already known, so it's not guessed
+  /// from the extension of the file.
+  pub fn from_file_with_mime<T: ToString>(
+    workspace_id: &str,
+    file_name: &str,
+    file_path: T,
+    mime: String,
+  ) -> Self {
+    let mut object = Self::from_file(workspace_id, file_name, file_path);
+    if let ObjectValueSupabase::File { mime: cached, .. } = &mut object.value {
+      *cached = OnceLock::from(mime);
+    }
+    object
+  }
+
+  /// Creates a `StorageObject` from a file after checking that the file can be uploaded.
   ///
   /// # Parameters
   ///
   /// * `name`: The name of the storage object.
+  /// * `file_path`: The file path to the storage object's data.
+  ///
+  /// # Returns
+  ///
+  /// An error if the path doesn't exist, is a directory, is a broken symlink or the file can't be
+  /// opened for reading.
+  pub fn try_from_file<T: ToString>(
+    workspace_id: &str,
+    file_name: &str,
+    file_path: T,
+  ) -> Result<Self, FlowyError> {
+    let file_path = file_path.to_string();
+    check_file_path(&file_path)?;
+    Ok(Self::from_file(workspace_id, file_name, file_path))
+  }
+
+  /// Creates a `StorageObject` from bytes.
+  ///
+  /// # Parameters
+  ///
+  /// * `name`: The name of the storage object, normalized with [sanitize_file_name]. The
+  ///   content hash, the `file_id` of the object, is used if nothing is left of it.
   /// * `bytes`: The byte data of the storage object.
   /// * `mime`: The MIME type of the storage object.
   ///
@@ -167,20 +1707,661 @@ impl StorageObject {
     let bytes = bytes.into();
     Self {
       workspace_id: workspace_id.to_string(),
-      file_name: file_name.to_string(),
+      file_name: sanitize_file_name(file_name).unwrap_or_else(|| content_hash(&bytes)),
+      original_file_name: file_name.to_string(),
       value: ObjectValueSupabase::Bytes { bytes, mime },
     }
   }
 
-  /// Gets the file size of the `StorageObject`.
+  /// Creates a `StorageObject` from a `data:` URI, for example an image inlined by the editor.
+  /// The content and the MIME type are taken from the URI, see [parse_data_uri].
+  ///
+  /// # Returns
+  ///
+  /// An [ErrorCode::InvalidParams] error if the URI is malformed.
+  pub fn from_data_uri(workspace_id: &str, file_name: &str, uri: &str) -> Result<Self, FlowyError> {
+    let (mime, bytes) = parse_data_uri(uri)?;
+    Ok(Self::from_bytes(
+      workspace_id,
+      file_name,
+      bytes,
+      mime.to_string(),
+    ))
+  }
+
+  /// Creates a `StorageObject` whose content is read from a stream, without buffering it in
+  /// memory first. The content can only be uploaded once, see [ObjectReader].
+  ///
+  /// # Parameters
+  ///
+  /// * `name`: The name of the storage object, normalized with [sanitize_file_name]. A random
+  ///   id is used if nothing is left of it, like for the `file_id` of the object.
+  /// * `reader`: The source of the content.
+  /// * `content_length`: The number of bytes the reader produces, `None` if it's unknown. The
+  ///   upload fails if the reader produces a different number of bytes.
+  /// * `mime`: The MIME type of the storage object.
+  ///
+  pub fn from_reader<R>(
+    workspace_id: &str,
+    file_name: &str,
+    reader: R,
+    content_length: Option<u64>,
+    mime: String,
+  ) -> Self
+  where
+    R: AsyncRead + Send + Sync + 'static,
+  {
+    Self {
+      workspace_id: workspace_id.to_string(),
+      file_name: sanitize_file_name(file_name).unwrap_or_else(random_file_id),
+      original_file_name: file_name.to_string(),
+      value: ObjectValueSupabase::Reader {
+        reader: ObjectReader::new(reader),
+        content_length,
+        mime,
+      },
+    }
+  }
+
+  /// Gets the size of the `StorageObject` if it's known before reading the content. The metadata
+  /// of a file is read with `tokio::fs`, so a slow file system doesn't stall the runtime, and
+  /// only by the first call: the size is kept for the following ones. The other sources resolve
+  /// without touching the file system.
   ///
   /// # Returns
   ///
-  /// The file size in bytes.
-  pub fn file_size(&self) -> u64 {
+  /// The size in bytes, `None` for a stream of unknown length, or an error if the file can't be
+  /// read, for example because it was moved or deleted after the object was created.
+  pub async fn content_length(&self) -> Result<Option<u64>, FlowyError> {
     match &self.value {
-      ObjectValueSupabase::File { file_path } => std::fs::metadata(file_path).unwrap().len(),
-      ObjectValueSupabase::Bytes { bytes, .. } => bytes.len() as u64,
+      ObjectValueSupabase::File {
+        file_path, size, ..
+      } => {
+        if let Some(size) = size.get() {
+          return Ok(Some(*size));
+        }
+        let len = file_len(file_path).await?;
+        Ok(Some(*size.get_or_init(|| len)))
+      },
+      value => Ok(value.content_length()),
+    }
+  }
+
+  /// Gets the file size of the `StorageObject`.
+  ///
+  /// # Returns
+  ///
+  /// The file size in bytes, or an error if the file can't be read or if the object is a stream
+  /// of unknown length, see [Self::content_length].
+  pub async fn file_size_async(&self) -> Result<u64, FlowyError> {
+    self.content_length().await?.ok_or_else(|| {
+      FlowyError::new(
+        ErrorCode::InvalidParams,
+        format!("the size of {} is unknown until it's read", self.file_name),
+      )
+    })
+  }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+async fn file_len(file_path: &str) -> Result<u64, FlowyError> {
+  let metadata = tokio::fs::metadata(file_path)
+    .await
+    .map_err(|err| io_error(err, Path::new(file_path)))?;
+  Ok(metadata.len())
+}
+
+/// The browser has no file system, the content of a file can only be handed over as bytes or as
+/// a reader.
+#[cfg(target_arch = "wasm32")]
+async fn file_len(file_path: &str) -> Result<u64, FlowyError> {
+  Err(FlowyError::new(
+    ErrorCode::NotSupportYet,
+    format!("reading the size of {} is not supported on wasm", file_path),
+  ))
+}
+
+fn check_file_path(file_path: &str) -> Result<(), FlowyError> {
+  check_regular_file(Path::new(file_path), std::fs::metadata(file_path))?;
+  std::fs::File::open(file_path).map_err(|err| io_error(err, Path::new(file_path)))?;
+  Ok(())
+}
+
+/// Checks, with the `metadata` of the path, that it's a regular file or a symlink to one, and
+/// returns the metadata. Reading a directory misbehaves on some platforms and reading a FIFO or a
+/// device can block forever, so they are rejected before anything is read.
+pub(crate) fn check_regular_file(
+  file_path: &Path,
+  metadata: std::io::Result<std::fs::Metadata>,
+) -> Result<std::fs::Metadata, FlowyError> {
+  let invalid_file = |msg: String| FlowyError::new(ErrorCode::InvalidParams, msg);
+  let display_path = file_path.display();
+  let metadata = match metadata {
+    Ok(metadata) => metadata,
+    Err(err) => {
+      let is_symlink = std::fs::symlink_metadata(file_path)
+        .map(|metadata| metadata.file_type().is_symlink())
+        .unwrap_or(false);
+      return Err(if is_symlink {
+        invalid_file(format!("{} is a broken symlink", display_path))
+      } else {
+        io_error(err, file_path)
+      });
+    },
+  };
+
+  if metadata.is_dir() {
+    return Err(invalid_file(format!("{} is a directory", display_path)));
+  }
+  if !metadata.is_file() {
+    return Err(invalid_file(format!(
+      "{} is not a regular file",
+      display_path
+    )));
+  }
+  Ok(metadata)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+
+  use super::*;
+
+  #[tokio::test]
+  async fn file_size_of_missing_file_test() {
+    let file_path = std::env::temp_dir().join(format!("missing-{}.txt", content_hash(b"missing")));
+    let object = StorageObject::from_file("workspace", "missing.txt", file_path.display());
+    let err = object.file_size_async().await.unwrap_err();
+    assert!(err.is_storage_kind(StorageErrorKind::NotFound));
+    assert!(err.msg.contains(&file_path.display().to_string()));
+  }
+
+  #[tokio::test]
+  async fn file_mime_and_size_are_memoized_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let file_path = dir.path().join("a.bin");
+    std::fs::write(&file_path, "hello").unwrap();
+    let object = StorageObject::from_file_with_mime(
+      "workspace",
+      "a.bin",
+      file_path.display(),
+      "text/plain".to_string(),
+    );
+    assert_eq!(object.value.mime_type(true), "text/plain");
+    assert_eq!(object.file_size_async().await.unwrap(), 5);
+
+    std::fs::write(&file_path, "hello world").unwrap();
+    assert_eq!(object.file_size_async().await.unwrap(), 5);
+  }
+
+  #[tokio::test]
+  async fn try_from_file_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let file_path = dir.path().join("a.txt");
+    std::fs::write(&file_path, b"hello").unwrap();
+    let object = StorageObject::try_from_file("workspace", "a.txt", file_path.display()).unwrap();
+    assert_eq!(object.file_size_async().await.unwrap(), 5);
+
+    let err = StorageObject::try_from_file("workspace", "dir", dir.path().display())
+      .err()
+      .unwrap();
+    assert!(err.msg.contains("is a directory"));
+
+    let missing_path = dir.path().join("missing.txt");
+    let err = StorageObject::try_from_file("workspace", "missing.txt", missing_path.display())
+      .err()
+      .unwrap();
+    assert!(err.msg.contains("doesn't exist"));
+  }
+
+  #[tokio::test]
+  async fn ext_from_sniffed_mime_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let file_path = dir.path().join("image");
+    std::fs::write(&file_path, b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR").unwrap();
+    let file_path = file_path.display().to_string();
+
+    let (identity, value) = object_from_disk("workspace", &file_path, true, None)
+      .await
+      .unwrap();
+    assert_eq!(value.mime, mime::IMAGE_PNG);
+    assert_eq!(identity.ext, "png");
+
+    // Without sniffing the type is unknown, the extension stays empty.
+    let (identity, _) = object_from_disk("workspace", &file_path, false, None)
+      .await
+      .unwrap();
+    assert_eq!(identity.ext, "");
+  }
+
+  #[test]
+  fn sniffed_mime_type_test() {
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR";
+    let object = StorageObject::from_bytes("workspace", "a", PNG.to_vec(), "".into());
+    assert_eq!(object.value.mime_type(true), "image/png");
+    assert_eq!(object.value.mime_type(false), "");
+
+    // A type given by the caller is trusted unless it's generic.
+    let object = StorageObject::from_bytes("workspace", "a", PNG.to_vec(), "image/gif".into());
+    assert_eq!(object.value.mime_type(true), "image/gif");
+
+    let dir = tempfile::tempdir().unwrap();
+    let file_path = dir.path().join("image");
+    std::fs::write(&file_path, PNG).unwrap();
+    let object = StorageObject::from_file("workspace", "image", file_path.display());
+    assert_eq!(object.value.mime_type(true), "image/png");
+    assert_eq!(object.value.mime_type(false), "application/octet-stream");
+  }
+
+  #[tokio::test]
+  async fn from_data_uri_test() {
+    let object =
+      StorageObject::from_data_uri("workspace", "image", "data:image/png;base64,iVBORw0KGgo=")
+        .unwrap();
+    assert_eq!(object.value.mime_type(true), "image/png");
+    let (identity, value) = crate::upload::read_storage_object(&object).await.unwrap();
+    assert_eq!(identity.ext, "png");
+    assert_eq!(value.raw.as_ref(), b"\x89PNG\r\n\x1a\n");
+
+    assert!(StorageObject::from_data_uri("workspace", "image", "data:image/png").is_err());
+  }
+
+  #[test]
+  fn sanitized_file_name_test() {
+    let object = StorageObject::from_bytes("workspace", "../a/b.txt", "hello", "text/plain".into());
+    assert_eq!(object.file_name, "b.txt");
+    assert_eq!(object.original_file_name, "../a/b.txt");
+
+    // Nothing is left of the name, the file_id of the object is used instead.
+    let object = StorageObject::from_bytes("workspace", "..", "hello", "text/plain".into());
+    assert_eq!(object.file_name, content_hash(b"hello"));
+    assert_eq!(object.original_file_name, "..");
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn try_from_broken_symlink_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let link_path = dir.path().join("link.txt");
+    std::os::unix::fs::symlink(dir.path().join("missing.txt"), &link_path).unwrap();
+    let err = StorageObject::try_from_file("workspace", "link.txt", link_path.display())
+      .err()
+      .unwrap();
+    assert!(err.msg.contains("broken symlink"));
+  }
+
+  #[tokio::test]
+  async fn object_from_disk_rejects_directory_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir_path = dir.path().display().to_string();
+    let err = object_from_disk("workspace", &dir_path, false, None)
+      .await
+      .err()
+      .unwrap();
+    assert_eq!(err.code, ErrorCode::InvalidParams);
+    assert!(err.msg.contains("is a directory"));
+  }
+
+  #[cfg(unix)]
+  #[tokio::test]
+  async fn object_from_disk_rejects_broken_symlink_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let link_path = dir.path().join("link.txt");
+    std::os::unix::fs::symlink(dir.path().join("missing.txt"), &link_path).unwrap();
+    let err = object_from_disk("workspace", &link_path.display().to_string(), false, None)
+      .await
+      .err()
+      .unwrap();
+    assert_eq!(err.code, ErrorCode::InvalidParams);
+    assert!(err.msg.contains("broken symlink"));
+
+    // A symlink to a directory is rejected like the directory.
+    let link_path = dir.path().join("dir-link");
+    std::os::unix::fs::symlink(dir.path(), &link_path).unwrap();
+    let err = crate::object_stream_from_disk("workspace", &link_path.display().to_string(), 64)
+      .await
+      .err()
+      .unwrap();
+    assert!(err.msg.contains("is a directory"));
+  }
+
+  #[tokio::test]
+  async fn object_from_disk_max_bytes_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let file_path = dir.path().join("a.txt");
+    std::fs::write(&file_path, b"0123456789").unwrap();
+    let file_path = file_path.display().to_string();
+
+    let err = object_from_disk("workspace", &file_path, false, Some(4))
+      .await
+      .err()
+      .unwrap();
+    assert_eq!(err.code, ErrorCode::FileTooLarge);
+    assert!(err.msg.contains("10 bytes"));
+    assert!(err.msg.contains("limit of 4 bytes"));
+
+    let (_, value) = object_from_disk("workspace", &file_path, false, Some(10))
+      .await
+      .unwrap();
+    assert_eq!(value.raw.len(), 10);
+  }
+
+  #[tokio::test]
+  async fn object_from_disk_single_pass_hash_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let file_path = dir.path().join("a.bin");
+    let content = (0..5 * 1024 * 1024 + 3u32)
+      .map(|i| (i % 251) as u8)
+      .collect::<Vec<u8>>();
+    std::fs::write(&file_path, &content).unwrap();
+
+    let mut file = tokio::fs::File::open(&file_path).await.unwrap();
+    let hasher = copy_and_hash(
+      &mut file,
+      &mut tokio::io::sink(),
+      ContentHasher::new(content.len() as u64),
+      64 * 1024 + 5,
+    )
+    .await
+    .unwrap();
+    assert!(hasher.is_complete());
+    assert_eq!(hasher.finish(), fxhash::hash(&content).to_string());
+
+    let file_path = file_path.display().to_string();
+    let (identity, value) = object_from_disk("workspace", &file_path, false, None)
+      .await
+      .unwrap();
+    assert_eq!(identity.file_id, content_hash(&content));
+    assert_eq!(
+      identity.hash_algorithm.as_deref(),
+      Some(DEFAULT_HASH_ALGORITHM)
+    );
+    assert_eq!(value.raw.as_ref(), content.as_slice());
+  }
+
+  #[tokio::test]
+  async fn object_from_bytes_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let content = b"\x89PNG\r\n\x1a\n not really an image".to_vec();
+    for sniff_mime in [true, false] {
+      for file_name in ["image.png", "image", "notes.txt"] {
+        let file_path = dir.path().join(file_name);
+        std::fs::write(&file_path, &content).unwrap();
+        let (disk_identity, disk_value) =
+          object_from_disk("workspace", &file_path, sniff_mime, None)
+            .await
+            .unwrap();
+        let (identity, value) =
+          object_from_bytes("workspace", file_name, content.clone(), sniff_mime, None);
+        assert_eq!(identity.workspace_id, disk_identity.workspace_id);
+        assert_eq!(identity.file_id, disk_identity.file_id);
+        assert_eq!(
+          identity.ext, disk_identity.ext,
+          "{} {}",
+          file_name, sniff_mime
+        );
+        assert_eq!(identity.hash_algorithm, disk_identity.hash_algorithm);
+        assert_eq!(value.mime, disk_value.mime);
+        assert_eq!(value.raw, disk_value.raw);
+      }
+    }
+    // Without sniffing, the content of a name without extension is not looked at.
+    let (_, value) = object_from_bytes("workspace", "image", content.clone(), false, None);
+    assert_eq!(value.mime, mime::APPLICATION_OCTET_STREAM);
+
+    // The given mime type is kept, and gives the extension when the file name has none.
+    let (identity, value) = object_from_bytes(
+      "workspace",
+      "pasted",
+      b"hello".to_vec(),
+      true,
+      Some(mime::TEXT_PLAIN),
+    );
+    assert_eq!(identity.ext, "txt");
+    assert_eq!(value.mime, mime::TEXT_PLAIN);
+    assert_eq!(identity.file_id, content_hash(b"hello"));
+  }
+
+  #[test]
+  fn file_size_of_bytes_test() {
+    let object =
+      StorageObject::from_bytes("workspace", "a.txt", vec![1, 2, 3], "text/plain".into());
+    // The size of bytes is known without waiting.
+    let size = futures::FutureExt::now_or_never(object.file_size_async()).unwrap();
+    assert_eq!(size.unwrap(), 3);
+  }
+
+  /// Holds `1.png` and an empty `2.txt` in the `w1` workspace.
+  async fn defaults_only_storage() -> DefaultsOnly {
+    let storage = DefaultsOnly::default();
+    for (file_name, content) in [("1.png", "png"), ("2.txt", "")] {
+      storage
+        .put_object(
+          format!("memory://w1/{}", file_name),
+          memory_object_value(file_name, content),
+        )
+        .await
+        .unwrap();
+    }
+    storage
+  }
+
+  #[tokio::test]
+  async fn object_exists_test() {
+    let storage = defaults_only_storage().await;
+    let exists = |url: &str| storage.object_exists(url.to_string());
+    assert!(exists("memory://w1/1.png").await.unwrap());
+    assert!(exists("memory://w1/2.txt").await.unwrap());
+    assert!(!exists("memory://w1/3.png").await.unwrap());
+    storage
+      .0
+      .fail_next(StorageOperation::Get, ErrorCode::ConnectTimeout.into());
+    let err = exists("memory://w1/1.png").await.unwrap_err();
+    assert_eq!(err.code, ErrorCode::ConnectTimeout);
+
+    let meta = storage
+      .head_object("memory://w1/1.png".to_string())
+      .await
+      .unwrap();
+    assert_eq!(meta.file_id, "1");
+    assert_eq!(meta.size, 3);
+    assert_eq!(meta.mime, mime::IMAGE_PNG);
+  }
+
+  #[test]
+  fn file_ext_test() {
+    for (file_name, ext) in [
+      ("report.pdf", "pdf"),
+      ("dir.v2/archive.tar.gz", "gz"),
+      (r"C:\Users\a.b\notes", ""),
+      (r"\\?\C:\Users\a\photo.JPG", "JPG"),
+      (r"\\?\UNC\server\share\clip.mp4", "mp4"),
+      (".env", ""),
+      ("file.", ""),
+      ("caf\u{FFFD}.png", "png"),
+      ("image.p\u{FFFD}g", ""),
+    ] {
+      assert_eq!(file_ext(file_name), ext, "{}", file_name);
+    }
+    assert_eq!(guess_mime(r"\\?\C:\Users\a\photo.png"), mime::IMAGE_PNG);
+  }
+
+  #[cfg(unix)]
+  #[tokio::test]
+  async fn object_from_non_utf8_path_test() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let file_path = dir.path().join(OsStr::from_bytes(b"caf\xe9.png"));
+    std::fs::write(&file_path, b"\x89PNG\r\n\x1a\n").unwrap();
+    let (identity, value) = object_from_disk("w1", &file_path, false, None)
+      .await
+      .unwrap();
+    assert_eq!(identity.ext, "png");
+    assert_eq!(value.mime, mime::IMAGE_PNG);
+
+    // The extension itself isn't valid UTF-8, it's inferred from the content.
+    let file_path = dir.path().join(OsStr::from_bytes(b"image.p\xffg"));
+    std::fs::write(&file_path, b"\x89PNG\r\n\x1a\n").unwrap();
+    let (identity, _) = object_from_disk("w1", &file_path, true, None)
+      .await
+      .unwrap();
+    assert_eq!(identity.ext, "png");
+  }
+
+  #[tokio::test]
+  async fn get_object_stream_with_meta_test() {
+    let (meta, stream) = defaults_only_storage()
+      .await
+      .get_object_stream_with_meta("memory://w1/1.png".to_string())
+      .await
+      .unwrap();
+    assert_eq!((meta.file_id.as_str(), meta.size), ("1", 3));
+    assert_eq!(meta.mime, mime::IMAGE_PNG);
+    let chunks = stream.collect::<Vec<_>>().await;
+    assert_eq!(chunks[0].as_ref().unwrap().as_ref(), b"png");
+
+    let storage = InMemoryObjectStorage::new();
+    let url = "memory://w1/1.txt".to_string();
+    storage
+      .put_object(url.clone(), memory_object_value("1.txt", "hello"))
+      .await
+      .unwrap();
+    let (meta, _) = storage.get_object_stream_with_meta(url).await.unwrap();
+    assert_eq!(meta.etag, Some(content_etag(b"hello")));
+    assert!(meta.last_modified.is_some());
+
+    // Nothing is yielded after an error.
+    let failing = futures::stream::iter([
+      Ok(Bytes::from_static(b"a")),
+      Err(FlowyError::new(ErrorCode::ConnectClose, "closed")),
+      Ok(Bytes::from_static(b"b")),
+    ]);
+    let chunks = end_on_error(Box::pin(failing)).collect::<Vec<_>>().await;
+    assert_eq!(chunks.len(), 2);
+    assert_eq!(
+      chunks[1].as_ref().unwrap_err().code,
+      ErrorCode::ConnectClose
+    );
+  }
+
+  #[tokio::test]
+  async fn object_from_disk_with_file_id_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let file_path = dir.path().join("cover.png");
+    std::fs::write(&file_path, b"\x89PNG\r\n\x1a\n").unwrap();
+    let file_path = file_path.to_str().unwrap();
+
+    let (identity, value) =
+      object_from_disk_with_file_id("w1", file_path, "cover-image", true, None)
+        .await
+        .unwrap();
+    let (hashed_identity, hashed_value) =
+      object_from_disk("w1", file_path, true, None).await.unwrap();
+    assert_eq!(identity.file_id, "cover-image");
+    assert_ne!(hashed_identity.file_id, identity.file_id);
+    assert_eq!(identity.ext, hashed_identity.ext);
+    assert_eq!(value.mime, hashed_value.mime);
+    assert_eq!(value.mime, mime::IMAGE_PNG);
+
+    for file_id in ["", "../cover", "cover.png", "cover image"] {
+      let err = object_from_disk_with_file_id("w1", file_path, file_id, true, None)
+        .await
+        .err()
+        .unwrap();
+      assert_eq!(err.code, ErrorCode::InvalidParams);
     }
   }
+
+  #[tokio::test]
+  async fn put_object_from_channel_test() {
+    let storage = InMemoryObjectStorage::new();
+    let (tx, rx) = tokio::sync::mpsc::channel(1);
+    let producer = tokio::spawn(async move {
+      for chunk in ["a", "bc", "def"] {
+        tx.send(Bytes::from_static(chunk.as_bytes())).await.unwrap();
+      }
+    });
+    let url = "memory://w1/1.txt".to_string();
+    storage
+      .put_object_from_channel(url.clone(), mime::TEXT_PLAIN, rx)
+      .await
+      .unwrap();
+    producer.await.unwrap();
+    assert_eq!(storage.object(&url).unwrap().raw.as_ref(), b"abcdef");
+    assert_eq!(storage.call_count(StorageOperation::CompleteMultipart), 1);
+  }
+
+  #[tokio::test]
+  async fn get_object_with_progress_test() {
+    let inner = Arc::new(InMemoryObjectStorage::new());
+    let url = "memory://w1/1.txt".to_string();
+    inner
+      .put_object(url.clone(), memory_object_value("1.txt", "hello"))
+      .await
+      .unwrap();
+    let storage = CachingObjectStorage::new(inner.clone(), CacheConfig::default());
+
+    type Reports = Arc<parking_lot::Mutex<Vec<(u64, Option<u64>)>>>;
+    let reports = Reports::default();
+    let progress = |reports: &Reports| {
+      let reports = reports.clone();
+      Box::new(move |downloaded, total| reports.lock().push((downloaded, total)))
+        as DownloadProgressCallback
+    };
+    let value = storage
+      .get_object_with_progress(url.clone(), progress(&reports))
+      .await
+      .unwrap();
+    assert_eq!(value.raw.as_ref(), b"hello");
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(reports.lock().first(), Some(&(0, Some(5))));
+    assert_eq!(reports.lock().last(), Some(&(5, Some(5))));
+    assert_eq!(inner.call_count(StorageOperation::Get), 1);
+
+    // The cached object is complete at once.
+    reports.lock().clear();
+    storage
+      .get_object_with_progress(url, progress(&reports))
+      .await
+      .unwrap();
+    assert_eq!(*reports.lock(), vec![(5, Some(5))]);
+    assert_eq!(inner.call_count(StorageOperation::Get), 1);
+  }
+
+  #[tokio::test]
+  async fn object_from_missing_file_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let file_path = dir.path().join("missing.txt");
+    let err = object_from_disk("w1", &file_path, false, None)
+      .await
+      .err()
+      .unwrap();
+    assert!(err.is_storage_kind(StorageErrorKind::NotFound));
+    assert!(err.msg.contains(&file_path.display().to_string()));
+  }
+
+  #[tokio::test]
+  async fn content_length_test() {
+    let value = memory_object_value("1.txt", "hello");
+    assert_eq!(value.content_length(), Some(5));
+    let stream = ObjectStream::from(value);
+    assert_eq!(ContentLength::content_length(&stream), Some(5));
+
+    let object = StorageObject::from_bytes("w1", "1.txt", b"hello".to_vec(), "text/plain".into());
+    assert_eq!(object.value.content_length(), Some(5));
+    let (reader, _writer) = tokio::io::duplex(8);
+    let object = StorageObject::from_reader("w1", "1.bin", reader, None, "text/plain".into());
+    assert_eq!(object.value.content_length(), None);
+
+    let dir = tempfile::tempdir().unwrap();
+    let file_path = dir.path().join("1.txt");
+    std::fs::write(&file_path, b"hello").unwrap();
+    let object = StorageObject::from_file("w1", "1.txt", file_path.display());
+    assert_eq!(object.value.content_length(), None);
+    assert_eq!(object.content_length().await.unwrap(), Some(5));
+    assert_eq!(object.value.content_length(), Some(5));
+  }
 }
@@ -0,0 +1,128 @@
+use std::future::Future;
+use std::time::{Duration, SystemTime};
+
+use mime::Mime;
+
+use flowy_error::FlowyError;
+
+/// Describes an object returned by [crate::ObjectStorageService::list_objects].
+#[derive(Debug, Clone)]
+pub struct ObjectMeta {
+  pub url: String,
+  pub file_id: String,
+  /// The size of the object in bytes.
+  pub size: u64,
+  pub mime: Mime,
+  /// When the object was moved to the trash, `None` if it's not in the trash. See
+  /// [crate::ObjectStorageService::trash_object].
+  pub trashed_at: Option<SystemTime>,
+  /// When the object expires, `None` if it's kept until it's deleted. See
+  /// [crate::ObjectStorageService::put_object_with_ttl].
+  pub expires_at: Option<SystemTime>,
+  /// Identifies the content of the object, it changes whenever the object is replaced. Callers
+  /// can store it with the cached content, see
+  /// [crate::ObjectStorageService::get_object_if_modified].
+  pub etag: Option<String>,
+  /// When the object was last written, `None` if the backend doesn't tell.
+  pub last_modified: Option<SystemTime>,
+}
+
+impl ObjectMeta {
+  /// Returns how long the object is kept, zero if it already expired.
+  pub fn remaining_ttl(&self) -> Option<Duration> {
+    self.expires_at.map(|expires_at| {
+      expires_at
+        .duration_since(SystemTime::now())
+        .unwrap_or(Duration::ZERO)
+    })
+  }
+}
+
+/// The options of [crate::ObjectStorageService::list_objects_with_options].
+#[derive(Debug, Clone, Default)]
+pub struct ListOptions {
+  /// Only the objects whose `file_id` starts with the prefix are returned.
+  pub prefix: Option<String>,
+  /// Also returns the objects in the trash, with their original url.
+  pub include_trashed: bool,
+}
+
+/// One page of a paginated listing.
+#[derive(Debug, Clone, Default)]
+pub struct ObjectListPage {
+  pub objects: Vec<ObjectMeta>,
+  /// The token used to fetch the next page. `None` if this is the last page.
+  pub continuation_token: Option<String>,
+}
+
+/// Fetches the pages one after another, passing the continuation token of each page to the next
+/// call of `fetch_page`, and flattens them. The first page is fetched with `None`.
+///
+/// Implementations of [crate::ObjectStorageService::list_objects] whose backend paginates the
+/// listing can use it to follow the continuation tokens.
+pub async fn list_all_pages<F, Fut>(mut fetch_page: F) -> Result<Vec<ObjectMeta>, FlowyError>
+where
+  F: FnMut(Option<String>) -> Fut,
+  Fut: Future<Output = Result<ObjectListPage, FlowyError>>,
+{
+  let mut objects = vec![];
+  let mut continuation_token = None;
+  loop {
+    let page = fetch_page(continuation_token.take()).await?;
+    objects.extend(page.objects);
+    match page.continuation_token {
+      Some(token) => continuation_token = Some(token),
+      None => return Ok(objects),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn meta(index: usize) -> ObjectMeta {
+    ObjectMeta {
+      url: format!("url-{}", index),
+      file_id: format!("file-{}", index),
+      size: index as u64,
+      mime: mime::APPLICATION_OCTET_STREAM,
+      trashed_at: None,
+      expires_at: None,
+      etag: None,
+      last_modified: None,
+    }
+  }
+
+  #[tokio::test]
+  async fn list_all_pages_follows_continuation_tokens() {
+    let mut tokens = vec![];
+    let objects = list_all_pages(|token| {
+      tokens.push(token.clone());
+      let page = token
+        .map(|token| token.parse::<usize>().unwrap())
+        .unwrap_or(0);
+      async move {
+        Ok(ObjectListPage {
+          objects: (page * 2..page * 2 + 2).map(meta).collect(),
+          continuation_token: (page < 2).then(|| (page + 1).to_string()),
+        })
+      }
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(
+      tokens,
+      vec![None, Some("1".to_string()), Some("2".to_string())]
+    );
+    let file_ids = objects
+      .into_iter()
+      .map(|object| object.file_id)
+      .collect::<Vec<_>>();
+    assert_eq!(
+      file_ids,
+      (0..6).map(|i| format!("file-{}", i)).collect::<Vec<_>>()
+    );
+  }
+}
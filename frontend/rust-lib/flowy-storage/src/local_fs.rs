@@ -0,0 +1,801 @@
+use std::io::SeekFrom;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use url::Url;
+
+use flowy_error::{ErrorCode, FlowyError};
+
+use crate::confine::path_traversal;
+use crate::copy::destination_exists;
+use crate::{
+  atomic_write, atomic_write_with, check_path_in_root, etag_matches, guess_mime, io_error,
+  DeleteReport, ListOptions, ObjectIdentity, ObjectMeta, ObjectStorageService, ObjectValue,
+};
+
+/// An [ObjectStorageService] that stores the objects on the local file system, under
+/// `{root}/{workspace_id}/{file_id}.{ext}`. The urls of the objects are `file://` urls.
+///
+/// Writes go to a temporary file which is renamed into place, so concurrent writes of the same
+/// object never produce a mix of both contents, and a reader never sees a partial object. The
+/// content is stored decompressed and the mime type is guessed from the extension when reading.
+///
+/// Trashed objects are moved to `{root}/{workspace_id}/.trash/`, the modification time of the
+/// file records when it was trashed.
+pub struct LocalFsObjectStorage {
+  root: PathBuf,
+}
+
+impl LocalFsObjectStorage {
+  /// Creates the storage. The `root` must be an absolute path, it's created on the first write.
+  pub fn new(root: impl Into<PathBuf>) -> Self {
+    Self { root: root.into() }
+  }
+
+  pub fn root(&self) -> &Path {
+    &self.root
+  }
+
+  /// Returns the path of the object, checking that it's inside the root, see
+  /// [check_path_in_root].
+  async fn object_path(&self, object_id: &ObjectIdentity) -> Result<PathBuf, FlowyError> {
+    let file_name = if object_id.ext.is_empty() {
+      object_id.file_id.clone()
+    } else {
+      format!("{}.{}", object_id.file_id, object_id.ext)
+    };
+    check_path_component(&object_id.workspace_id)?;
+    check_path_component(&file_name)?;
+    let path = self.root.join(&object_id.workspace_id).join(file_name);
+    check_path_in_root(&self.root, &path).await?;
+    Ok(path)
+  }
+
+  /// Returns the path of the object the url points to, checking that it's inside the root.
+  async fn path_from_url(&self, url: &str) -> Result<PathBuf, FlowyError> {
+    let invalid_url =
+      |msg: &str| FlowyError::new(ErrorCode::InvalidURL, format!("{}: {}", msg, url));
+    let url = Url::parse(url).map_err(|_| invalid_url("invalid url"))?;
+    if url.scheme() != "file" {
+      return Err(invalid_url("not a file url"));
+    }
+    let path = url
+      .to_file_path()
+      .map_err(|_| invalid_url("not a file path"))?;
+    let relative = path
+      .strip_prefix(&self.root)
+      .map_err(|_| path_traversal(&path))?;
+    if relative.components().count() != 2 {
+      return Err(invalid_url("not an object url"));
+    }
+    check_path_in_root(&self.root, &path).await?;
+    Ok(path)
+  }
+}
+
+fn check_path_component(component: &str) -> Result<(), FlowyError> {
+  let path = Path::new(component);
+  let mut components = path.components();
+  match (components.next(), components.next()) {
+    (Some(Component::Normal(_)), None) => Ok(()),
+    _ if path.components().any(|component| {
+      matches!(
+        component,
+        Component::ParentDir | Component::RootDir | Component::Prefix(_)
+      )
+    }) =>
+    {
+      Err(path_traversal(path))
+    },
+    _ => Err(FlowyError::new(
+      ErrorCode::InvalidParams,
+      format!("{:?} is not a valid file name", component),
+    )),
+  }
+}
+
+fn file_url(path: &Path) -> Result<String, FlowyError> {
+  Url::from_file_path(path)
+    .map(|url| url.to_string())
+    .map_err(|_| {
+      FlowyError::new(
+        ErrorCode::InvalidURL,
+        format!("{} is not an absolute path", path.display()),
+      )
+    })
+}
+
+/// The trash of a workspace lives in its directory. Object urls have exactly 2 components below
+/// the root, so a trashed object can't be reached through its url.
+const TRASH_DIR: &str = ".trash";
+
+fn trash_path(path: &Path) -> PathBuf {
+  let file_name = path.file_name().unwrap_or_default();
+  path
+    .parent()
+    .map(|parent| parent.join(TRASH_DIR).join(file_name))
+    .unwrap_or_default()
+}
+
+/// Lists the objects in `dir`, skipping the hidden files. The objects in the trash are listed
+/// with the url they had before they were trashed.
+async fn list_dir(
+  dir: &Path,
+  prefix: &str,
+  trashed: bool,
+  objects: &mut Vec<ObjectMeta>,
+) -> Result<(), FlowyError> {
+  let mut entries = match tokio::fs::read_dir(dir).await {
+    Ok(entries) => entries,
+    Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+    Err(err) => return Err(err.into()),
+  };
+
+  while let Some(entry) = entries.next_entry().await? {
+    let name = entry.file_name().to_string_lossy().into_owned();
+    // Skip the temporary files of the writes in progress and the trash.
+    if name.starts_with('.') || !name.starts_with(prefix) {
+      continue;
+    }
+    let path = entry.path();
+    let url = if trashed {
+      let workspace_dir = dir.parent().unwrap_or(dir);
+      file_url(&workspace_dir.join(&name))?
+    } else {
+      file_url(&path)?
+    };
+    let trashed_at = if trashed {
+      Some(entry.metadata().await?.modified()?)
+    } else {
+      None
+    };
+    match object_meta(path, url).await {
+      Ok(meta) => objects.push(ObjectMeta { trashed_at, ..meta }),
+      Err(err) if err.is_record_not_found() => continue,
+      Err(err) => return Err(err),
+    }
+  }
+  Ok(())
+}
+
+async fn object_meta(path: PathBuf, url: String) -> Result<ObjectMeta, FlowyError> {
+  let metadata = tokio::fs::metadata(&path)
+    .await
+    .map_err(|err| io_error(err, &path))?;
+  if !metadata.is_file() {
+    return Err(FlowyError::record_not_found());
+  }
+  let file_name = path
+    .file_name()
+    .map(|name| name.to_string_lossy().into_owned())
+    .unwrap_or_default();
+  let file_id = file_name.split('.').next().unwrap_or_default().to_string();
+  Ok(ObjectMeta {
+    url,
+    file_id,
+    size: metadata.len(),
+    mime: guess_mime(&file_name),
+    trashed_at: None,
+    expires_at: None,
+    etag: Some(file_etag(&metadata)),
+    last_modified: metadata.modified().ok(),
+  })
+}
+
+/// The ETag of a file is made of its modification time and its size, like the ones of the
+/// static file servers. Writes replace the file, so the modification time changes with every
+/// write.
+fn file_etag(metadata: &std::fs::Metadata) -> String {
+  let modified = metadata
+    .modified()
+    .ok()
+    .and_then(|modified| modified.duration_since(SystemTime::UNIX_EPOCH).ok())
+    .unwrap_or_default();
+  format!("{:x}-{:x}", modified.as_nanos(), metadata.len())
+}
+
+#[async_trait]
+impl ObjectStorageService for LocalFsObjectStorage {
+  async fn get_object_url(&self, object_id: ObjectIdentity) -> Result<String, FlowyError> {
+    let path = self.object_path(&object_id).await?;
+    file_url(&path)
+  }
+
+  async fn put_object(&self, url: String, object_value: ObjectValue) -> Result<(), FlowyError> {
+    let path = self.path_from_url(&url).await?;
+    let value = object_value.decompress()?;
+    atomic_write(&path, &value.raw).await
+  }
+
+  /// Deleting an object that doesn't exist succeeds.
+  async fn delete_object(&self, url: String) -> Result<(), FlowyError> {
+    let path = self.path_from_url(&url).await?;
+    match tokio::fs::remove_file(&path).await {
+      Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(io_error(err, &path)),
+      _ => Ok(()),
+    }
+  }
+
+  /// Removes the directory of the workspace, with its trash and the writes in progress.
+  async fn delete_workspace_objects(&self, workspace_id: &str) -> Result<DeleteReport, FlowyError> {
+    let options = ListOptions {
+      prefix: None,
+      include_trashed: true,
+    };
+    let objects = self
+      .list_objects_with_options(workspace_id, &options)
+      .await?;
+    let dir = self.root.join(workspace_id);
+    match tokio::fs::remove_dir_all(&dir).await {
+      Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(io_error(err, &dir)),
+      _ => Ok(DeleteReport {
+        deleted: objects.len(),
+        freed_bytes: objects.iter().map(|object| object.size).sum(),
+        failed: vec![],
+      }),
+    }
+  }
+
+  async fn list_objects(
+    &self,
+    workspace_id: &str,
+    prefix: Option<&str>,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    let options = ListOptions {
+      prefix: prefix.map(|prefix| prefix.to_string()),
+      include_trashed: false,
+    };
+    self.list_objects_with_options(workspace_id, &options).await
+  }
+
+  async fn list_objects_with_options(
+    &self,
+    workspace_id: &str,
+    options: &ListOptions,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    check_path_component(workspace_id)?;
+    let dir = self.root.join(workspace_id);
+    check_path_in_root(&self.root, &dir).await?;
+    let prefix = options.prefix.clone().unwrap_or_default();
+    let mut objects = vec![];
+    list_dir(&dir, &prefix, false, &mut objects).await?;
+    if options.include_trashed {
+      list_dir(&dir.join(TRASH_DIR), &prefix, true, &mut objects).await?;
+    }
+    objects.sort_by(|a, b| a.url.cmp(&b.url));
+    Ok(objects)
+  }
+
+  async fn get_object(&self, url: String) -> Result<ObjectValue, FlowyError> {
+    let path = self.path_from_url(&url).await?;
+    let raw = tokio::fs::read(&path)
+      .await
+      .map_err(|err| io_error(err, &path))?;
+    Ok(ObjectValue {
+      raw: raw.into(),
+      mime: guess_mime(&path.to_string_lossy()),
+      content_encoding: None,
+    })
+  }
+
+  async fn get_object_if_modified(
+    &self,
+    url: String,
+    etag: Option<String>,
+  ) -> Result<Option<(ObjectValue, String)>, FlowyError> {
+    let path = self.path_from_url(&url).await?;
+    // The metadata and the content are read from the same file, so the ETag matches the
+    // content even if the object is replaced meanwhile.
+    let mut file = tokio::fs::File::open(&path)
+      .await
+      .map_err(|err| io_error(err, &path))?;
+    let metadata = file.metadata().await.map_err(|err| io_error(err, &path))?;
+    let current = file_etag(&metadata);
+    if matches!(etag, Some(etag) if etag_matches(&etag, &current)) {
+      return Ok(None);
+    }
+    let mut raw = vec![];
+    file
+      .read_to_end(&mut raw)
+      .await
+      .map_err(|err| io_error(err, &path))?;
+    let value = ObjectValue {
+      raw: raw.into(),
+      mime: guess_mime(&path.to_string_lossy()),
+      content_encoding: None,
+    };
+    Ok(Some((value, current)))
+  }
+
+  fn supports_range_requests(&self) -> bool {
+    true
+  }
+
+  async fn get_object_range(
+    &self,
+    url: String,
+    start: u64,
+    end: Option<u64>,
+  ) -> Result<ObjectValue, FlowyError> {
+    let path = self.path_from_url(&url).await?;
+    let mut file = tokio::fs::File::open(&path)
+      .await
+      .map_err(|err| io_error(err, &path))?;
+    let len = file
+      .metadata()
+      .await
+      .map_err(|err| io_error(err, &path))?
+      .len();
+    if start >= len {
+      return Err(FlowyError::new(
+        ErrorCode::OutOfBounds,
+        format!("range start {} is out of the object size {}", start, len),
+      ));
+    }
+    let end = end.map(|end| end.min(len - 1)).unwrap_or(len - 1);
+    if end < start {
+      return Err(FlowyError::new(
+        ErrorCode::OutOfBounds,
+        format!("range end {} is before the range start {}", end, start),
+      ));
+    }
+
+    file.seek(SeekFrom::Start(start)).await?;
+    let mut raw = Vec::with_capacity((end - start + 1) as usize);
+    file.take(end - start + 1).read_to_end(&mut raw).await?;
+    Ok(ObjectValue {
+      raw: raw.into(),
+      mime: guess_mime(&path.to_string_lossy()),
+      content_encoding: None,
+    })
+  }
+
+  async fn head_object(&self, url: String) -> Result<ObjectMeta, FlowyError> {
+    let path = self.path_from_url(&url).await?;
+    object_meta(path, url).await
+  }
+
+  async fn object_exists(&self, url: String) -> Result<bool, FlowyError> {
+    let path = self.path_from_url(&url).await?;
+    match tokio::fs::metadata(path).await {
+      Ok(metadata) => Ok(metadata.is_file()),
+      Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(false),
+      Err(err) => Err(err.into()),
+    }
+  }
+
+  async fn trash_object(&self, url: String) -> Result<(), FlowyError> {
+    let path = self.path_from_url(&url).await?;
+    let trash_path = trash_path(&path);
+    check_path_in_root(&self.root, &trash_path).await?;
+    if let Some(parent) = trash_path.parent() {
+      tokio::fs::create_dir_all(parent)
+        .await
+        .map_err(|err| io_error(err, parent))?;
+    }
+    // Record when the object was trashed before moving it, so it can't be purged too early.
+    let file = tokio::fs::OpenOptions::new()
+      .write(true)
+      .open(&path)
+      .await
+      .map_err(|err| io_error(err, &path))?
+      .into_std()
+      .await;
+    tokio::task::spawn_blocking(move || file.set_modified(SystemTime::now()))
+      .await
+      .map_err(|err| FlowyError::internal().with_context(err))??;
+    tokio::fs::rename(&path, &trash_path)
+      .await
+      .map_err(|err| io_error(err, &path))
+  }
+
+  async fn restore_object(&self, url: String) -> Result<(), FlowyError> {
+    let path = self.path_from_url(&url).await?;
+    let trash_path = trash_path(&path);
+    check_path_in_root(&self.root, &trash_path).await?;
+    match tokio::fs::rename(trash_path, &path).await {
+      Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+        Err(FlowyError::record_not_found().with_context(format!("{} is not in the trash", url)))
+      },
+      result => result.map_err(|err| io_error(err, &path)),
+    }
+  }
+
+  async fn purge_trash(&self, older_than: Duration) -> Result<Vec<String>, FlowyError> {
+    let mut workspaces = match tokio::fs::read_dir(&self.root).await {
+      Ok(workspaces) => workspaces,
+      Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+      Err(err) => return Err(err.into()),
+    };
+
+    let now = SystemTime::now();
+    let mut purged = vec![];
+    while let Some(workspace) = workspaces.next_entry().await? {
+      if !workspace.file_type().await?.is_dir() {
+        continue;
+      }
+      let mut trashed = vec![];
+      list_dir(&workspace.path().join(TRASH_DIR), "", true, &mut trashed).await?;
+      for meta in trashed {
+        let expired = meta
+          .trashed_at
+          .and_then(|trashed_at| now.duration_since(trashed_at).ok())
+          .map_or(false, |elapsed| elapsed >= older_than);
+        if !expired {
+          continue;
+        }
+        let file_name = meta.url.rsplit('/').next().unwrap_or_default();
+        let path = workspace.path().join(TRASH_DIR).join(file_name);
+        match tokio::fs::remove_file(&path).await {
+          Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            return Err(io_error(err, &path))
+          },
+          _ => purged.push(meta.url),
+        }
+      }
+    }
+    purged.sort();
+    Ok(purged)
+  }
+
+  fn supports_copy_object(&self) -> bool {
+    true
+  }
+
+  async fn copy_object(
+    &self,
+    src_url: String,
+    dst_identity: ObjectIdentity,
+  ) -> Result<String, FlowyError> {
+    let src_path = self.path_from_url(&src_url).await?;
+    let dst_path = self.object_path(&dst_identity).await?;
+    let dst_url = file_url(&dst_path)?;
+    if src_path == dst_path {
+      return Ok(dst_url);
+    }
+    atomic_write_with(&dst_path, |temp_path| async move {
+      tokio::fs::copy(&src_path, &temp_path)
+        .await
+        .map_err(|err| io_error(err, &src_path))
+    })
+    .await?;
+    Ok(dst_url)
+  }
+
+  /// Renames the file. Without `overwrite`, the file is linked to its new path first, which fails
+  /// if a file is there already, so an existing object is never replaced by a concurrent move.
+  async fn move_object(
+    &self,
+    src_url: String,
+    dst_identity: ObjectIdentity,
+    overwrite: bool,
+  ) -> Result<String, FlowyError> {
+    let src_path = self.path_from_url(&src_url).await?;
+    let dst_path = self.object_path(&dst_identity).await?;
+    let dst_url = file_url(&dst_path)?;
+    if src_path == dst_path {
+      return Ok(dst_url);
+    }
+    if let Some(parent) = dst_path.parent() {
+      tokio::fs::create_dir_all(parent)
+        .await
+        .map_err(|err| io_error(err, parent))?;
+    }
+    if overwrite {
+      tokio::fs::rename(&src_path, &dst_path)
+        .await
+        .map_err(|err| io_error(err, &src_path))?;
+    } else {
+      match tokio::fs::hard_link(&src_path, &dst_path).await {
+        Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+          return Err(destination_exists(&dst_url));
+        },
+        result => result.map_err(|err| io_error(err, &src_path))?,
+      }
+      tokio::fs::remove_file(&src_path)
+        .await
+        .map_err(|err| io_error(err, &src_path))?;
+    }
+    Ok(dst_url)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+
+  use super::*;
+
+  fn identity(workspace_id: &str, file_id: &str) -> ObjectIdentity {
+    ObjectIdentity {
+      workspace_id: workspace_id.to_string(),
+      file_id: file_id.to_string(),
+      ext: "txt".to_string(),
+      hash_algorithm: None,
+      size: None,
+    }
+  }
+
+  fn text(content: &str) -> ObjectValue {
+    ObjectValue {
+      raw: content.as_bytes().to_vec().into(),
+      mime: mime::TEXT_PLAIN,
+      content_encoding: None,
+    }
+  }
+
+  #[tokio::test]
+  async fn local_fs_round_trip_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = LocalFsObjectStorage::new(dir.path());
+    let url = storage.get_object_url(identity("w1", "1")).await.unwrap();
+    assert!(url.starts_with("file://"));
+    assert!(url.ends_with("/w1/1.txt"));
+
+    storage
+      .put_object(url.clone(), text("hello"))
+      .await
+      .unwrap();
+    let value = storage.get_object(url.clone()).await.unwrap();
+    assert_eq!(value.raw.as_ref(), b"hello");
+    assert_eq!(value.mime, mime::TEXT_PLAIN);
+    let range = storage
+      .get_object_range(url.clone(), 1, Some(2))
+      .await
+      .unwrap();
+    assert_eq!(range.raw.as_ref(), b"el");
+    let meta = storage.head_object(url.clone()).await.unwrap();
+    assert_eq!((meta.file_id.as_str(), meta.size), ("1", 5));
+
+    let copy_url = storage
+      .copy_object(url.clone(), identity("w2", "1"))
+      .await
+      .unwrap();
+    assert_eq!(storage.list_objects("w2", None).await.unwrap().len(), 1);
+    assert_eq!(
+      storage.get_object(copy_url).await.unwrap().raw.as_ref(),
+      b"hello"
+    );
+
+    storage.delete_object(url.clone()).await.unwrap();
+    assert!(!storage.object_exists(url.clone()).await.unwrap());
+    let err = storage.get_object(url.clone()).await.err().unwrap();
+    assert!(err.is_record_not_found());
+    storage.delete_object(url).await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn delete_workspace_objects_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = LocalFsObjectStorage::new(dir.path());
+    let url = storage.get_object_url(identity("w1", "1")).await.unwrap();
+    let trashed_url = storage.get_object_url(identity("w1", "2")).await.unwrap();
+    let kept_url = storage.get_object_url(identity("w2", "1")).await.unwrap();
+    storage.put_object(url, text("hello")).await.unwrap();
+    storage
+      .put_object(trashed_url.clone(), text("world!"))
+      .await
+      .unwrap();
+    storage
+      .put_object(kept_url.clone(), text("kept"))
+      .await
+      .unwrap();
+    storage.trash_object(trashed_url).await.unwrap();
+
+    let report = storage.delete_workspace_objects("w1").await.unwrap();
+    assert_eq!((report.deleted, report.freed_bytes), (2, 11));
+    assert!(!dir.path().join("w1").exists());
+    assert!(storage.object_exists(kept_url).await.unwrap());
+
+    let report = storage.delete_workspace_objects("w1").await.unwrap();
+    assert_eq!(report.deleted, 0);
+    let err = storage.delete_workspace_objects("..").await.unwrap_err();
+    assert_eq!(err.code, ErrorCode::PathTraversal);
+  }
+
+  #[tokio::test]
+  async fn move_object_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = LocalFsObjectStorage::new(dir.path());
+    let url = storage.get_object_url(identity("w1", "1")).await.unwrap();
+    let dst_url = storage.get_object_url(identity("w2", "1")).await.unwrap();
+    storage
+      .put_object(url.clone(), text("hello"))
+      .await
+      .unwrap();
+    storage
+      .put_object(dst_url.clone(), text("world"))
+      .await
+      .unwrap();
+
+    let err = storage
+      .move_object(url.clone(), identity("w2", "1"), false)
+      .await
+      .err()
+      .unwrap();
+    assert_eq!(err.code, ErrorCode::RecordAlreadyExists);
+    assert!(storage.object_exists(url.clone()).await.unwrap());
+
+    let moved = storage
+      .move_object(url.clone(), identity("w2", "1"), true)
+      .await
+      .unwrap();
+    assert_eq!(moved, dst_url);
+    assert!(!storage.object_exists(url.clone()).await.unwrap());
+
+    let back = storage
+      .move_object(dst_url.clone(), identity("w1", "1"), false)
+      .await
+      .unwrap();
+    assert_eq!(back, url);
+    let value = storage.get_object(url).await.unwrap();
+    assert_eq!(value.raw.as_ref(), b"hello");
+    assert!(!storage.object_exists(dst_url).await.unwrap());
+  }
+
+  #[tokio::test]
+  async fn get_object_if_modified_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = LocalFsObjectStorage::new(dir.path());
+    let url = storage.get_object_url(identity("w1", "1")).await.unwrap();
+    storage
+      .put_object(url.clone(), text("hello"))
+      .await
+      .unwrap();
+
+    let (value, etag) = storage
+      .get_object_if_modified(url.clone(), None)
+      .await
+      .unwrap()
+      .unwrap();
+    assert_eq!(value.raw.as_ref(), b"hello");
+    let meta = storage.head_object(url.clone()).await.unwrap();
+    assert_eq!(meta.etag.as_deref(), Some(etag.as_str()));
+    assert!(storage
+      .get_object_if_modified(url.clone(), Some(etag.clone()))
+      .await
+      .unwrap()
+      .is_none());
+
+    storage
+      .put_object(url.clone(), text("hello world"))
+      .await
+      .unwrap();
+    let (value, new_etag) = storage
+      .get_object_if_modified(url, Some(etag.clone()))
+      .await
+      .unwrap()
+      .unwrap();
+    assert_eq!(value.raw.as_ref(), b"hello world");
+    assert_ne!(new_etag, etag);
+  }
+
+  #[tokio::test]
+  async fn trash_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = LocalFsObjectStorage::new(dir.path());
+    let url = storage.get_object_url(identity("w1", "1")).await.unwrap();
+    storage
+      .put_object(url.clone(), text("hello"))
+      .await
+      .unwrap();
+
+    storage.trash_object(url.clone()).await.unwrap();
+    assert!(!storage.object_exists(url.clone()).await.unwrap());
+    assert!(storage.list_objects("w1", None).await.unwrap().is_empty());
+    let options = ListOptions {
+      include_trashed: true,
+      ..Default::default()
+    };
+    let objects = storage
+      .list_objects_with_options("w1", &options)
+      .await
+      .unwrap();
+    assert_eq!(objects.len(), 1);
+    assert_eq!(objects[0].url, url);
+    assert!(objects[0].trashed_at.is_some());
+
+    storage.restore_object(url.clone()).await.unwrap();
+    assert_eq!(
+      storage.get_object(url.clone()).await.unwrap().raw.as_ref(),
+      b"hello"
+    );
+    assert!(storage.restore_object(url.clone()).await.is_err());
+
+    storage.trash_object(url.clone()).await.unwrap();
+    let purged = storage
+      .purge_trash(Duration::from_secs(3600))
+      .await
+      .unwrap();
+    assert!(purged.is_empty());
+    let purged = storage.purge_trash(Duration::ZERO).await.unwrap();
+    assert_eq!(purged, vec![url.clone()]);
+    assert!(storage.restore_object(url).await.is_err());
+  }
+
+  #[tokio::test]
+  async fn reject_paths_outside_root_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = LocalFsObjectStorage::new(dir.path().join("root"));
+    let err = storage
+      .get_object_url(identity("..", "1"))
+      .await
+      .unwrap_err();
+    assert_eq!(err.code, ErrorCode::PathTraversal);
+    let err = storage
+      .get_object_url(identity("w1", "../../etc/passwd"))
+      .await
+      .unwrap_err();
+    assert_eq!(err.code, ErrorCode::PathTraversal);
+    let err = storage
+      .get_object_url(identity("w1", "a/b"))
+      .await
+      .unwrap_err();
+    assert_eq!(err.code, ErrorCode::InvalidParams);
+
+    let outside = file_url(&dir.path().join("root/../secret.txt")).unwrap();
+    let err = storage.get_object(outside).await.err().unwrap();
+    assert_eq!(err.code, ErrorCode::PathTraversal);
+    let passwd = format!("{}/w1/../../etc/passwd", file_url(storage.root()).unwrap());
+    let err = storage.put_object(passwd, text("hello")).await.unwrap_err();
+    assert_eq!(err.code, ErrorCode::PathTraversal);
+    let err = storage
+      .get_object("https://host/w1/1.txt".to_string())
+      .await
+      .err()
+      .unwrap();
+    assert_eq!(err.code, ErrorCode::InvalidURL);
+  }
+
+  #[cfg(unix)]
+  #[tokio::test]
+  async fn reject_symlinks_out_of_root_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = LocalFsObjectStorage::new(dir.path().join("root"));
+    let outside = dir.path().join("outside");
+    std::fs::create_dir_all(storage.root()).unwrap();
+    std::fs::create_dir_all(&outside).unwrap();
+    std::fs::write(outside.join("1.txt"), b"secret").unwrap();
+    std::os::unix::fs::symlink(&outside, storage.root().join("w1")).unwrap();
+
+    let err = storage
+      .get_object_url(identity("w1", "1"))
+      .await
+      .unwrap_err();
+    assert_eq!(err.code, ErrorCode::PathTraversal);
+    let url = file_url(&storage.root().join("w1/1.txt")).unwrap();
+    let err = storage.get_object(url.clone()).await.err().unwrap();
+    assert_eq!(err.code, ErrorCode::PathTraversal);
+    let err = storage.put_object(url, text("hello")).await.unwrap_err();
+    assert_eq!(err.code, ErrorCode::PathTraversal);
+    assert_eq!(std::fs::read(outside.join("1.txt")).unwrap(), b"secret");
+    let err = storage.list_objects("w1", None).await.unwrap_err();
+    assert_eq!(err.code, ErrorCode::PathTraversal);
+  }
+
+  #[tokio::test]
+  async fn concurrent_writes_are_atomic_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = Arc::new(LocalFsObjectStorage::new(dir.path()));
+    let url = storage.get_object_url(identity("w1", "1")).await.unwrap();
+    let contents = ["a".repeat(100_000), "b".repeat(100_000)];
+    let writes = contents.iter().map(|content| {
+      let storage = storage.clone();
+      let url = url.clone();
+      let value = text(content);
+      tokio::spawn(async move { storage.put_object(url, value).await })
+    });
+    for write in futures::future::join_all(writes).await {
+      write.unwrap().unwrap();
+    }
+
+    let value = storage.get_object(url).await.unwrap();
+    assert!(contents
+      .iter()
+      .any(|content| value.raw.as_ref() == content.as_bytes()));
+    let files = std::fs::read_dir(dir.path().join("w1")).unwrap().count();
+    assert_eq!(files, 1);
+  }
+}
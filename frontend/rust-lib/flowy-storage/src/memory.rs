@@ -0,0 +1,1019 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use futures::StreamExt;
+use mime::Mime;
+use parking_lot::Mutex;
+
+use flowy_error::{ErrorCode, FlowyError};
+
+use crate::conditional::{check_if_match, precondition_failed};
+use crate::copy::destination_exists;
+use crate::{
+  content_etag, content_hash, etag_matches, guess_mime, slice_object_range, DeleteReport,
+  ListOptions, ObjectByteStream, ObjectIdentity, ObjectMeta, ObjectStorageService, ObjectStream,
+  ObjectValue, PartETag, UploadId,
+};
+
+const URL_SCHEME: &str = "memory://";
+
+/// The operations of [InMemoryObjectStorage] a failure or latency can be injected into with
+/// [InMemoryObjectStorage::fail_next] and [InMemoryObjectStorage::set_latency].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StorageOperation {
+  GetUrl,
+  Put,
+  Delete,
+  List,
+  Get,
+  GetRange,
+  Head,
+  Copy,
+  Move,
+  InitiateMultipart,
+  UploadPart,
+  CompleteMultipart,
+  AbortMultipart,
+  Trash,
+  Restore,
+  PurgeTrash,
+}
+
+/// An [ObjectStorageService] that keeps the objects in memory, for tests. It implements every
+/// method of the trait and is the reference for their expected semantics:
+/// - the url of an object is `memory://{workspace_id}/{file_id}.{ext}`, so it's deterministic.
+/// - objects are stored as they're put, with their `content_encoding`.
+/// - getting or heading a missing object fails with [FlowyError::is_record_not_found], deleting
+///   a missing object succeeds.
+/// - the parts of a multipart upload are only visible once the upload is completed.
+/// - a trashed object is kept aside until it's restored or purged, putting an object at its url
+///   doesn't affect it.
+///
+/// Failures are injected with [Self::fail_next] and latency with [Self::set_latency], so the
+/// error paths and the concurrency of the callers can be tested.
+#[derive(Default)]
+pub struct InMemoryObjectStorage {
+  state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+  objects: HashMap<String, ObjectValue>,
+  modified: HashMap<String, SystemTime>,
+  trash: HashMap<String, (ObjectValue, SystemTime)>,
+  uploads: HashMap<UploadId, Upload>,
+  /// `None` lets a call succeed, see [InMemoryObjectStorage::fail_after].
+  failures: HashMap<StorageOperation, VecDeque<Option<FlowyError>>>,
+  calls: HashMap<StorageOperation, usize>,
+  latencies: HashMap<StorageOperation, Duration>,
+  in_flight: HashMap<StorageOperation, usize>,
+  max_in_flight: HashMap<StorageOperation, usize>,
+  interruptions: VecDeque<(usize, FlowyError)>,
+  stream_chunking: Option<(usize, Duration)>,
+  range_requests_disabled: bool,
+  next_upload_id: u64,
+}
+
+/// Ends a call of an operation when it's dropped, so the calls that are cancelled while they're
+/// delayed aren't counted as in flight anymore.
+struct InFlight<'a> {
+  state: &'a Mutex<State>,
+  op: StorageOperation,
+}
+
+impl Drop for InFlight<'_> {
+  fn drop(&mut self) {
+    if let Some(in_flight) = self.state.lock().in_flight.get_mut(&self.op) {
+      *in_flight -= 1;
+    }
+  }
+}
+
+struct Upload {
+  url: String,
+  mime: Mime,
+  parts: BTreeMap<u32, (String, Bytes)>,
+}
+
+impl State {
+  /// Counts the call and returns the latency of the operation, if any.
+  fn begin(&mut self, op: StorageOperation) -> Option<Duration> {
+    *self.calls.entry(op).or_default() += 1;
+    let in_flight = self.in_flight.entry(op).or_default();
+    *in_flight += 1;
+    let in_flight = *in_flight;
+    let max = self.max_in_flight.entry(op).or_default();
+    *max = (*max).max(in_flight);
+    self.latencies.get(&op).copied()
+  }
+
+  /// Returns the next failure injected into the operation, if any.
+  fn next_failure(&mut self, op: StorageOperation) -> Result<(), FlowyError> {
+    match self
+      .failures
+      .get_mut(&op)
+      .and_then(|errors| errors.pop_front())
+      .flatten()
+    {
+      Some(err) => Err(err),
+      None => Ok(()),
+    }
+  }
+
+  fn insert(&mut self, url: String, value: ObjectValue) {
+    self.modified.insert(url.clone(), SystemTime::now());
+    self.objects.insert(url, value);
+  }
+
+  fn remove(&mut self, url: &str) -> Option<ObjectValue> {
+    self.modified.remove(url);
+    self.objects.remove(url)
+  }
+
+  fn meta(&self, url: &str) -> Result<ObjectMeta, FlowyError> {
+    let mut meta = object_meta(url, self.object(url)?)?;
+    meta.last_modified = self.modified.get(url).copied();
+    Ok(meta)
+  }
+
+  fn object(&self, url: &str) -> Result<&ObjectValue, FlowyError> {
+    parse_url(url)?;
+    self
+      .objects
+      .get(url)
+      .ok_or_else(|| FlowyError::record_not_found().with_context(format!("{} doesn't exist", url)))
+  }
+}
+
+impl InMemoryObjectStorage {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Makes the next call of `op` fail with `error`. Calling it several times queues the errors,
+  /// each call of the operation consumes one of them.
+  pub fn fail_next(&self, op: StorageOperation, error: FlowyError) {
+    self
+      .state
+      .lock()
+      .failures
+      .entry(op)
+      .or_default()
+      .push_back(Some(error));
+  }
+
+  /// Lets the next `successes` calls of `op` succeed and makes the one after them fail with
+  /// `error`, for the failures in the middle of a sequence of calls.
+  pub fn fail_after(&self, op: StorageOperation, successes: usize, error: FlowyError) {
+    let mut state = self.state.lock();
+    let failures = state.failures.entry(op).or_default();
+    failures.extend((0..successes).map(|_| None));
+    failures.push_back(Some(error));
+  }
+
+  /// Removes the failures that haven't been consumed yet.
+  pub fn clear_failures(&self) {
+    self.state.lock().failures.clear();
+  }
+
+  /// Returns how many times `op` was called, including the calls that failed.
+  pub fn call_count(&self, op: StorageOperation) -> usize {
+    self
+      .state
+      .lock()
+      .calls
+      .get(&op)
+      .copied()
+      .unwrap_or_default()
+  }
+
+  /// Delays every call of `op` by `latency`, so the callers can be tested with calls in flight.
+  /// A latency longer than the test behaves like a service that doesn't respond.
+  pub fn set_latency(&self, op: StorageOperation, latency: Duration) {
+    self.state.lock().latencies.insert(op, latency);
+  }
+
+  /// Returns the highest number of calls of `op` that were in flight at the same time.
+  pub fn max_concurrent_calls(&self, op: StorageOperation) -> usize {
+    self
+      .state
+      .lock()
+      .max_in_flight
+      .get(&op)
+      .copied()
+      .unwrap_or_default()
+  }
+
+  /// Makes the next stream returned by [ObjectStorageService::get_object_stream] fail with
+  /// `error` once it yielded `after` bytes, the way a connection reset in the middle of a
+  /// download does. Calling it several times queues the interruptions.
+  pub fn interrupt_next_stream(&self, after: usize, error: FlowyError) {
+    self.state.lock().interruptions.push_back((after, error));
+  }
+
+  /// Makes the streams returned by [ObjectStorageService::get_object_stream] yield `chunk_size`
+  /// bytes at a time, one chunk every `interval`, like a slow download.
+  pub fn stream_in_chunks(&self, chunk_size: usize, interval: Duration) {
+    self.state.lock().stream_chunking = Some((chunk_size.max(1), interval));
+  }
+
+  /// Makes [ObjectStorageService::supports_range_requests] return false, like the backends that
+  /// can only return whole objects.
+  pub fn disable_range_requests(&self) {
+    self.state.lock().range_requests_disabled = true;
+  }
+
+  /// Returns the object stored at `url` as it was put.
+  pub fn object(&self, url: &str) -> Option<ObjectValue> {
+    self.state.lock().objects.get(url).cloned()
+  }
+
+  pub fn len(&self) -> usize {
+    self.state.lock().objects.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  async fn run<T, F>(&self, op: StorageOperation, f: F) -> Result<T, FlowyError>
+  where
+    F: FnOnce(&mut State) -> Result<T, FlowyError>,
+  {
+    let latency = self.state.lock().begin(op);
+    let _in_flight = InFlight {
+      state: &self.state,
+      op,
+    };
+    if let Some(latency) = latency {
+      tokio::time::sleep(latency).await;
+    }
+    let mut state = self.state.lock();
+    state.next_failure(op)?;
+    f(&mut state)
+  }
+}
+
+fn object_url(object_id: &ObjectIdentity) -> Result<String, FlowyError> {
+  for component in [&object_id.workspace_id, &object_id.file_id, &object_id.ext] {
+    if component.contains('/') {
+      return Err(FlowyError::new(
+        ErrorCode::InvalidParams,
+        format!("{:?} is not a valid url component", component),
+      ));
+    }
+  }
+  if object_id.workspace_id.is_empty() || object_id.file_id.is_empty() {
+    return Err(FlowyError::new(
+      ErrorCode::InvalidParams,
+      "the workspace id and the file id can't be empty",
+    ));
+  }
+  if object_id.ext.is_empty() {
+    Ok(format!(
+      "{}{}/{}",
+      URL_SCHEME, object_id.workspace_id, object_id.file_id
+    ))
+  } else {
+    Ok(format!(
+      "{}{}/{}.{}",
+      URL_SCHEME, object_id.workspace_id, object_id.file_id, object_id.ext
+    ))
+  }
+}
+
+/// Returns the workspace id and the file name of the object the url points to.
+fn parse_url(url: &str) -> Result<(&str, &str), FlowyError> {
+  url
+    .strip_prefix(URL_SCHEME)
+    .and_then(|path| path.split_once('/'))
+    .filter(|(workspace_id, file_name)| {
+      !workspace_id.is_empty() && !file_name.is_empty() && !file_name.contains('/')
+    })
+    .ok_or_else(|| FlowyError::new(ErrorCode::InvalidURL, format!("not an object url: {}", url)))
+}
+
+fn object_meta(url: &str, value: &ObjectValue) -> Result<ObjectMeta, FlowyError> {
+  let (_, file_name) = parse_url(url)?;
+  let content = value.clone().decompress()?.raw;
+  Ok(ObjectMeta {
+    url: url.to_string(),
+    file_id: file_name.split('.').next().unwrap_or_default().to_string(),
+    size: content.len() as u64,
+    mime: value.mime.clone(),
+    trashed_at: None,
+    expires_at: None,
+    etag: Some(content_etag(&content)),
+    last_modified: None,
+  })
+}
+
+fn upload_not_found(upload_id: &str) -> FlowyError {
+  FlowyError::record_not_found().with_context(format!("upload {} doesn't exist", upload_id))
+}
+
+#[async_trait]
+impl ObjectStorageService for InMemoryObjectStorage {
+  async fn get_object_url(&self, object_id: ObjectIdentity) -> Result<String, FlowyError> {
+    self
+      .run(StorageOperation::GetUrl, move |_| object_url(&object_id))
+      .await
+  }
+
+  async fn put_object(&self, url: String, object_value: ObjectValue) -> Result<(), FlowyError> {
+    self
+      .run(StorageOperation::Put, move |state| {
+        parse_url(&url)?;
+        state.insert(url, object_value);
+        Ok(())
+      })
+      .await
+  }
+
+  /// The precondition is checked atomically with the write.
+  async fn put_object_if_absent(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+  ) -> Result<(), FlowyError> {
+    self
+      .run(StorageOperation::Put, move |state| {
+        parse_url(&url)?;
+        if state.objects.contains_key(&url) {
+          return Err(precondition_failed(&url, "it already exists"));
+        }
+        state.insert(url, object_value);
+        Ok(())
+      })
+      .await
+  }
+
+  /// The precondition is checked atomically with the write.
+  async fn put_object_if_match(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    etag: String,
+  ) -> Result<(), FlowyError> {
+    self
+      .run(StorageOperation::Put, move |state| {
+        parse_url(&url)?;
+        let current = match state.objects.get(&url) {
+          Some(_) => state.meta(&url)?.etag,
+          None => None,
+        };
+        check_if_match(&url, current.as_deref(), &etag)?;
+        state.insert(url, object_value);
+        Ok(())
+      })
+      .await
+  }
+
+  /// Deleting an object that doesn't exist succeeds.
+  async fn delete_object(&self, url: String) -> Result<(), FlowyError> {
+    self
+      .run(StorageOperation::Delete, move |state| {
+        parse_url(&url)?;
+        state.remove(&url);
+        Ok(())
+      })
+      .await
+  }
+
+  /// Deletes the objects, the trash and the pending uploads of the workspace at once, the way a
+  /// prefix delete does.
+  async fn delete_workspace_objects(&self, workspace_id: &str) -> Result<DeleteReport, FlowyError> {
+    let dir = format!("{}{}/", URL_SCHEME, workspace_id);
+    self
+      .run(StorageOperation::Delete, move |state| {
+        let mut report = DeleteReport::default();
+        let urls = state
+          .objects
+          .keys()
+          .filter(|url| url.starts_with(&dir))
+          .cloned()
+          .collect::<Vec<_>>();
+        for url in urls {
+          if let Some(value) = state.remove(&url) {
+            report.deleted += 1;
+            report.freed_bytes += value.raw.len() as u64;
+          }
+        }
+        state.trash.retain(|url, (value, _)| {
+          let trashed = url.starts_with(&dir);
+          if trashed {
+            report.deleted += 1;
+            report.freed_bytes += value.raw.len() as u64;
+          }
+          !trashed
+        });
+        state
+          .uploads
+          .retain(|_, upload| !upload.url.starts_with(&dir));
+        Ok(report)
+      })
+      .await
+  }
+
+  async fn list_objects(
+    &self,
+    workspace_id: &str,
+    prefix: Option<&str>,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    let options = ListOptions {
+      prefix: prefix.map(|prefix| prefix.to_string()),
+      include_trashed: false,
+    };
+    self.list_objects_with_options(workspace_id, &options).await
+  }
+
+  async fn list_objects_with_options(
+    &self,
+    workspace_id: &str,
+    options: &ListOptions,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    let dir = format!("{}{}/", URL_SCHEME, workspace_id);
+    let options = options.clone();
+    self
+      .run(StorageOperation::List, move |state| {
+        let prefix = options.prefix.unwrap_or_default();
+        let in_dir = |url: &str| {
+          url
+            .strip_prefix(&dir)
+            .map_or(false, |file_name| file_name.starts_with(&prefix))
+        };
+        let mut objects = state
+          .objects
+          .keys()
+          .filter(|url| in_dir(url))
+          .map(|url| state.meta(url))
+          .collect::<Result<Vec<_>, _>>()?;
+        if options.include_trashed {
+          for (url, (value, trashed_at)) in state.trash.iter().filter(|(url, _)| in_dir(url)) {
+            let mut meta = object_meta(url, value)?;
+            meta.trashed_at = Some(*trashed_at);
+            objects.push(meta);
+          }
+        }
+        objects.sort_by(|a, b| a.url.cmp(&b.url));
+        Ok(objects)
+      })
+      .await
+  }
+
+  async fn get_object(&self, url: String) -> Result<ObjectValue, FlowyError> {
+    self
+      .run(StorageOperation::Get, move |state| {
+        state.object(&url).cloned()
+      })
+      .await
+  }
+
+  async fn get_object_stream(&self, url: String) -> Result<ObjectStream, FlowyError> {
+    let value = self.get_object(url).await?.decompress()?;
+    let (interruption, chunking) = {
+      let mut state = self.state.lock();
+      (state.interruptions.pop_front(), state.stream_chunking)
+    };
+    if interruption.is_none() && chunking.is_none() {
+      return Ok(value.into());
+    }
+
+    let (content, error) = match interruption {
+      Some((after, error)) => (value.raw.slice(..after.min(value.raw.len())), Some(error)),
+      None => (value.raw.clone(), None),
+    };
+    let (chunk_size, interval) = chunking.unwrap_or((content.len().max(1), Duration::ZERO));
+    let mut chunks = content
+      .chunks(chunk_size)
+      .map(|chunk| Ok(content.slice_ref(chunk)))
+      .collect::<Vec<_>>();
+    chunks.extend(error.map(Err));
+    let stream = futures::stream::iter(chunks).then(move |chunk| async move {
+      if !interval.is_zero() {
+        tokio::time::sleep(interval).await;
+      }
+      chunk
+    });
+    Ok(ObjectStream {
+      content_length: value.raw.len() as u64,
+      mime: value.mime,
+      stream: Box::pin(stream),
+    })
+  }
+
+  /// The metadata is the one of [Self::head_object].
+  async fn get_object_stream_with_meta(
+    &self,
+    url: String,
+  ) -> Result<(ObjectMeta, ObjectByteStream), FlowyError> {
+    let (meta, value) = self
+      .run(StorageOperation::Get, move |state| {
+        Ok((state.meta(&url)?, state.object(&url)?.clone()))
+      })
+      .await?;
+    let stream = ObjectStream::from(value.decompress()?).stream;
+    Ok((meta, stream))
+  }
+
+  async fn get_object_if_modified(
+    &self,
+    url: String,
+    etag: Option<String>,
+  ) -> Result<Option<(ObjectValue, String)>, FlowyError> {
+    self
+      .run(StorageOperation::Get, move |state| {
+        let value = state.object(&url)?.clone().decompress()?;
+        let current = content_etag(&value.raw);
+        match etag {
+          Some(etag) if etag_matches(&etag, &current) => Ok(None),
+          _ => Ok(Some((value, current))),
+        }
+      })
+      .await
+  }
+
+  fn supports_range_requests(&self) -> bool {
+    !self.state.lock().range_requests_disabled
+  }
+
+  async fn get_object_range(
+    &self,
+    url: String,
+    start: u64,
+    end: Option<u64>,
+  ) -> Result<ObjectValue, FlowyError> {
+    self
+      .run(StorageOperation::GetRange, move |state| {
+        slice_object_range(state.object(&url)?.clone(), start, end)
+      })
+      .await
+  }
+
+  async fn head_object(&self, url: String) -> Result<ObjectMeta, FlowyError> {
+    self
+      .run(StorageOperation::Head, move |state| state.meta(&url))
+      .await
+  }
+
+  async fn object_exists(&self, url: String) -> Result<bool, FlowyError> {
+    self
+      .run(StorageOperation::Head, move |state| {
+        parse_url(&url)?;
+        Ok(state.objects.contains_key(&url))
+      })
+      .await
+  }
+
+  fn supports_copy_object(&self) -> bool {
+    true
+  }
+
+  async fn copy_object(
+    &self,
+    src_url: String,
+    dst_identity: ObjectIdentity,
+  ) -> Result<String, FlowyError> {
+    self
+      .run(StorageOperation::Copy, move |state| {
+        let value = state.object(&src_url)?.clone();
+        let dst_url = object_url(&dst_identity)?;
+        state.insert(dst_url.clone(), value);
+        Ok(dst_url)
+      })
+      .await
+  }
+
+  async fn move_object(
+    &self,
+    src_url: String,
+    dst_identity: ObjectIdentity,
+    overwrite: bool,
+  ) -> Result<String, FlowyError> {
+    self
+      .run(StorageOperation::Move, move |state| {
+        state.object(&src_url)?;
+        let dst_url = object_url(&dst_identity)?;
+        if dst_url == src_url {
+          return Ok(dst_url);
+        }
+        if !overwrite && state.objects.contains_key(&dst_url) {
+          return Err(destination_exists(&dst_url));
+        }
+        let value = state.remove(&src_url).unwrap();
+        state.insert(dst_url.clone(), value);
+        Ok(dst_url)
+      })
+      .await
+  }
+
+  async fn trash_object(&self, url: String) -> Result<(), FlowyError> {
+    self
+      .run(StorageOperation::Trash, move |state| {
+        state.object(&url)?;
+        let value = state.remove(&url).unwrap();
+        state.trash.insert(url, (value, SystemTime::now()));
+        Ok(())
+      })
+      .await
+  }
+
+  async fn restore_object(&self, url: String) -> Result<(), FlowyError> {
+    self
+      .run(StorageOperation::Restore, move |state| {
+        parse_url(&url)?;
+        let (value, _) = state.trash.remove(&url).ok_or_else(|| {
+          FlowyError::record_not_found().with_context(format!("{} is not in the trash", url))
+        })?;
+        state.insert(url, value);
+        Ok(())
+      })
+      .await
+  }
+
+  async fn purge_trash(&self, older_than: Duration) -> Result<Vec<String>, FlowyError> {
+    self
+      .run(StorageOperation::PurgeTrash, move |state| {
+        let now = SystemTime::now();
+        let mut purged = state
+          .trash
+          .iter()
+          .filter(|(_, (_, trashed_at))| {
+            now
+              .duration_since(*trashed_at)
+              .map_or(false, |elapsed| elapsed >= older_than)
+          })
+          .map(|(url, _)| url.clone())
+          .collect::<Vec<_>>();
+        for url in &purged {
+          state.trash.remove(url);
+        }
+        purged.sort();
+        Ok(purged)
+      })
+      .await
+  }
+
+  fn supports_multipart(&self) -> bool {
+    true
+  }
+
+  async fn initiate_multipart(&self, url: String, mime: Mime) -> Result<UploadId, FlowyError> {
+    self
+      .run(StorageOperation::InitiateMultipart, move |state| {
+        parse_url(&url)?;
+        state.next_upload_id += 1;
+        let upload_id = format!("upload-{}", state.next_upload_id);
+        let upload = Upload {
+          url,
+          mime,
+          parts: BTreeMap::new(),
+        };
+        state.uploads.insert(upload_id.clone(), upload);
+        Ok(upload_id)
+      })
+      .await
+  }
+
+  async fn upload_part(
+    &self,
+    url: String,
+    upload_id: UploadId,
+    part_number: u32,
+    bytes: Bytes,
+  ) -> Result<PartETag, FlowyError> {
+    self
+      .run(StorageOperation::UploadPart, move |state| {
+        if part_number == 0 {
+          return Err(FlowyError::new(
+            ErrorCode::InvalidParams,
+            "part numbers start from 1",
+          ));
+        }
+        let upload = state
+          .uploads
+          .get_mut(&upload_id)
+          .filter(|upload| upload.url == url)
+          .ok_or_else(|| upload_not_found(&upload_id))?;
+        let e_tag = content_hash(&bytes);
+        upload.parts.insert(part_number, (e_tag.clone(), bytes));
+        Ok(PartETag { part_number, e_tag })
+      })
+      .await
+  }
+
+  async fn complete_multipart(
+    &self,
+    url: String,
+    upload_id: UploadId,
+    parts: Vec<PartETag>,
+  ) -> Result<(), FlowyError> {
+    self
+      .run(StorageOperation::CompleteMultipart, move |state| {
+        let upload = state
+          .uploads
+          .get(&upload_id)
+          .filter(|upload| upload.url == url)
+          .ok_or_else(|| upload_not_found(&upload_id))?;
+        let mut content = BytesMut::new();
+        let mut last_part_number = 0;
+        for part in &parts {
+          if part.part_number <= last_part_number {
+            return Err(FlowyError::new(
+              ErrorCode::InvalidParams,
+              "the parts must be ordered by part number",
+            ));
+          }
+          last_part_number = part.part_number;
+          match upload.parts.get(&part.part_number) {
+            Some((e_tag, bytes)) if *e_tag == part.e_tag => content.extend_from_slice(bytes),
+            _ => {
+              return Err(FlowyError::new(
+                ErrorCode::InvalidParams,
+                format!("part {} wasn't uploaded", part.part_number),
+              ))
+            },
+          }
+        }
+
+        let upload = state.uploads.remove(&upload_id).unwrap();
+        let value = ObjectValue {
+          raw: content.freeze(),
+          mime: upload.mime,
+          content_encoding: None,
+        };
+        state.insert(url, value);
+        Ok(())
+      })
+      .await
+  }
+
+  /// Aborting an upload that doesn't exist succeeds.
+  async fn abort_multipart(&self, _url: String, upload_id: UploadId) -> Result<(), FlowyError> {
+    self
+      .run(StorageOperation::AbortMultipart, move |state| {
+        state.uploads.remove(&upload_id);
+        Ok(())
+      })
+      .await
+  }
+}
+
+/// Builds an uncompressed [ObjectValue] with the mime type guessed from the file name, to put
+/// into an [InMemoryObjectStorage] in tests.
+pub fn memory_object_value(file_name: &str, content: impl Into<Bytes>) -> ObjectValue {
+  ObjectValue {
+    raw: content.into(),
+    mime: guess_mime(file_name),
+    content_encoding: None,
+  }
+}
+
+/// Only forwards the required methods to an [InMemoryObjectStorage], so the tests exercise the
+/// default implementation of the other methods.
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct DefaultsOnly(pub(crate) InMemoryObjectStorage);
+
+#[cfg(test)]
+#[async_trait]
+impl ObjectStorageService for DefaultsOnly {
+  async fn get_object_url(&self, object_id: ObjectIdentity) -> Result<String, FlowyError> {
+    self.0.get_object_url(object_id).await
+  }
+
+  async fn put_object(&self, url: String, object_value: ObjectValue) -> Result<(), FlowyError> {
+    self.0.put_object(url, object_value).await
+  }
+
+  async fn delete_object(&self, url: String) -> Result<(), FlowyError> {
+    self.0.delete_object(url).await
+  }
+
+  async fn get_object(&self, url: String) -> Result<ObjectValue, FlowyError> {
+    self.0.get_object(url).await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn identity(workspace_id: &str, file_id: &str) -> ObjectIdentity {
+    ObjectIdentity {
+      workspace_id: workspace_id.to_string(),
+      file_id: file_id.to_string(),
+      ext: "txt".to_string(),
+      hash_algorithm: None,
+      size: None,
+    }
+  }
+
+  #[tokio::test]
+  async fn memory_round_trip_test() {
+    let storage = InMemoryObjectStorage::new();
+    let url = storage.get_object_url(identity("w1", "1")).await.unwrap();
+    assert_eq!(url, "memory://w1/1.txt");
+
+    storage
+      .put_object(url.clone(), memory_object_value("1.txt", "hello"))
+      .await
+      .unwrap();
+    let value = storage.get_object(url.clone()).await.unwrap();
+    assert_eq!(value.raw.as_ref(), b"hello");
+    assert_eq!(value.mime, mime::TEXT_PLAIN);
+    let range = storage
+      .get_object_range(url.clone(), 1, Some(2))
+      .await
+      .unwrap();
+    assert_eq!(range.raw.as_ref(), b"el");
+    let meta = storage.head_object(url.clone()).await.unwrap();
+    assert_eq!((meta.file_id.as_str(), meta.size), ("1", 5));
+
+    let copy_url = storage
+      .copy_object(url.clone(), identity("w2", "1"))
+      .await
+      .unwrap();
+    assert_eq!(copy_url, "memory://w2/1.txt");
+    assert_eq!(storage.list_objects("w2", None).await.unwrap().len(), 1);
+    assert_eq!(
+      storage.list_objects("w1", Some("2")).await.unwrap().len(),
+      0
+    );
+
+    storage.delete_object(url.clone()).await.unwrap();
+    assert!(!storage.object_exists(url.clone()).await.unwrap());
+    let err = storage.get_object(url.clone()).await.err().unwrap();
+    assert!(err.is_record_not_found());
+    storage.delete_object(url.clone()).await.unwrap();
+    assert_eq!(storage.len(), 1);
+
+    // Moving an object onto itself is a no-op, even without overwrite.
+    let same = storage
+      .move_object(copy_url.clone(), identity("w2", "1"), false)
+      .await;
+    assert_eq!(same.unwrap(), copy_url);
+    storage
+      .put_object(url.clone(), memory_object_value("1.txt", "world"))
+      .await
+      .unwrap();
+    let err = storage
+      .move_object(url.clone(), identity("w2", "1"), false)
+      .await
+      .err()
+      .unwrap();
+    assert_eq!(err.code, ErrorCode::RecordAlreadyExists);
+    storage
+      .move_object(url.clone(), identity("w2", "1"), true)
+      .await
+      .unwrap();
+    assert!(!storage.object_exists(url).await.unwrap());
+    let moved = storage.object(&copy_url).unwrap();
+    assert_eq!(moved.raw.as_ref(), b"world");
+  }
+
+  #[tokio::test]
+  async fn fail_next_test() {
+    let storage = InMemoryObjectStorage::new();
+    let url = storage.get_object_url(identity("w1", "1")).await.unwrap();
+    storage.fail_next(
+      StorageOperation::Put,
+      FlowyError::new(ErrorCode::ConnectTimeout, "timeout"),
+    );
+
+    let value = memory_object_value("1.txt", "hello");
+    let err = storage
+      .put_object(url.clone(), value.clone())
+      .await
+      .unwrap_err();
+    assert_eq!(err.code, ErrorCode::ConnectTimeout);
+    assert!(storage.is_empty());
+
+    storage.put_object(url.clone(), value).await.unwrap();
+    assert_eq!(storage.call_count(StorageOperation::Put), 2);
+    assert!(storage.object(&url).is_some());
+  }
+
+  #[tokio::test]
+  async fn interrupt_next_stream_test() {
+    let storage = InMemoryObjectStorage::new();
+    let url = storage.get_object_url(identity("w1", "1")).await.unwrap();
+    storage
+      .put_object(url.clone(), memory_object_value("1.txt", "hello"))
+      .await
+      .unwrap();
+    storage.fail_after(
+      StorageOperation::Get,
+      1,
+      FlowyError::new(ErrorCode::ConnectTimeout, "timeout"),
+    );
+    storage.interrupt_next_stream(2, FlowyError::new(ErrorCode::ConnectClose, "reset"));
+    storage.stream_in_chunks(2, Duration::ZERO);
+
+    let object = storage.get_object_stream(url.clone()).await.unwrap();
+    assert_eq!(object.content_length, 5);
+    let chunks = object.stream.collect::<Vec<_>>().await;
+    assert_eq!(chunks[0].as_ref().unwrap().as_ref(), b"he");
+    assert_eq!(
+      chunks[1].as_ref().unwrap_err().code,
+      ErrorCode::ConnectClose
+    );
+    let err = storage.get_object_stream(url.clone()).await.err().unwrap();
+    assert_eq!(err.code, ErrorCode::ConnectTimeout);
+
+    let object = storage.get_object_stream(url).await.unwrap();
+    let chunks = object.stream.collect::<Vec<_>>().await;
+    let chunks = chunks.into_iter().map(Result::unwrap).collect::<Vec<_>>();
+    assert_eq!(chunks, vec!["he", "ll", "o"]);
+  }
+
+  #[tokio::test]
+  async fn set_latency_test() {
+    let storage = InMemoryObjectStorage::new();
+    storage.set_latency(StorageOperation::Delete, Duration::from_millis(10));
+    let delete_all = || {
+      let urls = (0..4).map(|i| format!("memory://w1/{}.txt", i));
+      futures::future::join_all(urls.map(|url| storage.delete_object(url)))
+    };
+    delete_all().await;
+    assert_eq!(storage.max_concurrent_calls(StorageOperation::Delete), 4);
+
+    // A call cancelled while it's delayed isn't in flight anymore.
+    let delete = storage.delete_object("memory://w1/1.txt".to_string());
+    assert!(futures::FutureExt::now_or_never(delete).is_none());
+    delete_all().await;
+    assert_eq!(storage.max_concurrent_calls(StorageOperation::Delete), 4);
+    assert_eq!(storage.call_count(StorageOperation::Delete), 9);
+  }
+
+  #[tokio::test]
+  async fn trash_test() {
+    let storage = InMemoryObjectStorage::new();
+    let url = storage.get_object_url(identity("w1", "1")).await.unwrap();
+    storage
+      .put_object(url.clone(), memory_object_value("1.txt", "hello"))
+      .await
+      .unwrap();
+    storage.trash_object(url.clone()).await.unwrap();
+    assert!(storage.get_object(url.clone()).await.is_err());
+    assert!(storage.list_objects("w1", None).await.unwrap().is_empty());
+    let options = ListOptions {
+      include_trashed: true,
+      ..Default::default()
+    };
+    let objects = storage
+      .list_objects_with_options("w1", &options)
+      .await
+      .unwrap();
+    assert_eq!(objects.len(), 1);
+    assert!(objects[0].trashed_at.is_some());
+
+    storage.restore_object(url.clone()).await.unwrap();
+    assert!(storage.object_exists(url.clone()).await.unwrap());
+    let err = storage.restore_object(url.clone()).await.unwrap_err();
+    assert!(err.is_record_not_found());
+
+    storage.trash_object(url.clone()).await.unwrap();
+    let purged = storage
+      .purge_trash(Duration::from_secs(3600))
+      .await
+      .unwrap();
+    assert!(purged.is_empty());
+    let purged = storage.purge_trash(Duration::ZERO).await.unwrap();
+    assert_eq!(purged, vec![url]);
+  }
+
+  #[tokio::test]
+  async fn multipart_test() {
+    let storage = InMemoryObjectStorage::new();
+    let url = storage.get_object_url(identity("w1", "1")).await.unwrap();
+    let upload_id = storage
+      .initiate_multipart(url.clone(), mime::TEXT_PLAIN)
+      .await
+      .unwrap();
+    let mut parts = vec![];
+    for (part_number, bytes) in [(1, "hel"), (2, "lo")] {
+      let part = storage
+        .upload_part(url.clone(), upload_id.clone(), part_number, bytes.into())
+        .await
+        .unwrap();
+      parts.push(part);
+    }
+    assert!(!storage.object_exists(url.clone()).await.unwrap());
+
+    storage
+      .complete_multipart(url.clone(), upload_id.clone(), parts)
+      .await
+      .unwrap();
+    let value = storage.get_object(url.clone()).await.unwrap();
+    assert_eq!(value.raw.as_ref(), b"hello");
+    let err = storage
+      .upload_part(url, upload_id, 3, "!".into())
+      .await
+      .unwrap_err();
+    assert!(err.is_record_not_found());
+  }
+}
@@ -0,0 +1,258 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::RwLock;
+
+use flowy_error::FlowyError;
+
+use crate::{ObjectStorageObserver, StorageErrorKind};
+
+/// Returns the workspace of the object the url points to. The url of an object ends with
+/// `{workspace_id}/{file_id}.{ext}`, see [crate::ObjectStorageService::get_object_url]. `None` if
+/// the url has no directory.
+pub fn workspace_id_from_url(url: &str) -> Option<&str> {
+  let path = url.split(['?', '#']).next()?;
+  let mut segments = path.rsplit('/');
+  segments.next()?;
+  segments
+    .next()
+    .filter(|workspace_id| !workspace_id.is_empty())
+}
+
+/// The counters of the operations observed by [StorageMetrics], as returned by
+/// [StorageMetrics::snapshot].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TransferCounts {
+  /// The bytes of the successful uploads.
+  pub bytes_uploaded: u64,
+  /// The bytes of the successful downloads.
+  pub bytes_downloaded: u64,
+  pub uploads: u64,
+  pub downloads: u64,
+  pub deletes: u64,
+  /// The uploads, downloads and deletions that failed.
+  pub failures: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StorageMetricsSnapshot {
+  pub total: TransferCounts,
+  /// The counters of each workspace, see [workspace_id_from_url]. The operations on urls without
+  /// a workspace are only counted in the `total`.
+  pub by_workspace: HashMap<String, TransferCounts>,
+  /// The failures whose error is a storage error, see [StorageErrorKind::of].
+  pub errors_by_kind: HashMap<StorageErrorKind, u64>,
+}
+
+#[derive(Default)]
+struct AtomicTransferCounts {
+  bytes_uploaded: AtomicU64,
+  bytes_downloaded: AtomicU64,
+  uploads: AtomicU64,
+  downloads: AtomicU64,
+  deletes: AtomicU64,
+  failures: AtomicU64,
+}
+
+impl AtomicTransferCounts {
+  fn snapshot(&self) -> TransferCounts {
+    TransferCounts {
+      bytes_uploaded: self.bytes_uploaded.load(Ordering::Relaxed),
+      bytes_downloaded: self.bytes_downloaded.load(Ordering::Relaxed),
+      uploads: self.uploads.load(Ordering::Relaxed),
+      downloads: self.downloads.load(Ordering::Relaxed),
+      deletes: self.deletes.load(Ordering::Relaxed),
+      failures: self.failures.load(Ordering::Relaxed),
+    }
+  }
+}
+
+/// Cumulative counters of the uploads, downloads and deletions of an
+/// [crate::ObservedObjectStorage], for diagnostics. Every observed storage keeps one, see
+/// [crate::ObservedObjectStorage::metrics], and it can be attached to other ones as an
+/// [ObjectStorageObserver] to aggregate them.
+///
+/// Unlike the observer callbacks, which notify every operation, the metrics are aggregate state
+/// read with [Self::snapshot].
+#[derive(Default)]
+pub struct StorageMetrics {
+  total: AtomicTransferCounts,
+  by_workspace: RwLock<HashMap<String, Arc<AtomicTransferCounts>>>,
+  errors_by_kind: RwLock<HashMap<StorageErrorKind, u64>>,
+}
+
+impl StorageMetrics {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn snapshot(&self) -> StorageMetricsSnapshot {
+    StorageMetricsSnapshot {
+      total: self.total.snapshot(),
+      by_workspace: self
+        .by_workspace
+        .read()
+        .iter()
+        .map(|(workspace_id, counts)| (workspace_id.clone(), counts.snapshot()))
+        .collect(),
+      errors_by_kind: self.errors_by_kind.read().clone(),
+    }
+  }
+
+  /// Sets all the counters back to zero.
+  pub fn reset(&self) {
+    let total = &self.total;
+    for counter in [
+      &total.bytes_uploaded,
+      &total.bytes_downloaded,
+      &total.uploads,
+      &total.downloads,
+      &total.deletes,
+      &total.failures,
+    ] {
+      counter.store(0, Ordering::Relaxed);
+    }
+    self.by_workspace.write().clear();
+    self.errors_by_kind.write().clear();
+  }
+
+  /// Applies `update` to the total counters and to the ones of the workspace of the url.
+  fn record<F>(&self, url: &str, result: Result<(), &FlowyError>, update: F)
+  where
+    F: Fn(&AtomicTransferCounts),
+  {
+    let workspace = workspace_id_from_url(url).map(|workspace_id| self.workspace(workspace_id));
+    for counts in std::iter::once(&self.total).chain(workspace.as_deref()) {
+      update(counts);
+      if result.is_err() {
+        counts.failures.fetch_add(1, Ordering::Relaxed);
+      }
+    }
+    if let Some(kind) = result.err().and_then(StorageErrorKind::of) {
+      *self.errors_by_kind.write().entry(kind).or_default() += 1;
+    }
+  }
+
+  fn workspace(&self, workspace_id: &str) -> Arc<AtomicTransferCounts> {
+    if let Some(counts) = self.by_workspace.read().get(workspace_id) {
+      return counts.clone();
+    }
+    self
+      .by_workspace
+      .write()
+      .entry(workspace_id.to_string())
+      .or_default()
+      .clone()
+  }
+}
+
+impl ObjectStorageObserver for StorageMetrics {
+  fn on_put_finished(
+    &self,
+    url: &str,
+    result: Result<(), &FlowyError>,
+    bytes: u64,
+    _duration: Duration,
+  ) {
+    self.record(url, result, |counts| {
+      counts.uploads.fetch_add(1, Ordering::Relaxed);
+      if result.is_ok() {
+        counts.bytes_uploaded.fetch_add(bytes, Ordering::Relaxed);
+      }
+    });
+  }
+
+  fn on_get_finished(
+    &self,
+    url: &str,
+    result: Result<(), &FlowyError>,
+    bytes: u64,
+    _duration: Duration,
+  ) {
+    self.record(url, result, |counts| {
+      counts.downloads.fetch_add(1, Ordering::Relaxed);
+      counts.bytes_downloaded.fetch_add(bytes, Ordering::Relaxed);
+    });
+  }
+
+  fn on_delete_finished(&self, url: &str, result: Result<(), &FlowyError>, _duration: Duration) {
+    self.record(url, result, |counts| {
+      counts.deletes.fetch_add(1, Ordering::Relaxed);
+    });
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use flowy_error::ErrorCode;
+
+  use super::*;
+  use crate::{
+    memory_object_value, InMemoryObjectStorage, ObjectStorageService, ObservedObjectStorage,
+    StorageOperation,
+  };
+
+  #[test]
+  fn workspace_id_from_url_test() {
+    assert_eq!(workspace_id_from_url("memory://w1/1.txt"), Some("w1"));
+    assert_eq!(
+      workspace_id_from_url("https://host/blob/w1/1.txt?token=a/b"),
+      Some("w1")
+    );
+    assert_eq!(workspace_id_from_url("1.txt"), None);
+  }
+
+  #[tokio::test]
+  async fn storage_metrics_test() {
+    let inner = Arc::new(InMemoryObjectStorage::new());
+    let storage = ObservedObjectStorage::new(inner.clone());
+    for (url, content) in [("memory://w1/1.txt", "hello"), ("memory://w2/1.txt", "hi")] {
+      storage
+        .put_object(url.to_string(), memory_object_value("1.txt", content))
+        .await
+        .unwrap();
+    }
+    storage
+      .get_object("memory://w1/1.txt".to_string())
+      .await
+      .unwrap();
+    assert!(storage
+      .get_object("memory://w1/2.txt".to_string())
+      .await
+      .is_err());
+    inner.fail_next(
+      StorageOperation::Delete,
+      FlowyError::new(ErrorCode::ConnectTimeout, "timeout"),
+    );
+    assert!(storage
+      .delete_object("memory://w2/1.txt".to_string())
+      .await
+      .is_err());
+
+    let snapshot = storage.metrics().snapshot();
+    assert_eq!(
+      snapshot.total,
+      TransferCounts {
+        bytes_uploaded: 7,
+        bytes_downloaded: 5,
+        uploads: 2,
+        downloads: 2,
+        deletes: 1,
+        failures: 2,
+      }
+    );
+    let w1 = &snapshot.by_workspace["w1"];
+    assert_eq!((w1.bytes_uploaded, w1.downloads, w1.failures), (5, 2, 1));
+    assert_eq!(snapshot.by_workspace["w2"].deletes, 1);
+    assert_eq!(snapshot.errors_by_kind[&StorageErrorKind::NotFound], 1);
+    assert_eq!(snapshot.errors_by_kind[&StorageErrorKind::Network], 1);
+
+    storage.metrics().reset();
+    assert_eq!(
+      storage.metrics().snapshot(),
+      StorageMetricsSnapshot::default()
+    );
+  }
+}
@@ -0,0 +1,256 @@
+use bytes::{Bytes, BytesMut};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use tracing::{trace, warn};
+
+use flowy_error::FlowyError;
+
+use crate::retry::retry;
+use crate::timeout::configured_idle_timeout_stream;
+use crate::{
+  cancellable, cancelled_error, CancellationToken, ObjectByteStream, ObjectStorageService,
+  ObjectStream, ObjectValue, RetryPolicy,
+};
+
+/// The id of a multipart upload returned by [ObjectStorageService::initiate_multipart].
+pub type UploadId = String;
+
+/// The default size of each part of a multipart upload.
+pub const DEFAULT_MULTIPART_PART_SIZE: usize = 8 * 1024 * 1024;
+
+/// Identifies an uploaded part of a multipart upload. All the parts of an upload are passed to
+/// [ObjectStorageService::complete_multipart] to assemble the object.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartETag {
+  /// Part numbers start from 1.
+  pub part_number: u32,
+  pub e_tag: String,
+}
+
+/// Uploads the object part by part if the service supports multipart uploads. Each part is
+/// retried on its own according to the `policy`, so a failed part doesn't restart the whole
+/// transfer. At most one part is held in memory at a time.
+///
+/// When the service doesn't support multipart uploads, the content is collected and uploaded
+/// with [ObjectStorageService::put_object].
+///
+/// The upload fails if the content doesn't yield a chunk within the
+/// [crate::StorageConfig::idle_timeout], see [crate::idle_timeout_stream].
+pub async fn put_object_in_parts<S>(
+  service: &S,
+  url: String,
+  object: ObjectStream,
+  part_size: usize,
+  policy: RetryPolicy,
+) -> Result<(), FlowyError>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  put_object_in_parts_cancellable(
+    service,
+    url,
+    object,
+    part_size,
+    policy,
+    &CancellationToken::new(),
+  )
+  .await
+}
+
+/// The cancellable variant of [put_object_in_parts]. Once `cancel` is cancelled, the content is
+/// not read anymore and the multipart upload is aborted, so no partial object is left on the
+/// server. Returns an [ErrorCode::Cancelled](flowy_error::ErrorCode::Cancelled) error in that
+/// case.
+pub async fn put_object_in_parts_cancellable<S>(
+  service: &S,
+  url: String,
+  object: ObjectStream,
+  part_size: usize,
+  policy: RetryPolicy,
+  cancel: &CancellationToken,
+) -> Result<(), FlowyError>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  let ObjectStream {
+    content_length,
+    mime,
+    stream,
+  } = object;
+  let mut stream = configured_idle_timeout_stream(stream);
+
+  if !service.supports_multipart() {
+    let upload = async {
+      let mut raw = BytesMut::with_capacity(content_length as usize);
+      while let Some(chunk) = stream.next().await {
+        raw.extend_from_slice(&chunk?);
+      }
+      let value = ObjectValue {
+        raw: raw.freeze(),
+        mime,
+        content_encoding: None,
+      };
+      service.put_object(url, value).await
+    };
+    return cancellable(upload, cancel).await;
+  }
+
+  let upload_id = cancellable(service.initiate_multipart(url.clone(), mime), cancel).await?;
+  let mut reader = PartReader::new(stream, part_size);
+  let mut parts = vec![];
+  let upload = async {
+    while let Some(part) = reader.next_part().await? {
+      let part_number = parts.len() as u32 + 1;
+      parts.push(upload_part(service, &url, &upload_id, part_number, part, &policy).await?);
+    }
+    Ok(())
+  };
+  let result = cancellable(upload, cancel).await;
+
+  match result {
+    Ok(_) if !cancel.is_cancelled() => {
+      retry(policy, || {
+        service.complete_multipart(url.clone(), upload_id.clone(), parts.clone())
+      })
+      .await
+    },
+    result => {
+      if let Err(abort_err) = service.abort_multipart(url, upload_id).await {
+        warn!("abort multipart upload failed: {}", abort_err);
+      }
+      Err(result.err().unwrap_or_else(cancelled_error))
+    },
+  }
+}
+
+/// Splits a stream into parts of `part_size` bytes. Only the last part might be smaller. An
+/// empty stream produces a single empty part, since backends expect at least one part.
+pub(crate) struct PartReader {
+  stream: ObjectByteStream,
+  part_size: usize,
+  buffer: BytesMut,
+  pending: Bytes,
+  finished: bool,
+  produced: usize,
+}
+
+impl PartReader {
+  pub(crate) fn new(stream: ObjectByteStream, part_size: usize) -> Self {
+    let part_size = part_size.max(1);
+    Self {
+      stream,
+      part_size,
+      buffer: BytesMut::with_capacity(part_size),
+      pending: Bytes::new(),
+      finished: false,
+      produced: 0,
+    }
+  }
+
+  pub(crate) async fn next_part(&mut self) -> Result<Option<Bytes>, FlowyError> {
+    loop {
+      if !self.pending.is_empty() {
+        let n = (self.part_size - self.buffer.len()).min(self.pending.len());
+        self.buffer.extend_from_slice(&self.pending.split_to(n));
+        if self.buffer.len() == self.part_size {
+          return Ok(Some(self.take_part()));
+        }
+        continue;
+      }
+
+      if self.finished {
+        if !self.buffer.is_empty() || self.produced == 0 {
+          return Ok(Some(self.take_part()));
+        }
+        return Ok(None);
+      }
+
+      match self.stream.next().await {
+        Some(chunk) => self.pending = chunk?,
+        None => self.finished = true,
+      }
+    }
+  }
+
+  fn take_part(&mut self) -> Bytes {
+    self.produced += 1;
+    self.buffer.split().freeze()
+  }
+}
+
+pub(crate) async fn upload_part<S>(
+  service: &S,
+  url: &str,
+  upload_id: &str,
+  part_number: u32,
+  part: Bytes,
+  policy: &RetryPolicy,
+) -> Result<PartETag, FlowyError>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  trace!("upload part {} with {} bytes", part_number, part.len());
+  retry(policy.clone(), || {
+    service.upload_part(
+      url.to_string(),
+      upload_id.to_string(),
+      part_number,
+      part.clone(),
+    )
+  })
+  .await
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::atomic::{AtomicUsize, Ordering};
+  use std::sync::Arc;
+  use std::time::Duration;
+
+  use flowy_error::ErrorCode;
+
+  use super::*;
+  use crate::{InMemoryObjectStorage, StorageOperation};
+
+  #[tokio::test]
+  async fn cancel_stops_reading_the_source_test() {
+    let chunks_read = Arc::new(AtomicUsize::new(0));
+    let cloned_chunks_read = chunks_read.clone();
+    let stream = futures::stream::iter(0..100).map(move |_| {
+      cloned_chunks_read.fetch_add(1, Ordering::SeqCst);
+      Ok(Bytes::from_static(&[0; 4]))
+    });
+    let object = ObjectStream {
+      content_length: 400,
+      mime: mime::APPLICATION_OCTET_STREAM,
+      stream: Box::pin(stream),
+    };
+
+    let storage = InMemoryObjectStorage::new();
+    storage.set_latency(StorageOperation::UploadPart, Duration::from_millis(20));
+    let cancel = CancellationToken::new();
+    let cloned_cancel = cancel.clone();
+    tokio::spawn(async move {
+      tokio::time::sleep(Duration::from_millis(50)).await;
+      cloned_cancel.cancel();
+    });
+
+    let err = put_object_in_parts_cancellable(
+      &storage,
+      "memory://w1/1.bin".to_string(),
+      object,
+      4,
+      RetryPolicy::default(),
+      &cancel,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err.code, ErrorCode::Cancelled);
+    assert_eq!(storage.call_count(StorageOperation::AbortMultipart), 1);
+
+    let read = chunks_read.load(Ordering::SeqCst);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(read < 100);
+    assert_eq!(chunks_read.load(Ordering::SeqCst), read);
+  }
+}
@@ -0,0 +1,518 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use mime::Mime;
+use parking_lot::RwLock;
+
+use flowy_error::FlowyError;
+
+use crate::{
+  DeleteReport, HealthStatus, ListOptions, ObjectIdentity, ObjectMeta, ObjectStorageService,
+  ObjectStream, ObjectValue, PartETag, ProgressCallback, StorageMetrics, UploadId, VersionMeta,
+};
+
+/// Gets notified when an operation of [ObservedObjectStorage] starts and finishes. All the
+/// callbacks do nothing by default, so an observer only implements the ones it cares about.
+///
+/// The callbacks are called on the task running the operation, they must not block.
+pub trait ObjectStorageObserver: Send + Sync + 'static {
+  fn on_put_started(&self, _url: &str, _bytes: u64) {}
+
+  fn on_put_finished(
+    &self,
+    _url: &str,
+    _result: Result<(), &FlowyError>,
+    _bytes: u64,
+    _duration: Duration,
+  ) {
+  }
+
+  fn on_get_started(&self, _url: &str) {}
+
+  /// `bytes` is the size of the downloaded content, 0 if the download failed.
+  fn on_get_finished(
+    &self,
+    _url: &str,
+    _result: Result<(), &FlowyError>,
+    _bytes: u64,
+    _duration: Duration,
+  ) {
+  }
+
+  fn on_delete_started(&self, _url: &str) {}
+
+  fn on_delete_finished(&self, _url: &str, _result: Result<(), &FlowyError>, _duration: Duration) {}
+}
+
+type Observers = Arc<RwLock<Vec<Arc<dyn ObjectStorageObserver>>>>;
+
+/// An [ObjectStorageService] that notifies the attached [ObjectStorageObserver]s around the
+/// uploads, downloads and deletions of the inner service, and counts them in its
+/// [StorageMetrics]. The other operations are forwarded without notification.
+pub struct ObservedObjectStorage<S: ?Sized> {
+  inner: Arc<S>,
+  observers: Observers,
+  metrics: Arc<StorageMetrics>,
+}
+
+impl<S> ObservedObjectStorage<S>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  pub fn new(inner: Arc<S>) -> Self {
+    Self {
+      inner,
+      observers: Default::default(),
+      metrics: Default::default(),
+    }
+  }
+
+  /// The counters of the operations observed since the storage was created.
+  pub fn metrics(&self) -> &Arc<StorageMetrics> {
+    &self.metrics
+  }
+
+  /// Attaches an observer. It's notified of the operations started after this call.
+  pub fn add_observer(&self, observer: Arc<dyn ObjectStorageObserver>) {
+    self.observers.write().push(observer);
+  }
+
+  /// Detaches an observer previously passed to [Self::add_observer].
+  pub fn remove_observer(&self, observer: &Arc<dyn ObjectStorageObserver>) {
+    self
+      .observers
+      .write()
+      .retain(|attached| !Arc::ptr_eq(attached, observer));
+  }
+
+  fn snapshot(&self) -> Vec<Arc<dyn ObjectStorageObserver>> {
+    let mut observers = self.observers.read().clone();
+    observers.push(self.metrics.clone());
+    observers
+  }
+
+  async fn observe_put(
+    &self,
+    url: String,
+    bytes: u64,
+    fut: impl Future<Output = Result<(), FlowyError>>,
+  ) -> Result<(), FlowyError> {
+    let observers = self.snapshot();
+    observers
+      .iter()
+      .for_each(|observer| observer.on_put_started(&url, bytes));
+    let started_at = Instant::now();
+    let result = fut.await;
+    let duration = started_at.elapsed();
+    observers.iter().for_each(|observer| {
+      observer.on_put_finished(&url, result.as_ref().map(|_| ()), bytes, duration)
+    });
+    result
+  }
+
+  async fn observe_get<T, F>(
+    &self,
+    url: String,
+    fut: impl Future<Output = Result<T, FlowyError>>,
+    size: F,
+  ) -> Result<T, FlowyError>
+  where
+    F: Fn(&T) -> u64,
+  {
+    let observers = self.snapshot();
+    observers
+      .iter()
+      .for_each(|observer| observer.on_get_started(&url));
+    let started_at = Instant::now();
+    let result = fut.await;
+    let duration = started_at.elapsed();
+    let bytes = result.as_ref().map(&size).unwrap_or(0);
+    observers.iter().for_each(|observer| {
+      observer.on_get_finished(&url, result.as_ref().map(|_| ()), bytes, duration)
+    });
+    result
+  }
+}
+
+#[async_trait]
+impl<S> ObjectStorageService for ObservedObjectStorage<S>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  async fn get_object_url(&self, object_id: ObjectIdentity) -> Result<String, FlowyError> {
+    self.inner.get_object_url(object_id).await
+  }
+
+  async fn put_object(&self, url: String, object_value: ObjectValue) -> Result<(), FlowyError> {
+    let bytes = object_value.raw.len() as u64;
+    let fut = self.inner.put_object(url.clone(), object_value);
+    self.observe_put(url, bytes, fut).await
+  }
+
+  async fn put_object_with_progress(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    progress: ProgressCallback,
+  ) -> Result<(), FlowyError> {
+    let bytes = object_value.raw.len() as u64;
+    let fut = self
+      .inner
+      .put_object_with_progress(url.clone(), object_value, progress);
+    self.observe_put(url, bytes, fut).await
+  }
+
+  fn supports_object_ttl(&self) -> bool {
+    self.inner.supports_object_ttl()
+  }
+
+  async fn put_object_with_ttl(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    ttl: Duration,
+  ) -> Result<(), FlowyError> {
+    let bytes = object_value.raw.len() as u64;
+    let fut = self
+      .inner
+      .put_object_with_ttl(url.clone(), object_value, ttl);
+    self.observe_put(url, bytes, fut).await
+  }
+
+  async fn put_object_if_absent(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+  ) -> Result<(), FlowyError> {
+    let bytes = object_value.raw.len() as u64;
+    let fut = self.inner.put_object_if_absent(url.clone(), object_value);
+    self.observe_put(url, bytes, fut).await
+  }
+
+  async fn put_object_if_match(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    etag: String,
+  ) -> Result<(), FlowyError> {
+    let bytes = object_value.raw.len() as u64;
+    let fut = self
+      .inner
+      .put_object_if_match(url.clone(), object_value, etag);
+    self.observe_put(url, bytes, fut).await
+  }
+
+  async fn delete_object(&self, url: String) -> Result<(), FlowyError> {
+    let observers = self.snapshot();
+    observers
+      .iter()
+      .for_each(|observer| observer.on_delete_started(&url));
+    let started_at = Instant::now();
+    let result = self.inner.delete_object(url.clone()).await;
+    let duration = started_at.elapsed();
+    observers.iter().for_each(|observer| {
+      observer.on_delete_finished(&url, result.as_ref().map(|_| ()), duration)
+    });
+    result
+  }
+
+  async fn delete_objects(
+    &self,
+    urls: Vec<String>,
+  ) -> Result<Vec<Result<(), FlowyError>>, FlowyError> {
+    let observers = self.snapshot();
+    for url in &urls {
+      observers
+        .iter()
+        .for_each(|observer| observer.on_delete_started(url));
+    }
+    let started_at = Instant::now();
+    let result = self.inner.delete_objects(urls.clone()).await;
+    let duration = started_at.elapsed();
+    match &result {
+      Ok(results) => {
+        for (url, result) in urls.iter().zip(results) {
+          observers.iter().for_each(|observer| {
+            observer.on_delete_finished(url, result.as_ref().map(|_| ()), duration)
+          });
+        }
+      },
+      Err(err) => {
+        for url in &urls {
+          observers
+            .iter()
+            .for_each(|observer| observer.on_delete_finished(url, Err(err), duration));
+        }
+      },
+    }
+    result
+  }
+
+  async fn delete_workspace_objects(&self, workspace_id: &str) -> Result<DeleteReport, FlowyError> {
+    // The objects are deleted by the inner service at once, their urls are not known to be
+    // reported to the observers.
+    self.inner.delete_workspace_objects(workspace_id).await
+  }
+
+  async fn list_objects(
+    &self,
+    workspace_id: &str,
+    prefix: Option<&str>,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    self.inner.list_objects(workspace_id, prefix).await
+  }
+
+  async fn list_objects_with_options(
+    &self,
+    workspace_id: &str,
+    options: &ListOptions,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    self
+      .inner
+      .list_objects_with_options(workspace_id, options)
+      .await
+  }
+
+  async fn get_object(&self, url: String) -> Result<ObjectValue, FlowyError> {
+    let fut = self.inner.get_object(url.clone());
+    self
+      .observe_get(url, fut, |value| value.raw.len() as u64)
+      .await
+  }
+
+  /// The download is reported as finished once the stream is opened, with the announced
+  /// content length.
+  async fn get_object_if_modified(
+    &self,
+    url: String,
+    etag: Option<String>,
+  ) -> Result<Option<(ObjectValue, String)>, FlowyError> {
+    let fut = self.inner.get_object_if_modified(url.clone(), etag);
+    self
+      .observe_get(url, fut, |modified| {
+        modified
+          .as_ref()
+          .map(|(value, _)| value.raw.len() as u64)
+          .unwrap_or_default()
+      })
+      .await
+  }
+
+  async fn get_object_stream(&self, url: String) -> Result<ObjectStream, FlowyError> {
+    let fut = self.inner.get_object_stream(url.clone());
+    self
+      .observe_get(url, fut, |object| object.content_length)
+      .await
+  }
+
+  async fn get_object_verified(
+    &self,
+    url: String,
+    expected_file_id: &str,
+  ) -> Result<ObjectValue, FlowyError> {
+    let fut = self
+      .inner
+      .get_object_verified(url.clone(), expected_file_id);
+    self
+      .observe_get(url, fut, |value| value.raw.len() as u64)
+      .await
+  }
+
+  fn supports_range_requests(&self) -> bool {
+    self.inner.supports_range_requests()
+  }
+
+  async fn get_object_range(
+    &self,
+    url: String,
+    start: u64,
+    end: Option<u64>,
+  ) -> Result<ObjectValue, FlowyError> {
+    let fut = self.inner.get_object_range(url.clone(), start, end);
+    self
+      .observe_get(url, fut, |value| value.raw.len() as u64)
+      .await
+  }
+
+  async fn head_object(&self, url: String) -> Result<ObjectMeta, FlowyError> {
+    self.inner.head_object(url).await
+  }
+
+  async fn object_exists(&self, url: String) -> Result<bool, FlowyError> {
+    self.inner.object_exists(url).await
+  }
+
+  async fn presign_get_url(&self, url: String, expires_in: Duration) -> Result<String, FlowyError> {
+    self.inner.presign_get_url(url, expires_in).await
+  }
+
+  async fn presign_put_url(&self, url: String, expires_in: Duration) -> Result<String, FlowyError> {
+    self.inner.presign_put_url(url, expires_in).await
+  }
+
+  fn supports_copy_object(&self) -> bool {
+    self.inner.supports_copy_object()
+  }
+
+  async fn copy_object(
+    &self,
+    src_url: String,
+    dst_identity: ObjectIdentity,
+  ) -> Result<String, FlowyError> {
+    self.inner.copy_object(src_url, dst_identity).await
+  }
+
+  async fn trash_object(&self, url: String) -> Result<(), FlowyError> {
+    self.inner.trash_object(url).await
+  }
+
+  async fn restore_object(&self, url: String) -> Result<(), FlowyError> {
+    self.inner.restore_object(url).await
+  }
+
+  async fn purge_trash(&self, older_than: Duration) -> Result<Vec<String>, FlowyError> {
+    self.inner.purge_trash(older_than).await
+  }
+
+  fn supports_versioning(&self) -> bool {
+    self.inner.supports_versioning()
+  }
+
+  async fn list_versions(&self, url: String) -> Result<Vec<VersionMeta>, FlowyError> {
+    self.inner.list_versions(url).await
+  }
+
+  async fn get_object_version(
+    &self,
+    url: String,
+    version_id: String,
+  ) -> Result<ObjectValue, FlowyError> {
+    self.inner.get_object_version(url, version_id).await
+  }
+
+  async fn restore_version(&self, url: String, version_id: String) -> Result<(), FlowyError> {
+    self.inner.restore_version(url, version_id).await
+  }
+
+  fn supports_multipart(&self) -> bool {
+    self.inner.supports_multipart()
+  }
+
+  async fn initiate_multipart(&self, url: String, mime: Mime) -> Result<UploadId, FlowyError> {
+    self.inner.initiate_multipart(url, mime).await
+  }
+
+  async fn upload_part(
+    &self,
+    url: String,
+    upload_id: UploadId,
+    part_number: u32,
+    bytes: Bytes,
+  ) -> Result<PartETag, FlowyError> {
+    self
+      .inner
+      .upload_part(url, upload_id, part_number, bytes)
+      .await
+  }
+
+  async fn complete_multipart(
+    &self,
+    url: String,
+    upload_id: UploadId,
+    parts: Vec<PartETag>,
+  ) -> Result<(), FlowyError> {
+    self.inner.complete_multipart(url, upload_id, parts).await
+  }
+
+  async fn abort_multipart(&self, url: String, upload_id: UploadId) -> Result<(), FlowyError> {
+    self.inner.abort_multipart(url, upload_id).await
+  }
+
+  async fn health_check(&self) -> Result<HealthStatus, FlowyError> {
+    self.inner.health_check().await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use parking_lot::Mutex;
+
+  use super::*;
+  use crate::{InMemoryObjectStorage, StorageOperation};
+
+  #[derive(Default)]
+  struct RecordingObserver {
+    events: Mutex<Vec<String>>,
+  }
+
+  impl ObjectStorageObserver for RecordingObserver {
+    fn on_put_started(&self, url: &str, bytes: u64) {
+      self.events.lock().push(format!("put {} {}", url, bytes));
+    }
+
+    fn on_put_finished(
+      &self,
+      url: &str,
+      result: Result<(), &FlowyError>,
+      bytes: u64,
+      _duration: Duration,
+    ) {
+      let event = format!("put done {} {} {}", url, result.is_ok(), bytes);
+      self.events.lock().push(event);
+    }
+
+    fn on_get_finished(
+      &self,
+      url: &str,
+      result: Result<(), &FlowyError>,
+      bytes: u64,
+      _duration: Duration,
+    ) {
+      let event = format!("get done {} {} {}", url, result.is_ok(), bytes);
+      self.events.lock().push(event);
+    }
+
+    fn on_delete_finished(&self, url: &str, result: Result<(), &FlowyError>, _duration: Duration) {
+      let event = format!("delete done {} {}", url, result.is_ok());
+      self.events.lock().push(event);
+    }
+  }
+
+  #[tokio::test]
+  async fn observers_are_notified_test() {
+    let inner = Arc::new(InMemoryObjectStorage::new());
+    let storage = ObservedObjectStorage::new(inner.clone());
+    let first = Arc::new(RecordingObserver::default());
+    let second = Arc::new(RecordingObserver::default());
+    storage.add_observer(first.clone());
+    storage.add_observer(second.clone());
+
+    let value = ObjectValue {
+      raw: vec![0; 4].into(),
+      mime: mime::TEXT_PLAIN,
+      content_encoding: None,
+    };
+    let url = "memory://w1/a.txt".to_string();
+    storage.put_object(url.clone(), value).await.unwrap();
+    storage.get_object(url.clone()).await.unwrap();
+    inner.fail_next(StorageOperation::Delete, FlowyError::internal());
+    assert!(storage.delete_object(url.clone()).await.is_err());
+
+    let expected = vec![
+      format!("put {} 4", url),
+      format!("put done {} true 4", url),
+      format!("get done {} true 4", url),
+      format!("delete done {} false", url),
+    ];
+    assert_eq!(*first.events.lock(), expected);
+    assert_eq!(*second.events.lock(), expected);
+
+    let second: Arc<dyn ObjectStorageObserver> = second;
+    storage.remove_observer(&second);
+    storage.get_object(url).await.unwrap();
+    assert_eq!(first.events.lock().len(), 5);
+  }
+}
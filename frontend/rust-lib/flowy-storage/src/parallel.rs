@@ -0,0 +1,201 @@
+use std::io::SeekFrom;
+use std::path::Path;
+
+use futures::{StreamExt, TryStreamExt};
+use tokio::fs::OpenOptions;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+
+use flowy_error::{ErrorCode, FlowyError};
+
+use crate::retry::retry;
+use crate::{atomic_write_with, ObjectStorageService, RetryPolicy};
+
+/// The default size of the ranges fetched by [download_parallel].
+pub const DEFAULT_DOWNLOAD_CHUNK_SIZE: u64 = 8 * 1024 * 1024;
+
+/// The default number of ranges fetched at the same time by [download_parallel].
+pub const DEFAULT_DOWNLOAD_CONCURRENCY: usize = 4;
+
+/// Downloads an object to a file by fetching up to `concurrency` ranges of `chunk_size` bytes at
+/// the same time, which uses the bandwidth of high latency links better than a single stream.
+/// Each range is retried on its own with the default [RetryPolicy], see
+/// [download_parallel_with_retry].
+///
+/// Returns the number of bytes written.
+pub async fn download_parallel<S>(
+  service: &S,
+  url: String,
+  dest: &Path,
+  chunk_size: u64,
+  concurrency: usize,
+) -> Result<u64, FlowyError>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  download_parallel_with_retry(
+    service,
+    url,
+    dest,
+    chunk_size,
+    concurrency,
+    RetryPolicy::default(),
+  )
+  .await
+}
+
+/// The variant of [download_parallel] that retries the failed ranges according to `policy`.
+///
+/// The object is downloaded with [ObjectStorageService::download_to_file] if the service doesn't
+/// fetch ranges without downloading the whole object, see
+/// [ObjectStorageService::supports_range_requests], or if it fits in a single chunk. Otherwise
+/// the ranges are written at their offset in a temporary file next to `dest`, which is renamed
+/// into place once its length matches the size of the object, so `dest` is never left half
+/// written.
+pub async fn download_parallel_with_retry<S>(
+  service: &S,
+  url: String,
+  dest: &Path,
+  chunk_size: u64,
+  concurrency: usize,
+  policy: RetryPolicy,
+) -> Result<u64, FlowyError>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  if !service.supports_range_requests() {
+    return service.download_to_file(url, dest).await;
+  }
+  let chunk_size = chunk_size.max(1);
+  let size = service.head_object(url.clone()).await?.size;
+  if size <= chunk_size {
+    return service.download_to_file(url, dest).await;
+  }
+
+  atomic_write_with(dest, |temp_path| async move {
+    download_ranges(
+      service,
+      &url,
+      &temp_path,
+      size,
+      chunk_size,
+      concurrency,
+      policy,
+    )
+    .await?;
+    Ok(size)
+  })
+  .await
+}
+
+async fn download_ranges<S>(
+  service: &S,
+  url: &str,
+  path: &Path,
+  size: u64,
+  chunk_size: u64,
+  concurrency: usize,
+  policy: RetryPolicy,
+) -> Result<(), FlowyError>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  tokio::fs::File::create(path).await?.set_len(size).await?;
+
+  let ranges = (0..size)
+    .step_by(chunk_size as usize)
+    .map(|start| (start, (start + chunk_size).min(size) - 1));
+  futures::stream::iter(ranges)
+    .map(|(start, end)| {
+      let policy = policy.clone();
+      async move {
+        let value = retry(policy, || {
+          service.get_object_range(url.to_string(), start, Some(end))
+        })
+        .await?;
+        let expected = end - start + 1;
+        if value.raw.len() as u64 != expected {
+          return Err(FlowyError::new(
+            ErrorCode::Internal,
+            format!(
+              "range {}-{} of {} returned {} bytes, expected {} bytes",
+              start,
+              end,
+              url,
+              value.raw.len(),
+              expected
+            ),
+          ));
+        }
+        let mut file = OpenOptions::new().write(true).open(path).await?;
+        file.seek(SeekFrom::Start(start)).await?;
+        file.write_all(&value.raw).await?;
+        file.sync_all().await?;
+        Ok(())
+      }
+    })
+    .buffer_unordered(concurrency.max(1))
+    .try_collect::<Vec<_>>()
+    .await?;
+
+  let written = tokio::fs::metadata(path).await?.len();
+  if written != size {
+    return Err(FlowyError::new(
+      ErrorCode::Internal,
+      format!(
+        "download of {} has {} bytes, expected {} bytes",
+        url, written, size
+      ),
+    ));
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use super::*;
+  use crate::{memory_object_value, InMemoryObjectStorage, ObjectIdentity, StorageOperation};
+
+  fn policy() -> RetryPolicy {
+    RetryPolicy {
+      max_attempts: 3,
+      base_delay: Duration::from_millis(1),
+      max_delay: Duration::from_millis(2),
+      retry_put: false,
+    }
+  }
+
+  #[tokio::test]
+  async fn download_parallel_test() {
+    let storage = InMemoryObjectStorage::new();
+    let identity = ObjectIdentity {
+      workspace_id: "w1".to_string(),
+      file_id: "big".to_string(),
+      ext: "bin".to_string(),
+      hash_algorithm: None,
+      size: None,
+    };
+    let url = storage.get_object_url(identity).await.unwrap();
+    let content = (0..10_000u32).map(|i| i as u8).collect::<Vec<_>>();
+    storage
+      .put_object(url.clone(), memory_object_value("big.bin", content.clone()))
+      .await
+      .unwrap();
+
+    // One range fails once, only that range is fetched again.
+    storage.fail_next(
+      StorageOperation::GetRange,
+      FlowyError::new(ErrorCode::ConnectTimeout, "timeout"),
+    );
+    let dir = tempfile::tempdir().unwrap();
+    let dest = dir.path().join("big.bin");
+    let written = download_parallel_with_retry(&storage, url, &dest, 1000, 4, policy())
+      .await
+      .unwrap();
+    assert_eq!(written, 10_000);
+    assert_eq!(tokio::fs::read(&dest).await.unwrap(), content);
+    assert_eq!(storage.call_count(StorageOperation::GetRange), 11);
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+  }
+}
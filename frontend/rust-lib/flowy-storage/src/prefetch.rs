@@ -0,0 +1,432 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use mime::Mime;
+use tokio::sync::{Notify, Semaphore};
+use tracing::trace;
+
+use flowy_error::FlowyError;
+
+use crate::{
+  cancellable, CancellationToken, DeleteReport, HealthStatus, ListOptions, ObjectIdentity,
+  ObjectMeta, ObjectStorageService, ObjectStream, ObjectValue, PartETag, ProgressCallback,
+  StorageErrorExt, StorageErrorKind, UploadId, VersionMeta,
+};
+
+/// Controls the background downloads of [PrefetchingObjectStorage::prefetch].
+#[derive(Debug, Clone)]
+pub struct PrefetchConfig {
+  /// The number of objects prefetched at the same time, across all the calls of
+  /// [PrefetchingObjectStorage::prefetch].
+  pub max_concurrent: usize,
+}
+
+impl Default for PrefetchConfig {
+  fn default() -> Self {
+    Self { max_concurrent: 2 }
+  }
+}
+
+/// Counts the downloads requested by the user, the prefetches wait until there are none.
+#[derive(Default)]
+struct OnDemand {
+  in_flight: AtomicUsize,
+  idle: Notify,
+}
+
+impl OnDemand {
+  async fn wait_idle(&self) {
+    loop {
+      // Created before the check so that a notification sent in between is not missed.
+      let idle = self.idle.notified();
+      if self.in_flight.load(Ordering::SeqCst) == 0 {
+        return;
+      }
+      idle.await;
+    }
+  }
+}
+
+struct OnDemandGuard<'a>(&'a OnDemand);
+
+impl<'a> OnDemandGuard<'a> {
+  fn new(on_demand: &'a OnDemand) -> Self {
+    on_demand.in_flight.fetch_add(1, Ordering::SeqCst);
+    Self(on_demand)
+  }
+}
+
+impl Drop for OnDemandGuard<'_> {
+  fn drop(&mut self) {
+    if self.0.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+      self.0.idle.notify_waiters();
+    }
+  }
+}
+
+/// An [ObjectStorageService] that can download objects in the background before they are
+/// requested, for example the attachments of the board the user just opened.
+///
+/// Put it above a [crate::CachingObjectStorage] or a [crate::DiskCachedObjectStorage], so that
+/// the prefetched objects are cached and the next [ObjectStorageService::get_object] returns
+/// them without a request, and above the [crate::ThrottledObjectStorage] so that the prefetches
+/// share its bandwidth.
+pub struct PrefetchingObjectStorage<S: ?Sized> {
+  inner: Arc<S>,
+  permits: Arc<Semaphore>,
+  on_demand: Arc<OnDemand>,
+}
+
+impl<S> PrefetchingObjectStorage<S>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  pub fn new(inner: Arc<S>, config: PrefetchConfig) -> Self {
+    Self {
+      inner,
+      permits: Arc::new(Semaphore::new(config.max_concurrent.max(1))),
+      on_demand: Arc::new(OnDemand::default()),
+    }
+  }
+
+  /// Starts downloading `urls` in the background and returns right away. Cancelling the
+  /// returned token stops the downloads that are not finished.
+  ///
+  /// The prefetches have a lower priority than the downloads requested through this storage:
+  /// a prefetch only starts when no such download is in flight. A prefetch that already started
+  /// is not interrupted. The failures are ignored, the object is downloaded again when it's
+  /// requested.
+  pub fn prefetch(&self, urls: Vec<String>) -> CancellationToken {
+    let cancel = CancellationToken::new();
+    for url in urls {
+      let inner = self.inner.clone();
+      let permits = self.permits.clone();
+      let on_demand = self.on_demand.clone();
+      let cancel = cancel.clone();
+      tokio::spawn(async move {
+        let fetch = async {
+          let _permit = permits
+            .acquire()
+            .await
+            .map_err(|err| FlowyError::internal().with_context(err))?;
+          on_demand.wait_idle().await;
+          inner.get_object(url.clone()).await
+        };
+        match cancellable(fetch, &cancel).await {
+          Ok(_) => trace!("prefetched {}", url),
+          Err(err) if err.is_storage_kind(StorageErrorKind::Cancelled) => {},
+          Err(err) => trace!("failed to prefetch {}: {}", url, err),
+        }
+      });
+    }
+    cancel
+  }
+
+  async fn on_demand<T>(
+    &self,
+    fut: impl Future<Output = Result<T, FlowyError>>,
+  ) -> Result<T, FlowyError> {
+    let _guard = OnDemandGuard::new(&self.on_demand);
+    fut.await
+  }
+}
+
+#[async_trait]
+impl<S> ObjectStorageService for PrefetchingObjectStorage<S>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  async fn get_object_url(&self, object_id: ObjectIdentity) -> Result<String, FlowyError> {
+    self.inner.get_object_url(object_id).await
+  }
+
+  async fn put_object(&self, url: String, object_value: ObjectValue) -> Result<(), FlowyError> {
+    self.inner.put_object(url, object_value).await
+  }
+
+  async fn put_object_with_progress(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    progress: ProgressCallback,
+  ) -> Result<(), FlowyError> {
+    self
+      .inner
+      .put_object_with_progress(url, object_value, progress)
+      .await
+  }
+
+  fn supports_object_ttl(&self) -> bool {
+    self.inner.supports_object_ttl()
+  }
+
+  async fn put_object_with_ttl(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    ttl: Duration,
+  ) -> Result<(), FlowyError> {
+    self.inner.put_object_with_ttl(url, object_value, ttl).await
+  }
+
+  async fn put_object_if_absent(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+  ) -> Result<(), FlowyError> {
+    self.inner.put_object_if_absent(url, object_value).await
+  }
+
+  async fn put_object_if_match(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    etag: String,
+  ) -> Result<(), FlowyError> {
+    self
+      .inner
+      .put_object_if_match(url, object_value, etag)
+      .await
+  }
+
+  async fn delete_object(&self, url: String) -> Result<(), FlowyError> {
+    self.inner.delete_object(url).await
+  }
+
+  async fn delete_objects(
+    &self,
+    urls: Vec<String>,
+  ) -> Result<Vec<Result<(), FlowyError>>, FlowyError> {
+    self.inner.delete_objects(urls).await
+  }
+
+  async fn delete_workspace_objects(&self, workspace_id: &str) -> Result<DeleteReport, FlowyError> {
+    self.inner.delete_workspace_objects(workspace_id).await
+  }
+
+  async fn list_objects(
+    &self,
+    workspace_id: &str,
+    prefix: Option<&str>,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    self.inner.list_objects(workspace_id, prefix).await
+  }
+
+  async fn list_objects_with_options(
+    &self,
+    workspace_id: &str,
+    options: &ListOptions,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    self
+      .inner
+      .list_objects_with_options(workspace_id, options)
+      .await
+  }
+
+  async fn get_object(&self, url: String) -> Result<ObjectValue, FlowyError> {
+    self.on_demand(self.inner.get_object(url)).await
+  }
+
+  async fn get_object_if_modified(
+    &self,
+    url: String,
+    etag: Option<String>,
+  ) -> Result<Option<(ObjectValue, String)>, FlowyError> {
+    self
+      .on_demand(self.inner.get_object_if_modified(url, etag))
+      .await
+  }
+
+  async fn get_object_stream(&self, url: String) -> Result<ObjectStream, FlowyError> {
+    self.on_demand(self.inner.get_object_stream(url)).await
+  }
+
+  async fn get_object_verified(
+    &self,
+    url: String,
+    expected_file_id: &str,
+  ) -> Result<ObjectValue, FlowyError> {
+    self
+      .on_demand(self.inner.get_object_verified(url, expected_file_id))
+      .await
+  }
+
+  fn supports_range_requests(&self) -> bool {
+    self.inner.supports_range_requests()
+  }
+
+  async fn get_object_range(
+    &self,
+    url: String,
+    start: u64,
+    end: Option<u64>,
+  ) -> Result<ObjectValue, FlowyError> {
+    self
+      .on_demand(self.inner.get_object_range(url, start, end))
+      .await
+  }
+
+  async fn head_object(&self, url: String) -> Result<ObjectMeta, FlowyError> {
+    self.inner.head_object(url).await
+  }
+
+  async fn object_exists(&self, url: String) -> Result<bool, FlowyError> {
+    self.inner.object_exists(url).await
+  }
+
+  async fn presign_get_url(&self, url: String, expires_in: Duration) -> Result<String, FlowyError> {
+    self.inner.presign_get_url(url, expires_in).await
+  }
+
+  async fn presign_put_url(&self, url: String, expires_in: Duration) -> Result<String, FlowyError> {
+    self.inner.presign_put_url(url, expires_in).await
+  }
+
+  fn supports_copy_object(&self) -> bool {
+    self.inner.supports_copy_object()
+  }
+
+  async fn copy_object(
+    &self,
+    src_url: String,
+    dst_identity: ObjectIdentity,
+  ) -> Result<String, FlowyError> {
+    self.inner.copy_object(src_url, dst_identity).await
+  }
+
+  async fn trash_object(&self, url: String) -> Result<(), FlowyError> {
+    self.inner.trash_object(url).await
+  }
+
+  async fn restore_object(&self, url: String) -> Result<(), FlowyError> {
+    self.inner.restore_object(url).await
+  }
+
+  async fn purge_trash(&self, older_than: Duration) -> Result<Vec<String>, FlowyError> {
+    self.inner.purge_trash(older_than).await
+  }
+
+  fn supports_versioning(&self) -> bool {
+    self.inner.supports_versioning()
+  }
+
+  async fn list_versions(&self, url: String) -> Result<Vec<VersionMeta>, FlowyError> {
+    self.inner.list_versions(url).await
+  }
+
+  async fn get_object_version(
+    &self,
+    url: String,
+    version_id: String,
+  ) -> Result<ObjectValue, FlowyError> {
+    self
+      .on_demand(self.inner.get_object_version(url, version_id))
+      .await
+  }
+
+  async fn restore_version(&self, url: String, version_id: String) -> Result<(), FlowyError> {
+    self.inner.restore_version(url, version_id).await
+  }
+
+  fn supports_multipart(&self) -> bool {
+    self.inner.supports_multipart()
+  }
+
+  async fn initiate_multipart(&self, url: String, mime: Mime) -> Result<UploadId, FlowyError> {
+    self.inner.initiate_multipart(url, mime).await
+  }
+
+  async fn upload_part(
+    &self,
+    url: String,
+    upload_id: UploadId,
+    part_number: u32,
+    bytes: Bytes,
+  ) -> Result<PartETag, FlowyError> {
+    self
+      .inner
+      .upload_part(url, upload_id, part_number, bytes)
+      .await
+  }
+
+  async fn complete_multipart(
+    &self,
+    url: String,
+    upload_id: UploadId,
+    parts: Vec<PartETag>,
+  ) -> Result<(), FlowyError> {
+    self.inner.complete_multipart(url, upload_id, parts).await
+  }
+
+  async fn abort_multipart(&self, url: String, upload_id: UploadId) -> Result<(), FlowyError> {
+    self.inner.abort_multipart(url, upload_id).await
+  }
+
+  async fn health_check(&self) -> Result<HealthStatus, FlowyError> {
+    self.inner.health_check().await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    memory_object_value, CacheConfig, CachingObjectStorage, InMemoryObjectStorage, StorageOperation,
+  };
+
+  fn prefetching_storage() -> (
+    Arc<InMemoryObjectStorage>,
+    PrefetchingObjectStorage<CachingObjectStorage<InMemoryObjectStorage>>,
+  ) {
+    let inner = Arc::new(InMemoryObjectStorage::new());
+    let cached = Arc::new(CachingObjectStorage::new(
+      inner.clone(),
+      CacheConfig::default(),
+    ));
+    let storage = PrefetchingObjectStorage::new(cached, PrefetchConfig::default());
+    (inner, storage)
+  }
+
+  #[tokio::test]
+  async fn prefetch_test() {
+    let (inner, storage) = prefetching_storage();
+    let mut urls = vec![];
+    for name in ["1.txt", "2.txt"] {
+      let url = format!("memory://w1/{}", name);
+      storage
+        .put_object(url.clone(), memory_object_value(name, "hello"))
+        .await
+        .unwrap();
+      urls.push(url);
+    }
+
+    storage.prefetch(urls.clone());
+    while inner.call_count(StorageOperation::Get) < 2 {
+      tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    for url in urls {
+      let value = storage.get_object(url).await.unwrap();
+      assert_eq!(value.raw, Bytes::from("hello"));
+    }
+    assert_eq!(inner.call_count(StorageOperation::Get), 2);
+  }
+
+  #[tokio::test]
+  async fn cancel_prefetch_test() {
+    let (inner, storage) = prefetching_storage();
+    let url = "memory://w1/1.txt".to_string();
+    storage
+      .put_object(url.clone(), memory_object_value("1.txt", "hello"))
+      .await
+      .unwrap();
+
+    // The test runtime is single threaded, so the prefetch can't start before it's cancelled.
+    storage.prefetch(vec![url.clone()]).cancel();
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    assert_eq!(inner.call_count(StorageOperation::Get), 0);
+  }
+}
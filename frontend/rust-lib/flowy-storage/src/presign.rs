@@ -0,0 +1,44 @@
+use std::time::Duration;
+
+use flowy_error::FlowyError;
+
+/// The longest lifetime of a presigned URL. It's the limit of the S3 signature v4, so a presigned
+/// URL with this lifetime is accepted by every S3 compatible backend.
+pub const MAX_PRESIGN_EXPIRATION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// The shortest lifetime of a presigned URL.
+pub const MIN_PRESIGN_EXPIRATION: Duration = Duration::from_secs(1);
+
+/// Clamps the lifetime of a presigned URL to [MIN_PRESIGN_EXPIRATION] and
+/// [MAX_PRESIGN_EXPIRATION].
+pub fn clamp_presign_expiration(expires_in: Duration) -> Duration {
+  expires_in.clamp(MIN_PRESIGN_EXPIRATION, MAX_PRESIGN_EXPIRATION)
+}
+
+pub(crate) fn presign_not_support(method: &str) -> FlowyError {
+  FlowyError::not_support().with_context(format!(
+    "presigned {} urls are not supported by the storage",
+    method
+  ))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn clamp_presign_expiration_test() {
+    assert_eq!(
+      clamp_presign_expiration(Duration::ZERO),
+      MIN_PRESIGN_EXPIRATION
+    );
+    assert_eq!(
+      clamp_presign_expiration(Duration::from_secs(60)),
+      Duration::from_secs(60)
+    );
+    assert_eq!(
+      clamp_presign_expiration(Duration::from_secs(30 * 24 * 60 * 60)),
+      MAX_PRESIGN_EXPIRATION
+    );
+  }
+}
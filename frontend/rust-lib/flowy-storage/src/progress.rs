@@ -0,0 +1,276 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::{StreamExt, TryStreamExt};
+
+use crate::ObjectByteStream;
+
+/// Receives the progress of a transfer. The first argument is the number of bytes transferred
+/// so far and the second one is the total number of bytes.
+pub type ProgressCallback = Box<dyn Fn(u64, u64) + Send + Sync>;
+
+/// Receives the progress of a transfer whose size may be unknown. The first argument is the
+/// number of bytes transferred so far and the second one is the total number of bytes, `None` if
+/// it's not known before the transfer ends.
+pub type TransferProgressCallback = Box<dyn Fn(u64, Option<u64>) + Send + Sync>;
+
+/// Receives the progress of a download. The first argument is the number of bytes downloaded so
+/// far and the second one is the size of the object, `None` if the server didn't tell it.
+pub type DownloadProgressCallback = TransferProgressCallback;
+
+/// The shortest time between two calls of a [TransferProgressCallback], so a fast transfer of
+/// many small chunks doesn't flood the UI.
+pub const DOWNLOAD_PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The default of [crate::StorageConfig::progress_rate_window].
+pub const DEFAULT_PROGRESS_RATE_WINDOW: Duration = Duration::from_secs(3);
+
+/// Estimates the speed of a transfer from its progress, and the time left from the speed.
+///
+/// The speed is an exponentially weighted moving average of the speed between two reports,
+/// where a report weighs as much as the time it covers relative to the `window`. A short window
+/// follows the changes of the network quickly, a long one gives an estimate that doesn't jump
+/// around from one chunk to the next. A zero window doesn't smooth at all.
+#[derive(Debug, Clone)]
+pub struct TransferRate {
+  window: Duration,
+  bytes_per_second: Option<f64>,
+  last: Option<(Instant, u64)>,
+}
+
+impl TransferRate {
+  pub fn new(window: Duration) -> Self {
+    Self {
+      window,
+      bytes_per_second: None,
+      last: None,
+    }
+  }
+
+  /// Records that `transferred` bytes were transferred so far.
+  pub fn record(&mut self, transferred: u64) {
+    self.record_at(Instant::now(), transferred);
+  }
+
+  fn record_at(&mut self, now: Instant, transferred: u64) {
+    let Some((last_time, last_transferred)) = self.last else {
+      self.last = Some((now, transferred));
+      return;
+    };
+    let elapsed = now.saturating_duration_since(last_time).as_secs_f64();
+    // The bytes of the reports made at the same time are counted with the next report.
+    if elapsed <= 0.0 || transferred < last_transferred {
+      return;
+    }
+    let current = (transferred - last_transferred) as f64 / elapsed;
+    let weight = 1.0 - (-elapsed / self.window.as_secs_f64()).exp();
+    self.bytes_per_second = Some(match self.bytes_per_second {
+      Some(rate) => rate + weight * (current - rate),
+      None => current,
+    });
+    self.last = Some((now, transferred));
+  }
+
+  /// The smoothed speed, `None` until two reports were recorded.
+  pub fn bytes_per_second(&self) -> Option<u64> {
+    self.bytes_per_second.map(|rate| rate.round() as u64)
+  }
+
+  /// The time left to transfer the `total` bytes at the current speed. `None` if the total is
+  /// unknown, or if nothing was transferred lately.
+  pub fn eta(&self, total: Option<u64>) -> Option<Duration> {
+    let (_, transferred) = self.last?;
+    let remaining = total?.saturating_sub(transferred);
+    if remaining == 0 {
+      return Some(Duration::ZERO);
+    }
+    let rate = self.bytes_per_second.filter(|rate| *rate > 0.0)?;
+    Some(Duration::from_secs_f64(remaining as f64 / rate))
+  }
+}
+
+/// Forwards the progress of a transfer to a [ProgressCallback].
+///
+/// The callback is called by the task that reports the progress, without a runtime of its own,
+/// so it should return quickly, for example by sending the progress to the UI through a channel.
+#[derive(Clone)]
+pub struct ProgressReporter {
+  total: u64,
+  callback: Arc<ProgressCallback>,
+}
+
+impl ProgressReporter {
+  pub fn new(total: u64, callback: ProgressCallback) -> Self {
+    callback(0, total);
+    Self {
+      total,
+      callback: Arc::new(callback),
+    }
+  }
+
+  pub fn total(&self) -> u64 {
+    self.total
+  }
+
+  pub fn report(&self, transferred: u64) {
+    (self.callback)(transferred, self.total);
+  }
+
+  pub fn finish(&self) {
+    self.report(self.total);
+  }
+}
+
+/// Wraps the stream so that the progress is reported every time a chunk is pulled out of it.
+/// An HTTP client consumes the body stream as the chunks are flushed to the network, which makes
+/// the reported progress follow the upload.
+pub fn progress_stream(stream: ObjectByteStream, reporter: ProgressReporter) -> ObjectByteStream {
+  let mut transferred = 0;
+  Box::pin(stream.inspect_ok(move |chunk| {
+    transferred += chunk.len() as u64;
+    reporter.report(transferred);
+  }))
+}
+
+/// Wraps the stream of a download so that the progress is reported as the chunks arrive, see
+/// [transfer_progress_stream].
+pub fn download_progress_stream(
+  stream: ObjectByteStream,
+  total: Option<u64>,
+  callback: DownloadProgressCallback,
+) -> ObjectByteStream {
+  transfer_progress_stream(stream, total, callback)
+}
+
+/// Wraps the stream so that the progress is reported as the chunks are pulled out of it, by a
+/// download as they arrive or by an upload as they are sent. Like [ProgressReporter], the
+/// callback is called by the task polling the stream, at most once per
+/// [DOWNLOAD_PROGRESS_INTERVAL]. The last progress is always delivered once the stream ends.
+pub fn transfer_progress_stream(
+  stream: ObjectByteStream,
+  total: Option<u64>,
+  callback: TransferProgressCallback,
+) -> ObjectByteStream {
+  let progress = ThrottledProgress::new(total, callback);
+  Box::pin(futures::stream::unfold(
+    (stream, progress),
+    |(mut stream, mut progress)| async move {
+      let chunk = stream.next().await;
+      match &chunk {
+        Some(Ok(chunk)) => progress.add(chunk.len() as u64),
+        Some(Err(_)) => {},
+        None => progress.flush(),
+      }
+      Some((chunk?, (stream, progress)))
+    },
+  ))
+}
+
+/// Calls a [TransferProgressCallback] at most once per [DOWNLOAD_PROGRESS_INTERVAL].
+struct ThrottledProgress {
+  callback: TransferProgressCallback,
+  total: Option<u64>,
+  transferred: u64,
+  reported: u64,
+  reported_at: Instant,
+}
+
+impl ThrottledProgress {
+  fn new(total: Option<u64>, callback: TransferProgressCallback) -> Self {
+    callback(0, total);
+    Self {
+      callback,
+      total,
+      transferred: 0,
+      reported: 0,
+      reported_at: Instant::now(),
+    }
+  }
+
+  fn add(&mut self, len: u64) {
+    self.transferred += len;
+    if self.reported_at.elapsed() >= DOWNLOAD_PROGRESS_INTERVAL {
+      self.report();
+    }
+  }
+
+  /// Delivers the progress that was held back.
+  fn flush(&mut self) {
+    if self.reported != self.transferred {
+      self.report();
+    }
+  }
+
+  fn report(&mut self) {
+    (self.callback)(self.transferred, self.total);
+    self.reported = self.transferred;
+    self.reported_at = Instant::now();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use bytes::Bytes;
+  use parking_lot::Mutex;
+
+  #[test]
+  fn report_without_runtime_test() {
+    let reports = Arc::new(Mutex::new(vec![]));
+    let cloned = reports.clone();
+    let reporter = ProgressReporter::new(
+      10,
+      Box::new(move |transferred, total| cloned.lock().push((transferred, total))),
+    );
+    reporter.report(4);
+    reporter.finish();
+    assert_eq!(*reports.lock(), vec![(0, 10), (4, 10), (10, 10)]);
+
+    let cloned = reports.clone();
+    reports.lock().clear();
+    let chunks = (0..3).map(|_| Ok(Bytes::from_static(b"abc")));
+    let stream = transfer_progress_stream(
+      Box::pin(futures::stream::iter(chunks)),
+      None,
+      Box::new(move |transferred, total| cloned.lock().push((transferred, total.unwrap_or(0)))),
+    );
+    let chunks = futures::executor::block_on(stream.try_collect::<Vec<_>>()).unwrap();
+    assert_eq!(chunks.len(), 3);
+    // The chunks came faster than the interval, only the first and the last progress are sent.
+    assert_eq!(*reports.lock(), vec![(0, 0), (9, 0)]);
+  }
+
+  #[test]
+  fn transfer_rate_test() {
+    let start = Instant::now();
+    let at = |millis| start + Duration::from_millis(millis);
+    let mut rate = TransferRate::new(Duration::from_secs(1));
+    rate.record_at(at(0), 0);
+    assert_eq!(rate.bytes_per_second(), None);
+    assert_eq!(rate.eta(Some(1000)), None);
+
+    rate.record_at(at(1000), 100);
+    assert_eq!(rate.bytes_per_second(), Some(100));
+    assert_eq!(rate.eta(Some(1000)), Some(Duration::from_secs(9)));
+    assert_eq!(rate.eta(None), None);
+
+    // A burst moves the estimate toward the new speed without jumping to it.
+    rate.record_at(at(1100), 200);
+    let smoothed = rate.bytes_per_second().unwrap();
+    assert!(smoothed > 100 && smoothed < 1000, "{}", smoothed);
+
+    // Reports made at the same time don't divide by zero.
+    rate.record_at(at(1100), 300);
+    assert_eq!(rate.bytes_per_second(), Some(smoothed));
+
+    rate.record_at(at(1200), 1000);
+    assert_eq!(rate.eta(Some(1000)), Some(Duration::ZERO));
+
+    // Without smoothing the estimate is the speed of the last report.
+    let mut rate = TransferRate::new(Duration::ZERO);
+    rate.record_at(at(0), 0);
+    rate.record_at(at(1000), 100);
+    rate.record_at(at(1500), 1100);
+    assert_eq!(rate.bytes_per_second(), Some(2000));
+  }
+}
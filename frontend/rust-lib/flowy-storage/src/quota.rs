@@ -0,0 +1,758 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+
+use flowy_error::FlowyError;
+
+use crate::{
+  storage_error, workspace_id_from_url, DeleteReport, FileStoragePlan, HealthStatus, ListOptions,
+  ObjectIdentity, ObjectMeta, ObjectStorageService, ObjectStream, ObjectValue, ProgressCallback,
+  StorageErrorKind, StorageObject, VersionMeta,
+};
+
+/// Why an upload was rejected by the storage limit, carried as JSON in the
+/// [FlowyError::payload] of the [StorageErrorKind::QuotaExceeded] error so the UI can tell the
+/// user how much room is left. All the sizes are in bytes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuotaExceededDetails {
+  /// The bytes stored before the upload.
+  pub used: u64,
+  pub limit: u64,
+  pub incoming_size: u64,
+  /// How many bytes the upload goes over the limit.
+  pub overage: u64,
+}
+
+impl QuotaExceededDetails {
+  /// Returns the [StorageErrorKind::QuotaExceeded] error if `incoming_size` more bytes don't fit
+  /// in the `limit`.
+  pub fn check(used: u64, limit: u64, incoming_size: u64) -> Result<(), FlowyError> {
+    let total = used.saturating_add(incoming_size);
+    if total <= limit {
+      return Ok(());
+    }
+    let details = Self {
+      used,
+      limit,
+      incoming_size,
+      overage: total - limit,
+    };
+    Err(details.into_error())
+  }
+
+  /// The details of a rejected upload, `None` if the error is not a quota error or was not
+  /// built by [Self::check].
+  pub fn from_error(err: &FlowyError) -> Option<Self> {
+    if StorageErrorKind::of(err) != Some(StorageErrorKind::QuotaExceeded) {
+      return None;
+    }
+    serde_json::from_slice(&err.payload).ok()
+  }
+
+  /// The bytes the user could still upload.
+  pub fn remaining(&self) -> u64 {
+    self.limit.saturating_sub(self.used)
+  }
+
+  fn into_error(self) -> FlowyError {
+    let mut err = storage_error(
+      StorageErrorKind::QuotaExceeded,
+      format!(
+        "the upload of {} bytes is {} bytes over the storage limit of {} bytes, {} bytes are left",
+        self.incoming_size,
+        self.overage,
+        self.limit,
+        self.remaining()
+      ),
+    );
+    err.payload = serde_json::to_vec(&self).unwrap_or_default();
+    err
+  }
+}
+
+/// The default [FileStoragePlan::check_upload_object]: the object must fit in the
+/// [FileStoragePlan::storage_limit] next to the [FileStoragePlan::storage_size].
+pub(crate) async fn check_storage_quota<P>(
+  plan: &P,
+  object: &StorageObject,
+) -> Result<(), FlowyError>
+where
+  P: FileStoragePlan + ?Sized,
+{
+  plan.upload_type_policy().check(object)?;
+  if let Some(limit) = plan.storage_limit().await? {
+    let used = plan.storage_size().await?;
+    QuotaExceededDetails::check(used, limit, object.file_size_async().await?)?;
+  }
+  Ok(())
+}
+
+/// Provides the [FileStoragePlan] of each workspace, the workspaces can be on different tiers.
+pub trait FileStoragePlanProvider: Send + Sync + 'static {
+  /// The plan of the workspace. An empty `workspace_id` is passed for the uploads whose workspace
+  /// isn't known.
+  fn plan_for_workspace(&self, workspace_id: &str) -> Arc<dyn FileStoragePlan>;
+}
+
+/// Holds the plan of the workspaces whose plan was set, the others get the default plan.
+pub struct WorkspacePlans {
+  default_plan: Arc<dyn FileStoragePlan>,
+  plans: RwLock<HashMap<String, Arc<dyn FileStoragePlan>>>,
+}
+
+impl WorkspacePlans {
+  pub fn new(default_plan: Arc<dyn FileStoragePlan>) -> Self {
+    Self {
+      default_plan,
+      plans: Default::default(),
+    }
+  }
+
+  pub fn set_plan(&self, workspace_id: &str, plan: Arc<dyn FileStoragePlan>) {
+    self.plans.write().insert(workspace_id.to_string(), plan);
+  }
+
+  pub fn remove_plan(&self, workspace_id: &str) {
+    self.plans.write().remove(workspace_id);
+  }
+}
+
+impl FileStoragePlanProvider for WorkspacePlans {
+  fn plan_for_workspace(&self, workspace_id: &str) -> Arc<dyn FileStoragePlan> {
+    self
+      .plans
+      .read()
+      .get(workspace_id)
+      .cloned()
+      .unwrap_or_else(|| self.default_plan.clone())
+  }
+}
+
+/// An [ObjectStorageService] that checks every upload against the [FileStoragePlan] of its
+/// workspace before sending it to the inner service: the type of the object must be allowed, see
+/// [FileStoragePlan::upload_type_policy], the object must not be larger than the limit of its
+/// mime type, see [FileStoragePlan::mime_size_limits], and
+/// [FileStoragePlan::check_upload_object] must accept it. The error of a rejected upload is
+/// returned as is, nothing is uploaded.
+///
+/// The storage size reported by each plan is cached and updated locally with the uploads and the
+/// deletions going through the wrapper, see [Self::storage_size].
+///
+/// The uploads that can't be checked up front are not supported: multipart uploads, so
+/// [crate::put_object_in_parts] falls back to [ObjectStorageService::put_object], copies, so
+/// [crate::copy_object_or_reupload] downloads and uploads the object again, and presigned upload
+/// urls.
+pub struct PlanEnforcingObjectStorage<S: ?Sized> {
+  inner: Arc<S>,
+  plans: Arc<dyn FileStoragePlanProvider>,
+  usage: Arc<Mutex<Usage>>,
+}
+
+/// Identifies a plan in [Usage], the workspaces that share a plan share its storage size.
+type PlanKey = usize;
+
+fn plan_key(plan: &Arc<dyn FileStoragePlan>) -> PlanKey {
+  Arc::as_ptr(plan) as *const () as usize
+}
+
+#[derive(Default)]
+struct Usage {
+  storage_sizes: HashMap<PlanKey, u64>,
+  /// The workspace of the urls returned by `get_object_url` that weren't uploaded yet, used to
+  /// pick the plan and to build the [StorageObject] passed to it.
+  workspaces: HashMap<String, String>,
+  /// The plan and the size of the objects uploaded through the wrapper, freed when they are
+  /// deleted.
+  sizes: HashMap<String, (PlanKey, u64)>,
+}
+
+/// Dropping the workspaces of the urls that are never uploaded keeps the map from growing
+/// forever. The objects uploaded afterwards are checked without their workspace.
+const MAX_PENDING_URLS: usize = 1024;
+
+impl Usage {
+  fn uploaded(&mut self, url: String, plan: PlanKey, size: u64) {
+    if let Some((previous_plan, previous)) = self.sizes.insert(url, (plan, size)) {
+      if let Some(storage_size) = self.storage_sizes.get_mut(&previous_plan) {
+        *storage_size = storage_size.saturating_sub(previous);
+      }
+    }
+    if let Some(storage_size) = self.storage_sizes.get_mut(&plan) {
+      *storage_size += size;
+    }
+  }
+
+  fn deleted(&mut self, url: &str) {
+    match self.sizes.remove(url) {
+      Some((plan, size)) => {
+        if let Some(storage_size) = self.storage_sizes.get_mut(&plan) {
+          *storage_size = storage_size.saturating_sub(size);
+        }
+      },
+      // The plan and the size of the object are unknown, ask the plans again next time.
+      None => self.storage_sizes.clear(),
+    }
+  }
+}
+
+impl<S> PlanEnforcingObjectStorage<S>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  /// Checks the uploads of every workspace against the same plan.
+  pub fn new(inner: Arc<S>, plan: Arc<dyn FileStoragePlan>) -> Self {
+    Self::with_plan_provider(inner, Arc::new(WorkspacePlans::new(plan)))
+  }
+
+  /// Checks the uploads against the plan of their workspace, see [WorkspacePlans].
+  pub fn with_plan_provider(inner: Arc<S>, plans: Arc<dyn FileStoragePlanProvider>) -> Self {
+    Self {
+      inner,
+      plans,
+      usage: Default::default(),
+    }
+  }
+
+  /// Returns the storage size reported by [FileStoragePlan::storage_size] for the plan of the
+  /// workspace the first time, then keeps it up to date with the uploads and deletions going
+  /// through the wrapper. Deleting an object that wasn't uploaded through the wrapper asks the
+  /// plans again.
+  pub async fn storage_size(&self, workspace_id: &str) -> Result<u64, FlowyError> {
+    let plan = self.plans.plan_for_workspace(workspace_id);
+    let key = plan_key(&plan);
+    if let Some(storage_size) = self.usage.lock().storage_sizes.get(&key) {
+      return Ok(*storage_size);
+    }
+    let storage_size = plan.storage_size().await?;
+    Ok(
+      *self
+        .usage
+        .lock()
+        .storage_sizes
+        .entry(key)
+        .or_insert(storage_size),
+    )
+  }
+
+  /// Forgets the cached storage sizes, the next [Self::storage_size] asks the plans.
+  pub fn refresh_storage_size(&self) {
+    self.usage.lock().storage_sizes.clear();
+  }
+
+  /// Checks the object against the limits of the plan of its workspace before it's uploaded and
+  /// returns the plan and the size of the object. The storage limit is checked against the
+  /// cached [Self::storage_size], so the rejection tells the room left with the uploads of the
+  /// wrapper counted in.
+  async fn check_put(&self, url: &str, value: &ObjectValue) -> Result<(PlanKey, u64), FlowyError> {
+    let workspace_id = self.usage.lock().workspaces.remove(url).unwrap_or_default();
+    let plan = self.plans.plan_for_workspace(&workspace_id);
+    let object = StorageObject::from_bytes(
+      &workspace_id,
+      file_name_from_url(url),
+      value.raw.clone(),
+      value.mime.to_string(),
+    );
+    plan.upload_type_policy().check(&object)?;
+    let size = object.file_size_async().await?;
+    let maximum_file_size = plan.maximum_file_size().await?;
+    plan
+      .mime_size_limits()
+      .check(&object, maximum_file_size)
+      .await?;
+    if let Some(limit) = plan.storage_limit().await? {
+      let used = self.storage_size(&workspace_id).await?;
+      QuotaExceededDetails::check(used, limit, size)?;
+    }
+    plan.check_upload_object(&object).await?;
+    Ok((plan_key(&plan), size))
+  }
+}
+
+fn file_name_from_url(url: &str) -> &str {
+  let path = url.split(['?', '#']).next().unwrap_or_default();
+  path.rsplit('/').next().unwrap_or_default()
+}
+
+#[async_trait]
+impl<S> ObjectStorageService for PlanEnforcingObjectStorage<S>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  async fn get_object_url(&self, object_id: ObjectIdentity) -> Result<String, FlowyError> {
+    let workspace_id = object_id.workspace_id.clone();
+    let url = self.inner.get_object_url(object_id).await?;
+    let mut usage = self.usage.lock();
+    if usage.workspaces.len() >= MAX_PENDING_URLS {
+      usage.workspaces.clear();
+    }
+    usage.workspaces.insert(url.clone(), workspace_id);
+    Ok(url)
+  }
+
+  async fn put_object(&self, url: String, object_value: ObjectValue) -> Result<(), FlowyError> {
+    let (plan, size) = self.check_put(&url, &object_value).await?;
+    self.inner.put_object(url.clone(), object_value).await?;
+    self.usage.lock().uploaded(url, plan, size);
+    Ok(())
+  }
+
+  async fn put_object_with_progress(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    progress: ProgressCallback,
+  ) -> Result<(), FlowyError> {
+    let (plan, size) = self.check_put(&url, &object_value).await?;
+    self
+      .inner
+      .put_object_with_progress(url.clone(), object_value, progress)
+      .await?;
+    self.usage.lock().uploaded(url, plan, size);
+    Ok(())
+  }
+
+  fn supports_object_ttl(&self) -> bool {
+    self.inner.supports_object_ttl()
+  }
+
+  async fn put_object_with_ttl(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    ttl: Duration,
+  ) -> Result<(), FlowyError> {
+    let (plan, size) = self.check_put(&url, &object_value).await?;
+    self
+      .inner
+      .put_object_with_ttl(url.clone(), object_value, ttl)
+      .await?;
+    self.usage.lock().uploaded(url, plan, size);
+    Ok(())
+  }
+
+  async fn put_object_if_absent(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+  ) -> Result<(), FlowyError> {
+    let (plan, size) = self.check_put(&url, &object_value).await?;
+    self
+      .inner
+      .put_object_if_absent(url.clone(), object_value)
+      .await?;
+    self.usage.lock().uploaded(url, plan, size);
+    Ok(())
+  }
+
+  async fn put_object_if_match(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    etag: String,
+  ) -> Result<(), FlowyError> {
+    let (plan, size) = self.check_put(&url, &object_value).await?;
+    self
+      .inner
+      .put_object_if_match(url.clone(), object_value, etag)
+      .await?;
+    self.usage.lock().uploaded(url, plan, size);
+    Ok(())
+  }
+
+  async fn delete_object(&self, url: String) -> Result<(), FlowyError> {
+    self.inner.delete_object(url.clone()).await?;
+    self.usage.lock().deleted(&url);
+    Ok(())
+  }
+
+  async fn delete_objects(
+    &self,
+    urls: Vec<String>,
+  ) -> Result<Vec<Result<(), FlowyError>>, FlowyError> {
+    let results = self.inner.delete_objects(urls.clone()).await?;
+    let mut usage = self.usage.lock();
+    for (url, result) in urls.iter().zip(&results) {
+      if result.is_ok() {
+        usage.deleted(url);
+      }
+    }
+    Ok(results)
+  }
+
+  async fn delete_workspace_objects(&self, workspace_id: &str) -> Result<DeleteReport, FlowyError> {
+    let result = self.inner.delete_workspace_objects(workspace_id).await;
+    // Which objects were deleted is not known, the plans are asked for the storage size again.
+    let mut usage = self.usage.lock();
+    usage
+      .sizes
+      .retain(|url, _| workspace_id_from_url(url) != Some(workspace_id));
+    usage.storage_sizes.clear();
+    result
+  }
+
+  async fn list_objects(
+    &self,
+    workspace_id: &str,
+    prefix: Option<&str>,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    self.inner.list_objects(workspace_id, prefix).await
+  }
+
+  async fn list_objects_with_options(
+    &self,
+    workspace_id: &str,
+    options: &ListOptions,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    self
+      .inner
+      .list_objects_with_options(workspace_id, options)
+      .await
+  }
+
+  async fn get_object(&self, url: String) -> Result<ObjectValue, FlowyError> {
+    self.inner.get_object(url).await
+  }
+
+  async fn get_object_if_modified(
+    &self,
+    url: String,
+    etag: Option<String>,
+  ) -> Result<Option<(ObjectValue, String)>, FlowyError> {
+    self.inner.get_object_if_modified(url, etag).await
+  }
+
+  async fn get_object_stream(&self, url: String) -> Result<ObjectStream, FlowyError> {
+    self.inner.get_object_stream(url).await
+  }
+
+  async fn get_object_verified(
+    &self,
+    url: String,
+    expected_file_id: &str,
+  ) -> Result<ObjectValue, FlowyError> {
+    self.inner.get_object_verified(url, expected_file_id).await
+  }
+
+  fn supports_range_requests(&self) -> bool {
+    self.inner.supports_range_requests()
+  }
+
+  async fn get_object_range(
+    &self,
+    url: String,
+    start: u64,
+    end: Option<u64>,
+  ) -> Result<ObjectValue, FlowyError> {
+    self.inner.get_object_range(url, start, end).await
+  }
+
+  async fn head_object(&self, url: String) -> Result<ObjectMeta, FlowyError> {
+    self.inner.head_object(url).await
+  }
+
+  async fn object_exists(&self, url: String) -> Result<bool, FlowyError> {
+    self.inner.object_exists(url).await
+  }
+
+  async fn presign_get_url(&self, url: String, expires_in: Duration) -> Result<String, FlowyError> {
+    self.inner.presign_get_url(url, expires_in).await
+  }
+
+  async fn trash_object(&self, url: String) -> Result<(), FlowyError> {
+    self.inner.trash_object(url).await
+  }
+
+  async fn restore_object(&self, url: String) -> Result<(), FlowyError> {
+    self.inner.restore_object(url).await
+  }
+
+  /// The purged objects free storage, the next [Self::storage_size] asks the plan.
+  async fn purge_trash(&self, older_than: Duration) -> Result<Vec<String>, FlowyError> {
+    let purged = self.inner.purge_trash(older_than).await?;
+    if !purged.is_empty() {
+      self.usage.lock().storage_sizes.clear();
+    }
+    Ok(purged)
+  }
+
+  fn supports_versioning(&self) -> bool {
+    self.inner.supports_versioning()
+  }
+
+  async fn list_versions(&self, url: String) -> Result<Vec<VersionMeta>, FlowyError> {
+    self.inner.list_versions(url).await
+  }
+
+  async fn get_object_version(
+    &self,
+    url: String,
+    version_id: String,
+  ) -> Result<ObjectValue, FlowyError> {
+    self.inner.get_object_version(url, version_id).await
+  }
+
+  /// The restored version is stored again, the next [Self::storage_size] asks the plan.
+  async fn restore_version(&self, url: String, version_id: String) -> Result<(), FlowyError> {
+    self.inner.restore_version(url, version_id).await?;
+    self.usage.lock().storage_sizes.clear();
+    Ok(())
+  }
+
+  async fn health_check(&self) -> Result<HealthStatus, FlowyError> {
+    self.inner.health_check().await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use flowy_error::ErrorCode;
+
+  use super::*;
+  use crate::{
+    memory_object_value, InMemoryObjectStorage, MimeSizeLimits, StorageOperation, UploadTypePolicy,
+  };
+
+  struct TestPlan;
+
+  #[async_trait]
+  impl FileStoragePlan for TestPlan {
+    async fn storage_size(&self) -> Result<u64, FlowyError> {
+      Ok(100)
+    }
+
+    async fn maximum_file_size(&self) -> Result<u64, FlowyError> {
+      Ok(10)
+    }
+
+    fn mime_size_limits(&self) -> MimeSizeLimits {
+      MimeSizeLimits::new().with_rule("image/", 3)
+    }
+
+    async fn check_upload_object(&self, object: &StorageObject) -> Result<(), FlowyError> {
+      let blocked = object.workspace_id == "blocked";
+      if blocked {
+        Err(FlowyError::new(ErrorCode::ExcessStorageLimited, "quota"))
+      } else {
+        Ok(())
+      }
+    }
+  }
+
+  fn identity(workspace_id: &str, file_id: &str) -> ObjectIdentity {
+    ObjectIdentity {
+      workspace_id: workspace_id.to_string(),
+      file_id: file_id.to_string(),
+      ext: "txt".to_string(),
+      hash_algorithm: None,
+      size: None,
+    }
+  }
+
+  #[tokio::test]
+  async fn reject_uploads_over_the_plan_test() {
+    let inner = Arc::new(InMemoryObjectStorage::new());
+    let storage = PlanEnforcingObjectStorage::new(inner.clone(), Arc::new(TestPlan));
+
+    let url = storage.get_object_url(identity("w1", "1")).await.unwrap();
+    let err = storage
+      .put_object(url, memory_object_value("1.txt", "01234567890"))
+      .await
+      .unwrap_err();
+    assert_eq!(err.code, ErrorCode::FileTooLarge);
+    let url = storage.get_object_url(identity("w1", "2")).await.unwrap();
+    let err = storage
+      .put_object(url, memory_object_value("2.png", "0123"))
+      .await
+      .unwrap_err();
+    assert!(err.msg.contains("image/*"));
+
+    let url = storage
+      .get_object_url(identity("blocked", "1"))
+      .await
+      .unwrap();
+    let err = storage
+      .put_object(url, memory_object_value("1.txt", "hello"))
+      .await
+      .unwrap_err();
+    assert_eq!(err.code, ErrorCode::ExcessStorageLimited);
+    assert_eq!(inner.call_count(StorageOperation::Put), 0);
+  }
+
+  #[tokio::test]
+  async fn track_storage_size_test() {
+    let inner = Arc::new(InMemoryObjectStorage::new());
+    let storage = PlanEnforcingObjectStorage::new(inner.clone(), Arc::new(TestPlan));
+    assert_eq!(storage.storage_size("w1").await.unwrap(), 100);
+
+    let url = storage.get_object_url(identity("w1", "1")).await.unwrap();
+    storage
+      .put_object(url.clone(), memory_object_value("1.txt", "hello"))
+      .await
+      .unwrap();
+    assert_eq!(storage.storage_size("w1").await.unwrap(), 105);
+    storage
+      .put_object(url.clone(), memory_object_value("1.txt", "hi"))
+      .await
+      .unwrap();
+    assert_eq!(storage.storage_size("w1").await.unwrap(), 102);
+
+    storage.delete_object(url).await.unwrap();
+    assert_eq!(storage.storage_size("w1").await.unwrap(), 100);
+  }
+
+  struct TierPlan {
+    storage_size: u64,
+    maximum_file_size: u64,
+  }
+
+  #[async_trait]
+  impl FileStoragePlan for TierPlan {
+    async fn storage_size(&self) -> Result<u64, FlowyError> {
+      Ok(self.storage_size)
+    }
+
+    async fn maximum_file_size(&self) -> Result<u64, FlowyError> {
+      Ok(self.maximum_file_size)
+    }
+
+    async fn check_upload_object(&self, _object: &StorageObject) -> Result<(), FlowyError> {
+      Ok(())
+    }
+  }
+
+  #[tokio::test]
+  async fn per_workspace_plan_test() {
+    let plans = Arc::new(WorkspacePlans::new(Arc::new(TierPlan {
+      storage_size: 10,
+      maximum_file_size: 4,
+    })));
+    plans.set_plan(
+      "pro",
+      Arc::new(TierPlan {
+        storage_size: 1000,
+        maximum_file_size: 100,
+      }),
+    );
+    let inner = Arc::new(InMemoryObjectStorage::new());
+    let storage = PlanEnforcingObjectStorage::with_plan_provider(inner.clone(), plans.clone());
+
+    let url = storage.get_object_url(identity("free", "1")).await.unwrap();
+    let err = storage
+      .put_object(url, memory_object_value("1.txt", "hello"))
+      .await
+      .unwrap_err();
+    assert_eq!(err.code, ErrorCode::FileTooLarge);
+
+    assert_eq!(storage.storage_size("pro").await.unwrap(), 1000);
+    let url = storage.get_object_url(identity("pro", "1")).await.unwrap();
+    storage
+      .put_object(url, memory_object_value("1.txt", "hello"))
+      .await
+      .unwrap();
+    assert_eq!(storage.storage_size("pro").await.unwrap(), 1005);
+    assert_eq!(storage.storage_size("free").await.unwrap(), 10);
+
+    // The workspace falls back to the default plan once its own plan is removed.
+    plans.remove_plan("pro");
+    let url = storage.get_object_url(identity("pro", "2")).await.unwrap();
+    let err = storage
+      .put_object(url, memory_object_value("2.txt", "hello"))
+      .await
+      .unwrap_err();
+    assert_eq!(err.code, ErrorCode::FileTooLarge);
+    assert_eq!(inner.call_count(StorageOperation::Put), 1);
+  }
+
+  struct LimitedPlan;
+
+  #[async_trait]
+  impl FileStoragePlan for LimitedPlan {
+    async fn storage_size(&self) -> Result<u64, FlowyError> {
+      Ok(100)
+    }
+
+    async fn storage_limit(&self) -> Result<Option<u64>, FlowyError> {
+      Ok(Some(110))
+    }
+
+    async fn maximum_file_size(&self) -> Result<u64, FlowyError> {
+      Ok(10)
+    }
+  }
+
+  #[tokio::test]
+  async fn quota_exceeded_details_test() {
+    let object = StorageObject::from_bytes("w1", "1.txt", vec![0; 11], "text/plain".to_string());
+    let err = LimitedPlan.check_upload_object(&object).await.unwrap_err();
+    assert_eq!(err.code, ErrorCode::ExcessStorageLimited);
+    assert_eq!(
+      QuotaExceededDetails::from_error(&err),
+      Some(QuotaExceededDetails {
+        used: 100,
+        limit: 110,
+        incoming_size: 11,
+        overage: 1,
+      })
+    );
+
+    // The uploads through the wrapper are counted in the used bytes.
+    let inner = Arc::new(InMemoryObjectStorage::new());
+    let storage = PlanEnforcingObjectStorage::new(inner.clone(), Arc::new(LimitedPlan));
+    let url = storage.get_object_url(identity("w1", "1")).await.unwrap();
+    storage
+      .put_object(url, memory_object_value("1.txt", "hello"))
+      .await
+      .unwrap();
+    let url = storage.get_object_url(identity("w1", "2")).await.unwrap();
+    let err = storage
+      .put_object(url, memory_object_value("2.txt", "12345678"))
+      .await
+      .unwrap_err();
+    let details = QuotaExceededDetails::from_error(&err).unwrap();
+    assert_eq!((details.used, details.overage), (105, 3));
+    assert_eq!(details.remaining(), 5);
+    assert!(err.msg.contains("3 bytes over"));
+    assert_eq!(inner.call_count(StorageOperation::Put), 1);
+
+    let err = FlowyError::new(ErrorCode::ExcessStorageLimited, "quota");
+    assert_eq!(QuotaExceededDetails::from_error(&err), None);
+  }
+
+  struct ImagesOnlyPlan;
+
+  #[async_trait]
+  impl FileStoragePlan for ImagesOnlyPlan {
+    async fn storage_size(&self) -> Result<u64, FlowyError> {
+      Ok(0)
+    }
+
+    async fn maximum_file_size(&self) -> Result<u64, FlowyError> {
+      Ok(100)
+    }
+
+    fn upload_type_policy(&self) -> UploadTypePolicy {
+      UploadTypePolicy::new().allow_mime("image/*")
+    }
+
+    async fn check_upload_object(&self, _object: &StorageObject) -> Result<(), FlowyError> {
+      Ok(())
+    }
+  }
+
+  #[tokio::test]
+  async fn reject_disallowed_type_test() {
+    let inner = Arc::new(InMemoryObjectStorage::new());
+    let storage = PlanEnforcingObjectStorage::new(inner.clone(), Arc::new(ImagesOnlyPlan));
+    let url = storage.get_object_url(identity("w1", "1")).await.unwrap();
+    let err = storage
+      .put_object(url, memory_object_value("1.sh", "echo"))
+      .await
+      .unwrap_err();
+    assert_eq!(err.code, ErrorCode::FileTypeNotAllowed);
+    assert_eq!(inner.call_count(StorageOperation::Put), 0);
+  }
+}
@@ -0,0 +1,78 @@
+use flowy_error::{ErrorCode, FlowyError};
+
+use crate::ObjectValue;
+
+/// Formats the value of an HTTP `Range` header. Both `start` and `end` are inclusive, `None`
+/// means until the end of the object.
+pub fn range_header_value(start: u64, end: Option<u64>) -> String {
+  match end {
+    None => format!("bytes={}-", start),
+    Some(end) => format!("bytes={}-{}", start, end),
+  }
+}
+
+/// Returns the bytes of `value` between `start` and `end`, both inclusive. `end` is clamped to
+/// the last byte of the object. A compressed value is decompressed first.
+pub fn slice_object_range(
+  value: ObjectValue,
+  start: u64,
+  end: Option<u64>,
+) -> Result<ObjectValue, FlowyError> {
+  let value = value.decompress()?;
+  let len = value.raw.len() as u64;
+  if start >= len {
+    return Err(FlowyError::new(
+      ErrorCode::OutOfBounds,
+      format!("range start {} is out of the object size {}", start, len),
+    ));
+  }
+
+  let end = end.map(|end| end.min(len - 1)).unwrap_or(len - 1);
+  if end < start {
+    return Err(FlowyError::new(
+      ErrorCode::OutOfBounds,
+      format!("range end {} is before the range start {}", end, start),
+    ));
+  }
+
+  Ok(ObjectValue {
+    raw: value.raw.slice(start as usize..=end as usize),
+    mime: value.mime,
+    content_encoding: None,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn value() -> ObjectValue {
+    ObjectValue {
+      raw: (0..10u8).collect::<Vec<_>>().into(),
+      mime: mime::APPLICATION_PDF,
+      content_encoding: None,
+    }
+  }
+
+  #[test]
+  fn slice_range_test() {
+    let slice = slice_object_range(value(), 2, Some(4)).unwrap();
+    assert_eq!(slice.raw.as_ref(), &[2, 3, 4]);
+    assert_eq!(slice.mime, mime::APPLICATION_PDF);
+
+    let slice = slice_object_range(value(), 8, None).unwrap();
+    assert_eq!(slice.raw.as_ref(), &[8, 9]);
+
+    let slice = slice_object_range(value(), 8, Some(100)).unwrap();
+    assert_eq!(slice.raw.as_ref(), &[8, 9]);
+  }
+
+  #[test]
+  fn out_of_range_start_test() {
+    let err = slice_object_range(value(), 10, None).err().unwrap();
+    assert_eq!(err.code, ErrorCode::OutOfBounds);
+
+    let err = slice_object_range(value(), 5, Some(3)).err().unwrap();
+    assert_eq!(err.code, ErrorCode::OutOfBounds);
+  }
+}
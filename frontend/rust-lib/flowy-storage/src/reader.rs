@@ -0,0 +1,154 @@
+use std::pin::Pin;
+
+use bytes::Bytes;
+use mime::Mime;
+use parking_lot::Mutex;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use flowy_error::{ErrorCode, FlowyError};
+
+use crate::{ObjectByteStream, ObjectValue};
+
+/// A boxed source of object content, see [ObjectReader].
+pub type BoxedAsyncRead = Pin<Box<dyn AsyncRead + Send + Sync>>;
+
+/// The content of a [crate::StorageObject] created by [crate::StorageObject::from_reader], for
+/// example a socket or a decompressor. Unlike the other sources, the content can only be read
+/// once: the first upload takes the reader and the following ones fail.
+pub struct ObjectReader {
+  reader: Mutex<Option<BoxedAsyncRead>>,
+}
+
+impl ObjectReader {
+  pub fn new<R>(reader: R) -> Self
+  where
+    R: AsyncRead + Send + Sync + 'static,
+  {
+    Self {
+      reader: Mutex::new(Some(Box::pin(reader))),
+    }
+  }
+
+  /// Takes the reader out, returns an error if it was already taken.
+  pub fn take(&self) -> Result<BoxedAsyncRead, FlowyError> {
+    self.reader.lock().take().ok_or_else(|| {
+      FlowyError::new(
+        ErrorCode::InvalidParams,
+        "the content of the object was already read",
+      )
+    })
+  }
+
+  pub fn is_consumed(&self) -> bool {
+    self.reader.lock().is_none()
+  }
+}
+
+impl ObjectValue {
+  /// Reads the whole content of `reader` in memory, for the callers that don't need to stream
+  /// it. Fails with an [ErrorCode::FileTooLarge] error as soon as the reader produces more than
+  /// `max_bytes`, so an unbounded source can't exhaust the memory.
+  pub async fn from_reader<R>(
+    mut reader: R,
+    mime: Mime,
+    max_bytes: Option<u64>,
+  ) -> Result<ObjectValue, FlowyError>
+  where
+    R: AsyncRead + Unpin,
+  {
+    let mut raw = vec![];
+    match max_bytes {
+      Some(max_bytes) => {
+        // Read one byte over the limit to find out if the content goes over it.
+        reader.take(max_bytes + 1).read_to_end(&mut raw).await?;
+        if raw.len() as u64 > max_bytes {
+          return Err(FlowyError::new(
+            ErrorCode::FileTooLarge,
+            format!("the content is over the limit of {} bytes", max_bytes),
+          ));
+        }
+      },
+      None => {
+        reader.read_to_end(&mut raw).await?;
+      },
+    }
+    Ok(ObjectValue {
+      raw: raw.into(),
+      mime,
+      content_encoding: None,
+    })
+  }
+}
+
+/// Reads the whole content in memory. If `content_length` is known, the content must have that
+/// length.
+pub(crate) async fn read_to_bytes(
+  mut reader: BoxedAsyncRead,
+  content_length: Option<u64>,
+) -> Result<Bytes, FlowyError> {
+  let mut raw = Vec::with_capacity(content_length.unwrap_or_default() as usize);
+  reader.read_to_end(&mut raw).await?;
+  check_length(raw.len() as u64, content_length)?;
+  Ok(raw.into())
+}
+
+/// Turns the reader into a stream of chunks of at most `buffer_size` bytes. If `content_length`
+/// is known, the stream fails if the reader produces more or less bytes.
+pub(crate) fn reader_stream(
+  reader: BoxedAsyncRead,
+  content_length: Option<u64>,
+  buffer_size: usize,
+) -> ObjectByteStream {
+  let buffer_size = buffer_size.max(1);
+  let stream = futures::stream::try_unfold((reader, 0u64), move |(mut reader, read)| async move {
+    let mut buffer = vec![0; buffer_size];
+    let n = reader.read(&mut buffer).await?;
+    if n == 0 {
+      check_length(read, content_length)?;
+      return Ok(None);
+    }
+
+    let read = read + n as u64;
+    if content_length.map(|len| read > len).unwrap_or(false) {
+      check_length(read, content_length)?;
+    }
+    buffer.truncate(n);
+    Ok(Some((Bytes::from(buffer), (reader, read))))
+  });
+  Box::pin(stream)
+}
+
+fn check_length(read: u64, content_length: Option<u64>) -> Result<(), FlowyError> {
+  match content_length {
+    Some(len) if len != read => Err(FlowyError::new(
+      ErrorCode::Internal,
+      format!("the reader produced {} bytes, expected {} bytes", read, len),
+    )),
+    _ => Ok(()),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn object_value_from_reader_test() {
+    let value = ObjectValue::from_reader(&b"hello"[..], mime::TEXT_PLAIN, Some(5))
+      .await
+      .unwrap();
+    assert_eq!(value.raw, Bytes::from("hello"));
+    assert_eq!(value.mime, mime::TEXT_PLAIN);
+
+    let err = ObjectValue::from_reader(&b"hello"[..], mime::TEXT_PLAIN, Some(4))
+      .await
+      .err()
+      .unwrap();
+    assert_eq!(err.code, ErrorCode::FileTooLarge);
+
+    let value = ObjectValue::from_reader(&b"hello"[..], mime::TEXT_PLAIN, None)
+      .await
+      .unwrap();
+    assert_eq!(value.raw.len(), 5);
+  }
+}
@@ -0,0 +1,558 @@
+use std::future::Future;
+use std::io::SeekFrom;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::{select, Either};
+use futures::StreamExt;
+use tokio::fs::File;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::watch;
+use tracing::warn;
+
+use flowy_error::{ErrorCode, FlowyError};
+
+use crate::{
+  atomic_write_with, storage_error, ObjectStorageService, StorageErrorKind,
+  DEFAULT_DOWNLOAD_CHUNK_SIZE,
+};
+
+/// What the app knows of the network of the device, see [NetworkMonitor].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NetworkState {
+  pub online: bool,
+  /// Bumped on every change, including a switch from one network to another that keeps the
+  /// device online, for example from wifi to cellular.
+  pub generation: u64,
+}
+
+/// Tells the transfers about the network changes the platform notifies the app of. A transfer
+/// that reconnects with a [ReconnectPolicy] drops its connection as soon as the network changes,
+/// instead of waiting for it to time out, and waits for the device to be back online before it
+/// resumes. The clones share the same state.
+#[derive(Clone)]
+pub struct NetworkMonitor {
+  sender: Arc<watch::Sender<NetworkState>>,
+}
+
+impl Default for NetworkMonitor {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl NetworkMonitor {
+  /// Starts online, so the transfers are not held until the platform reports the network.
+  pub fn new() -> Self {
+    let (sender, _) = watch::channel(NetworkState {
+      online: true,
+      generation: 0,
+    });
+    Self {
+      sender: Arc::new(sender),
+    }
+  }
+
+  pub fn state(&self) -> NetworkState {
+    *self.sender.borrow()
+  }
+
+  pub fn is_online(&self) -> bool {
+    self.state().online
+  }
+
+  /// Records that the device went offline or is back online, nothing changes if it already was.
+  pub fn set_online(&self, online: bool) {
+    self.sender.send_if_modified(|state| {
+      if state.online == online {
+        return false;
+      }
+      state.online = online;
+      state.generation += 1;
+      true
+    });
+  }
+
+  /// Records that the device switched to another network, the connections opened on the
+  /// previous one are likely dead.
+  pub fn network_changed(&self) {
+    self.sender.send_modify(|state| state.generation += 1);
+  }
+
+  /// Notifies of every change, for example to pause the queued transfers while offline.
+  pub fn subscribe(&self) -> watch::Receiver<NetworkState> {
+    self.sender.subscribe()
+  }
+
+  /// Waits for the device to be online. Returns false if it's still offline after `timeout`.
+  pub async fn wait_online(&self, timeout: Duration) -> bool {
+    let mut receiver = self.subscribe();
+    let online = async move { receiver.wait_for(|state| state.online).await.is_ok() };
+    tokio::time::timeout(timeout, online).await.unwrap_or(false)
+  }
+
+  /// Resolves on the first change after it's first polled.
+  async fn changed(&self) {
+    let mut receiver = self.subscribe();
+    // The sender lives as long as `self`, the channel can't be closed while this is awaited.
+    let _ = receiver.changed().await;
+  }
+}
+
+/// Controls how a transfer reconnects when its connection is lost, see [is_connection_lost].
+/// Unlike a [crate::RetryPolicy], which sends a request again, a reconnect continues the
+/// transfer from the last confirmed part or the last byte written.
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+  /// How many times a transfer reconnects before the error is returned, `0` disables the
+  /// reconnects.
+  pub max_reconnects: usize,
+  /// The delay before reconnecting, once the device is online. It gives a new network the time
+  /// to settle.
+  pub delay: Duration,
+  /// How long a transfer waits for the device to be back online, when it has a
+  /// [NetworkMonitor], before it fails.
+  pub offline_timeout: Duration,
+}
+
+impl Default for ReconnectPolicy {
+  fn default() -> Self {
+    Self {
+      max_reconnects: 5,
+      delay: Duration::from_secs(1),
+      offline_timeout: Duration::from_secs(60),
+    }
+  }
+}
+
+impl ReconnectPolicy {
+  /// The transfer fails on the first lost connection.
+  pub fn never() -> Self {
+    Self {
+      max_reconnects: 0,
+      ..Default::default()
+    }
+  }
+}
+
+/// Returns true if the connection was reset, refused or timed out, the transfer can continue
+/// once it reconnects. An answer of the backend, like an unauthorized user or a server error,
+/// is not: reconnecting doesn't change it.
+pub fn is_connection_lost(error: &FlowyError) -> bool {
+  matches!(
+    error.code,
+    ErrorCode::HttpError
+      | ErrorCode::ConnectTimeout
+      | ErrorCode::ConnectClose
+      | ErrorCode::ConnectRefused
+      | ErrorCode::Timeout
+  )
+}
+
+/// Counts the reconnects of one transfer.
+pub(crate) struct Reconnector<'a> {
+  policy: &'a ReconnectPolicy,
+  monitor: Option<&'a NetworkMonitor>,
+  reconnects: usize,
+}
+
+impl<'a> Reconnector<'a> {
+  pub(crate) fn new(policy: &'a ReconnectPolicy, monitor: Option<&'a NetworkMonitor>) -> Self {
+    Self {
+      policy,
+      monitor,
+      reconnects: 0,
+    }
+  }
+
+  /// Runs one attempt of the transfer. It's dropped, and fails as if the connection was lost,
+  /// when the network changes.
+  pub(crate) async fn attempt<T>(
+    &self,
+    fut: impl Future<Output = Result<T, FlowyError>>,
+  ) -> Result<T, FlowyError> {
+    let monitor = match self.monitor {
+      Some(monitor) if self.policy.max_reconnects > 0 => monitor,
+      _ => return fut.await,
+    };
+    let fut = std::pin::pin!(fut);
+    let changed = std::pin::pin!(monitor.changed());
+    match select(fut, changed).await {
+      Either::Left((result, _)) => result,
+      Either::Right(_) => Err(FlowyError::new(
+        ErrorCode::ConnectClose,
+        "the network changed during the transfer",
+      )),
+    }
+  }
+
+  /// Returns the error if the attempt can't be continued. Otherwise waits for the device to be
+  /// online, then for the [ReconnectPolicy::delay].
+  pub(crate) async fn reconnect(&mut self, err: FlowyError, url: &str) -> Result<(), FlowyError> {
+    if self.reconnects >= self.policy.max_reconnects || !is_connection_lost(&err) {
+      return Err(err);
+    }
+    self.reconnects += 1;
+    warn!(
+      "connection lost during the transfer of {}, reconnect {}/{}: {}",
+      url, self.reconnects, self.policy.max_reconnects, err
+    );
+    if let Some(monitor) = self.monitor {
+      if !monitor.wait_online(self.policy.offline_timeout).await {
+        return Err(storage_error(
+          StorageErrorKind::Network,
+          format!(
+            "the device is still offline after {:?}, the transfer of {} failed: {}",
+            self.policy.offline_timeout, url, err.msg
+          ),
+        ));
+      }
+    }
+    tokio::time::sleep(self.policy.delay).await;
+    Ok(())
+  }
+}
+
+/// Downloads the object into `dest` like [ObjectStorageService::download_to_file], and
+/// reconnects when the connection is lost. The download continues from the last byte written
+/// with [ObjectStorageService::get_object_range] if the backend supports ranges, it starts over
+/// otherwise. The object must not be replaced while it's downloaded.
+///
+/// Returns the size of the object.
+pub async fn download_to_file_reconnecting<S>(
+  service: &S,
+  url: String,
+  dest: &Path,
+  policy: &ReconnectPolicy,
+  monitor: Option<&NetworkMonitor>,
+) -> Result<u64, FlowyError>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  atomic_write_with(dest, |temp_path| async move {
+    let io_error = |err| crate::io_error(err, &temp_path);
+    let mut file = File::create(&temp_path).await.map_err(io_error)?;
+    let mut download = Download {
+      written: 0,
+      size: None,
+    };
+    let mut reconnector = Reconnector::new(policy, monitor);
+    loop {
+      let result = reconnector
+        .attempt(download.next(service, &url, &mut file))
+        .await;
+      match result {
+        Ok(()) => break,
+        Err(err) => reconnector.reconnect(err, &url).await?,
+      }
+      if !service.supports_range_requests() {
+        download.written = 0;
+      }
+      // An interrupted attempt may have written a part of a chunk it didn't count.
+      file.set_len(download.written).await.map_err(io_error)?;
+      file
+        .seek(SeekFrom::Start(download.written))
+        .await
+        .map_err(io_error)?;
+    }
+    file.flush().await.map_err(io_error)?;
+    Ok(download.written)
+  })
+  .await
+}
+
+struct Download {
+  written: u64,
+  /// Known once the download started.
+  size: Option<u64>,
+}
+
+impl Download {
+  async fn next<S>(&mut self, service: &S, url: &str, file: &mut File) -> Result<(), FlowyError>
+  where
+    S: ObjectStorageService + ?Sized,
+  {
+    let size = match self.size {
+      Some(size) if self.written > 0 => {
+        while self.written < size {
+          let end = (self.written + DEFAULT_DOWNLOAD_CHUNK_SIZE).min(size) - 1;
+          let value = service
+            .get_object_range(url.to_string(), self.written, Some(end))
+            .await?;
+          if value.raw.is_empty() {
+            break;
+          }
+          file.write_all(&value.raw).await?;
+          self.written += value.raw.len() as u64;
+        }
+        size
+      },
+      _ => {
+        let object = service.get_object_stream(url.to_string()).await?;
+        self.size = Some(object.content_length);
+        let mut stream = crate::timeout::configured_idle_timeout_stream(object.stream);
+        while let Some(chunk) = stream.next().await {
+          let chunk = chunk?;
+          file.write_all(&chunk).await?;
+          self.written += chunk.len() as u64;
+        }
+        object.content_length
+      },
+    };
+    if self.written != size {
+      return Err(FlowyError::new(
+        ErrorCode::Internal,
+        format!(
+          "download of {} ended after {} bytes, expected {} bytes",
+          url, self.written, size
+        ),
+      ));
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use parking_lot::Mutex;
+
+  use super::*;
+  use crate::{
+    memory_object_value, InMemoryObjectStorage, ResumableUploader, RetryPolicy, StorageObject,
+    StorageOperation, UploadSession, UploadSessionStore,
+  };
+
+  const URL: &str = "memory://w1/1.txt";
+
+  /// Holds `content` at [URL], the first `resets` downloads are reset after they sent a few bytes.
+  async fn flaky_storage(
+    content: &'static str,
+    resets: usize,
+    ranges: bool,
+  ) -> InMemoryObjectStorage {
+    let storage = InMemoryObjectStorage::new();
+    storage
+      .put_object(URL.to_string(), memory_object_value("1.txt", content))
+      .await
+      .unwrap();
+    for _ in 0..resets {
+      storage.interrupt_next_stream(4, connection_reset());
+    }
+    if !ranges {
+      storage.disable_range_requests();
+    }
+    storage
+  }
+
+  fn connection_reset() -> FlowyError {
+    FlowyError::new(ErrorCode::ConnectClose, "connection reset")
+  }
+
+  fn policy(max_reconnects: usize) -> ReconnectPolicy {
+    ReconnectPolicy {
+      max_reconnects,
+      delay: Duration::from_millis(1),
+      offline_timeout: Duration::from_millis(50),
+    }
+  }
+
+  #[test]
+  fn is_connection_lost_test() {
+    for code in [
+      ErrorCode::ConnectClose,
+      ErrorCode::ConnectTimeout,
+      ErrorCode::ConnectRefused,
+      ErrorCode::Timeout,
+    ] {
+      assert!(is_connection_lost(&FlowyError::new(code, "")));
+    }
+    // A 403 or a 500 is what the backend answered, reconnecting doesn't change it.
+    for code in [
+      ErrorCode::UserUnauthorized,
+      ErrorCode::NotEnoughPermissions,
+      ErrorCode::InternalServerError,
+      ErrorCode::RecordNotFound,
+    ] {
+      assert!(!is_connection_lost(&FlowyError::new(code, "")));
+    }
+  }
+
+  #[tokio::test]
+  async fn network_monitor_test() {
+    let monitor = NetworkMonitor::new();
+    let mut receiver = monitor.subscribe();
+    assert!(monitor.is_online());
+
+    monitor.set_online(false);
+    monitor.set_online(false);
+    assert_eq!(
+      *receiver.borrow_and_update(),
+      NetworkState {
+        online: false,
+        generation: 1
+      }
+    );
+    assert!(!monitor.wait_online(Duration::from_millis(10)).await);
+
+    let clone = monitor.clone();
+    tokio::spawn(async move {
+      tokio::time::sleep(Duration::from_millis(10)).await;
+      clone.set_online(true);
+    });
+    assert!(monitor.wait_online(Duration::from_secs(5)).await);
+
+    monitor.network_changed();
+    receiver.changed().await.unwrap();
+    assert_eq!(
+      *receiver.borrow(),
+      NetworkState {
+        online: true,
+        generation: 3
+      }
+    );
+  }
+
+  #[tokio::test]
+  async fn download_resumes_from_offset_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let dest = dir.path().join("1.txt");
+    let content = "# title\n\nsome text\n";
+
+    let storage = flaky_storage(content, 1, true).await;
+    let size = download_to_file_reconnecting(&storage, URL.to_string(), &dest, &policy(2), None)
+      .await
+      .unwrap();
+    assert_eq!(size, content.len() as u64);
+    assert_eq!(std::fs::read_to_string(&dest).unwrap(), content);
+    // The bytes received before the reset are not downloaded again, the rest is a range.
+    assert_eq!(storage.call_count(StorageOperation::Get), 1);
+    assert_eq!(storage.call_count(StorageOperation::GetRange), 1);
+
+    // Without ranges, the download starts over.
+    let storage = flaky_storage(content, 2, false).await;
+    download_to_file_reconnecting(&storage, URL.to_string(), &dest, &policy(2), None)
+      .await
+      .unwrap();
+    assert_eq!(std::fs::read_to_string(&dest).unwrap(), content);
+    assert_eq!(storage.call_count(StorageOperation::Get), 3);
+    assert_eq!(storage.call_count(StorageOperation::GetRange), 0);
+  }
+
+  #[tokio::test]
+  async fn download_gives_up_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let dest = dir.path().join("1.txt");
+
+    let storage = flaky_storage("# title\n", 3, false).await;
+    let err = download_to_file_reconnecting(&storage, URL.to_string(), &dest, &policy(2), None)
+      .await
+      .unwrap_err();
+    assert_eq!(err.code, ErrorCode::ConnectClose);
+    assert!(!dest.exists());
+
+    // Still offline after the timeout.
+    let storage = flaky_storage("# title\n", 1, true).await;
+    let monitor = NetworkMonitor::new();
+    monitor.set_online(false);
+    let err =
+      download_to_file_reconnecting(&storage, URL.to_string(), &dest, &policy(2), Some(&monitor))
+        .await
+        .unwrap_err();
+    assert!(err.msg.contains("still offline"), "{}", err.msg);
+  }
+
+  #[derive(Default)]
+  struct MemorySessionStore(Mutex<HashMap<String, UploadSession>>);
+
+  impl UploadSessionStore for MemorySessionStore {
+    fn load_sessions(&self) -> HashMap<String, UploadSession> {
+      self.0.lock().clone()
+    }
+
+    fn save_sessions(&self, sessions: &HashMap<String, UploadSession>) -> Result<(), FlowyError> {
+      *self.0.lock() = sessions.clone();
+      Ok(())
+    }
+  }
+
+  fn uploader(
+    storage: Arc<InMemoryObjectStorage>,
+    max_reconnects: usize,
+  ) -> ResumableUploader<InMemoryObjectStorage> {
+    let retry = RetryPolicy {
+      max_attempts: 1,
+      ..Default::default()
+    };
+    ResumableUploader::new(storage, Arc::new(MemorySessionStore::default()), 4, retry)
+      .with_reconnect(policy(max_reconnects), Some(NetworkMonitor::new()))
+  }
+
+  #[tokio::test]
+  async fn upload_resumes_from_confirmed_part_test() {
+    let object =
+      || StorageObject::from_bytes("w1", "1.txt", "0123456789", "text/plain".to_string());
+
+    // The connection is reset once, while the second part is uploaded.
+    let reset_part_storage = || {
+      let storage = Arc::new(InMemoryObjectStorage::new());
+      storage.fail_after(StorageOperation::UploadPart, 1, connection_reset());
+      storage
+    };
+
+    let storage = reset_part_storage();
+    let (_, url) = uploader(storage.clone(), 2)
+      .resume_upload(&object())
+      .await
+      .unwrap();
+    assert_eq!(storage.object(&url).unwrap().raw.as_ref(), b"0123456789");
+    // The first part was confirmed before the reset, it's not uploaded again.
+    assert_eq!(storage.call_count(StorageOperation::UploadPart), 4);
+
+    // Without reconnects the reset is returned.
+    let failing = uploader(reset_part_storage(), 0);
+    let err = failing.resume_upload(&object()).await.unwrap_err();
+    assert_eq!(err.code, ErrorCode::ConnectClose);
+    assert_eq!(failing.pending_uploads()[0].parts.len(), 1);
+
+    // A forbidden upload is not reconnected.
+    let storage = Arc::new(InMemoryObjectStorage::new());
+    storage.fail_next(
+      StorageOperation::UploadPart,
+      FlowyError::new(ErrorCode::UserUnauthorized, "forbidden"),
+    );
+    let err = uploader(storage.clone(), 2)
+      .resume_upload(&object())
+      .await
+      .unwrap_err();
+    assert_eq!(err.code, ErrorCode::UserUnauthorized);
+    assert_eq!(storage.call_count(StorageOperation::UploadPart), 1);
+  }
+
+  #[tokio::test]
+  async fn unauthorized_is_not_reconnected_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = InMemoryObjectStorage::new();
+    storage
+      .put_object(URL.to_string(), memory_object_value("1.txt", "hello"))
+      .await
+      .unwrap();
+    storage.fail_next(
+      StorageOperation::Get,
+      FlowyError::new(ErrorCode::UserUnauthorized, "forbidden"),
+    );
+    let err = download_to_file_reconnecting(
+      &storage,
+      URL.to_string(),
+      &dir.path().join("1.txt"),
+      &policy(5),
+      None,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err.code, ErrorCode::UserUnauthorized);
+    assert_eq!(storage.call_count(StorageOperation::Get), 1);
+  }
+}
@@ -0,0 +1,626 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use mime::Mime;
+use parking_lot::Mutex;
+use tracing::info;
+
+use flowy_error::{ErrorCode, FlowyError};
+#[cfg(not(target_arch = "wasm32"))]
+use flowy_sqlite::kv::StorePreferences;
+
+use crate::{
+  file_id_from_url, workspace_id_from_url, DeleteReport, HealthStatus, ListOptions, ObjectIdentity,
+  ObjectMeta, ObjectStorageService, ObjectStream, ObjectValue, PartETag, ProgressCallback,
+  UploadId, VersionMeta,
+};
+
+/// Persists the reference counts of [RefCountedObjectStorage] so that they survive app restarts.
+pub trait RefCountStore: Send + Sync + 'static {
+  fn load_ref_counts(&self) -> HashMap<String, u64>;
+  fn save_ref_counts(&self, counts: &HashMap<String, u64>) -> Result<(), FlowyError>;
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+const REF_COUNTS_KEY: &str = "flowy_storage_ref_counts";
+
+#[cfg(not(target_arch = "wasm32"))]
+impl RefCountStore for StorePreferences {
+  fn load_ref_counts(&self) -> HashMap<String, u64> {
+    self.get_object(REF_COUNTS_KEY).unwrap_or_default()
+  }
+
+  fn save_ref_counts(&self, counts: &HashMap<String, u64>) -> Result<(), FlowyError> {
+    self.set_object(REF_COUNTS_KEY, counts)?;
+    Ok(())
+  }
+}
+
+/// An [ObjectStorageService] that counts the references to the objects whose `file_id` is a
+/// content hash, see [file_id_from_url]. The documents that contain the same file share its
+/// object, so deleting the object when one of them is deleted would break the others:
+/// - [ObjectStorageService::put_object] and [ObjectStorageService::copy_object] add a reference.
+///   Putting an object that is already referenced only adds the reference, the content is the
+///   same.
+/// - [Self::retain] adds a reference to a stored object, when a document is duplicated.
+/// - [ObjectStorageService::delete_object] removes a reference, and only deletes the object when
+///   it was the last one. An object that is not tracked, like the ones uploaded before the
+///   counts were kept, is deleted.
+/// - [ObjectStorageService::delete_workspace_objects] deletes the objects of the workspace
+///   whatever their references, the documents referencing them are deleted too.
+///
+/// The operations on the same url run one at a time, so a reference can't be added while the
+/// object is being deleted. The counts are saved to the [RefCountStore] after every change, a
+/// change that can't be saved fails the operation.
+///
+/// The objects under an explicit `file_id` are not counted, their operations go straight to the
+/// inner service.
+pub struct RefCountedObjectStorage<S: ?Sized> {
+  inner: Arc<S>,
+  counts: Arc<RefCounts>,
+}
+
+struct RefCounts {
+  store: Arc<dyn RefCountStore>,
+  counts: Mutex<HashMap<String, u64>>,
+  locks: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl RefCounts {
+  fn get(&self, url: &str) -> u64 {
+    self.counts.lock().get(url).copied().unwrap_or_default()
+  }
+
+  /// Sets the count of the url and saves the counts, the count is restored if they can't be
+  /// saved.
+  fn set(&self, url: &str, count: u64) -> Result<(), FlowyError> {
+    let mut counts = self.counts.lock();
+    let previous = if count == 0 {
+      counts.remove(url)
+    } else {
+      counts.insert(url.to_string(), count)
+    };
+    let result = self.store.save_ref_counts(&counts);
+    if result.is_err() {
+      match previous {
+        Some(previous) => counts.insert(url.to_string(), previous),
+        None => counts.remove(url),
+      };
+    }
+    result
+  }
+
+  /// Runs `f` while holding the lock of the url, so the operations on an object don't overlap.
+  async fn locked<F, T>(&self, url: &str, f: F) -> T
+  where
+    F: Future<Output = T>,
+  {
+    let lock = {
+      let mut locks = self.locks.lock();
+      locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+      locks.entry(url.to_string()).or_default().clone()
+    };
+    let _guard = lock.lock().await;
+    f.await
+  }
+
+  /// Adds a reference to the url once `fut`, the upload of the object, succeeded. The upload is
+  /// skipped if the object is already referenced, once `same_content` confirmed that the stored
+  /// object is the one being uploaded.
+  async fn add_ref<F, C>(
+    &self,
+    url: &str,
+    fut: Option<F>,
+    same_content: Option<C>,
+  ) -> Result<u64, FlowyError>
+  where
+    F: Future<Output = Result<(), FlowyError>>,
+    C: Future<Output = Result<(), FlowyError>>,
+  {
+    self
+      .locked(url, async {
+        let count = self.get(url);
+        if count == 0 {
+          if let Some(fut) = fut {
+            fut.await?;
+          }
+        } else if let Some(same_content) = same_content {
+          same_content.await?;
+        }
+        self.set(url, count + 1)?;
+        Ok(count + 1)
+      })
+      .await
+  }
+
+  /// Forgets the counts of the objects of the workspace, once they were deleted together, except
+  /// the `kept` ones that couldn't be deleted.
+  fn forget_workspace(&self, workspace_id: &str, kept: &[&str]) -> Result<(), FlowyError> {
+    let mut counts = self.counts.lock();
+    counts.retain(|url, _| {
+      workspace_id_from_url(url) != Some(workspace_id) || kept.contains(&url.as_str())
+    });
+    self.store.save_ref_counts(&counts)
+  }
+
+  /// Adds a reference to an object that is already stored.
+  async fn add_existing_ref(&self, url: &str) -> Result<u64, FlowyError> {
+    let none = None::<futures::future::Ready<_>>;
+    self.add_ref(url, none.clone(), none).await
+  }
+
+  async fn remove_ref<F>(&self, url: &str, delete: F) -> Result<u64, FlowyError>
+  where
+    F: Future<Output = Result<(), FlowyError>>,
+  {
+    self
+      .locked(url, async {
+        let count = self.get(url);
+        if count <= 1 {
+          delete.await?;
+          if count == 1 {
+            info!("deleted {}, it was not referenced anymore", url);
+            self.set(url, 0)?;
+          }
+          return Ok(0);
+        }
+        self.set(url, count - 1)?;
+        Ok(count - 1)
+      })
+      .await
+  }
+}
+
+impl<S> RefCountedObjectStorage<S>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  /// Creates the storage and loads the counts saved by a previous run from the `store`.
+  pub fn new(inner: Arc<S>, store: Arc<dyn RefCountStore>) -> Self {
+    let counts = store.load_ref_counts();
+    Self {
+      inner,
+      counts: Arc::new(RefCounts {
+        store,
+        counts: Mutex::new(counts),
+        locks: Default::default(),
+      }),
+    }
+  }
+
+  /// The number of references to the object, zero if it's not tracked.
+  pub fn ref_count(&self, url: &str) -> u64 {
+    self.counts.get(url)
+  }
+
+  /// Adds a reference to an object that is already stored, for example when the document that
+  /// contains it is duplicated. Returns the number of references.
+  pub async fn retain(&self, url: String) -> Result<u64, FlowyError> {
+    self.counts.add_existing_ref(&url).await
+  }
+
+  /// Fails if the object stored at the url doesn't have the `size` of the one being uploaded. The
+  /// `file_id` is not a cryptographic hash, so it may be shared by two different contents, and
+  /// counting the upload as a reference to the stored object would lose its content.
+  async fn check_same_size(&self, url: String, size: u64) -> Result<(), FlowyError> {
+    let meta = self.inner.head_object(url.clone()).await?;
+    if meta.size != size {
+      return Err(FlowyError::new(
+        ErrorCode::Conflict,
+        format!(
+          "{} is stored with {} bytes instead of {}, another content has the same file_id",
+          url, meta.size, size
+        ),
+      ));
+    }
+    Ok(())
+  }
+}
+
+#[async_trait]
+impl<S> ObjectStorageService for RefCountedObjectStorage<S>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  async fn get_object_url(&self, object_id: ObjectIdentity) -> Result<String, FlowyError> {
+    self.inner.get_object_url(object_id).await
+  }
+
+  async fn put_object(&self, url: String, object_value: ObjectValue) -> Result<(), FlowyError> {
+    if file_id_from_url(&url).is_none() {
+      return self.inner.put_object(url, object_value).await;
+    }
+    let size = object_value.clone().decompress()?.raw.len() as u64;
+    let same_content = self.check_same_size(url.clone(), size);
+    let fut = self.inner.put_object(url.clone(), object_value);
+    self
+      .counts
+      .add_ref(&url, Some(fut), Some(same_content))
+      .await?;
+    Ok(())
+  }
+
+  async fn put_object_with_progress(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    progress: ProgressCallback,
+  ) -> Result<(), FlowyError> {
+    if file_id_from_url(&url).is_none() {
+      return self
+        .inner
+        .put_object_with_progress(url, object_value, progress)
+        .await;
+    }
+    let size = object_value.clone().decompress()?.raw.len() as u64;
+    let same_content = self.check_same_size(url.clone(), size);
+    let fut = self
+      .inner
+      .put_object_with_progress(url.clone(), object_value, progress);
+    self
+      .counts
+      .add_ref(&url, Some(fut), Some(same_content))
+      .await?;
+    Ok(())
+  }
+
+  fn supports_object_ttl(&self) -> bool {
+    self.inner.supports_object_ttl()
+  }
+
+  async fn put_object_with_ttl(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    ttl: Duration,
+  ) -> Result<(), FlowyError> {
+    self.inner.put_object_with_ttl(url, object_value, ttl).await
+  }
+
+  async fn put_object_if_absent(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+  ) -> Result<(), FlowyError> {
+    if file_id_from_url(&url).is_none() {
+      return self.inner.put_object_if_absent(url, object_value).await;
+    }
+    // A referenced object exists, so only the upload that creates the object adds the first
+    // reference.
+    let fut = self.inner.put_object_if_absent(url.clone(), object_value);
+    self
+      .counts
+      .locked(&url, async {
+        fut.await?;
+        self.counts.set(&url, 1)
+      })
+      .await
+  }
+
+  async fn put_object_if_match(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    etag: String,
+  ) -> Result<(), FlowyError> {
+    self
+      .inner
+      .put_object_if_match(url, object_value, etag)
+      .await
+  }
+
+  async fn delete_object(&self, url: String) -> Result<(), FlowyError> {
+    if file_id_from_url(&url).is_none() {
+      return self.inner.delete_object(url).await;
+    }
+    let fut = self.inner.delete_object(url.clone());
+    self.counts.remove_ref(&url, fut).await?;
+    Ok(())
+  }
+
+  async fn delete_objects(
+    &self,
+    urls: Vec<String>,
+  ) -> Result<Vec<Result<(), FlowyError>>, FlowyError> {
+    let deletes = urls
+      .into_iter()
+      .map(|url| self.delete_object(url))
+      .collect::<Vec<_>>();
+    Ok(futures::future::join_all(deletes).await)
+  }
+
+  async fn delete_workspace_objects(&self, workspace_id: &str) -> Result<DeleteReport, FlowyError> {
+    let report = self.inner.delete_workspace_objects(workspace_id).await?;
+    let failed = report
+      .failed
+      .iter()
+      .map(|(url, _)| url.as_str())
+      .collect::<Vec<_>>();
+    self.counts.forget_workspace(workspace_id, &failed)?;
+    Ok(report)
+  }
+
+  async fn list_objects(
+    &self,
+    workspace_id: &str,
+    prefix: Option<&str>,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    self.inner.list_objects(workspace_id, prefix).await
+  }
+
+  async fn list_objects_with_options(
+    &self,
+    workspace_id: &str,
+    options: &ListOptions,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    self
+      .inner
+      .list_objects_with_options(workspace_id, options)
+      .await
+  }
+
+  async fn get_object(&self, url: String) -> Result<ObjectValue, FlowyError> {
+    self.inner.get_object(url).await
+  }
+
+  async fn get_object_stream(&self, url: String) -> Result<ObjectStream, FlowyError> {
+    self.inner.get_object_stream(url).await
+  }
+
+  async fn get_object_verified(
+    &self,
+    url: String,
+    expected_file_id: &str,
+  ) -> Result<ObjectValue, FlowyError> {
+    self.inner.get_object_verified(url, expected_file_id).await
+  }
+
+  async fn get_object_if_modified(
+    &self,
+    url: String,
+    etag: Option<String>,
+  ) -> Result<Option<(ObjectValue, String)>, FlowyError> {
+    self.inner.get_object_if_modified(url, etag).await
+  }
+
+  fn supports_range_requests(&self) -> bool {
+    self.inner.supports_range_requests()
+  }
+
+  async fn get_object_range(
+    &self,
+    url: String,
+    start: u64,
+    end: Option<u64>,
+  ) -> Result<ObjectValue, FlowyError> {
+    self.inner.get_object_range(url, start, end).await
+  }
+
+  async fn head_object(&self, url: String) -> Result<ObjectMeta, FlowyError> {
+    self.inner.head_object(url).await
+  }
+
+  async fn object_exists(&self, url: String) -> Result<bool, FlowyError> {
+    self.inner.object_exists(url).await
+  }
+
+  async fn presign_get_url(&self, url: String, expires_in: Duration) -> Result<String, FlowyError> {
+    self.inner.presign_get_url(url, expires_in).await
+  }
+
+  async fn presign_put_url(&self, url: String, expires_in: Duration) -> Result<String, FlowyError> {
+    self.inner.presign_put_url(url, expires_in).await
+  }
+
+  fn supports_copy_object(&self) -> bool {
+    self.inner.supports_copy_object()
+  }
+
+  async fn copy_object(
+    &self,
+    src_url: String,
+    dst_identity: ObjectIdentity,
+  ) -> Result<String, FlowyError> {
+    let dst_url = self.inner.copy_object(src_url, dst_identity).await?;
+    if file_id_from_url(&dst_url).is_some() {
+      self.counts.add_existing_ref(&dst_url).await?;
+    }
+    Ok(dst_url)
+  }
+
+  async fn trash_object(&self, url: String) -> Result<(), FlowyError> {
+    self.inner.trash_object(url).await
+  }
+
+  async fn restore_object(&self, url: String) -> Result<(), FlowyError> {
+    self.inner.restore_object(url).await
+  }
+
+  async fn purge_trash(&self, older_than: Duration) -> Result<Vec<String>, FlowyError> {
+    self.inner.purge_trash(older_than).await
+  }
+
+  fn supports_versioning(&self) -> bool {
+    self.inner.supports_versioning()
+  }
+
+  async fn list_versions(&self, url: String) -> Result<Vec<VersionMeta>, FlowyError> {
+    self.inner.list_versions(url).await
+  }
+
+  async fn get_object_version(
+    &self,
+    url: String,
+    version_id: String,
+  ) -> Result<ObjectValue, FlowyError> {
+    self.inner.get_object_version(url, version_id).await
+  }
+
+  async fn restore_version(&self, url: String, version_id: String) -> Result<(), FlowyError> {
+    self.inner.restore_version(url, version_id).await
+  }
+
+  fn supports_multipart(&self) -> bool {
+    self.inner.supports_multipart()
+  }
+
+  async fn initiate_multipart(&self, url: String, mime: Mime) -> Result<UploadId, FlowyError> {
+    self.inner.initiate_multipart(url, mime).await
+  }
+
+  async fn upload_part(
+    &self,
+    url: String,
+    upload_id: UploadId,
+    part_number: u32,
+    bytes: Bytes,
+  ) -> Result<PartETag, FlowyError> {
+    self
+      .inner
+      .upload_part(url, upload_id, part_number, bytes)
+      .await
+  }
+
+  async fn complete_multipart(
+    &self,
+    url: String,
+    upload_id: UploadId,
+    parts: Vec<PartETag>,
+  ) -> Result<(), FlowyError> {
+    if file_id_from_url(&url).is_none() {
+      return self.inner.complete_multipart(url, upload_id, parts).await;
+    }
+    self
+      .inner
+      .complete_multipart(url.clone(), upload_id, parts)
+      .await?;
+    self.counts.add_existing_ref(&url).await?;
+    Ok(())
+  }
+
+  async fn abort_multipart(&self, url: String, upload_id: UploadId) -> Result<(), FlowyError> {
+    self.inner.abort_multipart(url, upload_id).await
+  }
+
+  async fn health_check(&self) -> Result<HealthStatus, FlowyError> {
+    self.inner.health_check().await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{content_hash, memory_object_value, InMemoryObjectStorage, StorageOperation};
+
+  #[derive(Default)]
+  struct MemoryRefCountStore(Mutex<HashMap<String, u64>>);
+
+  impl RefCountStore for MemoryRefCountStore {
+    fn load_ref_counts(&self) -> HashMap<String, u64> {
+      self.0.lock().clone()
+    }
+
+    fn save_ref_counts(&self, counts: &HashMap<String, u64>) -> Result<(), FlowyError> {
+      *self.0.lock() = counts.clone();
+      Ok(())
+    }
+  }
+
+  #[tokio::test]
+  async fn delete_last_reference_test() {
+    let inner = Arc::new(InMemoryObjectStorage::new());
+    let store = Arc::new(MemoryRefCountStore::default());
+    let storage = RefCountedObjectStorage::new(inner.clone(), store.clone());
+    let identity = ObjectIdentity {
+      workspace_id: "w1".to_string(),
+      file_id: content_hash(b"hello"),
+      ext: "txt".to_string(),
+      hash_algorithm: None,
+      size: None,
+    };
+    let url = storage.get_object_url(identity).await.unwrap();
+
+    // Two documents upload the same file, a third one is a duplicate.
+    for _ in 0..2 {
+      storage
+        .put_object(url.clone(), memory_object_value("a.txt", "hello"))
+        .await
+        .unwrap();
+    }
+    assert_eq!(storage.retain(url.clone()).await.unwrap(), 3);
+    assert_eq!(inner.call_count(StorageOperation::Put), 1);
+
+    // The counts survive a restart.
+    let storage = RefCountedObjectStorage::new(inner.clone(), store);
+    assert_eq!(storage.ref_count(&url), 3);
+
+    storage.delete_object(url.clone()).await.unwrap();
+    storage.delete_object(url.clone()).await.unwrap();
+    assert!(inner.object(&url).is_some());
+
+    // A failed deletion keeps the last reference.
+    inner.fail_next(
+      StorageOperation::Delete,
+      FlowyError::new(ErrorCode::ConnectTimeout, "timeout"),
+    );
+    assert!(storage.delete_object(url.clone()).await.is_err());
+    assert_eq!(storage.ref_count(&url), 1);
+    storage.delete_object(url.clone()).await.unwrap();
+    assert!(inner.object(&url).is_none());
+    assert_eq!(storage.ref_count(&url), 0);
+  }
+
+  #[tokio::test]
+  async fn colliding_file_id_is_not_a_reference_test() {
+    let inner = Arc::new(InMemoryObjectStorage::new());
+    let storage =
+      RefCountedObjectStorage::new(inner.clone(), Arc::new(MemoryRefCountStore::default()));
+    // Two payloads forced to share the same file_id.
+    let url = format!("memory://w1/{}.txt", content_hash(b"hello"));
+    storage
+      .put_object(url.clone(), memory_object_value("a.txt", "hello"))
+      .await
+      .unwrap();
+    let err = storage
+      .put_object(url.clone(), memory_object_value("a.txt", "hello world"))
+      .await
+      .unwrap_err();
+    assert_eq!(err.code, ErrorCode::Conflict);
+    assert_eq!(storage.ref_count(&url), 1);
+    assert_eq!(inner.object(&url).unwrap().raw.as_ref(), b"hello");
+  }
+
+  #[tokio::test]
+  async fn delete_workspace_objects_forgets_counts_test() {
+    let inner = Arc::new(InMemoryObjectStorage::new());
+    let storage =
+      RefCountedObjectStorage::new(inner.clone(), Arc::new(MemoryRefCountStore::default()));
+    let mut urls = vec![];
+    for workspace_id in ["w1", "w2"] {
+      let identity = ObjectIdentity {
+        workspace_id: workspace_id.to_string(),
+        file_id: content_hash(b"hello"),
+        ext: "txt".to_string(),
+        hash_algorithm: None,
+        size: None,
+      };
+      let url = storage.get_object_url(identity).await.unwrap();
+      storage
+        .put_object(url.clone(), memory_object_value("a.txt", "hello"))
+        .await
+        .unwrap();
+      storage.retain(url.clone()).await.unwrap();
+      urls.push(url);
+    }
+
+    let report = storage.delete_workspace_objects("w1").await.unwrap();
+    assert_eq!(report.deleted, 1);
+    assert!(inner.object(&urls[0]).is_none());
+    assert_eq!(storage.ref_count(&urls[0]), 0);
+    assert_eq!(storage.ref_count(&urls[1]), 2);
+  }
+}
@@ -0,0 +1,377 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use flowy_error::{ErrorCode, FlowyError};
+use flowy_sqlite::kv::StorePreferences;
+
+use crate::multipart::{upload_part, PartReader};
+use crate::reader::read_to_bytes;
+use crate::reconnect::Reconnector;
+use crate::retry::retry;
+use crate::{
+  content_hash, fill_ext_from_mime, object_identity, object_stream_from_disk, put_object_in_parts,
+  storage_config, NetworkMonitor, ObjectIdentity, ObjectStorageService, ObjectStream, ObjectValue,
+  ObjectValueSupabase, PartETag, ReconnectPolicy, RetryPolicy, StorageObject, UploadId,
+};
+
+/// The state of an interrupted multipart upload.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadSession {
+  pub workspace_id: String,
+  pub file_name: String,
+  /// The local file being uploaded. `None` if the content was held in memory, in which case the
+  /// upload can only be resumed by a caller that still has the content.
+  pub file_path: Option<String>,
+  pub url: String,
+  pub upload_id: UploadId,
+  /// The content hash of the source when the upload was started. The upload restarts from
+  /// scratch if the source doesn't match it anymore.
+  pub content_hash: String,
+  pub part_size: usize,
+  pub parts: Vec<PartETag>,
+}
+
+impl UploadSession {
+  /// Rebuilds the [StorageObject] of the session, if its content lives on disk.
+  pub fn storage_object(&self) -> Option<StorageObject> {
+    self
+      .file_path
+      .as_ref()
+      .map(|file_path| StorageObject::from_file(&self.workspace_id, &self.file_name, file_path))
+  }
+}
+
+/// Persists the upload sessions so that they survive app restarts.
+pub trait UploadSessionStore: Send + Sync + 'static {
+  fn load_sessions(&self) -> HashMap<String, UploadSession>;
+  fn save_sessions(&self, sessions: &HashMap<String, UploadSession>) -> Result<(), FlowyError>;
+}
+
+const UPLOAD_SESSIONS_KEY: &str = "flowy_storage_upload_sessions";
+
+impl UploadSessionStore for StorePreferences {
+  fn load_sessions(&self) -> HashMap<String, UploadSession> {
+    self.get_object(UPLOAD_SESSIONS_KEY).unwrap_or_default()
+  }
+
+  fn save_sessions(&self, sessions: &HashMap<String, UploadSession>) -> Result<(), FlowyError> {
+    self.set_object(UPLOAD_SESSIONS_KEY, sessions)?;
+    Ok(())
+  }
+}
+
+/// Uploads [StorageObject]s with the multipart API and records every confirmed part, so an
+/// upload interrupted by a network failure or by closing the app continues where it stopped.
+///
+/// Sessions are keyed by the workspace id and the file name of the [StorageObject]. Uploading the
+/// same object concurrently from two tasks is not supported.
+pub struct ResumableUploader<S: ?Sized> {
+  service: Arc<S>,
+  store: Arc<dyn UploadSessionStore>,
+  sessions: Mutex<HashMap<String, UploadSession>>,
+  part_size: usize,
+  policy: RetryPolicy,
+  reconnect: ReconnectPolicy,
+  monitor: Option<NetworkMonitor>,
+}
+
+impl<S> ResumableUploader<S>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  /// Creates the uploader and loads the sessions left by a previous run from the `store`.
+  pub fn new(
+    service: Arc<S>,
+    store: Arc<dyn UploadSessionStore>,
+    part_size: usize,
+    policy: RetryPolicy,
+  ) -> Self {
+    let sessions = store.load_sessions();
+    if !sessions.is_empty() {
+      info!("found {} interrupted uploads", sessions.len());
+    }
+    Self {
+      service,
+      store,
+      sessions: Mutex::new(sessions),
+      part_size: part_size.max(1),
+      policy,
+      reconnect: ReconnectPolicy::never(),
+      monitor: None,
+    }
+  }
+
+  /// Continues the uploads whose connection is lost from their last confirmed part, instead of
+  /// failing them. With a `monitor`, an upload is interrupted as soon as the network changes and
+  /// waits for the device to be back online before it continues.
+  pub fn with_reconnect(
+    mut self,
+    policy: ReconnectPolicy,
+    monitor: Option<NetworkMonitor>,
+  ) -> Self {
+    self.reconnect = policy;
+    self.monitor = monitor;
+    self
+  }
+
+  /// Returns the uploads that were started but not completed yet.
+  pub fn pending_uploads(&self) -> Vec<UploadSession> {
+    self.sessions.lock().values().cloned().collect()
+  }
+
+  /// Uploads the object, skipping the parts that were already confirmed by the server in a
+  /// previous attempt.
+  ///
+  /// The upload starts over when the content changed since the interrupted attempt, or when the
+  /// server doesn't know the upload anymore, for example because it expired. See
+  /// [Self::with_reconnect] to continue it when the connection is lost.
+  ///
+  /// Returns the `file_id` and the url of the uploaded object.
+  pub async fn resume_upload(
+    &self,
+    object: &StorageObject,
+  ) -> Result<(String, String), FlowyError> {
+    let key = session_key(&object.workspace_id, &object.file_name);
+    let (identity, stream) = open_object(object).await?;
+    let hash = identity.file_id.clone();
+    let url = self.service.get_object_url(identity).await?;
+
+    if !self.service.supports_multipart() {
+      self.remove_session(&key)?;
+      put_object_in_parts(
+        &*self.service,
+        url.clone(),
+        stream,
+        self.part_size,
+        self.policy.clone(),
+      )
+      .await?;
+      return Ok((hash, url));
+    }
+
+    let existing = self.sessions.lock().get(&key).cloned();
+    let session = match existing {
+      Some(session)
+        if session.content_hash == hash
+          && session.url == url
+          && session.part_size == self.part_size =>
+      {
+        info!(
+          "resume upload of {} with {} uploaded parts",
+          session.url,
+          session.parts.len()
+        );
+        Some(session)
+      },
+      Some(session) => {
+        info!(
+          "content of {} changed, restart the upload",
+          session.file_name
+        );
+        self.abort_session(session).await;
+        None
+      },
+      None => None,
+    };
+    let session = match session {
+      Some(session) => session,
+      None => self.start_session(&key, object, &url, &hash).await?,
+    };
+
+    let mut reconnector = Reconnector::new(&self.reconnect, self.monitor.as_ref());
+    let mut session = session;
+    let mut stream = stream;
+    let mut restarted = false;
+    loop {
+      match reconnector
+        .attempt(self.upload_remaining(&key, session, stream))
+        .await
+      {
+        Ok(()) => return Ok((hash, url)),
+        Err(err) if err.is_record_not_found() && !restarted => {
+          warn!("upload session of {} expired, restart the upload", url);
+          restarted = true;
+          self.remove_session(&key)?;
+          session = self.start_session(&key, object, &url, &hash).await?;
+        },
+        Err(err) => {
+          reconnector.reconnect(err, &url).await?;
+          // The parts confirmed before the connection was lost were saved with the session.
+          let saved = self.sessions.lock().get(&key).cloned();
+          session = match saved {
+            Some(session) => session,
+            None => self.start_session(&key, object, &url, &hash).await?,
+          };
+          info!(
+            "reconnected, resume upload of {} after {} uploaded parts",
+            url,
+            session.parts.len()
+          );
+        },
+      }
+      // The content of an [ObjectValueSupabase::Reader] can only be read once, its upload fails
+      // here.
+      let (identity, reopened) = open_object(object).await?;
+      if identity.file_id != hash {
+        return Err(FlowyError::new(
+          ErrorCode::Internal,
+          format!("{} was modified while being uploaded", object.file_name),
+        ));
+      }
+      stream = reopened;
+    }
+  }
+
+  async fn start_session(
+    &self,
+    key: &str,
+    object: &StorageObject,
+    url: &str,
+    hash: &str,
+  ) -> Result<UploadSession, FlowyError> {
+    let upload_id = self
+      .service
+      .initiate_multipart(url.to_string(), object_mime(object))
+      .await?;
+    let file_path = match &object.value {
+      ObjectValueSupabase::File { file_path, .. } => Some(file_path.clone()),
+      ObjectValueSupabase::Bytes { .. } | ObjectValueSupabase::Reader { .. } => None,
+    };
+    let session = UploadSession {
+      workspace_id: object.workspace_id.clone(),
+      file_name: object.file_name.clone(),
+      file_path,
+      url: url.to_string(),
+      upload_id,
+      content_hash: hash.to_string(),
+      part_size: self.part_size,
+      parts: vec![],
+    };
+    self.save_session(key, &session)?;
+    Ok(session)
+  }
+
+  async fn upload_remaining(
+    &self,
+    key: &str,
+    mut session: UploadSession,
+    object: ObjectStream,
+  ) -> Result<(), FlowyError> {
+    let uploaded = session
+      .parts
+      .iter()
+      .map(|part| part.part_number)
+      .collect::<HashSet<_>>();
+    let mut reader = PartReader::new(object.stream, session.part_size);
+    let mut part_number = 0;
+    while let Some(part) = reader.next_part().await? {
+      part_number += 1;
+      if uploaded.contains(&part_number) {
+        continue;
+      }
+
+      let part = upload_part(
+        &*self.service,
+        &session.url,
+        &session.upload_id,
+        part_number,
+        part,
+        &self.policy,
+      )
+      .await?;
+      session.parts.push(part);
+      self.save_session(key, &session)?;
+    }
+
+    session.parts.sort_by_key(|part| part.part_number);
+    retry(self.policy.clone(), || {
+      self.service.complete_multipart(
+        session.url.clone(),
+        session.upload_id.clone(),
+        session.parts.clone(),
+      )
+    })
+    .await?;
+    self.remove_session(key)
+  }
+
+  async fn abort_session(&self, session: UploadSession) {
+    let key = session_key(&session.workspace_id, &session.file_name);
+    if let Err(err) = self
+      .service
+      .abort_multipart(session.url, session.upload_id)
+      .await
+    {
+      warn!("abort stale upload session failed: {}", err);
+    }
+    if let Err(err) = self.remove_session(&key) {
+      warn!("remove stale upload session failed: {}", err);
+    }
+  }
+
+  fn save_session(&self, key: &str, session: &UploadSession) -> Result<(), FlowyError> {
+    let mut sessions = self.sessions.lock();
+    sessions.insert(key.to_string(), session.clone());
+    self.store.save_sessions(&sessions)
+  }
+
+  fn remove_session(&self, key: &str) -> Result<(), FlowyError> {
+    let mut sessions = self.sessions.lock();
+    if sessions.remove(key).is_some() {
+      self.store.save_sessions(&sessions)?;
+    }
+    Ok(())
+  }
+}
+
+fn session_key(workspace_id: &str, file_name: &str) -> String {
+  format!("{}:{}", workspace_id, file_name)
+}
+
+fn object_mime(object: &StorageObject) -> mime::Mime {
+  object
+    .value
+    .mime_type(true)
+    .parse()
+    .unwrap_or(mime::APPLICATION_OCTET_STREAM)
+}
+
+async fn open_object(object: &StorageObject) -> Result<(ObjectIdentity, ObjectStream), FlowyError> {
+  match &object.value {
+    ObjectValueSupabase::File { file_path, .. } => {
+      let read_buffer_size = storage_config().read_buffer_size;
+      object_stream_from_disk(&object.workspace_id, file_path, read_buffer_size).await
+    },
+    ObjectValueSupabase::Bytes { bytes, .. } => Ok(bytes_stream(object, bytes.clone())),
+    // The session is keyed by the content hash, so the content is read before the upload starts.
+    ObjectValueSupabase::Reader {
+      reader,
+      content_length,
+      ..
+    } => {
+      let bytes = read_to_bytes(reader.take()?, *content_length).await?;
+      Ok(bytes_stream(object, bytes))
+    },
+  }
+}
+
+fn bytes_stream(object: &StorageObject, bytes: Bytes) -> (ObjectIdentity, ObjectStream) {
+  let mut identity = object_identity(
+    &object.workspace_id,
+    &object.file_name,
+    content_hash(&bytes),
+    Some(bytes.len() as u64),
+  );
+  let value = ObjectValue {
+    raw: bytes,
+    mime: object_mime(object),
+    content_encoding: None,
+  };
+  fill_ext_from_mime(&mut identity, &value.mime);
+  (identity, value.into())
+}
@@ -0,0 +1,586 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use mime::Mime;
+use rand::Rng;
+use tracing::warn;
+
+use flowy_error::FlowyError;
+
+use crate::{
+  DeleteReport, HealthStatus, ListOptions, ObjectIdentity, ObjectMeta, ObjectStorageService,
+  ObjectStream, ObjectValue, PartETag, ProgressCallback, StorageErrorExt, StorageErrorKind,
+  UploadId, VersionMeta,
+};
+
+/// Controls how [RetryingObjectStorage] retries a failed operation.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+  /// The maximum number of attempts, including the first one.
+  pub max_attempts: usize,
+  /// The delay before the first retry. The delay doubles after each attempt.
+  pub base_delay: Duration,
+  /// The upper bound of the delay between two attempts.
+  pub max_delay: Duration,
+  /// Uploads are not retried by default because a failed upload might have partially written
+  /// the object on the server.
+  pub retry_put: bool,
+}
+
+impl Default for RetryPolicy {
+  fn default() -> Self {
+    Self {
+      max_attempts: 3,
+      base_delay: Duration::from_millis(200),
+      max_delay: Duration::from_secs(5),
+      retry_put: false,
+    }
+  }
+}
+
+impl RetryPolicy {
+  pub fn with_retry_put(mut self, retry_put: bool) -> Self {
+    self.retry_put = retry_put;
+    self
+  }
+
+  /// Returns the delay before the next attempt. `attempt` starts from 1. The delay grows
+  /// exponentially and a random jitter is applied so that clients that failed at the same time
+  /// don't retry at the same time.
+  pub fn backoff(&self, attempt: usize) -> Duration {
+    let exp = attempt.saturating_sub(1).min(31) as u32;
+    let delay = self
+      .base_delay
+      .saturating_mul(2u32.saturating_pow(exp))
+      .min(self.max_delay);
+    let half = delay / 2;
+    half + delay.mul_f64(rand::thread_rng().gen_range(0.0..0.5))
+  }
+}
+
+/// Returns true if the operation that produced the error might succeed when it's tried again,
+/// see [StorageErrorKind::is_transient]. Errors like an exceeded quota or an unauthorized user
+/// are returned to the caller right away. A corrupt content is retried because it's usually
+/// caused by a truncated response.
+pub fn is_retryable_error(error: &FlowyError) -> bool {
+  error
+    .storage_kind()
+    .map(StorageErrorKind::is_transient)
+    .unwrap_or(false)
+}
+
+/// An [ObjectStorageService] that retries the failed operations of the inner service according
+/// to the [RetryPolicy].
+pub struct RetryingObjectStorage<S: ?Sized> {
+  inner: Arc<S>,
+  policy: RetryPolicy,
+}
+
+impl<S> RetryingObjectStorage<S>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  pub fn new(inner: Arc<S>, policy: RetryPolicy) -> Self {
+    Self { inner, policy }
+  }
+}
+
+pub(crate) async fn retry<T, F, Fut>(policy: RetryPolicy, mut action: F) -> Result<T, FlowyError>
+where
+  F: FnMut() -> Fut,
+  Fut: Future<Output = Result<T, FlowyError>>,
+{
+  let mut attempt = 1;
+  loop {
+    match action().await {
+      Ok(value) => return Ok(value),
+      Err(err) if attempt < policy.max_attempts && is_retryable_error(&err) => {
+        let delay = policy.backoff(attempt);
+        warn!(
+          "storage operation failed on attempt {}, retry in {:?}: {}",
+          attempt, delay, err
+        );
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+      },
+      Err(err) => return Err(err),
+    }
+  }
+}
+
+#[async_trait]
+impl<S> ObjectStorageService for RetryingObjectStorage<S>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  async fn get_object_url(&self, object_id: ObjectIdentity) -> Result<String, FlowyError> {
+    self.inner.get_object_url(object_id).await
+  }
+
+  async fn put_object(&self, url: String, object_value: ObjectValue) -> Result<(), FlowyError> {
+    if !self.policy.retry_put {
+      return self.inner.put_object(url, object_value).await;
+    }
+    retry(self.policy.clone(), || {
+      self.inner.put_object(url.clone(), object_value.clone())
+    })
+    .await
+  }
+
+  async fn put_object_with_progress(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    progress: ProgressCallback,
+  ) -> Result<(), FlowyError> {
+    if !self.policy.retry_put {
+      return self
+        .inner
+        .put_object_with_progress(url, object_value, progress)
+        .await;
+    }
+
+    let progress = Arc::new(progress);
+    retry(self.policy.clone(), || {
+      let progress = progress.clone();
+      self.inner.put_object_with_progress(
+        url.clone(),
+        object_value.clone(),
+        Box::new(move |sent, total| progress(sent, total)),
+      )
+    })
+    .await
+  }
+
+  fn supports_object_ttl(&self) -> bool {
+    self.inner.supports_object_ttl()
+  }
+
+  async fn put_object_with_ttl(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    ttl: Duration,
+  ) -> Result<(), FlowyError> {
+    if !self.policy.retry_put {
+      return self.inner.put_object_with_ttl(url, object_value, ttl).await;
+    }
+    retry(self.policy.clone(), || {
+      self
+        .inner
+        .put_object_with_ttl(url.clone(), object_value.clone(), ttl)
+    })
+    .await
+  }
+
+  async fn put_object_if_absent(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+  ) -> Result<(), FlowyError> {
+    if !self.policy.retry_put {
+      return self.inner.put_object_if_absent(url, object_value).await;
+    }
+    // Every attempt checks the precondition again, so the attempt following one whose response
+    // was lost after the object was written fails with a precondition error.
+    retry(self.policy.clone(), || {
+      self
+        .inner
+        .put_object_if_absent(url.clone(), object_value.clone())
+    })
+    .await
+  }
+
+  async fn put_object_if_match(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    etag: String,
+  ) -> Result<(), FlowyError> {
+    if !self.policy.retry_put {
+      return self
+        .inner
+        .put_object_if_match(url, object_value, etag)
+        .await;
+    }
+    retry(self.policy.clone(), || {
+      self
+        .inner
+        .put_object_if_match(url.clone(), object_value.clone(), etag.clone())
+    })
+    .await
+  }
+
+  async fn delete_object(&self, url: String) -> Result<(), FlowyError> {
+    retry(self.policy.clone(), || {
+      self.inner.delete_object(url.clone())
+    })
+    .await
+  }
+
+  async fn delete_objects(
+    &self,
+    urls: Vec<String>,
+  ) -> Result<Vec<Result<(), FlowyError>>, FlowyError> {
+    let mut results = retry(self.policy.clone(), || {
+      self.inner.delete_objects(urls.clone())
+    })
+    .await?;
+    // Only the deletions that failed with a retryable error are sent again, so the objects that
+    // were already deleted are not deleted twice.
+    for attempt in 1..self.policy.max_attempts {
+      let failed = results
+        .iter()
+        .enumerate()
+        .filter(|(_, result)| matches!(result, Err(err) if is_retryable_error(err)))
+        .map(|(index, _)| index)
+        .collect::<Vec<_>>();
+      if failed.is_empty() {
+        break;
+      }
+
+      let delay = self.policy.backoff(attempt);
+      warn!(
+        "{} deletions failed on attempt {}, retry in {:?}",
+        failed.len(),
+        attempt,
+        delay
+      );
+      tokio::time::sleep(delay).await;
+      let retry_urls = failed.iter().map(|index| urls[*index].clone()).collect();
+      let retried = self.inner.delete_objects(retry_urls).await?;
+      for (index, result) in failed.into_iter().zip(retried) {
+        results[index] = result;
+      }
+    }
+    Ok(results)
+  }
+
+  async fn delete_workspace_objects(&self, workspace_id: &str) -> Result<DeleteReport, FlowyError> {
+    // Only the objects left are deleted again, it doesn't matter which ones the failed attempt
+    // deleted.
+    retry(self.policy.clone(), || {
+      self.inner.delete_workspace_objects(workspace_id)
+    })
+    .await
+  }
+
+  async fn list_objects(
+    &self,
+    workspace_id: &str,
+    prefix: Option<&str>,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    retry(self.policy.clone(), || {
+      self.inner.list_objects(workspace_id, prefix)
+    })
+    .await
+  }
+
+  async fn list_objects_with_options(
+    &self,
+    workspace_id: &str,
+    options: &ListOptions,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    retry(self.policy.clone(), || {
+      self.inner.list_objects_with_options(workspace_id, options)
+    })
+    .await
+  }
+
+  async fn get_object(&self, url: String) -> Result<ObjectValue, FlowyError> {
+    retry(self.policy.clone(), || self.inner.get_object(url.clone())).await
+  }
+
+  async fn get_object_if_modified(
+    &self,
+    url: String,
+    etag: Option<String>,
+  ) -> Result<Option<(ObjectValue, String)>, FlowyError> {
+    retry(self.policy.clone(), || {
+      self.inner.get_object_if_modified(url.clone(), etag.clone())
+    })
+    .await
+  }
+
+  async fn get_object_stream(&self, url: String) -> Result<ObjectStream, FlowyError> {
+    // Only opening the stream is retried, a failure in the middle of the body is returned to
+    // the caller.
+    retry(self.policy.clone(), || {
+      self.inner.get_object_stream(url.clone())
+    })
+    .await
+  }
+
+  async fn get_object_verified(
+    &self,
+    url: String,
+    expected_file_id: &str,
+  ) -> Result<ObjectValue, FlowyError> {
+    retry(self.policy.clone(), || {
+      self
+        .inner
+        .get_object_verified(url.clone(), expected_file_id)
+    })
+    .await
+  }
+
+  fn supports_range_requests(&self) -> bool {
+    self.inner.supports_range_requests()
+  }
+
+  async fn get_object_range(
+    &self,
+    url: String,
+    start: u64,
+    end: Option<u64>,
+  ) -> Result<ObjectValue, FlowyError> {
+    retry(self.policy.clone(), || {
+      self.inner.get_object_range(url.clone(), start, end)
+    })
+    .await
+  }
+
+  async fn head_object(&self, url: String) -> Result<ObjectMeta, FlowyError> {
+    retry(self.policy.clone(), || self.inner.head_object(url.clone())).await
+  }
+
+  async fn object_exists(&self, url: String) -> Result<bool, FlowyError> {
+    retry(self.policy.clone(), || {
+      self.inner.object_exists(url.clone())
+    })
+    .await
+  }
+
+  async fn presign_get_url(&self, url: String, expires_in: Duration) -> Result<String, FlowyError> {
+    retry(self.policy.clone(), || {
+      self.inner.presign_get_url(url.clone(), expires_in)
+    })
+    .await
+  }
+
+  async fn presign_put_url(&self, url: String, expires_in: Duration) -> Result<String, FlowyError> {
+    retry(self.policy.clone(), || {
+      self.inner.presign_put_url(url.clone(), expires_in)
+    })
+    .await
+  }
+
+  fn supports_copy_object(&self) -> bool {
+    self.inner.supports_copy_object()
+  }
+
+  async fn copy_object(
+    &self,
+    src_url: String,
+    dst_identity: ObjectIdentity,
+  ) -> Result<String, FlowyError> {
+    // Copying the same object again produces the same object, so a copy is always safe to retry.
+    retry(self.policy.clone(), || {
+      self
+        .inner
+        .copy_object(src_url.clone(), dst_identity.clone())
+    })
+    .await
+  }
+
+  // Trashing or restoring an object that was already moved fails with a not found error, which
+  // is not retried.
+  async fn trash_object(&self, url: String) -> Result<(), FlowyError> {
+    retry(self.policy.clone(), || self.inner.trash_object(url.clone())).await
+  }
+
+  async fn restore_object(&self, url: String) -> Result<(), FlowyError> {
+    retry(self.policy.clone(), || {
+      self.inner.restore_object(url.clone())
+    })
+    .await
+  }
+
+  async fn purge_trash(&self, older_than: Duration) -> Result<Vec<String>, FlowyError> {
+    retry(self.policy.clone(), || self.inner.purge_trash(older_than)).await
+  }
+
+  fn supports_versioning(&self) -> bool {
+    self.inner.supports_versioning()
+  }
+
+  async fn list_versions(&self, url: String) -> Result<Vec<VersionMeta>, FlowyError> {
+    self.inner.list_versions(url).await
+  }
+
+  async fn get_object_version(
+    &self,
+    url: String,
+    version_id: String,
+  ) -> Result<ObjectValue, FlowyError> {
+    retry(self.policy.clone(), || {
+      self
+        .inner
+        .get_object_version(url.clone(), version_id.clone())
+    })
+    .await
+  }
+
+  async fn restore_version(&self, url: String, version_id: String) -> Result<(), FlowyError> {
+    self.inner.restore_version(url, version_id).await
+  }
+
+  fn supports_multipart(&self) -> bool {
+    self.inner.supports_multipart()
+  }
+
+  async fn initiate_multipart(&self, url: String, mime: Mime) -> Result<UploadId, FlowyError> {
+    self.inner.initiate_multipart(url, mime).await
+  }
+
+  async fn upload_part(
+    &self,
+    url: String,
+    upload_id: UploadId,
+    part_number: u32,
+    bytes: Bytes,
+  ) -> Result<PartETag, FlowyError> {
+    // Uploading the same part again replaces it, so a part is always safe to retry.
+    retry(self.policy.clone(), || {
+      self
+        .inner
+        .upload_part(url.clone(), upload_id.clone(), part_number, bytes.clone())
+    })
+    .await
+  }
+
+  async fn complete_multipart(
+    &self,
+    url: String,
+    upload_id: UploadId,
+    parts: Vec<PartETag>,
+  ) -> Result<(), FlowyError> {
+    retry(self.policy.clone(), || {
+      self
+        .inner
+        .complete_multipart(url.clone(), upload_id.clone(), parts.clone())
+    })
+    .await
+  }
+
+  async fn abort_multipart(&self, url: String, upload_id: UploadId) -> Result<(), FlowyError> {
+    retry(self.policy.clone(), || {
+      self.inner.abort_multipart(url.clone(), upload_id.clone())
+    })
+    .await
+  }
+
+  async fn health_check(&self) -> Result<HealthStatus, FlowyError> {
+    self.inner.health_check().await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use flowy_error::ErrorCode;
+
+  use super::*;
+  use crate::{memory_object_value, InMemoryObjectStorage, StorageOperation};
+
+  const URL: &str = "memory://w1/1.bin";
+
+  /// Makes the next `count` calls of each operation fail with `error`.
+  fn retrying_storage(
+    error: ErrorCode,
+    count: usize,
+    retry_put: bool,
+  ) -> (
+    Arc<InMemoryObjectStorage>,
+    RetryingObjectStorage<InMemoryObjectStorage>,
+  ) {
+    let inner = Arc::new(InMemoryObjectStorage::new());
+    for op in [
+      StorageOperation::Put,
+      StorageOperation::Delete,
+      StorageOperation::Get,
+    ] {
+      for _ in 0..count {
+        inner.fail_next(op, error.clone().into());
+      }
+    }
+    let policy = RetryPolicy {
+      max_attempts: 3,
+      base_delay: Duration::from_millis(1),
+      max_delay: Duration::from_millis(2),
+      retry_put,
+    };
+    (inner.clone(), RetryingObjectStorage::new(inner, policy))
+  }
+
+  fn value() -> ObjectValue {
+    memory_object_value("1.bin", vec![1, 2, 3])
+  }
+
+  #[tokio::test]
+  async fn retry_retryable_error_until_max_attempts() {
+    let (inner, storage) = retrying_storage(ErrorCode::HttpError, 3, false);
+    assert!(storage.get_object(URL.to_string()).await.is_err());
+    assert_eq!(inner.call_count(StorageOperation::Get), 3);
+  }
+
+  #[tokio::test]
+  async fn retryable_error_is_recovered_from() {
+    let (inner, storage) = retrying_storage(ErrorCode::HttpError, 2, true);
+    storage.put_object(URL.to_string(), value()).await.unwrap();
+    assert_eq!(inner.call_count(StorageOperation::Put), 3);
+    assert_eq!(
+      storage.get_object(URL.to_string()).await.unwrap().raw,
+      value().raw
+    );
+  }
+
+  #[tokio::test]
+  async fn non_retryable_error_is_returned_immediately() {
+    let (inner, storage) = retrying_storage(ErrorCode::ExcessStorageLimited, 3, true);
+    let err = storage
+      .put_object(URL.to_string(), value())
+      .await
+      .unwrap_err();
+    assert_eq!(err.code, ErrorCode::ExcessStorageLimited);
+    assert_eq!(inner.call_count(StorageOperation::Put), 1);
+  }
+
+  #[tokio::test]
+  async fn put_is_only_retried_when_enabled() {
+    let (inner, storage) = retrying_storage(ErrorCode::HttpError, 3, false);
+    assert!(storage.put_object(URL.to_string(), value()).await.is_err());
+    assert_eq!(inner.call_count(StorageOperation::Put), 1);
+
+    let (inner, storage) = retrying_storage(ErrorCode::HttpError, 3, true);
+    assert!(storage.put_object(URL.to_string(), value()).await.is_err());
+    assert_eq!(inner.call_count(StorageOperation::Put), 3);
+  }
+
+  #[tokio::test]
+  async fn failed_deletions_are_retried() {
+    let (inner, storage) = retrying_storage(ErrorCode::HttpError, 6, false);
+    let results = storage
+      .delete_objects(vec![
+        "memory://w1/a.bin".to_string(),
+        "memory://w1/b.bin".to_string(),
+      ])
+      .await
+      .unwrap();
+    assert_eq!(results.len(), 2);
+    assert!(results.iter().all(|result| result.is_err()));
+    assert_eq!(inner.call_count(StorageOperation::Delete), 6);
+  }
+
+  #[test]
+  fn backoff_is_bounded_by_max_delay() {
+    let policy = RetryPolicy::default();
+    for attempt in 1..40 {
+      let delay = policy.backoff(attempt);
+      assert!(delay <= policy.max_delay);
+      assert!(delay >= policy.base_delay.min(policy.max_delay) / 2);
+    }
+  }
+}
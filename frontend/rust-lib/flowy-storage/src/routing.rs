@@ -0,0 +1,653 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use mime::Mime;
+
+use flowy_error::{ErrorCode, FlowyError};
+
+use crate::{
+  guess_mime, DeleteReport, HealthStatus, ListOptions, ObjectIdentity, ObjectMeta,
+  ObjectStorageService, ObjectStream, ObjectValue, PartETag, ProgressCallback, UploadId,
+  VersionMeta,
+};
+
+/// The scheme of the urls returned by [RoutingObjectStorage]: `routed://{backend}/{url}`, where
+/// `url` is the url of the object in the backend.
+const ROUTED_URL_SCHEME: &str = "routed://";
+
+/// Sends the objects matching all its conditions to `backend`, see [RoutingObjectStorage]. A
+/// rule without conditions matches every object.
+#[derive(Debug, Clone)]
+pub struct RoutingRule {
+  pub backend: String,
+  /// Matches the objects whose mime type starts with the prefix, like `image/`.
+  pub mime_prefix: Option<String>,
+  /// Matches the objects of at least this many bytes. The rules with a size condition never
+  /// match when the size is not known, see [RoutingObjectStorage::get_object_url_with_size].
+  pub min_size: Option<u64>,
+  /// Matches the objects of at most this many bytes.
+  pub max_size: Option<u64>,
+  pub workspace_id: Option<String>,
+}
+
+impl RoutingRule {
+  pub fn new(backend: &str) -> Self {
+    Self {
+      backend: backend.to_string(),
+      mime_prefix: None,
+      min_size: None,
+      max_size: None,
+      workspace_id: None,
+    }
+  }
+
+  pub fn with_mime_prefix(mut self, mime_prefix: &str) -> Self {
+    self.mime_prefix = Some(mime_prefix.to_string());
+    self
+  }
+
+  pub fn with_min_size(mut self, min_size: u64) -> Self {
+    self.min_size = Some(min_size);
+    self
+  }
+
+  pub fn with_max_size(mut self, max_size: u64) -> Self {
+    self.max_size = Some(max_size);
+    self
+  }
+
+  pub fn with_workspace_id(mut self, workspace_id: &str) -> Self {
+    self.workspace_id = Some(workspace_id.to_string());
+    self
+  }
+
+  fn matches(&self, workspace_id: &str, mime: &Mime, size: Option<u64>) -> bool {
+    if let Some(prefix) = &self.mime_prefix {
+      if !mime.essence_str().starts_with(prefix.as_str()) {
+        return false;
+      }
+    }
+    if let Some(id) = &self.workspace_id {
+      if id != workspace_id {
+        return false;
+      }
+    }
+    if self.min_size.is_none() && self.max_size.is_none() {
+      return true;
+    }
+    match size {
+      Some(size) => {
+        self.min_size.map_or(true, |min| size >= min)
+          && self.max_size.map_or(true, |max| size <= max)
+      },
+      None => false,
+    }
+  }
+}
+
+fn routed_url(backend: &str, url: &str) -> String {
+  format!("{}{}/{}", ROUTED_URL_SCHEME, backend, url)
+}
+
+fn routed_objects(
+  backend: &str,
+  objects: Vec<ObjectMeta>,
+) -> impl Iterator<Item = ObjectMeta> + '_ {
+  objects.into_iter().map(move |mut object| {
+    object.url = routed_url(backend, &object.url);
+    object
+  })
+}
+
+/// An [ObjectStorageService] that spreads the objects over several named backends, for example
+/// the images on a bucket behind a CDN and the other files on a cheaper one. The backend of a new
+/// object is chosen by the first [RoutingRule] it matches, in the order they were added, or the
+/// default backend if it matches none. The url of the object records the backend, so the
+/// following operations on the url go to the same backend whatever the rules became. The urls
+/// that don't record a backend, the objects stored before the routing was set up, go to the
+/// default backend.
+///
+/// The listings and [ObjectStorageService::purge_trash] cover all the backends, in the order
+/// they were added. Copying an object to another backend downloads and uploads it.
+pub struct RoutingObjectStorage {
+  /// The default backend comes first.
+  backends: Vec<(String, Arc<dyn ObjectStorageService>)>,
+  rules: Vec<RoutingRule>,
+}
+
+impl RoutingObjectStorage {
+  pub fn new(default_backend: &str, service: Arc<dyn ObjectStorageService>) -> Self {
+    Self {
+      backends: vec![(default_backend.to_string(), service)],
+      rules: vec![],
+    }
+  }
+
+  /// Adds a backend the rules can route to. A backend with the same name is replaced.
+  pub fn with_backend(mut self, name: &str, service: Arc<dyn ObjectStorageService>) -> Self {
+    match self.backends.iter_mut().find(|(n, _)| n == name) {
+      Some(backend) => backend.1 = service,
+      None => self.backends.push((name.to_string(), service)),
+    }
+    self
+  }
+
+  pub fn with_rule(mut self, rule: RoutingRule) -> Self {
+    self.rules.push(rule);
+    self
+  }
+
+  /// Returns the url of the object in the backend chosen for an object of `size` bytes. Unlike
+  /// [ObjectStorageService::get_object_url], the rules with a size condition are applied.
+  pub async fn get_object_url_with_size(
+    &self,
+    object_id: ObjectIdentity,
+    size: u64,
+  ) -> Result<String, FlowyError> {
+    self.url_for(object_id, Some(size)).await
+  }
+
+  /// Returns the name of the backend a new object goes to.
+  pub fn backend_for(&self, workspace_id: &str, mime: &Mime, size: Option<u64>) -> &str {
+    self
+      .rules
+      .iter()
+      .find(|rule| rule.matches(workspace_id, mime, size))
+      .map(|rule| rule.backend.as_str())
+      .unwrap_or(&self.backends[0].0)
+  }
+
+  fn backend(&self, name: &str) -> Result<&Arc<dyn ObjectStorageService>, FlowyError> {
+    self
+      .backends
+      .iter()
+      .find(|(n, _)| n == name)
+      .map(|(_, service)| service)
+      .ok_or_else(|| {
+        FlowyError::new(
+          ErrorCode::InvalidParams,
+          format!("unknown storage backend: {}", name),
+        )
+      })
+  }
+
+  async fn url_for(
+    &self,
+    object_id: ObjectIdentity,
+    size: Option<u64>,
+  ) -> Result<String, FlowyError> {
+    let mime = guess_mime(&format!("{}.{}", object_id.file_id, object_id.ext));
+    let name = self.backend_for(&object_id.workspace_id, &mime, size);
+    let url = self.backend(name)?.get_object_url(object_id).await?;
+    Ok(routed_url(name, &url))
+  }
+
+  /// Returns the name of the backend owning the url and the url of the object in it.
+  fn split<'a>(&'a self, url: &'a str) -> (&'a str, &'a str) {
+    url
+      .strip_prefix(ROUTED_URL_SCHEME)
+      .and_then(|rest| rest.split_once('/'))
+      .unwrap_or((&self.backends[0].0, url))
+  }
+
+  fn route(&self, url: &str) -> Result<(&Arc<dyn ObjectStorageService>, String), FlowyError> {
+    let (name, url) = self.split(url);
+    Ok((self.backend(name)?, url.to_string()))
+  }
+}
+
+#[async_trait]
+impl ObjectStorageService for RoutingObjectStorage {
+  async fn get_object_url(&self, object_id: ObjectIdentity) -> Result<String, FlowyError> {
+    self.url_for(object_id, None).await
+  }
+
+  async fn put_object(&self, url: String, object_value: ObjectValue) -> Result<(), FlowyError> {
+    let (backend, url) = self.route(&url)?;
+    backend.put_object(url, object_value).await
+  }
+
+  async fn put_object_with_progress(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    progress: ProgressCallback,
+  ) -> Result<(), FlowyError> {
+    let (backend, url) = self.route(&url)?;
+    backend
+      .put_object_with_progress(url, object_value, progress)
+      .await
+  }
+
+  fn supports_object_ttl(&self) -> bool {
+    self
+      .backends
+      .iter()
+      .all(|(_, service)| service.supports_object_ttl())
+  }
+
+  async fn put_object_with_ttl(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    ttl: Duration,
+  ) -> Result<(), FlowyError> {
+    let (backend, url) = self.route(&url)?;
+    backend.put_object_with_ttl(url, object_value, ttl).await
+  }
+
+  async fn put_object_if_absent(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+  ) -> Result<(), FlowyError> {
+    let (backend, url) = self.route(&url)?;
+    backend.put_object_if_absent(url, object_value).await
+  }
+
+  async fn put_object_if_match(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    etag: String,
+  ) -> Result<(), FlowyError> {
+    let (backend, url) = self.route(&url)?;
+    backend.put_object_if_match(url, object_value, etag).await
+  }
+
+  async fn delete_object(&self, url: String) -> Result<(), FlowyError> {
+    let (backend, url) = self.route(&url)?;
+    backend.delete_object(url).await
+  }
+
+  /// The urls are deleted in one batch per backend, the results keep the order of `urls`.
+  async fn delete_objects(
+    &self,
+    urls: Vec<String>,
+  ) -> Result<Vec<Result<(), FlowyError>>, FlowyError> {
+    let mut results = (0..urls.len()).map(|_| Ok(())).collect::<Vec<_>>();
+    for (name, service) in &self.backends {
+      let (indexes, backend_urls): (Vec<usize>, Vec<String>) = urls
+        .iter()
+        .enumerate()
+        .filter_map(|(i, url)| {
+          let (owner, url) = self.split(url);
+          (owner == name).then(|| (i, url.to_string()))
+        })
+        .unzip();
+      if backend_urls.is_empty() {
+        continue;
+      }
+      for (i, result) in indexes
+        .into_iter()
+        .zip(service.delete_objects(backend_urls).await?)
+      {
+        results[i] = result;
+      }
+    }
+    for (i, url) in urls.iter().enumerate() {
+      if let Err(err) = self.backend(self.split(url).0) {
+        results[i] = Err(err);
+      }
+    }
+    Ok(results)
+  }
+
+  async fn delete_workspace_objects(&self, workspace_id: &str) -> Result<DeleteReport, FlowyError> {
+    let mut report = DeleteReport::default();
+    for (name, service) in &self.backends {
+      let deleted = service.delete_workspace_objects(workspace_id).await?;
+      report.deleted += deleted.deleted;
+      report.freed_bytes += deleted.freed_bytes;
+      report.failed.extend(
+        deleted
+          .failed
+          .into_iter()
+          .map(|(url, err)| (routed_url(name, &url), err)),
+      );
+    }
+    Ok(report)
+  }
+
+  async fn list_objects(
+    &self,
+    workspace_id: &str,
+    prefix: Option<&str>,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    let mut objects = vec![];
+    for (name, service) in &self.backends {
+      objects.extend(routed_objects(
+        name,
+        service.list_objects(workspace_id, prefix).await?,
+      ));
+    }
+    Ok(objects)
+  }
+
+  async fn list_objects_with_options(
+    &self,
+    workspace_id: &str,
+    options: &ListOptions,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    let mut objects = vec![];
+    for (name, service) in &self.backends {
+      objects.extend(routed_objects(
+        name,
+        service
+          .list_objects_with_options(workspace_id, options)
+          .await?,
+      ));
+    }
+    Ok(objects)
+  }
+
+  async fn get_object(&self, url: String) -> Result<ObjectValue, FlowyError> {
+    let (backend, url) = self.route(&url)?;
+    backend.get_object(url).await
+  }
+
+  async fn get_object_if_modified(
+    &self,
+    url: String,
+    etag: Option<String>,
+  ) -> Result<Option<(ObjectValue, String)>, FlowyError> {
+    let (backend, url) = self.route(&url)?;
+    backend.get_object_if_modified(url, etag).await
+  }
+
+  async fn get_object_stream(&self, url: String) -> Result<ObjectStream, FlowyError> {
+    let (backend, url) = self.route(&url)?;
+    backend.get_object_stream(url).await
+  }
+
+  async fn get_object_verified(
+    &self,
+    url: String,
+    expected_file_id: &str,
+  ) -> Result<ObjectValue, FlowyError> {
+    let (backend, url) = self.route(&url)?;
+    backend.get_object_verified(url, expected_file_id).await
+  }
+
+  fn supports_range_requests(&self) -> bool {
+    self
+      .backends
+      .iter()
+      .all(|(_, service)| service.supports_range_requests())
+  }
+
+  async fn get_object_range(
+    &self,
+    url: String,
+    start: u64,
+    end: Option<u64>,
+  ) -> Result<ObjectValue, FlowyError> {
+    let (backend, url) = self.route(&url)?;
+    backend.get_object_range(url, start, end).await
+  }
+
+  async fn head_object(&self, url: String) -> Result<ObjectMeta, FlowyError> {
+    let (backend, backend_url) = self.route(&url)?;
+    let mut meta = backend.head_object(backend_url).await?;
+    meta.url = url;
+    Ok(meta)
+  }
+
+  async fn object_exists(&self, url: String) -> Result<bool, FlowyError> {
+    let (backend, url) = self.route(&url)?;
+    backend.object_exists(url).await
+  }
+
+  async fn presign_get_url(&self, url: String, expires_in: Duration) -> Result<String, FlowyError> {
+    let (backend, url) = self.route(&url)?;
+    backend.presign_get_url(url, expires_in).await
+  }
+
+  async fn presign_put_url(&self, url: String, expires_in: Duration) -> Result<String, FlowyError> {
+    let (backend, url) = self.route(&url)?;
+    backend.presign_put_url(url, expires_in).await
+  }
+
+  fn supports_copy_object(&self) -> bool {
+    self
+      .backends
+      .iter()
+      .all(|(_, service)| service.supports_copy_object())
+  }
+
+  async fn copy_object(
+    &self,
+    src_url: String,
+    dst_identity: ObjectIdentity,
+  ) -> Result<String, FlowyError> {
+    let (src, src_backend_url) = self.route(&src_url)?;
+    let dst_url = self.url_for(dst_identity.clone(), None).await?;
+    let (src_name, dst_name) = (self.split(&src_url).0, self.split(&dst_url).0);
+    if src_name == dst_name {
+      let url = src.copy_object(src_backend_url, dst_identity).await?;
+      return Ok(routed_url(src_name, &url));
+    }
+    let value = src.get_object(src_backend_url).await?;
+    let (dst, dst_backend_url) = self.route(&dst_url)?;
+    dst.put_object(dst_backend_url, value).await?;
+    Ok(dst_url)
+  }
+
+  async fn trash_object(&self, url: String) -> Result<(), FlowyError> {
+    let (backend, url) = self.route(&url)?;
+    backend.trash_object(url).await
+  }
+
+  async fn restore_object(&self, url: String) -> Result<(), FlowyError> {
+    let (backend, url) = self.route(&url)?;
+    backend.restore_object(url).await
+  }
+
+  async fn purge_trash(&self, older_than: Duration) -> Result<Vec<String>, FlowyError> {
+    let mut purged = vec![];
+    for (name, service) in &self.backends {
+      for url in service.purge_trash(older_than).await? {
+        purged.push(routed_url(name, &url));
+      }
+    }
+    Ok(purged)
+  }
+
+  fn supports_versioning(&self) -> bool {
+    self
+      .backends
+      .iter()
+      .all(|(_, service)| service.supports_versioning())
+  }
+
+  async fn list_versions(&self, url: String) -> Result<Vec<VersionMeta>, FlowyError> {
+    let (backend, url) = self.route(&url)?;
+    backend.list_versions(url).await
+  }
+
+  async fn get_object_version(
+    &self,
+    url: String,
+    version_id: String,
+  ) -> Result<ObjectValue, FlowyError> {
+    let (backend, url) = self.route(&url)?;
+    backend.get_object_version(url, version_id).await
+  }
+
+  async fn restore_version(&self, url: String, version_id: String) -> Result<(), FlowyError> {
+    let (backend, url) = self.route(&url)?;
+    backend.restore_version(url, version_id).await
+  }
+
+  fn supports_multipart(&self) -> bool {
+    self
+      .backends
+      .iter()
+      .all(|(_, service)| service.supports_multipart())
+  }
+
+  async fn initiate_multipart(&self, url: String, mime: Mime) -> Result<UploadId, FlowyError> {
+    let (backend, url) = self.route(&url)?;
+    backend.initiate_multipart(url, mime).await
+  }
+
+  async fn upload_part(
+    &self,
+    url: String,
+    upload_id: UploadId,
+    part_number: u32,
+    bytes: Bytes,
+  ) -> Result<PartETag, FlowyError> {
+    let (backend, url) = self.route(&url)?;
+    backend
+      .upload_part(url, upload_id, part_number, bytes)
+      .await
+  }
+
+  async fn complete_multipart(
+    &self,
+    url: String,
+    upload_id: UploadId,
+    parts: Vec<PartETag>,
+  ) -> Result<(), FlowyError> {
+    let (backend, url) = self.route(&url)?;
+    backend.complete_multipart(url, upload_id, parts).await
+  }
+
+  async fn abort_multipart(&self, url: String, upload_id: UploadId) -> Result<(), FlowyError> {
+    let (backend, url) = self.route(&url)?;
+    backend.abort_multipart(url, upload_id).await
+  }
+
+  /// Probes every backend. The storage is only healthy if all of them are, the first failure is
+  /// reported and the latency is the one of the slowest backend.
+  async fn health_check(&self) -> Result<HealthStatus, FlowyError> {
+    let mut status = HealthStatus::healthy(Duration::ZERO);
+    for (name, service) in &self.backends {
+      let backend_status = service.health_check().await?;
+      status.latency = status.latency.max(backend_status.latency);
+      if status.error.is_none() {
+        if let Some(err) = backend_status.error {
+          status.reachable = backend_status.reachable;
+          status.authorized = backend_status.authorized;
+          status.error = Some(format!("{}: {}", name, err));
+        }
+      }
+    }
+    Ok(status)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{memory_object_value, InMemoryObjectStorage, StorageOperation};
+
+  fn identity(file_id: &str, ext: &str) -> ObjectIdentity {
+    ObjectIdentity {
+      workspace_id: "w1".to_string(),
+      file_id: file_id.to_string(),
+      ext: ext.to_string(),
+      hash_algorithm: None,
+      size: None,
+    }
+  }
+
+  #[tokio::test]
+  async fn route_by_mime_and_size_test() {
+    let documents = Arc::new(InMemoryObjectStorage::new());
+    let images = Arc::new(InMemoryObjectStorage::new());
+    let archive = Arc::new(InMemoryObjectStorage::new());
+    let storage = RoutingObjectStorage::new("documents", documents.clone())
+      .with_backend("images", images.clone())
+      .with_backend("archive", archive.clone())
+      .with_rule(RoutingRule::new("archive").with_min_size(1024))
+      .with_rule(RoutingRule::new("images").with_mime_prefix("image/"));
+
+    let image_url = storage.get_object_url(identity("1", "png")).await.unwrap();
+    assert!(image_url.starts_with("routed://images/"));
+    storage
+      .put_object(image_url.clone(), memory_object_value("1.png", "png"))
+      .await
+      .unwrap();
+    let doc_url = storage.get_object_url(identity("2", "txt")).await.unwrap();
+    storage
+      .put_object(doc_url.clone(), memory_object_value("2.txt", "txt"))
+      .await
+      .unwrap();
+    // The size rule comes first, so a large image is archived.
+    let large_url = storage
+      .get_object_url_with_size(identity("3", "png"), 4096)
+      .await
+      .unwrap();
+    assert!(large_url.starts_with("routed://archive/"));
+
+    assert_eq!(images.call_count(StorageOperation::Put), 1);
+    assert_eq!(documents.call_count(StorageOperation::Put), 1);
+    let value = storage.get_object(image_url.clone()).await.unwrap();
+    assert_eq!(value.raw, Bytes::from("png"));
+
+    let listed = storage.list_objects("w1", None).await.unwrap();
+    let urls = listed.into_iter().map(|meta| meta.url).collect::<Vec<_>>();
+    assert_eq!(urls, vec![doc_url.clone(), image_url.clone()]);
+
+    let results = storage
+      .delete_objects(vec![
+        image_url.clone(),
+        "routed://missing/x.txt".to_string(),
+        doc_url,
+      ])
+      .await
+      .unwrap();
+    assert!(results[0].is_ok() && results[2].is_ok());
+    assert_eq!(
+      results[1].as_ref().unwrap_err().code,
+      ErrorCode::InvalidParams
+    );
+    assert_eq!(images.len() + documents.len(), 0);
+  }
+
+  #[tokio::test]
+  async fn unrouted_url_goes_to_default_backend_test() {
+    let documents = Arc::new(InMemoryObjectStorage::new());
+    let storage = RoutingObjectStorage::new("documents", documents.clone())
+      .with_backend("images", Arc::new(InMemoryObjectStorage::new()));
+    let url = documents
+      .get_object_url(identity("1", "png"))
+      .await
+      .unwrap();
+    documents
+      .put_object(url.clone(), memory_object_value("1.png", "png"))
+      .await
+      .unwrap();
+    assert!(storage.object_exists(url).await.unwrap());
+  }
+
+  #[tokio::test]
+  async fn delete_workspace_objects_of_every_backend_test() {
+    let documents = Arc::new(InMemoryObjectStorage::new());
+    let images = Arc::new(InMemoryObjectStorage::new());
+    let storage = RoutingObjectStorage::new("documents", documents.clone())
+      .with_backend("images", images.clone())
+      .with_rule(RoutingRule::new("images").with_mime_prefix("image/"));
+    for (file_id, ext) in [("1", "png"), ("2", "txt")] {
+      let url = storage
+        .get_object_url(identity(file_id, ext))
+        .await
+        .unwrap();
+      let file_name = format!("{}.{}", file_id, ext);
+      storage
+        .put_object(url, memory_object_value(&file_name, "data"))
+        .await
+        .unwrap();
+    }
+
+    let report = storage.delete_workspace_objects("w1").await.unwrap();
+    assert_eq!(report.deleted, 2);
+    assert_eq!(report.freed_bytes, 8);
+    assert!(documents.is_empty());
+    assert!(images.is_empty());
+  }
+}
@@ -0,0 +1,597 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use bytes::BytesMut;
+use futures::StreamExt;
+use parking_lot::Mutex;
+use tokio::sync::{oneshot, Notify};
+use tracing::{trace, warn};
+
+use flowy_error::FlowyError;
+
+use crate::multipart::upload_part;
+use crate::{
+  cancellable, cancelled_error, storage_config, BandwidthLimiter, BandwidthLimits,
+  CancellationToken, ObjectStorageService, ObjectValue, RetryPolicy,
+};
+
+/// The default number of transfers a [TransferScheduler] runs at the same time.
+pub const DEFAULT_SCHEDULER_CONCURRENCY: usize = 4;
+
+/// How urgent a transfer of a [TransferScheduler] is, the higher ones are dispatched first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TransferPriority {
+  /// The prefetches, the bulk uploads and the other transfers nobody waits for.
+  Background,
+  Normal,
+  /// A transfer the user waits for, for example the image they just clicked.
+  Interactive,
+}
+
+pub type TransferId = u64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferState {
+  /// Waiting for a free slot.
+  Queued,
+  Running,
+  /// Gave its slot up to a transfer of a higher priority. It continues where it stopped once it
+  /// gets a slot again.
+  Preempted,
+}
+
+pub enum TransferRequest {
+  Download { url: String },
+  Upload { url: String, value: ObjectValue },
+}
+
+impl TransferRequest {
+  pub fn url(&self) -> &str {
+    match self {
+      TransferRequest::Download { url } | TransferRequest::Upload { url, .. } => url,
+    }
+  }
+}
+
+pub enum TransferOutput {
+  Downloaded(ObjectValue),
+  Uploaded,
+}
+
+/// A transfer of a [TransferScheduler] that is not completed yet.
+#[derive(Debug, Clone)]
+pub struct TransferInfo {
+  pub id: TransferId,
+  pub priority: TransferPriority,
+  pub url: String,
+  pub is_upload: bool,
+  pub state: TransferState,
+  /// The bytes transferred so far.
+  pub transferred: u64,
+}
+
+/// Returned by [TransferScheduler::submit]. Dropping it doesn't cancel the transfer.
+pub struct TransferHandle {
+  id: TransferId,
+  cancel: CancellationToken,
+  receiver: oneshot::Receiver<Result<TransferOutput, FlowyError>>,
+}
+
+impl TransferHandle {
+  pub fn id(&self) -> TransferId {
+    self.id
+  }
+
+  pub fn cancel(&self) {
+    self.cancel.cancel();
+  }
+
+  /// Waits for the transfer to complete. The error is an
+  /// [ErrorCode::Cancelled](flowy_error::ErrorCode::Cancelled) if it was cancelled.
+  pub async fn wait(self) -> Result<TransferOutput, FlowyError> {
+    self
+      .receiver
+      .await
+      .unwrap_or_else(|_| Err(cancelled_error()))
+  }
+}
+
+/// Runs the uploads and downloads of the app by priority, at most `max_concurrent` at a time
+/// and within the [BandwidthLimits], so a download the user waits for doesn't queue behind the
+/// prefetches and the bulk uploads.
+///
+/// A transfer gives its slot up to a queued transfer of a higher priority at its next chunk
+/// boundary: between two chunks of a streamed download, between two parts of a multipart
+/// upload. It's not restarted, it continues once a slot is free again. The downloads of the
+/// backends that don't stream the objects, and the uploads that fit in one part, run to
+/// completion once they started.
+pub struct TransferScheduler<S: ?Sized> {
+  service: Arc<S>,
+  limits: BandwidthLimits,
+  slots: Arc<Slots>,
+}
+
+impl<S> TransferScheduler<S>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  pub fn new(service: Arc<S>, max_concurrent: usize, limits: BandwidthLimits) -> Self {
+    Self {
+      service,
+      limits,
+      slots: Arc::new(Slots {
+        max_concurrent: max_concurrent.max(1),
+        state: Mutex::new(SlotsState::default()),
+        changed: Notify::new(),
+      }),
+    }
+  }
+
+  pub fn limits(&self) -> &BandwidthLimits {
+    &self.limits
+  }
+
+  /// Queues the transfer and returns right away, it's dispatched in the background.
+  pub fn submit(&self, priority: TransferPriority, request: TransferRequest) -> TransferHandle {
+    let cancel = CancellationToken::new();
+    let id = self.slots.register(priority, &request, cancel.clone());
+    let (sender, receiver) = oneshot::channel();
+    let service = self.service.clone();
+    let limits = self.limits.clone();
+    let slot = Slot {
+      slots: self.slots.clone(),
+      id,
+      priority,
+    };
+    let task_cancel = cancel.clone();
+    tokio::spawn(async move {
+      let result = run(&*service, &limits, &slot, request, &task_cancel).await;
+      drop(slot);
+      // The handle may have been dropped, the result is not needed then.
+      let _ = sender.send(result);
+    });
+    TransferHandle {
+      id,
+      cancel,
+      receiver,
+    }
+  }
+
+  /// The transfers that are not completed, in the order they are dispatched: the running ones
+  /// first, then the waiting ones by priority and submission.
+  pub fn transfers(&self) -> Vec<TransferInfo> {
+    let state = self.slots.state.lock();
+    let mut transfers = state
+      .transfers
+      .values()
+      .map(|(info, _)| info.clone())
+      .collect::<Vec<_>>();
+    transfers.sort_by_key(|info| {
+      (
+        info.state != TransferState::Running,
+        Reverse(info.priority),
+        info.id,
+      )
+    });
+    transfers
+  }
+
+  /// Cancels the transfer, whether it's waiting or running. Returns false if it's not known,
+  /// for example because it's completed.
+  pub fn cancel(&self, id: TransferId) -> bool {
+    match self.slots.state.lock().transfers.get(&id) {
+      Some((_, cancel)) => {
+        cancel.cancel();
+        true
+      },
+      None => false,
+    }
+  }
+}
+
+struct Slots {
+  max_concurrent: usize,
+  state: Mutex<SlotsState>,
+  changed: Notify,
+}
+
+#[derive(Default)]
+struct SlotsState {
+  next_id: TransferId,
+  running: usize,
+  transfers: BTreeMap<TransferId, (TransferInfo, CancellationToken)>,
+}
+
+impl SlotsState {
+  /// The waiting transfer dispatched next: the one of the highest priority, then the oldest.
+  fn next_in_line(&self) -> Option<&TransferInfo> {
+    self
+      .transfers
+      .values()
+      .map(|(info, _)| info)
+      .filter(|info| info.state != TransferState::Running)
+      .max_by_key(|info| (info.priority, Reverse(info.id)))
+  }
+
+  fn set_state(&mut self, id: TransferId, state: TransferState) {
+    if let Some((info, _)) = self.transfers.get_mut(&id) {
+      info.state = state;
+    }
+  }
+}
+
+impl Slots {
+  fn register(
+    &self,
+    priority: TransferPriority,
+    request: &TransferRequest,
+    cancel: CancellationToken,
+  ) -> TransferId {
+    let mut state = self.state.lock();
+    state.next_id += 1;
+    let id = state.next_id;
+    let info = TransferInfo {
+      id,
+      priority,
+      url: request.url().to_string(),
+      is_upload: matches!(request, TransferRequest::Upload { .. }),
+      state: TransferState::Queued,
+      transferred: 0,
+    };
+    state.transfers.insert(id, (info, cancel));
+    id
+  }
+
+  async fn acquire(&self, id: TransferId) {
+    loop {
+      // Created before the state is read, so a slot freed meanwhile is not missed.
+      let changed = self.changed.notified();
+      {
+        let mut state = self.state.lock();
+        let next = state.next_in_line().map(|info| info.id);
+        if state.running < self.max_concurrent && next == Some(id) {
+          state.running += 1;
+          state.set_state(id, TransferState::Running);
+          drop(state);
+          // Another slot may be free for the transfer next in line.
+          self.changed.notify_waiters();
+          return;
+        }
+      }
+      changed.await;
+    }
+  }
+
+  /// Gives the slot up if a transfer of a higher priority waits for one, then waits to get a
+  /// slot again.
+  async fn checkpoint(&self, id: TransferId, priority: TransferPriority) {
+    let preempted = {
+      let mut state = self.state.lock();
+      let preempted = state.running >= self.max_concurrent
+        && state
+          .next_in_line()
+          .map_or(false, |next| next.priority > priority);
+      if preempted {
+        state.running -= 1;
+        state.set_state(id, TransferState::Preempted);
+      }
+      preempted
+    };
+    if preempted {
+      trace!(
+        "transfer {} preempted by a transfer of a higher priority",
+        id
+      );
+      self.changed.notify_waiters();
+      self.acquire(id).await;
+    }
+  }
+
+  fn add_transferred(&self, id: TransferId, bytes: usize) {
+    if let Some((info, _)) = self.state.lock().transfers.get_mut(&id) {
+      info.transferred += bytes as u64;
+    }
+  }
+
+  fn release(&self, id: TransferId) {
+    {
+      let mut state = self.state.lock();
+      if let Some((info, _)) = state.transfers.remove(&id) {
+        if info.state == TransferState::Running {
+          state.running -= 1;
+        }
+      }
+    }
+    self.changed.notify_waiters();
+  }
+}
+
+/// The place of a transfer in the [Slots], it's removed when the transfer completes or is
+/// cancelled.
+struct Slot {
+  slots: Arc<Slots>,
+  id: TransferId,
+  priority: TransferPriority,
+}
+
+impl Slot {
+  async fn acquire(&self) -> Result<(), FlowyError> {
+    self.slots.acquire(self.id).await;
+    Ok(())
+  }
+
+  async fn checkpoint(&self) {
+    self.slots.checkpoint(self.id, self.priority).await;
+  }
+
+  fn add_transferred(&self, bytes: usize) {
+    self.slots.add_transferred(self.id, bytes);
+  }
+}
+
+impl Drop for Slot {
+  fn drop(&mut self) {
+    self.slots.release(self.id);
+  }
+}
+
+async fn run<S>(
+  service: &S,
+  limits: &BandwidthLimits,
+  slot: &Slot,
+  request: TransferRequest,
+  cancel: &CancellationToken,
+) -> Result<TransferOutput, FlowyError>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  cancellable(slot.acquire(), cancel).await?;
+  match request {
+    TransferRequest::Download { url } => {
+      let download = download(service, &limits.download, slot, url);
+      cancellable(download, cancel)
+        .await
+        .map(TransferOutput::Downloaded)
+    },
+    TransferRequest::Upload { url, value } => {
+      upload(service, &limits.upload, slot, url, value, cancel).await?;
+      Ok(TransferOutput::Uploaded)
+    },
+  }
+}
+
+async fn download<S>(
+  service: &S,
+  limiter: &BandwidthLimiter,
+  slot: &Slot,
+  url: String,
+) -> Result<ObjectValue, FlowyError>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  let mut object = service.get_object_stream(url).await?;
+  let mut raw = BytesMut::with_capacity(object.content_length as usize);
+  while let Some(chunk) = object.stream.next().await {
+    let chunk = chunk?;
+    limiter.acquire(chunk.len() as u64).await;
+    raw.extend_from_slice(&chunk);
+    slot.add_transferred(chunk.len());
+    slot.checkpoint().await;
+  }
+  Ok(ObjectValue {
+    raw: raw.freeze(),
+    mime: object.mime,
+    content_encoding: None,
+  })
+}
+
+/// Uploads the value part by part when it's larger than the
+/// [crate::StorageConfig::multipart_part_size], so it can be preempted between two parts. A
+/// failed or cancelled multipart upload is aborted.
+async fn upload<S>(
+  service: &S,
+  limiter: &BandwidthLimiter,
+  slot: &Slot,
+  url: String,
+  value: ObjectValue,
+  cancel: &CancellationToken,
+) -> Result<(), FlowyError>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  let part_size = storage_config().multipart_part_size;
+  let size = value.raw.len();
+  // The parts of a compressed value would lose its encoding.
+  if size <= part_size || value.content_encoding.is_some() || !service.supports_multipart() {
+    limiter.acquire(size as u64).await;
+    cancellable(service.put_object(url, value), cancel).await?;
+    slot.add_transferred(size);
+    return Ok(());
+  }
+
+  let upload_id = cancellable(
+    service.initiate_multipart(url.clone(), value.mime.clone()),
+    cancel,
+  )
+  .await?;
+  let policy = RetryPolicy::default();
+  let parts = async {
+    let mut parts = vec![];
+    for (index, start) in (0..size).step_by(part_size).enumerate() {
+      if index > 0 {
+        slot.checkpoint().await;
+      }
+      let part = value.raw.slice(start..(start + part_size).min(size));
+      let part_len = part.len();
+      limiter.acquire(part_len as u64).await;
+      let part_number = index as u32 + 1;
+      parts.push(upload_part(service, &url, &upload_id, part_number, part, &policy).await?);
+      slot.add_transferred(part_len);
+    }
+    service
+      .complete_multipart(url.clone(), upload_id.clone(), parts)
+      .await
+  };
+  match cancellable(parts, cancel).await {
+    Ok(()) => Ok(()),
+    Err(err) => {
+      if let Err(abort_err) = service.abort_multipart(url, upload_id).await {
+        warn!("abort multipart upload failed: {}", abort_err);
+      }
+      Err(err)
+    },
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use flowy_error::ErrorCode;
+
+  use super::*;
+  use crate::{memory_object_value, InMemoryObjectStorage};
+
+  async fn storage(objects: &[(&str, &'static str)]) -> Arc<InMemoryObjectStorage> {
+    let storage = Arc::new(InMemoryObjectStorage::new());
+    // The downloads are in progress for a while, and they can be preempted after each byte.
+    storage.stream_in_chunks(1, Duration::from_millis(5));
+    for (url, content) in objects {
+      storage
+        .put_object(url.to_string(), memory_object_value("a.txt", *content))
+        .await
+        .unwrap();
+    }
+    storage
+  }
+
+  fn download(url: &str) -> TransferRequest {
+    TransferRequest::Download {
+      url: url.to_string(),
+    }
+  }
+
+  async fn wait_running(scheduler: &TransferScheduler<InMemoryObjectStorage>, id: TransferId) {
+    while !scheduler
+      .transfers()
+      .iter()
+      .any(|info| info.id == id && info.state == TransferState::Running && info.transferred > 0)
+    {
+      tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+  }
+
+  #[tokio::test]
+  async fn dispatch_by_priority_test() {
+    let storage = storage(&[
+      ("memory://w1/busy.txt", "busy"),
+      ("memory://w1/bg.txt", "bg"),
+      ("memory://w1/normal.txt", "normal"),
+      ("memory://w1/click.txt", "click"),
+    ])
+    .await;
+    let scheduler = TransferScheduler::new(storage.clone(), 1, BandwidthLimits::default());
+    let busy = scheduler.submit(
+      TransferPriority::Interactive,
+      download("memory://w1/busy.txt"),
+    );
+    wait_running(&scheduler, busy.id()).await;
+
+    let bg = scheduler.submit(TransferPriority::Background, download("memory://w1/bg.txt"));
+    let normal = scheduler.submit(TransferPriority::Normal, download("memory://w1/normal.txt"));
+    let click = scheduler.submit(
+      TransferPriority::Interactive,
+      download("memory://w1/click.txt"),
+    );
+    let queue = scheduler.transfers();
+    let order = queue.iter().map(|info| info.id).collect::<Vec<_>>();
+    assert_eq!(order, vec![busy.id(), click.id(), normal.id(), bg.id()]);
+    assert_eq!(queue[1].state, TransferState::Queued);
+
+    let finished = Arc::new(Mutex::new(vec![]));
+    let waits = [
+      ("busy", busy),
+      ("bg", bg),
+      ("normal", normal),
+      ("click", click),
+    ]
+    .map(|(name, handle)| {
+      let finished = finished.clone();
+      async move {
+        handle.wait().await.unwrap();
+        finished.lock().push(name);
+      }
+    });
+    futures::future::join_all(waits).await;
+    assert_eq!(*finished.lock(), vec!["busy", "click", "normal", "bg"]);
+    assert!(scheduler.transfers().is_empty());
+  }
+
+  #[tokio::test]
+  async fn preempt_background_transfer_test() {
+    let storage = storage(&[
+      ("memory://w1/bg.txt", "a background download"),
+      ("memory://w1/click.txt", "click"),
+    ])
+    .await;
+    let scheduler = TransferScheduler::new(storage.clone(), 1, BandwidthLimits::default());
+    let bg = scheduler.submit(TransferPriority::Background, download("memory://w1/bg.txt"));
+    wait_running(&scheduler, bg.id()).await;
+
+    let click = scheduler.submit(
+      TransferPriority::Interactive,
+      download("memory://w1/click.txt"),
+    );
+    match click.wait().await.unwrap() {
+      TransferOutput::Downloaded(value) => assert_eq!(value.raw.as_ref(), b"click"),
+      TransferOutput::Uploaded => unreachable!(),
+    }
+    // The background download gave its slot up at a chunk boundary and continues from there.
+    let info = scheduler.transfers()[0].clone();
+    assert_eq!(info.id, bg.id());
+    assert!(info.transferred > 0 && info.transferred < 21);
+    match bg.wait().await.unwrap() {
+      TransferOutput::Downloaded(value) => {
+        assert_eq!(value.raw.as_ref(), b"a background download")
+      },
+      TransferOutput::Uploaded => unreachable!(),
+    }
+  }
+
+  #[tokio::test]
+  async fn cancel_transfer_test() {
+    let storage = storage(&[("memory://w1/busy.txt", "busy"), ("memory://w1/1.txt", "1")]).await;
+    let scheduler = TransferScheduler::new(storage.clone(), 1, BandwidthLimits::default());
+    let busy = scheduler.submit(TransferPriority::Normal, download("memory://w1/busy.txt"));
+    let queued = scheduler.submit(TransferPriority::Normal, download("memory://w1/1.txt"));
+    assert!(scheduler.cancel(queued.id()));
+    let err = queued.wait().await.err().unwrap();
+    assert_eq!(err.code, ErrorCode::Cancelled);
+
+    busy.cancel();
+    let busy_id = busy.id();
+    assert_eq!(busy.wait().await.err().unwrap().code, ErrorCode::Cancelled);
+    assert!(scheduler.transfers().is_empty());
+    assert!(!scheduler.cancel(busy_id));
+  }
+
+  #[tokio::test]
+  async fn upload_test() {
+    let storage = storage(&[]).await;
+    let scheduler = TransferScheduler::new(storage.clone(), 2, BandwidthLimits::default());
+    let url = "memory://w1/1.txt".to_string();
+    let upload = scheduler.submit(
+      TransferPriority::Background,
+      TransferRequest::Upload {
+        url: url.clone(),
+        value: memory_object_value("1.txt", "hello"),
+      },
+    );
+    assert!(matches!(
+      upload.wait().await.unwrap(),
+      TransferOutput::Uploaded
+    ));
+    assert_eq!(storage.object(&url).unwrap().raw.as_ref(), b"hello");
+  }
+}
@@ -0,0 +1,112 @@
+use flowy_error::{ErrorCode, FlowyError};
+
+use crate::StorageObject;
+
+/// Limits the size of an object depending on its mime type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MimeSizeRule {
+  /// Matches the mime types starting with the prefix, for example `image/` or `video/mp4`. The
+  /// comparison ignores the case.
+  pub mime_prefix: String,
+  pub max_bytes: u64,
+}
+
+/// The size limits of a [crate::FileStoragePlan] by mime type. The rule with the longest
+/// matching prefix applies, the global [crate::FileStoragePlan::maximum_file_size] applies when no
+/// rule matches. A rule can be larger than the global limit, to allow big videos for example.
+#[derive(Debug, Clone, Default)]
+pub struct MimeSizeLimits {
+  rules: Vec<MimeSizeRule>,
+}
+
+impl MimeSizeLimits {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Adds a rule. A rule with the same prefix is replaced.
+  pub fn with_rule(mut self, mime_prefix: impl Into<String>, max_bytes: u64) -> Self {
+    let mime_prefix = mime_prefix.into().to_ascii_lowercase();
+    self.rules.retain(|rule| rule.mime_prefix != mime_prefix);
+    self.rules.push(MimeSizeRule {
+      mime_prefix,
+      max_bytes,
+    });
+    self
+  }
+
+  pub fn rules(&self) -> &[MimeSizeRule] {
+    &self.rules
+  }
+
+  /// Returns the rule that applies to the mime type, `None` if the global limit applies.
+  pub fn rule_for(&self, mime: &str) -> Option<&MimeSizeRule> {
+    let mime = mime.to_ascii_lowercase();
+    self
+      .rules
+      .iter()
+      .filter(|rule| mime.starts_with(&rule.mime_prefix))
+      .max_by_key(|rule| rule.mime_prefix.len())
+  }
+
+  /// Returns an [ErrorCode::FileTooLarge] error naming the violated limit if the object is
+  /// larger than the limit of its mime type, or than `maximum_file_size` if no rule matches.
+  pub async fn check(
+    &self,
+    object: &StorageObject,
+    maximum_file_size: u64,
+  ) -> Result<(), FlowyError> {
+    let size = object.file_size_async().await?;
+    let mime = object.value.mime_type(true);
+    match self.rule_for(&mime) {
+      Some(rule) if size > rule.max_bytes => Err(FlowyError::new(
+        ErrorCode::FileTooLarge,
+        format!(
+          "{} is {} bytes, which is over the limit of {} bytes for the {}* files",
+          object.file_name, size, rule.max_bytes, rule.mime_prefix
+        ),
+      )),
+      Some(_) => Ok(()),
+      None if size > maximum_file_size => Err(FlowyError::new(
+        ErrorCode::FileTooLarge,
+        format!(
+          "{} is {} bytes, which is over the maximum file size of {} bytes",
+          object.file_name, size, maximum_file_size
+        ),
+      )),
+      None => Ok(()),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn object(mime: &str, size: usize) -> StorageObject {
+    StorageObject::from_bytes("w1", "a", vec![0; size], mime.to_string())
+  }
+
+  #[tokio::test]
+  async fn longest_prefix_wins_test() {
+    let limits = MimeSizeLimits::new()
+      .with_rule("image/", 10)
+      .with_rule("image/gif", 20)
+      .with_rule("Video/", 1000);
+    assert_eq!(limits.rule_for("image/png").unwrap().max_bytes, 10);
+    assert_eq!(limits.rule_for("image/GIF").unwrap().max_bytes, 20);
+    assert!(limits.rule_for("text/plain").is_none());
+
+    assert!(limits.check(&object("image/gif", 15), 5).await.is_ok());
+    assert!(limits.check(&object("video/mp4", 500), 5).await.is_ok());
+    let err = limits
+      .check(&object("image/png", 15), 100)
+      .await
+      .unwrap_err();
+    assert_eq!(err.code, ErrorCode::FileTooLarge);
+    assert!(err.msg.contains("image/*"));
+
+    let err = limits.check(&object("text/plain", 6), 5).await.unwrap_err();
+    assert!(err.msg.contains("maximum file size of 5 bytes"));
+  }
+}
@@ -0,0 +1,246 @@
+use mime::Mime;
+
+use crate::guess_mime;
+
+/// The number of leading bytes inspected by [sniff_mime].
+pub const MIME_SNIFF_LEN: usize = 512;
+
+/// (offset, magic bytes, mime type). The first signature that matches wins.
+const SIGNATURES: &[(usize, &[u8], &str)] = &[
+  (0, b"\x89PNG\r\n\x1a\n", "image/png"),
+  (0, b"\xff\xd8\xff", "image/jpeg"),
+  (0, b"GIF87a", "image/gif"),
+  (0, b"GIF89a", "image/gif"),
+  (0, b"BM", "image/bmp"),
+  (0, b"II*\x00", "image/tiff"),
+  (0, b"MM\x00*", "image/tiff"),
+  (0, b"\x00\x00\x01\x00", "image/x-icon"),
+  (0, b"%PDF-", "application/pdf"),
+  (0, b"PK\x03\x04", "application/zip"),
+  (0, b"\x1f\x8b", "application/gzip"),
+  (0, b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+  (0, b"Rar!\x1a\x07", "application/vnd.rar"),
+  (0, b"ID3", "audio/mpeg"),
+  (0, b"OggS", "audio/ogg"),
+  (0, b"fLaC", "audio/flac"),
+  (0, b"\x1a\x45\xdf\xa3", "video/webm"),
+  (4, b"ftypqt", "video/quicktime"),
+  (4, b"ftypheic", "image/heic"),
+  (4, b"ftyp", "video/mp4"),
+];
+
+/// Formats that are containers of other formats. An extension that is more specific than the
+/// container, for example `.docx` for a zip file, is kept.
+const CONTAINERS: &[&str] = &["application/zip", "application/gzip"];
+
+/// The extension of the mime types for which [mime_guess] knows several extensions and lists
+/// the usual one after the others.
+const CANONICAL_EXTENSIONS: &[(&str, &str)] = &[
+  ("image/jpeg", "jpg"),
+  ("image/tiff", "tiff"),
+  ("image/x-icon", "ico"),
+  ("text/plain", "txt"),
+  ("text/html", "html"),
+  ("text/markdown", "md"),
+  ("audio/mpeg", "mp3"),
+  ("audio/ogg", "ogg"),
+  ("video/mpeg", "mpeg"),
+  ("video/mp4", "mp4"),
+  ("video/quicktime", "mov"),
+  ("application/javascript", "js"),
+];
+
+/// Returns the usual extension of files of the `mime` type, without the leading dot. `None` for
+/// `application/octet-stream`, which says nothing about the content, and for unknown types.
+pub fn ext_from_mime(mime: &Mime) -> Option<&'static str> {
+  if *mime == mime::APPLICATION_OCTET_STREAM {
+    return None;
+  }
+  let essence = mime.essence_str();
+  CANONICAL_EXTENSIONS
+    .iter()
+    .find(|(canonical_mime, _)| *canonical_mime == essence)
+    .map(|(_, ext)| *ext)
+    .or_else(|| {
+      mime_guess::get_mime_extensions_str(essence)
+        .and_then(|extensions| extensions.first().copied())
+    })
+}
+
+/// Detects the mime type from the magic bytes at the beginning of the content. Returns `None`
+/// if the content doesn't match any known signature, which is always the case for text files.
+pub fn sniff_mime(content: &[u8]) -> Option<Mime> {
+  let content = &content[..content.len().min(MIME_SNIFF_LEN)];
+  if content.len() >= 12 && &content[0..4] == b"RIFF" {
+    match &content[8..12] {
+      b"WEBP" => return "image/webp".parse().ok(),
+      b"WAVE" => return "audio/wav".parse().ok(),
+      b"AVI " => return "video/x-msvideo".parse().ok(),
+      _ => {},
+    }
+  }
+
+  SIGNATURES
+    .iter()
+    .find(|(offset, magic, _)| content.get(*offset..*offset + magic.len()) == Some(*magic))
+    .and_then(|(_, _, mime)| mime.parse().ok())
+}
+
+/// Returns the mime type of a file. The type guessed from the extension of `file_name` is used
+/// unless `sniff` is true and the content says otherwise: the sniffed type is preferred when the
+/// guess is generic or conflicts with it.
+pub fn detect_mime(file_name: &str, content: &[u8], sniff: bool) -> Mime {
+  let guess = guess_mime(file_name);
+  if !sniff {
+    return guess;
+  }
+
+  match sniff_mime(content) {
+    Some(detected) if detected != guess => {
+      let is_generic = guess == mime::APPLICATION_OCTET_STREAM || guess == mime::TEXT_PLAIN;
+      if is_generic || !CONTAINERS.contains(&detected.essence_str()) {
+        detected
+      } else {
+        guess
+      }
+    },
+    _ => guess,
+  }
+}
+
+/// Detects the charset of text content: from its byte order mark if it has one, otherwise
+/// `utf-8` if it's valid UTF-8 and `iso-8859-1` if every byte is a printable Latin-1 character.
+/// Returns `None` for binary content, anything with a NUL byte or control characters other than
+/// the usual whitespace.
+pub fn sniff_charset(content: &[u8]) -> Option<&'static str> {
+  const BOMS: &[(&[u8], &str)] = &[
+    (b"\xef\xbb\xbf", "utf-8"),
+    (b"\xff\xfe\x00\x00", "utf-32le"),
+    (b"\x00\x00\xfe\xff", "utf-32be"),
+    (b"\xff\xfe", "utf-16le"),
+    (b"\xfe\xff", "utf-16be"),
+  ];
+  if let Some((_, charset)) = BOMS.iter().find(|(bom, _)| content.starts_with(bom)) {
+    return Some(charset);
+  }
+
+  let is_binary = content
+    .iter()
+    .any(|b| b.is_ascii_control() && !matches!(b, b'\t' | b'\n' | b'\r' | b'\x0c' | b'\x1b'));
+  if is_binary {
+    None
+  } else if std::str::from_utf8(content).is_ok() {
+    Some("utf-8")
+  } else if content.iter().all(|b| !(0x80..0xa0).contains(b)) {
+    Some("iso-8859-1")
+  } else {
+    None
+  }
+}
+
+/// Adds the charset sniffed with [sniff_charset] to a `text/*` mime type that doesn't have one,
+/// like `text/plain; charset=utf-8`. The other mime types are returned unchanged.
+pub fn with_text_charset(mime: Mime, content: &[u8]) -> Mime {
+  if mime.type_() != mime::TEXT || mime.get_param(mime::CHARSET).is_some() {
+    return mime;
+  }
+  match sniff_charset(content) {
+    Some(charset) => format!("{}; charset={}", mime.essence_str(), charset)
+      .parse()
+      .unwrap_or(mime),
+    None => mime,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR";
+  const ZIP: &[u8] = b"PK\x03\x04\x14\x00\x06\x00";
+
+  #[test]
+  fn sniff_mime_test() {
+    assert_eq!(sniff_mime(PNG).unwrap(), mime::IMAGE_PNG);
+    assert_eq!(sniff_mime(b"\xff\xd8\xff\xe0").unwrap(), mime::IMAGE_JPEG);
+    assert_eq!(
+      sniff_mime(b"RIFF\x00\x00\x00\x00WEBPVP8 ").unwrap(),
+      "image/webp"
+    );
+    assert_eq!(
+      sniff_mime(b"\x00\x00\x00\x20ftypisom").unwrap(),
+      "video/mp4"
+    );
+    assert!(sniff_mime(b"hello world").is_none());
+    assert!(sniff_mime(b"").is_none());
+  }
+
+  #[test]
+  fn detect_mime_test() {
+    // no extension or a mislabeled extension
+    assert_eq!(detect_mime("image", PNG, true), mime::IMAGE_PNG);
+    assert_eq!(detect_mime("image.txt", PNG, true), mime::IMAGE_PNG);
+    assert_eq!(detect_mime("image.jpg", PNG, true), mime::IMAGE_PNG);
+    // the extension is trusted when sniffing is disabled
+    assert_eq!(detect_mime("image.txt", PNG, false), mime::TEXT_PLAIN);
+    // unknown content falls back to the extension
+    assert_eq!(detect_mime("notes.md", b"# title", true), "text/markdown");
+    // a more specific extension of a container is kept
+    assert_eq!(
+      detect_mime("report.docx", ZIP, true),
+      "application/vnd.openxmlformats-officedocument.wordprocessingml.document"
+    );
+    assert_eq!(detect_mime("archive", ZIP, true), "application/zip");
+  }
+
+  #[test]
+  fn ext_from_mime_test() {
+    assert_eq!(ext_from_mime(&mime::IMAGE_PNG), Some("png"));
+    assert_eq!(ext_from_mime(&mime::IMAGE_JPEG), Some("jpg"));
+    assert_eq!(ext_from_mime(&mime::TEXT_PLAIN_UTF_8), Some("txt"));
+    assert_eq!(
+      ext_from_mime(&"application/pdf".parse().unwrap()),
+      Some("pdf")
+    );
+    assert_eq!(ext_from_mime(&mime::APPLICATION_OCTET_STREAM), None);
+    assert_eq!(
+      ext_from_mime(&"application/x-unknown".parse().unwrap()),
+      None
+    );
+  }
+
+  #[test]
+  fn sniff_charset_test() {
+    assert_eq!(sniff_charset(b"\xef\xbb\xbfhello"), Some("utf-8"));
+    assert_eq!(sniff_charset(b"\xff\xfeh\x00i\x00"), Some("utf-16le"));
+    assert_eq!(sniff_charset("naïve\r\n".as_bytes()), Some("utf-8"));
+    assert_eq!(sniff_charset(b"caf\xe9\n"), Some("iso-8859-1"));
+    assert_eq!(sniff_charset(b""), Some("utf-8"));
+    // Binary content is never text.
+    assert_eq!(sniff_charset(PNG), None);
+    assert_eq!(sniff_charset(ZIP), None);
+    assert_eq!(sniff_charset(b"h\x00i\x00"), None);
+    assert_eq!(sniff_charset(b"\x93\x94"), None);
+  }
+
+  #[test]
+  fn with_text_charset_test() {
+    assert_eq!(
+      with_text_charset(mime::TEXT_PLAIN, b"caf\xe9"),
+      "text/plain; charset=iso-8859-1"
+    );
+    assert_eq!(
+      with_text_charset("text/markdown".parse().unwrap(), b"# title"),
+      "text/markdown; charset=utf-8"
+    );
+    assert_eq!(
+      with_text_charset(mime::TEXT_PLAIN_UTF_8, b"caf\xe9"),
+      mime::TEXT_PLAIN_UTF_8
+    );
+    assert_eq!(with_text_charset(mime::TEXT_PLAIN, PNG), mime::TEXT_PLAIN);
+    assert_eq!(
+      with_text_charset(mime::APPLICATION_OCTET_STREAM, b"hello"),
+      mime::APPLICATION_OCTET_STREAM
+    );
+  }
+}
@@ -0,0 +1,329 @@
+use std::pin::Pin;
+
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use mime::Mime;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use flowy_error::FlowyError;
+
+use crate::{IncrementalHash, ObjectValue};
+
+/// A stream of object content chunks.
+pub type ObjectByteStream = Pin<Box<dyn Stream<Item = Result<Bytes, FlowyError>> + Send + Sync>>;
+
+/// The default size of the buffer used to read a file chunk by chunk.
+pub const DEFAULT_READ_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// The streaming counterpart of [crate::ObjectValue]. The content is produced chunk by chunk
+/// instead of being held in memory.
+pub struct ObjectStream {
+  /// The length of the content, 0 if it's unknown until the stream ends.
+  pub content_length: u64,
+  pub mime: Mime,
+  pub stream: ObjectByteStream,
+}
+
+impl From<ObjectValue> for ObjectStream {
+  fn from(value: ObjectValue) -> Self {
+    let content_length = value.raw.len() as u64;
+    Self {
+      content_length,
+      mime: value.mime,
+      stream: Box::pin(futures::stream::once(async move { Ok(value.raw) })),
+    }
+  }
+}
+
+/// Ends the stream after its first error, so the consumers see the `Err` item then the end of
+/// the stream instead of more chunks of a transfer that failed midway.
+pub fn end_on_error(stream: ObjectByteStream) -> ObjectByteStream {
+  Box::pin(stream.scan(false, |failed, chunk| {
+    let chunk = (!*failed).then_some(chunk);
+    *failed = matches!(chunk, Some(Err(_)));
+    futures::future::ready(chunk)
+  }))
+}
+
+/// Turns the chunks received on the channel into a stream that ends when every sender is
+/// dropped. A chunk is only received when the stream is polled, so a bounded channel makes the
+/// producer wait for the consumer.
+pub fn channel_stream(rx: tokio::sync::mpsc::Receiver<Bytes>) -> ObjectByteStream {
+  Box::pin(futures::stream::unfold(rx, |mut rx| async move {
+    let chunk = rx.recv().await?;
+    Some((Ok(chunk), rx))
+  }))
+}
+
+/// Copies `reader` into `writer` and feeds every chunk into a [ContentHasher] on the way, so the
+/// `file_id` of the content is computed in the same pass as the upload or the read, without
+/// holding more than `buffer_size` bytes in memory.
+///
+/// The hasher is returned once the reader is exhausted and the writer flushed. Check
+/// [IncrementalHash::is_complete] before calling [IncrementalHash::finish], the hash is
+/// meaningless if the reader didn't produce the length the hasher was created with.
+pub async fn copy_and_hash<H, R, W>(
+  reader: &mut R,
+  writer: &mut W,
+  mut hasher: H,
+  buffer_size: usize,
+) -> Result<H, FlowyError>
+where
+  H: IncrementalHash,
+  R: AsyncRead + Unpin + ?Sized,
+  W: AsyncWrite + Unpin + ?Sized,
+{
+  let mut buffer = vec![0; buffer_size.max(1)];
+  loop {
+    let n = reader.read(&mut buffer).await?;
+    if n == 0 {
+      break;
+    }
+    hasher.update(&buffer[..n]);
+    writer.write_all(&buffer[..n]).await?;
+  }
+  writer.flush().await?;
+  Ok(hasher)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use native::*;
+
+#[cfg(not(target_arch = "wasm32"))]
+mod native {
+  use std::path::{Path, PathBuf};
+
+  use bytes::Bytes;
+  use futures::StreamExt;
+  use tokio::fs::File;
+  use tokio::io::{AsyncReadExt, AsyncWriteExt};
+  use tracing::info;
+
+  use flowy_error::{ErrorCode, FlowyError};
+
+  #[cfg(feature = "blake3-hash")]
+  use crate::hash::IncrementalHash;
+  use crate::{
+    check_regular_file, guess_mime, object_identity, ContentHashAlgorithm, DefaultContentHash,
+    ObjectByteStream, ObjectIdentity,
+  };
+
+  use super::{copy_and_hash, ObjectStream};
+
+  /// Creates the identity of a local file and a stream of its content without loading the whole
+  /// file into memory.
+  ///
+  /// The file is read twice: the first pass computes the `file_id`, so the identity is available
+  /// before the upload starts, and the second one is driven by the returned stream. At most
+  /// `buffer_size` bytes are held in memory at a time by each pass.
+  pub async fn object_stream_from_disk(
+    workspace_id: &str,
+    local_file_path: &str,
+    buffer_size: usize,
+  ) -> Result<(ObjectIdentity, ObjectStream), FlowyError> {
+    let buffer_size = buffer_size.max(1);
+    let metadata = tokio::fs::metadata(local_file_path).await;
+    let content_length = check_regular_file(Path::new(local_file_path), metadata)?.len();
+
+    let io_error = |err| crate::io_error(err, Path::new(local_file_path));
+    let mut file = File::open(local_file_path).await.map_err(io_error)?;
+    let hasher = copy_and_hash(
+      &mut file,
+      &mut tokio::io::sink(),
+      DefaultContentHash::hasher(content_length),
+      buffer_size,
+    )
+    .await?;
+    if !hasher.is_complete() {
+      return Err(file_changed_error(local_file_path));
+    }
+    info!(
+      "hashed {} bytes from file: {}",
+      content_length, local_file_path
+    );
+
+    let identity = object_identity(
+      workspace_id,
+      local_file_path,
+      hasher.finish(),
+      Some(content_length),
+    );
+    let file = File::open(local_file_path).await.map_err(io_error)?;
+    let stream = ObjectStream {
+      content_length,
+      mime: guess_mime(local_file_path),
+      stream: file_stream(
+        file,
+        local_file_path.to_string(),
+        content_length,
+        buffer_size,
+      ),
+    };
+    Ok((identity, stream))
+  }
+
+  fn file_stream(
+    file: File,
+    local_file_path: String,
+    content_length: u64,
+    buffer_size: usize,
+  ) -> ObjectByteStream {
+    let stream = futures::stream::try_unfold((file, 0u64), move |(mut file, read)| {
+      let local_file_path = local_file_path.clone();
+      async move {
+        let mut buffer = vec![0; buffer_size];
+        let n = file.read(&mut buffer).await?;
+        if n == 0 {
+          if read != content_length {
+            return Err(file_changed_error(&local_file_path));
+          }
+          return Ok(None);
+        }
+
+        let read = read + n as u64;
+        if read > content_length {
+          return Err(file_changed_error(&local_file_path));
+        }
+        buffer.truncate(n);
+        Ok(Some((Bytes::from(buffer), (file, read))))
+      }
+    });
+    Box::pin(stream)
+  }
+
+  /// Writes the content of the stream to `dest` and returns the number of bytes written. The
+  /// content is written with [crate::atomic_write_with], so `dest` is never left half written.
+  /// The parent directories of `dest` are created if needed. The download fails if no chunk is
+  /// received within the [crate::StorageConfig::idle_timeout].
+  pub async fn write_stream_to_file(object: ObjectStream, dest: &Path) -> Result<u64, FlowyError> {
+    let object = ObjectStream {
+      stream: crate::timeout::configured_idle_timeout_stream(object.stream),
+      ..object
+    };
+    crate::atomic_write_with(dest, |temp_path| write_stream(object, temp_path)).await
+  }
+
+  async fn write_stream(mut object: ObjectStream, path: PathBuf) -> Result<u64, FlowyError> {
+    let io_error = |err| crate::io_error(err, &path);
+    let mut file = File::create(&path).await.map_err(io_error)?;
+    let mut written = 0u64;
+    while let Some(chunk) = object.stream.next().await {
+      let chunk = chunk?;
+      file.write_all(&chunk).await.map_err(io_error)?;
+      written += chunk.len() as u64;
+    }
+    // A length of 0 is unknown, the stream ending is all there is to check then.
+    if object.content_length != 0 && written != object.content_length {
+      return Err(FlowyError::new(
+        ErrorCode::Internal,
+        format!(
+          "download ended after {} bytes, expected {} bytes",
+          written, object.content_length
+        ),
+      ));
+    }
+    file.flush().await.map_err(io_error)?;
+    Ok(written)
+  }
+
+  fn file_changed_error(local_file_path: &str) -> FlowyError {
+    FlowyError::new(
+      ErrorCode::Internal,
+      format!("file was modified while being read: {}", local_file_path),
+    )
+  }
+
+  #[cfg(test)]
+  mod tests {
+    use crate::ObjectValue;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn write_stream_to_file_test() {
+      let dir = tempfile::tempdir().unwrap();
+      let dest = dir.path().join("nested/dir/file.bin");
+      let value = ObjectValue {
+        raw: vec![7; 100].into(),
+        mime: mime::APPLICATION_OCTET_STREAM,
+        content_encoding: None,
+      };
+      let written = write_stream_to_file(value.into(), &dest).await.unwrap();
+      assert_eq!(written, 100);
+      assert_eq!(std::fs::read(&dest).unwrap(), vec![7; 100]);
+
+      // The length of the content is not always known up front.
+      let unknown_length = ObjectStream {
+        content_length: 0,
+        mime: mime::APPLICATION_OCTET_STREAM,
+        stream: Box::pin(futures::stream::iter(vec![
+          Ok(Bytes::from_static(b"12345")),
+          Ok(Bytes::from_static(b"678")),
+        ])),
+      };
+      let dest = dir.path().join("nested/unknown.bin");
+      assert_eq!(
+        write_stream_to_file(unknown_length, &dest).await.unwrap(),
+        8
+      );
+      assert_eq!(std::fs::read(&dest).unwrap(), b"12345678");
+
+      // A truncated download of a known length fails.
+      let truncated = ObjectStream {
+        content_length: 10,
+        mime: mime::APPLICATION_OCTET_STREAM,
+        stream: Box::pin(futures::stream::iter(vec![Ok(Bytes::from_static(
+          b"12345",
+        ))])),
+      };
+      let dest = dir.path().join("nested/truncated.bin");
+      assert!(write_stream_to_file(truncated, &dest).await.is_err());
+      assert!(!dest.exists());
+
+      // A failed download leaves neither the destination nor a temporary file behind.
+      let failing = ObjectStream {
+        content_length: 10,
+        mime: mime::APPLICATION_OCTET_STREAM,
+        stream: Box::pin(futures::stream::iter(vec![
+          Ok(Bytes::from_static(b"12345")),
+          Err(FlowyError::internal()),
+        ])),
+      };
+      let dest = dir.path().join("nested/failed.bin");
+      assert!(write_stream_to_file(failing, &dest).await.is_err());
+      assert!(!dest.exists());
+      let files = std::fs::read_dir(dir.path().join("nested"))
+        .unwrap()
+        .count();
+      assert_eq!(files, 2);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn write_to_read_only_dir_test() {
+      use std::os::unix::fs::PermissionsExt;
+
+      use crate::{StorageErrorExt, StorageErrorKind};
+
+      let dir = tempfile::tempdir().unwrap();
+      let read_only = dir.path().join("read_only");
+      std::fs::create_dir(&read_only).unwrap();
+      std::fs::set_permissions(&read_only, std::fs::Permissions::from_mode(0o555)).unwrap();
+      // The permissions are not enforced for root.
+      if std::fs::write(read_only.join("probe"), b"").is_ok() {
+        return;
+      }
+
+      let dest = read_only.join("file.bin");
+      let value = ObjectValue {
+        raw: vec![7; 10].into(),
+        mime: mime::APPLICATION_OCTET_STREAM,
+        content_encoding: None,
+      };
+      let err = write_stream_to_file(value.into(), &dest).await.unwrap_err();
+      assert!(err.is_storage_kind(StorageErrorKind::PermissionDenied));
+      assert!(err.msg.contains(&dest.display().to_string()));
+      std::fs::set_permissions(&read_only, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+  }
+}
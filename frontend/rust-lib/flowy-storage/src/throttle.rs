@@ -0,0 +1,521 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::future::select;
+use futures::StreamExt;
+use mime::Mime;
+use parking_lot::Mutex;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+use flowy_error::FlowyError;
+
+use crate::{
+  DeleteReport, HealthStatus, ListOptions, ObjectByteStream, ObjectIdentity, ObjectMeta,
+  ObjectStorageService, ObjectStream, ObjectValue, PartETag, ProgressCallback, UploadId,
+  VersionMeta,
+};
+
+/// A token bucket capping the bandwidth of all the transfers sharing it, see
+/// [ThrottledObjectStorage]. Clones share the same bucket.
+///
+/// The bucket holds up to one second of transfer. A chunk larger than the bucket is let through
+/// as soon as the bucket is full and leaves it in debt, so the following transfers wait until the
+/// debt is paid back: the average rate stays under the cap without blocking large chunks forever.
+#[derive(Clone)]
+pub struct BandwidthLimiter {
+  state: Arc<Mutex<LimiterState>>,
+  changed: Arc<Notify>,
+}
+
+struct LimiterState {
+  /// Bytes per second, `None` if unlimited.
+  rate: Option<u64>,
+  paused: bool,
+  /// The bytes that can be transferred right away, negative while the bucket is in debt.
+  tokens: f64,
+  refilled_at: Instant,
+}
+
+impl LimiterState {
+  fn refill(&mut self, rate: u64) {
+    let now = Instant::now();
+    let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+    self.tokens = (self.tokens + elapsed * rate as f64).min(rate as f64);
+    self.refilled_at = now;
+  }
+}
+
+impl Default for BandwidthLimiter {
+  fn default() -> Self {
+    Self::unlimited()
+  }
+}
+
+impl BandwidthLimiter {
+  pub fn new(bytes_per_sec: u64) -> Self {
+    let limiter = Self::unlimited();
+    limiter.set_rate(Some(bytes_per_sec));
+    limiter
+  }
+
+  pub fn unlimited() -> Self {
+    Self {
+      state: Arc::new(Mutex::new(LimiterState {
+        rate: None,
+        paused: false,
+        tokens: 0.0,
+        refilled_at: Instant::now(),
+      })),
+      changed: Arc::new(Notify::new()),
+    }
+  }
+
+  /// Changes the cap of the transfers sharing the limiter, `None` lifts it. The transfers waiting
+  /// for the bucket are woken up with the new rate.
+  pub fn set_rate(&self, bytes_per_sec: Option<u64>) {
+    {
+      let mut state = self.state.lock();
+      let rate = bytes_per_sec.map(|rate| rate.max(1));
+      match (state.rate, rate) {
+        // Keep what was accumulated at the previous rate, including a debt.
+        (Some(previous), Some(rate)) => {
+          state.refill(previous);
+          state.tokens = state.tokens.min(rate as f64);
+        },
+        (None, Some(rate)) => {
+          state.tokens = rate as f64;
+          state.refilled_at = Instant::now();
+        },
+        (_, None) => {},
+      }
+      state.rate = rate;
+    }
+    self.changed.notify_waiters();
+  }
+
+  pub fn rate(&self) -> Option<u64> {
+    self.state.lock().rate
+  }
+
+  /// Stops all the transfers sharing the limiter before their next chunk, for example when the
+  /// device switches to a metered connection, until [Self::resume] is called.
+  pub fn pause(&self) {
+    self.state.lock().paused = true;
+    self.changed.notify_waiters();
+  }
+
+  pub fn resume(&self) {
+    self.state.lock().paused = false;
+    self.changed.notify_waiters();
+  }
+
+  pub fn is_paused(&self) -> bool {
+    self.state.lock().paused
+  }
+
+  /// Waits until `bytes` can be transferred.
+  pub async fn acquire(&self, bytes: u64) {
+    loop {
+      // Created before the state is read, so a change made meanwhile is not missed.
+      let changed = self.changed.notified();
+      let wait = {
+        let mut state = self.state.lock();
+        match state.rate {
+          _ if state.paused => None,
+          None => return,
+          Some(rate) => {
+            state.refill(rate);
+            if state.tokens >= 0.0 {
+              state.tokens -= bytes as f64;
+              return;
+            }
+            Some(Duration::from_secs_f64(-state.tokens / rate as f64))
+          },
+        }
+      };
+
+      match wait {
+        Some(wait) => {
+          let sleep = tokio::time::sleep(wait);
+          futures::pin_mut!(changed, sleep);
+          select(changed, sleep).await;
+        },
+        None => changed.await,
+      }
+    }
+  }
+}
+
+/// Wraps a stream so that each chunk is let through by the `limiter` before it's yielded.
+pub fn throttle_stream(stream: ObjectByteStream, limiter: BandwidthLimiter) -> ObjectByteStream {
+  Box::pin(stream.then(move |chunk| {
+    let limiter = limiter.clone();
+    async move {
+      let chunk = chunk?;
+      limiter.acquire(chunk.len() as u64).await;
+      Ok(chunk)
+    }
+  }))
+}
+
+/// The limiters of [ThrottledObjectStorage], one per direction so that a large upload doesn't
+/// slow the downloads down.
+#[derive(Clone, Default)]
+pub struct BandwidthLimits {
+  pub upload: BandwidthLimiter,
+  pub download: BandwidthLimiter,
+}
+
+/// An [ObjectStorageService] that caps the bandwidth of the inner service with the
+/// [BandwidthLimits]. Share the limits between all the storages of the app to cap the aggregate.
+///
+/// The streamed downloads are throttled chunk by chunk. The other transfers are throttled as a
+/// whole: an upload waits before it's sent, a download is held back once it's received. Stream
+/// large uploads with [crate::put_object_in_parts] so they are sent part by part.
+pub struct ThrottledObjectStorage<S: ?Sized> {
+  inner: Arc<S>,
+  limits: BandwidthLimits,
+}
+
+impl<S> ThrottledObjectStorage<S>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  pub fn new(inner: Arc<S>, limits: BandwidthLimits) -> Self {
+    Self { inner, limits }
+  }
+
+  pub fn limits(&self) -> &BandwidthLimits {
+    &self.limits
+  }
+
+  async fn throttled_upload(
+    &self,
+    bytes: u64,
+    fut: impl Future<Output = Result<(), FlowyError>>,
+  ) -> Result<(), FlowyError> {
+    let limiter = self.limits.upload.clone();
+    limiter.acquire(bytes).await;
+    fut.await
+  }
+
+  async fn throttled_download(
+    &self,
+    fut: impl Future<Output = Result<ObjectValue, FlowyError>>,
+  ) -> Result<ObjectValue, FlowyError> {
+    let limiter = self.limits.download.clone();
+    let value = fut.await?;
+    limiter.acquire(value.raw.len() as u64).await;
+    Ok(value)
+  }
+}
+
+#[async_trait]
+impl<S> ObjectStorageService for ThrottledObjectStorage<S>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  async fn get_object_url(&self, object_id: ObjectIdentity) -> Result<String, FlowyError> {
+    self.inner.get_object_url(object_id).await
+  }
+
+  async fn put_object(&self, url: String, object_value: ObjectValue) -> Result<(), FlowyError> {
+    let bytes = object_value.raw.len() as u64;
+    self
+      .throttled_upload(bytes, self.inner.put_object(url, object_value))
+      .await
+  }
+
+  async fn put_object_with_progress(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    progress: ProgressCallback,
+  ) -> Result<(), FlowyError> {
+    let bytes = object_value.raw.len() as u64;
+    self
+      .throttled_upload(
+        bytes,
+        self
+          .inner
+          .put_object_with_progress(url, object_value, progress),
+      )
+      .await
+  }
+
+  fn supports_object_ttl(&self) -> bool {
+    self.inner.supports_object_ttl()
+  }
+
+  async fn put_object_with_ttl(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    ttl: Duration,
+  ) -> Result<(), FlowyError> {
+    let bytes = object_value.raw.len() as u64;
+    self
+      .throttled_upload(
+        bytes,
+        self.inner.put_object_with_ttl(url, object_value, ttl),
+      )
+      .await
+  }
+
+  async fn put_object_if_absent(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+  ) -> Result<(), FlowyError> {
+    let bytes = object_value.raw.len() as u64;
+    self
+      .throttled_upload(bytes, self.inner.put_object_if_absent(url, object_value))
+      .await
+  }
+
+  async fn put_object_if_match(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    etag: String,
+  ) -> Result<(), FlowyError> {
+    let bytes = object_value.raw.len() as u64;
+    self
+      .throttled_upload(
+        bytes,
+        self.inner.put_object_if_match(url, object_value, etag),
+      )
+      .await
+  }
+
+  async fn delete_object(&self, url: String) -> Result<(), FlowyError> {
+    self.inner.delete_object(url).await
+  }
+
+  async fn delete_objects(
+    &self,
+    urls: Vec<String>,
+  ) -> Result<Vec<Result<(), FlowyError>>, FlowyError> {
+    self.inner.delete_objects(urls).await
+  }
+
+  async fn delete_workspace_objects(&self, workspace_id: &str) -> Result<DeleteReport, FlowyError> {
+    self.inner.delete_workspace_objects(workspace_id).await
+  }
+
+  async fn list_objects(
+    &self,
+    workspace_id: &str,
+    prefix: Option<&str>,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    self.inner.list_objects(workspace_id, prefix).await
+  }
+
+  async fn list_objects_with_options(
+    &self,
+    workspace_id: &str,
+    options: &ListOptions,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    self
+      .inner
+      .list_objects_with_options(workspace_id, options)
+      .await
+  }
+
+  async fn get_object(&self, url: String) -> Result<ObjectValue, FlowyError> {
+    self.throttled_download(self.inner.get_object(url)).await
+  }
+
+  async fn get_object_stream(&self, url: String) -> Result<ObjectStream, FlowyError> {
+    let limiter = self.limits.download.clone();
+    let object = self.inner.get_object_stream(url).await?;
+    Ok(ObjectStream {
+      stream: throttle_stream(object.stream, limiter),
+      ..object
+    })
+  }
+
+  async fn get_object_verified(
+    &self,
+    url: String,
+    expected_file_id: &str,
+  ) -> Result<ObjectValue, FlowyError> {
+    self
+      .throttled_download(self.inner.get_object_verified(url, expected_file_id))
+      .await
+  }
+
+  async fn get_object_if_modified(
+    &self,
+    url: String,
+    etag: Option<String>,
+  ) -> Result<Option<(ObjectValue, String)>, FlowyError> {
+    let limiter = self.limits.download.clone();
+    let modified = self.inner.get_object_if_modified(url, etag).await?;
+    if let Some((value, _)) = &modified {
+      limiter.acquire(value.raw.len() as u64).await;
+    }
+    Ok(modified)
+  }
+
+  fn supports_range_requests(&self) -> bool {
+    self.inner.supports_range_requests()
+  }
+
+  async fn get_object_range(
+    &self,
+    url: String,
+    start: u64,
+    end: Option<u64>,
+  ) -> Result<ObjectValue, FlowyError> {
+    self
+      .throttled_download(self.inner.get_object_range(url, start, end))
+      .await
+  }
+
+  async fn head_object(&self, url: String) -> Result<ObjectMeta, FlowyError> {
+    self.inner.head_object(url).await
+  }
+
+  async fn object_exists(&self, url: String) -> Result<bool, FlowyError> {
+    self.inner.object_exists(url).await
+  }
+
+  async fn presign_get_url(&self, url: String, expires_in: Duration) -> Result<String, FlowyError> {
+    self.inner.presign_get_url(url, expires_in).await
+  }
+
+  async fn presign_put_url(&self, url: String, expires_in: Duration) -> Result<String, FlowyError> {
+    self.inner.presign_put_url(url, expires_in).await
+  }
+
+  fn supports_copy_object(&self) -> bool {
+    self.inner.supports_copy_object()
+  }
+
+  async fn copy_object(
+    &self,
+    src_url: String,
+    dst_identity: ObjectIdentity,
+  ) -> Result<String, FlowyError> {
+    self.inner.copy_object(src_url, dst_identity).await
+  }
+
+  async fn trash_object(&self, url: String) -> Result<(), FlowyError> {
+    self.inner.trash_object(url).await
+  }
+
+  async fn restore_object(&self, url: String) -> Result<(), FlowyError> {
+    self.inner.restore_object(url).await
+  }
+
+  async fn purge_trash(&self, older_than: Duration) -> Result<Vec<String>, FlowyError> {
+    self.inner.purge_trash(older_than).await
+  }
+
+  fn supports_versioning(&self) -> bool {
+    self.inner.supports_versioning()
+  }
+
+  async fn list_versions(&self, url: String) -> Result<Vec<VersionMeta>, FlowyError> {
+    self.inner.list_versions(url).await
+  }
+
+  async fn get_object_version(
+    &self,
+    url: String,
+    version_id: String,
+  ) -> Result<ObjectValue, FlowyError> {
+    self
+      .throttled_download(self.inner.get_object_version(url, version_id))
+      .await
+  }
+
+  async fn restore_version(&self, url: String, version_id: String) -> Result<(), FlowyError> {
+    self.inner.restore_version(url, version_id).await
+  }
+
+  fn supports_multipart(&self) -> bool {
+    self.inner.supports_multipart()
+  }
+
+  async fn initiate_multipart(&self, url: String, mime: Mime) -> Result<UploadId, FlowyError> {
+    self.inner.initiate_multipart(url, mime).await
+  }
+
+  async fn upload_part(
+    &self,
+    url: String,
+    upload_id: UploadId,
+    part_number: u32,
+    bytes: Bytes,
+  ) -> Result<PartETag, FlowyError> {
+    let limiter = self.limits.upload.clone();
+    let len = bytes.len() as u64;
+    limiter.acquire(len).await;
+    self
+      .inner
+      .upload_part(url, upload_id, part_number, bytes)
+      .await
+  }
+
+  async fn complete_multipart(
+    &self,
+    url: String,
+    upload_id: UploadId,
+    parts: Vec<PartETag>,
+  ) -> Result<(), FlowyError> {
+    self.inner.complete_multipart(url, upload_id, parts).await
+  }
+
+  async fn abort_multipart(&self, url: String, upload_id: UploadId) -> Result<(), FlowyError> {
+    self.inner.abort_multipart(url, upload_id).await
+  }
+
+  async fn health_check(&self) -> Result<HealthStatus, FlowyError> {
+    self.inner.health_check().await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn chunk_larger_than_bucket_test() {
+    let limiter = BandwidthLimiter::new(100_000);
+    let started = std::time::Instant::now();
+    // The bucket is full, the chunk goes through although it's larger than the bucket.
+    limiter.acquire(150_000).await;
+    assert!(started.elapsed() < Duration::from_millis(100));
+
+    // The debt of 50 000 bytes is paid back before the next chunk.
+    limiter.acquire(1).await;
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_millis(450), "{:?}", elapsed);
+
+    // Lifting the cap lets everything through.
+    limiter.set_rate(None);
+    limiter.acquire(u64::MAX).await;
+  }
+
+  #[tokio::test]
+  async fn pause_and_resume_test() {
+    let limiter = BandwidthLimiter::unlimited();
+    limiter.pause();
+    let cloned_limiter = limiter.clone();
+    let transfer = tokio::spawn(async move { cloned_limiter.acquire(10).await });
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!transfer.is_finished());
+    limiter.resume();
+    tokio::time::timeout(Duration::from_secs(1), transfer)
+      .await
+      .unwrap()
+      .unwrap();
+  }
+}
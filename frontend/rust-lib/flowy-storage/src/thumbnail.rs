@@ -0,0 +1,336 @@
+use mime::Mime;
+
+#[cfg(feature = "thumbnail")]
+use flowy_error::{ErrorCode, FlowyError};
+
+#[cfg(feature = "thumbnail")]
+use crate::ObjectValue;
+
+/// The orientation of an image recorded in its EXIF metadata. Cameras store the pixels as they
+/// were captured and record how the image must be transformed to be displayed upright.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageOrientation {
+  Normal,
+  FlipHorizontal,
+  Rotate180,
+  FlipVertical,
+  /// Flipped along the top-left to bottom-right diagonal.
+  Transpose,
+  /// Must be rotated 90 degrees clockwise to be displayed upright.
+  Rotate90,
+  /// Flipped along the top-right to bottom-left diagonal.
+  Transverse,
+  /// Must be rotated 270 degrees clockwise to be displayed upright.
+  Rotate270,
+}
+
+impl ImageOrientation {
+  /// Parses the value of the EXIF `Orientation` tag.
+  pub fn from_exif(value: u16) -> Option<Self> {
+    match value {
+      1 => Some(Self::Normal),
+      2 => Some(Self::FlipHorizontal),
+      3 => Some(Self::Rotate180),
+      4 => Some(Self::FlipVertical),
+      5 => Some(Self::Transpose),
+      6 => Some(Self::Rotate90),
+      7 => Some(Self::Transverse),
+      8 => Some(Self::Rotate270),
+      _ => None,
+    }
+  }
+}
+
+const EXIF_ORIENTATION_TAG: u16 = 0x0112;
+
+/// Reads the EXIF orientation of a JPEG image. Returns `None` if the content is not a JPEG, has
+/// no EXIF metadata, or the metadata is malformed.
+pub fn exif_orientation(content: &[u8]) -> Option<ImageOrientation> {
+  if !content.starts_with(&[0xFF, 0xD8]) {
+    return None;
+  }
+
+  let mut pos = 2;
+  while pos + 4 <= content.len() {
+    if content[pos] != 0xFF {
+      return None;
+    }
+    let marker = content[pos + 1];
+    // Standalone markers don't have a length.
+    if marker == 0x01 || (0xD0..=0xD7).contains(&marker) {
+      pos += 2;
+      continue;
+    }
+    // The metadata segments come before the start of the scan.
+    if marker == 0xDA {
+      return None;
+    }
+
+    let len = u16::from_be_bytes([content[pos + 2], content[pos + 3]]) as usize;
+    let segment = content.get(pos + 4..pos + 2 + len)?;
+    if marker == 0xE1 && segment.starts_with(b"Exif\0\0") {
+      return tiff_orientation(&segment[6..]);
+    }
+    pos += 2 + len;
+  }
+  None
+}
+
+fn tiff_orientation(tiff: &[u8]) -> Option<ImageOrientation> {
+  let big_endian = match tiff.get(..2)? {
+    b"MM" => true,
+    b"II" => false,
+    _ => return None,
+  };
+  let read_u16 = |pos: usize| -> Option<u16> {
+    let bytes = [*tiff.get(pos)?, *tiff.get(pos + 1)?];
+    Some(if big_endian {
+      u16::from_be_bytes(bytes)
+    } else {
+      u16::from_le_bytes(bytes)
+    })
+  };
+  let read_u32 = |pos: usize| -> Option<u32> {
+    let bytes = [
+      *tiff.get(pos)?,
+      *tiff.get(pos + 1)?,
+      *tiff.get(pos + 2)?,
+      *tiff.get(pos + 3)?,
+    ];
+    Some(if big_endian {
+      u32::from_be_bytes(bytes)
+    } else {
+      u32::from_le_bytes(bytes)
+    })
+  };
+
+  if read_u16(2)? != 42 {
+    return None;
+  }
+  let ifd = read_u32(4)? as usize;
+  let entries = read_u16(ifd)? as usize;
+  (0..entries)
+    .map(|i| ifd + 2 + i * 12)
+    .find(|entry| read_u16(*entry) == Some(EXIF_ORIENTATION_TAG))
+    .and_then(|entry| read_u16(entry + 8))
+    .and_then(ImageOrientation::from_exif)
+}
+
+/// Decoding a thumbnail of an image bigger than this is refused, so a small file that decodes
+/// to a huge image can't exhaust the memory.
+pub const MAX_THUMBNAIL_SOURCE_BYTES: u64 = 256 * 1024 * 1024;
+
+#[cfg(feature = "thumbnail")]
+const THUMBNAIL_JPEG_QUALITY: u8 = 80;
+
+/// Creates a preview of an image that fits within `max_dim` x `max_dim` pixels, keeping its
+/// aspect ratio. Images that already fit are not upscaled. The EXIF orientation of the source is
+/// applied, so the thumbnail is displayed upright without any metadata.
+///
+/// The thumbnail is a JPEG, or a PNG if the source has an alpha channel.
+///
+/// # Returns
+/// - `Ok(ObjectValue)`: The thumbnail.
+/// - `Err(Error)`: [ErrorCode::InvalidParams] if the mime type of the value is not a raster
+///   image type, [ErrorCode::InvalidImage] if the image can't be decoded.
+#[cfg(feature = "thumbnail")]
+pub fn generate_thumbnail(value: &ObjectValue, max_dim: u32) -> Result<ObjectValue, FlowyError> {
+  use std::io::Cursor;
+  use std::panic::{catch_unwind, AssertUnwindSafe};
+
+  use image::io::{Limits, Reader};
+  use image::{DynamicImage, GenericImageView, ImageOutputFormat};
+
+  if !is_raster_image(&value.mime) {
+    return Err(FlowyError::new(
+      ErrorCode::InvalidParams,
+      format!(
+        "can't create a thumbnail of {}, it's not an image",
+        value.mime
+      ),
+    ));
+  }
+  if max_dim == 0 {
+    return Err(FlowyError::new(
+      ErrorCode::InvalidParams,
+      "the thumbnail size must be at least 1 pixel",
+    ));
+  }
+  let value = value.clone().decompress()?;
+
+  let decode = || {
+    let mut reader = Reader::new(Cursor::new(value.raw.as_ref())).with_guessed_format()?;
+    let mut limits = Limits::default();
+    limits.max_alloc = Some(MAX_THUMBNAIL_SOURCE_BYTES);
+    reader.limits(limits);
+    reader.decode()
+  };
+  // Some decoders panic on malformed content instead of returning an error.
+  let image = match catch_unwind(AssertUnwindSafe(decode)) {
+    Ok(Ok(image)) => image,
+    Ok(Err(err)) => return Err(invalid_image_error(err)),
+    Err(_) => return Err(invalid_image_error("the decoder panicked")),
+  };
+
+  let image = match exif_orientation(&value.raw).unwrap_or(ImageOrientation::Normal) {
+    ImageOrientation::Normal => image,
+    ImageOrientation::FlipHorizontal => image.fliph(),
+    ImageOrientation::Rotate180 => image.rotate180(),
+    ImageOrientation::FlipVertical => image.flipv(),
+    ImageOrientation::Transpose => image.rotate90().fliph(),
+    ImageOrientation::Rotate90 => image.rotate90(),
+    ImageOrientation::Transverse => image.rotate270().fliph(),
+    ImageOrientation::Rotate270 => image.rotate270(),
+  };
+  let (width, height) = image.dimensions();
+  let image = if width > max_dim || height > max_dim {
+    image.thumbnail(max_dim, max_dim)
+  } else {
+    image
+  };
+
+  let mut raw = Vec::new();
+  let mime = if image.color().has_alpha() {
+    image
+      .write_to(&mut Cursor::new(&mut raw), ImageOutputFormat::Png)
+      .map_err(invalid_image_error)?;
+    mime::IMAGE_PNG
+  } else {
+    DynamicImage::ImageRgb8(image.to_rgb8())
+      .write_to(
+        &mut Cursor::new(&mut raw),
+        ImageOutputFormat::Jpeg(THUMBNAIL_JPEG_QUALITY),
+      )
+      .map_err(invalid_image_error)?;
+    mime::IMAGE_JPEG
+  };
+  Ok(ObjectValue {
+    raw: raw.into(),
+    mime,
+    content_encoding: None,
+  })
+}
+
+/// Returns true if [generate_thumbnail] can decode images of the given mime type. SVG is an
+/// image type, but it's not a raster image.
+pub fn is_raster_image(mime: &Mime) -> bool {
+  mime.type_() == mime::IMAGE && mime.subtype() != mime::SVG
+}
+
+#[cfg(feature = "thumbnail")]
+fn invalid_image_error(err: impl std::fmt::Display) -> FlowyError {
+  FlowyError::new(
+    ErrorCode::InvalidImage,
+    format!("failed to decode the image: {}", err),
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// A JPEG header followed by an EXIF segment with the given orientation.
+  fn jpeg_with_orientation(orientation: u16, big_endian: bool) -> Vec<u8> {
+    let u16_bytes = |v: u16| {
+      if big_endian {
+        v.to_be_bytes()
+      } else {
+        v.to_le_bytes()
+      }
+    };
+    let mut tiff = Vec::new();
+    tiff.extend_from_slice(if big_endian { b"MM" } else { b"II" });
+    tiff.extend_from_slice(&u16_bytes(42));
+    let ifd_offset = 8u32;
+    tiff.extend_from_slice(&if big_endian {
+      ifd_offset.to_be_bytes()
+    } else {
+      ifd_offset.to_le_bytes()
+    });
+    tiff.extend_from_slice(&u16_bytes(2));
+    // An unrelated tag, then the orientation.
+    for (tag, value) in [(0x010F, 0), (EXIF_ORIENTATION_TAG, orientation)] {
+      tiff.extend_from_slice(&u16_bytes(tag));
+      tiff.extend_from_slice(&u16_bytes(3));
+      tiff.extend_from_slice(&if big_endian {
+        1u32.to_be_bytes()
+      } else {
+        1u32.to_le_bytes()
+      });
+      tiff.extend_from_slice(&u16_bytes(value));
+      tiff.extend_from_slice(&[0, 0]);
+    }
+
+    let mut segment = b"Exif\0\0".to_vec();
+    segment.extend_from_slice(&tiff);
+    let mut jpeg = vec![0xFF, 0xD8];
+    // An APP0 segment comes first in most files.
+    jpeg.extend_from_slice(&[0xFF, 0xE0, 0x00, 0x04, 0x00, 0x00]);
+    jpeg.extend_from_slice(&[0xFF, 0xE1]);
+    jpeg.extend_from_slice(&((segment.len() + 2) as u16).to_be_bytes());
+    jpeg.extend_from_slice(&segment);
+    jpeg.extend_from_slice(&[0xFF, 0xDA, 0x00, 0x02]);
+    jpeg
+  }
+
+  #[test]
+  fn exif_orientation_test() {
+    for big_endian in [true, false] {
+      let jpeg = jpeg_with_orientation(6, big_endian);
+      assert_eq!(exif_orientation(&jpeg), Some(ImageOrientation::Rotate90));
+      assert_eq!(
+        exif_orientation(&jpeg_with_orientation(9, big_endian)),
+        None
+      );
+
+      // A truncated or corrupted file never panics.
+      for len in 0..jpeg.len() {
+        exif_orientation(&jpeg[..len]);
+        let mut corrupted = jpeg.clone();
+        corrupted[len] = 0xFF;
+        exif_orientation(&corrupted);
+      }
+    }
+    assert_eq!(exif_orientation(b"\x89PNG\r\n\x1a\n"), None);
+  }
+
+  #[test]
+  fn is_raster_image_test() {
+    assert!(is_raster_image(&mime::IMAGE_PNG));
+    assert!(is_raster_image(&"image/webp".parse().unwrap()));
+    assert!(!is_raster_image(&mime::IMAGE_SVG));
+    assert!(!is_raster_image(&mime::APPLICATION_PDF));
+  }
+
+  #[cfg(feature = "thumbnail")]
+  #[test]
+  fn generate_thumbnail_test() {
+    use std::io::Cursor;
+
+    use image::{GenericImageView, ImageOutputFormat, RgbImage};
+
+    let mut png = Vec::new();
+    image::DynamicImage::ImageRgb8(RgbImage::new(40, 20))
+      .write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)
+      .unwrap();
+    let value = |raw: Vec<u8>, mime: Mime| ObjectValue {
+      raw: raw.into(),
+      mime,
+      content_encoding: None,
+    };
+
+    let thumbnail = generate_thumbnail(&value(png.clone(), mime::IMAGE_PNG), 10).unwrap();
+    assert_eq!(thumbnail.mime, mime::IMAGE_JPEG);
+    let decoded = image::load_from_memory(&thumbnail.raw).unwrap();
+    assert_eq!(decoded.dimensions(), (10, 5));
+
+    let err = generate_thumbnail(&value(png.clone(), mime::APPLICATION_PDF), 10)
+      .err()
+      .unwrap();
+    assert_eq!(err.code, ErrorCode::InvalidParams);
+    let err = generate_thumbnail(&value(png[..png.len() / 2].to_vec(), mime::IMAGE_PNG), 10)
+      .err()
+      .unwrap();
+    assert_eq!(err.code, ErrorCode::InvalidImage);
+  }
+}
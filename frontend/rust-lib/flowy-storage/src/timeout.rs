@@ -0,0 +1,624 @@
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{Future, Stream};
+use mime::Mime;
+use tokio::time::{sleep, Instant, Sleep};
+
+use flowy_error::{ErrorCode, FlowyError};
+
+use crate::{
+  DeleteReport, HealthStatus, ListOptions, ObjectByteStream, ObjectIdentity, ObjectMeta,
+  ObjectStorageService, ObjectStream, ObjectValue, PartETag, ProgressCallback, UploadId,
+  VersionMeta,
+};
+
+/// The default of [TimeoutConfig::idle] and [crate::StorageConfig::idle_timeout].
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The deadlines enforced by [TimeoutObjectStorage]. Each deadline covers the whole operation,
+/// including the connection setup.
+#[derive(Debug, Clone)]
+pub struct TimeoutConfig {
+  /// Deadline of the operations that don't transfer content, like [ObjectStorageService::get_object_url]
+  /// or [ObjectStorageService::delete_object].
+  pub request: Duration,
+  /// Deadline of the downloads.
+  pub get: Duration,
+  /// Deadline of the uploads, including each part of a multipart upload.
+  pub put: Duration,
+  /// The longest time a streaming transfer can go without receiving a chunk, see
+  /// [idle_timeout_stream].
+  pub idle: Duration,
+}
+
+impl Default for TimeoutConfig {
+  fn default() -> Self {
+    Self {
+      request: Duration::from_secs(30),
+      get: Duration::from_secs(2 * 60),
+      put: Duration::from_secs(10 * 60),
+      idle: DEFAULT_IDLE_TIMEOUT,
+    }
+  }
+}
+
+pub(crate) fn timeout_error(operation: &str, timeout: Duration) -> FlowyError {
+  FlowyError::new(
+    ErrorCode::Timeout,
+    format!("{} timed out after {:?}", operation, timeout),
+  )
+}
+
+async fn with_timeout<T>(
+  fut: impl Future<Output = Result<T, FlowyError>>,
+  timeout: Duration,
+  operation: &'static str,
+) -> Result<T, FlowyError> {
+  match tokio::time::timeout(timeout, fut).await {
+    Ok(result) => result,
+    Err(_) => Err(timeout_error(operation, timeout)),
+  }
+}
+
+/// The error of a transfer that didn't move a byte for `idle`, see [idle_timeout_stream].
+pub(crate) fn stalled_error(idle: Duration) -> FlowyError {
+  FlowyError::new(
+    ErrorCode::Timeout,
+    format!("the transfer stalled, no data was received for {:?}", idle),
+  )
+}
+
+/// Wraps a stream so that it fails with an [ErrorCode::Timeout] error when no chunk is received
+/// for `idle`. Unlike a deadline, the timer is reset on every chunk, so a slow but steady
+/// transfer is never interrupted. The timer only runs while the consumer waits for the next
+/// chunk: a consumer that takes its time with a chunk, like an upload of a part, doesn't count as
+/// a stall.
+pub fn idle_timeout_stream(stream: ObjectByteStream, idle: Duration) -> ObjectByteStream {
+  Box::pin(IdleTimeoutStream {
+    stream,
+    idle,
+    sleep: Box::pin(sleep(idle)),
+    waiting: false,
+    timed_out: false,
+  })
+}
+
+/// Applies [idle_timeout_stream] with the idle timeout of the [crate::StorageConfig], if any.
+pub(crate) fn configured_idle_timeout_stream(stream: ObjectByteStream) -> ObjectByteStream {
+  match crate::storage_config().idle_timeout {
+    Some(idle) => idle_timeout_stream(stream, idle),
+    None => stream,
+  }
+}
+
+struct IdleTimeoutStream {
+  stream: ObjectByteStream,
+  idle: Duration,
+  sleep: Pin<Box<Sleep>>,
+  /// Whether the last poll returned `Pending`, the timer is started by the first poll after a
+  /// chunk.
+  waiting: bool,
+  timed_out: bool,
+}
+
+impl Stream for IdleTimeoutStream {
+  type Item = Result<Bytes, FlowyError>;
+
+  fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    if self.timed_out {
+      return Poll::Ready(None);
+    }
+
+    match self.stream.as_mut().poll_next(cx) {
+      Poll::Ready(item) => {
+        self.waiting = false;
+        Poll::Ready(item)
+      },
+      Poll::Pending => {
+        if !self.waiting {
+          self.waiting = true;
+          let deadline = Instant::now() + self.idle;
+          self.sleep.as_mut().reset(deadline);
+        }
+        match self.sleep.as_mut().poll(cx) {
+          Poll::Ready(_) => {
+            self.timed_out = true;
+            Poll::Ready(Some(Err(stalled_error(self.idle))))
+          },
+          Poll::Pending => Poll::Pending,
+        }
+      },
+    }
+  }
+}
+
+/// An [ObjectStorageService] that fails the operations of the inner service that don't complete
+/// within the deadlines of the [TimeoutConfig], with an [ErrorCode::Timeout] error.
+///
+/// Use [TimeoutObjectStorage::with_config] to override the deadlines of a single call:
+/// ```ignore
+/// storage
+///   .with_config(TimeoutConfig { get: Duration::from_secs(10), ..Default::default() })
+///   .get_object(url)
+///   .await?;
+/// ```
+pub struct TimeoutObjectStorage<S: ?Sized> {
+  inner: Arc<S>,
+  config: TimeoutConfig,
+}
+
+impl<S> TimeoutObjectStorage<S>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  pub fn new(inner: Arc<S>, config: TimeoutConfig) -> Self {
+    Self { inner, config }
+  }
+
+  /// Returns a storage that shares the inner service but enforces other deadlines.
+  pub fn with_config(&self, config: TimeoutConfig) -> Self {
+    Self {
+      inner: self.inner.clone(),
+      config,
+    }
+  }
+
+  pub fn config(&self) -> &TimeoutConfig {
+    &self.config
+  }
+}
+
+#[async_trait]
+impl<S> ObjectStorageService for TimeoutObjectStorage<S>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  async fn get_object_url(&self, object_id: ObjectIdentity) -> Result<String, FlowyError> {
+    with_timeout(
+      self.inner.get_object_url(object_id),
+      self.config.request,
+      "get object url",
+    )
+    .await
+  }
+
+  async fn put_object(&self, url: String, object_value: ObjectValue) -> Result<(), FlowyError> {
+    with_timeout(
+      self.inner.put_object(url, object_value),
+      self.config.put,
+      "put object",
+    )
+    .await
+  }
+
+  async fn put_object_with_progress(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    progress: ProgressCallback,
+  ) -> Result<(), FlowyError> {
+    with_timeout(
+      self
+        .inner
+        .put_object_with_progress(url, object_value, progress),
+      self.config.put,
+      "put object",
+    )
+    .await
+  }
+
+  fn supports_object_ttl(&self) -> bool {
+    self.inner.supports_object_ttl()
+  }
+
+  async fn put_object_with_ttl(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    ttl: Duration,
+  ) -> Result<(), FlowyError> {
+    with_timeout(
+      self.inner.put_object_with_ttl(url, object_value, ttl),
+      self.config.put,
+      "put object",
+    )
+    .await
+  }
+
+  async fn put_object_if_absent(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+  ) -> Result<(), FlowyError> {
+    with_timeout(
+      self.inner.put_object_if_absent(url, object_value),
+      self.config.put,
+      "put object",
+    )
+    .await
+  }
+
+  async fn put_object_if_match(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    etag: String,
+  ) -> Result<(), FlowyError> {
+    with_timeout(
+      self.inner.put_object_if_match(url, object_value, etag),
+      self.config.put,
+      "put object",
+    )
+    .await
+  }
+
+  async fn delete_object(&self, url: String) -> Result<(), FlowyError> {
+    with_timeout(
+      self.inner.delete_object(url),
+      self.config.request,
+      "delete object",
+    )
+    .await
+  }
+
+  async fn delete_objects(
+    &self,
+    urls: Vec<String>,
+  ) -> Result<Vec<Result<(), FlowyError>>, FlowyError> {
+    with_timeout(
+      self.inner.delete_objects(urls),
+      self.config.request,
+      "delete objects",
+    )
+    .await
+  }
+
+  async fn delete_workspace_objects(&self, workspace_id: &str) -> Result<DeleteReport, FlowyError> {
+    with_timeout(
+      self.inner.delete_workspace_objects(workspace_id),
+      self.config.request,
+      "delete workspace objects",
+    )
+    .await
+  }
+
+  async fn list_objects(
+    &self,
+    workspace_id: &str,
+    prefix: Option<&str>,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    with_timeout(
+      self.inner.list_objects(workspace_id, prefix),
+      self.config.request,
+      "list objects",
+    )
+    .await
+  }
+
+  async fn list_objects_with_options(
+    &self,
+    workspace_id: &str,
+    options: &ListOptions,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    with_timeout(
+      self.inner.list_objects_with_options(workspace_id, options),
+      self.config.request,
+      "list objects",
+    )
+    .await
+  }
+
+  async fn get_object(&self, url: String) -> Result<ObjectValue, FlowyError> {
+    with_timeout(self.inner.get_object(url), self.config.get, "get object").await
+  }
+
+  /// The stream must be opened within [TimeoutConfig::request], then each chunk must arrive
+  /// within [TimeoutConfig::idle] of the previous one.
+  async fn get_object_if_modified(
+    &self,
+    url: String,
+    etag: Option<String>,
+  ) -> Result<Option<(ObjectValue, String)>, FlowyError> {
+    with_timeout(
+      self.inner.get_object_if_modified(url, etag),
+      self.config.get,
+      "get object",
+    )
+    .await
+  }
+
+  async fn get_object_stream(&self, url: String) -> Result<ObjectStream, FlowyError> {
+    let idle = self.config.idle;
+    let object = with_timeout(
+      self.inner.get_object_stream(url),
+      self.config.request,
+      "open object stream",
+    )
+    .await?;
+    Ok(ObjectStream {
+      stream: idle_timeout_stream(object.stream, idle),
+      ..object
+    })
+  }
+
+  async fn get_object_verified(
+    &self,
+    url: String,
+    expected_file_id: &str,
+  ) -> Result<ObjectValue, FlowyError> {
+    with_timeout(
+      self.inner.get_object_verified(url, expected_file_id),
+      self.config.get,
+      "get object",
+    )
+    .await
+  }
+
+  fn supports_range_requests(&self) -> bool {
+    self.inner.supports_range_requests()
+  }
+
+  async fn get_object_range(
+    &self,
+    url: String,
+    start: u64,
+    end: Option<u64>,
+  ) -> Result<ObjectValue, FlowyError> {
+    with_timeout(
+      self.inner.get_object_range(url, start, end),
+      self.config.get,
+      "get object range",
+    )
+    .await
+  }
+
+  async fn head_object(&self, url: String) -> Result<ObjectMeta, FlowyError> {
+    with_timeout(
+      self.inner.head_object(url),
+      self.config.request,
+      "head object",
+    )
+    .await
+  }
+
+  async fn object_exists(&self, url: String) -> Result<bool, FlowyError> {
+    with_timeout(
+      self.inner.object_exists(url),
+      self.config.request,
+      "check object existence",
+    )
+    .await
+  }
+
+  async fn presign_get_url(&self, url: String, expires_in: Duration) -> Result<String, FlowyError> {
+    with_timeout(
+      self.inner.presign_get_url(url, expires_in),
+      self.config.request,
+      "presign url",
+    )
+    .await
+  }
+
+  async fn presign_put_url(&self, url: String, expires_in: Duration) -> Result<String, FlowyError> {
+    with_timeout(
+      self.inner.presign_put_url(url, expires_in),
+      self.config.request,
+      "presign url",
+    )
+    .await
+  }
+
+  fn supports_copy_object(&self) -> bool {
+    self.inner.supports_copy_object()
+  }
+
+  async fn copy_object(
+    &self,
+    src_url: String,
+    dst_identity: ObjectIdentity,
+  ) -> Result<String, FlowyError> {
+    with_timeout(
+      self.inner.copy_object(src_url, dst_identity),
+      self.config.put,
+      "copy object",
+    )
+    .await
+  }
+
+  async fn trash_object(&self, url: String) -> Result<(), FlowyError> {
+    with_timeout(
+      self.inner.trash_object(url),
+      self.config.request,
+      "trash object",
+    )
+    .await
+  }
+
+  async fn restore_object(&self, url: String) -> Result<(), FlowyError> {
+    with_timeout(
+      self.inner.restore_object(url),
+      self.config.request,
+      "restore object",
+    )
+    .await
+  }
+
+  async fn purge_trash(&self, older_than: Duration) -> Result<Vec<String>, FlowyError> {
+    with_timeout(
+      self.inner.purge_trash(older_than),
+      self.config.request,
+      "purge trash",
+    )
+    .await
+  }
+
+  fn supports_versioning(&self) -> bool {
+    self.inner.supports_versioning()
+  }
+
+  async fn list_versions(&self, url: String) -> Result<Vec<VersionMeta>, FlowyError> {
+    self.inner.list_versions(url).await
+  }
+
+  async fn get_object_version(
+    &self,
+    url: String,
+    version_id: String,
+  ) -> Result<ObjectValue, FlowyError> {
+    with_timeout(
+      self.inner.get_object_version(url, version_id),
+      self.config.get,
+      "get object version",
+    )
+    .await
+  }
+
+  async fn restore_version(&self, url: String, version_id: String) -> Result<(), FlowyError> {
+    with_timeout(
+      self.inner.restore_version(url, version_id),
+      self.config.put,
+      "restore object version",
+    )
+    .await
+  }
+
+  fn supports_multipart(&self) -> bool {
+    self.inner.supports_multipart()
+  }
+
+  async fn initiate_multipart(&self, url: String, mime: Mime) -> Result<UploadId, FlowyError> {
+    with_timeout(
+      self.inner.initiate_multipart(url, mime),
+      self.config.request,
+      "initiate multipart upload",
+    )
+    .await
+  }
+
+  async fn upload_part(
+    &self,
+    url: String,
+    upload_id: UploadId,
+    part_number: u32,
+    bytes: Bytes,
+  ) -> Result<PartETag, FlowyError> {
+    with_timeout(
+      self.inner.upload_part(url, upload_id, part_number, bytes),
+      self.config.put,
+      "upload part",
+    )
+    .await
+  }
+
+  async fn complete_multipart(
+    &self,
+    url: String,
+    upload_id: UploadId,
+    parts: Vec<PartETag>,
+  ) -> Result<(), FlowyError> {
+    with_timeout(
+      self.inner.complete_multipart(url, upload_id, parts),
+      self.config.request,
+      "complete multipart upload",
+    )
+    .await
+  }
+
+  async fn abort_multipart(&self, url: String, upload_id: UploadId) -> Result<(), FlowyError> {
+    with_timeout(
+      self.inner.abort_multipart(url, upload_id),
+      self.config.request,
+      "abort multipart upload",
+    )
+    .await
+  }
+
+  /// A probe that doesn't answer within [TimeoutConfig::request] reports the backend as
+  /// unreachable.
+  async fn health_check(&self) -> Result<HealthStatus, FlowyError> {
+    let started_at = Instant::now();
+    match with_timeout(
+      self.inner.health_check(),
+      self.config.request,
+      "health check",
+    )
+    .await
+    {
+      Err(err) if err.code == ErrorCode::Timeout => {
+        Ok(HealthStatus::from_error(&err, started_at.elapsed()))
+      },
+      result => result,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use futures::StreamExt;
+
+  use super::*;
+  use crate::{InMemoryObjectStorage, StorageOperation};
+
+  #[tokio::test]
+  async fn get_object_timeout_test() {
+    let inner = InMemoryObjectStorage::new();
+    // The downloads never complete.
+    inner.set_latency(StorageOperation::Get, Duration::from_secs(3600));
+    let storage = TimeoutObjectStorage::new(Arc::new(inner), TimeoutConfig::default());
+    let storage = storage.with_config(TimeoutConfig {
+      get: Duration::from_millis(10),
+      ..Default::default()
+    });
+    let url = "memory://w1/1.txt".to_string();
+    let err = storage.get_object(url.clone()).await.err().unwrap();
+    assert_eq!(err.code, ErrorCode::Timeout);
+    assert!(storage.delete_object(url).await.is_ok());
+  }
+
+  #[tokio::test]
+  async fn idle_timeout_stream_test() {
+    // A slow but steady stream is not interrupted.
+    let steady = futures::stream::iter(0..5).then(|_| async {
+      tokio::time::sleep(Duration::from_millis(10)).await;
+      Ok(Bytes::from_static(b"chunk"))
+    });
+    let chunks = idle_timeout_stream(Box::pin(steady), Duration::from_millis(30))
+      .collect::<Vec<_>>()
+      .await;
+    assert_eq!(chunks.len(), 5);
+    assert!(chunks.iter().all(|chunk| chunk.is_ok()));
+
+    // A stalled stream fails once.
+    let stalled = futures::stream::once(async { Ok(Bytes::from_static(b"chunk")) })
+      .chain(futures::stream::pending());
+    let chunks = idle_timeout_stream(Box::pin(stalled), Duration::from_millis(30))
+      .collect::<Vec<_>>()
+      .await;
+    assert_eq!(chunks.len(), 2);
+    let err = chunks[1].as_ref().unwrap_err();
+    assert_eq!(err.code, ErrorCode::Timeout);
+    assert!(err.msg.contains("stalled"));
+  }
+
+  #[tokio::test]
+  async fn idle_timeout_ignores_slow_consumer_test() {
+    // Each chunk takes longer to consume than the idle timeout, the source is never idle.
+    let source = futures::stream::iter(0..3).then(|_| async {
+      tokio::time::sleep(Duration::from_millis(5)).await;
+      Ok(Bytes::from_static(b"chunk"))
+    });
+    let mut stream = idle_timeout_stream(Box::pin(source), Duration::from_millis(30));
+    let mut received = 0;
+    while let Some(chunk) = stream.next().await {
+      chunk.unwrap();
+      received += 1;
+      tokio::time::sleep(Duration::from_millis(60)).await;
+    }
+    assert_eq!(received, 3);
+  }
+}
@@ -0,0 +1,210 @@
+use std::path::Path;
+
+use flowy_error::{ErrorCode, FlowyError};
+
+use crate::StorageObject;
+
+/// The types of files a [crate::FileStoragePlan] accepts, by mime type and by extension. The
+/// patterns can contain `*` wildcards, like `application/x-*` or `ph*`, and are compared
+/// ignoring the case. The extensions are given without the leading dot.
+///
+/// A file matching a denied pattern is rejected. If there are allowed patterns, a file must also
+/// match one of them, either by its mime type or by its extension. Without any pattern every
+/// file is accepted, the default.
+#[derive(Debug, Clone, Default)]
+pub struct UploadTypePolicy {
+  allowed_mimes: Vec<String>,
+  denied_mimes: Vec<String>,
+  allowed_extensions: Vec<String>,
+  denied_extensions: Vec<String>,
+}
+
+impl UploadTypePolicy {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn allow_mime(mut self, pattern: impl Into<String>) -> Self {
+    self.allowed_mimes.push(pattern.into().to_ascii_lowercase());
+    self
+  }
+
+  pub fn deny_mime(mut self, pattern: impl Into<String>) -> Self {
+    self.denied_mimes.push(pattern.into().to_ascii_lowercase());
+    self
+  }
+
+  pub fn allow_extension(mut self, pattern: impl Into<String>) -> Self {
+    self.allowed_extensions.push(normalize_extension(pattern));
+    self
+  }
+
+  pub fn deny_extension(mut self, pattern: impl Into<String>) -> Self {
+    self.denied_extensions.push(normalize_extension(pattern));
+    self
+  }
+
+  /// Returns true if the policy accepts every file.
+  pub fn is_empty(&self) -> bool {
+    self.allowed_mimes.is_empty()
+      && self.denied_mimes.is_empty()
+      && self.allowed_extensions.is_empty()
+      && self.denied_extensions.is_empty()
+  }
+
+  /// Returns an [ErrorCode::FileTypeNotAllowed] error naming the type of the object if the
+  /// policy rejects it. The mime type is the one sniffed from the content when the given one is
+  /// generic, see [crate::ObjectValueSupabase::mime_type], so nothing but the head of a file is
+  /// read.
+  pub fn check(&self, object: &StorageObject) -> Result<(), FlowyError> {
+    if self.is_empty() {
+      return Ok(());
+    }
+    let mime = object.value.mime_type(true);
+    let mime = mime
+      .split(';')
+      .next()
+      .unwrap_or_default()
+      .trim()
+      .to_ascii_lowercase();
+    let extension = Path::new(&object.file_name)
+      .extension()
+      .map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+
+    let not_allowed = |reason: String| {
+      FlowyError::new(
+        ErrorCode::FileTypeNotAllowed,
+        format!("{} can't be uploaded, {}", object.file_name, reason),
+      )
+    };
+    if let Some(pattern) = matching(&self.denied_mimes, &mime) {
+      return Err(not_allowed(format!(
+        "the {} files are not allowed ({})",
+        mime, pattern
+      )));
+    }
+    if let Some(ext) = &extension {
+      if let Some(pattern) = matching(&self.denied_extensions, ext) {
+        return Err(not_allowed(format!(
+          "the .{} files are not allowed ({})",
+          ext, pattern
+        )));
+      }
+    }
+
+    if self.allowed_mimes.is_empty() && self.allowed_extensions.is_empty() {
+      return Ok(());
+    }
+    let allowed = matching(&self.allowed_mimes, &mime).is_some()
+      || extension.as_ref().map_or(false, |ext| {
+        matching(&self.allowed_extensions, ext).is_some()
+      });
+    if allowed {
+      Ok(())
+    } else {
+      Err(not_allowed(format!(
+        "the {} files are not in the allowed types",
+        mime
+      )))
+    }
+  }
+}
+
+fn normalize_extension(pattern: impl Into<String>) -> String {
+  let pattern = pattern.into().to_ascii_lowercase();
+  pattern.trim_start_matches('.').to_string()
+}
+
+fn matching<'a>(patterns: &'a [String], value: &str) -> Option<&'a str> {
+  patterns
+    .iter()
+    .find(|pattern| wildcard_match(pattern, value))
+    .map(|pattern| pattern.as_str())
+}
+
+/// Matches `value` against a pattern in which `*` stands for any sequence of characters.
+fn wildcard_match(pattern: &str, value: &str) -> bool {
+  let (pattern, value) = (pattern.as_bytes(), value.as_bytes());
+  let (mut p, mut v) = (0, 0);
+  // The position of the last `*` in the pattern, and of the value when it was reached.
+  let mut star = None;
+  while v < value.len() {
+    if p < pattern.len() && pattern[p] == b'*' {
+      star = Some((p, v));
+      p += 1;
+    } else if p < pattern.len() && pattern[p] == value[v] {
+      p += 1;
+      v += 1;
+    } else if let Some((star_p, star_v)) = star {
+      // Let the last `*` match one more character.
+      p = star_p + 1;
+      v = star_v + 1;
+      star = Some((star_p, star_v + 1));
+    } else {
+      return false;
+    }
+  }
+  pattern[p..].iter().all(|c| *c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn object(file_name: &str, mime: &str) -> StorageObject {
+    StorageObject::from_bytes("w1", file_name, "content", mime.to_string())
+  }
+
+  #[test]
+  fn wildcard_match_test() {
+    assert!(wildcard_match("application/x-*", "application/x-sh"));
+    assert!(wildcard_match("*", ""));
+    assert!(wildcard_match("*/*script", "text/javascript"));
+    assert!(wildcard_match("a*b*c", "aXbYbc"));
+    assert!(!wildcard_match("application/x-*", "application/json"));
+    assert!(!wildcard_match("image/png", "image/pngx"));
+  }
+
+  #[test]
+  fn deny_list_test() {
+    let policy = UploadTypePolicy::new()
+      .deny_mime("application/x-*")
+      .deny_extension(".EXE")
+      .deny_extension("ps*");
+    policy.check(&object("a.png", "image/png")).unwrap();
+
+    let err = policy
+      .check(&object("run.sh", "application/x-sh"))
+      .unwrap_err();
+    assert_eq!(err.code, ErrorCode::FileTypeNotAllowed);
+    assert!(err.msg.contains("application/x-sh"), "{}", err.msg);
+
+    let err = policy
+      .check(&object("Setup.Exe", "application/octet-stream"))
+      .unwrap_err();
+    assert!(err.msg.contains(".exe"), "{}", err.msg);
+    assert!(policy.check(&object("profile.PS1", "text/plain")).is_err());
+    // The parameters of the mime type are ignored.
+    assert!(policy
+      .check(&object("a", "Application/X-Sh; charset=utf-8"))
+      .is_err());
+  }
+
+  #[test]
+  fn allow_list_test() {
+    let policy = UploadTypePolicy::new()
+      .allow_mime("image/*")
+      .allow_extension("md")
+      .deny_mime("image/svg+xml");
+    policy.check(&object("a.png", "image/png")).unwrap();
+    policy.check(&object("notes.MD", "text/plain")).unwrap();
+
+    let err = policy.check(&object("a.html", "text/html")).unwrap_err();
+    assert!(err.msg.contains("not in the allowed types"), "{}", err.msg);
+    // Denied even though it matches an allowed pattern.
+    assert!(policy.check(&object("a.svg", "image/svg+xml")).is_err());
+    assert!(UploadTypePolicy::default()
+      .check(&object("a.exe", "application/x-msdownload"))
+      .is_ok());
+  }
+}
@@ -0,0 +1,442 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use futures::future::join_all;
+use tokio::sync::Semaphore;
+use tracing::{error, info};
+
+use flowy_error::{ErrorCode, FlowyError};
+
+use crate::reader::{read_to_bytes, reader_stream};
+use crate::{
+  cancellable, content_hash, fill_ext_from_mime, object_identity, put_object_in_parts,
+  random_file_id, storage_config, transfer_progress_stream, BoxedAsyncRead, CancellationToken,
+  ObjectIdentity, ObjectStorageService, ObjectStream, ObjectValue, ObjectValueSupabase,
+  RetryPolicy, StorageObject, TransferProgressCallback, UploadEvent, UploadEvents,
+};
+
+/// Uploads the objects with at most `max_concurrency` uploads in flight.
+///
+/// Returns the url of each uploaded object, in the same order as `objects`. A failed upload
+/// doesn't stop the other ones. Once `cancel` is cancelled, the uploads in flight are dropped and
+/// the pending ones are not started, their result is an [ErrorCode::Cancelled] error.
+pub async fn upload_many<S>(
+  service: &S,
+  objects: Vec<StorageObject>,
+  max_concurrency: usize,
+  cancel: CancellationToken,
+) -> Vec<Result<String, FlowyError>>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  upload_many_inner(service, objects, max_concurrency, cancel, None).await
+}
+
+/// Like [upload_many], and publishes the progress, the completion or the failure of each upload
+/// to `events`.
+pub async fn upload_many_with_events<S>(
+  service: &S,
+  objects: Vec<StorageObject>,
+  max_concurrency: usize,
+  cancel: CancellationToken,
+  events: &UploadEvents,
+) -> Vec<Result<String, FlowyError>>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  upload_many_inner(service, objects, max_concurrency, cancel, Some(events)).await
+}
+
+async fn upload_many_inner<S>(
+  service: &S,
+  objects: Vec<StorageObject>,
+  max_concurrency: usize,
+  cancel: CancellationToken,
+  events: Option<&UploadEvents>,
+) -> Vec<Result<String, FlowyError>>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  let semaphore = Arc::new(Semaphore::new(max_concurrency.max(1)));
+  let uploads = objects.into_iter().map(|object| {
+    let semaphore = semaphore.clone();
+    let cancel = cancel.clone();
+    async move {
+      let upload = async {
+        let _permit = semaphore
+          .acquire()
+          .await
+          .map_err(|err| FlowyError::internal().with_context(err))?;
+        upload_object(service, &object, events).await
+      };
+      let result = cancellable(upload, &cancel).await;
+      match &result {
+        Err(err) if err.code == ErrorCode::Cancelled => {
+          info!("upload {} was cancelled", object.file_name);
+        },
+        Err(err) => error!("upload {} failed: {}", object.file_name, err),
+        Ok(_) => {},
+      }
+      if let Some(events) = events {
+        events.publish(upload_event(&object, &result));
+      }
+      result.map(|(_, url)| url)
+    }
+  });
+  join_all(uploads).await
+}
+
+pub(crate) fn upload_event(
+  object: &StorageObject,
+  result: &Result<(String, String), FlowyError>,
+) -> UploadEvent {
+  let workspace_id = object.workspace_id.clone();
+  let file_name = object.file_name.clone();
+  match result {
+    Ok((file_id, url)) => UploadEvent::Completed {
+      workspace_id,
+      file_name,
+      file_id: file_id.clone(),
+      url: url.clone(),
+    },
+    Err(err) => UploadEvent::Failed {
+      workspace_id,
+      file_name,
+      error: err.msg.clone(),
+    },
+  }
+}
+
+/// Returns the `file_id` and the url of the uploaded object.
+async fn upload_object<S>(
+  service: &S,
+  object: &StorageObject,
+  events: Option<&UploadEvents>,
+) -> Result<(String, String), FlowyError>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  if let ObjectValueSupabase::Reader {
+    reader,
+    content_length,
+    mime,
+  } = &object.value
+  {
+    if storage_config().uses_multipart(*content_length) {
+      let progress = events
+        .map(|events| events.stream_progress_callback(&object.workspace_id, &object.file_name));
+      return upload_stream(
+        service,
+        object,
+        reader.take()?,
+        *content_length,
+        mime,
+        progress,
+      )
+      .await;
+    }
+  }
+
+  let (identity, value) = read_storage_object(object).await?;
+  let file_id = identity.file_id.clone();
+  let url = service.get_object_url(identity).await?;
+  match events {
+    Some(events) => {
+      let progress = events.progress_callback(&object.workspace_id, &object.file_name);
+      service
+        .put_object_with_progress(url.clone(), value, progress)
+        .await?
+    },
+    None => service.put_object(url.clone(), value).await?,
+  }
+  Ok((file_id, url))
+}
+
+/// Uploads a stream that is too large to be held in memory, or whose length is unknown, part by
+/// part with [put_object_in_parts]. The content hash is only known once the content is read, so
+/// the `file_id` of the object is random instead.
+///
+/// The progress is reported as the content is read, which follows the upload of the parts.
+async fn upload_stream<S>(
+  service: &S,
+  object: &StorageObject,
+  reader: BoxedAsyncRead,
+  content_length: Option<u64>,
+  mime: &str,
+  progress: Option<TransferProgressCallback>,
+) -> Result<(String, String), FlowyError>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  let mime = mime.parse().unwrap_or(mime::APPLICATION_OCTET_STREAM);
+  let mut identity = ObjectIdentity {
+    file_id: random_file_id(),
+    hash_algorithm: None,
+    ..object_identity(&object.workspace_id, &object.file_name, String::new(), None)
+  };
+  fill_ext_from_mime(&mut identity, &mime);
+  let file_id = identity.file_id.clone();
+  let url = service.get_object_url(identity).await?;
+  let config = storage_config();
+  let mut stream = reader_stream(reader, content_length, config.read_buffer_size);
+  if let Some(progress) = progress {
+    stream = transfer_progress_stream(stream, content_length, progress);
+  }
+  let stream = ObjectStream {
+    content_length: content_length.unwrap_or_default(),
+    mime,
+    stream,
+  };
+  put_object_in_parts(
+    service,
+    url.clone(),
+    stream,
+    config.multipart_part_size,
+    RetryPolicy::default(),
+  )
+  .await?;
+  Ok((file_id, url))
+}
+
+/// Reads the content of the object in memory.
+pub(crate) async fn read_storage_object(
+  object: &StorageObject,
+) -> Result<(ObjectIdentity, ObjectValue), FlowyError> {
+  match &object.value {
+    #[cfg(not(target_arch = "wasm32"))]
+    ObjectValueSupabase::File { file_path, .. } => {
+      crate::object_from_disk(&object.workspace_id, file_path, false, None).await
+    },
+    #[cfg(target_arch = "wasm32")]
+    ObjectValueSupabase::File { .. } => Err(
+      FlowyError::not_support()
+        .with_context("upload file from local path is not supported on wasm"),
+    ),
+    ObjectValueSupabase::Bytes { bytes, mime } => Ok(bytes_object(object, bytes.clone(), mime)),
+    ObjectValueSupabase::Reader {
+      reader,
+      content_length,
+      mime,
+    } => {
+      let bytes = read_to_bytes(reader.take()?, *content_length).await?;
+      Ok(bytes_object(object, bytes, mime))
+    },
+  }
+}
+
+fn bytes_object(object: &StorageObject, bytes: Bytes, mime: &str) -> (ObjectIdentity, ObjectValue) {
+  let mut identity = object_identity(
+    &object.workspace_id,
+    &object.file_name,
+    content_hash(&bytes),
+    Some(bytes.len() as u64),
+  );
+  let value = ObjectValue {
+    raw: bytes,
+    mime: mime.parse().unwrap_or(mime::APPLICATION_OCTET_STREAM),
+    content_encoding: None,
+  };
+  fill_ext_from_mime(&mut identity, &value.mime);
+  (identity, value)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use super::*;
+  use crate::{InMemoryObjectStorage, StorageOperation};
+
+  /// The file of the 4th object is missing, so its upload fails.
+  fn objects(count: usize) -> Vec<StorageObject> {
+    (0..count)
+      .map(|i| {
+        let file_name = format!("{}.txt", i);
+        if i == 3 {
+          let missing = std::env::temp_dir().join(format!("missing-{}", content_hash(b"missing")));
+          StorageObject::from_file("w1", &file_name, missing.display())
+        } else {
+          StorageObject::from_bytes("w1", &file_name, vec![i as u8], "text/plain".to_string())
+        }
+      })
+      .collect()
+  }
+
+  #[tokio::test]
+  async fn upload_many_test() {
+    let storage = InMemoryObjectStorage::new();
+    storage.set_latency(StorageOperation::Put, Duration::from_millis(5));
+    let results = upload_many(&storage, objects(10), 3, CancellationToken::new()).await;
+    assert_eq!(results.len(), 10);
+    for (i, result) in results.iter().enumerate() {
+      assert_eq!(result.is_err(), i == 3);
+    }
+    assert!(storage.max_concurrent_calls(StorageOperation::Put) <= 3);
+  }
+
+  #[tokio::test]
+  async fn upload_many_with_events_test() {
+    let storage = InMemoryObjectStorage::new();
+    let events = UploadEvents::new();
+    let subscription = events.subscribe(Some("w1"));
+    let mut objects = objects(4);
+    objects.push(StorageObject::from_bytes(
+      "w2",
+      "other.txt",
+      "other",
+      "text/plain".to_string(),
+    ));
+    upload_many_with_events(&storage, objects, 2, CancellationToken::new(), &events).await;
+    drop(events);
+
+    let mut completed = vec![];
+    let mut failed = vec![];
+    futures::pin_mut!(subscription);
+    while let Some(event) = futures::StreamExt::next(&mut subscription).await {
+      assert_eq!(event.workspace_id(), "w1");
+      match event {
+        UploadEvent::Completed { file_name, url, .. } => completed.push((file_name, url)),
+        UploadEvent::Failed { file_name, .. } => failed.push(file_name),
+        UploadEvent::Progress { .. } => {},
+      }
+    }
+    completed.sort();
+    assert_eq!(completed.len(), 3);
+    assert_eq!(completed[0].0, "0.txt");
+    assert_eq!(failed, vec!["3.txt".to_string()]);
+  }
+
+  #[tokio::test]
+  async fn upload_many_cancel_test() {
+    let storage = InMemoryObjectStorage::new();
+    storage.set_latency(StorageOperation::Put, Duration::from_secs(10));
+    let cancel = CancellationToken::new();
+    let cloned_cancel = cancel.clone();
+    tokio::spawn(async move {
+      tokio::time::sleep(Duration::from_millis(10)).await;
+      cloned_cancel.cancel();
+    });
+
+    let results = upload_many(&storage, objects(5), 2, cancel).await;
+    assert!(results
+      .iter()
+      .all(|result| matches!(result, Err(err) if err.code == ErrorCode::Cancelled)));
+  }
+
+  #[tokio::test]
+  async fn upload_reader_test() {
+    let storage = InMemoryObjectStorage::new();
+    let content = b"streamed content".to_vec();
+    let object = StorageObject::from_reader(
+      "w1",
+      "stream.txt",
+      std::io::Cursor::new(content.clone()),
+      None,
+      "text/plain".to_string(),
+    );
+    assert!(object.file_size_async().await.is_err());
+
+    // The length is unknown, so the content is streamed in parts.
+    let (_, url) = upload_object(&storage, &object, None).await.unwrap();
+    assert_eq!(storage.call_count(StorageOperation::InitiateMultipart), 1);
+    assert_eq!(storage.object(&url).unwrap().raw.to_vec(), content);
+
+    // The reader can only be read once.
+    let err = upload_object(&storage, &object, None).await.unwrap_err();
+    assert_eq!(err.code, ErrorCode::InvalidParams);
+  }
+
+  #[tokio::test]
+  async fn upload_progress_rate_test() {
+    let storage = InMemoryObjectStorage::new();
+    let events = UploadEvents::new();
+    let subscription = events.subscribe(None);
+    let objects = vec![
+      StorageObject::from_bytes("w1", "known.txt", "known size", "text/plain".to_string()),
+      StorageObject::from_reader(
+        "w1",
+        "stream.txt",
+        std::io::Cursor::new(b"streamed content".to_vec()),
+        None,
+        "text/plain".to_string(),
+      ),
+    ];
+    upload_many_with_events(&storage, objects, 2, CancellationToken::new(), &events).await;
+    drop(events);
+
+    let mut last_progress = std::collections::HashMap::new();
+    futures::pin_mut!(subscription);
+    while let Some(event) = futures::StreamExt::next(&mut subscription).await {
+      if let UploadEvent::Progress { file_name, .. } = &event {
+        last_progress.insert(file_name.clone(), event);
+      }
+    }
+    match &last_progress["known.txt"] {
+      UploadEvent::Progress {
+        uploaded,
+        total,
+        bytes_per_second,
+        eta,
+        ..
+      } => {
+        assert_eq!((*uploaded, *total), (10, 10));
+        assert!(bytes_per_second.is_some());
+        assert_eq!(*eta, Some(Duration::ZERO));
+      },
+      _ => unreachable!(),
+    }
+    // The speed of an upload of unknown size is reported, but not the time left.
+    match &last_progress["stream.txt"] {
+      UploadEvent::Progress {
+        uploaded,
+        total,
+        bytes_per_second,
+        eta,
+        ..
+      } => {
+        assert_eq!((*uploaded, *total), (16, 0));
+        assert!(bytes_per_second.is_some());
+        assert_eq!(*eta, None);
+      },
+      _ => unreachable!(),
+    }
+  }
+
+  #[tokio::test]
+  async fn upload_empty_file_test() {
+    let dir = tempfile::tempdir().unwrap();
+    for name in ["empty", "empty.txt"] {
+      std::fs::write(dir.path().join(name), b"").unwrap();
+    }
+    let path = |name: &str| dir.path().join(name).display().to_string();
+
+    let (identity, value) = crate::object_from_disk("w1", &path("empty"), true, None)
+      .await
+      .unwrap();
+    assert_eq!(identity.file_id, content_hash(b""));
+    assert!(crate::is_content_hash_id(&identity.file_id));
+    assert_eq!(value.mime, mime::APPLICATION_OCTET_STREAM);
+    let (_, value) = crate::object_from_disk("w1", &path("empty.txt"), true, None)
+      .await
+      .unwrap();
+    assert_eq!(value.mime, mime::TEXT_PLAIN);
+
+    let memory = InMemoryObjectStorage::new();
+    let local = crate::LocalFsObjectStorage::new(dir.path().join("storage"));
+    let services: [&dyn ObjectStorageService; 2] = [&memory, &local];
+    for service in services {
+      let object = StorageObject::from_file("w1", "empty.txt", path("empty.txt"));
+      let (_, url) = upload_object(service, &object, None).await.unwrap();
+      assert_eq!(service.head_object(url.clone()).await.unwrap().size, 0);
+      let value = service
+        .get_object_verified(url.clone(), &identity.file_id)
+        .await
+        .unwrap();
+      assert!(value.raw.is_empty());
+
+      let dest = dir.path().join("downloads/empty.txt");
+      assert_eq!(service.download_to_file(url, &dest).await.unwrap(), 0);
+      assert_eq!(std::fs::metadata(&dest).unwrap().len(), 0);
+    }
+  }
+}
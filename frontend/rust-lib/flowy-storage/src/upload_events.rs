@@ -0,0 +1,137 @@
+use std::time::Duration;
+
+use futures::Stream;
+use parking_lot::Mutex;
+use tokio::sync::broadcast;
+use tracing::warn;
+
+use crate::{storage_config, ProgressCallback, TransferProgressCallback, TransferRate};
+
+/// The number of events kept for the subscribers that fall behind. A subscriber that lags more
+/// misses the oldest events.
+pub const UPLOAD_EVENTS_CAPACITY: usize = 256;
+
+/// The progress of the uploads, published by [UploadEvents]. The events name the object with the
+/// [crate::StorageObject::file_name] it was created with, so the UI can match them with the
+/// placeholder it shows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UploadEvent {
+  Progress {
+    workspace_id: String,
+    file_name: String,
+    uploaded: u64,
+    /// 0 if the size of the object is unknown until it's uploaded, for example when it's read
+    /// from a stream.
+    total: u64,
+    /// The smoothed upload speed, see [crate::TransferRate]. `None` until it can be estimated.
+    bytes_per_second: Option<u64>,
+    /// The estimated time left, `None` if the total or the speed is unknown.
+    eta: Option<Duration>,
+  },
+  Completed {
+    workspace_id: String,
+    file_name: String,
+    file_id: String,
+    url: String,
+  },
+  Failed {
+    workspace_id: String,
+    file_name: String,
+    error: String,
+  },
+}
+
+impl UploadEvent {
+  pub fn workspace_id(&self) -> &str {
+    match self {
+      UploadEvent::Progress { workspace_id, .. }
+      | UploadEvent::Completed { workspace_id, .. }
+      | UploadEvent::Failed { workspace_id, .. } => workspace_id,
+    }
+  }
+}
+
+/// Publishes the [UploadEvent]s of [crate::upload_many_with_events] and of the
+/// [crate::UploadQueue] to any number of subscribers. Publishing never blocks the uploads, the
+/// events are dropped when nobody is subscribed.
+#[derive(Clone)]
+pub struct UploadEvents {
+  sender: broadcast::Sender<UploadEvent>,
+}
+
+impl Default for UploadEvents {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl UploadEvents {
+  pub fn new() -> Self {
+    let (sender, _) = broadcast::channel(UPLOAD_EVENTS_CAPACITY);
+    Self { sender }
+  }
+
+  /// Returns the events published from now on, only the ones of `workspace_id` if it's set.
+  pub fn subscribe(&self, workspace_id: Option<&str>) -> impl Stream<Item = UploadEvent> {
+    let workspace_id = workspace_id.map(|id| id.to_string());
+    futures::stream::unfold(self.sender.subscribe(), move |mut rx| {
+      let workspace_id = workspace_id.clone();
+      async move {
+        loop {
+          match rx.recv().await {
+            Ok(event) => {
+              if workspace_id
+                .as_deref()
+                .map_or(true, |id| id == event.workspace_id())
+              {
+                return Some((event, rx));
+              }
+            },
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+              warn!("an upload events subscriber missed {} events", skipped);
+            },
+            Err(broadcast::error::RecvError::Closed) => return None,
+          }
+        }
+      }
+    })
+  }
+
+  pub fn publish(&self, event: UploadEvent) {
+    // Fails only if there is no subscriber.
+    let _ = self.sender.send(event);
+  }
+
+  /// Returns a callback publishing the progress of the upload of `file_name`.
+  pub(crate) fn progress_callback(&self, workspace_id: &str, file_name: &str) -> ProgressCallback {
+    let publish = self.stream_progress_callback(workspace_id, file_name);
+    Box::new(move |uploaded, total| publish(uploaded, Some(total)))
+  }
+
+  /// The variant of [Self::progress_callback] for the uploads whose size may be unknown.
+  pub(crate) fn stream_progress_callback(
+    &self,
+    workspace_id: &str,
+    file_name: &str,
+  ) -> TransferProgressCallback {
+    let events = self.clone();
+    let workspace_id = workspace_id.to_string();
+    let file_name = file_name.to_string();
+    let rate = Mutex::new(TransferRate::new(storage_config().progress_rate_window));
+    Box::new(move |uploaded, total| {
+      let (bytes_per_second, eta) = {
+        let mut rate = rate.lock();
+        rate.record(uploaded);
+        (rate.bytes_per_second(), rate.eta(total))
+      };
+      events.publish(UploadEvent::Progress {
+        workspace_id: workspace_id.clone(),
+        file_name: file_name.clone(),
+        uploaded,
+        total: total.unwrap_or_default(),
+        bytes_per_second,
+        eta,
+      })
+    })
+  }
+}
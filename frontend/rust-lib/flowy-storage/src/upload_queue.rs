@@ -0,0 +1,656 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+
+use diesel::SqliteConnection;
+use futures::Stream;
+use parking_lot::Mutex;
+use tokio::sync::Notify;
+use tracing::{error, info};
+
+use flowy_error::{ErrorCode, FlowyError, FlowyResult};
+use flowy_sqlite::kv::StorePreferences;
+use flowy_sqlite::schema::{upload_queue_table, upload_queue_table::dsl};
+use flowy_sqlite::{prelude::*, DBConnection};
+use lib_infra::util::timestamp;
+
+use crate::upload::{read_storage_object, upload_event};
+use crate::{
+  cancellable, CancellationToken, ObjectStorageService, ObjectValueSupabase, ResumableUploader,
+  StorageObject, UploadEvent, UploadEvents,
+};
+
+const UPLOAD_QUEUE_PAUSED_KEY: &str = "flowy_storage_upload_queue_paused";
+
+/// Provides the connection to the database of the current user, where the queued uploads are
+/// stored.
+pub trait UploadQueueDB: Send + Sync + 'static {
+  fn get_connection(&self) -> FlowyResult<DBConnection>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UploadStatus {
+  Pending = 0,
+  InProgress = 1,
+  Failed = 2,
+  /// Paused with [UploadQueue::pause] or [UploadQueue::pause_all], not dispatched until it's
+  /// resumed.
+  Paused = 3,
+}
+
+impl From<i32> for UploadStatus {
+  fn from(value: i32) -> Self {
+    match value {
+      1 => UploadStatus::InProgress,
+      2 => UploadStatus::Failed,
+      3 => UploadStatus::Paused,
+      _ => UploadStatus::Pending,
+    }
+  }
+}
+
+/// A queued upload, as shown in the uploads panel.
+#[derive(Debug, Clone)]
+pub struct UploadTask {
+  pub id: String,
+  pub workspace_id: String,
+  pub file_name: String,
+  /// The path of the file to upload. `None` if the content was queued as bytes.
+  pub file_path: Option<String>,
+  /// The url of the object. Empty until the upload starts.
+  pub url: String,
+  pub status: UploadStatus,
+  /// The error of the last attempt if the status is [UploadStatus::Failed].
+  pub error: String,
+  pub created_at: i64,
+}
+
+#[derive(Clone, Debug, Queryable, Insertable)]
+#[diesel(table_name = upload_queue_table)]
+struct UploadQueueRow {
+  id: String,
+  workspace_id: String,
+  file_name: String,
+  file_path: Option<String>,
+  bytes: Option<Vec<u8>>,
+  mime: String,
+  url: String,
+  status: i32,
+  error: String,
+  created_at: i64,
+}
+
+impl UploadQueueRow {
+  fn storage_object(&self) -> StorageObject {
+    match &self.file_path {
+      Some(file_path) => StorageObject::from_file(&self.workspace_id, &self.file_name, file_path),
+      None => StorageObject::from_bytes(
+        &self.workspace_id,
+        &self.file_name,
+        self.bytes.clone().unwrap_or_default(),
+        self.mime.clone(),
+      ),
+    }
+  }
+}
+
+impl From<UploadQueueRow> for UploadTask {
+  fn from(row: UploadQueueRow) -> Self {
+    Self {
+      id: row.id,
+      workspace_id: row.workspace_id,
+      file_name: row.file_name,
+      file_path: row.file_path,
+      url: row.url,
+      status: row.status.into(),
+      error: row.error,
+      created_at: row.created_at,
+    }
+  }
+}
+
+/// A durable queue of uploads. Every queued [StorageObject] is recorded in the
+/// `upload_queue_table`, so the uploads that were pending when the app was closed are resumed by
+/// [UploadQueue::start]. The entries are removed once they are uploaded, the failed ones are kept
+/// until they are retried or removed.
+///
+/// The uploads can be paused, one by one or all together. The paused state is stored with the
+/// entries, and the state of the whole queue in the [StorePreferences] given to
+/// [UploadQueue::with_preferences], so both survive app restarts.
+pub struct UploadQueue<S: ?Sized> {
+  service: Arc<S>,
+  db: Arc<dyn UploadQueueDB>,
+  notify: Arc<Notify>,
+  events: UploadEvents,
+  resumable: Option<Arc<ResumableUploader<S>>>,
+  preferences: Option<Arc<StorePreferences>>,
+  paused: AtomicBool,
+  /// The cancellation tokens of the uploads in progress, by entry id.
+  in_flight: Mutex<HashMap<String, CancellationToken>>,
+}
+
+impl<S: ?Sized> Drop for UploadQueue<S> {
+  fn drop(&mut self) {
+    // Wakes the task spawned by [UploadQueue::start], it stops once it can't upgrade the queue.
+    self.notify.notify_one();
+  }
+}
+
+impl<S> UploadQueue<S>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  pub fn new(service: Arc<S>, db: Arc<dyn UploadQueueDB>) -> Self {
+    Self {
+      service,
+      db,
+      notify: Arc::new(Notify::new()),
+      events: UploadEvents::new(),
+      resumable: None,
+      preferences: None,
+      paused: AtomicBool::new(false),
+      in_flight: Mutex::new(HashMap::new()),
+    }
+  }
+
+  /// Uploads the entries with the `uploader`, so a paused or interrupted upload continues from
+  /// the last confirmed part instead of starting over.
+  pub fn with_resumable(mut self, uploader: Arc<ResumableUploader<S>>) -> Self {
+    self.resumable = Some(uploader);
+    self
+  }
+
+  /// Persists the paused state of the whole queue in `preferences`, and restores it.
+  pub fn with_preferences(mut self, preferences: Arc<StorePreferences>) -> Self {
+    self.paused = AtomicBool::new(preferences.get_bool(UPLOAD_QUEUE_PAUSED_KEY));
+    self.preferences = Some(preferences);
+    self
+  }
+
+  /// Returns the events of the uploads driven by the queue, see [UploadEvents::subscribe].
+  pub fn subscribe(&self, workspace_id: Option<&str>) -> impl Stream<Item = UploadEvent> {
+    self.events.subscribe(workspace_id)
+  }
+
+  /// Spawns the task that drives the queue. The uploads that were interrupted are queued again
+  /// first. The task stops when the queue is dropped.
+  pub fn start(self: &Arc<Self>) {
+    let queue = Arc::downgrade(self);
+    let notify = self.notify.clone();
+    tokio::spawn(async move {
+      if let Some(queue) = queue.upgrade() {
+        if let Err(err) = queue.resume_interrupted() {
+          error!("failed to resume the upload queue: {}", err);
+        }
+      }
+
+      loop {
+        match Weak::upgrade(&queue) {
+          None => break,
+          Some(queue) => {
+            if let Err(err) = queue.process_pending().await {
+              error!("failed to process the upload queue: {}", err);
+            }
+          },
+        }
+        notify.notified().await;
+      }
+    });
+  }
+
+  /// Records the object in the queue and returns the id of the entry.
+  pub fn enqueue(&self, object: StorageObject) -> FlowyResult<String> {
+    let (file_path, bytes, mime) = match object.value {
+      ObjectValueSupabase::File { file_path, .. } => (Some(file_path), None, String::new()),
+      ObjectValueSupabase::Bytes { bytes, mime } => (None, Some(bytes.to_vec()), mime),
+      ObjectValueSupabase::Reader { .. } => {
+        return Err(FlowyError::new(
+          ErrorCode::InvalidParams,
+          "an object read from a stream can't be queued, upload it directly",
+        ));
+      },
+    };
+    let row = UploadQueueRow {
+      id: uuid::Uuid::new_v4().to_string(),
+      workspace_id: object.workspace_id,
+      file_name: object.file_name,
+      file_path,
+      bytes,
+      mime,
+      url: String::new(),
+      status: if self.is_paused() {
+        UploadStatus::Paused as i32
+      } else {
+        UploadStatus::Pending as i32
+      },
+      error: String::new(),
+      created_at: timestamp(),
+    };
+
+    let mut conn = self.db.get_connection()?;
+    insert_into(upload_queue_table::table)
+      .values(&row)
+      .execute(&mut *conn)?;
+    self.notify.notify_one();
+    Ok(row.id)
+  }
+
+  /// Returns the uploads that are waiting or in progress, oldest first.
+  pub fn pending_uploads(&self) -> FlowyResult<Vec<UploadTask>> {
+    self.load_tasks(&[UploadStatus::Pending, UploadStatus::InProgress])
+  }
+
+  /// Returns the uploads whose last attempt failed, oldest first.
+  pub fn failed_uploads(&self) -> FlowyResult<Vec<UploadTask>> {
+    self.load_tasks(&[UploadStatus::Failed])
+  }
+
+  /// Returns the paused uploads, oldest first.
+  pub fn paused_uploads(&self) -> FlowyResult<Vec<UploadTask>> {
+    self.load_tasks(&[UploadStatus::Paused])
+  }
+
+  /// Whether the whole queue is paused with [UploadQueue::pause_all].
+  pub fn is_paused(&self) -> bool {
+    self.paused.load(Ordering::SeqCst)
+  }
+
+  /// Pauses an upload. An upload in progress is interrupted, and continues from the last
+  /// confirmed part when it's resumed if the queue uploads with a [ResumableUploader].
+  pub fn pause(&self, id: &str) -> FlowyResult<()> {
+    let mut conn = self.db.get_connection()?;
+    update_status(
+      &mut conn,
+      Some(id),
+      &[UploadStatus::Pending, UploadStatus::InProgress],
+      UploadStatus::Paused,
+    )?;
+    if let Some(cancel) = self.in_flight.lock().get(id) {
+      cancel.cancel();
+    }
+    Ok(())
+  }
+
+  /// Queues a paused upload again.
+  pub fn resume(&self, id: &str) -> FlowyResult<()> {
+    let mut conn = self.db.get_connection()?;
+    update_status(
+      &mut conn,
+      Some(id),
+      &[UploadStatus::Paused],
+      UploadStatus::Pending,
+    )?;
+    self.notify.notify_one();
+    Ok(())
+  }
+
+  /// Pauses every upload, including the ones queued until [UploadQueue::resume_all] is called.
+  pub fn pause_all(&self) -> FlowyResult<()> {
+    self.set_paused(true)?;
+    let mut conn = self.db.get_connection()?;
+    update_status(
+      &mut conn,
+      None,
+      &[UploadStatus::Pending, UploadStatus::InProgress],
+      UploadStatus::Paused,
+    )?;
+    for cancel in self.in_flight.lock().values() {
+      cancel.cancel();
+    }
+    Ok(())
+  }
+
+  /// Queues every paused upload again.
+  pub fn resume_all(&self) -> FlowyResult<()> {
+    self.set_paused(false)?;
+    let mut conn = self.db.get_connection()?;
+    update_status(
+      &mut conn,
+      None,
+      &[UploadStatus::Paused],
+      UploadStatus::Pending,
+    )?;
+    self.notify.notify_one();
+    Ok(())
+  }
+
+  /// Queues a failed upload again.
+  pub fn retry(&self, id: &str) -> FlowyResult<()> {
+    let mut conn = self.db.get_connection()?;
+    set_status(&mut conn, id, UploadStatus::Pending, "")?;
+    self.notify.notify_one();
+    Ok(())
+  }
+
+  /// Removes an upload from the queue. An upload in progress is not interrupted.
+  pub fn remove(&self, id: &str) -> FlowyResult<()> {
+    let mut conn = self.db.get_connection()?;
+    diesel::delete(dsl::upload_queue_table.filter(dsl::id.eq(id))).execute(&mut *conn)?;
+    Ok(())
+  }
+
+  /// Queues the uploads that were in progress when the app was closed again.
+  pub fn resume_interrupted(&self) -> FlowyResult<()> {
+    let mut conn = self.db.get_connection()?;
+    let n = diesel::update(
+      dsl::upload_queue_table.filter(dsl::status.eq(UploadStatus::InProgress as i32)),
+    )
+    .set(dsl::status.eq(UploadStatus::Pending as i32))
+    .execute(&mut *conn)?;
+    if n > 0 {
+      info!("resume {} interrupted uploads", n);
+    }
+    Ok(())
+  }
+
+  /// Uploads the pending entries one by one until there is none left. A failed upload is marked
+  /// as failed and doesn't stop the queue.
+  pub async fn process_pending(&self) -> FlowyResult<()> {
+    loop {
+      let row = {
+        let mut conn = self.db.get_connection()?;
+        let row = dsl::upload_queue_table
+          .filter(dsl::status.eq(UploadStatus::Pending as i32))
+          .order(dsl::created_at.asc())
+          .first::<UploadQueueRow>(&mut *conn)
+          .optional()?;
+        match row {
+          None => return Ok(()),
+          Some(row) => {
+            // Registered first, so a pause can't miss the upload.
+            self
+              .in_flight
+              .lock()
+              .insert(row.id.clone(), CancellationToken::new());
+            // The entry may have been paused since it was loaded.
+            let n = update_status(
+              &mut conn,
+              Some(&row.id),
+              &[UploadStatus::Pending],
+              UploadStatus::InProgress,
+            )?;
+            if n == 0 {
+              self.in_flight.lock().remove(&row.id);
+              continue;
+            }
+            row
+          },
+        }
+      };
+
+      let object = row.storage_object();
+      let cancel = self
+        .in_flight
+        .lock()
+        .get(&row.id)
+        .cloned()
+        .unwrap_or_default();
+      let result = cancellable(self.upload(&row.id, &object), &cancel).await;
+      self.in_flight.lock().remove(&row.id);
+
+      let paused = result.is_err() && cancel.is_cancelled();
+      if !paused {
+        self.events.publish(upload_event(&object, &result));
+      }
+      let mut conn = self.db.get_connection()?;
+      match result {
+        Err(_) if paused => {
+          info!("upload {} paused", row.file_name);
+        },
+        Ok(_) => {
+          diesel::delete(dsl::upload_queue_table.filter(dsl::id.eq(&row.id)))
+            .execute(&mut *conn)?;
+        },
+        Err(err) => {
+          error!("upload {} failed: {}", row.file_name, err);
+          set_status(&mut conn, &row.id, UploadStatus::Failed, &err.msg)?;
+        },
+      }
+    }
+  }
+
+  /// Returns the `file_id` and the url of the uploaded object.
+  async fn upload(&self, id: &str, object: &StorageObject) -> FlowyResult<(String, String)> {
+    if let Some(resumable) = &self.resumable {
+      return resumable.resume_upload(object).await;
+    }
+
+    let (identity, value) = read_storage_object(object).await?;
+    let file_id = identity.file_id.clone();
+    let url = self.service.get_object_url(identity).await?;
+    {
+      let mut conn = self.db.get_connection()?;
+      diesel::update(dsl::upload_queue_table.filter(dsl::id.eq(id)))
+        .set(dsl::url.eq(&url))
+        .execute(&mut *conn)?;
+    }
+    let progress = self
+      .events
+      .progress_callback(&object.workspace_id, &object.file_name);
+    self
+      .service
+      .put_object_with_progress(url.clone(), value, progress)
+      .await?;
+    Ok((file_id, url))
+  }
+
+  fn set_paused(&self, paused: bool) -> FlowyResult<()> {
+    self.paused.store(paused, Ordering::SeqCst);
+    if let Some(preferences) = &self.preferences {
+      preferences.set_bool(UPLOAD_QUEUE_PAUSED_KEY, paused)?;
+    }
+    Ok(())
+  }
+
+  fn load_tasks(&self, statuses: &[UploadStatus]) -> FlowyResult<Vec<UploadTask>> {
+    let mut conn = self.db.get_connection()?;
+    let statuses = statuses.iter().map(|status| *status as i32);
+    let rows = dsl::upload_queue_table
+      .filter(dsl::status.eq_any(statuses))
+      .order(dsl::created_at.asc())
+      .load::<UploadQueueRow>(&mut *conn)?;
+    Ok(rows.into_iter().map(UploadTask::from).collect())
+  }
+}
+
+/// Moves the entry `id`, or every entry if it's `None`, from one of the `from` statuses to `to`,
+/// and returns the number of moved entries.
+fn update_status(
+  conn: &mut SqliteConnection,
+  id: Option<&str>,
+  from: &[UploadStatus],
+  to: UploadStatus,
+) -> Result<usize, FlowyError> {
+  let from = from.iter().map(|status| *status as i32).collect::<Vec<_>>();
+  let with_status = dsl::upload_queue_table.filter(dsl::status.eq_any(from));
+  let n = match id {
+    Some(id) => diesel::update(with_status.filter(dsl::id.eq(id)))
+      .set(dsl::status.eq(to as i32))
+      .execute(conn)?,
+    None => diesel::update(with_status)
+      .set(dsl::status.eq(to as i32))
+      .execute(conn)?,
+  };
+  Ok(n)
+}
+
+fn set_status(
+  conn: &mut SqliteConnection,
+  id: &str,
+  status: UploadStatus,
+  error: &str,
+) -> Result<(), FlowyError> {
+  diesel::update(dsl::upload_queue_table.filter(dsl::id.eq(id)))
+    .set((dsl::status.eq(status as i32), dsl::error.eq(error)))
+    .execute(conn)?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use flowy_error::ErrorCode;
+  use flowy_sqlite::Database;
+
+  use super::*;
+  use crate::{InMemoryObjectStorage, StorageOperation};
+
+  struct TestDB(Database);
+
+  impl UploadQueueDB for TestDB {
+    fn get_connection(&self) -> FlowyResult<DBConnection> {
+      self
+        .0
+        .get_connection()
+        .map_err(|err| FlowyError::internal().with_context(err))
+    }
+  }
+
+  fn upload_queue(
+    dir: &tempfile::TempDir,
+  ) -> (
+    Arc<InMemoryObjectStorage>,
+    UploadQueue<InMemoryObjectStorage>,
+  ) {
+    let db = flowy_sqlite::init(dir.path()).unwrap();
+    let preferences = StorePreferences::new(dir.path().to_str().unwrap()).unwrap();
+    let storage = Arc::new(InMemoryObjectStorage::new());
+    let queue = UploadQueue::new(storage.clone(), Arc::new(TestDB(db)))
+      .with_preferences(Arc::new(preferences));
+    (storage, queue)
+  }
+
+  fn bytes_object(bytes: &[u8]) -> StorageObject {
+    StorageObject::from_bytes("w1", "a.txt", bytes.to_vec(), "text/plain".to_string())
+  }
+
+  #[tokio::test]
+  async fn upload_queue_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let (storage, queue) = upload_queue(&dir);
+    queue.enqueue(bytes_object(b"hello")).unwrap();
+    assert_eq!(queue.pending_uploads().unwrap().len(), 1);
+    queue.process_pending().await.unwrap();
+    assert_eq!(storage.len(), 1);
+
+    storage.fail_next(
+      StorageOperation::Put,
+      FlowyError::new(ErrorCode::InvalidParams, "rejected"),
+    );
+    let failed_id = queue.enqueue(bytes_object(b"world")).unwrap();
+    queue.process_pending().await.unwrap();
+    assert!(queue.pending_uploads().unwrap().is_empty());
+    assert_eq!(storage.len(), 1);
+
+    let failed = queue.failed_uploads().unwrap();
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].id, failed_id);
+    assert_eq!(failed[0].status, UploadStatus::Failed);
+    assert!(!failed[0].url.is_empty());
+
+    queue.remove(&failed_id).unwrap();
+    assert!(queue.failed_uploads().unwrap().is_empty());
+  }
+
+  #[tokio::test]
+  async fn upload_queue_resume_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let id = {
+      let (_, queue) = upload_queue(&dir);
+      let id = queue.enqueue(bytes_object(b"hello")).unwrap();
+      // Simulate an app that was closed during the upload.
+      let mut conn = queue.db.get_connection().unwrap();
+      set_status(&mut conn, &id, UploadStatus::InProgress, "").unwrap();
+      id
+    };
+
+    let (storage, queue) = upload_queue(&dir);
+    let pending = queue.pending_uploads().unwrap();
+    assert_eq!(pending[0].id, id);
+    assert_eq!(pending[0].status, UploadStatus::InProgress);
+
+    queue.resume_interrupted().unwrap();
+    queue.process_pending().await.unwrap();
+    assert!(queue.pending_uploads().unwrap().is_empty());
+    assert_eq!(storage.len(), 1);
+  }
+
+  #[tokio::test]
+  async fn upload_queue_pause_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let (storage, queue) = upload_queue(&dir);
+    let paused_id = queue.enqueue(bytes_object(b"hello")).unwrap();
+    queue.enqueue(bytes_object(b"world")).unwrap();
+    queue.pause(&paused_id).unwrap();
+    queue.process_pending().await.unwrap();
+    assert_eq!(storage.len(), 1);
+    assert_eq!(queue.paused_uploads().unwrap()[0].id, paused_id);
+
+    // Pausing an upload in progress interrupts it.
+    // The upload hangs until it's cancelled.
+    storage.set_latency(StorageOperation::Put, Duration::from_secs(3600));
+    queue.resume(&paused_id).unwrap();
+    let queue = Arc::new(queue);
+    let handle = tokio::spawn({
+      let queue = queue.clone();
+      async move { queue.process_pending().await }
+    });
+    while queue.pending_uploads().unwrap()[0].status != UploadStatus::InProgress {
+      tokio::task::yield_now().await;
+    }
+    queue.pause(&paused_id).unwrap();
+    handle.await.unwrap().unwrap();
+    assert_eq!(queue.paused_uploads().unwrap()[0].id, paused_id);
+    assert!(queue.failed_uploads().unwrap().is_empty());
+
+    storage.set_latency(StorageOperation::Put, Duration::ZERO);
+    queue.resume(&paused_id).unwrap();
+    queue.process_pending().await.unwrap();
+    assert!(queue.paused_uploads().unwrap().is_empty());
+    assert_eq!(storage.len(), 2);
+  }
+
+  #[tokio::test]
+  async fn upload_queue_pause_all_test() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+      let (_, queue) = upload_queue(&dir);
+      queue.enqueue(bytes_object(b"hello")).unwrap();
+      queue.pause_all().unwrap();
+    }
+
+    // The queue is still paused after a restart, the new uploads wait too.
+    let (storage, queue) = upload_queue(&dir);
+    assert!(queue.is_paused());
+    queue.enqueue(bytes_object(b"world")).unwrap();
+    queue.process_pending().await.unwrap();
+    assert!(storage.is_empty());
+    assert_eq!(queue.paused_uploads().unwrap().len(), 2);
+
+    queue.resume_all().unwrap();
+    assert!(!queue.is_paused());
+    queue.process_pending().await.unwrap();
+    assert_eq!(storage.len(), 2);
+  }
+
+  #[tokio::test]
+  async fn upload_queue_task_stops_when_dropped_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let (_, queue) = upload_queue(&dir);
+    let queue = Arc::new(queue);
+    queue.start();
+    tokio::task::yield_now().await;
+
+    // The task holds the only other reference to the notify.
+    let notify = queue.notify.clone();
+    drop(queue);
+    tokio::time::timeout(Duration::from_secs(5), async {
+      while Arc::strong_count(&notify) > 1 {
+        tokio::task::yield_now().await;
+      }
+    })
+    .await
+    .unwrap();
+  }
+}
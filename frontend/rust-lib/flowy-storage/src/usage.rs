@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+use flowy_error::FlowyError;
+
+use crate::{FileStoragePlan, ObjectStorageService};
+
+/// How long [StorageUsage] serves the usage of a workspace before listing its objects again.
+pub const DEFAULT_USAGE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// The storage used by the objects of a workspace.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WorkspaceUsage {
+  pub total_bytes: u64,
+  pub object_count: usize,
+  /// The bytes used by each category of objects, which is the type of their mime type, for
+  /// example `image` or `video`.
+  pub bytes_by_category: HashMap<String, u64>,
+}
+
+/// Computes the storage used by each workspace by summing the sizes returned by
+/// [ObjectStorageService::list_objects], so it only works with services that can list their
+/// objects. Listing is expensive, so the usage of a workspace is cached for the `ttl`: the usage
+/// can be stale by that much, unless the refresh is forced.
+pub struct StorageUsage<S: ?Sized> {
+  service: Arc<S>,
+  ttl: Duration,
+  cache: Arc<Mutex<HashMap<String, (Instant, WorkspaceUsage)>>>,
+}
+
+impl<S> StorageUsage<S>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  pub fn new(service: Arc<S>, ttl: Duration) -> Self {
+    Self {
+      service,
+      ttl,
+      cache: Default::default(),
+    }
+  }
+
+  /// Returns the usage of the workspace. Set `force_refresh` to list the objects even if the
+  /// cached usage is still fresh.
+  pub async fn workspace_usage(
+    &self,
+    workspace_id: &str,
+    force_refresh: bool,
+  ) -> Result<WorkspaceUsage, FlowyError> {
+    let cached = self
+      .cache
+      .lock()
+      .get(workspace_id)
+      .filter(|(computed_at, _)| !force_refresh && computed_at.elapsed() < self.ttl)
+      .map(|(_, usage)| usage.clone());
+    if let Some(usage) = cached {
+      return Ok(usage);
+    }
+    let mut usage = WorkspaceUsage::default();
+    for object in self.service.list_objects(workspace_id, None).await? {
+      usage.total_bytes += object.size;
+      usage.object_count += 1;
+      *usage
+        .bytes_by_category
+        .entry(object.mime.type_().as_str().to_string())
+        .or_default() += object.size;
+    }
+    self
+      .cache
+      .lock()
+      .insert(workspace_id.to_string(), (Instant::now(), usage.clone()));
+    Ok(usage)
+  }
+
+  /// Returns the bytes used by each of the workspaces, see [Self::workspace_usage].
+  pub async fn storage_size_by_workspace(
+    &self,
+    workspace_ids: Vec<String>,
+    force_refresh: bool,
+  ) -> Result<HashMap<String, u64>, FlowyError> {
+    let usages = workspace_ids
+      .into_iter()
+      .map(|workspace_id| async move {
+        let usage = self.workspace_usage(&workspace_id, force_refresh).await?;
+        Ok((workspace_id, usage.total_bytes))
+      })
+      .collect::<Vec<_>>();
+    futures::future::join_all(usages)
+      .await
+      .into_iter()
+      .collect()
+  }
+
+  /// Drops the cached usage of the workspace, or of all the workspaces if `workspace_id` is
+  /// `None`. Call it after uploading or deleting objects to show the change right away.
+  pub fn invalidate(&self, workspace_id: Option<&str>) {
+    let mut cache = self.cache.lock();
+    match workspace_id {
+      Some(workspace_id) => {
+        cache.remove(workspace_id);
+      },
+      None => cache.clear(),
+    }
+  }
+}
+
+/// Caches the sizes returned by [FileStoragePlan::storage_sizes] for the `ttl`, for views that
+/// show the size of many workspaces at once. A single call to the plan fetches the sizes that
+/// are not cached, the errors are never cached.
+pub struct StorageSizeCache<P: ?Sized> {
+  plan: Arc<P>,
+  ttl: Duration,
+  cache: Arc<Mutex<HashMap<String, (Instant, u64)>>>,
+}
+
+impl<P> StorageSizeCache<P>
+where
+  P: FileStoragePlan + ?Sized,
+{
+  pub fn new(plan: Arc<P>, ttl: Duration) -> Self {
+    Self {
+      plan,
+      ttl,
+      cache: Default::default(),
+    }
+  }
+
+  /// Returns the size of each of the workspaces. Set `force_refresh` to fetch all the sizes
+  /// even if the cached ones are still fresh.
+  pub async fn storage_sizes(
+    &self,
+    workspace_ids: Vec<String>,
+    force_refresh: bool,
+  ) -> Result<HashMap<String, Result<u64, FlowyError>>, FlowyError> {
+    let mut sizes = HashMap::with_capacity(workspace_ids.len());
+    let mut missing = vec![];
+    {
+      let cache = self.cache.lock();
+      for workspace_id in workspace_ids {
+        let cached = cache
+          .get(&workspace_id)
+          .filter(|(fetched_at, _)| !force_refresh && fetched_at.elapsed() < self.ttl);
+        match cached {
+          Some((_, size)) => {
+            sizes.insert(workspace_id, Ok(*size));
+          },
+          None if !missing.contains(&workspace_id) => missing.push(workspace_id),
+          None => {},
+        }
+      }
+    }
+    if missing.is_empty() {
+      return Ok(sizes);
+    }
+    let fetched = self.plan.storage_sizes(missing).await?;
+    let now = Instant::now();
+    let mut cache = self.cache.lock();
+    for (workspace_id, size) in fetched {
+      if let Ok(size) = &size {
+        cache.insert(workspace_id.clone(), (now, *size));
+      }
+      sizes.insert(workspace_id, size);
+    }
+    Ok(sizes)
+  }
+
+  /// Drops the cached size of the workspace, or of all the workspaces if `workspace_id` is
+  /// `None`.
+  pub fn invalidate(&self, workspace_id: Option<&str>) {
+    let mut cache = self.cache.lock();
+    match workspace_id {
+      Some(workspace_id) => {
+        cache.remove(workspace_id);
+      },
+      None => cache.clear(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use async_trait::async_trait;
+
+  use super::*;
+  use crate::{memory_object_value, InMemoryObjectStorage, ObjectIdentity, StorageOperation};
+
+  async fn put(storage: &InMemoryObjectStorage, workspace_id: &str, file_name: &str, size: usize) {
+    let (file_id, ext) = file_name.split_once('.').unwrap();
+    let identity = ObjectIdentity {
+      workspace_id: workspace_id.to_string(),
+      file_id: file_id.to_string(),
+      ext: ext.to_string(),
+      hash_algorithm: None,
+      size: None,
+    };
+    let url = storage.get_object_url(identity).await.unwrap();
+    storage
+      .put_object(url, memory_object_value(file_name, vec![0; size]))
+      .await
+      .unwrap();
+  }
+
+  #[tokio::test]
+  async fn workspace_usage_test() {
+    let storage = Arc::new(InMemoryObjectStorage::new());
+    put(&storage, "w1", "1.png", 10).await;
+    put(&storage, "w1", "2.jpg", 5).await;
+    put(&storage, "w1", "3.txt", 1).await;
+    put(&storage, "w2", "1.mp4", 100).await;
+
+    let usage = StorageUsage::new(storage.clone(), DEFAULT_USAGE_TTL);
+    let w1 = usage.workspace_usage("w1", false).await.unwrap();
+    assert_eq!((w1.total_bytes, w1.object_count), (16, 3));
+    assert_eq!(w1.bytes_by_category["image"], 15);
+    assert_eq!(w1.bytes_by_category["text"], 1);
+
+    let sizes = usage
+      .storage_size_by_workspace(vec!["w1".to_string(), "w2".to_string()], false)
+      .await
+      .unwrap();
+    assert_eq!((sizes["w1"], sizes["w2"]), (16, 100));
+    assert_eq!(storage.call_count(StorageOperation::List), 2);
+
+    put(&storage, "w1", "4.txt", 1).await;
+    let w1 = usage.workspace_usage("w1", false).await.unwrap();
+    assert_eq!(w1.total_bytes, 16);
+    let w1 = usage.workspace_usage("w1", true).await.unwrap();
+    assert_eq!(w1.total_bytes, 17);
+    assert_eq!(storage.call_count(StorageOperation::List), 3);
+  }
+
+  #[derive(Default)]
+  struct BreakdownPlan {
+    calls: std::sync::atomic::AtomicUsize,
+  }
+
+  #[async_trait]
+  impl FileStoragePlan for BreakdownPlan {
+    async fn storage_size(&self) -> Result<u64, FlowyError> {
+      Ok(0)
+    }
+
+    async fn storage_sizes(
+      &self,
+      workspace_ids: Vec<String>,
+    ) -> Result<HashMap<String, Result<u64, FlowyError>>, FlowyError> {
+      self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+      Ok(
+        workspace_ids
+          .into_iter()
+          .map(|workspace_id| {
+            let size = match workspace_id.as_str() {
+              "w1" => Ok(10),
+              "w2" => Ok(20),
+              _ => Err(FlowyError::record_not_found()),
+            };
+            (workspace_id, size)
+          })
+          .collect(),
+      )
+    }
+
+    async fn maximum_file_size(&self) -> Result<u64, FlowyError> {
+      Ok(u64::MAX)
+    }
+
+    async fn check_upload_object(&self, _object: &crate::StorageObject) -> Result<(), FlowyError> {
+      Ok(())
+    }
+  }
+
+  #[tokio::test]
+  async fn storage_size_cache_test() {
+    let plan = Arc::new(BreakdownPlan::default());
+    let cache = StorageSizeCache::new(plan.clone(), DEFAULT_USAGE_TTL);
+    let ids = |ids: &[&str]| ids.iter().map(|id| id.to_string()).collect::<Vec<_>>();
+
+    let sizes = cache
+      .storage_sizes(ids(&["w1", "w2", "missing"]), false)
+      .await
+      .unwrap();
+    assert_eq!(*sizes["w1"].as_ref().unwrap(), 10);
+    assert_eq!(*sizes["w2"].as_ref().unwrap(), 20);
+    assert!(sizes["missing"].as_ref().unwrap_err().is_record_not_found());
+    assert_eq!(plan.calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+    // The cached sizes are served without a call, the error is fetched again.
+    let sizes = cache
+      .storage_sizes(ids(&["w1", "w2"]), false)
+      .await
+      .unwrap();
+    assert_eq!(sizes.len(), 2);
+    assert_eq!(plan.calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+    cache
+      .storage_sizes(ids(&["w1", "missing"]), false)
+      .await
+      .unwrap();
+    assert_eq!(plan.calls.load(std::sync::atomic::Ordering::SeqCst), 2);
+  }
+}
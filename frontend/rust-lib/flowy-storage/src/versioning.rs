@@ -0,0 +1,560 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use flowy_error::FlowyError;
+use lib_infra::util::timestamp;
+
+use crate::conditional::Precondition;
+use crate::{
+  DeleteReport, HealthStatus, ListOptions, ObjectIdentity, ObjectMeta, ObjectStorageService,
+  ObjectStream, ObjectValue,
+};
+
+/// How many versions of an object [VersionedObjectStorage] keeps, unless another limit is set
+/// with [VersionedObjectStorage::with_max_versions].
+pub const DEFAULT_MAX_VERSIONS: usize = 20;
+
+/// A version of an object, returned by [ObjectStorageService::list_versions].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VersionMeta {
+  pub version_id: String,
+  /// The size of the version in bytes, as stored.
+  pub size: u64,
+  /// When the version was uploaded, in seconds since the epoch.
+  pub created_at: i64,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct VersionManifest {
+  next_version: u64,
+  versions: Vec<VersionMeta>,
+}
+
+/// An [ObjectStorageService] that keeps the previous versions of the objects for the services
+/// that can't version them. Every version is stored next to the object, under the url of the
+/// object suffixed with `.v{version_id}`, and the versions of an object are listed in a manifest
+/// stored under the url suffixed with `.versions`. The url of the object keeps the current
+/// version, so [ObjectStorageService::get_object] and the other reads are unchanged.
+///
+/// Only the last `max_versions` versions are kept, the oldest ones are deleted by the upload that
+/// goes over the limit. An object uploaded before it's versioned gets its previous content saved
+/// as its first version when it's overwritten. Deleting an object deletes all its versions.
+///
+/// The manifest is updated by one upload at a time within the wrapper, concurrent uploads to the
+/// same url from other clients can lose versions. The uploads that can't go through
+/// [Self::put_object] are not supported: multipart uploads, server side copies, presigned upload
+/// urls and uploads with a TTL.
+pub struct VersionedObjectStorage<S: ?Sized> {
+  inner: Arc<S>,
+  max_versions: usize,
+  manifest_lock: Arc<Mutex<()>>,
+}
+
+impl<S> VersionedObjectStorage<S>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  pub fn new(inner: Arc<S>) -> Self {
+    Self {
+      inner,
+      max_versions: DEFAULT_MAX_VERSIONS,
+      manifest_lock: Default::default(),
+    }
+  }
+
+  pub fn with_max_versions(mut self, max_versions: usize) -> Self {
+    self.max_versions = max_versions.max(1);
+    self
+  }
+}
+
+fn version_url(url: &str, version_id: &str) -> String {
+  format!("{}.v{}", url, version_id)
+}
+
+fn manifest_url(url: &str) -> String {
+  format!("{}.versions", url)
+}
+
+/// Returns true for the urls of the versions and the manifests, which are hidden from the
+/// listings.
+fn is_version_url(url: &str) -> bool {
+  if url.ends_with(".versions") {
+    return true;
+  }
+  match url.rsplit_once(".v") {
+    Some((_, version_id)) => {
+      !version_id.is_empty() && version_id.bytes().all(|byte| byte.is_ascii_digit())
+    },
+    None => false,
+  }
+}
+
+async fn load_manifest<S>(inner: &S, url: &str) -> Result<Option<VersionManifest>, FlowyError>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  match inner.get_object(manifest_url(url)).await {
+    Ok(value) => serde_json::from_slice(&value.decompress()?.raw)
+      .map(Some)
+      .map_err(|err| FlowyError::serde().with_context(err)),
+    Err(err) if err.is_record_not_found() => Ok(None),
+    Err(err) => Err(err),
+  }
+}
+
+async fn save_manifest<S>(
+  inner: &S,
+  url: &str,
+  manifest: &VersionManifest,
+) -> Result<(), FlowyError>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  let value = ObjectValue {
+    raw: serde_json::to_vec(manifest)
+      .map_err(|err| FlowyError::serde().with_context(err))?
+      .into(),
+    mime: mime::APPLICATION_JSON,
+    content_encoding: None,
+  };
+  inner.put_object(manifest_url(url), value).await
+}
+
+/// Stores the value as a new version of the object and records it in the manifest.
+async fn push_version<S>(
+  inner: &S,
+  url: &str,
+  manifest: &mut VersionManifest,
+  value: ObjectValue,
+) -> Result<(), FlowyError>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  manifest.next_version += 1;
+  let version_id = manifest.next_version.to_string();
+  let size = value.raw.len() as u64;
+  inner
+    .put_object(version_url(url, &version_id), value)
+    .await?;
+  manifest.versions.push(VersionMeta {
+    version_id,
+    size,
+    created_at: timestamp(),
+  });
+  Ok(())
+}
+
+async fn put_versioned<S>(
+  inner: &S,
+  url: &str,
+  value: ObjectValue,
+  max_versions: usize,
+  precondition: Precondition,
+) -> Result<(), FlowyError>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  let mut manifest = match load_manifest(inner, url).await? {
+    Some(manifest) => manifest,
+    None => {
+      let mut manifest = VersionManifest::default();
+      match inner.get_object(url.to_string()).await {
+        Ok(previous) => push_version(inner, url, &mut manifest, previous).await?,
+        Err(err) if err.is_record_not_found() => {},
+        Err(err) => return Err(err),
+      }
+      manifest
+    },
+  };
+
+  // The manifest is not saved if the precondition fails, so the version pushed above is
+  // overwritten by the next upload.
+  push_version(inner, url, &mut manifest, value.clone()).await?;
+  precondition.put(inner, url.to_string(), value).await?;
+
+  let excess = manifest.versions.len().saturating_sub(max_versions);
+  let pruned = manifest.versions.drain(..excess).collect::<Vec<_>>();
+  save_manifest(inner, url, &manifest).await?;
+
+  if !pruned.is_empty() {
+    let urls = pruned
+      .iter()
+      .map(|version| version_url(url, &version.version_id))
+      .collect::<Vec<_>>();
+    let results = inner.delete_objects(urls.clone()).await?;
+    for (url, result) in urls.iter().zip(results) {
+      if let Err(err) = result {
+        warn!("failed to delete the old version {}: {}", url, err);
+      }
+    }
+  }
+  Ok(())
+}
+
+#[async_trait]
+impl<S> ObjectStorageService for VersionedObjectStorage<S>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  async fn get_object_url(&self, object_id: ObjectIdentity) -> Result<String, FlowyError> {
+    self.inner.get_object_url(object_id).await
+  }
+
+  async fn put_object(&self, url: String, object_value: ObjectValue) -> Result<(), FlowyError> {
+    let max_versions = self.max_versions;
+    let _guard = self.manifest_lock.lock().await;
+    put_versioned(
+      &*self.inner.clone(),
+      &url,
+      object_value,
+      max_versions,
+      Precondition::None,
+    )
+    .await
+  }
+
+  async fn put_object_if_absent(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+  ) -> Result<(), FlowyError> {
+    let max_versions = self.max_versions;
+    let _guard = self.manifest_lock.lock().await;
+    put_versioned(
+      &*self.inner.clone(),
+      &url,
+      object_value,
+      max_versions,
+      Precondition::Absent,
+    )
+    .await
+  }
+
+  async fn put_object_if_match(
+    &self,
+    url: String,
+    object_value: ObjectValue,
+    etag: String,
+  ) -> Result<(), FlowyError> {
+    let max_versions = self.max_versions;
+    let _guard = self.manifest_lock.lock().await;
+    put_versioned(
+      &*self.inner.clone(),
+      &url,
+      object_value,
+      max_versions,
+      Precondition::Match(etag),
+    )
+    .await
+  }
+
+  async fn delete_object(&self, url: String) -> Result<(), FlowyError> {
+    let _guard = self.manifest_lock.lock().await;
+    self.inner.delete_object(url.clone()).await?;
+    if let Some(manifest) = load_manifest(&*self.inner.clone(), &url).await? {
+      let mut urls = manifest
+        .versions
+        .iter()
+        .map(|version| version_url(&url, &version.version_id))
+        .collect::<Vec<_>>();
+      urls.push(manifest_url(&url));
+      for (url, result) in urls
+        .iter()
+        .zip(self.inner.delete_objects(urls.clone()).await?)
+      {
+        if let Err(err) = result {
+          warn!("failed to delete the version {}: {}", url, err);
+        }
+      }
+    }
+    Ok(())
+  }
+
+  async fn delete_workspace_objects(&self, workspace_id: &str) -> Result<DeleteReport, FlowyError> {
+    self.inner.delete_workspace_objects(workspace_id).await
+  }
+
+  async fn list_objects(
+    &self,
+    workspace_id: &str,
+    prefix: Option<&str>,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    Ok(without_versions(
+      self.inner.list_objects(workspace_id, prefix).await?,
+    ))
+  }
+
+  async fn list_objects_with_options(
+    &self,
+    workspace_id: &str,
+    options: &ListOptions,
+  ) -> Result<Vec<ObjectMeta>, FlowyError> {
+    Ok(without_versions(
+      self
+        .inner
+        .list_objects_with_options(workspace_id, options)
+        .await?,
+    ))
+  }
+
+  async fn get_object(&self, url: String) -> Result<ObjectValue, FlowyError> {
+    self.inner.get_object(url).await
+  }
+
+  async fn get_object_if_modified(
+    &self,
+    url: String,
+    etag: Option<String>,
+  ) -> Result<Option<(ObjectValue, String)>, FlowyError> {
+    self.inner.get_object_if_modified(url, etag).await
+  }
+
+  async fn get_object_stream(&self, url: String) -> Result<ObjectStream, FlowyError> {
+    self.inner.get_object_stream(url).await
+  }
+
+  async fn get_object_verified(
+    &self,
+    url: String,
+    expected_file_id: &str,
+  ) -> Result<ObjectValue, FlowyError> {
+    self.inner.get_object_verified(url, expected_file_id).await
+  }
+
+  fn supports_range_requests(&self) -> bool {
+    self.inner.supports_range_requests()
+  }
+
+  async fn get_object_range(
+    &self,
+    url: String,
+    start: u64,
+    end: Option<u64>,
+  ) -> Result<ObjectValue, FlowyError> {
+    self.inner.get_object_range(url, start, end).await
+  }
+
+  async fn head_object(&self, url: String) -> Result<ObjectMeta, FlowyError> {
+    self.inner.head_object(url).await
+  }
+
+  async fn object_exists(&self, url: String) -> Result<bool, FlowyError> {
+    self.inner.object_exists(url).await
+  }
+
+  async fn presign_get_url(&self, url: String, expires_in: Duration) -> Result<String, FlowyError> {
+    self.inner.presign_get_url(url, expires_in).await
+  }
+
+  async fn trash_object(&self, url: String) -> Result<(), FlowyError> {
+    self.inner.trash_object(url).await
+  }
+
+  async fn restore_object(&self, url: String) -> Result<(), FlowyError> {
+    self.inner.restore_object(url).await
+  }
+
+  async fn purge_trash(&self, older_than: Duration) -> Result<Vec<String>, FlowyError> {
+    self.inner.purge_trash(older_than).await
+  }
+
+  fn supports_versioning(&self) -> bool {
+    true
+  }
+
+  async fn list_versions(&self, url: String) -> Result<Vec<VersionMeta>, FlowyError> {
+    Ok(
+      load_manifest(&*self.inner.clone(), &url)
+        .await?
+        .map(|manifest| manifest.versions)
+        .unwrap_or_default(),
+    )
+  }
+
+  async fn get_object_version(
+    &self,
+    url: String,
+    version_id: String,
+  ) -> Result<ObjectValue, FlowyError> {
+    let manifest = load_manifest(&*self.inner.clone(), &url)
+      .await?
+      .unwrap_or_default();
+    if !manifest
+      .versions
+      .iter()
+      .any(|version| version.version_id == version_id)
+    {
+      return Err(version_not_found(&url, &version_id));
+    }
+    self.inner.get_object(version_url(&url, &version_id)).await
+  }
+
+  async fn restore_version(&self, url: String, version_id: String) -> Result<(), FlowyError> {
+    let max_versions = self.max_versions;
+    let value = self.get_object_version(url.clone(), version_id).await?;
+    let _guard = self.manifest_lock.lock().await;
+    put_versioned(
+      &*self.inner.clone(),
+      &url,
+      value,
+      max_versions,
+      Precondition::None,
+    )
+    .await
+  }
+
+  async fn health_check(&self) -> Result<HealthStatus, FlowyError> {
+    self.inner.health_check().await
+  }
+}
+
+fn without_versions(objects: Vec<ObjectMeta>) -> Vec<ObjectMeta> {
+  objects
+    .into_iter()
+    .filter(|object| !is_version_url(&object.url))
+    .collect()
+}
+
+fn version_not_found(url: &str, version_id: &str) -> FlowyError {
+  FlowyError::record_not_found().with_context(format!("{} has no version {}", url, version_id))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{memory_object_value, InMemoryObjectStorage, StorageErrorExt, StorageErrorKind};
+
+  async fn url(storage: &VersionedObjectStorage<InMemoryObjectStorage>) -> String {
+    let identity = ObjectIdentity {
+      workspace_id: "w1".to_string(),
+      file_id: "doc".to_string(),
+      ext: "txt".to_string(),
+      hash_algorithm: None,
+      size: None,
+    };
+    storage.get_object_url(identity).await.unwrap()
+  }
+
+  fn content(value: ObjectValue) -> Vec<u8> {
+    value.decompress().unwrap().raw.to_vec()
+  }
+
+  #[tokio::test]
+  async fn versions_and_restore_test() {
+    let inner = Arc::new(InMemoryObjectStorage::new());
+    let url = url(&VersionedObjectStorage::new(inner.clone())).await;
+    // Uploaded before versioning was enabled.
+    inner
+      .put_object(url.clone(), memory_object_value("doc.txt", "v1"))
+      .await
+      .unwrap();
+
+    let storage = VersionedObjectStorage::new(inner.clone());
+    for content in ["v2", "v3"] {
+      storage
+        .put_object(url.clone(), memory_object_value("doc.txt", content))
+        .await
+        .unwrap();
+    }
+    let versions = storage.list_versions(url.clone()).await.unwrap();
+    let ids = versions
+      .iter()
+      .map(|version| version.version_id.as_str())
+      .collect::<Vec<_>>();
+    assert_eq!(ids, ["1", "2", "3"]);
+    assert_eq!(
+      content(storage.get_object(url.clone()).await.unwrap()),
+      b"v3"
+    );
+    let first = storage
+      .get_object_version(url.clone(), "1".to_string())
+      .await
+      .unwrap();
+    assert_eq!(content(first), b"v1");
+
+    storage
+      .restore_version(url.clone(), "1".to_string())
+      .await
+      .unwrap();
+    assert_eq!(
+      content(storage.get_object(url.clone()).await.unwrap()),
+      b"v1"
+    );
+    assert_eq!(storage.list_versions(url.clone()).await.unwrap().len(), 4);
+
+    let err = storage
+      .get_object_version(url.clone(), "9".to_string())
+      .await
+      .err()
+      .unwrap();
+    assert!(err.is_record_not_found());
+    let objects = storage.list_objects("w1", None).await.unwrap();
+    assert_eq!(objects.len(), 1);
+    assert_eq!(objects[0].url, url);
+  }
+
+  #[tokio::test]
+  async fn prune_and_delete_versions_test() {
+    let inner = Arc::new(InMemoryObjectStorage::new());
+    let storage = VersionedObjectStorage::new(inner.clone()).with_max_versions(2);
+    let url = url(&storage).await;
+    for content in ["v1", "v2", "v3"] {
+      storage
+        .put_object(url.clone(), memory_object_value("doc.txt", content))
+        .await
+        .unwrap();
+    }
+    let versions = storage.list_versions(url.clone()).await.unwrap();
+    assert_eq!(versions[0].version_id, "2");
+    assert!(inner.object(&version_url(&url, "1")).is_none());
+
+    storage.delete_object(url.clone()).await.unwrap();
+    assert!(inner.is_empty());
+    assert!(storage.list_versions(url).await.unwrap().is_empty());
+  }
+
+  #[tokio::test]
+  async fn conditional_put_versions_test() {
+    let storage = VersionedObjectStorage::new(Arc::new(InMemoryObjectStorage::new()));
+    let url = url(&storage).await;
+    storage
+      .put_object_if_absent(url.clone(), memory_object_value("doc.txt", "v1"))
+      .await
+      .unwrap();
+    let err = storage
+      .put_object_if_absent(url.clone(), memory_object_value("doc.txt", "v2"))
+      .await
+      .unwrap_err();
+    assert!(err.is_storage_kind(StorageErrorKind::PreconditionFailed));
+
+    let etag = storage
+      .head_object(url.clone())
+      .await
+      .unwrap()
+      .etag
+      .unwrap();
+    storage
+      .put_object_if_match(
+        url.clone(),
+        memory_object_value("doc.txt", "v2"),
+        etag.clone(),
+      )
+      .await
+      .unwrap();
+    let err = storage
+      .put_object_if_match(url.clone(), memory_object_value("doc.txt", "v3"), etag)
+      .await
+      .unwrap_err();
+    assert!(err.is_storage_kind(StorageErrorKind::PreconditionFailed));
+
+    // The rejected uploads are not recorded as versions.
+    assert_eq!(storage.list_versions(url.clone()).await.unwrap().len(), 2);
+    assert_eq!(content(storage.get_object(url).await.unwrap()), b"v2");
+  }
+}
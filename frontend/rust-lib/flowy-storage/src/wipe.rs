@@ -0,0 +1,169 @@
+use tracing::{info, warn};
+
+use flowy_error::FlowyError;
+
+use crate::ObjectStorageService;
+
+/// The result of [ObjectStorageService::delete_workspace_objects].
+#[derive(Debug, Default)]
+pub struct DeleteReport {
+  /// The number of objects deleted.
+  pub deleted: usize,
+  /// The number of bytes of the deleted objects.
+  pub freed_bytes: u64,
+  /// The objects that couldn't be deleted, deleting the workspace objects again retries them.
+  pub failed: Vec<(String, FlowyError)>,
+}
+
+impl DeleteReport {
+  /// True if nothing is left in the workspace.
+  pub fn is_complete(&self) -> bool {
+    self.failed.is_empty()
+  }
+}
+
+/// The default of [ObjectStorageService::delete_workspace_objects]: lists the objects of the
+/// workspace, then deletes them in one batch. The objects deleted by an interrupted run are not
+/// listed anymore, and the ones deleted in the meantime are not counted.
+pub(crate) async fn delete_listed_objects<S>(
+  service: &S,
+  workspace_id: &str,
+) -> Result<DeleteReport, FlowyError>
+where
+  S: ObjectStorageService + ?Sized,
+{
+  let objects = service.list_objects(workspace_id, None).await?;
+  let mut report = DeleteReport::default();
+  if objects.is_empty() {
+    return Ok(report);
+  }
+
+  let urls = objects.iter().map(|object| object.url.clone()).collect();
+  let results = service.delete_objects(urls).await?;
+  for (object, result) in objects.into_iter().zip(results) {
+    match result {
+      Ok(()) => {
+        report.deleted += 1;
+        report.freed_bytes += object.size;
+      },
+      Err(err) if err.is_record_not_found() => {},
+      Err(err) => {
+        warn!("failed to delete {} of the workspace: {}", object.url, err);
+        report.failed.push((object.url, err));
+      },
+    }
+  }
+  info!(
+    "deleted {} objects of workspace {}, {} bytes freed, {} failed",
+    report.deleted,
+    workspace_id,
+    report.freed_bytes,
+    report.failed.len()
+  );
+  Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use async_trait::async_trait;
+  use flowy_error::ErrorCode;
+
+  use super::*;
+  use crate::{
+    memory_object_value, InMemoryObjectStorage, ObjectIdentity, ObjectMeta, ObjectValue,
+    StorageOperation,
+  };
+
+  /// Only implements the required methods and the listing, so the defaults are used.
+  struct ListingStorage(InMemoryObjectStorage);
+
+  #[async_trait]
+  impl ObjectStorageService for ListingStorage {
+    async fn get_object_url(&self, object_id: ObjectIdentity) -> Result<String, FlowyError> {
+      self.0.get_object_url(object_id).await
+    }
+
+    async fn put_object(&self, url: String, value: ObjectValue) -> Result<(), FlowyError> {
+      self.0.put_object(url, value).await
+    }
+
+    async fn delete_object(&self, url: String) -> Result<(), FlowyError> {
+      self.0.delete_object(url).await
+    }
+
+    async fn list_objects(
+      &self,
+      workspace_id: &str,
+      prefix: Option<&str>,
+    ) -> Result<Vec<ObjectMeta>, FlowyError> {
+      self.0.list_objects(workspace_id, prefix).await
+    }
+
+    async fn get_object(&self, url: String) -> Result<ObjectValue, FlowyError> {
+      self.0.get_object(url).await
+    }
+  }
+
+  async fn put(storage: &impl ObjectStorageService, url: &str, content: &'static str) {
+    storage
+      .put_object(url.to_string(), memory_object_value("a.txt", content))
+      .await
+      .unwrap();
+  }
+
+  #[tokio::test]
+  async fn delete_workspace_objects_test() {
+    let storage = ListingStorage(InMemoryObjectStorage::new());
+    put(&storage, "memory://w1/1.txt", "hello").await;
+    put(&storage, "memory://w1/2.txt", "world!").await;
+    put(&storage, "memory://w2/1.txt", "kept").await;
+
+    storage.0.fail_next(
+      StorageOperation::Delete,
+      FlowyError::new(ErrorCode::ConnectClose, "closed"),
+    );
+    let report = storage.delete_workspace_objects("w1").await.unwrap();
+    assert_eq!(report.deleted, 1);
+    assert_eq!(report.failed.len(), 1);
+    assert!(!report.is_complete());
+
+    // Running it again deletes what's left.
+    let report = storage.delete_workspace_objects("w1").await.unwrap();
+    assert_eq!(report.deleted, 1);
+    assert!(report.is_complete());
+    assert!(storage.list_objects("w1", None).await.unwrap().is_empty());
+    assert_eq!(storage.list_objects("w2", None).await.unwrap().len(), 1);
+
+    let report = storage.delete_workspace_objects("w1").await.unwrap();
+    assert_eq!(report.deleted, 0);
+    assert!(report.is_complete());
+  }
+
+  #[tokio::test]
+  async fn delete_workspace_objects_with_prefix_delete_test() {
+    let storage = InMemoryObjectStorage::new();
+    put(&storage, "memory://w1/1.txt", "hello").await;
+    put(&storage, "memory://w1/2.txt", "world!").await;
+    put(&storage, "memory://w2/1.txt", "kept").await;
+    storage
+      .trash_object("memory://w1/2.txt".to_string())
+      .await
+      .unwrap();
+
+    let report = storage.delete_workspace_objects("w1").await.unwrap();
+    assert_eq!(report.deleted, 2);
+    assert_eq!(report.freed_bytes, 11);
+    assert_eq!(storage.call_count(StorageOperation::List), 0);
+    assert!(storage
+      .purge_trash(Duration::ZERO)
+      .await
+      .unwrap()
+      .is_empty());
+    assert_eq!(storage.len(), 1);
+
+    let report = storage.delete_workspace_objects("w1").await.unwrap();
+    assert_eq!(report.deleted, 0);
+  }
+}